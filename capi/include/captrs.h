@@ -0,0 +1,191 @@
+#ifndef CAPTRS_H
+#define CAPTRS_H
+
+/* Generated with cbindgen from captrs-capi. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Status codes returned by the fallible functions of the API
+ */
+typedef enum CaptrsStatus {
+  /**
+   * The operation succeeded
+   */
+  CAPTRS_STATUS_OK = 0,
+  /**
+   * A null pointer was passed where a valid one was required
+   */
+  CAPTRS_STATUS_NULL_POINTER = 1,
+  /**
+   * Could not duplicate output, access denied. Might be in protected fullscreen.
+   */
+  CAPTRS_STATUS_ACCESS_DENIED = 2,
+  /**
+   * Access to the duplicated output was lost. Likely, mode was changed e.g. window => full
+   */
+  CAPTRS_STATUS_ACCESS_LOST = 3,
+  /**
+   * Error when trying to refresh outputs after some failure.
+   */
+  CAPTRS_STATUS_REFRESH_FAILURE = 4,
+  /**
+   * Aquisition of next frame timed out.
+   */
+  CAPTRS_STATUS_TIMEOUT = 5,
+  /**
+   * General/Unexpected failure
+   */
+  CAPTRS_STATUS_FAIL = 6,
+} CaptrsStatus;
+
+/**
+ * Opaque handle to a screen capturer
+ */
+typedef struct CaptrsCapturer CaptrsCapturer;
+
+/**
+ * A captured frame, owned by the caller and freed with `captrs_free_frame`
+ *
+ * The layout of this struct is stable. Pixels are 4 bytes each, in the order
+ * blue, green, red, and alpha/padding, with `stride` bytes between the
+ * starts of rows.
+ */
+typedef struct CaptrsFrame {
+  /**
+   * The pixel data, of `len` bytes
+   */
+  uint8_t *data;
+  /**
+   * The number of bytes of `data`
+   */
+  size_t len;
+  /**
+   * The width of the frame, in pixels
+   */
+  uint32_t width;
+  /**
+   * The height of the frame, in pixels
+   */
+  uint32_t height;
+  /**
+   * The number of bytes between the starts of rows
+   */
+  size_t stride;
+} CaptrsFrame;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Construct a new capturer for a given capture source, e.g. a display.
+ *
+ * Returns null on failure. The capturer must be freed with `captrs_free`.
+ */
+struct CaptrsCapturer *captrs_new(size_t capture_src);
+
+/**
+ * Free a capturer created by `captrs_new`. Passing null does nothing.
+ *
+ * # Safety
+ *
+ * `capturer` must be null or a pointer returned by `captrs_new` that has not
+ * yet been freed.
+ */
+void captrs_free(struct CaptrsCapturer *capturer);
+
+/**
+ * Write the width and height of the area to capture to `width` and `height`
+ *
+ * # Safety
+ *
+ * `capturer` must be a valid capturer, and `width` and `height` must be
+ * valid for writes.
+ */
+enum CaptrsStatus captrs_geometry(const struct CaptrsCapturer *capturer,
+                                  uint32_t *width,
+                                  uint32_t *height);
+
+/**
+ * Capture screen and store the frame in the capturer for later retrieval with
+ * `captrs_frame_data`
+ *
+ * # Safety
+ *
+ * `capturer` must be a valid capturer. Any pointer previously returned by
+ * `captrs_frame_data` is invalidated.
+ */
+enum CaptrsStatus captrs_capture(struct CaptrsCapturer *capturer);
+
+/**
+ * Get the pixel data of the last frame captured by `captrs_capture`
+ *
+ * Pixels are 4 bytes each, in the order blue, green, red, and alpha/padding,
+ * row by row without any padding between rows. The number of bytes is
+ * written to `len`. Returns null if no frame has been captured yet.
+ *
+ * The data is owned by the capturer and stays valid until the next call to
+ * `captrs_capture` or `captrs_free`.
+ *
+ * # Safety
+ *
+ * `capturer` must be a valid capturer, and `len` must be null or valid for
+ * writes.
+ */
+const uint8_t *captrs_frame_data(const struct CaptrsCapturer *capturer, size_t *len);
+
+/**
+ * Capture screen directly into a buffer owned by the caller, and write the
+ * size of the frame to `width` and `height`
+ *
+ * Pixels are written as by `captrs_frame_data`, but with `stride` bytes
+ * between the starts of rows. Returns `CAPTRS_STATUS_FAIL` without writing
+ * to the buffer if it is too small for the frame, i.e. if `len` is less
+ * than `stride * (height - 1) + 4 * width` or `stride` is less than
+ * `4 * width`.
+ *
+ * # Safety
+ *
+ * `capturer` must be a valid capturer, `data` must be valid for writes of
+ * `len` bytes, and `width` and `height` must be null or valid for writes.
+ */
+enum CaptrsStatus captrs_capture_into(struct CaptrsCapturer *capturer,
+                                      uint8_t *data,
+                                      size_t len,
+                                      size_t stride,
+                                      uint32_t *width,
+                                      uint32_t *height);
+
+/**
+ * Capture screen into a new frame owned by the caller, written to `frame`
+ *
+ * On failure, `frame` is left untouched. On success, it must be freed with
+ * `captrs_free_frame`.
+ *
+ * # Safety
+ *
+ * `capturer` must be a valid capturer, and `frame` must be valid for writes.
+ */
+enum CaptrsStatus captrs_capture_frame(struct CaptrsCapturer *capturer, struct CaptrsFrame *frame);
+
+/**
+ * Free the pixel data of a frame captured by `captrs_capture_frame`, and
+ * reset it to an empty frame. Passing null, or an empty frame, does nothing.
+ *
+ * # Safety
+ *
+ * `frame` must be null or a frame written by `captrs_capture_frame` that has
+ * not yet been freed.
+ */
+void captrs_free_frame(struct CaptrsFrame *frame);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CAPTRS_H */
@@ -0,0 +1,269 @@
+//! C API for captrs
+//!
+//! A capturer is created with `captrs_new`, frames are captured into it with
+//! `captrs_capture`, and the last captured frame can be read with
+//! `captrs_frame_data`, or frames can be captured directly into memory owned
+//! by the caller with `captrs_capture_into`, or captured into a frame owned
+//! by the caller with `captrs_capture_frame`. The header `include/captrs.h` is generated from this
+//! file with `cbindgen --config cbindgen.toml --output include/captrs.h`.
+
+extern crate captrs;
+
+use captrs::{CaptureError, Capturer};
+use std::{mem, ptr};
+
+/// Opaque handle to a screen capturer
+pub struct CaptrsCapturer {
+    capturer: Capturer,
+}
+
+/// A captured frame, owned by the caller and freed with `captrs_free_frame`
+///
+/// The layout of this struct is stable. Pixels are 4 bytes each, in the order
+/// blue, green, red, and alpha/padding, with `stride` bytes between the
+/// starts of rows.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CaptrsFrame {
+    /// The pixel data, of `len` bytes
+    pub data: *mut u8,
+    /// The number of bytes of `data`
+    pub len: usize,
+    /// The width of the frame, in pixels
+    pub width: u32,
+    /// The height of the frame, in pixels
+    pub height: u32,
+    /// The number of bytes between the starts of rows
+    pub stride: usize,
+}
+
+/// Status codes returned by the fallible functions of the API
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptrsStatus {
+    /// The operation succeeded
+    Ok = 0,
+    /// A null pointer was passed where a valid one was required
+    NullPointer = 1,
+    /// Could not duplicate output, access denied. Might be in protected fullscreen.
+    AccessDenied = 2,
+    /// Access to the duplicated output was lost. Likely, mode was changed e.g. window => full
+    AccessLost = 3,
+    /// Error when trying to refresh outputs after some failure.
+    RefreshFailure = 4,
+    /// Aquisition of next frame timed out.
+    Timeout = 5,
+    /// General/Unexpected failure
+    Fail = 6,
+}
+
+impl From<CaptureError> for CaptrsStatus {
+    fn from(err: CaptureError) -> CaptrsStatus {
+        match err {
+            #[cfg(windows)]
+            CaptureError::AccessDenied => CaptrsStatus::AccessDenied,
+            CaptureError::PermissionDenied => CaptrsStatus::AccessDenied,
+            CaptureError::AccessLost => CaptrsStatus::AccessLost,
+            #[cfg(windows)]
+            CaptureError::RefreshFailure => CaptrsStatus::RefreshFailure,
+            CaptureError::Timeout | CaptureError::NoNewFrame => CaptrsStatus::Timeout,
+            CaptureError::Paused | CaptureError::Platform(_) | CaptureError::Fail(_) => {
+                CaptrsStatus::Fail
+            }
+        }
+    }
+}
+
+/// Construct a new capturer for a given capture source, e.g. a display.
+///
+/// Returns null on failure. The capturer must be freed with `captrs_free`.
+#[no_mangle]
+pub extern "C" fn captrs_new(capture_src: usize) -> *mut CaptrsCapturer {
+    match Capturer::new(capture_src) {
+        Ok(capturer) => Box::into_raw(Box::new(CaptrsCapturer { capturer })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Free a capturer created by `captrs_new`. Passing null does nothing.
+///
+/// # Safety
+///
+/// `capturer` must be null or a pointer returned by `captrs_new` that has not
+/// yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn captrs_free(capturer: *mut CaptrsCapturer) {
+    if !capturer.is_null() {
+        drop(Box::from_raw(capturer));
+    }
+}
+
+/// Write the width and height of the area to capture to `width` and `height`
+///
+/// # Safety
+///
+/// `capturer` must be a valid capturer, and `width` and `height` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn captrs_geometry(
+    capturer: *const CaptrsCapturer,
+    width: *mut u32,
+    height: *mut u32,
+) -> CaptrsStatus {
+    if capturer.is_null() || width.is_null() || height.is_null() {
+        return CaptrsStatus::NullPointer;
+    }
+    let (w, h) = (*capturer).capturer.geometry();
+    *width = w;
+    *height = h;
+    CaptrsStatus::Ok
+}
+
+/// Capture screen and store the frame in the capturer for later retrieval with
+/// `captrs_frame_data`
+///
+/// # Safety
+///
+/// `capturer` must be a valid capturer. Any pointer previously returned by
+/// `captrs_frame_data` is invalidated.
+#[no_mangle]
+pub unsafe extern "C" fn captrs_capture(capturer: *mut CaptrsCapturer) -> CaptrsStatus {
+    if capturer.is_null() {
+        return CaptrsStatus::NullPointer;
+    }
+    match (*capturer).capturer.capture_store_frame() {
+        Ok(()) => CaptrsStatus::Ok,
+        Err(e) => e.into(),
+    }
+}
+
+/// Get the pixel data of the last frame captured by `captrs_capture`
+///
+/// Pixels are 4 bytes each, in the order blue, green, red, and alpha/padding,
+/// row by row without any padding between rows. The number of bytes is
+/// written to `len`. Returns null if no frame has been captured yet.
+///
+/// The data is owned by the capturer and stays valid until the next call to
+/// `captrs_capture` or `captrs_free`.
+///
+/// # Safety
+///
+/// `capturer` must be a valid capturer, and `len` must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn captrs_frame_data(
+    capturer: *const CaptrsCapturer,
+    len: *mut usize,
+) -> *const u8 {
+    if capturer.is_null() {
+        return ptr::null();
+    }
+    let (data, n) = match (*capturer).capturer.get_stored_frame() {
+        Some(frame) => (frame.as_ptr() as *const u8, mem::size_of_val(frame)),
+        None => (ptr::null(), 0),
+    };
+    if !len.is_null() {
+        *len = n;
+    }
+    data
+}
+
+/// Capture screen directly into a buffer owned by the caller, and write the
+/// size of the frame to `width` and `height`
+///
+/// Pixels are written as by `captrs_frame_data`, but with `stride` bytes
+/// between the starts of rows. Returns `CAPTRS_STATUS_FAIL` without writing
+/// to the buffer if it is too small for the frame, i.e. if `len` is less
+/// than `stride * (height - 1) + 4 * width` or `stride` is less than
+/// `4 * width`.
+///
+/// # Safety
+///
+/// `capturer` must be a valid capturer, `data` must be valid for writes of
+/// `len` bytes, and `width` and `height` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn captrs_capture_into(
+    capturer: *mut CaptrsCapturer,
+    data: *mut u8,
+    len: usize,
+    stride: usize,
+    width: *mut u32,
+    height: *mut u32,
+) -> CaptrsStatus {
+    if capturer.is_null() || data.is_null() {
+        return CaptrsStatus::NullPointer;
+    }
+    match (*capturer)
+        .capturer
+        .capture_frame_into_raw(data, len, stride)
+    {
+        Ok((w, h)) => {
+            if !width.is_null() {
+                *width = w;
+            }
+            if !height.is_null() {
+                *height = h;
+            }
+            CaptrsStatus::Ok
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Capture screen into a new frame owned by the caller, written to `frame`
+///
+/// On failure, `frame` is left untouched. On success, it must be freed with
+/// `captrs_free_frame`.
+///
+/// # Safety
+///
+/// `capturer` must be a valid capturer, and `frame` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn captrs_capture_frame(
+    capturer: *mut CaptrsCapturer,
+    frame: *mut CaptrsFrame,
+) -> CaptrsStatus {
+    if capturer.is_null() || frame.is_null() {
+        return CaptrsStatus::NullPointer;
+    }
+    let captured = match (*capturer).capturer.capture_frame_meta() {
+        Ok(captured) => captured,
+        Err(e) => return e.into(),
+    };
+    let (width, height) = captured.geometry();
+    let stride = captured.stride();
+    let bytes = captured.as_bytes().to_vec().into_boxed_slice();
+    let len = bytes.len();
+    let data = Box::into_raw(bytes);
+    *frame = CaptrsFrame {
+        data: data as *mut u8,
+        len,
+        width,
+        height,
+        stride,
+    };
+    CaptrsStatus::Ok
+}
+
+/// Free the pixel data of a frame captured by `captrs_capture_frame`, and
+/// reset it to an empty frame. Passing null, or an empty frame, does nothing.
+///
+/// # Safety
+///
+/// `frame` must be null or a frame written by `captrs_capture_frame` that has
+/// not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn captrs_free_frame(frame: *mut CaptrsFrame) {
+    if frame.is_null() || (*frame).data.is_null() {
+        return;
+    }
+    let data = ptr::slice_from_raw_parts_mut((*frame).data, (*frame).len);
+    drop(Box::from_raw(data));
+    *frame = CaptrsFrame {
+        data: ptr::null_mut(),
+        len: 0,
+        width: 0,
+        height: 0,
+        stride: 0,
+    };
+}
@@ -0,0 +1,64 @@
+extern crate captrs;
+extern crate softbuffer;
+extern crate winit;
+
+use std::num::NonZeroU32;
+use std::rc::Rc;
+
+use captrs::softbuffer::{present, Scaling};
+use captrs::Capturer;
+use softbuffer::{Context, Surface};
+use winit::application::ApplicationHandler;
+use winit::event::WindowEvent;
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::window::{Window, WindowId};
+
+struct Mirror {
+    capturer: Capturer,
+    surface: Option<Surface<Rc<Window>, Rc<Window>>>,
+}
+
+impl ApplicationHandler for Mirror {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let attrs = Window::default_attributes().with_title("captrs mirror");
+        let window = Rc::new(event_loop.create_window(attrs).unwrap());
+        let context = Context::new(window.clone()).unwrap();
+        self.surface = Some(Surface::new(&context, window).unwrap());
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        let surface = match self.surface {
+            Some(ref mut surface) => surface,
+            None => return,
+        };
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::RedrawRequested => {
+                let size = surface.window().inner_size();
+                let (w, h) = match (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) {
+                    (Some(w), Some(h)) => (w, h),
+                    _ => return,
+                };
+                surface.resize(w, h).unwrap();
+                if self.capturer.capture_store_frame().is_ok() {
+                    let (w, h) = self.capturer.geometry();
+                    let frame = self.capturer.get_stored_frame().unwrap();
+                    present(surface, frame, w, h, Scaling::Fit).unwrap();
+                }
+                surface.window().request_redraw();
+            }
+            _ => (),
+        }
+    }
+}
+
+fn main() {
+    let capturer = Capturer::new(0).unwrap();
+    let event_loop = EventLoop::new().unwrap();
+    event_loop.set_control_flow(ControlFlow::Poll);
+    let mut mirror = Mirror {
+        capturer,
+        surface: None,
+    };
+    event_loop.run_app(&mut mirror).unwrap();
+}
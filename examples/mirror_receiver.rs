@@ -0,0 +1,16 @@
+extern crate captrs;
+
+use captrs::mirror::MirrorReceiver;
+
+fn main() {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7878".to_string());
+    let mut receiver = MirrorReceiver::connect(&addr).unwrap();
+
+    loop {
+        let n_tiles = receiver.recv().unwrap().len();
+        let (w, h) = receiver.geometry();
+        println!("{}x{}: {} tiles updated", w, h, n_tiles);
+    }
+}
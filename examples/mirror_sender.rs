@@ -0,0 +1,20 @@
+extern crate captrs;
+extern crate shuteye;
+
+use captrs::mirror::MirrorSender;
+use captrs::Capturer;
+use shuteye::sleep;
+use std::time::Duration;
+
+fn main() {
+    let mut capturer = Capturer::new(0).unwrap();
+    let mut sender = MirrorSender::bind("0.0.0.0:7878").unwrap();
+
+    loop {
+        if capturer.capture_store_frame().is_ok() {
+            let (w, h) = capturer.geometry();
+            sender.send_frame(capturer.get_stored_frame().unwrap(), w, h);
+        }
+        sleep(Duration::from_millis(16));
+    }
+}
@@ -0,0 +1,634 @@
+//! Analysis of the colors of frames, e.g. for ambient lighting
+
+use crate::{Bgr8, CaptureError, Capturer, Frame, Rect};
+use std::sync::OnceLock;
+
+/// Sums of the blue, green, and red components of some pixels
+fn channel_sums_scalar(pixels: &[Bgr8]) -> [u64; 3] {
+    let mut sums = [0u64; 3];
+    // Sums of up to 2^24 components fit in a u32, which vectorizes better
+    for chunk in pixels.chunks(1 << 24) {
+        let (mut b, mut g, mut r) = (0u32, 0u32, 0u32);
+        for p in chunk {
+            b += p.b as u32;
+            g += p.g as u32;
+            r += p.r as u32;
+        }
+        sums[0] += b as u64;
+        sums[1] += g as u64;
+        sums[2] += r as u64;
+    }
+    sums
+}
+
+#[cfg(target_arch = "x86_64")]
+fn channel_sums(pixels: &[Bgr8]) -> [u64; 3] {
+    use std::arch::x86_64::*;
+
+    let bytes = crate::pixel_bytes(pixels);
+    let chunks = bytes.chunks_exact(16);
+    let rest = &pixels[pixels.len() - chunks.remainder().len() / 4..];
+    // SSE2 is always available on x86_64. Each component is masked out of
+    // the pixels, and summed by `_mm_sad_epu8` into two 64-bit lanes.
+    let mut sums = unsafe {
+        let zero = _mm_setzero_si128();
+        let mask = _mm_set1_epi32(0xff);
+        let (mut b, mut g, mut r) = (zero, zero, zero);
+        for chunk in chunks {
+            let v = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            b = _mm_add_epi64(b, _mm_sad_epu8(_mm_and_si128(v, mask), zero));
+            g = _mm_add_epi64(
+                g,
+                _mm_sad_epu8(_mm_and_si128(_mm_srli_epi32(v, 8), mask), zero),
+            );
+            r = _mm_add_epi64(
+                r,
+                _mm_sad_epu8(_mm_and_si128(_mm_srli_epi32(v, 16), mask), zero),
+            );
+        }
+        let lanes = |v: __m128i| {
+            let mut out = [0u64; 2];
+            _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, v);
+            out[0] + out[1]
+        };
+        [lanes(b), lanes(g), lanes(r)]
+    };
+    let rest_sums = channel_sums_scalar(rest);
+    for (sum, rest_sum) in sums.iter_mut().zip(&rest_sums) {
+        *sum += rest_sum;
+    }
+    sums
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn channel_sums(pixels: &[Bgr8]) -> [u64; 3] {
+    channel_sums_scalar(pixels)
+}
+
+/// Table of sRGB encoded components to linear light
+pub(crate) fn srgb_to_linear_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, l) in table.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            *l = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
+        }
+        table
+    })
+}
+
+pub(crate) fn linear_to_srgb(l: f64) -> u8 {
+    let c = if l <= 0.0031308 {
+        l * 12.92
+    } else {
+        1.055 * l.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Average color of some pixels, averaging the sRGB encoded components
+pub(crate) fn average_color(pixels: &[Bgr8]) -> Bgr8 {
+    if pixels.is_empty() {
+        return Bgr8::new(0, 0, 0);
+    }
+    let n = pixels.len() as u64;
+    let [b, g, r] = channel_sums(pixels).map(|sum| ((sum + n / 2) / n) as u8);
+    Bgr8::new(r, g, b)
+}
+
+/// Average color of some pixels, averaging in linear light
+pub(crate) fn average_color_linear(pixels: &[Bgr8]) -> Bgr8 {
+    if pixels.is_empty() {
+        return Bgr8::new(0, 0, 0);
+    }
+    let table = srgb_to_linear_table();
+    let mut sums = [0f64; 3];
+    for p in pixels {
+        sums[0] += table[p.b as usize] as f64;
+        sums[1] += table[p.g as usize] as f64;
+        sums[2] += table[p.r as usize] as f64;
+    }
+    let n = pixels.len() as f64;
+    let [b, g, r] = sums.map(|sum| linear_to_srgb(sum / n));
+    Bgr8::new(r, g, b)
+}
+
+/// Bounds of `n` zones evenly dividing a length
+fn zone_bounds(len: u32, n: u32) -> Vec<usize> {
+    (0..=n)
+        .map(|i| (i as u64 * len as u64 / n as u64) as usize)
+        .collect()
+}
+
+/// Average colors of a grid of zones of a frame of the given size, row by row
+pub(crate) fn zone_averages(
+    pixels: &[Bgr8],
+    width: u32,
+    height: u32,
+    cols: u32,
+    rows: u32,
+) -> Vec<Bgr8> {
+    assert!(cols > 0 && rows > 0, "There must be at least one zone");
+    let xs = zone_bounds(width, cols);
+    let ys = zone_bounds(height, rows);
+    let mut sums = vec![[0u64; 3]; cols as usize * rows as usize];
+    for (zone_row, y_range) in ys.windows(2).enumerate() {
+        let zone_sums = &mut sums[zone_row * cols as usize..][..cols as usize];
+        for row in pixels[y_range[0] * width as usize..y_range[1] * width as usize]
+            .chunks_exact(width as usize)
+        {
+            for (sum, x_range) in zone_sums.iter_mut().zip(xs.windows(2)) {
+                let row_sums = channel_sums(&row[x_range[0]..x_range[1]]);
+                for (s, r) in sum.iter_mut().zip(&row_sums) {
+                    *s += r;
+                }
+            }
+        }
+    }
+    let mut zones = Vec::with_capacity(sums.len());
+    for (zone_row, y_range) in ys.windows(2).enumerate() {
+        for (zone_col, x_range) in xs.windows(2).enumerate() {
+            let n = ((y_range[1] - y_range[0]) * (x_range[1] - x_range[0])) as u64;
+            // Zones are empty if there are more zones than pixels
+            let [b, g, r] = sums[zone_row * cols as usize + zone_col]
+                .map(|sum| (sum + n / 2).checked_div(n).unwrap_or(0) as u8);
+            zones.push(Bgr8::new(r, g, b));
+        }
+    }
+    zones
+}
+
+/// Layout of LEDs along the edges of a screen, e.g. for bias lighting
+///
+/// LEDs are ordered clockwise, starting at the top left corner: the top edge
+/// left to right, the right edge top to bottom, the bottom edge right to left,
+/// and the left edge bottom to top. If `corners` is set, there's also one LED
+/// at each corner, before the edge following it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LedLayout {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub corners: bool,
+    /// Thickness in pixels of the strips sampled along the edges
+    pub thickness: u32,
+}
+
+impl LedLayout {
+    /// Total number of LEDs
+    pub fn len(&self) -> usize {
+        (self.top + self.right + self.bottom + self.left) as usize
+            + if self.corners { 4 } else { 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The regions sampled for each LED of a frame of the given size, in the
+    /// order of the LEDs
+    pub fn regions(&self, width: u32, height: u32) -> Vec<Rect> {
+        let t = self.thickness.min(width / 2).min(height / 2);
+        edge_regions(
+            [self.top, self.right, self.bottom, self.left],
+            [t; 4],
+            self.corners,
+            width,
+            height,
+        )
+    }
+}
+
+/// The regions of LEDs along the edges of a frame of the given size, of the
+/// given counts and thicknesses of the top, right, bottom, and left edges, in
+/// the order of `LedLayout`
+fn edge_regions(
+    [top, right, bottom, left]: [u32; 4],
+    thickness: [u32; 4],
+    corners: bool,
+    width: u32,
+    height: u32,
+) -> Vec<Rect> {
+    let tt = thickness[0].min(height);
+    let tb = thickness[2].min(height - tt);
+    let tl = thickness[3].min(width);
+    let tr = thickness[1].min(width - tl);
+    // Top and bottom strips leave room for the corners, and side strips
+    // always stay between the top and bottom strips
+    let (x0, strip_width) = if corners {
+        (tl, width - tl - tr)
+    } else {
+        (0, width)
+    };
+    let strip_height = height - tt - tb;
+    let span = |n: u32, len: u32, i: u32| {
+        let a = (i as u64 * len as u64 / n as u64) as u32;
+        let b = ((i + 1) as u64 * len as u64 / n as u64) as u32;
+        (a, b - a)
+    };
+    let mut regions = Vec::with_capacity((top + right + bottom + left) as usize + 4);
+    if corners {
+        regions.push(Rect {
+            x: 0,
+            y: 0,
+            width: tl,
+            height: tt,
+        });
+    }
+    regions.extend((0..top).map(|i| {
+        let (x, w) = span(top, strip_width, i);
+        Rect {
+            x: x0 + x,
+            y: 0,
+            width: w,
+            height: tt,
+        }
+    }));
+    if corners {
+        regions.push(Rect {
+            x: width - tr,
+            y: 0,
+            width: tr,
+            height: tt,
+        });
+    }
+    regions.extend((0..right).map(|i| {
+        let (y, h) = span(right, strip_height, i);
+        Rect {
+            x: width - tr,
+            y: tt + y,
+            width: tr,
+            height: h,
+        }
+    }));
+    if corners {
+        regions.push(Rect {
+            x: width - tr,
+            y: height - tb,
+            width: tr,
+            height: tb,
+        });
+    }
+    regions.extend((0..bottom).rev().map(|i| {
+        let (x, w) = span(bottom, strip_width, i);
+        Rect {
+            x: x0 + x,
+            y: height - tb,
+            width: w,
+            height: tb,
+        }
+    }));
+    if corners {
+        regions.push(Rect {
+            x: 0,
+            y: height - tb,
+            width: tl,
+            height: tb,
+        });
+    }
+    regions.extend((0..left).rev().map(|i| {
+        let (y, h) = span(left, strip_height, i);
+        Rect {
+            x: 0,
+            y: tt + y,
+            width: tl,
+            height: h,
+        }
+    }));
+    regions
+}
+
+/// Average colors of zones along each edge of a frame. See
+/// `Capturer::capture_edge_zones`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EdgeZones {
+    /// Zones of the top strip, left to right
+    pub top: Vec<Bgr8>,
+    /// Zones of the bottom strip, left to right
+    pub bottom: Vec<Bgr8>,
+    /// Zones of the left strip, top to bottom
+    pub left: Vec<Bgr8>,
+    /// Zones of the right strip, top to bottom
+    pub right: Vec<Bgr8>,
+}
+
+/// `EdgeZones` of strips of the given thicknesses of a frame, empty if
+/// `zones_per_edge` is 0
+pub(crate) fn edge_zones(
+    pixels: &[Bgr8],
+    width: u32,
+    height: u32,
+    [top, bottom, left, right]: [u32; 4],
+    zones_per_edge: u32,
+) -> EdgeZones {
+    let counts = [top, right, bottom, left].map(|t| if t > 0 { zones_per_edge } else { 0 });
+    let mut colors = edge_regions(counts, [top, right, bottom, left], false, width, height)
+        .into_iter()
+        .map(|region| region_average(pixels, width, region));
+    let mut edge = |n| colors.by_ref().take(n as usize).collect::<Vec<_>>();
+    let top = edge(counts[0]);
+    let right = edge(counts[1]);
+    let mut bottom = edge(counts[2]);
+    let mut left = edge(counts[3]);
+    // Clockwise in the order of `LedLayout`
+    bottom.reverse();
+    left.reverse();
+    EdgeZones {
+        top,
+        bottom,
+        left,
+        right,
+    }
+}
+
+/// Average color of a region of a frame of the given width
+pub(crate) fn region_average(pixels: &[Bgr8], width: u32, region: Rect) -> Bgr8 {
+    let mut sums = [0u64; 3];
+    for y in region.y..region.y + region.height {
+        let start = y as usize * width as usize + region.x as usize;
+        let row_sums = channel_sums(&pixels[start..start + region.width as usize]);
+        for (s, r) in sums.iter_mut().zip(&row_sums) {
+            *s += r;
+        }
+    }
+    let n = region.width as u64 * region.height as u64;
+    let [b, g, r] = sums.map(|sum| (sum + n / 2).checked_div(n).unwrap_or(0) as u8);
+    Bgr8::new(r, g, b)
+}
+
+/// Histograms of the red, green, blue, and luma values of the pixels of a
+/// frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    pub r: [u32; 256],
+    pub g: [u32; 256],
+    pub b: [u32; 256],
+    /// Luma with Rec. 709 coefficients, computed on the sRGB encoded components
+    pub luma: [u32; 256],
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            r: [0; 256],
+            g: [0; 256],
+            b: [0; 256],
+            luma: [0; 256],
+        }
+    }
+
+    /// Number of pixels counted
+    pub fn total(&self) -> u64 {
+        self.luma.iter().map(|&n| n as u64).sum()
+    }
+
+    /// Mean luma, from 0 to 255
+    pub fn mean_luma(&self) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            return 0.0;
+        }
+        let sum: u64 = self
+            .luma
+            .iter()
+            .enumerate()
+            .map(|(v, &n)| v as u64 * n as u64)
+            .sum();
+        sum as f64 / total as f64
+    }
+
+    /// Distance between the color distributions of two histograms, from 0 if
+    /// they are equal to 1 if they have no colors in common
+    ///
+    /// The mean over the red, green, and blue channels of half the L1
+    /// distance between the normalized histograms. Cheap enough to compute
+    /// for every frame, e.g. to detect scene changes.
+    pub fn distance(&self, other: &Histogram) -> f64 {
+        let (n, m) = (self.total() as f64, other.total() as f64);
+        if n == 0.0 || m == 0.0 {
+            return if n == m { 0.0 } else { 1.0 };
+        }
+        let channel = |a: &[u32; 256], b: &[u32; 256]| {
+            a.iter()
+                .zip(b)
+                .map(|(&x, &y)| (x as f64 / n - y as f64 / m).abs())
+                .sum::<f64>()
+                / 2.0
+        };
+        (channel(&self.r, &other.r) + channel(&self.g, &other.g) + channel(&self.b, &other.b)) / 3.0
+    }
+}
+
+/// Luma of a pixel with Rec. 709 coefficients, in 8-bit fixed point
+#[inline]
+pub(crate) fn luma(p: Bgr8) -> u8 {
+    ((54 * p.r as u32 + 183 * p.g as u32 + 19 * p.b as u32 + 128) >> 8) as u8
+}
+
+pub(crate) fn histogram(pixels: &[Bgr8]) -> Histogram {
+    let mut hist = Histogram::new();
+    let mut lumas = [0u8; 64];
+    for chunk in pixels.chunks(lumas.len()) {
+        // Computing the lumas of a chunk separately from the counting lets the
+        // compiler vectorize it
+        for (l, &p) in lumas.iter_mut().zip(chunk) {
+            *l = luma(p);
+        }
+        for (&l, p) in lumas.iter().zip(chunk) {
+            hist.r[p.r as usize] += 1;
+            hist.g[p.g as usize] += 1;
+            hist.b[p.b as usize] += 1;
+            hist.luma[l as usize] += 1;
+        }
+    }
+    hist
+}
+
+impl Frame {
+    /// The average color of the frame
+    ///
+    /// Averages the sRGB encoded components, which is fast but darkens mixes
+    /// of bright and dark colors. See `Self::average_color_linear`.
+    pub fn average_color(&self) -> Bgr8 {
+        average_color(self.pixels())
+    }
+
+    /// The average color of the frame, averaged in linear light
+    ///
+    /// Matches the perceived average color better than `Self::average_color`,
+    /// but is slower.
+    pub fn average_color_linear(&self) -> Bgr8 {
+        average_color_linear(self.pixels())
+    }
+
+    /// The average colors of a grid of `cols` by `rows` zones evenly dividing
+    /// the frame, row by row
+    ///
+    /// Computed in a single pass over the frame.
+    pub fn zone_averages(&self, cols: u32, rows: u32) -> Vec<Bgr8> {
+        zone_averages(self.pixels(), self.width(), self.height(), cols, rows)
+    }
+
+    /// Histograms of the red, green, blue, and luma values of the frame
+    pub fn histogram(&self) -> Histogram {
+        histogram(self.pixels())
+    }
+
+    /// The colors of LEDs along the edges of the screen, each the average
+    /// color of a strip along an edge. See `LedLayout` for the order.
+    pub fn edge_colors(&self, layout: &LedLayout) -> Vec<Bgr8> {
+        layout
+            .regions(self.width(), self.height())
+            .into_iter()
+            .map(|region| region_average(self.pixels(), self.width(), region))
+            .collect()
+    }
+}
+
+impl Capturer {
+    /// Capture the screen and return its average color
+    ///
+    /// The frame is stored in `self`, as by `Self::capture_store_frame`.
+    pub fn capture_average(&mut self) -> Result<Bgr8, CaptureError> {
+        self.capture_store_frame()?;
+        Ok(average_color(self.get_stored_frame().unwrap()))
+    }
+
+    /// Capture the screen and return its average color, averaged in linear
+    /// light
+    pub fn capture_average_linear(&mut self) -> Result<Bgr8, CaptureError> {
+        self.capture_store_frame()?;
+        Ok(average_color_linear(self.get_stored_frame().unwrap()))
+    }
+
+    /// Capture the screen and return the average colors of `zones_per_edge`
+    /// zones along strips of the given thicknesses in pixels at each edge,
+    /// e.g. for the LED strip of an ambilight
+    ///
+    /// The strips are sampled as by `LedLayout`, with the side strips between
+    /// the top and bottom strips. Edges with a thickness of 0 have no zones,
+    /// and all edges are empty if `zones_per_edge` is 0.
+    ///
+    /// Only the strips are read. Unless frames are cropped, scaled, or
+    /// otherwise post-processed, they are read straight from the backend's
+    /// frame, without copying it.
+    pub fn capture_edge_zones(
+        &mut self,
+        top: u32,
+        bottom: u32,
+        left: u32,
+        right: u32,
+        zones_per_edge: u32,
+    ) -> Result<EdgeZones, CaptureError> {
+        self.capture_store_frame()?;
+        let (pixels, width, height) = self.stored_frame_with_size().unwrap();
+        Ok(edge_zones(
+            pixels,
+            width,
+            height,
+            [top, bottom, left, right],
+            zones_per_edge,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod analysis_tests {
+    use super::*;
+
+    #[test]
+    fn test_average_color() {
+        let pixels: Vec<Bgr8> = (0..37u32)
+            .map(|i| Bgr8::new(i as u8 * 7, 255 - i as u8, 100))
+            .collect();
+        assert_eq!(channel_sums(&pixels), channel_sums_scalar(&pixels));
+        let frame = Frame::new(pixels, 37, 1);
+        assert_eq!(frame.average_color(), Bgr8::new(126, 237, 100));
+
+        let frame = Frame::new(vec![Bgr8::new(0, 0, 0), Bgr8::new(255, 255, 255)], 2, 1);
+        assert_eq!(frame.average_color(), Bgr8::new(128, 128, 128));
+        assert_eq!(frame.average_color_linear(), Bgr8::new(188, 188, 188));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let pixels = vec![
+            Bgr8::new(255, 255, 255),
+            Bgr8::new(0, 0, 0),
+            Bgr8::new(255, 0, 0),
+        ];
+        let hist = Frame::new(pixels, 3, 1).histogram();
+        assert_eq!(
+            (hist.r[255], hist.r[0], hist.g[0], hist.b[255]),
+            (2, 1, 2, 1)
+        );
+        assert_eq!((hist.luma[255], hist.luma[0], hist.luma[54]), (1, 1, 1));
+        assert_eq!(hist.total(), 3);
+        assert_eq!(hist.mean_luma(), 103.0);
+    }
+
+    #[test]
+    fn test_zone_averages() {
+        // Left column black, right two columns white, over two rows
+        let (black, white) = (Bgr8::new(0, 0, 0), Bgr8::new(255, 255, 255));
+        let pixels = vec![black, white, white, black, white, white];
+        let frame = Frame::new(pixels, 3, 2);
+        assert_eq!(frame.zone_averages(3, 1), vec![black, white, white]);
+        // The zones are columns 0 and 1..3
+        assert_eq!(frame.zone_averages(2, 2), vec![black, white, black, white]);
+        assert_eq!(frame.zone_averages(1, 1), vec![Bgr8::new(170, 170, 170)]);
+    }
+
+    #[test]
+    fn test_edge_colors() {
+        // 4x4 frame with a red top row, and a blue right column below it
+        let (red, blue, black) = (
+            Bgr8::new(255, 0, 0),
+            Bgr8::new(0, 0, 255),
+            Bgr8::new(0, 0, 0),
+        );
+        let mut pixels = vec![black; 16];
+        pixels[..4].fill(red);
+        for y in 1..4 {
+            pixels[y * 4 + 3] = blue;
+        }
+        let frame = Frame::new(pixels, 4, 4);
+        let layout = LedLayout {
+            top: 2,
+            right: 1,
+            bottom: 1,
+            left: 1,
+            corners: true,
+            thickness: 1,
+        };
+        assert_eq!(layout.len(), 9);
+        assert_eq!(
+            frame.edge_colors(&layout),
+            vec![red, red, red, red, blue, blue, black, black, black]
+        );
+    }
+
+    #[test]
+    fn test_edge_zones() {
+        // 4x4 frame with a red left half of the top two rows
+        let (red, black) = (Bgr8::new(255, 0, 0), Bgr8::new(0, 0, 0));
+        let mut pixels = vec![black; 16];
+        pixels[..2].fill(red);
+        pixels[4..6].fill(red);
+        let zones = edge_zones(&pixels, 4, 4, [1, 1, 2, 0], 2);
+        assert_eq!(zones.top, vec![red, black]);
+        assert_eq!(zones.bottom, vec![black, black]);
+        assert_eq!(zones.left, vec![red, black]);
+        assert!(zones.right.is_empty());
+        assert_eq!(
+            edge_zones(&pixels, 4, 4, [1, 1, 2, 0], 0),
+            EdgeZones::default()
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! Capture on Android through `MediaProjection`, with the `android` feature
+//!
+//! The user has to grant a `MediaProjection` to the app, e.g. after starting
+//! the intent of `MediaProjectionManager.createScreenCaptureIntent`, which
+//! the app then passes to `set_media_projection` from a native method.
+//! Capturers mirror the default display into a `VirtualDisplay` rendering into
+//! an `ImageReader`, and copy out its latest image.
+//!
+//! ```kotlin
+//! val projection = manager.getMediaProjection(resultCode, data)
+//! // Required before capture on Android 14 and later
+//! projection.registerCallback(callback, handler)
+//! NativeLib.setMediaProjection(projection)
+//! ```
+//!
+//! On Android 14 and later, a projection can only be captured by one
+//! capturer, and a new one has to be requested for the next.
+
+use crate::backend::Backend;
+use crate::{Bgr8, CaptureError, InitError};
+use jni::errors::Error;
+use jni::objects::{JByteBuffer, JObject, JObjectArray, JValue};
+use jni::refs::Global;
+use jni::{jni_sig, jni_str, Env, JavaVM};
+use std::ffi::c_void;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `PixelFormat.RGBA_8888`
+const PIXEL_FORMAT_RGBA_8888: i32 = 1;
+/// `DisplayManager.VIRTUAL_DISPLAY_FLAG_AUTO_MIRROR`
+const VIRTUAL_DISPLAY_FLAG_AUTO_MIRROR: i32 = 16;
+/// Images the reader may hold at once, one being copied and one being
+/// rendered
+const MAX_IMAGES: i32 = 2;
+/// How often to look for a new image while waiting for one
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// The projection granted by the user
+static PROJECTION: Mutex<Option<Global<JObject<'static>>>> = Mutex::new(None);
+
+/// Set the `MediaProjection` to capture with, as granted by the user
+///
+/// Fails with `InitError::InvalidArgument` if a pointer is null.
+///
+/// # Safety
+///
+/// `vm` must be a valid `JavaVM`, and `media_projection` a valid reference
+/// to an `android.media.projection.MediaProjection` in the current thread,
+/// e.g. an argument of a native method.
+pub unsafe fn set_media_projection(
+    vm: *mut c_void,
+    media_projection: *mut c_void,
+) -> Result<(), InitError> {
+    if vm.is_null() || media_projection.is_null() {
+        return Err(InitError::InvalidArgument(
+            "The JavaVM and MediaProjection must not be null".to_string(),
+        ));
+    }
+    let vm = JavaVM::from_raw(vm.cast());
+    let projection = with_env(&vm, |env| {
+        let projection = JObject::from_raw(env, media_projection.cast());
+        env.new_global_ref(projection)
+    })
+    .map_err(init_error)?;
+    *PROJECTION.lock().unwrap() = Some(projection);
+    Ok(())
+}
+
+/// Forget the `MediaProjection` set with `set_media_projection`, e.g. after
+/// it was stopped. Existing capturers keep capturing until it is stopped.
+pub fn clear_media_projection() {
+    PROJECTION.lock().unwrap().take();
+}
+
+/// Whether a projection was set with `set_media_projection`
+pub(crate) fn projection_set() -> bool {
+    PROJECTION.lock().unwrap().is_some()
+}
+
+/// Width, height and DPI of the default display, once the VM is known
+pub(crate) fn display_metrics() -> Option<(u32, u32, u32)> {
+    let vm = JavaVM::singleton().ok()?;
+    with_env(&vm, metrics).ok()
+}
+
+fn init_error(e: Error) -> InitError {
+    if is_security_exception(&e) {
+        InitError::PermissionDenied(e.to_string())
+    } else {
+        InitError::Other(e.to_string())
+    }
+}
+
+fn capture_error(e: Error) -> CaptureError {
+    if is_security_exception(&e) {
+        CaptureError::PermissionDenied
+    } else {
+        CaptureError::Fail(e.to_string())
+    }
+}
+
+/// Whether an error is a `SecurityException`, e.g. as the projection was
+/// stopped or already used
+fn is_security_exception(e: &Error) -> bool {
+    matches!(e, Error::CaughtJavaException { name, .. } if name == "java.lang.SecurityException")
+}
+
+/// Call `f` with the current thread attached to the VM, in a local frame, and
+/// catch any Java exception it throws
+fn with_env<T>(vm: &JavaVM, f: impl FnOnce(&mut Env) -> Result<T, Error>) -> Result<T, Error> {
+    vm.attach_current_thread(|env| {
+        env.with_local_frame(16, |env| match f(env) {
+            Err(Error::JavaException) => {
+                env.exception_catch()?;
+                Err(Error::JavaException)
+            }
+            res => res,
+        })
+    })
+}
+
+/// Width, height and DPI of the default display
+fn metrics(env: &mut Env) -> Result<(u32, u32, u32), Error> {
+    let resources = env
+        .call_static_method(
+            jni_str!("android/content/res/Resources"),
+            jni_str!("getSystem"),
+            jni_sig!("()Landroid/content/res/Resources;"),
+            &[],
+        )?
+        .l()?;
+    let metrics = env
+        .call_method(
+            &resources,
+            jni_str!("getDisplayMetrics"),
+            jni_sig!("()Landroid/util/DisplayMetrics;"),
+            &[],
+        )?
+        .l()?;
+    let mut field = |name| -> Result<u32, Error> {
+        Ok(env.get_field(&metrics, name, jni_sig!("I"))?.i()? as u32)
+    };
+    Ok((
+        field(jni_str!("widthPixels"))?,
+        field(jni_str!("heightPixels"))?,
+        field(jni_str!("densityDpi"))?,
+    ))
+}
+
+/// An `ImageReader` of RGBA images of a size
+fn new_reader(env: &mut Env, width: u32, height: u32) -> Result<Global<JObject<'static>>, Error> {
+    let reader = env
+        .call_static_method(
+            jni_str!("android/media/ImageReader"),
+            jni_str!("newInstance"),
+            jni_sig!("(IIII)Landroid/media/ImageReader;"),
+            &[
+                JValue::Int(width as i32),
+                JValue::Int(height as i32),
+                JValue::Int(PIXEL_FORMAT_RGBA_8888),
+                JValue::Int(MAX_IMAGES),
+            ],
+        )?
+        .l()?;
+    env.new_global_ref(reader)
+}
+
+/// The `Surface` images are rendered to by a reader
+fn surface<'local>(env: &mut Env<'local>, reader: &JObject) -> Result<JObject<'local>, Error> {
+    env.call_method(
+        reader,
+        jni_str!("getSurface"),
+        jni_sig!("()Landroid/view/Surface;"),
+        &[],
+    )?
+    .l()
+}
+
+/// Backend capturing the default display through a `VirtualDisplay` of the
+/// `MediaProjection`
+pub(crate) struct ProjectionBackend {
+    vm: JavaVM,
+    reader: Global<JObject<'static>>,
+    display: Global<JObject<'static>>,
+    width: u32,
+    height: u32,
+    frame: Option<Vec<Bgr8>>,
+    /// Size of the captured frame
+    frame_size: (u32, u32),
+}
+
+impl ProjectionBackend {
+    /// Mirror the default display, the only capture source on Android, with
+    /// the projection set by `set_media_projection`
+    pub(crate) fn new(capture_src: usize) -> Result<ProjectionBackend, InitError> {
+        if capture_src != 0 {
+            return Err(InitError::NotFound(format!(
+                "No display {}. Only the default display, 0, can be captured on Android",
+                capture_src
+            )));
+        }
+        let projection = PROJECTION.lock().unwrap();
+        let projection = projection.as_ref().ok_or_else(|| {
+            InitError::PermissionDenied(
+                "No MediaProjection was set with `captrs::android::set_media_projection`"
+                    .to_string(),
+            )
+        })?;
+        let vm = JavaVM::singleton().map_err(init_error)?;
+        let ((width, height), reader, display) = with_env(&vm, |env| {
+            let (width, height, dpi) = metrics(env)?;
+            let reader = new_reader(env, width, height)?;
+            let surface = surface(env, &reader)?;
+            let name = env.new_string("captrs")?;
+            let null = JObject::null();
+            let display = env
+                .call_method(
+                    projection.as_obj(),
+                    jni_str!("createVirtualDisplay"),
+                    jni_sig!(
+                        "(Ljava/lang/String;IIIILandroid/view/Surface;\
+                         Landroid/hardware/display/VirtualDisplay$Callback;Landroid/os/Handler;)\
+                         Landroid/hardware/display/VirtualDisplay;"
+                    ),
+                    &[
+                        JValue::Object(&name),
+                        JValue::Int(width as i32),
+                        JValue::Int(height as i32),
+                        JValue::Int(dpi as i32),
+                        JValue::Int(VIRTUAL_DISPLAY_FLAG_AUTO_MIRROR),
+                        JValue::Object(&surface),
+                        JValue::Object(&null),
+                        JValue::Object(&null),
+                    ],
+                )?
+                .l()?;
+            Ok(((width, height), reader, env.new_global_ref(display)?))
+        })
+        .map_err(init_error)?;
+        Ok(ProjectionBackend {
+            vm,
+            reader,
+            display,
+            width,
+            height,
+            frame: None,
+            frame_size: (0, 0),
+        })
+    }
+
+    /// Copy the latest image of the reader into the frame, if there is a new
+    /// one, and return its size
+    fn copy_latest(&mut self, env: &mut Env) -> Result<Option<(u32, u32)>, Error> {
+        let image = env
+            .call_method(
+                self.reader.as_obj(),
+                jni_str!("acquireLatestImage"),
+                jni_sig!("()Landroid/media/Image;"),
+                &[],
+            )?
+            .l()?;
+        if image.is_null() {
+            return Ok(None);
+        }
+        let frame = self.frame.get_or_insert_with(Vec::new);
+        let copied = copy_image(env, &image, frame);
+        if !env.exception_check() {
+            env.call_method(&image, jni_str!("close"), jni_sig!("()V"), &[])?
+                .v()?;
+        }
+        copied.map(Some)
+    }
+}
+
+/// Copy the pixels of an RGBA `Image` into `frame`, and return its size
+fn copy_image(env: &mut Env, image: &JObject, frame: &mut Vec<Bgr8>) -> Result<(u32, u32), Error> {
+    let mut int = |obj: &JObject, name| -> Result<usize, Error> {
+        Ok(env.call_method(obj, name, jni_sig!("()I"), &[])?.i()? as usize)
+    };
+    let (width, height) = (
+        int(image, jni_str!("getWidth"))?,
+        int(image, jni_str!("getHeight"))?,
+    );
+    let planes = env
+        .call_method(
+            image,
+            jni_str!("getPlanes"),
+            jni_sig!("()[Landroid/media/Image$Plane;"),
+            &[],
+        )?
+        .l()?;
+    let plane = env
+        .cast_local::<JObjectArray>(planes)?
+        .get_element(env, 0)?;
+    let mut int = |obj: &JObject, name| -> Result<usize, Error> {
+        Ok(env.call_method(obj, name, jni_sig!("()I"), &[])?.i()? as usize)
+    };
+    let (row_stride, pixel_stride) = (
+        int(&plane, jni_str!("getRowStride"))?,
+        int(&plane, jni_str!("getPixelStride"))?,
+    );
+    let buffer = env
+        .call_method(
+            &plane,
+            jni_str!("getBuffer"),
+            jni_sig!("()Ljava/nio/ByteBuffer;"),
+            &[],
+        )?
+        .l()?;
+    let buffer = env.cast_local::<JByteBuffer>(buffer)?;
+    let data = env.get_direct_buffer_address(&buffer)?;
+    let len = env.get_direct_buffer_capacity(&buffer)?;
+    if pixel_stride != 4 || row_stride < 4 * width || len < row_stride * height {
+        return Err(Error::JniCall(jni::errors::JniError::InvalidArguments));
+    }
+    // Valid while the image is open
+    let bytes = unsafe { std::slice::from_raw_parts(data, len) };
+    frame.resize(width * height, Bgr8::default());
+    for (src, dst) in bytes.chunks(row_stride).zip(frame.chunks_exact_mut(width)) {
+        crate::convert::from_rgba(src, dst);
+    }
+    Ok((width as u32, height as u32))
+}
+
+impl Backend for ProjectionBackend {
+    fn name(&self) -> &'static str {
+        "MediaProjection"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        let (width, height) = self.frame_size;
+        self.frame.as_deref().map(|frame| (frame, width, height))
+    }
+
+    /// Wait up to `timeout` for a new image, and copy it
+    ///
+    /// Images are only rendered when the display changes, so this times out
+    /// when the screen is still. Fails with `CaptureError::AccessLost` when
+    /// the size of the display changed, e.g. as it was rotated.
+    fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+        let start = Instant::now();
+        let vm = self.vm.clone();
+        loop {
+            let (copied, size) = with_env(&vm, |env| Ok((self.copy_latest(env)?, metrics(env)?)))
+                .map_err(capture_error)?;
+            if (size.0, size.1) != (self.width, self.height) {
+                return Err(CaptureError::AccessLost);
+            }
+            if let Some(frame_size) = copied {
+                self.frame_size = frame_size;
+                return Ok(());
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(CaptureError::Timeout);
+            }
+            std::thread::sleep(POLL_INTERVAL.min(timeout - elapsed));
+        }
+    }
+
+    /// Resizes the virtual display to the default display, e.g. after it was
+    /// rotated
+    fn reacquire(&mut self) -> Result<(), CaptureError> {
+        let vm = self.vm.clone();
+        let (width, height, reader) = with_env(&vm, |env| {
+            let (width, height, dpi) = metrics(env)?;
+            let reader = new_reader(env, width, height)?;
+            let surface = surface(env, &reader)?;
+            env.call_method(
+                self.display.as_obj(),
+                jni_str!("setSurface"),
+                jni_sig!("(Landroid/view/Surface;)V"),
+                &[JValue::Object(&surface)],
+            )?
+            .v()?;
+            env.call_method(
+                self.display.as_obj(),
+                jni_str!("resize"),
+                jni_sig!("(III)V"),
+                &[
+                    JValue::Int(width as i32),
+                    JValue::Int(height as i32),
+                    JValue::Int(dpi as i32),
+                ],
+            )?
+            .v()?;
+            env.call_method(
+                self.reader.as_obj(),
+                jni_str!("close"),
+                jni_sig!("()V"),
+                &[],
+            )?
+            .v()?;
+            Ok((width, height, reader))
+        })
+        .map_err(capture_error)?;
+        self.width = width;
+        self.height = height;
+        self.reader = reader;
+        Ok(())
+    }
+}
+
+impl Drop for ProjectionBackend {
+    fn drop(&mut self) {
+        let vm = self.vm.clone();
+        let _ = with_env(&vm, |env| {
+            env.call_method(
+                self.display.as_obj(),
+                jni_str!("release"),
+                jni_sig!("()V"),
+                &[],
+            )?
+            .v()?;
+            env.call_method(
+                self.reader.as_obj(),
+                jni_str!("close"),
+                jni_sig!("()V"),
+                &[],
+            )?
+            .v()
+        });
+    }
+}
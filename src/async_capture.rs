@@ -0,0 +1,133 @@
+//! Asynchronous capture, for async runtimes such as tokio
+//!
+//! A `Capturer` captures by blocking, so it runs on a dedicated thread of an
+//! `AsyncCapturer`, and frames are awaited without blocking the runtime.
+//!
+//! ```no_run
+//! use captrs::async_capture::AsyncCapturer;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let capturer = AsyncCapturer::new(0)?;
+//! let frame = capturer.capture_frame_async().await?;
+//! println!("{}x{}", frame.width(), frame.height());
+//! let frames = capturer.frames(); // impl Stream<Item = Result<Frame, CaptureError>>
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{CaptureError, Capturer, Frame, InitError};
+use futures_channel::oneshot;
+use futures_core::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::task::{Context, Poll};
+use std::thread;
+
+type Reply = oneshot::Sender<Result<Frame, CaptureError>>;
+
+fn thread_stopped() -> CaptureError {
+    CaptureError::Fail("Capture thread stopped".to_string())
+}
+
+/// A capturer running on a thread of its own
+///
+/// The thread stops when the `AsyncCapturer` and all its `FrameStream`s are
+/// dropped.
+pub struct AsyncCapturer {
+    requests: mpsc::Sender<Reply>,
+}
+
+impl AsyncCapturer {
+    /// Start capturing the given capture source, e.g. a display
+    ///
+    /// Blocks until the capturer is constructed.
+    pub fn new(capture_src: usize) -> Result<AsyncCapturer, InitError> {
+        AsyncCapturer::spawn(move || Capturer::new(capture_src))
+    }
+
+    /// Start capturing with a capturer constructed by `make` on the capture
+    /// thread, e.g. with a `CapturerBuilder`
+    ///
+    /// Blocks until the capturer is constructed.
+    pub fn spawn<F>(make: F) -> Result<AsyncCapturer, InitError>
+    where
+        F: FnOnce() -> Result<Capturer, InitError> + Send + 'static,
+    {
+        let (init_tx, init_rx) = mpsc::channel();
+        let (requests, requests_rx) = mpsc::channel::<Reply>();
+        thread::Builder::new()
+            .name("captrs-capture".to_string())
+            .spawn(move || {
+                let mut capturer = match make() {
+                    Ok(capturer) => capturer,
+                    Err(e) => return init_tx.send(Err(e)).unwrap_or(()),
+                };
+                let _ = init_tx.send(Ok(()));
+                for reply in requests_rx {
+                    if !reply.is_canceled() {
+                        let _ = reply.send(capturer.capture_frame_meta());
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())?;
+        init_rx
+            .recv()
+            .map_err(|_| "Capture thread panicked".to_string())??;
+        Ok(AsyncCapturer { requests })
+    }
+
+    /// Capture a frame on the capture thread
+    pub fn capture_frame_async(&self) -> impl Future<Output = Result<Frame, CaptureError>> {
+        let (reply, frame) = oneshot::channel();
+        let sent = self.requests.send(reply).is_ok();
+        async move {
+            if !sent {
+                return Err(thread_stopped());
+            }
+            frame.await.unwrap_or_else(|_| Err(thread_stopped()))
+        }
+    }
+
+    /// A stream of frames, captured as they are polled
+    pub fn frames(&self) -> FrameStream {
+        FrameStream {
+            requests: self.requests.clone(),
+            pending: None,
+        }
+    }
+}
+
+/// A stream of captured frames, from `AsyncCapturer::frames`
+///
+/// Each frame is captured when the stream is polled, so frames are never
+/// buffered. Capture errors, including timeouts, are yielded as items. The
+/// stream ends if the capture thread stops.
+pub struct FrameStream {
+    requests: mpsc::Sender<Reply>,
+    pending: Option<oneshot::Receiver<Result<Frame, CaptureError>>>,
+}
+
+impl Stream for FrameStream {
+    type Item = Result<Frame, CaptureError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let pending = match &mut this.pending {
+            Some(pending) => pending,
+            None => {
+                let (reply, frame) = oneshot::channel();
+                if this.requests.send(reply).is_err() {
+                    return Poll::Ready(None);
+                }
+                this.pending.insert(frame)
+            }
+        };
+        let res = match Pin::new(pending).poll(cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.pending = None;
+        Poll::Ready(res.ok())
+    }
+}
@@ -0,0 +1,810 @@
+//! Capture backends, and the selection of them at runtime
+//!
+//! A `Capturer` captures through a boxed `Backend`, which can be a custom one
+//! given to `Capturer::with_backend`.
+
+use crate::coords::Rotation;
+use crate::hdr::ToneMapping;
+use crate::{
+    Bgr8, CaptureError, CaptureSource, InitError, PixelFormat, Rect, Visibility, WindowId,
+};
+use std::time::{Duration, Instant};
+
+/// A source of captured frames, e.g. DXGI desktop duplication or X11
+///
+/// Backends are `Send`, so that capturers can be moved between threads, but
+/// are only used by one thread at a time.
+pub trait Backend: Send {
+    /// Name of the backend, e.g. for diagnostics
+    fn name(&self) -> &'static str;
+
+    /// Width and height of the area to capture
+    fn geometry(&self) -> (u32, u32);
+
+    /// Horizontal and vertical offset of the capture source from the primary
+    /// display
+    fn position(&self) -> (i32, i32) {
+        (0, 0)
+    }
+
+    /// Capture a frame and store it for `Self::frame`
+    ///
+    /// Backends that wait for new frames should time out after `timeout`
+    /// with `CaptureError::Timeout`.
+    fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError>;
+
+    /// The last captured frame and its width and height, if a frame has been
+    /// captured
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)>;
+
+    /// Regions of the last captured frame that changed since the previous
+    /// captured frame, if the backend tracks damage
+    ///
+    /// The first frame is entirely dirty.
+    fn dirty_rects(&self) -> Option<&[Rect]> {
+        None
+    }
+
+    /// Capture only `region` of the capture source, or all of it if `None`
+    ///
+    /// Returns whether the backend supports capturing a region, in which
+    /// case `Self::geometry` is the size of the region. If not, the
+    /// `Capturer` crops the frames of the whole source instead.
+    fn set_region(&mut self, region: Option<Rect>) -> bool {
+        let _ = region;
+        false
+    }
+
+    /// Time at which the last captured frame was presented on the display,
+    /// if reported
+    fn present_time(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Whether the last captured frame has new content, as opposed to e.g.
+    /// only the cursor being moved. `None` if not reported.
+    ///
+    /// Backends reporting `Self::dirty_rects` need not implement this.
+    fn content_changed(&self) -> Option<bool> {
+        None
+    }
+
+    /// Whether to rotate the frames of rotated displays upright, to the
+    /// orientation of the desktop. Backends that only capture upright frames
+    /// ignore this.
+    fn set_upright(&mut self, upright: bool) {
+        let _ = upright;
+    }
+
+    /// Whether to read frames immediately rather than wait for new content,
+    /// for `crate::LatencyMode::LowLatency`. Backends that don't wait ignore
+    /// this.
+    fn set_immediate(&mut self, immediate: bool) {
+        let _ = immediate;
+    }
+
+    /// Clockwise rotation of the frames relative to the desktop
+    fn rotation(&self) -> Rotation {
+        Rotation::Identity
+    }
+
+    /// How much of the captured window was shown at the last capture, if a
+    /// window is captured and its visibility is known
+    fn visibility(&self) -> Option<Visibility> {
+        None
+    }
+
+    /// Capture in the HDR format of the display if `Some`, tone mapping to
+    /// the 8-bit frames of `Self::frame`
+    ///
+    /// `None` if the backend only captures 8-bit frames.
+    fn set_hdr(&mut self, tone_mapping: Option<ToneMapping>) -> Option<Result<(), InitError>> {
+        let _ = tone_mapping;
+        None
+    }
+
+    /// The last captured frame in the HDR format of the display, of the same
+    /// size as `Self::frame`, if captured in one
+    fn hdr_frame(&self) -> Option<(&[u8], PixelFormat)> {
+        None
+    }
+
+    /// Capture a frame into a shareable D3D11 texture, without copying it to
+    /// the CPU. `None` if the backend doesn't capture with D3D11.
+    #[cfg(all(windows, feature = "d3d11"))]
+    fn capture_d3d11_texture(
+        &mut self,
+        timeout: Duration,
+    ) -> Option<Result<crate::d3d11::D3d11Frame, CaptureError>> {
+        let _ = timeout;
+        None
+    }
+
+    /// The DMA-BUF the last frame was captured into, if the backend captures
+    /// into GPU memory
+    #[cfg(target_os = "linux")]
+    fn dmabuf(&self) -> Option<crate::dmabuf::DmaBuf> {
+        None
+    }
+
+    /// Switch to capturing the monitor with the given index, keeping the
+    /// backend
+    ///
+    /// `None` if the backend can't switch, in which case the `Capturer`
+    /// replaces it with the default backend of the monitor.
+    fn set_source(&mut self, capture_src: usize) -> Option<Result<(), InitError>> {
+        let _ = capture_src;
+        None
+    }
+
+    /// Release the capture source while the `Capturer` is paused, e.g. the
+    /// desktop duplication, keeping the configuration of the backend
+    fn pause(&mut self) {}
+
+    /// Acquire the capture source released by `Self::pause` again
+    fn resume(&mut self) -> Result<(), CaptureError> {
+        Ok(())
+    }
+
+    /// Reacquire the capture source after access to it was lost. Called
+    /// before retrying a capture that failed with `CaptureError::AccessLost`.
+    fn reacquire(&mut self) -> Result<(), CaptureError> {
+        Ok(())
+    }
+}
+
+/// The backend of the platform for a capture source
+///
+/// DXGI desktop duplication on Windows, falling back to GDI when duplication
+/// is unavailable, e.g. over some RDP sessions, and `CGDisplayStream` on
+/// macOS. On other platforms, the ScreenCast portal is tried first in Wayland
+/// sessions with the `wayland` feature, falling back to X11, e.g. through
+/// XWayland, which is captured through XCB with the `x11-xcb` feature. On
+/// Linux without a display server, the framebuffers of DRM/KMS are captured,
+/// see `crate::drm`. On Android, the `MediaProjection` passed to
+/// `crate::android::set_media_projection` is captured, with the `android`
+/// feature.
+pub fn default_backend(capture_src: usize) -> Result<Box<dyn Backend>, InitError> {
+    #[cfg(windows)]
+    return windows_backend(capture_src, Duration::from_millis(200), None);
+    #[cfg(target_os = "macos")]
+    return crate::macos::Stream::new(capture_src)
+        .map(|backend| Box::new(backend) as Box<dyn Backend>);
+    #[cfg(all(target_os = "android", feature = "android"))]
+    return crate::android::ProjectionBackend::new(capture_src)
+        .map(|backend| Box::new(backend) as Box<dyn Backend>);
+    #[cfg(all(target_os = "android", not(feature = "android")))]
+    return Err(InitError::Unsupported(format!(
+        "Capture of display {} on Android requires the `android` feature",
+        capture_src
+    )));
+    #[cfg(target_arch = "wasm32")]
+    return Err(InitError::Unsupported(format!(
+        "Capture of display {} is asynchronous in browsers. Use `captrs::web::WebCapturer`",
+        capture_src
+    )));
+    #[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+    return unix_backend(capture_src, crate::X11Api::default());
+}
+
+/// The backend of a capture source on platforms with X11, capturing X11
+/// through `x11_api`, see `default_backend`
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+pub(crate) fn unix_backend(
+    capture_src: usize,
+    x11_api: crate::X11Api,
+) -> Result<Box<dyn Backend>, InitError> {
+    {
+        #[cfg(feature = "wayland")]
+        let mut wayland_err = None;
+        #[cfg(feature = "wayland")]
+        if crate::wayland::is_wayland_session() {
+            match crate::wayland::Session::new(capture_src) {
+                Ok(session) => return Ok(Box::new(session)),
+                Err(e) => wayland_err = Some(e),
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return crate::drm::DrmBackend::new(capture_src)
+                .map(|backend| Box::new(backend) as Box<dyn Backend>);
+        }
+        let x11 = match x11_api {
+            crate::X11Api::Xlib => crate::x11::X11Backend::new(capture_src)
+                .map(|backend| Box::new(backend) as Box<dyn Backend>),
+            #[cfg(feature = "x11-xcb")]
+            crate::X11Api::Xcb => crate::xcb::XcbBackend::new(capture_src)
+                .map(|backend| Box::new(backend) as Box<dyn Backend>),
+        };
+        #[cfg(feature = "wayland")]
+        let x11 = x11.map_err(|e| match wayland_err {
+            Some(wayland_err) => InitError::Other(format!("{}. Wayland: {}", e, wayland_err)),
+            None => e,
+        });
+        x11
+    }
+}
+
+/// The backend of the platform capturing a single window
+///
+/// `Windows.Graphics.Capture` on Windows, and X11 with XComposite on other
+/// platforms. Not supported on macOS, Android and in browsers.
+pub fn window_backend(window: WindowId) -> Result<Box<dyn Backend>, InitError> {
+    #[cfg(windows)]
+    return crate::wgc::WgcBackend::new_window(window)
+        .map(|backend| Box::new(backend) as Box<dyn Backend>);
+    #[cfg(target_os = "macos")]
+    return Err(InitError::Unsupported(format!(
+        "Capture of window {:?} is not supported on macOS",
+        window
+    )));
+    #[cfg(target_os = "android")]
+    return Err(InitError::Unsupported(format!(
+        "Capture of window {:?} is not supported on Android",
+        window
+    )));
+    #[cfg(target_arch = "wasm32")]
+    return Err(InitError::Unsupported(format!(
+        "Capture of window {:?} is not supported in browsers",
+        window
+    )));
+    #[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+    return crate::x11::X11WindowBackend::new(window)
+        .map(|backend| Box::new(backend) as Box<dyn Backend>);
+}
+
+/// DXGI desktop duplication of a capture source, or GDI if duplication can't
+/// be initialized
+#[cfg(windows)]
+pub(crate) fn windows_backend(
+    capture_src: usize,
+    timeout: Duration,
+    adapter: Option<crate::AdapterSelector>,
+) -> Result<Box<dyn Backend>, InitError> {
+    match crate::dxgi::DxgiBackend::new(capture_src, timeout, adapter) {
+        Ok(backend) => Ok(Box::new(backend)),
+        Err(e) => match crate::gdi::GdiBackend::new(capture_src) {
+            Ok(backend) => Ok(Box::new(backend)),
+            Err(_) => Err(e),
+        },
+    }
+}
+
+/// The backend of the platform for a capture source, see `default_backend`
+/// and `window_backend`
+///
+/// The virtual desktop is captured with a `MultiCapturer`.
+pub fn source_backend(source: CaptureSource) -> Result<Box<dyn Backend>, InitError> {
+    match source {
+        CaptureSource::Monitor(capture_src) => default_backend(capture_src),
+        CaptureSource::Name(name) => default_backend(crate::display::display_index(&name)?),
+        CaptureSource::Window(window) => window_backend(window),
+        CaptureSource::VirtualDesktop => {
+            crate::multi::VirtualDesktop::new().map(|backend| Box::new(backend) as Box<dyn Backend>)
+        }
+        CaptureSource::Screen => screen_backend(),
+    }
+}
+
+/// The backend capturing the whole screen of the X server, or the virtual
+/// desktop without one, see `CaptureSource::Screen`
+fn screen_backend() -> Result<Box<dyn Backend>, InitError> {
+    #[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+    {
+        #[cfg(feature = "wayland")]
+        let wayland = crate::wayland::is_wayland_session();
+        #[cfg(not(feature = "wayland"))]
+        let wayland = false;
+        if !wayland && std::env::var_os("DISPLAY").is_some() {
+            return crate::x11::X11Backend::new_screen()
+                .map(|backend| Box::new(backend) as Box<dyn Backend>);
+        }
+    }
+    crate::multi::VirtualDesktop::new().map(|backend| Box::new(backend) as Box<dyn Backend>)
+}
+
+#[cfg(test)]
+mod backend_tests {
+    use super::*;
+    use crate::{Backoff, Capturer, Frame, RecoveryPolicy, RetryOn, RetryPolicy};
+
+    /// Backend of a 2x1 frame, losing access on the first capture
+    struct Flaky {
+        lost: bool,
+        frame: Option<Vec<Bgr8>>,
+    }
+
+    impl Backend for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (2, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            if !self.lost {
+                self.lost = true;
+                return Err(CaptureError::AccessLost);
+            }
+            self.frame = Some(vec![Bgr8::new(1, 2, 3); 2]);
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            self.frame.as_deref().map(|frame| (frame, 2, 1))
+        }
+    }
+
+    #[test]
+    fn test_custom_backend() {
+        let flaky = || Flaky {
+            lost: false,
+            frame: None,
+        };
+        let mut capturer = Capturer::with_backend(Box::new(flaky()));
+        assert_eq!(capturer.backend_name(), "flaky");
+        assert!(matches!(
+            capturer.capture_frame(),
+            Err(CaptureError::AccessLost)
+        ));
+        assert_eq!(
+            capturer.capture_frame().unwrap(),
+            vec![Bgr8::new(1, 2, 3); 2]
+        );
+
+        let mut capturer = Capturer::with_backend(Box::new(flaky()));
+        capturer.set_recovery_policy(RecoveryPolicy::Retry {
+            attempts: 1,
+            delay: Duration::ZERO,
+        });
+        capturer.capture_store_frame().unwrap();
+        assert_eq!(capturer.get_stored_frame().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let mut retry = RetryPolicy {
+            max_attempts: 1,
+            backoff: Backoff::None,
+            retry_on: RetryOn::TRANSIENT,
+        };
+        let mut capturer = Capturer::with_backend(Box::new(Flaky {
+            lost: false,
+            frame: None,
+        }));
+        capturer.set_retry_policy(Some(retry));
+        assert!(matches!(
+            capturer.capture_store_frame(),
+            Err(CaptureError::AccessLost)
+        ));
+
+        retry.max_attempts = 2;
+        let mut capturer = Capturer::with_backend(Box::new(Flaky {
+            lost: false,
+            frame: None,
+        }));
+        capturer.set_retry_policy(Some(retry));
+        capturer.capture_store_frame().unwrap();
+        assert_eq!(capturer.stats().recoveries, 1);
+    }
+
+    /// Backend of a 3x2 frame of pixels with their index as red, of which
+    /// the bottom right 2x1 changed
+    struct Indexed(Vec<Bgr8>);
+
+    const DIRTY: [Rect; 1] = [Rect {
+        x: 1,
+        y: 1,
+        width: 2,
+        height: 1,
+    }];
+
+    impl Backend for Indexed {
+        fn name(&self) -> &'static str {
+            "indexed"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (3, 2)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.0 = (0..6).map(|i| Bgr8::new(i, 0, 0)).collect();
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 3, 2))
+        }
+
+        fn dirty_rects(&self) -> Option<&[Rect]> {
+            Some(&DIRTY)
+        }
+    }
+
+    #[test]
+    fn test_cropped_region() {
+        let mut capturer = Capturer::with_backend(Box::new(Indexed(Vec::new())));
+        let region = Rect {
+            x: 1,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        assert!(capturer
+            .set_capture_region(Rect { x: 2, ..region })
+            .is_err());
+        // Overflows u32 if unchecked
+        assert!(capturer
+            .set_capture_region(Rect {
+                x: u32::MAX,
+                ..region
+            })
+            .is_err());
+        capturer.set_capture_region(region).unwrap();
+        assert_eq!(capturer.geometry(), (2, 2));
+        let red: Vec<u8> = capturer
+            .capture_frame()
+            .unwrap()
+            .iter()
+            .map(|p| p.r)
+            .collect();
+        assert_eq!(red, [1, 2, 4, 5]);
+        capturer.clear_capture_region();
+        assert_eq!(capturer.geometry(), (3, 2));
+        assert_eq!(capturer.capture_frame().unwrap().len(), 6);
+
+        let capturer = crate::CapturerBuilder::new()
+            .region(region)
+            .build_with_backend(Box::new(Indexed(Vec::new())))
+            .unwrap();
+        assert_eq!(capturer.geometry(), (2, 2));
+    }
+
+    #[test]
+    fn test_capture_frame_meta() {
+        let mut capturer = Capturer::with_backend(Box::new(Indexed(Vec::new())));
+        let first = capturer.capture_frame_meta().unwrap();
+        assert_eq!(first.geometry(), (3, 2));
+        assert_eq!(first.stride(), 12);
+        assert_eq!(first.sequence(), 1);
+        assert_eq!(first.dirty_rects(), Some(&DIRTY[..]));
+        assert_eq!(first.cursor(), None);
+        let second = capturer.capture_frame_meta().unwrap();
+        assert_eq!(second.sequence(), 2);
+        assert!(second.timestamp().unwrap() >= first.timestamp().unwrap());
+    }
+
+    #[test]
+    fn test_frame_hook() {
+        let mut capturer = Capturer::with_backend(Box::new(Indexed(Vec::new())));
+        capturer.set_frame_hook(|frame: &mut Frame| {
+            assert_eq!(frame.geometry(), (3, 2));
+            let sequence = frame.sequence() as u8;
+            frame.pixels_mut()[0].g = sequence;
+        });
+        capturer.capture_store_frame().unwrap();
+        capturer.capture_store_frame().unwrap();
+        assert_eq!(capturer.get_stored_frame().unwrap()[0].g, 2);
+        assert_eq!(capturer.capture_frame_meta().unwrap().pixels()[0].g, 3);
+        capturer.clear_frame_hook();
+        capturer.capture_store_frame().unwrap();
+        let frame = capturer.get_stored_frame_mut().unwrap();
+        assert_eq!(frame[0].g, 0);
+        frame[1].g = 9;
+        assert_eq!(capturer.get_stored_frame().unwrap()[1].g, 9);
+    }
+
+    /// Backend of a 1x1 frame, presented anew on every other capture
+    struct Alternating(u32);
+
+    impl Backend for Alternating {
+        fn name(&self) -> &'static str {
+            "alternating"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.0 += 1;
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            const BLACK: [Bgr8; 1] = [Bgr8::new(0, 0, 0)];
+            Some((&BLACK, 1, 1))
+        }
+
+        fn content_changed(&self) -> Option<bool> {
+            Some(self.0 % 2 == 1)
+        }
+    }
+
+    #[test]
+    fn test_duplicate_frames() {
+        let mut capturer = Capturer::with_backend(Box::new(Alternating(0)));
+        assert!(!capturer.capture_frame_meta().unwrap().is_duplicate());
+        assert!(capturer.capture_frame_meta().unwrap().is_duplicate());
+        capturer.set_include_cursor(true);
+        assert!(!capturer.capture_frame_meta().unwrap().is_duplicate());
+        capturer.set_include_cursor(false);
+        capturer.set_skip_duplicates(true);
+        assert!(matches!(
+            capturer.capture_store_frame(),
+            Err(CaptureError::NoNewFrame)
+        ));
+        assert!(capturer.get_stored_frame().is_some());
+        capturer.capture_store_frame().unwrap();
+    }
+
+    #[test]
+    fn test_capture_frame_into() {
+        let mut capturer = Capturer::with_backend(Box::new(Indexed(Vec::new())));
+        let mut pixels = Vec::new();
+        assert_eq!(capturer.capture_frame_into(&mut pixels).unwrap(), (3, 2));
+        assert_eq!(pixels.len(), 6);
+        assert_eq!(pixels[5].r, 5);
+        let mut bytes = [0; 24];
+        assert_eq!(
+            capturer.capture_frame_into_bytes(&mut bytes).unwrap(),
+            (3, 2)
+        );
+        assert_eq!(bytes[4 * 5 + 2], 5);
+        assert!(capturer.capture_frame_into_bytes(&mut bytes[..23]).is_err());
+    }
+
+    /// Backend whose display changes mode after the first capture, losing
+    /// access until it is reacquired
+    struct ModeChange {
+        size: (u32, u32),
+        lost: bool,
+        frame: Vec<Bgr8>,
+    }
+
+    impl Backend for ModeChange {
+        fn name(&self) -> &'static str {
+            "mode change"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            self.size
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            if self.lost {
+                return Err(CaptureError::AccessLost);
+            }
+            self.frame = vec![Bgr8::default(); (self.size.0 * self.size.1) as usize];
+            self.lost = self.size == (2, 1);
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.frame, self.size.0, self.size.1))
+        }
+
+        fn reacquire(&mut self) -> Result<(), CaptureError> {
+            self.size = (3, 2);
+            self.lost = false;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_auto_recover() {
+        let mut capturer = Capturer::with_backend(Box::new(ModeChange {
+            size: (2, 1),
+            lost: false,
+            frame: Vec::new(),
+        }));
+        capturer.capture_store_frame().unwrap();
+        assert!(matches!(
+            capturer.capture_store_frame(),
+            Err(CaptureError::AccessLost)
+        ));
+        capturer.set_auto_recover(true);
+        capturer.capture_store_frame().unwrap();
+        assert_eq!(capturer.geometry(), (3, 2));
+        assert_eq!(
+            capturer.pending_event(),
+            Some(crate::CaptureEvent::Recovered)
+        );
+        assert_eq!(
+            capturer.pending_event(),
+            Some(crate::CaptureEvent::GeometryChanged { new: (3, 2) })
+        );
+        assert_eq!(capturer.pending_event(), None);
+    }
+
+    #[test]
+    fn test_poll_event() {
+        use crate::CaptureEvent;
+        let mut capturer = Capturer::with_backend(Box::new(ModeChange {
+            size: (2, 1),
+            lost: false,
+            frame: Vec::new(),
+        }));
+        let mut poll = || capturer.poll_event(Duration::ZERO).unwrap();
+        assert_eq!(poll(), CaptureEvent::NewFrame { sequence: 1 });
+        assert_eq!(poll(), CaptureEvent::SourceLost);
+        capturer.set_auto_recover(true);
+        let mut poll = || capturer.poll_event(Duration::ZERO).unwrap();
+        assert_eq!(poll(), CaptureEvent::Recovered);
+        assert_eq!(poll(), CaptureEvent::GeometryChanged { new: (3, 2) });
+        assert_eq!(poll(), CaptureEvent::NewFrame { sequence: 2 });
+        assert_eq!(poll(), CaptureEvent::NewFrame { sequence: 3 });
+        assert_eq!(capturer.timeout(), Duration::from_millis(200));
+    }
+
+    /// Backend of monitors of index + 1 by 1 pixels
+    struct Monitors {
+        source: usize,
+        frame: Vec<Bgr8>,
+    }
+
+    impl Backend for Monitors {
+        fn name(&self) -> &'static str {
+            "monitors"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (self.source as u32 + 1, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.frame = vec![Bgr8::new(self.source as u8, 0, 0); self.source + 1];
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.frame, self.source as u32 + 1, 1))
+        }
+
+        fn set_source(&mut self, capture_src: usize) -> Option<Result<(), InitError>> {
+            self.source = capture_src;
+            Some(Ok(()))
+        }
+    }
+
+    #[test]
+    fn test_set_capture_source() {
+        let mut capturer = Capturer::with_backend(Box::new(Monitors {
+            source: 2,
+            frame: Vec::new(),
+        }));
+        let region = Rect {
+            x: 1,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        capturer.set_capture_region(region).unwrap();
+        capturer.set_capture_source(3).unwrap();
+        assert_eq!(capturer.capture_region(), Some(region));
+        assert_eq!(capturer.capture_frame().unwrap(), [Bgr8::new(3, 0, 0); 2]);
+        // The region doesn't fit in monitor 1
+        capturer.set_capture_source(1).unwrap();
+        assert_eq!(capturer.capture_region(), None);
+        assert_eq!(capturer.geometry(), (2, 1));
+        assert_eq!(capturer.backend_name(), "monitors");
+    }
+
+    /// Backend of 1x1 frames of the colors in turn, repeating the last one
+    struct Slideshow {
+        colors: Vec<Bgr8>,
+        frame: Vec<Bgr8>,
+    }
+
+    impl Backend for Slideshow {
+        fn name(&self) -> &'static str {
+            "slideshow"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            if self.colors.len() > 1 {
+                self.frame = vec![self.colors.remove(0)];
+            } else {
+                self.frame = self.colors.clone();
+            }
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.frame, 1, 1))
+        }
+    }
+
+    /// Backend of a 1x1 frame, failing to capture while released
+    struct Releasable(bool, [Bgr8; 1]);
+
+    impl Backend for Releasable {
+        fn name(&self) -> &'static str {
+            "releasable"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            match self.0 {
+                true => Err(CaptureError::AccessLost),
+                false => Ok(()),
+            }
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.1, 1, 1))
+        }
+
+        fn pause(&mut self) {
+            self.0 = true;
+        }
+
+        fn resume(&mut self) -> Result<(), CaptureError> {
+            self.0 = false;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut capturer = Capturer::with_backend(Box::new(Releasable(false, [Bgr8::default()])));
+        capturer.capture_store_frame().unwrap();
+        capturer.pause();
+        assert!(capturer.is_paused());
+        assert!(matches!(
+            capturer.capture_store_frame(),
+            Err(CaptureError::Paused)
+        ));
+        capturer.resume().unwrap();
+        assert!(!capturer.is_paused());
+        capturer.capture_store_frame().unwrap();
+    }
+
+    #[test]
+    fn test_capture_frame_changed() {
+        let (black, white) = (Bgr8::new(0, 0, 0), Bgr8::new(255, 255, 255));
+        let mut capturer = Capturer::with_backend(Box::new(Slideshow {
+            colors: vec![black, black, black, white],
+            frame: Vec::new(),
+        }));
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            capturer.capture_frame_changed(timeout).unwrap().pixels()[0],
+            black
+        );
+        assert_eq!(
+            capturer.capture_frame_changed(timeout).unwrap().pixels()[0],
+            white
+        );
+        assert!(matches!(
+            capturer.capture_frame_changed(Duration::from_millis(40)),
+            Err(CaptureError::Timeout)
+        ));
+        assert_eq!(capturer.timeout(), Duration::from_millis(200));
+    }
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Capturer>();
+        assert_send::<crate::CapturerBuilder>();
+
+        let capturer = Capturer::with_backend(Box::new(Flaky {
+            lost: false,
+            frame: None,
+        }));
+        let geometry = std::thread::spawn(move || capturer.geometry())
+            .join()
+            .unwrap();
+        assert_eq!(geometry, (2, 1));
+    }
+}
@@ -0,0 +1,125 @@
+//! Bevy integration. `CapturePlugin` captures frames on a thread of its own,
+//! and uploads the latest one to an `Image` asset every update, which can be
+//! used like any other texture, e.g. in a sprite or a material.
+//!
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use captrs::bevy::{CapturePlugin, CaptureTexture};
+//! fn setup(mut commands: Commands, texture: Res<CaptureTexture>) {
+//!     commands.spawn(Camera2d);
+//!     commands.spawn(Sprite::from_image(texture.0.clone()));
+//! }
+//!
+//! App::new()
+//!     .add_plugins((DefaultPlugins, CapturePlugin { capture_src: 0, fps: 60.0 }))
+//!     .add_systems(Startup, setup)
+//!     .run();
+//! ```
+
+use crate::{Capturer, ThreadedCapturer};
+use bevy::asset::RenderAssetUsages;
+use bevy::image::Image;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use std::time::Duration;
+
+/// Plugin that captures the screen into the `Image` of the `CaptureTexture`
+/// resource
+///
+/// Frames are captured on a `ThreadedCapturer`, so updates never wait for the
+/// capture backend, e.g. while the screen is still. Only frames that were not
+/// uploaded yet are uploaded.
+///
+/// Must be added after the plugins providing `Assets<Image>`, e.g.
+/// `DefaultPlugins`. If the capturer fails to initialize, the error is logged
+/// and the texture stays a black 1x1 image.
+pub struct CapturePlugin {
+    /// Capture source to capture from, as in `Capturer::new`
+    pub capture_src: usize,
+    /// Maximum rate of captures per second
+    pub fps: f64,
+}
+
+/// Handle to the image that captured frames are uploaded to
+#[derive(Resource, Clone, Debug)]
+pub struct CaptureTexture(pub Handle<Image>);
+
+#[derive(Resource)]
+struct CaptureState {
+    capturer: ThreadedCapturer,
+    /// Number of capture errors that were logged
+    errors: u64,
+}
+
+impl Plugin for CapturePlugin {
+    fn build(&self, app: &mut App) {
+        // Constructed here, rather than on the capture thread, for the size of
+        // the image
+        let fps = self.fps;
+        let capturer = Capturer::new(self.capture_src).and_then(|capturer| {
+            let geometry = capturer.geometry();
+            ThreadedCapturer::spawn(move || Ok(capturer), fps).map(|c| (c, geometry))
+        });
+        let (capturer, (w, h)) = match capturer {
+            Ok((capturer, geometry)) => (Some(capturer), geometry),
+            Err(e) => {
+                error!("Failed to create capturer: {}", e);
+                (None, (1, 1))
+            }
+        };
+        let image = Image::new_fill(
+            Extent3d {
+                width: w,
+                height: h,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Bgra8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        let handle = app.world_mut().resource_mut::<Assets<Image>>().add(image);
+        app.insert_resource(CaptureTexture(handle));
+        if let Some(capturer) = capturer {
+            app.insert_resource(CaptureState {
+                capturer,
+                errors: 0,
+            })
+            .add_systems(PreUpdate, update_capture_texture);
+        }
+    }
+}
+
+fn update_capture_texture(
+    mut state: ResMut<CaptureState>,
+    texture: Res<CaptureTexture>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let errors = state.capturer.stats().errors;
+    if errors > state.errors {
+        state.errors = errors;
+        if let Some(e) = state.capturer.last_error() {
+            warn!("Failed to capture frame: {:?}", e);
+        }
+    }
+    let Some(frame) = state.capturer.wait_for_frame(Duration::ZERO) else {
+        return;
+    };
+    let (w, h) = frame.geometry();
+    if let Some(image) = images.get_mut(&texture.0) {
+        if image.size() != UVec2::new(w, h) {
+            image.resize(Extent3d {
+                width: w,
+                height: h,
+                depth_or_array_layers: 1,
+            });
+        }
+        let data = image.data.get_or_insert_with(Vec::new);
+        data.clear();
+        data.extend_from_slice(frame.as_bytes());
+        // The fourth byte is padding on some platforms, but it's alpha to Bevy
+        for pixel in data.chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+    }
+}
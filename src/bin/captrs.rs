@@ -0,0 +1,205 @@
+//! Command line screen capture tool
+//!
+//! ```text
+//! captrs list
+//! captrs shot [--display N] -o out.png
+//! captrs record [--display N] [--fps N] [--duration 10s] -o out.mp4
+//! ```
+//!
+//! `list-displays` is an alias of `list`. Durations are in seconds, or with a
+//! unit of `ms`, `s`, `m` or `h`. `record` encodes with `ffmpeg`, which must
+//! be in `PATH`, into H.264 or, for `.gif` outputs, an animated GIF.
+
+extern crate captrs;
+extern crate png;
+
+use captrs::{CaptureError, Capturer};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage:
+    captrs list | list-displays
+    captrs shot [--display N] -o <out.png>
+    captrs record [--display N] [--fps N] [--duration 10s] -o <out.mp4|out.gif>";
+
+struct Options {
+    display: usize,
+    fps: u32,
+    duration: Option<Duration>,
+    output: Option<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options {
+        display: 0,
+        fps: 30,
+        duration: None,
+        output: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "-d" | "--display" => {
+                opts.display = value()?
+                    .parse()
+                    .map_err(|e| format!("Bad display: {}", e))?
+            }
+            "--fps" => opts.fps = value()?.parse().map_err(|e| format!("Bad fps: {}", e))?,
+            "--duration" => opts.duration = Some(parse_duration(value()?)?),
+            "-o" | "--output" => opts.output = Some(value()?.clone()),
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+    if opts.fps == 0 {
+        return Err("fps must be positive".to_string());
+    }
+    Ok(opts)
+}
+
+/// Parse a duration in seconds, or with a unit, e.g. `10s` or `500ms`
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|e| format!("Bad duration {}: {}", s, e))?;
+    let secs = match unit {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("Bad duration unit {}", unit)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("Bad duration {}: {}", s, e))
+}
+
+/// Capture a frame, retrying on timeouts
+fn capture(capturer: &mut Capturer) -> Result<Vec<u8>, String> {
+    loop {
+        match capturer.capture_frame_components() {
+            Ok(frame) => return Ok(frame),
+            Err(CaptureError::Timeout | CaptureError::NoNewFrame) => continue,
+            Err(e) => return Err(format!("Failed to capture frame: {}", e)),
+        }
+    }
+}
+
+fn list() -> Result<(), String> {
+    for d in captrs::displays() {
+        print!(
+            "{}: {} {}x{} at ({}, {}), scale {}",
+            d.index, d.name, d.size.0, d.size.1, d.position.0, d.position.1, d.scale_factor
+        );
+        if let Some(monitor_name) = &d.monitor_name {
+            print!(", {}", monitor_name);
+        }
+        if let Some(hz) = d.refresh_rate {
+            print!(", {:.2} Hz", hz);
+        }
+        if d.primary {
+            print!(", primary");
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn shot(opts: Options) -> Result<(), String> {
+    let output = opts.output.ok_or("Missing output file")?;
+    let mut capturer = Capturer::new(opts.display).map_err(|e| e.to_string())?;
+    let frame = capture(&mut capturer)?;
+    let (w, h) = capturer.geometry();
+    let rgb: Vec<u8> = frame
+        .chunks_exact(4)
+        .flat_map(|p| [p[2], p[1], p[0]])
+        .collect();
+    let file = File::create(&output).map_err(|e| format!("{}: {}", output, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), w, h);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgb))
+        .map_err(|e| format!("Failed to write PNG: {}", e))
+}
+
+fn record(opts: Options) -> Result<(), String> {
+    let output = opts.output.ok_or("Missing output file")?;
+    let mut capturer = Capturer::new(opts.display).map_err(|e| e.to_string())?;
+    let mut frame = capture(&mut capturer)?;
+    let (w, h) = capturer.geometry();
+    let encoding: &[&str] = if output.to_lowercase().ends_with(".gif") {
+        // With a palette generated from the whole recording
+        &[
+            "-vf",
+            "split[a][b];[a]palettegen[p];[b][p]paletteuse",
+            "-loop",
+            "0",
+        ]
+    } else {
+        &["-c:v", "libx264", "-pix_fmt", "yuv420p"]
+    };
+    let mut ffmpeg = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-y"])
+        .args(["-f", "rawvideo", "-pix_fmt", "bgr0"])
+        .args(["-s", &format!("{}x{}", w, h)])
+        .args(["-r", &opts.fps.to_string(), "-i", "-"])
+        .args(encoding)
+        .arg(&output)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start ffmpeg: {}", e))?;
+    let mut stdin = ffmpeg.stdin.take().unwrap();
+    let interval = Duration::from_secs(1) / opts.fps;
+    let start = Instant::now();
+    let mut next = start;
+    loop {
+        // On timeouts the screen is unchanged, so the last frame is repeated
+        match capturer.capture_frame_components() {
+            Ok(f) if capturer.geometry() == (w, h) => frame = f,
+            Ok(_) => return Err("Resolution changed during recording".to_string()),
+            Err(CaptureError::Timeout | CaptureError::NoNewFrame) => (),
+            Err(e) => return Err(format!("Failed to capture frame: {}", e)),
+        }
+        if stdin.write_all(&frame).is_err() {
+            break;
+        }
+        next += interval;
+        if opts.duration.is_some_and(|d| next - start >= d) {
+            break;
+        }
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+    drop(stdin);
+    match ffmpeg.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("ffmpeg failed: {}", status)),
+        Err(e) => Err(format!("ffmpeg failed: {}", e)),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let res = match args.split_first() {
+        Some((cmd, rest)) => match (cmd.as_str(), parse_options(rest)) {
+            ("list" | "list-displays", Ok(_)) => list(),
+            ("shot", Ok(opts)) => shot(opts),
+            ("record", Ok(opts)) => record(opts),
+            (_, Err(e)) => Err(e),
+            _ => Err(USAGE.to_string()),
+        },
+        None => Err(USAGE.to_string()),
+    };
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,574 @@
+//! Configuration of capturers
+
+use crate::hdr::ToneMapping;
+use crate::{
+    Backend, CaptureError, Capturer, InitError, LatencyMode, PixelFormat, ProtectedPolicy, Rect,
+};
+use std::time::Duration;
+
+/// What to do when a capture fails because access to the capture source was
+/// lost, e.g. when the display mode changed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RecoveryPolicy {
+    /// Return the error
+    #[default]
+    Fail,
+    /// Retry the capture up to `attempts` times, waiting `delay` between
+    /// attempts, before returning the error
+    Retry { attempts: u32, delay: Duration },
+}
+
+impl RecoveryPolicy {
+    /// The equivalent retry policy, retrying lost access and platform
+    /// errors at a fixed delay
+    pub(crate) fn retry_policy(self) -> Option<RetryPolicy> {
+        match self {
+            RecoveryPolicy::Fail => None,
+            RecoveryPolicy::Retry { attempts, delay } => Some(RetryPolicy {
+                max_attempts: attempts.saturating_add(1),
+                backoff: Backoff::Fixed(delay),
+                retry_on: RetryOn {
+                    timeout: false,
+                    access_lost: true,
+                    platform: true,
+                },
+            }),
+        }
+    }
+}
+
+/// How long to wait before the retries of a `RetryPolicy`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backoff {
+    /// Retry right away
+    None,
+    /// Wait the same time before each retry
+    Fixed(Duration),
+    /// Wait `initial` before the first retry, doubling the wait before each
+    /// following retry, up to `max`
+    Exponential { initial: Duration, max: Duration },
+}
+
+impl Backoff {
+    /// The wait before retry number `retry`, counting from 0
+    fn delay(&self, retry: u32) -> Duration {
+        match *self {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => {
+                initial.saturating_mul(1 << retry.min(31)).min(max)
+            }
+        }
+    }
+}
+
+/// The errors of captures retried by a `RetryPolicy`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RetryOn {
+    /// `CaptureError::Timeout`
+    pub timeout: bool,
+    /// `CaptureError::AccessLost`. The capture source is reacquired before
+    /// retrying.
+    pub access_lost: bool,
+    /// `CaptureError::Platform` and `CaptureError::Fail`, and
+    /// `CaptureError::RefreshFailure` on Windows
+    pub platform: bool,
+}
+
+impl RetryOn {
+    /// No errors
+    pub const NONE: RetryOn = RetryOn {
+        timeout: false,
+        access_lost: false,
+        platform: false,
+    };
+
+    /// Timeouts and lost access, which are usually brief glitches, e.g. when
+    /// the display mode changes
+    pub const TRANSIENT: RetryOn = RetryOn {
+        timeout: true,
+        access_lost: true,
+        platform: false,
+    };
+
+    fn matches(&self, err: &CaptureError) -> bool {
+        match err {
+            CaptureError::Timeout => self.timeout,
+            CaptureError::AccessLost => self.access_lost,
+            CaptureError::Platform(_) | CaptureError::Fail(_) => self.platform,
+            #[cfg(windows)]
+            CaptureError::RefreshFailure => self.platform,
+            _ => false,
+        }
+    }
+}
+
+/// How captures failing with transient errors are retried within a single
+/// call to capture, so that brief glitches are ridden through while
+/// persistent failures are still returned
+///
+/// ```no_run
+/// use captrs::{Backoff, CapturerBuilder, RetryOn, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let capturer = CapturerBuilder::new()
+///     .retry_policy(RetryPolicy {
+///         max_attempts: 5,
+///         backoff: Backoff::Fixed(Duration::from_millis(20)),
+///         retry_on: RetryOn::TRANSIENT,
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    /// Attempts at capturing, including the first one, before returning the
+    /// error of the last one
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+    pub retry_on: RetryOn,
+}
+
+impl RetryPolicy {
+    /// Whether a capture that failed after `retries` retries should be
+    /// retried, after waiting for the backoff if so
+    pub(crate) fn should_retry(&self, err: &CaptureError, retries: u32) -> bool {
+        if !self.retry_on.matches(err) || retries.saturating_add(1) >= self.max_attempts {
+            return false;
+        }
+        std::thread::sleep(self.backoff.delay(retries));
+        true
+    }
+}
+
+impl Default for RetryPolicy {
+    /// 3 attempts on transient errors, waiting 10 ms before the first retry
+    /// and 20 ms before the second
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(100),
+            },
+            retry_on: RetryOn::TRANSIENT,
+        }
+    }
+}
+
+/// Capture backend on Windows
+#[cfg(windows)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WindowsBackend {
+    /// DXGI desktop duplication, falling back to GDI if duplication can't
+    /// be initialized
+    #[default]
+    DXGI,
+    /// GDI `BitBlt`. Slower, but works where desktop duplication doesn't,
+    /// e.g. over some RDP sessions and in session 0.
+    GDI,
+    /// The WinRT `Windows.Graphics.Capture` API. Works in some cases where
+    /// desktop duplication fails, e.g. on some hybrid-GPU laptops. Requires
+    /// Windows 10 1803 or later.
+    WGC,
+}
+
+/// Client library to capture X11 through
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum X11Api {
+    /// Xlib, with MIT-SHM when available
+    #[cfg_attr(not(feature = "x11-xcb"), default)]
+    Xlib,
+    /// XCB, with MIT-SHM when available, and RandR. Thread-safe, unlike
+    /// Xlib. The default with the `x11-xcb` feature.
+    #[cfg(feature = "x11-xcb")]
+    #[default]
+    Xcb,
+}
+
+/// Adapter, i.e. GPU, to duplicate outputs on with DXGI on Windows
+///
+/// Desktop duplication only works on the adapter that an output is attached
+/// to, e.g. the integrated GPU on hybrid-graphics laptops. By default, the
+/// adapter of the captured output is used.
+#[cfg(windows)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AdapterSelector {
+    /// An adapter by its index in the enumeration order of DXGI
+    Index(usize),
+    /// The adapter that the output of a capture source index is attached to,
+    /// by its LUID
+    LuidOf(usize),
+    /// The adapter that the primary output is attached to
+    Primary,
+}
+
+/// Builder of a `Capturer` with custom configuration
+///
+/// ```no_run
+/// use captrs::CapturerBuilder;
+/// use std::time::Duration;
+///
+/// let capturer = CapturerBuilder::new()
+///     .source(1)
+///     .timeout(Duration::from_millis(500))
+///     .include_cursor(true)
+///     .region(captrs::Rect { x: 0, y: 0, width: 640, height: 480 })
+///     .fps(30.0)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct CapturerBuilder {
+    source: usize,
+    timeout: Duration,
+    latency_mode: LatencyMode,
+    include_cursor: bool,
+    track_cursor: bool,
+    fps: Option<f64>,
+    retry: Option<RetryPolicy>,
+    auto_recover: bool,
+    skip_duplicates: bool,
+    protected: ProtectedPolicy,
+    region: Option<Rect>,
+    auto_crop: bool,
+    pixel_format: PixelFormat,
+    upright: bool,
+    hdr: Option<ToneMapping>,
+    #[cfg(windows)]
+    backend: WindowsBackend,
+    #[cfg(windows)]
+    adapter: Option<AdapterSelector>,
+    #[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+    x11_api: X11Api,
+    #[cfg(all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "android"),
+        feature = "wayland"
+    ))]
+    dmabuf: bool,
+    #[cfg(feature = "rayon")]
+    threads: Option<usize>,
+}
+
+impl CapturerBuilder {
+    pub fn new() -> CapturerBuilder {
+        CapturerBuilder {
+            source: 0,
+            timeout: Duration::from_millis(200),
+            latency_mode: LatencyMode::Throughput,
+            include_cursor: false,
+            track_cursor: false,
+            fps: None,
+            retry: None,
+            auto_recover: false,
+            skip_duplicates: false,
+            protected: ProtectedPolicy::Error,
+            region: None,
+            auto_crop: false,
+            pixel_format: PixelFormat::Bgra8,
+            upright: true,
+            hdr: None,
+            #[cfg(windows)]
+            backend: WindowsBackend::DXGI,
+            #[cfg(windows)]
+            adapter: None,
+            #[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+            x11_api: X11Api::default(),
+            #[cfg(all(
+                unix,
+                not(target_os = "macos"),
+                not(target_os = "android"),
+                feature = "wayland"
+            ))]
+            dmabuf: false,
+            #[cfg(feature = "rayon")]
+            threads: None,
+        }
+    }
+
+    /// Index of the capture source, e.g. a display. 0 is the primary display.
+    pub fn source(mut self, source: usize) -> CapturerBuilder {
+        self.source = source;
+        self
+    }
+
+    /// How long to wait for a new frame before timing out. See
+    /// `Capturer::set_timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> CapturerBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether captures favour throughput or latency. See
+    /// `Capturer::set_latency_mode`.
+    pub fn latency_mode(mut self, mode: LatencyMode) -> CapturerBuilder {
+        self.latency_mode = mode;
+        self
+    }
+
+    /// Whether to draw the cursor in captured frames. See
+    /// `Capturer::set_include_cursor`.
+    pub fn include_cursor(mut self, include: bool) -> CapturerBuilder {
+        self.include_cursor = include;
+        self
+    }
+
+    /// Whether to query the cursor on every capture. See
+    /// `Capturer::set_track_cursor`.
+    pub fn track_cursor(mut self, track: bool) -> CapturerBuilder {
+        self.track_cursor = track;
+        self
+    }
+
+    /// Capture only a region of the capture source. See
+    /// `Capturer::set_capture_region`.
+    pub fn region(mut self, region: Rect) -> CapturerBuilder {
+        self.region = Some(region);
+        self
+    }
+
+    /// Pixel format of captured frames. See `Capturer::set_pixel_format`.
+    pub fn pixel_format(mut self, format: PixelFormat) -> CapturerBuilder {
+        self.pixel_format = format;
+        self
+    }
+
+    /// Whether to rotate frames of rotated displays upright. See
+    /// `Capturer::set_upright`.
+    pub fn upright(mut self, upright: bool) -> CapturerBuilder {
+        self.upright = upright;
+        self
+    }
+
+    /// Capture in the HDR format of the display, tone mapping to 8-bit
+    /// frames. See `Capturer::set_hdr`.
+    pub fn hdr(mut self, tone_mapping: ToneMapping) -> CapturerBuilder {
+        self.hdr = Some(tone_mapping);
+        self
+    }
+
+    /// Maximum rate of captures per second. See `Capturer::set_max_fps`.
+    pub fn fps(mut self, fps: f64) -> CapturerBuilder {
+        self.fps = Some(fps);
+        self
+    }
+
+    /// What to do when access to the capture source is lost. Replaces the
+    /// retry policy, see `Capturer::set_recovery_policy`.
+    pub fn recovery(mut self, recovery: RecoveryPolicy) -> CapturerBuilder {
+        self.retry = recovery.retry_policy();
+        self
+    }
+
+    /// How to retry captures failing with transient errors. See
+    /// `Capturer::set_retry_policy`.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> CapturerBuilder {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Whether to reacquire the capture source when access to it is lost.
+    /// See `Capturer::set_auto_recover`.
+    pub fn auto_recover(mut self, auto_recover: bool) -> CapturerBuilder {
+        self.auto_recover = auto_recover;
+        self
+    }
+
+    /// Whether to crop the black borders of frames, e.g. of games that
+    /// letterbox. See `Capturer::set_auto_crop`.
+    pub fn auto_crop(mut self, auto_crop: bool) -> CapturerBuilder {
+        self.auto_crop = auto_crop;
+        self
+    }
+
+    /// Whether to fail captures of frames without new content. See
+    /// `Capturer::set_skip_duplicates`.
+    pub fn skip_duplicates(mut self, skip: bool) -> CapturerBuilder {
+        self.skip_duplicates = skip;
+        self
+    }
+
+    /// What to do when protected content is displayed. See
+    /// `Capturer::set_protected_policy`.
+    pub fn on_protected(mut self, policy: ProtectedPolicy) -> CapturerBuilder {
+        self.protected = policy;
+        self
+    }
+
+    /// Capture backend to use on Windows
+    #[cfg(windows)]
+    pub fn backend(mut self, backend: WindowsBackend) -> CapturerBuilder {
+        self.backend = backend;
+        self
+    }
+
+    /// Adapter to duplicate the output on with the DXGI backend
+    #[cfg(windows)]
+    pub fn adapter(mut self, adapter: AdapterSelector) -> CapturerBuilder {
+        self.adapter = Some(adapter);
+        self
+    }
+
+    /// Client library to capture X11 through
+    #[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+    pub fn x11_api(mut self, x11_api: X11Api) -> CapturerBuilder {
+        self.x11_api = x11_api;
+        self
+    }
+
+    /// Whether to capture into DMA-BUFs on Wayland, if the compositor
+    /// supports it. See `Frame::dmabuf`.
+    #[cfg(all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "android"),
+        feature = "wayland"
+    ))]
+    pub fn dmabuf(mut self, dmabuf: bool) -> CapturerBuilder {
+        self.dmabuf = dmabuf;
+        self
+    }
+
+    /// Number of threads to convert and scale frames on. See
+    /// `Capturer::set_threads`.
+    #[cfg(feature = "rayon")]
+    pub fn threads(mut self, threads: usize) -> CapturerBuilder {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Construct the configured capturer
+    #[cfg(windows)]
+    pub fn build(&self) -> Result<Capturer, InitError> {
+        let backend: Box<dyn Backend> = match self.backend {
+            WindowsBackend::DXGI => {
+                crate::backend::windows_backend(self.source, self.timeout, self.adapter)?
+            }
+            WindowsBackend::GDI => Box::new(crate::gdi::GdiBackend::new(self.source)?),
+            WindowsBackend::WGC => Box::new(crate::wgc::WgcBackend::new(self.source)?),
+        };
+        self.build_with_backend(backend)
+    }
+
+    /// Construct the configured capturer
+    #[cfg(not(windows))]
+    pub fn build(&self) -> Result<Capturer, InitError> {
+        #[cfg(all(
+            unix,
+            not(target_os = "macos"),
+            not(target_os = "android"),
+            feature = "wayland"
+        ))]
+        if self.dmabuf && crate::wayland::is_wayland_session() {
+            let session = crate::wayland::Session::with_dmabuf(self.source)?;
+            return self.build_with_backend(Box::new(session));
+        }
+        #[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+        let backend = crate::backend::unix_backend(self.source, self.x11_api)?;
+        #[cfg(any(target_os = "macos", target_os = "android", target_arch = "wasm32"))]
+        let backend = crate::backend::default_backend(self.source)?;
+        self.build_with_backend(backend)
+    }
+
+    /// Construct the configured capturer, capturing with a given backend
+    /// instead of the default one for the source
+    pub fn build_with_backend(&self, backend: Box<dyn Backend>) -> Result<Capturer, InitError> {
+        let mut capturer = Capturer::with_backend(backend);
+        capturer.set_timeout(self.timeout)?;
+        capturer.set_latency_mode(self.latency_mode);
+        capturer.set_include_cursor(self.include_cursor);
+        capturer.set_track_cursor(self.track_cursor);
+        capturer.set_max_fps(self.fps);
+        capturer.set_retry_policy(self.retry);
+        capturer.set_auto_recover(self.auto_recover);
+        capturer.set_skip_duplicates(self.skip_duplicates);
+        capturer.set_protected_policy(self.protected);
+        capturer.set_pixel_format(self.pixel_format);
+        capturer.set_upright(self.upright);
+        capturer.set_hdr(self.hdr)?;
+        if let Some(region) = self.region {
+            capturer.set_capture_region(region)?;
+        }
+        capturer.set_auto_crop(self.auto_crop);
+        #[cfg(feature = "rayon")]
+        if let Some(threads) = self.threads {
+            capturer.set_threads(threads)?;
+        }
+        Ok(capturer)
+    }
+}
+
+impl Default for CapturerBuilder {
+    fn default() -> CapturerBuilder {
+        CapturerBuilder::new()
+    }
+}
+
+impl Capturer {
+    /// A builder of a capturer with custom configuration
+    pub fn builder() -> CapturerBuilder {
+        CapturerBuilder::new()
+    }
+
+    /// Limit the rate of captures to at most `fps` per second, by sleeping
+    /// before captures that would come too soon after the previous one
+    pub fn set_max_fps(&mut self, fps: Option<f64>) {
+        self.frame_interval = fps
+            .filter(|&fps| fps > 0.0)
+            .map(|fps| Duration::from_secs_f64(1.0 / fps));
+    }
+
+    /// Set what to do when access to the capture source is lost
+    ///
+    /// Shorthand for the equivalent `RetryPolicy`, replacing the one set with
+    /// `Self::set_retry_policy`.
+    pub fn set_recovery_policy(&mut self, recovery: RecoveryPolicy) {
+        self.retry = recovery.retry_policy();
+    }
+
+    /// Retry captures failing with transient errors within the same call, by
+    /// `Self::capture_store_frame` and all the `capture_frame*` methods, or
+    /// stop if `None`
+    ///
+    /// With `Self::set_auto_recover`, lost access is retried at least once
+    /// regardless of the policy.
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.retry = retry;
+    }
+
+    /// Whether to transparently reacquire the capture source and capture
+    /// again when access to it is lost, e.g. when toggling fullscreen or
+    /// changing the resolution
+    ///
+    /// A change of the size of the frames is reported by `Self::poll_event`,
+    /// after `CaptureEvent::Recovered`.
+    pub fn set_auto_recover(&mut self, auto_recover: bool) {
+        self.auto_recover = auto_recover;
+    }
+
+    /// Whether to fail captures with `CaptureError::NoNewFrame` when the
+    /// backend reports that no new frame was presented, e.g. when DXGI
+    /// desktop duplication only updated the cursor, rather than returning
+    /// a duplicate frame. See `Frame::is_duplicate`.
+    ///
+    /// The duplicate frame is still stored, as by
+    /// `Self::capture_store_frame`.
+    pub fn set_skip_duplicates(&mut self, skip: bool) {
+        self.skip_duplicates = skip;
+    }
+
+    /// Sleep until the next capture is due, if the rate is limited
+    pub(crate) fn pace(&self) {
+        if let (Some(interval), Some(last)) = (self.frame_interval, self.clock.timestamp) {
+            let due = last + interval;
+            let now = std::time::Instant::now();
+            if due > now {
+                std::thread::sleep(due - now);
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Capture of a fixed number of frames as fast as possible, e.g. to profile
+//! animations by the timing of their frames
+
+use crate::{CaptureError, Capturer, Frame};
+use std::time::{Duration, Instant};
+
+/// Frames captured by `Capturer::capture_burst`, oldest first
+#[derive(Clone, Debug)]
+pub struct Burst {
+    pub frames: Vec<Frame>,
+    /// Capture times of the frames, as their `Frame::timestamp`
+    pub timestamps: Vec<Instant>,
+}
+
+impl Burst {
+    /// Time between each frame and the next
+    pub fn intervals(&self) -> Vec<Duration> {
+        self.timestamps.windows(2).map(|t| t[1] - t[0]).collect()
+    }
+
+    /// Time from the first frame to the last
+    pub fn duration(&self) -> Duration {
+        match (self.timestamps.first(), self.timestamps.last()) {
+            (Some(&first), Some(&last)) => last - first,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+impl Capturer {
+    /// Capture `n` frames as fast as possible, or as many as are captured
+    /// within `max_duration`
+    ///
+    /// The buffers of the frames are allocated up front, so that capturing
+    /// only copies the stored frames into them, in the format of `Bgr8`.
+    /// Captures are not limited by `Self::set_max_fps`. Captures that time
+    /// out are retried, but other errors end the burst with the error.
+    pub fn capture_burst(
+        &mut self,
+        n: usize,
+        max_duration: Duration,
+    ) -> Result<Burst, CaptureError> {
+        let (width, height) = self.geometry();
+        let mut buffers: Vec<_> = (0..n)
+            .map(|_| Vec::with_capacity(width as usize * height as usize))
+            .collect();
+        let mut burst = Burst {
+            frames: Vec::with_capacity(n),
+            timestamps: Vec::with_capacity(n),
+        };
+        let frame_interval = self.frame_interval.take();
+        let deadline = Instant::now() + max_duration;
+        let res = loop {
+            let buf = match buffers.pop() {
+                Some(buf) if Instant::now() < deadline => buf,
+                _ => break Ok(()),
+            };
+            match self.capture_store_frame() {
+                Ok(()) => {
+                    let frame = self.stored_frame_meta_in(buf);
+                    burst
+                        .timestamps
+                        .push(frame.timestamp().unwrap_or_else(Instant::now));
+                    burst.frames.push(frame);
+                }
+                Err(CaptureError::Timeout | CaptureError::NoNewFrame) => buffers.push(buf),
+                Err(e) => break Err(e),
+            }
+        };
+        self.frame_interval = frame_interval;
+        res.map(|()| burst)
+    }
+}
+
+#[cfg(test)]
+mod burst_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::Bgr8;
+
+    /// Backend of 2x1 frames numbered by their blue component, timing out
+    /// every other capture
+    struct Numbered {
+        captures: u8,
+        frame: Vec<Bgr8>,
+    }
+
+    impl Backend for Numbered {
+        fn name(&self) -> &'static str {
+            "numbered"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (2, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.captures += 1;
+            if self.captures.is_multiple_of(2) {
+                return Err(CaptureError::Timeout);
+            }
+            self.frame = vec![Bgr8::new(0, 0, self.captures / 2); 2];
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.frame, 2, 1))
+        }
+    }
+
+    #[test]
+    fn test_capture_burst() {
+        let mut capturer = Capturer::with_backend(Box::new(Numbered {
+            captures: 0,
+            frame: Vec::new(),
+        }));
+        capturer.set_max_fps(Some(1.0));
+        let burst = capturer.capture_burst(4, Duration::from_secs(10)).unwrap();
+        let numbers: Vec<_> = burst.frames.iter().map(|f| f.pixels()[0].b).collect();
+        assert_eq!(numbers, [0, 1, 2, 3]);
+        assert_eq!(burst.timestamps.len(), 4);
+        assert_eq!(burst.intervals().len(), 3);
+        assert!(burst.duration() < Duration::from_secs(1));
+        assert!(capturer
+            .capture_burst(4, Duration::ZERO)
+            .unwrap()
+            .frames
+            .is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! Capture on a background thread into a bounded queue of frames, with a
+//! choice of what to do when the consumer falls behind
+//!
+//! ```no_run
+//! use captrs::{Capturer, OverflowPolicy};
+//!
+//! let capturer = Capturer::new(0).unwrap();
+//! let frames = capturer.into_channel(8, OverflowPolicy::Block).unwrap();
+//! for frame in frames.iter() {
+//!     println!("{}x{}", frame.width(), frame.height());
+//! }
+//! ```
+
+use crate::threaded::CaptureStats;
+use crate::{CaptureError, Capturer, Frame, InitError};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// What to do with a captured frame when the queue of a `BoundedReceiver` is
+/// full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued frame to make room, so the queue holds the
+    /// latest frames
+    #[default]
+    DropOldest,
+    /// Drop the captured frame, so the queue holds the frames captured
+    /// first
+    DropNewest,
+    /// Stop capturing until the receiver takes a frame, so no frame is
+    /// dropped
+    Block,
+}
+
+/// The queued frames, shared with the capture thread
+#[derive(Default)]
+struct Queue {
+    frames: VecDeque<Frame>,
+    last_error: Option<CaptureError>,
+    stats: CaptureStats,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    /// Whether the receiver is gone, so the capture thread should stop
+    stop: AtomicBool,
+    /// Whether the capture thread is gone, so no more frames will be queued
+    closed: AtomicBool,
+}
+
+/// Receiver of the frames captured by `Capturer::into_channel`
+///
+/// The capture thread is stopped on drop.
+pub struct BoundedReceiver {
+    shared: Arc<Shared>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BoundedReceiver {
+    /// Wait for the next queued frame
+    ///
+    /// Returns `None` if the capture thread is gone, e.g. because it
+    /// panicked.
+    pub fn recv(&self) -> Option<Frame> {
+        let queue = self.shared.queue.lock().unwrap();
+        let queue = self
+            .shared
+            .not_empty
+            .wait_while(queue, |q| {
+                q.frames.is_empty() && !self.shared.closed.load(Ordering::SeqCst)
+            })
+            .unwrap();
+        self.pop(queue)
+    }
+
+    /// Wait up to `timeout` for the next queued frame
+    ///
+    /// Returns `None` on timeout, or if the capture thread is gone.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Frame> {
+        let queue = self.shared.queue.lock().unwrap();
+        let (queue, _) = self
+            .shared
+            .not_empty
+            .wait_timeout_while(queue, timeout, |q| {
+                q.frames.is_empty() && !self.shared.closed.load(Ordering::SeqCst)
+            })
+            .unwrap();
+        self.pop(queue)
+    }
+
+    /// The next queued frame, if any, without waiting
+    pub fn try_recv(&self) -> Option<Frame> {
+        self.pop(self.shared.queue.lock().unwrap())
+    }
+
+    fn pop(&self, mut queue: std::sync::MutexGuard<Queue>) -> Option<Frame> {
+        let frame = queue.frames.pop_front();
+        drop(queue);
+        if frame.is_some() {
+            self.shared.not_full.notify_one();
+        }
+        frame
+    }
+
+    /// Iterator waiting for the queued frames, ending when the capture
+    /// thread is gone
+    pub fn iter(&self) -> impl Iterator<Item = Frame> + '_ {
+        std::iter::from_fn(move || self.recv())
+    }
+
+    /// Number of frames queued
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().unwrap().frames.len()
+    }
+
+    /// Whether no frames are queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of frames that can be queued
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// What is done with captured frames when the queue is full
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// The last capture error other than a timeout, if any
+    pub fn last_error(&self) -> Option<CaptureError> {
+        self.shared.queue.lock().unwrap().last_error.clone()
+    }
+
+    /// Statistics of the captures so far. Frames dropped by the overflow
+    /// policy are counted as dropped.
+    pub fn stats(&self) -> CaptureStats {
+        self.shared.queue.lock().unwrap().stats
+    }
+}
+
+impl Drop for BoundedReceiver {
+    fn drop(&mut self) {
+        // Lock to not race the capture thread about to block
+        let queue = self.shared.queue.lock().unwrap();
+        self.shared.stop.store(true, Ordering::SeqCst);
+        drop(queue);
+        self.shared.not_full.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Marks the queue closed when the capture thread ends, even by panicking
+struct CloseOnDrop(Arc<Shared>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        let _queue = self.0.queue.lock();
+        self.0.closed.store(true, Ordering::SeqCst);
+        self.0.not_empty.notify_all();
+    }
+}
+
+fn capture_loop(mut capturer: Capturer, capacity: usize, policy: OverflowPolicy, shared: &Shared) {
+    while !shared.stop.load(Ordering::SeqCst) {
+        let res = capturer.capture_frame_meta();
+        let mut queue = shared.queue.lock().unwrap();
+        let frame = match res {
+            Ok(frame) => frame,
+            Err(CaptureError::Timeout | CaptureError::NoNewFrame) => {
+                queue.stats.timeouts += 1;
+                continue;
+            }
+            Err(e) => {
+                queue.stats.errors += 1;
+                queue.last_error = Some(e);
+                continue;
+            }
+        };
+        queue.stats.captured += 1;
+        if queue.frames.len() == capacity {
+            match policy {
+                OverflowPolicy::DropOldest => {
+                    queue.frames.pop_front();
+                    queue.stats.dropped += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    queue.stats.dropped += 1;
+                    continue;
+                }
+                OverflowPolicy::Block => {
+                    queue = shared
+                        .not_full
+                        .wait_while(queue, |q| {
+                            q.frames.len() == capacity && !shared.stop.load(Ordering::SeqCst)
+                        })
+                        .unwrap();
+                    if shared.stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                }
+            }
+        }
+        queue.frames.push_back(frame);
+        drop(queue);
+        shared.not_empty.notify_one();
+    }
+}
+
+impl Capturer {
+    /// Capture on a thread of its own into a queue of at most `capacity`
+    /// frames, handling a full queue by `policy`
+    ///
+    /// Frames are captured as fast as the configuration of the capturer
+    /// allows, e.g. at most at the rate of `Self::set_max_fps`. Dropped
+    /// frames are counted in `BoundedReceiver::stats`. A `capacity` of 0 is
+    /// treated as 1.
+    pub fn into_channel(
+        self,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Result<BoundedReceiver, InitError> {
+        let capacity = capacity.max(1);
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("captrs-channel".to_string())
+                .spawn(move || {
+                    let close = CloseOnDrop(shared);
+                    capture_loop(self, capacity, policy, &close.0)
+                })
+                .map_err(|e| e.to_string())?
+        };
+        Ok(BoundedReceiver {
+            shared,
+            capacity,
+            policy,
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(test)]
+mod channel_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::Bgr8;
+
+    /// Backend of 1x1 frames numbered by their blue component, of which
+    /// `limit` are captured before timing out
+    struct Numbered {
+        frame: Vec<Bgr8>,
+        captured: u8,
+        limit: u8,
+    }
+
+    impl Backend for Numbered {
+        fn name(&self) -> &'static str {
+            "numbered"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+            if self.captured == self.limit {
+                thread::sleep(timeout);
+                return Err(CaptureError::Timeout);
+            }
+            self.frame = vec![Bgr8::new(0, 0, self.captured)];
+            self.captured += 1;
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.frame, 1, 1))
+        }
+    }
+
+    fn channel(limit: u8, policy: OverflowPolicy) -> BoundedReceiver {
+        let mut capturer = Capturer::with_backend(Box::new(Numbered {
+            frame: Vec::new(),
+            captured: 0,
+            limit,
+        }));
+        capturer.set_timeout(Duration::from_millis(5)).unwrap();
+        capturer.into_channel(3, policy).unwrap()
+    }
+
+    /// Wait until all frames are captured, and return the numbers of those
+    /// queued
+    fn drain(frames: &BoundedReceiver, captured: u64) -> Vec<u8> {
+        while frames.stats().captured < captured {
+            thread::sleep(Duration::from_millis(1));
+        }
+        std::iter::from_fn(|| frames.try_recv())
+            .map(|frame| frame.pixels()[0].b)
+            .collect()
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let frames = channel(5, OverflowPolicy::DropOldest);
+        assert_eq!(frames.capacity(), 3);
+        assert_eq!(drain(&frames, 5), [2, 3, 4]);
+        assert_eq!(frames.stats().dropped, 2);
+
+        let frames = channel(5, OverflowPolicy::DropNewest);
+        assert_eq!(drain(&frames, 5), [0, 1, 2]);
+        assert_eq!(frames.stats().dropped, 2);
+        assert!(frames.is_empty());
+
+        let frames = channel(5, OverflowPolicy::Block);
+        let received: Vec<_> = (0..5)
+            .map(|_| frames.recv().unwrap().pixels()[0].b)
+            .collect();
+        assert_eq!(received, [0, 1, 2, 3, 4]);
+        assert_eq!(frames.stats().dropped, 0);
+        assert!(frames.recv_timeout(Duration::from_millis(20)).is_none());
+        assert!(frames.stats().timeouts > 0);
+        // Stops the capture thread blocked on a full queue
+        drop(channel(5, OverflowPolicy::Block));
+    }
+}
@@ -0,0 +1,687 @@
+//! Color correction of frames, e.g. from the gamut of a wide-gamut monitor
+//! to sRGB
+//!
+//! Transforms are either a 3x3 matrix applied in linear light, a 3D LUT
+//! loaded from an Adobe/Resolve `.cube` file, or the conversion from the ICC
+//! profile of a display, see `Capturer::set_color_management`.
+
+use crate::analysis::{linear_to_srgb, srgb_to_linear_table};
+use crate::{Bgr8, CaptureError, Capturer, Frame};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Number of entries of the table of linear light to sRGB encoded components
+const ENCODE_TABLE_SIZE: usize = 4096;
+
+/// Table of linear light, quantized to `ENCODE_TABLE_SIZE` steps, to sRGB
+/// encoded components
+fn linear_to_srgb_table() -> &'static [u8; ENCODE_TABLE_SIZE] {
+    static TABLE: OnceLock<[u8; ENCODE_TABLE_SIZE]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0; ENCODE_TABLE_SIZE];
+        for (i, c) in table.iter_mut().enumerate() {
+            *c = linear_to_srgb(i as f64 / (ENCODE_TABLE_SIZE - 1) as f64);
+        }
+        table
+    })
+}
+
+/// The color space of the pixels of a frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// The values of the framebuffer, in the color space of the display
+    #[default]
+    Display,
+    /// sRGB, converted from the color space of the display
+    Srgb,
+}
+
+/// A color transform to apply to frames
+pub trait ColorTransform {
+    /// Transform the colors of some pixels in place. Alpha is left as is.
+    fn apply(&self, pixels: &mut [Bgr8]);
+}
+
+/// A 3x3 matrix transforming linear RGB colors, e.g. to convert between
+/// color spaces of the same white point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorMatrix(pub [[f32; 3]; 3]);
+
+impl ColorMatrix {
+    pub const IDENTITY: ColorMatrix =
+        ColorMatrix([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+
+    /// Display P3 to sRGB. Colors outside of the sRGB gamut are clipped.
+    pub const DISPLAY_P3_TO_SRGB: ColorMatrix = ColorMatrix([
+        [1.2249, -0.2247, 0.0],
+        [-0.0420, 1.0419, 0.0],
+        [-0.0197, -0.0786, 1.0979],
+    ]);
+
+    /// Adobe RGB (1998) to sRGB. Colors outside of the sRGB gamut are
+    /// clipped.
+    pub const ADOBE_RGB_TO_SRGB: ColorMatrix = ColorMatrix([
+        [1.3982, -0.3982, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, -0.0429, 1.0429],
+    ]);
+}
+
+impl ColorTransform for ColorMatrix {
+    fn apply(&self, pixels: &mut [Bgr8]) {
+        let decode = srgb_to_linear_table();
+        let encode = linear_to_srgb_table();
+        let scale = (ENCODE_TABLE_SIZE - 1) as f32;
+        let m = &self.0;
+        for p in pixels {
+            let rgb = [
+                decode[p.r as usize],
+                decode[p.g as usize],
+                decode[p.b as usize],
+            ];
+            let [r, g, b] = m.map(|row| {
+                let l = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                encode[(l * scale + 0.5).clamp(0.0, scale) as usize]
+            });
+            p.r = r;
+            p.g = g;
+            p.b = b;
+        }
+    }
+}
+
+/// A 3D lookup table of colors, applied with trilinear interpolation to the
+/// sRGB encoded components
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut3d {
+    size: usize,
+    /// Output colors with red changing fastest, then green, then blue
+    table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// Construct a LUT of `size` entries per dimension from its output colors,
+    /// in the range 0 to 1, with red changing fastest, then green, then blue
+    pub fn new(size: usize, table: Vec<[f32; 3]>) -> Result<Lut3d, String> {
+        if size < 2 {
+            return Err("LUT size must be at least 2".to_string());
+        }
+        if table.len() != size * size * size {
+            return Err(format!(
+                "Expected {} LUT entries, got {}",
+                size * size * size,
+                table.len()
+            ));
+        }
+        Ok(Lut3d { size, table })
+    }
+
+    /// Parse a LUT in the `.cube` format
+    pub fn parse_cube(src: &str) -> Result<Lut3d, String> {
+        let mut size = None;
+        let (mut min, mut max) = ([0.0f32; 3], [1.0f32; 3]);
+        let mut table = Vec::new();
+        let parse_triple = |words: &[&str], line: usize| -> Result<[f32; 3], String> {
+            match words {
+                [a, b, c] => {
+                    let f = |s: &str| {
+                        s.parse::<f32>()
+                            .map_err(|e| format!("Line {}: {}", line, e))
+                    };
+                    Ok([f(a)?, f(b)?, f(c)?])
+                }
+                _ => Err(format!("Line {}: expected 3 values", line)),
+            }
+        };
+        for (i, line) in src.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.first() {
+                None => (),
+                Some(w) if w.starts_with('#') || *w == "TITLE" => (),
+                Some(&"LUT_3D_SIZE") => {
+                    size = Some(
+                        words
+                            .get(1)
+                            .and_then(|n| n.parse::<usize>().ok())
+                            .ok_or_else(|| format!("Line {}: invalid LUT_3D_SIZE", i + 1))?,
+                    )
+                }
+                Some(&"DOMAIN_MIN") => min = parse_triple(&words[1..], i + 1)?,
+                Some(&"DOMAIN_MAX") => max = parse_triple(&words[1..], i + 1)?,
+                Some(&"LUT_1D_SIZE") => return Err("1D LUTs are not supported".to_string()),
+                Some(w) if w.chars().all(|c| c.is_ascii_uppercase() || c == '_') => (),
+                Some(_) => {
+                    let rgb = parse_triple(&words, i + 1)?;
+                    // Normalize the output to the range 0 to 1
+                    let mut out = [0.0; 3];
+                    for c in 0..3 {
+                        out[c] = (rgb[c] - min[c]) / (max[c] - min[c]);
+                    }
+                    table.push(out);
+                }
+            }
+        }
+        Lut3d::new(size.ok_or("Missing LUT_3D_SIZE")?, table)
+    }
+
+    /// Load a LUT from a `.cube` file
+    pub fn load_cube<P: AsRef<Path>>(path: P) -> Result<Lut3d, String> {
+        let src = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Lut3d::parse_cube(&src)
+    }
+
+    /// Number of entries per dimension
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Look up a color with components in the range 0 to 1
+    pub fn lookup(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let n = self.size;
+        let mut i0 = [0; 3];
+        let mut t = [0.0; 3];
+        for c in 0..3 {
+            let x = rgb[c].clamp(0.0, 1.0) * (n - 1) as f32;
+            i0[c] = (x as usize).min(n - 2);
+            t[c] = x - i0[c] as f32;
+        }
+        let at = |dr: usize, dg: usize, db: usize| {
+            self.table[((i0[2] + db) * n + i0[1] + dg) * n + i0[0] + dr]
+        };
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| {
+            [
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]
+        };
+        let c00 = lerp(at(0, 0, 0), at(1, 0, 0), t[0]);
+        let c10 = lerp(at(0, 1, 0), at(1, 1, 0), t[0]);
+        let c01 = lerp(at(0, 0, 1), at(1, 0, 1), t[0]);
+        let c11 = lerp(at(0, 1, 1), at(1, 1, 1), t[0]);
+        lerp(lerp(c00, c10, t[1]), lerp(c01, c11, t[1]), t[2])
+    }
+}
+
+impl ColorTransform for Lut3d {
+    fn apply(&self, pixels: &mut [Bgr8]) {
+        for p in pixels {
+            let rgb = [p.r, p.g, p.b].map(|c| c as f32 / 255.0);
+            let [r, g, b] = self
+                .lookup(rgb)
+                .map(|c| (c * 255.0 + 0.5).clamp(0.0, 255.0) as u8);
+            p.r = r;
+            p.g = g;
+            p.b = b;
+        }
+    }
+}
+
+/// Linear Bradford-adapted XYZ with the D50 white point of ICC profiles to
+/// linear sRGB
+const XYZ_D50_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_142, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+/// The ICC profile of an RGB display, converting its colors to sRGB
+///
+/// Only matrix/TRC profiles are supported, i.e. profiles with primaries and
+/// tone response curves, which are what calibration tools and monitor
+/// drivers install.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+    /// Linear light of each of the 256 values of red, green, and blue
+    curves: [[f32; 256]; 3],
+    /// Linear RGB of the display to linear sRGB
+    matrix: ColorMatrix,
+}
+
+/// The tag with a signature of an ICC profile
+fn icc_tag<'a>(data: &'a [u8], signature: &[u8; 4]) -> Result<&'a [u8], String> {
+    let be32 = |o: usize| {
+        data.get(o..o + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+    let count = be32(128).ok_or("Truncated ICC profile")?;
+    (0..count)
+        .map(|i| 132 + 12 * i)
+        .find(|&o| data.get(o..o + 4) == Some(&signature[..]))
+        .and_then(|o| {
+            let (offset, size) = (be32(o + 4)?, be32(o + 8)?);
+            data.get(offset..offset.checked_add(size)?)
+        })
+        .ok_or_else(|| {
+            format!(
+                "ICC profile has no {} tag",
+                String::from_utf8_lossy(signature)
+            )
+        })
+}
+
+/// The `s15Fixed16Number`s of an ICC tag, from an offset
+fn icc_fixed(tag: &[u8], offset: usize, n: usize) -> Result<Vec<f32>, String> {
+    (0..n)
+        .map(|i| {
+            let b = tag
+                .get(offset + 4 * i..offset + 4 * i + 4)
+                .ok_or("Truncated ICC tag")?;
+            Ok(i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32 / 65536.0)
+        })
+        .collect()
+}
+
+/// Linear light of the 256 values of a `curv` or `para` tone response curve
+fn icc_curve(tag: &[u8]) -> Result<[f32; 256], String> {
+    let be16 = |o: usize| {
+        tag.get(o..o + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or("Truncated ICC curve")
+    };
+    let f: Box<dyn Fn(f32) -> f32> = match tag.get(..4) {
+        Some(b"curv") => {
+            let n = tag
+                .get(8..12)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+                .ok_or("Truncated ICC curve")?;
+            match n {
+                0 => Box::new(|x| x),
+                1 => {
+                    let gamma = be16(12)? as f32 / 256.0;
+                    Box::new(move |x: f32| x.powf(gamma))
+                }
+                _ => {
+                    let table = (0..n)
+                        .map(|i| Ok(be16(12 + 2 * i)? as f32 / 65535.0))
+                        .collect::<Result<Vec<f32>, String>>()?;
+                    Box::new(move |x: f32| {
+                        let x = x * (n - 1) as f32;
+                        let i = (x as usize).min(n - 2);
+                        table[i] + (table[i + 1] - table[i]) * (x - i as f32)
+                    })
+                }
+            }
+        }
+        Some(b"para") => {
+            let n_params = match be16(8)? {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                t => return Err(format!("Unknown ICC parametric curve type {}", t)),
+            };
+            let mut p = icc_fixed(tag, 12, n_params)?;
+            p.resize(7, 0.0);
+            let (g, a, b, c, d, e, f) = (p[0], p[1], p[2], p[3], p[4], p[5], p[6]);
+            Box::new(move |x: f32| match n_params {
+                1 => x.powf(g),
+                3 if x >= -b / a => (a * x + b).powf(g),
+                3 => 0.0,
+                4 if x >= -b / a => (a * x + b).powf(g) + c,
+                4 => c,
+                5 if x >= d => (a * x + b).powf(g),
+                5 => c * x,
+                _ if x >= d => (a * x + b).powf(g) + e,
+                _ => c * x + f,
+            })
+        }
+        _ => return Err("Unsupported ICC tone response curve".to_string()),
+    };
+    let mut curve = [0.0; 256];
+    for (i, v) in curve.iter_mut().enumerate() {
+        *v = f(i as f32 / 255.0);
+    }
+    Ok(curve)
+}
+
+impl IccProfile {
+    /// Parse an ICC profile, e.g. the contents of an `.icc` file
+    pub fn parse(data: &[u8]) -> Result<IccProfile, String> {
+        if data.len() < 132 {
+            return Err("Truncated ICC profile".to_string());
+        }
+        if &data[36..40] != b"acsp" {
+            return Err("Not an ICC profile".to_string());
+        }
+        if &data[16..20] != b"RGB " || &data[20..24] != b"XYZ " {
+            return Err("Only RGB ICC profiles with an XYZ connection space are supported".into());
+        }
+        let mut to_xyz = [[0.0; 3]; 3];
+        let mut curves = [[0.0; 256]; 3];
+        for (c, (xyz, trc)) in [(b"rXYZ", b"rTRC"), (b"gXYZ", b"gTRC"), (b"bXYZ", b"bTRC")]
+            .iter()
+            .enumerate()
+        {
+            let primary = icc_fixed(icc_tag(data, xyz)?, 8, 3)?;
+            for (row, v) in to_xyz.iter_mut().zip(primary) {
+                row[c] = v;
+            }
+            curves[c] = icc_curve(icc_tag(data, trc)?)?;
+        }
+        let mut matrix = [[0.0; 3]; 3];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..3).map(|k| XYZ_D50_TO_SRGB[i][k] * to_xyz[k][j]).sum();
+            }
+        }
+        Ok(IccProfile {
+            curves,
+            matrix: ColorMatrix(matrix),
+        })
+    }
+
+    /// The profile of the display with a capture source index, if one is
+    /// assigned to it and supported
+    ///
+    /// Profiles are assigned in the color management settings of Windows and
+    /// macOS, and on X11 by color managers as the `_ICC_PROFILE` properties
+    /// of the root window.
+    pub fn for_display(index: usize) -> Option<IccProfile> {
+        IccProfile::parse(&crate::display::icc_profile(index)?).ok()
+    }
+
+    /// The 3x3 matrix from the linear RGB of the display to linear sRGB
+    pub fn matrix(&self) -> ColorMatrix {
+        self.matrix
+    }
+}
+
+impl ColorTransform for IccProfile {
+    fn apply(&self, pixels: &mut [Bgr8]) {
+        let encode = linear_to_srgb_table();
+        let scale = (ENCODE_TABLE_SIZE - 1) as f32;
+        let [cr, cg, cb] = &self.curves;
+        let m = &self.matrix.0;
+        for p in pixels {
+            let rgb = [cr[p.r as usize], cg[p.g as usize], cb[p.b as usize]];
+            let [r, g, b] = m.map(|row| {
+                let l = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                encode[(l * scale + 0.5).clamp(0.0, scale) as usize]
+            });
+            p.r = r;
+            p.g = g;
+            p.b = b;
+        }
+    }
+}
+
+/// A chain of brightness, contrast, and gamma adjustments, applied in the
+/// order they were added
+///
+/// The chain is baked into a table of the 256 component values, so applying
+/// it costs the same no matter how many adjustments it has.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameFilter {
+    /// The adjusted components, in the range 0 to 1 but not clamped
+    curve: [f32; 256],
+    table: [u8; 256],
+}
+
+impl FrameFilter {
+    /// A filter that does not change the colors
+    pub fn new() -> FrameFilter {
+        let mut filter = FrameFilter {
+            curve: [0.0; 256],
+            table: [0; 256],
+        };
+        for (i, (c, v)) in filter.curve.iter_mut().zip(&mut filter.table).enumerate() {
+            *c = i as f32 / 255.0;
+            *v = i as u8;
+        }
+        filter
+    }
+
+    fn map(mut self, f: impl Fn(f32) -> f32) -> FrameFilter {
+        for (c, v) in self.curve.iter_mut().zip(&mut self.table) {
+            *c = f(*c);
+            *v = (*c * 255.0 + 0.5).clamp(0.0, 255.0) as u8;
+        }
+        self
+    }
+
+    /// Add `amount`, from -1 to 1, to the components
+    pub fn brightness(self, amount: f32) -> FrameFilter {
+        self.map(|c| c + amount)
+    }
+
+    /// Scale the distance of the components from mid-gray by `factor`.
+    /// Factors above 1 increase the contrast.
+    pub fn contrast(self, factor: f32) -> FrameFilter {
+        self.map(|c| (c - 0.5) * factor + 0.5)
+    }
+
+    /// Raise the components to the power of `1 / gamma`. Gammas above 1
+    /// brighten the shadows.
+    pub fn gamma(self, gamma: f32) -> FrameFilter {
+        self.map(|c| c.max(0.0).powf(1.0 / gamma))
+    }
+
+    /// Copy some pixels into `dst`, applying the filter in the same pass
+    pub fn apply_copy(&self, src: &[Bgr8], dst: &mut Vec<Bgr8>) {
+        let t = &self.table;
+        dst.clear();
+        dst.extend(src.iter().map(|p| Bgr8 {
+            b: t[p.b as usize],
+            g: t[p.g as usize],
+            r: t[p.r as usize],
+            a: p.a,
+        }));
+    }
+}
+
+impl Default for FrameFilter {
+    fn default() -> FrameFilter {
+        FrameFilter::new()
+    }
+}
+
+impl ColorTransform for FrameFilter {
+    fn apply(&self, pixels: &mut [Bgr8]) {
+        let t = &self.table;
+        for p in pixels {
+            p.b = t[p.b as usize];
+            p.g = t[p.g as usize];
+            p.r = t[p.r as usize];
+        }
+    }
+}
+
+impl Capturer {
+    /// Capture the screen and return an owned `Vec` of the image color data,
+    /// with a filter applied while copying it
+    ///
+    /// The unfiltered frame is stored in `self`, as by
+    /// `Self::capture_store_frame`.
+    pub fn capture_frame_filtered(
+        &mut self,
+        filter: &FrameFilter,
+    ) -> Result<Vec<Bgr8>, CaptureError> {
+        self.capture_store_frame()?;
+        let mut frame = Vec::new();
+        filter.apply_copy(self.get_stored_frame().unwrap(), &mut frame);
+        Ok(frame)
+    }
+}
+
+impl Capturer {
+    /// Convert captured frames from the ICC profile of the display to sRGB,
+    /// e.g. so that the colors of wide-gamut monitors are not oversaturated
+    /// in saved images
+    ///
+    /// The profile is loaded now and when the capture source changes.
+    /// Displays without a supported profile are assumed to be sRGB, see
+    /// `Self::display_profile`. The conversion is applied last, to the
+    /// scaled frames with the cursor drawn.
+    pub fn set_color_management(&mut self, enabled: bool) {
+        self.color_managed = enabled;
+        self.load_display_profile();
+    }
+
+    /// Whether captured frames are converted to sRGB, see
+    /// `Self::set_color_management`
+    pub fn color_management(&self) -> bool {
+        self.color_managed
+    }
+
+    /// The ICC profile frames are converted from, if color managed and the
+    /// display has a supported one
+    pub fn display_profile(&self) -> Option<&IccProfile> {
+        self.display_profile.as_ref()
+    }
+
+    /// The color space of captured frames
+    pub fn color_space(&self) -> ColorSpace {
+        if self.color_managed {
+            ColorSpace::Srgb
+        } else {
+            ColorSpace::Display
+        }
+    }
+
+    /// Load the ICC profile of the display of the capture source, if color
+    /// managed
+    pub(crate) fn load_display_profile(&mut self) {
+        self.display_profile = if self.color_managed {
+            self.display()
+                .and_then(|d| IccProfile::for_display(d.index))
+        } else {
+            None
+        };
+        self.processed = None;
+    }
+}
+
+impl Frame {
+    /// Apply a color transform to the frame
+    pub fn transform_colors<T: ColorTransform + ?Sized>(&mut self, transform: &T) {
+        transform.apply(self.pixels_mut())
+    }
+}
+
+#[cfg(test)]
+mod color_tests {
+    use super::*;
+
+    #[test]
+    fn test_color_matrix() {
+        let mut pixels = vec![Bgr8::new(0, 128, 255), Bgr8::new(10, 20, 30)];
+        let orig = pixels.clone();
+        ColorMatrix::IDENTITY.apply(&mut pixels);
+        assert_eq!(pixels, orig);
+        // Saturated P3 red is outside of sRGB and gets clipped
+        let mut red = [Bgr8::new(255, 0, 0)];
+        ColorMatrix::DISPLAY_P3_TO_SRGB.apply(&mut red);
+        assert_eq!(red, [Bgr8::new(255, 0, 0)]);
+    }
+
+    #[test]
+    fn test_lut3d() {
+        // Inverts the colors
+        let src = "TITLE \"invert\"\n# comment\nLUT_3D_SIZE 2\n\
+                   1 1 1\n0 1 1\n1 0 1\n0 0 1\n1 1 0\n0 1 0\n1 0 0\n0 0 0\n";
+        let lut = Lut3d::parse_cube(src).unwrap();
+        assert_eq!(lut.size(), 2);
+        let mut frame = Frame::new(vec![Bgr8::new(0, 100, 255)], 1, 1);
+        frame.transform_colors(&lut);
+        assert_eq!(frame.pixels(), &[Bgr8::new(255, 155, 0)]);
+        assert!(Lut3d::parse_cube("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+    }
+
+    #[test]
+    fn test_frame_filter() {
+        let pixels = [Bgr8::new(0, 64, 255), Bgr8::new(128, 200, 30)];
+        let mut out = Vec::new();
+        FrameFilter::new().apply_copy(&pixels, &mut out);
+        assert_eq!(out, pixels);
+        let filter = FrameFilter::new().brightness(0.5).contrast(2.0);
+        filter.apply_copy(&pixels, &mut out);
+        assert_eq!(out, [Bgr8::new(128, 255, 255), Bgr8::new(255, 255, 188)]);
+        let mut dark = [Bgr8::new(64, 64, 64)];
+        FrameFilter::new().gamma(2.0).apply(&mut dark);
+        assert_eq!(dark, [Bgr8::new(128, 128, 128)]);
+    }
+
+    /// A matrix/TRC ICC profile of primaries in D50 XYZ, with a `curv` of
+    /// the gamma 2.2 or a `para` of the sRGB curve
+    fn icc_profile(primaries: [[f32; 3]; 3], srgb_curve: bool) -> Vec<u8> {
+        let fixed = |v: f32| ((v * 65536.0).round() as i32).to_be_bytes();
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+        for (xyz, primary) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().zip(&primaries) {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            primary.iter().for_each(|&v| tag.extend(fixed(v)));
+            tags.push((xyz, tag));
+        }
+        let curve = if srgb_curve {
+            let mut tag = b"para\0\0\0\0\0\x03\0\0".to_vec();
+            for &v in &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+                tag.extend(fixed(v));
+            }
+            tag
+        } else {
+            b"curv\0\0\0\0\0\0\0\x01\x02\x33".to_vec()
+        };
+        for trc in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((trc, curve.clone()));
+        }
+        let mut data = vec![0; 128];
+        data[16..24].copy_from_slice(b"RGB XYZ ");
+        data[36..40].copy_from_slice(b"acsp");
+        data.extend((tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + 12 * tags.len();
+        for (signature, tag) in &tags {
+            data.extend_from_slice(&signature[..]);
+            data.extend((offset as u32).to_be_bytes());
+            data.extend((tag.len() as u32).to_be_bytes());
+            offset += tag.len();
+        }
+        tags.iter().for_each(|(_, tag)| data.extend(tag));
+        data
+    }
+
+    #[test]
+    fn test_icc_profile() {
+        // sRGB, adapted to D50, converts to itself
+        let srgb = [
+            [0.436_075, 0.222_504, 0.013_932],
+            [0.385_065, 0.716_879, 0.097_105],
+            [0.143_080, 0.060_617, 0.714_173],
+        ];
+        let profile = IccProfile::parse(&icc_profile(srgb, true)).unwrap();
+        let pixels: Vec<Bgr8> = (0..=255).map(|v| Bgr8::new(v, 255 - v, v / 2)).collect();
+        let close = |a: &[Bgr8], b: &[Bgr8], d: u8| {
+            a.iter().zip(b).all(|(a, b)| {
+                a.r.abs_diff(b.r) <= d && a.g.abs_diff(b.g) <= d && a.b.abs_diff(b.b) <= d
+            })
+        };
+        let mut converted = pixels.clone();
+        profile.apply(&mut converted);
+        assert!(close(&pixels, &converted, 1));
+        // Display P3 converts as with its matrix
+        let p3 = [
+            [0.515_1, 0.241_2, -0.001_1],
+            [0.292_0, 0.692_2, 0.041_9],
+            [0.157_1, 0.066_6, 0.784_1],
+        ];
+        let profile = IccProfile::parse(&icc_profile(p3, true)).unwrap();
+        let (mut converted, mut expected) = (pixels.clone(), pixels.clone());
+        profile.apply(&mut converted);
+        ColorMatrix::DISPLAY_P3_TO_SRGB.apply(&mut expected);
+        assert!(close(&converted, &expected, 2));
+        // Gray stays gray with a gamma 2.2 curve
+        let profile = IccProfile::parse(&icc_profile(srgb, false)).unwrap();
+        let mut gray = [Bgr8::new(128, 128, 128)];
+        profile.apply(&mut gray);
+        assert!(close(
+            &gray,
+            &[Bgr8::new(gray[0].g, gray[0].g, gray[0].g)],
+            1
+        ));
+        // Not an RGB matrix/TRC profile
+        let mut cmyk = icc_profile(srgb, true);
+        cmyk[16..20].copy_from_slice(b"CMYK");
+        assert!(IccProfile::parse(&cmyk).is_err());
+        assert!(IccProfile::parse(&icc_profile(srgb, true)[..140]).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! Similarity metrics between frames, e.g. to compare captures to golden
+//! images in visual regression tests, and perceptual hashes of frames, e.g.
+//! to skip near-identical frames
+
+use crate::analysis::luma;
+use crate::{Bgr8, Frame};
+
+/// Width and height of the windows that SSIM is computed over
+const SSIM_WINDOW: usize = 8;
+
+/// Width and height of the luma plane that `Frame::phash` is computed from
+const PHASH_SIZE: usize = 32;
+
+/// Most pixels sampled per row and column of each cell of a downscaled luma
+/// plane
+const CELL_SAMPLES: usize = 4;
+
+/// A 64-bit perceptual hash of a frame, from `Frame::phash` or
+/// `Frame::dhash`
+///
+/// Similar frames have hashes differing in few bits, even if scaled or
+/// slightly changed, e.g. by noise or compression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FrameHash(pub u64);
+
+impl FrameHash {
+    /// The number of bits differing between two hashes of the same kind,
+    /// from 0 if the frames look alike to 64
+    ///
+    /// Frames with hashes less than about 5 bits apart look nearly
+    /// identical.
+    pub fn hamming_distance(&self, other: &FrameHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+}
+
+/// The luma of a frame downscaled to `w`x`h` cells, each the mean of up to
+/// `CELL_SAMPLES` squared pixels sampled evenly from it
+fn luma_plane(pixels: &[Bgr8], width: usize, height: usize, w: usize, h: usize) -> Vec<f32> {
+    let mut plane = vec![0.0; w * h];
+    if width == 0 || height == 0 {
+        return plane;
+    }
+    let bounds = |i: usize, len: usize, n: usize| {
+        let start = i * len / n;
+        let end = ((i + 1) * len / n).max(start + 1);
+        (start, end, (end - start).div_ceil(CELL_SAMPLES))
+    };
+    for cy in 0..h {
+        let (y0, y1, sy) = bounds(cy, height, h);
+        for cx in 0..w {
+            let (x0, x1, sx) = bounds(cx, width, w);
+            let (mut sum, mut n) = (0, 0);
+            for y in (y0..y1).step_by(sy) {
+                for x in (x0..x1).step_by(sx) {
+                    sum += luma(pixels[y * width + x]) as u32;
+                    n += 1;
+                }
+            }
+            plane[cy * w + cx] = sum as f32 / n as f32;
+        }
+    }
+    plane
+}
+
+/// Mean squared error of the red, green, and blue components of two frames
+/// of equal size
+fn mse(a: &[Bgr8], b: &[Bgr8]) -> f64 {
+    let sum: u64 = a
+        .iter()
+        .zip(b)
+        .map(|(p, q)| {
+            let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u64;
+            d(p.r, q.r) + d(p.g, q.g) + d(p.b, q.b)
+        })
+        .sum();
+    sum as f64 / (3 * a.len()) as f64
+}
+
+/// SSIM of the luma of two frames of equal size, as the mean over windows of
+/// `SSIM_WINDOW` pixels square
+fn ssim(a: &[Bgr8], b: &[Bgr8], width: usize, height: usize) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (mut total, mut n_windows) = (0.0, 0);
+    for y in (0..height).step_by(SSIM_WINDOW) {
+        for x in (0..width).step_by(SSIM_WINDOW) {
+            let (w, h) = (SSIM_WINDOW.min(width - x), SSIM_WINDOW.min(height - y));
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for row in y..y + h {
+                for i in row * width + x..row * width + x + w {
+                    let (u, v) = (luma(a[i]) as f64, luma(b[i]) as f64);
+                    sa += u;
+                    sb += v;
+                    saa += u * u;
+                    sbb += v * v;
+                    sab += u * v;
+                }
+            }
+            let n = (w * h) as f64;
+            let (ma, mb) = (sa / n, sb / n);
+            let (va, vb) = (saa / n - ma * ma, sbb / n - mb * mb);
+            let cov = sab / n - ma * mb;
+            total += (2.0 * ma * mb + C1) * (2.0 * cov + C2)
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            n_windows += 1;
+        }
+    }
+    if n_windows == 0 {
+        1.0
+    } else {
+        total / n_windows as f64
+    }
+}
+
+impl Frame {
+    /// Peak signal-to-noise ratio in decibels between this frame and another
+    /// of the same size, over the red, green, and blue components
+    ///
+    /// Infinite if the frames are equal. Above 40 dB, differences are hardly
+    /// visible.
+    pub fn psnr(&self, other: &Frame) -> f64 {
+        assert_eq!(self.geometry(), other.geometry());
+        let mse = mse(self.pixels(), other.pixels());
+        if mse == 0.0 {
+            f64::INFINITY
+        } else {
+            10.0 * (255.0 * 255.0 / mse).log10()
+        }
+    }
+
+    /// Structural similarity index between this frame and another of the
+    /// same size, from 1 if they are equal down to -1
+    ///
+    /// Computed on the luma over 8x8 windows, so it is not sensitive to
+    /// changes in hue.
+    pub fn ssim(&self, other: &Frame) -> f64 {
+        assert_eq!(self.geometry(), other.geometry());
+        let (width, height) = self.geometry();
+        ssim(
+            self.pixels(),
+            other.pixels(),
+            width as usize,
+            height as usize,
+        )
+    }
+
+    /// Perceptual hash of the frame, from the lowest frequencies of the DCT
+    /// of its luma downscaled to 32x32
+    ///
+    /// More robust to changes of brightness and contrast than
+    /// `Self::dhash`, and about as fast: few pixels are sampled, so that
+    /// hashing takes well below a millisecond even for 4K frames.
+    pub fn phash(&self) -> FrameHash {
+        const N: usize = PHASH_SIZE;
+        let (width, height) = self.geometry();
+        let plane = luma_plane(self.pixels(), width as usize, height as usize, N, N);
+        let cos: Vec<f32> = (0..8 * N)
+            .map(|i| {
+                let (u, x) = (i / N, i % N);
+                ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / (2 * N) as f32).cos()
+            })
+            .collect();
+        // The 8 lowest frequencies of each row, then of each column of those
+        let mut rows = [0.0; 8 * N];
+        for y in 0..N {
+            for u in 0..8 {
+                rows[y * 8 + u] = (0..N).map(|x| plane[y * N + x] * cos[u * N + x]).sum();
+            }
+        }
+        let mut dct = [0.0f32; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                dct[v * 8 + u] = (0..N).map(|y| rows[y * 8 + u] * cos[v * N + y]).sum();
+            }
+        }
+        // The median without the mean brightness
+        let mut sorted = dct[1..].to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let median = sorted[sorted.len() / 2];
+        FrameHash(
+            dct.iter()
+                .enumerate()
+                .filter(|&(_, &c)| c > median)
+                .fold(0, |hash, (i, _)| hash | 1 << i),
+        )
+    }
+
+    /// Difference hash of the frame, of whether the luma increases between
+    /// horizontally adjacent cells of it downscaled to 9x8
+    ///
+    /// The fastest hash, and robust to scaling and noise. See
+    /// `Self::phash`.
+    pub fn dhash(&self) -> FrameHash {
+        let (width, height) = self.geometry();
+        let plane = luma_plane(self.pixels(), width as usize, height as usize, 9, 8);
+        let mut hash = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                if plane[y * 9 + x] < plane[y * 9 + x + 1] {
+                    hash |= 1 << (y * 8 + x);
+                }
+            }
+        }
+        FrameHash(hash)
+    }
+}
+
+#[cfg(test)]
+mod compare_tests {
+    use super::*;
+
+    #[test]
+    fn test_psnr_ssim() {
+        let gray = Frame::new(vec![Bgr8::new(128, 128, 128); 16 * 16], 16, 16);
+        let mut pixels = gray.pixels().to_vec();
+        for p in pixels.iter_mut().step_by(2) {
+            *p = Bgr8::new(138, 138, 138);
+        }
+        let noisy = Frame::new(pixels, 16, 16);
+        assert_eq!(gray.psnr(&gray), f64::INFINITY);
+        assert_eq!(gray.ssim(&gray), 1.0);
+        // Half of the components differ by 10, so the MSE is 50
+        assert!((gray.psnr(&noisy) - 10.0 * (255.0 * 255.0 / 50.0f64).log10()).abs() < 1e-9);
+        assert!(gray.ssim(&noisy) < 1.0);
+    }
+
+    /// A frame of a bright blob in a gradient, with noise if `seed` is not 0
+    fn blob(width: u32, height: u32, seed: u32) -> Frame {
+        let pixels = (0..width * height)
+            .map(|i| {
+                // In units of 1/100 of the width and height
+                let (x, y) = (i % width * 100 / width, i / width * 100 / height);
+                let d = (x as i32 - 30).pow(2) + (y as i32 - 60).pow(2);
+                let v = x + y / 2 + if d < 400 { 100 } else { 0 };
+                let noise = i.wrapping_mul(seed).wrapping_mul(2_654_435_761) >> 29;
+                let v = (v + noise) as u8;
+                Bgr8::new(v, v, v)
+            })
+            .collect();
+        Frame::new(pixels, width, height)
+    }
+
+    #[test]
+    fn test_perceptual_hashes() {
+        let frame = blob(200, 120, 0);
+        for hash in [Frame::phash, Frame::dhash] {
+            let h = hash(&frame);
+            assert_eq!(h, hash(&frame.clone()));
+            // Scaled and with noise
+            assert!(h.hamming_distance(&hash(&blob(320, 180, 7))) <= 5);
+            // Mirrored
+            let mut pixels = frame.pixels().to_vec();
+            pixels.chunks_mut(200).for_each(|row| row.reverse());
+            let mirrored = Frame::new(pixels, 200, 120);
+            assert!(h.hamming_distance(&hash(&mirrored)) > 16);
+        }
+        let flat = Frame::new(vec![Bgr8::new(9, 9, 9); 4], 2, 2);
+        assert_eq!(flat.dhash(), FrameHash(0));
+        assert_eq!(Frame::new(Vec::new(), 0, 0).dhash(), FrameHash(0));
+    }
+}
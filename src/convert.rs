@@ -0,0 +1,425 @@
+//! Conversion of pixels to other pixel formats
+//!
+//! Uses AVX2, SSSE3 or SSE2 on x86_64 and NEON on aarch64 when available, and
+//! falls back to scalar code.
+
+use crate::analysis::luma;
+use crate::parallel::{convert_chunks, fill_rows};
+use crate::{pixel_bytes, pixel_bytes_mut, Bgr8, PixelFormat};
+
+/// Convert pixels of rows of `width` pixels to the given format, replacing
+/// the contents of `dst`
+pub(crate) fn convert(src: &[Bgr8], width: u32, format: PixelFormat, dst: &mut Vec<u8>) {
+    dst.clear();
+    match format {
+        PixelFormat::Bgra8 => dst.extend_from_slice(pixel_bytes(src)),
+        // Dithered by position, so converted row by row
+        PixelFormat::Rgb565 | PixelFormat::Rgb332 => {
+            let n = format.bytes_per_pixel();
+            dst.resize(n * src.len(), 0);
+            let f = match format {
+                PixelFormat::Rgb565 => to_rgb565,
+                _ => to_rgb332,
+            };
+            let width = width as usize;
+            fill_rows(dst, n * width, |y, row| {
+                f(&src[y * width..(y + 1) * width], y, row)
+            });
+        }
+        format => {
+            let n = format.bytes_per_pixel();
+            dst.resize(n * src.len(), 0);
+            let f = match format {
+                PixelFormat::Rgba8 => to_rgba,
+                PixelFormat::Rgb8 => to_rgb,
+                PixelFormat::Gray8 => to_gray,
+                PixelFormat::Rgba16F => crate::hdr::to_rgba16f,
+                _ => crate::hdr::to_rgb10a2,
+            };
+            convert_chunks(src, dst, n, f);
+        }
+    }
+}
+
+/// 4x4 Bayer matrix of the thresholds of ordered dithering, from 0 to 15
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Quantize an 8-bit component to `levels + 1` levels, rounding up if its
+/// fraction exceeds the Bayer threshold `t`. 0 and 255 are exact.
+fn dither(v: u8, levels: u32, t: u8) -> u32 {
+    (v as u32 * levels + (2 * t as u32 + 1) * 255 / 32) / 255
+}
+
+/// Convert row `y` of pixels to dithered RGB565. `dst` must have room for 2
+/// bytes per pixel.
+fn to_rgb565(src: &[Bgr8], y: usize, dst: &mut [u8]) {
+    let thresholds = &BAYER[y % 4];
+    for (x, (p, d)) in src.iter().zip(dst.chunks_exact_mut(2)).enumerate() {
+        let t = thresholds[x % 4];
+        let v = dither(p.r, 31, t) << 11 | dither(p.g, 63, t) << 5 | dither(p.b, 31, t);
+        d.copy_from_slice(&(v as u16).to_le_bytes());
+    }
+}
+
+/// Convert row `y` of pixels to dithered RGB332. `dst` must have room for 1
+/// byte per pixel.
+fn to_rgb332(src: &[Bgr8], y: usize, dst: &mut [u8]) {
+    let thresholds = &BAYER[y % 4];
+    for (x, (p, d)) in src.iter().zip(dst).enumerate() {
+        let t = thresholds[x % 4];
+        *d = (dither(p.r, 7, t) << 5 | dither(p.g, 7, t) << 2 | dither(p.b, 3, t)) as u8;
+    }
+}
+
+/// Convert pixels to RGBA with opaque alpha. `dst` must have room for 4
+/// bytes per pixel.
+pub(crate) fn to_rgba(src: &[Bgr8], dst: &mut [u8]) {
+    assert!(dst.len() >= 4 * src.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safe as AVX2 is available
+            return unsafe { x86::to_rgba_avx2(src, dst) };
+        }
+        // SSE2 is always available on x86_64
+        unsafe { x86::to_rgba_sse2(src, dst) }
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        // Safe as NEON is available
+        return unsafe { neon::to_rgba(src, dst) };
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    to_rgba_scalar(src, dst)
+}
+
+/// Convert RGBA bytes to pixels with opaque alpha, e.g. from browsers and
+/// Android. `src` must have 4 bytes per pixel of `dst`.
+#[cfg_attr(
+    not(any(
+        all(target_os = "android", feature = "android"),
+        all(target_arch = "wasm32", feature = "web")
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn from_rgba(src: &[u8], dst: &mut [Bgr8]) {
+    assert!(src.len() >= 4 * dst.len());
+    // Swapping red and blue is its own inverse. `Bgr8` is 4 bytes of
+    // alignment 1.
+    let src = unsafe { std::slice::from_raw_parts(src.as_ptr() as *const Bgr8, dst.len()) };
+    to_rgba(src, pixel_bytes_mut(dst));
+}
+
+/// Convert pixels to RGB. `dst` must have room for 3 bytes per pixel.
+pub(crate) fn to_rgb(src: &[Bgr8], dst: &mut [u8]) {
+    assert!(dst.len() >= 3 * src.len());
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // Safe as AVX2 is available
+        return unsafe { x86::to_rgb_avx2(src, dst) };
+    }
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("ssse3") {
+        // Safe as SSSE3 is available
+        return unsafe { x86::to_rgb_ssse3(src, dst) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        // Safe as NEON is available
+        return unsafe { neon::to_rgb(src, dst) };
+    }
+    to_rgb_scalar(src, dst)
+}
+
+/// Convert pixels to their BT.709 weighted luminance. `dst` must have room
+/// for 1 byte per pixel.
+pub(crate) fn to_gray(src: &[Bgr8], dst: &mut [u8]) {
+    assert!(dst.len() >= src.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SSE2 is always available on x86_64
+        unsafe { x86::to_gray_sse2(src, dst) }
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        // Safe as NEON is available
+        return unsafe { neon::to_gray(src, dst) };
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    to_gray_scalar(src, dst)
+}
+
+fn to_rgba_scalar(src: &[Bgr8], dst: &mut [u8]) {
+    for (p, d) in src.iter().zip(dst.chunks_exact_mut(4)) {
+        d.copy_from_slice(&[p.r, p.g, p.b, 255]);
+    }
+}
+
+fn to_rgb_scalar(src: &[Bgr8], dst: &mut [u8]) {
+    for (p, d) in src.iter().zip(dst.chunks_exact_mut(3)) {
+        d.copy_from_slice(&[p.r, p.g, p.b]);
+    }
+}
+
+fn to_gray_scalar(src: &[Bgr8], dst: &mut [u8]) {
+    for (p, d) in src.iter().zip(dst) {
+        *d = luma(*p);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{to_gray_scalar, to_rgb_scalar, to_rgba_scalar};
+    use crate::Bgr8;
+    use std::arch::x86_64::*;
+
+    /// 8 pixels at a time with `vpshufb`
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn to_rgba_avx2(src: &[Bgr8], dst: &mut [u8]) {
+        let shuffle = _mm256_setr_epi8(
+            2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15, //
+            2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15,
+        );
+        let opaque = _mm256_set1_epi32(0xff00_0000u32 as i32);
+        let n = src.len() / 8 * 8;
+        for i in (0..n).step_by(8) {
+            let px = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+            let px = _mm256_or_si256(_mm256_shuffle_epi8(px, shuffle), opaque);
+            _mm256_storeu_si256(dst.as_mut_ptr().add(4 * i) as *mut __m256i, px);
+        }
+        to_rgba_scalar(&src[n..], &mut dst[4 * n..]);
+    }
+
+    /// 4 pixels at a time, swapping red and blue with shifts and masks
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn to_rgba_sse2(src: &[Bgr8], dst: &mut [u8]) {
+        let green = _mm_set1_epi32(0x0000_ff00);
+        let low = _mm_set1_epi32(0x0000_00ff);
+        let opaque = _mm_set1_epi32(0xff00_0000u32 as i32);
+        let n = src.len() / 4 * 4;
+        for i in (0..n).step_by(4) {
+            let px = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+            let r = _mm_and_si128(_mm_srli_epi32(px, 16), low);
+            let b = _mm_slli_epi32(_mm_and_si128(px, low), 16);
+            let g = _mm_and_si128(px, green);
+            let px = _mm_or_si128(_mm_or_si128(r, g), _mm_or_si128(b, opaque));
+            _mm_storeu_si128(dst.as_mut_ptr().add(4 * i) as *mut __m128i, px);
+        }
+        to_rgba_scalar(&src[n..], &mut dst[4 * n..]);
+    }
+
+    /// 8 pixels at a time with `vpshufb`, packing each 128-bit lane into
+    /// 12 bytes
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn to_rgb_avx2(src: &[Bgr8], dst: &mut [u8]) {
+        let shuffle = _mm256_setr_epi8(
+            2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1, //
+            2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1,
+        );
+        // The store of the upper lane writes 4 bytes past the 24 of the 8
+        // pixels, so at least 2 pixels are left for the scalar code
+        let n = src.len().saturating_sub(2) / 8 * 8;
+        for i in (0..n).step_by(8) {
+            let px = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+            let px = _mm256_shuffle_epi8(px, shuffle);
+            let out = dst.as_mut_ptr().add(3 * i);
+            _mm_storeu_si128(out as *mut __m128i, _mm256_castsi256_si128(px));
+            _mm_storeu_si128(out.add(12) as *mut __m128i, _mm256_extracti128_si256(px, 1));
+        }
+        to_rgb_scalar(&src[n..], &mut dst[3 * n..]);
+    }
+
+    /// 4 pixels at a time with `pshufb`
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn to_rgb_ssse3(src: &[Bgr8], dst: &mut [u8]) {
+        let shuffle = _mm_setr_epi8(2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1);
+        // Each store writes 4 bytes past the 12 of the 4 pixels
+        let n = src.len().saturating_sub(2) / 4 * 4;
+        for i in (0..n).step_by(4) {
+            let px = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+            let px = _mm_shuffle_epi8(px, shuffle);
+            _mm_storeu_si128(dst.as_mut_ptr().add(3 * i) as *mut __m128i, px);
+        }
+        to_rgb_scalar(&src[n..], &mut dst[3 * n..]);
+    }
+
+    /// 4 pixels at a time, with the weighted sums of 16-bit components
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn to_gray_sse2(src: &[Bgr8], dst: &mut [u8]) {
+        // The weights of blue, green, red and alpha, as in `luma`
+        let weights = _mm_setr_epi16(19, 183, 54, 0, 19, 183, 54, 0);
+        let round = _mm_set1_epi32(128);
+        let zero = _mm_setzero_si128();
+        let n = src.len() / 4 * 4;
+        for i in (0..n).step_by(4) {
+            let px = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+            // Blue and green, and red and alpha, of 2 pixels each
+            let lo = _mm_castsi128_ps(_mm_madd_epi16(_mm_unpacklo_epi8(px, zero), weights));
+            let hi = _mm_castsi128_ps(_mm_madd_epi16(_mm_unpackhi_epi8(px, zero), weights));
+            let bg = _mm_castps_si128(_mm_shuffle_ps(lo, hi, 0b10_00_10_00));
+            let ra = _mm_castps_si128(_mm_shuffle_ps(lo, hi, 0b11_01_11_01));
+            let sum = _mm_add_epi32(_mm_add_epi32(bg, ra), round);
+            let y = _mm_srli_epi32(sum, 8);
+            let y = _mm_packus_epi16(_mm_packs_epi32(y, zero), zero);
+            let out = _mm_cvtsi128_si32(y).to_ne_bytes();
+            dst[i..i + 4].copy_from_slice(&out);
+        }
+        to_gray_scalar(&src[n..], &mut dst[n..]);
+    }
+}
+
+/// 16 pixels at a time with interleaving loads and stores
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{to_gray_scalar, to_rgb_scalar, to_rgba_scalar};
+    use crate::Bgr8;
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn to_rgba(src: &[Bgr8], dst: &mut [u8]) {
+        let n = src.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let bgra = vld4q_u8(src.as_ptr().add(i) as *const u8);
+            let rgba = uint8x16x4_t(bgra.2, bgra.1, bgra.0, vdupq_n_u8(255));
+            vst4q_u8(dst.as_mut_ptr().add(4 * i), rgba);
+        }
+        to_rgba_scalar(&src[n..], &mut dst[4 * n..]);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn to_rgb(src: &[Bgr8], dst: &mut [u8]) {
+        let n = src.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let bgra = vld4q_u8(src.as_ptr().add(i) as *const u8);
+            vst3q_u8(
+                dst.as_mut_ptr().add(3 * i),
+                uint8x16x3_t(bgra.2, bgra.1, bgra.0),
+            );
+        }
+        to_rgb_scalar(&src[n..], &mut dst[3 * n..]);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn to_gray(src: &[Bgr8], dst: &mut [u8]) {
+        let (wb, wg, wr) = (vdup_n_u8(19), vdup_n_u8(183), vdup_n_u8(54));
+        let n = src.len() / 16 * 16;
+        for i in (0..n).step_by(16) {
+            let bgra = vld4q_u8(src.as_ptr().add(i) as *const u8);
+            // The weighted sums fit in 16 bits, as the weights sum to 256
+            let lo = vmull_u8(vget_low_u8(bgra.0), wb);
+            let lo = vmlal_u8(lo, vget_low_u8(bgra.1), wg);
+            let lo = vmlal_u8(lo, vget_low_u8(bgra.2), wr);
+            let hi = vmull_u8(vget_high_u8(bgra.0), wb);
+            let hi = vmlal_u8(hi, vget_high_u8(bgra.1), wg);
+            let hi = vmlal_u8(hi, vget_high_u8(bgra.2), wr);
+            let y = vcombine_u8(vrshrn_n_u16(lo, 8), vrshrn_n_u16(hi, 8));
+            vst1q_u8(dst.as_mut_ptr().add(i), y);
+        }
+        to_gray_scalar(&src[n..], &mut dst[n..]);
+    }
+}
+
+#[cfg(test)]
+mod convert_tests {
+    use super::*;
+
+    fn pixels(n: usize) -> Vec<Bgr8> {
+        (0..n)
+            .map(|i| Bgr8 {
+                b: i as u8,
+                g: (i + 100) as u8,
+                r: (i + 200) as u8,
+                a: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_simd_matches_scalar() {
+        // Lengths around the widths of all SIMD paths
+        for n in (0..40).chain([1000, 1001]) {
+            let src = pixels(n);
+            let mut dst = Vec::new();
+            convert(&src, src.len() as u32, PixelFormat::Rgba8, &mut dst);
+            let mut expected = vec![0; 4 * n];
+            to_rgba_scalar(&src, &mut expected);
+            assert_eq!(dst, expected, "RGBA of {} pixels", n);
+            convert(&src, src.len() as u32, PixelFormat::Rgb8, &mut dst);
+            let mut expected = vec![0; 3 * n];
+            to_rgb_scalar(&src, &mut expected);
+            assert_eq!(dst, expected, "RGB of {} pixels", n);
+            convert(&src, src.len() as u32, PixelFormat::Gray8, &mut dst);
+            let mut expected = vec![0; n];
+            to_gray_scalar(&src, &mut expected);
+            assert_eq!(dst, expected, "gray of {} pixels", n);
+        }
+        #[cfg(target_arch = "x86_64")]
+        {
+            let src = pixels(11);
+            let mut dst = vec![0; 4 * src.len()];
+            // Safe as SSE2 is always available on x86_64
+            unsafe { x86::to_rgba_sse2(&src, &mut dst) };
+            let mut expected = vec![0; 4 * src.len()];
+            to_rgba_scalar(&src, &mut expected);
+            assert_eq!(dst, expected);
+            if is_x86_feature_detected!("ssse3") {
+                let mut dst = vec![0; 3 * src.len()];
+                // Safe as SSSE3 is available
+                unsafe { x86::to_rgb_ssse3(&src, &mut dst) };
+                let mut expected = vec![0; 3 * src.len()];
+                to_rgb_scalar(&src, &mut expected);
+                assert_eq!(dst, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let src = pixels(11);
+        let mut dst = Vec::new();
+        convert(&src, src.len() as u32, PixelFormat::Rgba8, &mut dst);
+        assert_eq!(dst[4..8], [201, 101, 1, 255]);
+        convert(&src, src.len() as u32, PixelFormat::Rgb8, &mut dst);
+        assert_eq!(dst[30..], [210, 110, 10]);
+        convert(&src, src.len() as u32, PixelFormat::Gray8, &mut dst);
+        assert_eq!(dst[1], luma(src[1]));
+        convert(&src, src.len() as u32, PixelFormat::Bgra8, &mut dst);
+        assert_eq!(dst, pixel_bytes(&src));
+    }
+
+    #[test]
+    fn test_dithered() {
+        let white = vec![Bgr8::new(255, 255, 255); 8];
+        let mut dst = Vec::new();
+        convert(&white, 4, PixelFormat::Rgb565, &mut dst);
+        assert!(dst.iter().all(|&b| b == 0xff));
+        convert(&white, 4, PixelFormat::Rgb332, &mut dst);
+        assert!(dst.iter().all(|&b| b == 0xff));
+        // Pure red is exact
+        convert(&[Bgr8::new(255, 0, 0)], 1, PixelFormat::Rgb565, &mut dst);
+        assert_eq!(dst, [0x00, 0xf8]);
+        // The average of dithered levels of a flat color is that color
+        let gray = vec![Bgr8::new(100, 100, 100); 64];
+        convert(&gray, 8, PixelFormat::Rgb565, &mut dst);
+        let levels: Vec<u16> = dst
+            .chunks(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) >> 11)
+            .collect();
+        let mean = levels.iter().map(|&r| r as f64).sum::<f64>() / 64.0 * 255.0 / 31.0;
+        assert!((mean - 100.0).abs() < 1.0, "{}", mean);
+        assert!(levels.iter().any(|&r| r != levels[0]));
+    }
+
+    #[test]
+    fn test_from_rgba() {
+        let src = pixels(37);
+        let mut rgba = vec![0; 4 * src.len()];
+        to_rgba(&src, &mut rgba);
+        let mut dst = vec![Bgr8::default(); src.len()];
+        from_rgba(&rgba, &mut dst);
+        assert!(dst
+            .iter()
+            .zip(&src)
+            .all(|(d, s)| (d.b, d.g, d.r, d.a) == (s.b, s.g, s.r, 255)));
+    }
+}
@@ -0,0 +1,306 @@
+//! Mapping of coordinates between the virtual desktop, monitors, and
+//! captured frames
+//!
+//! Desktop coordinates are relative to the top left corner of the primary
+//! display. Monitor coordinates are relative to the top left corner of a
+//! monitor. Frame coordinates are pixels of captured frames, which may be
+//! scaled or rotated relative to the monitor.
+
+use crate::Capturer;
+
+/// Clockwise rotation of frames relative to the monitor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    #[default]
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+/// Mapping between the coordinates of a monitor and of frames captured from
+/// it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoordinateMap {
+    /// Top left corner of the monitor in desktop coordinates
+    pub position: (i32, i32),
+    /// Width and height of the monitor in desktop coordinates
+    pub monitor_size: (u32, u32),
+    /// Width and height of the frames
+    pub frame_size: (u32, u32),
+    pub rotation: Rotation,
+    /// Ratio of physical to logical pixels, e.g. 1.5 at 144 DPI on Windows
+    pub scale_factor: f64,
+}
+
+/// Map a coordinate from a length of `from` to a length of `to`, at the
+/// center of pixels
+fn rescale(v: i64, from: u32, to: u32) -> i64 {
+    if from == to || from == 0 {
+        v
+    } else {
+        ((v as f64 + 0.5) * to as f64 / from as f64).floor() as i64
+    }
+}
+
+impl CoordinateMap {
+    /// Convert desktop coordinates to coordinates on the monitor
+    pub fn desktop_to_monitor(&self, x: i32, y: i32) -> (i32, i32) {
+        (x - self.position.0, y - self.position.1)
+    }
+
+    /// Convert coordinates on the monitor to desktop coordinates
+    pub fn monitor_to_desktop(&self, x: i32, y: i32) -> (i32, i32) {
+        (x + self.position.0, y + self.position.1)
+    }
+
+    /// Whether coordinates on the monitor are within its bounds
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as u32) < self.monitor_size.0 && (y as u32) < self.monitor_size.1
+    }
+
+    /// Convert coordinates on the monitor to frame pixel coordinates, if
+    /// within the bounds of the monitor
+    pub fn monitor_to_frame(&self, x: i32, y: i32) -> Option<(u32, u32)> {
+        if !self.contains(x, y) {
+            return None;
+        }
+        let (mw, mh) = self.monitor_size;
+        let (x, y) = (x as i64, y as i64);
+        // Rotate, then scale to the frame
+        let ((rx, ry), (rw, rh)) = match self.rotation {
+            Rotation::Identity => ((x, y), (mw, mh)),
+            Rotation::Rotate90 => ((mh as i64 - 1 - y, x), (mh, mw)),
+            Rotation::Rotate180 => ((mw as i64 - 1 - x, mh as i64 - 1 - y), (mw, mh)),
+            Rotation::Rotate270 => ((y, mw as i64 - 1 - x), (mh, mw)),
+        };
+        let (fw, fh) = self.frame_size;
+        let (fx, fy) = (rescale(rx, rw, fw), rescale(ry, rh, fh));
+        Some((
+            fx.clamp(0, fw as i64 - 1) as u32,
+            fy.clamp(0, fh as i64 - 1) as u32,
+        ))
+    }
+
+    /// Convert frame pixel coordinates to coordinates on the monitor
+    pub fn frame_to_monitor(&self, x: u32, y: u32) -> (i32, i32) {
+        let (mw, mh) = self.monitor_size;
+        let (rw, rh) = match self.rotation {
+            Rotation::Identity | Rotation::Rotate180 => (mw, mh),
+            Rotation::Rotate90 | Rotation::Rotate270 => (mh, mw),
+        };
+        let (fw, fh) = self.frame_size;
+        let (rx, ry) = (rescale(x as i64, fw, rw), rescale(y as i64, fh, rh));
+        let (mx, my) = match self.rotation {
+            Rotation::Identity => (rx, ry),
+            Rotation::Rotate90 => (ry, mh as i64 - 1 - rx),
+            Rotation::Rotate180 => (mw as i64 - 1 - rx, mh as i64 - 1 - ry),
+            Rotation::Rotate270 => (mw as i64 - 1 - ry, rx),
+        };
+        (mx as i32, my as i32)
+    }
+
+    /// Convert desktop coordinates to frame pixel coordinates, if on the
+    /// monitor
+    pub fn desktop_to_frame(&self, x: i32, y: i32) -> Option<(u32, u32)> {
+        let (mx, my) = self.desktop_to_monitor(x, y);
+        self.monitor_to_frame(mx, my)
+    }
+
+    /// Convert frame pixel coordinates to desktop coordinates
+    pub fn frame_to_desktop(&self, x: u32, y: u32) -> (i32, i32) {
+        let (mx, my) = self.frame_to_monitor(x, y);
+        self.monitor_to_desktop(mx, my)
+    }
+
+    /// Convert physical coordinates to logical coordinates, as used by
+    /// applications that are not DPI aware
+    pub fn physical_to_logical(&self, x: i32, y: i32) -> (f64, f64) {
+        (x as f64 / self.scale_factor, y as f64 / self.scale_factor)
+    }
+
+    /// Convert logical coordinates to physical coordinates
+    pub fn logical_to_physical(&self, x: f64, y: f64) -> (i32, i32) {
+        (
+            (x * self.scale_factor).round() as i32,
+            (y * self.scale_factor).round() as i32,
+        )
+    }
+}
+
+impl Capturer {
+    /// Mapping between desktop coordinates and pixels of captured frames
+    ///
+    /// The position of the capture source, `Self::rotation`, and the output
+    /// size of `Self::set_output_size` are accounted for.
+    pub fn coordinate_map(&self) -> CoordinateMap {
+        let frame_size = self.geometry();
+        let rotation = self.rotation();
+        let (width, height) = self.source_geometry();
+        let monitor_size = match rotation {
+            Rotation::Identity | Rotation::Rotate180 => (width, height),
+            Rotation::Rotate90 | Rotation::Rotate270 => (height, width),
+        };
+        CoordinateMap {
+            position: self.position(),
+            monitor_size,
+            frame_size,
+            rotation,
+            scale_factor: self.scale_factor(),
+        }
+    }
+
+    /// Set whether to rotate the frames of rotated displays upright, to the
+    /// orientation of the desktop. On by default.
+    ///
+    /// Rotating frames costs a copy with poor locality on Windows. With it
+    /// off, frames are as scanned out to the display, `Frame::rotation` is
+    /// their rotation, and `Self::geometry` and capture regions are in their
+    /// orientation. Frames are always upright on X11 and Wayland, where
+    /// this has no effect.
+    pub fn set_upright(&mut self, upright: bool) {
+        self.upright = upright;
+        self.backend.set_upright(upright);
+        self.processed = None;
+        if let Some(region) = self.region {
+            // Clears the region if it doesn't fit the rotated frames
+            let _ = self.set_capture_region(region);
+        }
+    }
+
+    /// Whether frames of rotated displays are rotated upright
+    pub fn upright(&self) -> bool {
+        self.upright
+    }
+
+    /// Clockwise rotation of captured frames relative to the desktop.
+    /// Always `Rotation::Identity` unless frames are captured with
+    /// `Self::set_upright(false)`.
+    pub fn rotation(&self) -> Rotation {
+        self.backend.rotation()
+    }
+
+    /// Ratio of physical pixels to logical coordinates on the display of
+    /// the capture source, e.g. 1.5 at 150% scaling, as
+    /// `DisplayInfo::scale_factor`
+    ///
+    /// 1 if the display is not found, e.g. for windows. On Windows, this is
+    /// only the scaling of the display if the process is DPI aware.
+    pub fn scale_factor(&self) -> f64 {
+        self.display().map_or(1.0, |d| d.scale_factor)
+    }
+
+    /// Width and height of the area to capture in logical coordinates, i.e.
+    /// `Self::geometry` before scaling divided by `Self::scale_factor`
+    pub fn logical_geometry(&self) -> (u32, u32) {
+        let (width, height) = self.source_geometry();
+        let scale = self.scale_factor();
+        (
+            (width as f64 / scale).round() as u32,
+            (height as f64 / scale).round() as u32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod coords_tests {
+    use super::*;
+
+    #[test]
+    fn test_coordinate_map() {
+        let mut map = CoordinateMap {
+            position: (1920, -200),
+            monitor_size: (400, 200),
+            frame_size: (200, 100),
+            rotation: Rotation::Identity,
+            scale_factor: 2.0,
+        };
+        assert_eq!(map.desktop_to_frame(1920 + 101, -200 + 51), Some((50, 25)));
+        assert_eq!(map.desktop_to_frame(0, 0), None);
+        assert_eq!(map.frame_to_desktop(50, 25), (1920 + 101, -200 + 51));
+        assert_eq!(map.physical_to_logical(30, 10), (15.0, 5.0));
+
+        map.rotation = Rotation::Rotate90;
+        map.frame_size = (200, 400);
+        // The top left corner of the monitor is the top right of the frame
+        assert_eq!(map.monitor_to_frame(0, 0), Some((199, 0)));
+        for &rotation in &[Rotation::Rotate90, Rotation::Rotate180, Rotation::Rotate270] {
+            map.rotation = rotation;
+            map.frame_size = match rotation {
+                Rotation::Rotate180 => (400, 200),
+                _ => (200, 400),
+            };
+            let (fx, fy) = map.monitor_to_frame(10, 20).unwrap();
+            assert_eq!(map.frame_to_monitor(fx, fy), (10, 20));
+        }
+    }
+    /// A monitor of 4x2 pixels rotated for display, scanned out as 2x4
+    struct Portrait {
+        upright: bool,
+        frame: Vec<crate::Bgr8>,
+    }
+
+    impl crate::backend::Backend for Portrait {
+        fn name(&self) -> &'static str {
+            "portrait"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            if self.upright {
+                (4, 2)
+            } else {
+                (2, 4)
+            }
+        }
+
+        fn capture_frame(
+            &mut self,
+            _timeout: std::time::Duration,
+        ) -> Result<(), crate::CaptureError> {
+            let (width, height) = self.geometry();
+            self.frame = vec![crate::Bgr8::default(); (width * height) as usize];
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[crate::Bgr8], u32, u32)> {
+            let (width, height) = self.geometry();
+            Some((&self.frame, width, height))
+        }
+
+        fn set_upright(&mut self, upright: bool) {
+            self.upright = upright;
+        }
+
+        fn rotation(&self) -> Rotation {
+            if self.upright {
+                Rotation::Identity
+            } else {
+                Rotation::Rotate270
+            }
+        }
+    }
+
+    #[test]
+    fn test_upright() {
+        let backend = Portrait {
+            upright: true,
+            frame: Vec::new(),
+        };
+        let mut capturer = Capturer::builder()
+            .upright(false)
+            .build_with_backend(Box::new(backend))
+            .unwrap();
+        let frame = capturer.capture_frame_meta().unwrap();
+        assert_eq!((frame.width(), frame.height()), (2, 4));
+        assert_eq!(frame.rotation(), Rotation::Rotate270);
+        let map = capturer.coordinate_map();
+        assert_eq!(map.monitor_size, (4, 2));
+        // The top left corner of the monitor is the bottom left of the frame
+        assert_eq!(map.monitor_to_frame(0, 0), Some((0, 3)));
+
+        capturer.set_upright(true);
+        let frame = capturer.capture_frame_meta().unwrap();
+        assert_eq!((frame.width(), frame.height()), (4, 2));
+        assert_eq!(frame.rotation(), Rotation::Identity);
+    }
+}
@@ -0,0 +1,169 @@
+//! Detection and cropping of black borders of frames, e.g. the letterboxing
+//! of games and videos
+
+use crate::{Bgr8, CaptureError, Capturer, Frame, Rect};
+
+/// Brightest component of the borders cropped by `Capturer::set_auto_crop`,
+/// above black to allow for the noise of video compression
+const AUTO_CROP_THRESHOLD: u8 = 16;
+
+/// The rectangle of a frame within its borders of pixels no brighter than
+/// `threshold` in any component
+///
+/// All of the frame if it has no such borders, or if it's all border.
+pub(crate) fn content_rect(pixels: &[Bgr8], width: u32, height: u32, threshold: u8) -> Rect {
+    let full = Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+    let (w, h) = (width as usize, height as usize);
+    let border = |p: &Bgr8| p.r <= threshold && p.g <= threshold && p.b <= threshold;
+    let row_border = |y: usize| pixels[y * w..(y + 1) * w].iter().all(border);
+    let top = match (0..h).find(|&y| !row_border(y)) {
+        Some(top) => top,
+        None => return full,
+    };
+    let bottom = (top..h).rev().find(|&y| !row_border(y)).unwrap_or(top) + 1;
+    let column_border = |x: usize| (top..bottom).all(|y| border(&pixels[y * w + x]));
+    let left = (0..w).find(|&x| !column_border(x)).unwrap_or(0);
+    let right = (left..w).rev().find(|&x| !column_border(x)).unwrap_or(left) + 1;
+    Rect {
+        x: left as u32,
+        y: top as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    }
+}
+
+impl Frame {
+    /// The rectangle of the frame within its black borders, e.g. the bars
+    /// of letterboxed or pillarboxed content
+    ///
+    /// Borders are rows and columns of pixels no brighter than `threshold`
+    /// in any component. All of the frame if it has no borders, or is all
+    /// black.
+    pub fn detect_content_rect(&self, threshold: u8) -> Rect {
+        content_rect(self.pixels(), self.width(), self.height(), threshold)
+    }
+}
+
+impl Capturer {
+    /// Crop the black borders of captured frames, e.g. of games that
+    /// letterbox
+    ///
+    /// The borders are detected in a frame of the whole capture source, and
+    /// the capture region set to the content within them. The detection is
+    /// reused for subsequent frames until the capture source changes or is
+    /// reacquired, e.g. after a change of resolution.
+    pub fn set_auto_crop(&mut self, auto_crop: bool) {
+        if self.auto_crop && !auto_crop {
+            self.clear_capture_region();
+        }
+        self.auto_crop = auto_crop;
+        self.crop_detected = false;
+    }
+
+    /// Whether the black borders of captured frames are cropped, see
+    /// `Self::set_auto_crop`
+    pub fn auto_crop(&self) -> bool {
+        self.auto_crop
+    }
+
+    /// Detect the borders to crop by capturing the whole capture source, if
+    /// auto-cropping and not yet detected
+    pub(crate) fn detect_crop(&mut self) -> Result<(), CaptureError> {
+        if !self.auto_crop || self.crop_detected {
+            return Ok(());
+        }
+        self.clear_capture_region();
+        self.acquire_frame()?;
+        let (pixels, width, height) = self
+            .backend
+            .frame()
+            .ok_or_else(|| CaptureError::Fail("No frame to detect borders in".to_string()))?;
+        let content = content_rect(pixels, width, height, AUTO_CROP_THRESHOLD);
+        self.crop_detected = true;
+        if (content.width, content.height) != (width, height) {
+            // Fits, as it's within the frame of the source
+            let _ = self.set_capture_region(content);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod crop_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use std::time::Duration;
+
+    /// Backend of 4x3 frames of a gray row between black bars
+    struct Letterboxed(Vec<Bgr8>);
+
+    impl Backend for Letterboxed {
+        fn name(&self) -> &'static str {
+            "letterboxed"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (4, 3)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.0 = vec![Bgr8::new(0, 0, 0); 12];
+            self.0[4..8].fill(Bgr8::new(90, 90, 90));
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 4, 3))
+        }
+    }
+
+    #[test]
+    fn test_auto_crop() {
+        let mut capturer = Capturer::with_backend(Box::new(Letterboxed(Vec::new())));
+        capturer.set_auto_crop(true);
+        let frame = capturer.capture_frame_meta().unwrap();
+        assert_eq!(frame.geometry(), (4, 1));
+        assert!(frame.pixels().iter().all(|p| p.g == 90));
+        capturer.set_auto_crop(false);
+        assert_eq!(capturer.capture_frame_meta().unwrap().geometry(), (4, 3));
+    }
+
+    #[test]
+    fn test_content_rect() {
+        let (black, gray, white) = (
+            Bgr8::new(8, 8, 8),
+            Bgr8::new(100, 100, 100),
+            Bgr8::new(255, 255, 255),
+        );
+        // Letterboxed and pillarboxed 4x2 content in a 6x4 frame
+        let mut pixels = vec![black; 24];
+        for y in 1..3 {
+            for x in 1..5 {
+                pixels[y * 6 + x] = if x == 2 { white } else { gray };
+            }
+        }
+        pixels[6 + 1] = black;
+        let frame = Frame::new(pixels, 6, 4);
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(frame.detect_content_rect(16), rect(1, 1, 4, 2));
+        // Gray is border below a threshold of 100
+        assert_eq!(frame.detect_content_rect(100), rect(2, 1, 1, 2));
+        assert_eq!(frame.detect_content_rect(0), rect(0, 0, 6, 4));
+        let black = Frame::new(vec![black; 6], 3, 2);
+        assert_eq!(black.detect_content_rect(16), rect(0, 0, 3, 2));
+        assert_eq!(
+            Frame::new(Vec::new(), 0, 0).detect_content_rect(16).width,
+            0
+        );
+    }
+}
@@ -0,0 +1,534 @@
+//! Position and shape of the mouse cursor, so that clients can draw it
+//! themselves or visualize clicks
+//!
+//! Queried with XFixes on Linux, `GetCursorInfo` on Windows, and `CGEvent` on
+//! macOS, where only the position is available.
+
+use crate::overlay;
+use crate::{Bgr8, CaptureError, Capturer};
+
+/// Position and visibility of the cursor
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CursorInfo {
+    /// Whether the cursor is shown. On Linux, a cursor is considered hidden
+    /// if its image is fully transparent.
+    pub visible: bool,
+    /// Position of the hotspot of the cursor in desktop coordinates, i.e.
+    /// relative to the top left corner of the primary display
+    pub x: i32,
+    pub y: i32,
+    /// Identifies the shape of the cursor. Changes when the shape changes.
+    pub serial: u64,
+}
+
+/// Image of the cursor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CursorShape {
+    /// See `CursorInfo::serial`
+    pub serial: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Position of the hotspot in the image
+    pub hotspot_x: u32,
+    pub hotspot_y: u32,
+    /// Pixels of the image row by row, with straight alpha
+    pub pixels: Vec<Bgr8>,
+}
+
+/// The cursor at the time a frame was captured, see `Frame::cursor`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FrameCursor {
+    pub visible: bool,
+    /// Position of the hotspot relative to the top left corner of the
+    /// frame. May be outside of the frame.
+    pub x: i32,
+    pub y: i32,
+    /// Position of the hotspot in the image of the cursor, if known. Not
+    /// available on macOS.
+    pub hotspot: Option<(u32, u32)>,
+    /// See `CursorInfo::serial`
+    pub serial: u64,
+}
+
+/// Per-capturer state of cursor queries
+#[derive(Default)]
+pub(crate) struct CursorState {
+    reader: Option<sys::Reader>,
+    tracking: bool,
+    last: Option<CursorInfo>,
+    /// Whether to draw the cursor in captured frames
+    pub(crate) include: bool,
+    /// Shape of the cursor, cached for drawing
+    shape: Option<CursorShape>,
+    /// Size of the frame and position of the capture source, cached for
+    /// drawing
+    pub(crate) origin: Option<((u32, u32), (i32, i32))>,
+}
+
+impl CursorState {
+    fn reader(&mut self) -> Result<&mut sys::Reader, CaptureError> {
+        if self.reader.is_none() {
+            self.reader = Some(sys::Reader::new().map_err(CaptureError::Fail)?);
+        }
+        Ok(self.reader.as_mut().unwrap())
+    }
+
+    /// Called when a frame is captured, to track the cursor if enabled.
+    /// Returns the serial of the shape of the cursor if it changed.
+    pub(crate) fn frame_captured(&mut self) -> Option<u64> {
+        if self.tracking {
+            self.last = self.reader().and_then(|r| r.query()).ok();
+            if let Some(info) = self.last.filter(|info| info.visible) {
+                if self.update_shape(info.serial) {
+                    return Some(info.serial);
+                }
+            }
+        }
+        None
+    }
+
+    /// Query the shape of the cursor if it changed since it was cached.
+    /// Returns whether it changed.
+    fn update_shape(&mut self, serial: u64) -> bool {
+        if self.shape.as_ref().map(|s| s.serial) == Some(serial) {
+            return false;
+        }
+        self.shape = self.reader().and_then(|r| r.shape()).ok();
+        true
+    }
+
+    /// The tracked cursor at the time the last frame was captured, relative
+    /// to a frame at `origin` in desktop coordinates
+    pub(crate) fn frame_cursor(&self, origin: (i32, i32)) -> Option<FrameCursor> {
+        let info = self.last?;
+        let hotspot = self
+            .shape
+            .as_ref()
+            .filter(|shape| shape.serial == info.serial)
+            .map(|shape| (shape.hotspot_x, shape.hotspot_y));
+        Some(FrameCursor {
+            visible: info.visible,
+            x: info.x - origin.0,
+            y: info.y - origin.1,
+            hotspot,
+            serial: info.serial,
+        })
+    }
+}
+
+impl CursorState {
+    /// Draw the current cursor onto a captured frame of the given size, of a
+    /// capture source at `origin` in desktop coordinates
+    ///
+    /// Failures to query the cursor are ignored, leaving the frame as is.
+    pub(crate) fn draw(&mut self, frame: &mut [Bgr8], width: u32, height: u32, origin: (i32, i32)) {
+        let info = match self.reader().and_then(|r| r.query()) {
+            Ok(info) if info.visible => info,
+            _ => return,
+        };
+        self.update_shape(info.serial);
+        if let Some(shape) = &self.shape {
+            let x = info.x as i64 - origin.0 as i64 - shape.hotspot_x as i64;
+            let y = info.y as i64 - origin.1 as i64 - shape.hotspot_y as i64;
+            let image = (&shape.pixels[..], (shape.width, shape.height));
+            overlay::blend(frame, (width, height), image, (x, y), 255);
+        }
+    }
+}
+
+impl Capturer {
+    /// Whether to draw the cursor in subsequently captured frames
+    ///
+    /// Neither DXGI desktop duplication nor X11 include the cursor in
+    /// captured frames, so it is drawn from its shape after capture.
+    pub fn set_include_cursor(&mut self, include: bool) {
+        self.cursor.include = include;
+    }
+
+    /// Whether the cursor is drawn in captured frames
+    pub fn include_cursor(&self) -> bool {
+        self.cursor.include
+    }
+
+    /// Query the current position and visibility of the cursor
+    pub fn cursor(&mut self) -> Result<CursorInfo, CaptureError> {
+        self.cursor.reader()?.query()
+    }
+
+    /// Query the current image of the cursor
+    ///
+    /// Comparatively expensive, so only query it when the serial of the
+    /// cursor changes.
+    pub fn cursor_shape(&mut self) -> Result<CursorShape, CaptureError> {
+        self.cursor.reader()?.shape()
+    }
+
+    /// The image of the cursor, with its size and hotspot, e.g. to draw it
+    /// in a remote desktop viewer
+    ///
+    /// While tracking the cursor with `Self::set_track_cursor`, this is the
+    /// image at the time the last frame was captured, cached until
+    /// `CaptureEvent::CursorShapeChanged` reports a new one. Otherwise the
+    /// current image is queried, as by `Self::cursor_shape`.
+    pub fn cursor_image(&mut self) -> Result<CursorShape, CaptureError> {
+        let cached = self.cursor.shape.as_ref().filter(|shape| {
+            self.cursor.tracking
+                && self
+                    .cursor
+                    .last
+                    .is_some_and(|info| info.visible && info.serial == shape.serial)
+        });
+        match cached {
+            Some(shape) => Ok(shape.clone()),
+            None => self.cursor_shape(),
+        }
+    }
+
+    /// Enable or disable querying the cursor on every capture, for
+    /// `Self::frame_cursor` and `Frame::cursor`
+    pub fn set_track_cursor(&mut self, track: bool) {
+        self.cursor.tracking = track;
+        if !track {
+            self.cursor.last = None;
+        }
+    }
+
+    /// The cursor at the time the last frame was captured, if tracking is
+    /// enabled with `Self::set_track_cursor` and the query succeeded
+    pub fn frame_cursor(&self) -> Option<CursorInfo> {
+        self.cursor.last
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+mod sys {
+    use super::{CursorInfo, CursorShape};
+    use crate::{Bgr8, CaptureError};
+    use x11::{xfixes, xlib};
+
+    pub struct Reader {
+        display: *mut xlib::Display,
+    }
+
+    // The connection is only ever used by the thread owning the reader
+    unsafe impl Send for Reader {}
+
+    impl Reader {
+        pub fn new() -> Result<Reader, String> {
+            let display = crate::x11::open_display();
+            if display.is_null() {
+                return Err("Failed to open X display".to_string());
+            }
+            let (mut event_base, mut error_base) = (0, 0);
+            if unsafe { xfixes::XFixesQueryExtension(display, &mut event_base, &mut error_base) }
+                == 0
+            {
+                unsafe { xlib::XCloseDisplay(display) };
+                return Err("XFixes extension is unavailable".to_string());
+            }
+            Ok(Reader { display })
+        }
+
+        /// Get the cursor image and pass it, with its pixels as ARGB
+        /// premultiplied by alpha, to `f`
+        fn with_image<T>(
+            &mut self,
+            f: impl FnOnce(&xfixes::XFixesCursorImage, &[std::os::raw::c_ulong]) -> T,
+        ) -> Result<T, CaptureError> {
+            let image = unsafe { xfixes::XFixesGetCursorImage(self.display) };
+            if image.is_null() {
+                return Err(CaptureError::Fail("Failed to get cursor image".to_string()));
+            }
+            let res = unsafe {
+                let img = &*image;
+                let n = img.width as usize * img.height as usize;
+                let pixels = if n == 0 {
+                    &[][..]
+                } else {
+                    std::slice::from_raw_parts(img.pixels, n)
+                };
+                f(img, pixels)
+            };
+            unsafe { xlib::XFree(image as *mut _) };
+            Ok(res)
+        }
+
+        // `c_ulong` is 32 bits on some platforms
+        #[allow(clippy::unnecessary_cast)]
+        pub fn query(&mut self) -> Result<CursorInfo, CaptureError> {
+            self.with_image(|img, pixels| CursorInfo {
+                visible: pixels.iter().any(|&p| p >> 24 & 0xff != 0),
+                x: img.x as i32,
+                y: img.y as i32,
+                serial: img.cursor_serial as u64,
+            })
+        }
+
+        #[allow(clippy::unnecessary_cast)]
+        pub fn shape(&mut self) -> Result<CursorShape, CaptureError> {
+            self.with_image(|img, pixels| CursorShape {
+                serial: img.cursor_serial as u64,
+                width: img.width as u32,
+                height: img.height as u32,
+                hotspot_x: img.xhot as u32,
+                hotspot_y: img.yhot as u32,
+                pixels: pixels
+                    .iter()
+                    .map(|&p| {
+                        let a = (p >> 24 & 0xff) as u32;
+                        let unpremultiply = |c: u32| {
+                            (c * 255 + a / 2)
+                                .checked_div(a)
+                                .map_or(0, |c| c.min(255) as u8)
+                        };
+                        Bgr8 {
+                            b: unpremultiply((p & 0xff) as u32),
+                            g: unpremultiply((p >> 8 & 0xff) as u32),
+                            r: unpremultiply((p >> 16 & 0xff) as u32),
+                            a: a as u8,
+                        }
+                    })
+                    .collect(),
+            })
+        }
+    }
+
+    impl Drop for Reader {
+        fn drop(&mut self) {
+            unsafe { xlib::XCloseDisplay(self.display) };
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::{CursorInfo, CursorShape};
+    use crate::{Bgr8, CaptureError};
+    use std::mem;
+    use std::ptr;
+    use winapi::shared::windef::{HBITMAP, HICON};
+    use winapi::um::wingdi::{
+        DeleteObject, GetDIBits, GetObjectW, BITMAP, BITMAPINFO, BITMAPINFOHEADER, BI_RGB,
+        DIB_RGB_COLORS,
+    };
+    use winapi::um::winuser::{
+        GetCursorInfo, GetDC, GetIconInfo, ReleaseDC, CURSORINFO, CURSOR_SHOWING, ICONINFO,
+    };
+
+    #[derive(Default)]
+    pub struct Reader;
+
+    fn fail(msg: &str) -> CaptureError {
+        CaptureError::Fail(format!("{}: {}", msg, std::io::Error::last_os_error()))
+    }
+
+    fn cursor_info() -> Result<CURSORINFO, CaptureError> {
+        let mut info: CURSORINFO = unsafe { mem::zeroed() };
+        info.cbSize = mem::size_of::<CURSORINFO>() as u32;
+        if unsafe { GetCursorInfo(&mut info) } == 0 {
+            Err(fail("Failed to get cursor info"))
+        } else {
+            Ok(info)
+        }
+    }
+
+    /// Read a bitmap as top-down 32-bit BGRA pixels
+    fn bitmap_pixels(bitmap: HBITMAP) -> Result<(u32, u32, Vec<Bgr8>), CaptureError> {
+        let mut bm: BITMAP = unsafe { mem::zeroed() };
+        let size = mem::size_of::<BITMAP>() as i32;
+        if unsafe { GetObjectW(bitmap as _, size, &mut bm as *mut _ as _) } == 0 {
+            return Err(fail("Failed to get cursor bitmap"));
+        }
+        let (w, h) = (bm.bmWidth.max(0) as u32, bm.bmHeight.max(0) as u32);
+        let mut info: BITMAPINFO = unsafe { mem::zeroed() };
+        info.bmiHeader = BITMAPINFOHEADER {
+            biSize: mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: w as i32,
+            biHeight: -(h as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB,
+            ..unsafe { mem::zeroed() }
+        };
+        let mut pixels = vec![Bgr8::default(); w as usize * h as usize];
+        let dc = unsafe { GetDC(ptr::null_mut()) };
+        let lines = unsafe {
+            GetDIBits(
+                dc,
+                bitmap,
+                0,
+                h,
+                pixels.as_mut_ptr() as _,
+                &mut info,
+                DIB_RGB_COLORS,
+            )
+        };
+        unsafe { ReleaseDC(ptr::null_mut(), dc) };
+        if lines == 0 {
+            return Err(fail("Failed to read cursor bitmap"));
+        }
+        Ok((w, h, pixels))
+    }
+
+    impl Reader {
+        pub fn new() -> Result<Reader, String> {
+            Ok(Reader)
+        }
+
+        pub fn query(&mut self) -> Result<CursorInfo, CaptureError> {
+            let info = cursor_info()?;
+            Ok(CursorInfo {
+                visible: info.flags & CURSOR_SHOWING != 0,
+                x: info.ptScreenPos.x,
+                y: info.ptScreenPos.y,
+                serial: info.hCursor as usize as u64,
+            })
+        }
+
+        pub fn shape(&mut self) -> Result<CursorShape, CaptureError> {
+            let info = cursor_info()?;
+            let mut icon: ICONINFO = unsafe { mem::zeroed() };
+            if unsafe { GetIconInfo(info.hCursor as HICON, &mut icon) } == 0 {
+                return Err(fail("Failed to get cursor icon"));
+            }
+            let res = if icon.hbmColor.is_null() {
+                // Monochrome cursor. The mask holds the AND mask above the XOR
+                // mask. Inverted pixels are approximated with black.
+                bitmap_pixels(icon.hbmMask).map(|(w, h, mask)| {
+                    let h = h / 2;
+                    let n = w as usize * h as usize;
+                    let pixels = mask[..n]
+                        .iter()
+                        .zip(&mask[n..2 * n])
+                        .map(|(and, xor)| match (and.r != 0, xor.r != 0) {
+                            (true, false) => Bgr8 {
+                                b: 0,
+                                g: 0,
+                                r: 0,
+                                a: 0,
+                            },
+                            (false, true) => Bgr8::new(255, 255, 255),
+                            _ => Bgr8::new(0, 0, 0),
+                        })
+                        .collect();
+                    (w, h, pixels)
+                })
+            } else {
+                bitmap_pixels(icon.hbmColor).and_then(|(w, h, mut pixels)| {
+                    // Cursors without alpha use the AND mask for transparency
+                    if pixels.iter().all(|p| p.a == 0) {
+                        let (_, _, mask) = bitmap_pixels(icon.hbmMask)?;
+                        for (p, m) in pixels.iter_mut().zip(&mask) {
+                            p.a = if m.r == 0 { 255 } else { 0 };
+                        }
+                    }
+                    Ok((w, h, pixels))
+                })
+            };
+            unsafe {
+                if !icon.hbmColor.is_null() {
+                    DeleteObject(icon.hbmColor as _);
+                }
+                DeleteObject(icon.hbmMask as _);
+            }
+            let (width, height, pixels) = res?;
+            Ok(CursorShape {
+                serial: info.hCursor as usize as u64,
+                width,
+                height,
+                hotspot_x: icon.xHotspot,
+                hotspot_y: icon.yHotspot,
+                pixels,
+            })
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use super::{CursorInfo, CursorShape};
+    use crate::CaptureError;
+    use objc2_core_graphics::CGEvent;
+
+    pub struct Reader;
+
+    impl Reader {
+        pub fn new() -> Result<Reader, String> {
+            Ok(Reader)
+        }
+
+        pub fn query(&mut self) -> Result<CursorInfo, CaptureError> {
+            let event = CGEvent::new(None)
+                .ok_or_else(|| CaptureError::Fail("Failed to create event".to_string()))?;
+            let location = CGEvent::location(Some(&event));
+            Ok(CursorInfo {
+                visible: true,
+                x: location.x as i32,
+                y: location.y as i32,
+                serial: 0,
+            })
+        }
+
+        pub fn shape(&mut self) -> Result<CursorShape, CaptureError> {
+            Err(CaptureError::Fail(
+                "Cursor shapes are not supported on macOS".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(any(target_os = "android", target_arch = "wasm32"))]
+mod sys {
+    use super::{CursorInfo, CursorShape};
+    use crate::CaptureError;
+
+    pub enum Reader {}
+
+    impl Reader {
+        pub fn new() -> Result<Reader, String> {
+            Err("The cursor is not supported on this platform".to_string())
+        }
+
+        pub fn query(&mut self) -> Result<CursorInfo, CaptureError> {
+            match *self {}
+        }
+
+        pub fn shape(&mut self) -> Result<CursorShape, CaptureError> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod cursor_tests {
+    use super::*;
+
+    #[test]
+    fn test_blend() {
+        let mut frame = vec![Bgr8::new(0, 0, 0); 3 * 3];
+        let shape = CursorShape {
+            serial: 0,
+            width: 2,
+            height: 2,
+            hotspot_x: 0,
+            hotspot_y: 0,
+            pixels: vec![
+                Bgr8::new(255, 255, 255),
+                Bgr8 {
+                    a: 0,
+                    ..Bgr8::new(255, 255, 255)
+                },
+                Bgr8 {
+                    a: 128,
+                    ..Bgr8::new(255, 255, 255)
+                },
+                Bgr8::new(255, 0, 0),
+            ],
+        };
+        // Partly outside of the frame
+        let image = (&shape.pixels[..], (shape.width, shape.height));
+        overlay::blend(&mut frame, (3, 3), image, (2, 1), 255);
+        let reds: Vec<u8> = frame.iter().map(|p| p.r).collect();
+        assert_eq!(reds, [0, 0, 0, 0, 0, 255, 0, 0, 128]);
+    }
+}
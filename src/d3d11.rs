@@ -0,0 +1,75 @@
+//! Access to the D3D11 textures of DXGI desktop duplication, e.g. for
+//! zero-copy encoding with NVENC or Media Foundation
+
+use crate::{CaptureError, Capturer};
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D};
+
+/// A frame captured into a D3D11 texture, see
+/// `Capturer::capture_d3d11_texture`
+#[derive(Clone, Debug)]
+pub struct D3d11Frame {
+    /// `DXGI_FORMAT_B8G8R8A8_UNORM` copy of the whole output, created with
+    /// `D3D11_RESOURCE_MISC_SHARED`
+    pub texture: ID3D11Texture2D,
+    /// The device the texture was created on
+    pub device: ID3D11Device,
+    /// Legacy shared handle of the texture, for
+    /// `ID3D11Device::OpenSharedResource` on other devices
+    pub shared_handle: HANDLE,
+    pub width: u32,
+    pub height: u32,
+    /// Clockwise rotation of the output in degrees, which is not undone in
+    /// the texture
+    pub rotation: u32,
+    /// `QueryPerformanceCounter` time of the last update of the desktop, or
+    /// 0 if only the mouse pointer changed
+    pub last_present_time: i64,
+    /// Number of desktop updates since the previous frame
+    pub accumulated_frames: u32,
+}
+
+impl Capturer {
+    /// Capture screen into a D3D11 texture on the GPU, without copying the
+    /// frame to the CPU
+    ///
+    /// The texture is of the whole output. The capture region and the
+    /// cursor overlay are not applied, and the frame is not stored for
+    /// `Self::get_stored_frame`. Fails with `CaptureError::Fail` if the
+    /// capturer doesn't capture with DXGI desktop duplication, or if
+    /// regions are redacted or windows blacked out, as they can't be hidden.
+    ///
+    /// # Safety
+    ///
+    /// The same texture is reused by every capture. Its contents are only
+    /// valid until the next call to this method, so the caller must be done
+    /// reading it, e.g. have waited for an encode of it to complete, before
+    /// capturing again. The texture and its shared handle are released, and
+    /// the handle dangles, when the capturer is dropped, when the capture
+    /// source is reacquired, or when the size of the output changes. Reads
+    /// on other devices through the shared handle are not synchronized with
+    /// the copy into the texture, other than by the copy being flushed
+    /// before this returns.
+    pub unsafe fn capture_d3d11_texture(&mut self) -> Result<D3d11Frame, CaptureError> {
+        if self.hides_regions() {
+            return Err(CaptureError::Fail(
+                "Redactions and excluded windows can't be applied to D3D11 textures".to_string(),
+            ));
+        }
+        self.pace();
+        let res = self
+            .backend
+            .capture_d3d11_texture(self.timeout)
+            .unwrap_or_else(|| {
+                Err(CaptureError::Fail(format!(
+                    "{} doesn't capture D3D11 textures",
+                    self.backend.name()
+                )))
+            });
+        match &res {
+            Ok(_) => self.frame_captured(),
+            Err(e) => crate::diagnostics::record_error(e),
+        }
+        res
+    }
+}
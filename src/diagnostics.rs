@@ -0,0 +1,275 @@
+//! Diagnostics report of the capture environment, for bug reports
+//!
+//! ```no_run
+//! println!("{}", captrs::diagnostics());
+//! ```
+
+use crate::{CaptureError, Capturer};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Number of capture errors kept for reports
+const MAX_ERRORS: usize = 8;
+
+static LAST_ERRORS: Mutex<Vec<(Instant, String)>> = Mutex::new(Vec::new());
+
+/// Record a capture error for diagnostics reports
+pub(crate) fn record_error(err: &CaptureError) {
+    let mut errors = LAST_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    if errors.len() == MAX_ERRORS {
+        errors.remove(0);
+    }
+    errors.push((Instant::now(), err.to_string()));
+}
+
+/// A graphics adapter
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub driver_version: Option<String>,
+}
+
+/// A capture source, e.g. a display
+#[derive(Clone, Debug)]
+pub struct OutputInfo {
+    pub index: usize,
+    pub position: (i32, i32),
+    pub size: (u32, u32),
+}
+
+/// Report of the capture environment
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    pub crate_version: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    /// The capture backend in use
+    pub backend: &'static str,
+    /// Relevant details of the environment, e.g. the session type and
+    /// display server
+    pub environment: Vec<(String, String)>,
+    /// Graphics adapters. Only reported on Windows.
+    pub adapters: Vec<AdapterInfo>,
+    pub outputs: Vec<OutputInfo>,
+    /// Pixel formats that frames can be captured in
+    pub formats: Vec<&'static str>,
+    /// Size of the frame from a test capture of the primary display, or the
+    /// error. Fails if capture is not permitted, e.g. in protected content.
+    pub test_capture: Result<(u32, u32), String>,
+    /// The last capture errors in the process and how long ago they
+    /// occurred, oldest first
+    pub last_errors: Vec<(std::time::Duration, String)>,
+}
+
+/// Produce a report of the capture environment, to paste into bug reports
+///
+/// Does a test capture of the primary display.
+pub fn diagnostics() -> Diagnostics {
+    let mut environment = Vec::new();
+    for var in ["XDG_SESSION_TYPE", "WAYLAND_DISPLAY", "DISPLAY"] {
+        if let Ok(value) = std::env::var(var) {
+            environment.push((var.to_string(), value));
+        }
+    }
+    sys::environment(&mut environment);
+    let test_capture = Capturer::new(0)
+        .map_err(|e| e.to_string())
+        .and_then(|mut capturer| {
+            capturer
+                .capture_store_frame()
+                .map(|()| capturer.geometry())
+                .map_err(|e| e.to_string())
+        });
+    let last_errors = LAST_ERRORS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(t, err)| (t.elapsed(), err.clone()))
+        .collect();
+    Diagnostics {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        backend: sys::backend(),
+        environment,
+        adapters: sys::adapters(),
+        outputs: crate::displays()
+            .into_iter()
+            .map(|d| OutputInfo {
+                index: d.index,
+                position: d.position,
+                size: d.size,
+            })
+            .collect(),
+        formats: vec!["BGRA8", "RGBA8", "RGB8", "GRAY8"],
+        test_capture,
+        last_errors,
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "captrs {}", self.crate_version)?;
+        writeln!(f, "OS: {} {}", self.os, self.arch)?;
+        writeln!(f, "Backend: {}", self.backend)?;
+        for (key, value) in &self.environment {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+        for a in &self.adapters {
+            write!(
+                f,
+                "Adapter: {} ({:04x}:{:04x})",
+                a.name, a.vendor_id, a.device_id
+            )?;
+            match &a.driver_version {
+                Some(v) => writeln!(f, ", driver {}", v)?,
+                None => writeln!(f)?,
+            }
+        }
+        for o in &self.outputs {
+            writeln!(
+                f,
+                "Output {}: {}x{} at ({}, {})",
+                o.index, o.size.0, o.size.1, o.position.0, o.position.1
+            )?;
+        }
+        writeln!(f, "Formats: {}", self.formats.join(", "))?;
+        match &self.test_capture {
+            Ok((w, h)) => writeln!(f, "Test capture: ok, {}x{}", w, h)?,
+            Err(e) => writeln!(f, "Test capture: failed, {}", e)?,
+        }
+        for (ago, err) in &self.last_errors {
+            writeln!(f, "Error {:.1} s ago: {}", ago.as_secs_f64(), err)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::AdapterInfo;
+    use crate::dxgi;
+
+    pub fn backend() -> &'static str {
+        "DXGI desktop duplication"
+    }
+
+    pub fn environment(_env: &mut Vec<(String, String)>) {}
+
+    pub fn adapters() -> Vec<AdapterInfo> {
+        dxgi::adapters()
+            .into_iter()
+            .map(|a| AdapterInfo {
+                name: a.name,
+                vendor_id: a.vendor_id,
+                device_id: a.device_id,
+                driver_version: a
+                    .driver_version
+                    .map(|[a, b, c, d]| format!("{}.{}.{}.{}", a, b, c, d)),
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+mod sys {
+    use super::AdapterInfo;
+    use std::ffi::{CStr, CString};
+    use x11::xlib;
+
+    pub fn backend() -> &'static str {
+        if wayland() {
+            "ScreenCast portal with PipeWire"
+        } else {
+            "X11 XShmGetImage, or XGetImage without MIT-SHM"
+        }
+    }
+
+    /// Whether capture goes through the ScreenCast portal
+    fn wayland() -> bool {
+        #[cfg(feature = "wayland")]
+        return crate::wayland::is_wayland_session();
+        #[cfg(not(feature = "wayland"))]
+        false
+    }
+
+    pub fn environment(env: &mut Vec<(String, String)>) {
+        let display = crate::x11::open_display();
+        if display.is_null() {
+            env.push(("X server".to_string(), "failed to open display".to_string()));
+            return;
+        }
+        unsafe {
+            let vendor = CStr::from_ptr(xlib::XServerVendor(display)).to_string_lossy();
+            let release = xlib::XVendorRelease(display);
+            env.push(("X server".to_string(), format!("{} {}", vendor, release)));
+            let extensions: Vec<&str> = ["MIT-SHM", "RANDR", "XFIXES", "Composite", "DAMAGE"]
+                .iter()
+                .copied()
+                .filter(|name| {
+                    let name = CString::new(*name).unwrap();
+                    let (mut op, mut ev, mut err) = (0, 0, 0);
+                    xlib::XQueryExtension(display, name.as_ptr(), &mut op, &mut ev, &mut err) != 0
+                })
+                .collect();
+            env.push(("X extensions".to_string(), extensions.join(", ")));
+            xlib::XCloseDisplay(display);
+        }
+    }
+
+    pub fn adapters() -> Vec<AdapterInfo> {
+        Vec::new()
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use super::AdapterInfo;
+
+    pub fn backend() -> &'static str {
+        "CGDisplayStream"
+    }
+
+    pub fn environment(_env: &mut Vec<(String, String)>) {}
+
+    pub fn adapters() -> Vec<AdapterInfo> {
+        Vec::new()
+    }
+}
+
+#[cfg(target_os = "android")]
+mod sys {
+    use super::AdapterInfo;
+
+    pub fn backend() -> &'static str {
+        if cfg!(feature = "android") {
+            "MediaProjection"
+        } else {
+            "none, without the `android` feature"
+        }
+    }
+
+    pub fn environment(_env: &mut Vec<(String, String)>) {}
+
+    pub fn adapters() -> Vec<AdapterInfo> {
+        Vec::new()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod sys {
+    use super::AdapterInfo;
+
+    pub fn backend() -> &'static str {
+        "getDisplayMedia, through `captrs::web`"
+    }
+
+    pub fn environment(_env: &mut Vec<(String, String)>) {}
+
+    pub fn adapters() -> Vec<AdapterInfo> {
+        Vec::new()
+    }
+}
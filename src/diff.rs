@@ -0,0 +1,431 @@
+//! Detection of the regions that changed between two frames
+
+use crate::analysis::{histogram, luma};
+use crate::{pixel_bytes, Bgr8, Frame, Histogram, Rect};
+
+/// Compare two frames of 4 byte pixels of the given size tile by tile, and
+/// return the tiles that differ
+///
+/// Tiles are `tile_size` pixels square, except at the right and bottom edges.
+pub fn changed_tiles(
+    prev: &[u8],
+    cur: &[u8],
+    width: u32,
+    height: u32,
+    tile_size: u32,
+) -> Vec<Rect> {
+    assert!(tile_size > 0);
+    assert_eq!(prev.len(), 4 * width as usize * height as usize);
+    assert_eq!(cur.len(), prev.len());
+    let stride = 4 * width as usize;
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_size as usize) {
+        let tile_height = tile_size.min(height - y);
+        for x in (0..width).step_by(tile_size as usize) {
+            let tile_width = tile_size.min(width - x);
+            let changed = (y..y + tile_height).any(|row| {
+                let start = row as usize * stride + 4 * x as usize;
+                let end = start + 4 * tile_width as usize;
+                prev[start..end] != cur[start..end]
+            });
+            if changed {
+                tiles.push(Rect {
+                    x,
+                    y,
+                    width: tile_width,
+                    height: tile_height,
+                });
+            }
+        }
+    }
+    tiles
+}
+
+/// Copy the pixels of a region of a frame of 4 byte pixels of the given
+/// width into `buf`, row by row
+pub fn copy_region(frame: &[u8], width: u32, region: Rect, buf: &mut Vec<u8>) {
+    let stride = 4 * width as usize;
+    for row in region.y..region.y + region.height {
+        let start = row as usize * stride + 4 * region.x as usize;
+        buf.extend_from_slice(&frame[start..start + 4 * region.width as usize]);
+    }
+}
+
+/// Difference between two frames, e.g. for a cheap loop only handling
+/// frames that changed
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameDiff {
+    /// Whether any pixel differs
+    pub changed: bool,
+    /// Percentage of the pixels that differ, from 0 to 100
+    pub changed_percent: f64,
+    /// The tiles containing pixels that differ
+    pub tiles: Vec<Rect>,
+}
+
+impl FrameDiff {
+    /// Size of the tiles of `Self::compare`
+    pub const TILE_SIZE: u32 = 64;
+
+    /// Compare two frames in tiles of `Self::TILE_SIZE` pixels square
+    pub fn compare(prev: &Frame, cur: &Frame) -> FrameDiff {
+        FrameDiff::compare_tiled(prev, cur, FrameDiff::TILE_SIZE)
+    }
+
+    /// Compare two frames in tiles of `tile_size` pixels square, except at
+    /// the right and bottom edges
+    ///
+    /// Rows of tiles are compared with `memcmp`, and the pixels of rows that
+    /// differ are counted with SIMD where available. All of `cur` differs
+    /// from a frame of another size.
+    pub fn compare_tiled(prev: &Frame, cur: &Frame, tile_size: u32) -> FrameDiff {
+        assert!(tile_size > 0);
+        let (width, height) = cur.geometry();
+        if prev.geometry() != cur.geometry() {
+            let changed = width > 0 && height > 0;
+            return FrameDiff {
+                changed,
+                changed_percent: if changed { 100.0 } else { 0.0 },
+                tiles: all_tiles(width, height, tile_size),
+            };
+        }
+        let (a, b) = (prev.pixels(), cur.pixels());
+        let mut tiles = Vec::new();
+        let mut n_changed = 0;
+        for y in (0..height).step_by(tile_size as usize) {
+            let tile_height = tile_size.min(height - y);
+            for x in (0..width).step_by(tile_size as usize) {
+                let tile_width = tile_size.min(width - x);
+                let mut tile_changed = 0;
+                for row in y..y + tile_height {
+                    let start = row as usize * width as usize + x as usize;
+                    let end = start + tile_width as usize;
+                    let (a, b) = (&a[start..end], &b[start..end]);
+                    if pixel_bytes(a) != pixel_bytes(b) {
+                        tile_changed += count_changed(a, b);
+                    }
+                }
+                if tile_changed > 0 {
+                    tiles.push(Rect {
+                        x,
+                        y,
+                        width: tile_width,
+                        height: tile_height,
+                    });
+                }
+                n_changed += tile_changed;
+            }
+        }
+        let n = width as usize * height as usize;
+        FrameDiff {
+            changed: n_changed > 0,
+            changed_percent: if n > 0 {
+                100.0 * n_changed as f64 / n as f64
+            } else {
+                0.0
+            },
+            tiles,
+        }
+    }
+}
+
+/// All tiles of a frame of the given size
+fn all_tiles(width: u32, height: u32, tile_size: u32) -> Vec<Rect> {
+    (0..height)
+        .step_by(tile_size as usize)
+        .flat_map(|y| {
+            (0..width).step_by(tile_size as usize).map(move |x| Rect {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            })
+        })
+        .collect()
+}
+
+/// Number of pixels that differ between `a` and `b`, of the same length
+fn count_changed(a: &[Bgr8], b: &[Bgr8]) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SSE2 is always available on x86_64
+        unsafe { count_changed_sse2(a, b) }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    count_changed_scalar(a, b)
+}
+
+fn count_changed_scalar(a: &[Bgr8], b: &[Bgr8]) -> usize {
+    a.iter().zip(b).filter(|(a, b)| a != b).count()
+}
+
+/// 4 pixels at a time, comparing them as 32-bit lanes
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn count_changed_sse2(a: &[Bgr8], b: &[Bgr8]) -> usize {
+    use std::arch::x86_64::*;
+    let n = a.len().min(b.len()) / 4 * 4;
+    let mut changed = 0;
+    for i in (0..n).step_by(4) {
+        let va = _mm_loadu_si128(a.as_ptr().add(i) as *const __m128i);
+        let vb = _mm_loadu_si128(b.as_ptr().add(i) as *const __m128i);
+        let equal = _mm_movemask_ps(_mm_castsi128_ps(_mm_cmpeq_epi32(va, vb)));
+        changed += 4 - equal.count_ones() as usize;
+    }
+    changed + count_changed_scalar(&a[n..], &b[n..])
+}
+
+/// Motion detected between two frames
+#[derive(Clone, Debug, PartialEq)]
+pub struct Motion {
+    /// The blocks where motion was detected
+    pub regions: Vec<Rect>,
+    /// Fraction of the blocks where motion was detected, from 0 to 1
+    pub score: f32,
+}
+
+impl Motion {
+    /// Whether any motion was detected
+    pub fn detected(&self) -> bool {
+        !self.regions.is_empty()
+    }
+}
+
+/// Detector of motion between successive frames, e.g. to only record when
+/// something happens
+///
+/// Frames are compared block by block. Motion is detected in a block if the
+/// mean absolute difference of the luma of its pixels exceeds the threshold.
+pub struct MotionDetector {
+    block_size: u32,
+    threshold: u8,
+    prev: Vec<u8>,
+    lumas: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl MotionDetector {
+    /// Construct a detector comparing blocks of `block_size` pixels square,
+    /// with a threshold of mean absolute luma difference from 0 to 255
+    pub fn new(block_size: u32, threshold: u8) -> MotionDetector {
+        assert!(block_size > 0);
+        MotionDetector {
+            block_size,
+            threshold,
+            prev: Vec::new(),
+            lumas: Vec::new(),
+            width: 0,
+            height: 0,
+        }
+    }
+
+    /// Compare a frame of the given size to the previous one
+    ///
+    /// No motion is detected for the first frame, or when the size changes.
+    pub fn update(&mut self, frame: &[Bgr8], width: u32, height: u32) -> Motion {
+        assert_eq!(frame.len(), width as usize * height as usize);
+        self.lumas.clear();
+        self.lumas.extend(frame.iter().map(|&p| luma(p)));
+        let mut motion = Motion {
+            regions: Vec::new(),
+            score: 0.0,
+        };
+        if (width, height) == (self.width, self.height) && width > 0 && height > 0 {
+            let bs = self.block_size;
+            let mut n_blocks = 0;
+            for y in (0..height).step_by(bs as usize) {
+                let block_height = bs.min(height - y);
+                for x in (0..width).step_by(bs as usize) {
+                    let block_width = bs.min(width - x);
+                    let mut sad = 0u64;
+                    for row in y..y + block_height {
+                        let start = row as usize * width as usize + x as usize;
+                        let end = start + block_width as usize;
+                        sad += self.prev[start..end]
+                            .iter()
+                            .zip(&self.lumas[start..end])
+                            .map(|(&a, &b)| a.abs_diff(b) as u64)
+                            .sum::<u64>();
+                    }
+                    let n = block_width as u64 * block_height as u64;
+                    if sad > self.threshold as u64 * n {
+                        motion.regions.push(Rect {
+                            x,
+                            y,
+                            width: block_width,
+                            height: block_height,
+                        });
+                    }
+                    n_blocks += 1;
+                }
+            }
+            motion.score = motion.regions.len() as f32 / n_blocks as f32;
+        }
+        std::mem::swap(&mut self.prev, &mut self.lumas);
+        self.width = width;
+        self.height = height;
+        motion
+    }
+}
+
+impl Default for MotionDetector {
+    /// A detector of 16 pixel blocks, with a threshold of 10
+    fn default() -> MotionDetector {
+        MotionDetector::new(16, 10)
+    }
+}
+
+/// Scene change score of a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneChange {
+    /// Histogram distance to the previous frame, from 0 to 1
+    pub score: f64,
+    /// Whether the score exceeds the threshold of the detector
+    pub is_cut: bool,
+}
+
+/// Detector of scene changes between successive frames, e.g. to place
+/// keyframes when encoding or to reset deduplication of streams
+///
+/// Frames are compared by the distance between their color histograms, see
+/// `Histogram::distance`. This ignores motion within a scene, but is cheap.
+pub struct SceneChangeDetector {
+    threshold: f64,
+    prev: Option<Histogram>,
+}
+
+impl SceneChangeDetector {
+    /// Construct a detector reporting a cut when the score exceeds
+    /// `threshold`, from 0 to 1
+    pub fn new(threshold: f64) -> SceneChangeDetector {
+        SceneChangeDetector {
+            threshold,
+            prev: None,
+        }
+    }
+
+    /// Score a frame against the previous one
+    ///
+    /// The first frame is always a cut.
+    pub fn update(&mut self, frame: &[Bgr8]) -> SceneChange {
+        let hist = histogram(frame);
+        let change = match &self.prev {
+            Some(prev) => {
+                let score = prev.distance(&hist);
+                SceneChange {
+                    score,
+                    is_cut: score > self.threshold,
+                }
+            }
+            None => SceneChange {
+                score: 1.0,
+                is_cut: true,
+            },
+        };
+        self.prev = Some(hist);
+        change
+    }
+
+    /// Forget the previous frame, so that the next one is a cut
+    pub fn reset(&mut self) {
+        self.prev = None;
+    }
+}
+
+impl Default for SceneChangeDetector {
+    /// A detector with a threshold of 0.3
+    fn default() -> SceneChangeDetector {
+        SceneChangeDetector::new(0.3)
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_tiles() {
+        let (w, h) = (5, 3);
+        let prev = vec![0; 4 * w * h];
+        let mut cur = prev.clone();
+        // Change the pixel at (4, 2), in the bottom right tile
+        cur[4 * (2 * w + 4)] = 1;
+        let tiles = changed_tiles(&prev, &cur, w as u32, h as u32, 2);
+        assert_eq!(
+            tiles,
+            vec![Rect {
+                x: 4,
+                y: 2,
+                width: 1,
+                height: 1
+            }]
+        );
+        assert!(changed_tiles(&prev, &prev, w as u32, h as u32, 2).is_empty());
+    }
+
+    #[test]
+    fn test_frame_diff() {
+        let black = Frame::new(vec![Bgr8::new(0, 0, 0); 10 * 6], 10, 6);
+        let mut cur = black.clone();
+        for &(x, y) in &[(1, 1), (2, 1), (3, 1), (4, 1), (9, 5)] {
+            cur.pixels_mut()[y * 10 + x] = Bgr8::new(255, 0, 0);
+        }
+        let diff = FrameDiff::compare_tiled(&black, &cur, 4);
+        assert!(diff.changed);
+        assert_eq!(diff.changed_percent, 100.0 * 5.0 / 60.0);
+        assert_eq!(
+            diff.tiles,
+            [(0, 0, 4, 4), (4, 0, 4, 4), (8, 4, 2, 2)]
+                .iter()
+                .map(|&(x, y, width, height)| Rect {
+                    x,
+                    y,
+                    width,
+                    height
+                })
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(count_changed(cur.pixels(), black.pixels()), 5);
+        assert_eq!(count_changed_scalar(cur.pixels(), black.pixels()), 5);
+
+        let same = FrameDiff::compare(&cur, &cur);
+        assert!(!same.changed && same.tiles.is_empty());
+        let resized = Frame::new(vec![Bgr8::new(0, 0, 0); 4], 2, 2);
+        assert_eq!(FrameDiff::compare(&black, &resized).changed_percent, 100.0);
+    }
+
+    #[test]
+    fn test_motion_detector() {
+        let mut detector = MotionDetector::new(2, 10);
+        let mut frame = vec![Bgr8::new(0, 0, 0); 4 * 4];
+        assert!(!detector.update(&frame, 4, 4).detected());
+        assert!(!detector.update(&frame, 4, 4).detected());
+        // One white pixel makes the mean difference of its block 255 / 4
+        frame[3 * 4] = Bgr8::new(255, 255, 255);
+        let motion = detector.update(&frame, 4, 4);
+        assert_eq!(
+            motion.regions,
+            vec![Rect {
+                x: 0,
+                y: 2,
+                width: 2,
+                height: 2
+            }]
+        );
+        assert_eq!(motion.score, 0.25);
+    }
+
+    #[test]
+    fn test_scene_change() {
+        let mut detector = SceneChangeDetector::default();
+        let black = vec![Bgr8::new(0, 0, 0); 16];
+        let mut half = black.clone();
+        half[..8].fill(Bgr8::new(255, 255, 255));
+        assert!(detector.update(&black).is_cut);
+        assert_eq!(detector.update(&black).score, 0.0);
+        let change = detector.update(&half);
+        assert_eq!(change.score, 0.5);
+        assert!(change.is_cut);
+    }
+}
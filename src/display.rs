@@ -0,0 +1,693 @@
+//! Enumeration of the displays that can be captured
+
+use crate::{Capturer, InitError};
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// DPI of a desktop with scale factor 1 on Windows and X11
+const NOMINAL_DPI: f64 = 96.0;
+
+/// A display that can be captured
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplayInfo {
+    /// Index of the display as a capture source, e.g. for `Capturer::new`
+    pub index: usize,
+    /// Name of the display, e.g. `\\.\DISPLAY1` on Windows and `DP-1` on X11
+    pub name: String,
+    /// Name of the monitor, e.g. `DELL U2720Q`, from its EDID on X11 and its
+    /// device string on Windows, if known
+    pub monitor_name: Option<String>,
+    /// Offset from the primary display, as `Capturer::position`
+    pub position: (i32, i32),
+    /// Width and height in pixels
+    pub size: (u32, u32),
+    /// Width and height of the visible area in millimeters, from the EDID of
+    /// the monitor, or as reported by the system if the EDID is missing,
+    /// e.g. for projectors and virtual displays. `None` if unknown.
+    pub physical_size: Option<(u32, u32)>,
+    /// Scale factor of the desktop on the display, e.g. 2.0 for HiDPI
+    pub scale_factor: f64,
+    /// Refresh rate in Hz, if known
+    pub refresh_rate: Option<f64>,
+    /// Bits per pixel of the current mode, e.g. 32, if known
+    pub bit_depth: Option<u32>,
+    pub primary: bool,
+}
+
+impl DisplayInfo {
+    /// Horizontal and vertical pixels per inch, from `Self::physical_size`
+    ///
+    /// Falls back to the nominal DPI of the desktop, i.e. 96 times
+    /// `Self::scale_factor`, when the physical size is unknown.
+    pub fn dpi(&self) -> (f64, f64) {
+        match self.physical_size {
+            Some((width_mm, height_mm)) if width_mm > 0 && height_mm > 0 => (
+                self.size.0 as f64 * MM_PER_INCH / width_mm as f64,
+                self.size.1 as f64 * MM_PER_INCH / height_mm as f64,
+            ),
+            _ => {
+                let dpi = NOMINAL_DPI * self.scale_factor;
+                (dpi, dpi)
+            }
+        }
+    }
+}
+
+impl Capturer {
+    /// The display of the top left corner of the captured area, with its
+    /// current mode, if any
+    ///
+    /// `None` for sources that are not on a listed display, e.g. on Wayland.
+    pub fn display(&self) -> Option<DisplayInfo> {
+        let (x, y) = self.position();
+        displays().into_iter().find(|d| {
+            let (dx, dy) = d.position;
+            x >= dx && y >= dy && ((x - dx) as u32) < d.size.0 && ((y - dy) as u32) < d.size.1
+        })
+    }
+
+    /// Refresh rate in Hz of the display of the captured area, if known,
+    /// e.g. to pick the frame rate of a recording
+    pub fn refresh_rate(&self) -> Option<f64> {
+        self.display().and_then(|d| d.refresh_rate)
+    }
+}
+
+/// The displays that can be captured, in the order of their capture source
+/// indices
+///
+/// On Wayland, the displays of XWayland are listed, as the ScreenCast portal
+/// can't enumerate displays without asking the user.
+pub fn displays() -> Vec<DisplayInfo> {
+    sys::displays()
+}
+
+/// The ICC profile assigned to the display with a capture source index, if
+/// any, see `crate::color::IccProfile::for_display`
+pub(crate) fn icc_profile(index: usize) -> Option<Vec<u8>> {
+    sys::icc_profile(index)
+}
+
+/// The index of the display with a name or monitor name, see
+/// `CaptureSource::Name`
+pub(crate) fn display_index(name: &str) -> Result<usize, InitError> {
+    let displays = displays();
+    let found = displays
+        .iter()
+        .find(|d| d.name == name || d.monitor_name.as_deref() == Some(name));
+    match found {
+        Some(display) => Ok(display.index),
+        None => {
+            let names: Vec<String> = displays
+                .iter()
+                .map(|d| match &d.monitor_name {
+                    Some(monitor_name) => format!("{} ({})", d.name, monitor_name),
+                    None => d.name.clone(),
+                })
+                .collect();
+            Err(InitError::NotFound(format!(
+                "No display named {}. Available displays: {}",
+                name,
+                names.join(", ")
+            )))
+        }
+    }
+}
+
+/// The monitor name of an EDID, from its display product name descriptor
+#[cfg_attr(
+    not(all(unix, not(target_os = "macos"), not(target_os = "android"))),
+    allow(dead_code)
+)]
+pub(crate) fn edid_monitor_name(edid: &[u8]) -> Option<String> {
+    [54, 72, 90, 108].iter().find_map(|&o| {
+        let descriptor = edid.get(o..o + 18)?;
+        if descriptor[..5] != [0, 0, 0, 0xFC, 0] {
+            return None;
+        }
+        let text = &descriptor[5..];
+        let end = text.iter().position(|&c| c == b'\n').unwrap_or(text.len());
+        let name = String::from_utf8_lossy(&text[..end]).trim().to_string();
+        Some(name).filter(|name| !name.is_empty())
+    })
+}
+
+/// The width and height of the image in millimeters of an EDID, from its
+/// preferred detailed timing, or from the screen size in centimeters in the
+/// basic display parameters
+///
+/// Both are 0 for projectors, and one of them is the aspect ratio in EDID 1.4
+/// if the other is 0, so `None` unless both are known.
+#[cfg_attr(
+    not(any(
+        windows,
+        all(unix, not(target_os = "macos"), not(target_os = "android"))
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn edid_physical_size(edid: &[u8]) -> Option<(u32, u32)> {
+    let edid = edid.get(..128)?;
+    let timing = &edid[54..72];
+    // Detailed timings have a nonzero pixel clock, other descriptors 0
+    if timing[0] != 0 || timing[1] != 0 {
+        let width = timing[12] as u32 | (timing[14] as u32 & 0xF0) << 4;
+        let height = timing[13] as u32 | (timing[14] as u32 & 0x0F) << 8;
+        if width > 0 && height > 0 {
+            return Some((width, height));
+        }
+    }
+    let (width_cm, height_cm) = (edid[21] as u32, edid[22] as u32);
+    Some((width_cm * 10, height_cm * 10)).filter(|_| width_cm > 0 && height_cm > 0)
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::DisplayInfo;
+    use crate::dxgi;
+    use std::ffi::OsString;
+    use std::mem;
+    use std::os::windows::ffi::OsStringExt;
+    use winapi::shared::minwindef::MAX_PATH;
+    use winapi::shared::winerror::SUCCEEDED;
+    use winapi::um::shellscalingapi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
+    use winapi::um::wingdi::{
+        CreateDCW, DeleteDC, GetDeviceCaps, GetICMProfileW, DEVMODEW, DISPLAY_DEVICEW, HORZSIZE,
+        VERTSIZE,
+    };
+    use winapi::um::winreg::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY};
+    use winapi::um::winuser::{
+        EnumDisplayDevicesW, EnumDisplaySettingsW, EDD_GET_DEVICE_INTERFACE_NAME,
+        ENUM_CURRENT_SETTINGS,
+    };
+
+    /// DPI of a display with scale factor 1
+    const BASE_DPI: f64 = 96.0;
+
+    pub fn displays() -> Vec<DisplayInfo> {
+        (0..)
+            .map_while(|i| dxgi::capture_source_output(i).map(|o| (i, o)))
+            .map(|(index, o)| {
+                let name_len = o.device_name.iter().position(|&c| c == 0);
+                let name = String::from_utf16_lossy(&o.device_name[..name_len.unwrap_or(32)]);
+                let mode = mode(&o);
+                DisplayInfo {
+                    index,
+                    name,
+                    monitor_name: monitor_name(&o),
+                    position: (o.rect.left, o.rect.top),
+                    size: (
+                        (o.rect.right - o.rect.left) as u32,
+                        (o.rect.bottom - o.rect.top) as u32,
+                    ),
+                    physical_size: edid(&o)
+                        .as_deref()
+                        .and_then(super::edid_physical_size)
+                        .or_else(|| physical_size(&o)),
+                    scale_factor: scale_factor(&o),
+                    // 0 and 1 mean the default refresh rate of the hardware
+                    refresh_rate: mode
+                        .map(|m| m.dmDisplayFrequency as f64)
+                        .filter(|&hz| hz > 1.0),
+                    bit_depth: mode.map(|m| m.dmBitsPerPel).filter(|&bits| bits > 0),
+                    primary: o.primary,
+                }
+            })
+            .collect()
+    }
+
+    /// The scale factor of the monitor. 1 unless the process is DPI aware.
+    fn scale_factor(output: &dxgi::Output) -> f64 {
+        let (mut dpi_x, mut dpi_y) = (0, 0);
+        let hr =
+            unsafe { GetDpiForMonitor(output.monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) };
+        if SUCCEEDED(hr) && dpi_x > 0 {
+            dpi_x as f64 / BASE_DPI
+        } else {
+            1.0
+        }
+    }
+
+    /// The current mode of the monitor
+    fn mode(output: &dxgi::Output) -> Option<DEVMODEW> {
+        unsafe {
+            let mut mode: DEVMODEW = mem::zeroed();
+            mode.dmSize = mem::size_of::<DEVMODEW>() as u16;
+            let ok = EnumDisplaySettingsW(
+                output.device_name.as_ptr(),
+                ENUM_CURRENT_SETTINGS,
+                &mut mode,
+            );
+            Some(mode).filter(|_| ok != 0)
+        }
+    }
+
+    /// The device string of the first monitor on the output
+    fn monitor_name(output: &dxgi::Output) -> Option<String> {
+        unsafe {
+            let mut device: DISPLAY_DEVICEW = mem::zeroed();
+            device.cb = mem::size_of::<DISPLAY_DEVICEW>() as u32;
+            if EnumDisplayDevicesW(output.device_name.as_ptr(), 0, &mut device, 0) == 0 {
+                return None;
+            }
+            let len = device.DeviceString.iter().position(|&c| c == 0);
+            let name = String::from_utf16_lossy(&device.DeviceString[..len.unwrap_or(128)]);
+            Some(name.trim().to_string()).filter(|name| !name.is_empty())
+        }
+    }
+
+    /// The EDID of the first monitor on the output, as stored in the registry
+    /// by the monitor driver
+    fn edid(output: &dxgi::Output) -> Option<Vec<u8>> {
+        unsafe {
+            let mut device: DISPLAY_DEVICEW = mem::zeroed();
+            device.cb = mem::size_of::<DISPLAY_DEVICEW>() as u32;
+            let flags = EDD_GET_DEVICE_INTERFACE_NAME;
+            if EnumDisplayDevicesW(output.device_name.as_ptr(), 0, &mut device, flags) == 0 {
+                return None;
+            }
+            // E.g. `\\?\DISPLAY#DEL4123#5&2a1f6a4&0&UID4353#{e6f07b5f-...}`
+            let len = device.DeviceID.iter().position(|&c| c == 0);
+            let id = String::from_utf16_lossy(&device.DeviceID[..len.unwrap_or(128)]);
+            let mut parts = id.split('#').skip(1);
+            let (model, instance) = (parts.next()?, parts.next()?);
+            let key: Vec<u16> = format!(
+                "SYSTEM\\CurrentControlSet\\Enum\\DISPLAY\\{}\\{}\\Device Parameters\0",
+                model, instance
+            )
+            .encode_utf16()
+            .collect();
+            let value: Vec<u16> = "EDID\0".encode_utf16().collect();
+            // Extension blocks make EDIDs longer than the base block of 128
+            let mut edid = vec![0u8; 1024];
+            let mut len = edid.len() as u32;
+            let status = RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                key.as_ptr(),
+                value.as_ptr(),
+                RRF_RT_REG_BINARY,
+                std::ptr::null_mut(),
+                edid.as_mut_ptr() as *mut _,
+                &mut len,
+            );
+            if status != 0 {
+                return None;
+            }
+            edid.truncate(len as usize);
+            Some(edid)
+        }
+    }
+
+    /// The physical size in millimeters reported by the display driver, e.g.
+    /// for monitors without an EDID
+    fn physical_size(output: &dxgi::Output) -> Option<(u32, u32)> {
+        let driver: Vec<u16> = "DISPLAY\0".encode_utf16().collect();
+        unsafe {
+            let dc = CreateDCW(
+                driver.as_ptr(),
+                output.device_name.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null(),
+            );
+            if dc.is_null() {
+                return None;
+            }
+            let size = (GetDeviceCaps(dc, HORZSIZE), GetDeviceCaps(dc, VERTSIZE));
+            DeleteDC(dc);
+            Some((size.0 as u32, size.1 as u32)).filter(|_| size.0 > 0 && size.1 > 0)
+        }
+    }
+
+    pub fn icc_profile(index: usize) -> Option<Vec<u8>> {
+        let output = dxgi::capture_source_output(index)?;
+        let driver: Vec<u16> = "DISPLAY\0".encode_utf16().collect();
+        let mut path = [0u16; MAX_PATH];
+        let mut len = path.len() as u32;
+        unsafe {
+            let dc = CreateDCW(
+                driver.as_ptr(),
+                output.device_name.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null(),
+            );
+            if dc.is_null() {
+                return None;
+            }
+            let ok = GetICMProfileW(dc, &mut len, path.as_mut_ptr());
+            DeleteDC(dc);
+            if ok == 0 {
+                return None;
+            }
+        }
+        let end = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+        std::fs::read(OsString::from_wide(&path[..end])).ok()
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use super::DisplayInfo;
+    use objc2_core_graphics::{
+        CGColorSpace, CGDirectDisplayID, CGDisplayBounds, CGDisplayCopyColorSpace,
+        CGDisplayCopyDisplayMode, CGDisplayMode, CGDisplayScreenSize, CGMainDisplayID,
+    };
+
+    pub fn displays() -> Vec<DisplayInfo> {
+        crate::macos::displays()
+            .into_iter()
+            .enumerate()
+            .map(|(index, id)| {
+                let mode = CGDisplayCopyDisplayMode(id);
+                let mode = mode.as_deref();
+                let (width, height) = (
+                    CGDisplayMode::pixel_width(mode) as u32,
+                    CGDisplayMode::pixel_height(mode) as u32,
+                );
+                let points = CGDisplayMode::width(mode);
+                let refresh_rate = CGDisplayMode::refresh_rate(mode);
+                let bounds = CGDisplayBounds(id);
+                DisplayInfo {
+                    index,
+                    name: format!("Display {}", id),
+                    monitor_name: None,
+                    position: (bounds.origin.x as i32, bounds.origin.y as i32),
+                    size: (width, height),
+                    physical_size: physical_size(id),
+                    scale_factor: if points > 0 {
+                        width as f64 / points as f64
+                    } else {
+                        1.0
+                    },
+                    // 0 for displays without a fixed refresh rate, e.g. LCDs
+                    refresh_rate: Some(refresh_rate).filter(|&hz| hz > 0.0),
+                    // Only reported by deprecated APIs
+                    bit_depth: None,
+                    primary: id == CGMainDisplayID(),
+                }
+            })
+            .collect()
+    }
+
+    /// The physical size in millimeters, from the EDID of the display
+    fn physical_size(id: CGDirectDisplayID) -> Option<(u32, u32)> {
+        let size = CGDisplayScreenSize(id);
+        Some((size.width.round() as u32, size.height.round() as u32))
+            .filter(|&(width, height)| width > 0 && height > 0)
+    }
+
+    pub fn icc_profile(index: usize) -> Option<Vec<u8>> {
+        let id = *crate::macos::displays().get(index)?;
+        let space = CGDisplayCopyColorSpace(id);
+        CGColorSpace::icc_data(Some(&space)).map(|data| data.to_vec())
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+mod sys {
+    use super::DisplayInfo;
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+    use x11::{xlib, xrandr};
+
+    /// DPI of a display with scale factor 1
+    const BASE_DPI: f64 = 96.0;
+
+    pub fn displays() -> Vec<DisplayInfo> {
+        unsafe {
+            let display = crate::x11::open_display();
+            if display.is_null() {
+                return Vec::new();
+            }
+            let root = xlib::XDefaultRootWindow(display);
+            let scale_factor = scale_factor(display);
+            // The depth of the root window, which all monitors share
+            let depth = xlib::XDefaultDepth(display, xlib::XDefaultScreen(display)) as u32;
+            let resources = xrandr::XRRGetScreenResourcesCurrent(display, root);
+            // The monitors of the capture sources of the X11 backend
+            let displays = crate::x11::monitors(display)
+                .into_iter()
+                .enumerate()
+                .map(|(index, monitor)| {
+                    let edid = edid(display, monitor.output);
+                    let physical_size = edid
+                        .as_deref()
+                        .and_then(super::edid_physical_size)
+                        .or_else(|| physical_size(display, resources, monitor.output));
+                    DisplayInfo {
+                        index,
+                        monitor_name: edid.as_deref().and_then(super::edid_monitor_name),
+                        position: (monitor.x, monitor.y),
+                        size: (monitor.width, monitor.height),
+                        physical_size,
+                        scale_factor,
+                        refresh_rate: refresh_rate(display, resources, monitor.crtc),
+                        bit_depth: Some(depth),
+                        primary: monitor.primary,
+                        name: monitor.name,
+                    }
+                })
+                .collect();
+            if !resources.is_null() {
+                xrandr::XRRFreeScreenResources(resources);
+            }
+            xlib::XCloseDisplay(display);
+            displays
+        }
+    }
+
+    /// The EDID of a RandR output
+    unsafe fn edid(display: *mut xlib::Display, output: xrandr::RROutput) -> Option<Vec<u8>> {
+        if output == 0 {
+            return None;
+        }
+        let atom = xlib::XInternAtom(display, b"EDID\0".as_ptr() as *const c_char, xlib::True);
+        if atom == 0 {
+            return None;
+        }
+        let (mut actual_type, mut actual_format) = (0, 0);
+        let (mut n, mut bytes_after) = (0, 0);
+        let mut data = std::ptr::null_mut();
+        let status = xrandr::XRRGetOutputProperty(
+            display,
+            output,
+            atom,
+            0,
+            // In 32 bit units
+            64,
+            xlib::False,
+            xlib::False,
+            xlib::AnyPropertyType as xlib::Atom,
+            &mut actual_type,
+            &mut actual_format,
+            &mut n,
+            &mut bytes_after,
+            &mut data,
+        );
+        if status != xlib::Success as i32 || data.is_null() {
+            return None;
+        }
+        let edid = Some(std::slice::from_raw_parts(data, n as usize))
+            .filter(|_| actual_format == 8)
+            .map(|edid| edid.to_vec());
+        xlib::XFree(data as *mut _);
+        edid
+    }
+
+    /// The physical size in millimeters of a RandR output, as reported by
+    /// the driver, e.g. for outputs without an EDID
+    unsafe fn physical_size(
+        display: *mut xlib::Display,
+        resources: *mut xrandr::XRRScreenResources,
+        output: xrandr::RROutput,
+    ) -> Option<(u32, u32)> {
+        if resources.is_null() || output == 0 {
+            return None;
+        }
+        let info = xrandr::XRRGetOutputInfo(display, resources, output);
+        if info.is_null() {
+            return None;
+        }
+        let size = ((*info).mm_width as u32, (*info).mm_height as u32);
+        xrandr::XRRFreeOutputInfo(info);
+        Some(size).filter(|&(width, height)| width > 0 && height > 0)
+    }
+
+    /// The profile of the `_ICC_PROFILE` property of the root window for the
+    /// first monitor, and `_ICC_PROFILE_<index>` for the others, as set by
+    /// color managers following the ICC Profiles in X Specification
+    pub fn icc_profile(index: usize) -> Option<Vec<u8>> {
+        let name = match index {
+            0 => "_ICC_PROFILE\0".to_string(),
+            _ => format!("_ICC_PROFILE_{}\0", index),
+        };
+        unsafe {
+            let display = crate::x11::open_display();
+            if display.is_null() {
+                return None;
+            }
+            let atom = xlib::XInternAtom(display, name.as_ptr() as *const c_char, xlib::True);
+            let profile = if atom != 0 {
+                root_property(display, atom)
+            } else {
+                None
+            };
+            xlib::XCloseDisplay(display);
+            profile
+        }
+    }
+
+    /// The bytes of a property of the root window, if set in 8-bit format
+    unsafe fn root_property(display: *mut xlib::Display, atom: xlib::Atom) -> Option<Vec<u8>> {
+        let (mut actual_type, mut actual_format) = (0, 0);
+        let (mut n, mut bytes_after) = (0, 0);
+        let mut data = std::ptr::null_mut();
+        let status = xlib::XGetWindowProperty(
+            display,
+            xlib::XDefaultRootWindow(display),
+            atom,
+            0,
+            // All of it, in 32 bit units
+            std::os::raw::c_long::MAX / 4,
+            xlib::False,
+            xlib::AnyPropertyType as xlib::Atom,
+            &mut actual_type,
+            &mut actual_format,
+            &mut n,
+            &mut bytes_after,
+            &mut data,
+        );
+        if status != xlib::Success as i32 || data.is_null() {
+            return None;
+        }
+        let bytes = Some(std::slice::from_raw_parts(data, n as usize))
+            .filter(|_| actual_format == 8)
+            .map(|bytes| bytes.to_vec());
+        xlib::XFree(data as *mut _);
+        bytes
+    }
+
+    /// The scale factor from the `Xft.dpi` resource, which X11 desktops set
+    /// for all displays
+    unsafe fn scale_factor(display: *mut xlib::Display) -> f64 {
+        let dpi = xlib::XGetDefault(
+            display,
+            b"Xft\0".as_ptr() as *const c_char,
+            b"dpi\0".as_ptr() as *const c_char,
+        );
+        if dpi.is_null() {
+            return 1.0;
+        }
+        CStr::from_ptr(dpi)
+            .to_str()
+            .ok()
+            .and_then(|dpi| dpi.trim().parse::<f64>().ok())
+            .filter(|&dpi| dpi > 0.0)
+            .map_or(1.0, |dpi| dpi / BASE_DPI)
+    }
+
+    /// The refresh rate of the mode of the CRTC of a monitor
+    unsafe fn refresh_rate(
+        display: *mut xlib::Display,
+        resources: *mut xrandr::XRRScreenResources,
+        crtc: xrandr::RRCrtc,
+    ) -> Option<f64> {
+        if resources.is_null() || crtc == 0 {
+            return None;
+        }
+        let crtc = xrandr::XRRGetCrtcInfo(display, resources, crtc);
+        if crtc.is_null() {
+            return None;
+        }
+        let mode = (*crtc).mode;
+        xrandr::XRRFreeCrtcInfo(crtc);
+        let modes = std::slice::from_raw_parts((*resources).modes, (*resources).nmode as usize);
+        modes
+            .iter()
+            .find(|m| m.id == mode && m.hTotal > 0 && m.vTotal > 0)
+            .map(|m| m.dotClock as f64 / (m.hTotal as f64 * m.vTotal as f64))
+    }
+}
+
+#[cfg(any(target_os = "android", target_arch = "wasm32"))]
+mod sys {
+    use super::DisplayInfo;
+
+    /// DPI of a display with scale factor 1, `DisplayMetrics.DENSITY_DEFAULT`
+    #[cfg(all(target_os = "android", feature = "android"))]
+    const BASE_DPI: f64 = 160.0;
+
+    /// The default display, once a projection is set with
+    /// `crate::android::set_media_projection`. None in browsers, where the
+    /// user picks what to share.
+    pub fn displays() -> Vec<DisplayInfo> {
+        #[cfg(all(target_os = "android", feature = "android"))]
+        if let Some((width, height, dpi)) = crate::android::display_metrics() {
+            return vec![DisplayInfo {
+                index: 0,
+                name: "Built-in Screen".to_string(),
+                monitor_name: None,
+                position: (0, 0),
+                size: (width, height),
+                // `densityDpi` is bucketed, not the physical density
+                physical_size: None,
+                scale_factor: dpi as f64 / BASE_DPI,
+                refresh_rate: None,
+                bit_depth: Some(32),
+                primary: true,
+            }];
+        }
+        Vec::new()
+    }
+
+    pub fn icc_profile(_index: usize) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod display_tests {
+    use super::*;
+
+    #[test]
+    fn test_edid_monitor_name() {
+        let mut edid = vec![0; 128];
+        edid[72..77].copy_from_slice(&[0, 0, 0, 0xFC, 0]);
+        edid[77..90].copy_from_slice(b"DELL U2720Q\n ");
+        assert_eq!(edid_monitor_name(&edid).as_deref(), Some("DELL U2720Q"));
+        assert_eq!(edid_monitor_name(&edid[..80]), None);
+        edid[75] = 0xFF;
+        assert_eq!(edid_monitor_name(&edid), None);
+    }
+
+    #[test]
+    fn test_edid_physical_size() {
+        let mut edid = vec![0; 128];
+        assert_eq!(edid_physical_size(&edid), None);
+        // 60x34 cm screen size
+        edid[21..23].copy_from_slice(&[60, 34]);
+        assert_eq!(edid_physical_size(&edid), Some((600, 340)));
+        // 597x336 mm image size of the preferred timing
+        edid[54..56].copy_from_slice(&[0x56, 0x5E]);
+        edid[66..69].copy_from_slice(&[0x55, 0x50, 0x21]);
+        assert_eq!(edid_physical_size(&edid), Some((597, 336)));
+        assert_eq!(edid_physical_size(&edid[..100]), None);
+    }
+
+    #[test]
+    fn test_dpi() {
+        let mut display = DisplayInfo {
+            index: 0,
+            name: "DP-1".to_string(),
+            monitor_name: None,
+            position: (0, 0),
+            size: (3840, 2160),
+            physical_size: None,
+            scale_factor: 2.0,
+            refresh_rate: None,
+            bit_depth: None,
+            primary: true,
+        };
+        assert_eq!(display.dpi(), (192.0, 192.0));
+        display.physical_size = Some((508, 254));
+        assert_eq!(display.dpi(), (192.0, 216.0));
+    }
+}
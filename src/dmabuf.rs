@@ -0,0 +1,126 @@
+//! DMA-BUF handles of frames in GPU memory on Linux, for importing them
+//! into VAAPI, Vulkan or EGL without copying them to the CPU
+
+use std::fmt;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::sync::Arc;
+
+/// `DRM_FORMAT_MOD_LINEAR`, for buffers that are not tiled
+pub const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+/// `DRM_FORMAT_MOD_INVALID`, for buffers with a driver specific layout
+/// that is not described by a modifier
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+/// A DRM fourcc code, e.g. `XR24` for blue, green, red, and padding
+pub const fn drm_fourcc(code: &[u8; 4]) -> u32 {
+    code[0] as u32 | (code[1] as u32) << 8 | (code[2] as u32) << 16 | (code[3] as u32) << 24
+}
+
+/// A single plane DMA-BUF of a frame
+///
+/// The file descriptor is shared by clones, and closed when the last clone
+/// is dropped.
+#[derive(Clone)]
+pub struct DmaBuf {
+    fd: Arc<OwnedFd>,
+    width: u32,
+    height: u32,
+    fourcc: u32,
+    modifier: u64,
+    offset: usize,
+    stride: usize,
+}
+
+impl DmaBuf {
+    #[cfg_attr(not(feature = "wayland"), allow(dead_code))]
+    pub(crate) fn new(
+        fd: OwnedFd,
+        (width, height): (u32, u32),
+        fourcc: u32,
+        modifier: u64,
+        offset: usize,
+        stride: usize,
+    ) -> DmaBuf {
+        DmaBuf {
+            fd: Arc::new(fd),
+            width,
+            height,
+            fourcc,
+            modifier,
+            offset,
+            stride,
+        }
+    }
+
+    /// The file descriptor of the buffer
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// DRM fourcc code of the pixel format, see `drm_fourcc`
+    pub fn fourcc(&self) -> u32 {
+        self.fourcc
+    }
+
+    /// DRM format modifier of the layout of the buffer, e.g.
+    /// `DRM_FORMAT_MOD_LINEAR`
+    pub fn modifier(&self) -> u64 {
+        self.modifier
+    }
+
+    /// Offset of the first pixel in the buffer, in bytes
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Number of bytes from the start of one row to the next
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+}
+
+impl fmt::Debug for DmaBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DmaBuf")
+            .field("fd", &self.fd)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field(
+                "fourcc",
+                &String::from_utf8_lossy(&self.fourcc.to_le_bytes()),
+            )
+            .field("modifier", &format_args!("{:#x}", self.modifier))
+            .field("offset", &self.offset)
+            .field("stride", &self.stride)
+            .finish()
+    }
+}
+
+/// Buffers are equal if they are clones of the same buffer
+impl PartialEq for DmaBuf {
+    fn eq(&self, other: &DmaBuf) -> bool {
+        Arc::ptr_eq(&self.fd, &other.fd)
+    }
+}
+
+impl Eq for DmaBuf {}
+
+#[cfg(test)]
+mod dmabuf_tests {
+    use super::*;
+
+    #[test]
+    fn test_drm_fourcc() {
+        // DRM_FORMAT_XRGB8888 and DRM_FORMAT_ARGB8888
+        assert_eq!(drm_fourcc(b"XR24"), 0x3432_5258);
+        assert_eq!(drm_fourcc(b"AR24"), 0x3432_5241);
+    }
+}
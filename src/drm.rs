@@ -0,0 +1,566 @@
+//! Capture of the framebuffers scanned out by DRM/KMS, for consoles and
+//! headless machines without X11 or Wayland, e.g. kiosks running a KMS app
+//!
+//! The framebuffer of a CRTC is mapped with a dumb buffer mapping, or through
+//! a PRIME DMA-BUF if it's not a dumb buffer. Getting the buffers of
+//! framebuffers requires root or `CAP_SYS_ADMIN`. Framebuffers with a tiled
+//! layout, as rendered by most GPUs, can't be read on the CPU, so this mostly
+//! works with dumb buffers, e.g. of `vkms`, simple display drivers, or apps
+//! rendering in software.
+//!
+//! `Capturer::new` falls back to this backend when there is no X11 or
+//! Wayland display. It can also be picked explicitly:
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::drm::DrmBackend;
+//! let backend = DrmBackend::open("/dev/dri/card0", 0).unwrap();
+//! let mut capturer = Capturer::with_backend(Box::new(backend));
+//! ```
+
+use crate::backend::Backend;
+use crate::dmabuf::{drm_fourcc, DRM_FORMAT_MOD_INVALID, DRM_FORMAT_MOD_LINEAR};
+use crate::{Bgr8, CaptureError, InitError, PlatformCode, PlatformError};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::Duration;
+
+/// Blue, green, red, and padding or alpha. Same layout as `Bgr8`.
+const DRM_FORMAT_XRGB8888: u32 = drm_fourcc(b"XR24");
+const DRM_FORMAT_ARGB8888: u32 = drm_fourcc(b"AR24");
+/// Red, green, blue, and padding or alpha
+const DRM_FORMAT_XBGR8888: u32 = drm_fourcc(b"XB24");
+const DRM_FORMAT_ABGR8888: u32 = drm_fourcc(b"AB24");
+
+/// `flags` of `drm_mode_fb_cmd2` when `modifier` is valid
+const DRM_MODE_FB_MODIFIERS: u32 = 2;
+
+const DMA_BUF_SYNC_READ: u64 = 1;
+const DMA_BUF_SYNC_END: u64 = 4;
+
+/// `struct drm_mode_card_res` from `drm/drm_mode.h`
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCardRes {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+/// `struct drm_mode_modeinfo` from `drm/drm_mode.h`
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeModeInfo {
+    clock: u32,
+    hdisplay: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    hskew: u16,
+    vdisplay: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+    vscan: u16,
+    vrefresh: u32,
+    flags: u32,
+    type_: u32,
+    name: [u8; 32],
+}
+
+/// `struct drm_mode_crtc` from `drm/drm_mode.h`
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCrtc {
+    set_connectors_ptr: u64,
+    count_connectors: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    x: u32,
+    y: u32,
+    gamma_size: u32,
+    mode_valid: u32,
+    mode: DrmModeModeInfo,
+}
+
+/// `struct drm_mode_fb_cmd` from `drm/drm_mode.h`
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeFbCmd {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bpp: u32,
+    depth: u32,
+    handle: u32,
+}
+
+/// `struct drm_mode_fb_cmd2` from `drm/drm_mode.h`
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeFbCmd2 {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pixel_format: u32,
+    flags: u32,
+    handles: [u32; 4],
+    pitches: [u32; 4],
+    offsets: [u32; 4],
+    modifier: [u64; 4],
+}
+
+/// `struct drm_mode_map_dumb` from `drm/drm_mode.h`
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeMapDumb {
+    handle: u32,
+    pad: u32,
+    offset: u64,
+}
+
+/// `struct drm_prime_handle` from `drm/drm.h`
+#[repr(C)]
+#[derive(Default)]
+struct DrmPrimeHandle {
+    handle: u32,
+    flags: u32,
+    fd: i32,
+}
+
+/// `struct drm_gem_close` from `drm/drm.h`
+#[repr(C)]
+#[derive(Default)]
+struct DrmGemClose {
+    handle: u32,
+    pad: u32,
+}
+
+const fn ioc(dir: libc::c_ulong, ty: u8, nr: u8, size: usize) -> libc::c_ulong {
+    (dir << 30)
+        | ((size as libc::c_ulong) << 16)
+        | ((ty as libc::c_ulong) << 8)
+        | nr as libc::c_ulong
+}
+
+const fn drm_iow<T>(nr: u8) -> libc::c_ulong {
+    ioc(1, b'd', nr, mem::size_of::<T>())
+}
+
+const fn drm_iowr<T>(nr: u8) -> libc::c_ulong {
+    ioc(3, b'd', nr, mem::size_of::<T>())
+}
+
+const DRM_IOCTL_GEM_CLOSE: libc::c_ulong = drm_iow::<DrmGemClose>(0x09);
+const DRM_IOCTL_PRIME_HANDLE_TO_FD: libc::c_ulong = drm_iowr::<DrmPrimeHandle>(0x2d);
+const DRM_IOCTL_MODE_GETRESOURCES: libc::c_ulong = drm_iowr::<DrmModeCardRes>(0xa0);
+const DRM_IOCTL_MODE_GETCRTC: libc::c_ulong = drm_iowr::<DrmModeCrtc>(0xa1);
+const DRM_IOCTL_MODE_GETFB: libc::c_ulong = drm_iowr::<DrmModeFbCmd>(0xad);
+const DRM_IOCTL_MODE_MAP_DUMB: libc::c_ulong = drm_iowr::<DrmModeMapDumb>(0xb3);
+const DRM_IOCTL_MODE_GETFB2: libc::c_ulong = drm_iowr::<DrmModeFbCmd2>(0xce);
+/// `_IOW('b', 0, struct dma_buf_sync)` from `linux/dma-buf.h`
+const DMA_BUF_IOCTL_SYNC: libc::c_ulong = ioc(1, b'b', 0, mem::size_of::<u64>());
+
+/// Call an ioctl, retrying when interrupted
+fn ioctl<T>(fd: RawFd, request: libc::c_ulong, arg: &mut T) -> io::Result<()> {
+    loop {
+        if unsafe { libc::ioctl(fd, request as _, arg as *mut T) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+fn platform_error(message: impl Into<String>, err: io::Error) -> PlatformError {
+    PlatformError::new(message, PlatformCode::Os(err.raw_os_error().unwrap_or(0)))
+}
+
+/// The DRM device nodes, e.g. `/dev/dri/card0`, in order of their numbers
+pub fn cards() -> Vec<PathBuf> {
+    let mut cards: Vec<(u32, PathBuf)> = std::fs::read_dir("/dev/dri")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let n = name.strip_prefix("card")?.parse().ok()?;
+            Some((n, entry.path()))
+        })
+        .collect();
+    cards.sort();
+    cards.into_iter().map(|(_, path)| path).collect()
+}
+
+/// The IDs of the CRTCs of a card
+fn crtcs(card: &File) -> io::Result<Vec<u32>> {
+    let fd = card.as_raw_fd();
+    let mut res = DrmModeCardRes::default();
+    ioctl(fd, DRM_IOCTL_MODE_GETRESOURCES, &mut res)?;
+    let mut ids = vec![0u32; res.count_crtcs as usize];
+    // Only get the CRTCs
+    res = DrmModeCardRes {
+        crtc_id_ptr: ids.as_mut_ptr() as u64,
+        count_crtcs: ids.len() as u32,
+        ..DrmModeCardRes::default()
+    };
+    ioctl(fd, DRM_IOCTL_MODE_GETRESOURCES, &mut res)?;
+    ids.truncate(res.count_crtcs as usize);
+    Ok(ids)
+}
+
+fn get_crtc(card: &File, crtc_id: u32) -> io::Result<DrmModeCrtc> {
+    let mut crtc = DrmModeCrtc {
+        crtc_id,
+        ..DrmModeCrtc::default()
+    };
+    ioctl(card.as_raw_fd(), DRM_IOCTL_MODE_GETCRTC, &mut crtc)?;
+    Ok(crtc)
+}
+
+/// Whether a CRTC is scanning out a framebuffer
+fn is_active(crtc: &DrmModeCrtc) -> bool {
+    crtc.mode_valid != 0 && crtc.fb_id != 0
+}
+
+/// A framebuffer and the GEM handle of its buffer, closed on drop
+struct Framebuffer<'a> {
+    card: &'a File,
+    width: u32,
+    height: u32,
+    pixel_format: u32,
+    modifier: u64,
+    handle: u32,
+    pitch: u32,
+    offset: u32,
+}
+
+impl<'a> Framebuffer<'a> {
+    fn get(card: &'a File, fb_id: u32) -> io::Result<Framebuffer<'a>> {
+        let fd = card.as_raw_fd();
+        let mut fb2 = DrmModeFbCmd2 {
+            fb_id,
+            ..DrmModeFbCmd2::default()
+        };
+        match ioctl(fd, DRM_IOCTL_MODE_GETFB2, &mut fb2) {
+            Ok(()) => Ok(Framebuffer {
+                card,
+                width: fb2.width,
+                height: fb2.height,
+                pixel_format: fb2.pixel_format,
+                modifier: if fb2.flags & DRM_MODE_FB_MODIFIERS != 0 {
+                    fb2.modifier[0]
+                } else {
+                    DRM_FORMAT_MOD_INVALID
+                },
+                handle: fb2.handles[0],
+                pitch: fb2.pitches[0],
+                offset: fb2.offsets[0],
+            }),
+            // `GETFB2` is only in Linux 5.7 and later
+            Err(e)
+                if e.raw_os_error() == Some(libc::EINVAL)
+                    || e.raw_os_error() == Some(libc::ENOTTY) =>
+            {
+                let mut fb = DrmModeFbCmd {
+                    fb_id,
+                    ..DrmModeFbCmd::default()
+                };
+                ioctl(fd, DRM_IOCTL_MODE_GETFB, &mut fb)?;
+                let pixel_format = match (fb.bpp, fb.depth) {
+                    (32, 24) => DRM_FORMAT_XRGB8888,
+                    (32, 32) => DRM_FORMAT_ARGB8888,
+                    _ => 0,
+                };
+                Ok(Framebuffer {
+                    card,
+                    width: fb.width,
+                    height: fb.height,
+                    pixel_format,
+                    modifier: DRM_FORMAT_MOD_INVALID,
+                    handle: fb.handle,
+                    pitch: fb.pitch,
+                    offset: 0,
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Map the buffer for reading, as a dumb buffer, or else through a
+    /// DMA-BUF
+    fn map(&self) -> io::Result<Mapping> {
+        let len = self.offset as usize + self.pitch as usize * self.height as usize;
+        let mut map = DrmModeMapDumb {
+            handle: self.handle,
+            ..DrmModeMapDumb::default()
+        };
+        if ioctl(self.card.as_raw_fd(), DRM_IOCTL_MODE_MAP_DUMB, &mut map).is_ok() {
+            return Mapping::new(self.card.as_raw_fd(), len, map.offset as libc::off_t, None);
+        }
+        let mut prime = DrmPrimeHandle {
+            handle: self.handle,
+            flags: libc::O_CLOEXEC as u32,
+            ..DrmPrimeHandle::default()
+        };
+        ioctl(
+            self.card.as_raw_fd(),
+            DRM_IOCTL_PRIME_HANDLE_TO_FD,
+            &mut prime,
+        )?;
+        let dmabuf = unsafe { OwnedFd::from_raw_fd(prime.fd) };
+        Mapping::new(dmabuf.as_raw_fd(), len, 0, Some(dmabuf))
+    }
+}
+
+impl Drop for Framebuffer<'_> {
+    fn drop(&mut self) {
+        if self.handle != 0 {
+            let mut close = DrmGemClose {
+                handle: self.handle,
+                pad: 0,
+            };
+            let _ = ioctl(self.card.as_raw_fd(), DRM_IOCTL_GEM_CLOSE, &mut close);
+        }
+    }
+}
+
+/// A read-only memory mapping of a buffer, and the DMA-BUF it's mapped
+/// through, if any
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+    dmabuf: Option<OwnedFd>,
+}
+
+impl Mapping {
+    fn new(
+        fd: RawFd,
+        len: usize,
+        offset: libc::off_t,
+        dmabuf: Option<OwnedFd>,
+    ) -> io::Result<Mapping> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mapping = Mapping { ptr, len, dmabuf };
+        if let Some(dmabuf) = &mapping.dmabuf {
+            let mut sync = DMA_BUF_SYNC_READ;
+            ioctl(dmabuf.as_raw_fd(), DMA_BUF_IOCTL_SYNC, &mut sync)?;
+        }
+        Ok(mapping)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if let Some(dmabuf) = &self.dmabuf {
+            let mut sync = DMA_BUF_SYNC_READ | DMA_BUF_SYNC_END;
+            let _ = ioctl(dmabuf.as_raw_fd(), DMA_BUF_IOCTL_SYNC, &mut sync);
+        }
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// Backend capturing the framebuffer scanned out by a CRTC of a DRM card
+pub struct DrmBackend {
+    card: File,
+    crtc_id: u32,
+    width: u32,
+    height: u32,
+    frame: Option<Vec<Bgr8>>,
+}
+
+impl DrmBackend {
+    /// Capture the active CRTC at index `capture_src` of the CRTCs of all
+    /// cards
+    pub fn new(capture_src: usize) -> Result<DrmBackend, InitError> {
+        let mut n_active = 0;
+        let mut last_err = None;
+        for path in cards() {
+            match DrmBackend::open(&path, capture_src - n_active) {
+                Ok(backend) => return Ok(backend),
+                Err(InitError::NotFound(_)) => {
+                    n_active += DrmBackend::active_crtcs(&path).map_or(0, |crtcs| crtcs.len())
+                }
+                Err(e) => last_err = Some(e),
+            }
+            if n_active > capture_src {
+                break;
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            InitError::NotFound(format!("No active DRM CRTC at index {}", capture_src))
+        }))
+    }
+
+    /// Capture the active CRTC at index `crtc` of a card, e.g.
+    /// `/dev/dri/card0`
+    pub fn open<P: AsRef<Path>>(card: P, crtc: usize) -> Result<DrmBackend, InitError> {
+        let path = card.as_ref();
+        let crtcs = DrmBackend::active_crtcs(path)?;
+        let crtc = crtcs.get(crtc).ok_or_else(|| {
+            InitError::NotFound(format!(
+                "No active CRTC at index {} of {}",
+                crtc,
+                path.display()
+            ))
+        })?;
+        let card = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| platform_error(format!("Failed to open {}", path.display()), e))?;
+        Ok(DrmBackend {
+            card,
+            crtc_id: crtc.crtc_id,
+            width: crtc.mode.hdisplay as u32,
+            height: crtc.mode.vdisplay as u32,
+            frame: None,
+        })
+    }
+
+    /// The active CRTCs of a card
+    fn active_crtcs(path: &Path) -> Result<Vec<DrmModeCrtc>, InitError> {
+        let card = File::open(path).map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => {
+                InitError::PermissionDenied(format!("Failed to open {}: {}", path.display(), e))
+            }
+            _ => platform_error(format!("Failed to open {}", path.display()), e).into(),
+        })?;
+        let ids = crtcs(&card).map_err(|e| {
+            InitError::Unsupported(format!("{} is not a KMS device: {}", path.display(), e))
+        })?;
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| get_crtc(&card, id).ok())
+            .filter(is_active)
+            .collect())
+    }
+}
+
+impl Backend for DrmBackend {
+    fn name(&self) -> &'static str {
+        "DRM/KMS"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_deref()
+            .map(|frame| (frame, self.width, self.height))
+    }
+
+    /// Read the framebuffer currently scanned out by the CRTC. Doesn't wait
+    /// for new frames.
+    fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+        let crtc = get_crtc(&self.card, self.crtc_id)
+            .map_err(|e| platform_error("Failed to get the CRTC", e))?;
+        if !is_active(&crtc) {
+            return Err(CaptureError::AccessLost);
+        }
+        let fb = Framebuffer::get(&self.card, crtc.fb_id)
+            .map_err(|e| platform_error("Failed to get the framebuffer", e))?;
+        if fb.handle == 0 {
+            // Handles are only given to root or with `CAP_SYS_ADMIN`
+            return Err(CaptureError::Fail(
+                "No permission to read the framebuffer. Requires CAP_SYS_ADMIN.".to_string(),
+            ));
+        }
+        let bgr = match fb.pixel_format {
+            DRM_FORMAT_XRGB8888 | DRM_FORMAT_ARGB8888 => true,
+            DRM_FORMAT_XBGR8888 | DRM_FORMAT_ABGR8888 => false,
+            format => {
+                return Err(CaptureError::Fail(format!(
+                    "Unsupported framebuffer format {}",
+                    String::from_utf8_lossy(&format.to_le_bytes())
+                )))
+            }
+        };
+        if fb.modifier != DRM_FORMAT_MOD_LINEAR && fb.modifier != DRM_FORMAT_MOD_INVALID {
+            return Err(CaptureError::Fail(format!(
+                "Framebuffer with tiled layout {:#x} can't be read",
+                fb.modifier
+            )));
+        }
+        let mapping = fb
+            .map()
+            .map_err(|e| platform_error("Failed to map the framebuffer", e))?;
+        let (width, height) = (crtc.mode.hdisplay as u32, crtc.mode.vdisplay as u32);
+        let (x, y) = (crtc.x.min(fb.width), crtc.y.min(fb.height));
+        let (width, height) = (width.min(fb.width - x), height.min(fb.height - y));
+        let bytes = &mapping.bytes()[fb.offset as usize..];
+        let frame = self.frame.get_or_insert_with(Vec::new);
+        frame.clear();
+        frame.reserve(width as usize * height as usize);
+        for row in bytes
+            .chunks(fb.pitch as usize)
+            .skip(y as usize)
+            .take(height as usize)
+        {
+            let row = &row[4 * x as usize..4 * (x + width) as usize];
+            frame.extend(row.chunks_exact(4).map(|p| {
+                let (b, r) = if bgr { (p[0], p[2]) } else { (p[2], p[0]) };
+                Bgr8 {
+                    b,
+                    g: p[1],
+                    r,
+                    a: 255,
+                }
+            }));
+        }
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod drm_tests {
+    use super::*;
+
+    #[test]
+    fn test_ioctl_numbers() {
+        assert_eq!(DRM_IOCTL_GEM_CLOSE, 0x4008_6409);
+        assert_eq!(DRM_IOCTL_PRIME_HANDLE_TO_FD, 0xc00c_642d);
+        assert_eq!(DRM_IOCTL_MODE_GETRESOURCES, 0xc040_64a0);
+        assert_eq!(DRM_IOCTL_MODE_GETCRTC, 0xc068_64a1);
+        assert_eq!(DRM_IOCTL_MODE_GETFB, 0xc01c_64ad);
+        assert_eq!(DRM_IOCTL_MODE_MAP_DUMB, 0xc010_64b3);
+        assert_eq!(DRM_IOCTL_MODE_GETFB2, 0xc068_64ce);
+        assert_eq!(DMA_BUF_IOCTL_SYNC, 0x4008_6200);
+    }
+}
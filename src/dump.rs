@@ -0,0 +1,459 @@
+//! Lossless dumps of raw frames, compressed with zstd, for pixel-exact
+//! analysis of captures after the fact, without the losses of a video codec
+//!
+//! A dump, conventionally a `.craw` file, is a 16 byte file header followed
+//! by the frames, and an index of the frames written when the dump is
+//! finished. All fields are in little-endian byte order. The file header:
+//!
+//! | Offset | Type | Field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | 0      | u32  | Magic, `b"CRAW"`                                       |
+//! | 4      | u16  | Format version, currently 1                            |
+//! | 6      | u16  | Reserved, 0                                            |
+//! | 8      | u64  | Start of the dump in µs since the Unix epoch           |
+//!
+//! Each frame is a 40 byte header followed by a zstd frame of the pixels,
+//! as planes of all the blue, green, red, and alpha components in turn,
+//! which compress better than interleaved pixels:
+//!
+//! | Offset | Type | Field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | 0      | u32  | Magic, `b"CRFR"`                                       |
+//! | 4      | u32  | Frame width                                            |
+//! | 8      | u32  | Frame height                                           |
+//! | 12     | u32  | Reserved, 0                                            |
+//! | 16     | u64  | Sequence number                                        |
+//! | 24     | u64  | Capture time in µs since the start of the dump         |
+//! | 32     | u64  | Length of the compressed pixels in bytes               |
+//! | 40     |      | Compressed pixels                                      |
+//!
+//! The index is the offset of each frame in the file as a u64, followed by
+//! the offset of the index as a u64, the number of frames as a u32, and the
+//! magic `b"CRIX"`. Dumps that were not finished, e.g. because the process
+//! crashed, have no index, and are read up to the last complete frame.
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::dump::{DumpReader, DumpWriter};
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let mut dump = DumpWriter::create("capture.craw").unwrap();
+//! for _ in 0..100 {
+//!     dump.write(&capturer.capture_frame_meta().unwrap()).unwrap();
+//! }
+//! dump.finish().unwrap();
+//!
+//! let mut dump = DumpReader::open("capture.craw").unwrap();
+//! let last = dump.read(dump.len() - 1).unwrap();
+//! for frame in dump {
+//!     let frame = frame.unwrap();
+//! }
+//! ```
+
+use crate::{Bgr8, Frame};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CRAW");
+const FRAME_MAGIC: u32 = u32::from_le_bytes(*b"CRFR");
+const INDEX_MAGIC: u32 = u32::from_le_bytes(*b"CRIX");
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 16;
+const FRAME_HEADER_SIZE: usize = 40;
+const TRAILER_SIZE: usize = 16;
+
+/// zstd compression level of dumps by default. Fast enough to keep up with
+/// captures at high resolutions.
+pub const DEFAULT_LEVEL: i32 = 3;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Writer of frames to a dump
+pub struct DumpWriter<W: Write> {
+    /// `None` once finished
+    writer: Option<W>,
+    level: i32,
+    /// The monotonic time of the start of the dump
+    start: Instant,
+    /// Offset of the next frame
+    offset: u64,
+    index: Vec<u64>,
+    /// Planes of the frame being written, reused between frames
+    planes: Vec<u8>,
+}
+
+impl DumpWriter<BufWriter<File>> {
+    /// Create a dump file, replacing the file if it exists
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        DumpWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> DumpWriter<W> {
+    /// Start a dump, writing the file header
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        let mut buf = [0; HEADER_SIZE];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        buf[8..16].copy_from_slice(&micros.to_le_bytes());
+        writer.write_all(&buf)?;
+        Ok(DumpWriter {
+            writer: Some(writer),
+            level: DEFAULT_LEVEL,
+            start: Instant::now(),
+            offset: HEADER_SIZE as u64,
+            index: Vec::new(),
+            planes: Vec::new(),
+        })
+    }
+
+    /// Set the zstd compression level of the frames written after, from 1
+    /// to 22. Higher levels compress better, but slower.
+    pub fn level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Number of frames written
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Write a frame
+    ///
+    /// Frames without a timestamp are timestamped as written.
+    pub fn write(&mut self, frame: &Frame) -> io::Result<()> {
+        let writer = self.writer.as_mut().expect("Dump is finished");
+        let pixels = frame.pixels();
+        let n = pixels.len();
+        self.planes.resize(4 * n, 0);
+        let (b, rest) = self.planes.split_at_mut(n);
+        let (g, rest) = rest.split_at_mut(n);
+        let (r, a) = rest.split_at_mut(n);
+        for (i, p) in pixels.iter().enumerate() {
+            b[i] = p.b;
+            g[i] = p.g;
+            r[i] = p.r;
+            a[i] = p.a;
+        }
+        let compressed = zstd::bulk::compress(&self.planes, self.level)?;
+        let timestamp = frame.timestamp().unwrap_or_else(Instant::now);
+        let micros = timestamp.saturating_duration_since(self.start).as_micros() as u64;
+        let mut buf = [0; FRAME_HEADER_SIZE];
+        buf[0..4].copy_from_slice(&FRAME_MAGIC.to_le_bytes());
+        buf[4..8].copy_from_slice(&frame.width().to_le_bytes());
+        buf[8..12].copy_from_slice(&frame.height().to_le_bytes());
+        buf[16..24].copy_from_slice(&frame.sequence().to_le_bytes());
+        buf[24..32].copy_from_slice(&micros.to_le_bytes());
+        buf[32..40].copy_from_slice(&(compressed.len() as u64).to_le_bytes());
+        writer.write_all(&buf)?;
+        writer.write_all(&compressed)?;
+        self.index.push(self.offset);
+        self.offset += (FRAME_HEADER_SIZE + compressed.len()) as u64;
+        Ok(())
+    }
+
+    /// Write the index of the frames, and flush the dump
+    ///
+    /// Done on drop too, ignoring errors.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_index()
+            .map(|()| self.writer.take().expect("Dump is finished"))
+    }
+
+    fn write_index(&mut self) -> io::Result<()> {
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        let count: u32 = self
+            .index
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too many frames"))?;
+        for offset in &self.index {
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        let mut trailer = [0; TRAILER_SIZE];
+        trailer[0..8].copy_from_slice(&self.offset.to_le_bytes());
+        trailer[8..12].copy_from_slice(&count.to_le_bytes());
+        trailer[12..16].copy_from_slice(&INDEX_MAGIC.to_le_bytes());
+        writer.write_all(&trailer)?;
+        writer.flush()
+    }
+}
+
+impl<W: Write> Drop for DumpWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.write_index();
+    }
+}
+
+/// Reader of the frames of a dump, as an iterator of the frames from the
+/// current position, or by their index with `Self::read`
+///
+/// The timestamps of the frames are monotonic times as far in the past as
+/// they were captured, so the times between frames are as captured.
+pub struct DumpReader<R: Read + Seek> {
+    reader: R,
+    /// Wall clock time of the start of the dump
+    start: SystemTime,
+    /// The monotonic time of `start`
+    base: Instant,
+    index: Vec<u64>,
+    /// Index of the next frame of the iterator
+    position: usize,
+}
+
+impl DumpReader<BufReader<File>> {
+    /// Open a dump file
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        DumpReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> DumpReader<R> {
+    /// Read the file header and the index of a dump, or find the frames if
+    /// the dump has no index
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut buf = [0; HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut buf)?;
+        if u32::from_le_bytes(buf[0..4].try_into().unwrap()) != MAGIC {
+            return Err(invalid("Not a raw frame dump"));
+        }
+        if u16::from_le_bytes([buf[4], buf[5]]) != VERSION {
+            return Err(invalid("Unsupported dump version"));
+        }
+        let micros = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let start = UNIX_EPOCH + Duration::from_micros(micros);
+        let ago = SystemTime::now().duration_since(start).unwrap_or_default();
+        let now = Instant::now();
+        let index = match read_index(&mut reader)? {
+            Some(index) => index,
+            None => scan(&mut reader)?,
+        };
+        Ok(DumpReader {
+            reader,
+            start,
+            base: now.checked_sub(ago).unwrap_or(now),
+            index,
+            position: 0,
+        })
+    }
+
+    /// Number of frames in the dump
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Wall clock time at which the dump was started
+    pub fn start_time(&self) -> SystemTime {
+        self.start
+    }
+
+    /// Continue iterating from the frame at `index`
+    pub fn seek(&mut self, index: usize) {
+        self.position = index.min(self.index.len());
+    }
+
+    /// Read the frame at `index`, without moving the position of the
+    /// iterator
+    pub fn read(&mut self, index: usize) -> io::Result<Frame> {
+        let offset = *self
+            .index
+            .get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No such frame"))?;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let header = read_frame_header(&mut self.reader)?;
+        let n = (header.width as usize)
+            .checked_mul(header.height as usize)
+            .filter(|n| n.checked_mul(4).is_some())
+            .ok_or_else(|| invalid("Frame is too large"))?;
+        // The size is untrusted, so the planes grow with the decompressed data
+        // rather than being allocated up front
+        let mut planes = Vec::new();
+        zstd::stream::Decoder::new((&mut self.reader).take(header.len))?
+            .take(4 * n as u64 + 1)
+            .read_to_end(&mut planes)?;
+        if planes.len() != 4 * n {
+            return Err(invalid("Pixels do not match the frame size"));
+        }
+        let (b, rest) = planes.split_at(n);
+        let (g, rest) = rest.split_at(n);
+        let (r, a) = rest.split_at(n);
+        let pixels = (0..n)
+            .map(|i| Bgr8 {
+                b: b[i],
+                g: g[i],
+                r: r[i],
+                a: a[i],
+            })
+            .collect();
+        Ok(Frame::new(pixels, header.width, header.height)
+            .with_sequence(header.sequence)
+            .with_timestamp(self.base + header.elapsed))
+    }
+}
+
+impl<R: Read + Seek> Iterator for DumpReader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<io::Result<Frame>> {
+        if self.position >= self.index.len() {
+            return None;
+        }
+        self.position += 1;
+        Some(self.read(self.position - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.index.len() - self.position;
+        (n, Some(n))
+    }
+}
+
+/// Header of a frame in a dump
+struct FrameHeader {
+    width: u32,
+    height: u32,
+    sequence: u64,
+    /// Time since the start of the dump
+    elapsed: Duration,
+    /// Length of the compressed pixels
+    len: u64,
+}
+
+fn read_frame_header(reader: &mut impl Read) -> io::Result<FrameHeader> {
+    let mut buf = [0; FRAME_HEADER_SIZE];
+    reader.read_exact(&mut buf)?;
+    let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+    let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+    if u32_at(0) != FRAME_MAGIC {
+        return Err(invalid("Bad frame magic"));
+    }
+    Ok(FrameHeader {
+        width: u32_at(4),
+        height: u32_at(8),
+        sequence: u64_at(16),
+        elapsed: Duration::from_micros(u64_at(24)),
+        len: u64_at(32),
+    })
+}
+
+/// The index at the end of a finished dump, or `None` if there is no valid
+/// index
+fn read_index(reader: &mut (impl Read + Seek)) -> io::Result<Option<Vec<u64>>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    if len < (HEADER_SIZE + TRAILER_SIZE) as u64 {
+        return Ok(None);
+    }
+    let mut trailer = [0; TRAILER_SIZE];
+    reader.seek(SeekFrom::Start(len - TRAILER_SIZE as u64))?;
+    reader.read_exact(&mut trailer)?;
+    let offset = u64::from_le_bytes(trailer[0..8].try_into().unwrap());
+    let count = u32::from_le_bytes(trailer[8..12].try_into().unwrap()) as u64;
+    let magic = u32::from_le_bytes(trailer[12..16].try_into().unwrap());
+    if magic != INDEX_MAGIC || offset.checked_add(8 * count + TRAILER_SIZE as u64) != Some(len) {
+        return Ok(None);
+    }
+    let mut buf = vec![0; 8 * count as usize];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut buf)?;
+    Ok(Some(
+        buf.chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .collect(),
+    ))
+}
+
+/// The offsets of the frames of a dump without an index, up to the last
+/// complete frame
+fn scan(reader: &mut (impl Read + Seek)) -> io::Result<Vec<u64>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut offset = HEADER_SIZE as u64;
+    let mut index = Vec::new();
+    reader.seek(SeekFrom::Start(offset))?;
+    while let Ok(header) = read_frame_header(reader) {
+        let end = offset + FRAME_HEADER_SIZE as u64 + header.len;
+        if end > len {
+            break;
+        }
+        index.push(offset);
+        offset = end;
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod dump_tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn frame(n: u8) -> Frame {
+        let pixels = (0..6).map(|i| Bgr8::new(i, n, 255 - i)).collect();
+        Frame::new(pixels, 3, 2).with_sequence(n as u64)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut dump = DumpWriter::new(Cursor::new(Vec::new())).unwrap();
+        for n in 0..3 {
+            dump.write(&frame(n)).unwrap();
+        }
+        let bytes = dump.finish().unwrap().into_inner();
+        let mut dump = DumpReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(dump.len(), 3);
+        let last = dump.read(2).unwrap();
+        assert_eq!(last.pixels(), frame(2).pixels());
+        assert_eq!(last.sequence(), 2);
+        dump.seek(1);
+        let rest: Vec<Frame> = dump.map(Result::unwrap).collect();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[0].pixels(), frame(1).pixels());
+        assert!(rest[0].timestamp() <= rest[1].timestamp());
+    }
+
+    #[test]
+    fn test_unfinished() {
+        let mut bytes = Vec::new();
+        let mut dump = DumpWriter::new(&mut bytes).unwrap();
+        dump.write(&frame(0)).unwrap();
+        dump.write(&frame(1)).unwrap();
+        // Without the index, and with the last frame cut short
+        std::mem::forget(dump);
+        bytes.truncate(bytes.len() - 1);
+        let dump = DumpReader::new(Cursor::new(bytes)).unwrap();
+        let frames: Vec<Frame> = dump.map(Result::unwrap).collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].pixels(), frame(0).pixels());
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut dump = DumpWriter::new(Cursor::new(Vec::new())).unwrap();
+        dump.write(&frame(0)).unwrap();
+        let mut bytes = dump.finish().unwrap().into_inner();
+        // A header claiming a 16 GiB frame
+        let width = HEADER_SIZE + 4;
+        bytes[width..width + 4].copy_from_slice(&0x4000_0000u32.to_le_bytes());
+        let mut dump = DumpReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(dump.read(0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,879 @@
+//! Capture with DXGI desktop duplication, and enumeration of DXGI outputs as
+//! capture sources
+
+use crate::backend::Backend;
+use crate::builder::AdapterSelector;
+use crate::coords::Rotation;
+#[cfg(feature = "d3d11")]
+use crate::d3d11::D3d11Frame;
+use crate::hdr::{self, ToneMapping};
+use crate::{Bgr8, CaptureError, InitError, PixelFormat, Rect};
+use std::mem;
+use std::ptr;
+use std::time::{Duration, Instant};
+use winapi::shared::dxgi::{
+    CreateDXGIFactory1, IDXGIAdapter1, IDXGIDevice, IDXGIFactory1, IDXGIOutput, DXGI_ADAPTER_DESC1,
+    DXGI_OUTPUT_DESC,
+};
+use winapi::shared::windef::{HMONITOR, RECT};
+use winapi::shared::winerror::SUCCEEDED;
+use winapi::um::unknwnbase::IUnknown;
+use winapi::um::winnt::LARGE_INTEGER;
+use winapi::um::winuser::{GetMonitorInfoW, MONITORINFO, MONITORINFOF_PRIMARY};
+use winapi::Interface;
+use windows::core::Interface as _;
+use windows::Win32::Foundation::{E_ACCESSDENIED, E_POINTER};
+use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_UNKNOWN;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BOX,
+    D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_MAPPED_SUBRESOURCE,
+    D3D11_MAP_READ, D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+};
+#[cfg(feature = "d3d11")]
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_RESOURCE_MISC_SHARED,
+    D3D11_USAGE_DEFAULT,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R10G10B10A2_UNORM,
+    DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_MODE_ROTATION, DXGI_MODE_ROTATION_ROTATE180,
+    DXGI_MODE_ROTATION_ROTATE270, DXGI_MODE_ROTATION_ROTATE90,
+};
+#[cfg(feature = "d3d11")]
+use windows::Win32::Graphics::Dxgi::IDXGIResource;
+use windows::Win32::Graphics::Dxgi::{
+    IDXGIOutput1, IDXGIOutput5, IDXGIOutputDuplication, DXGI_ERROR_ACCESS_LOST,
+    DXGI_ERROR_UNSUPPORTED, DXGI_ERROR_WAIT_TIMEOUT, DXGI_OUTDUPL_FRAME_INFO,
+};
+use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
+
+/// Description of an output attached to the desktop
+#[derive(Clone, Copy)]
+pub(crate) struct Output {
+    /// Bounds in desktop coordinates
+    pub(crate) rect: RECT,
+    pub(crate) primary: bool,
+    /// GDI device name, e.g. `\\.\DISPLAY1`, NUL-terminated
+    pub(crate) device_name: [u16; 32],
+    pub(crate) monitor: HMONITOR,
+    /// LUID of the adapter the output is attached to
+    pub(crate) adapter_luid: u64,
+}
+
+/// Description of an adapter and its outputs attached to the desktop
+#[derive(Clone)]
+pub(crate) struct Adapter {
+    pub(crate) name: String,
+    pub(crate) luid: u64,
+    pub(crate) vendor_id: u32,
+    pub(crate) device_id: u32,
+    /// Version of the user mode driver
+    pub(crate) driver_version: Option<[u16; 4]>,
+    pub(crate) outputs: Vec<Output>,
+}
+
+unsafe fn release<T>(p: *mut T) {
+    (*(p as *mut IUnknown)).Release();
+}
+
+/// The adapters, with their outputs attached to the desktop
+pub(crate) fn adapters() -> Vec<Adapter> {
+    let mut adapters = Vec::new();
+    unsafe {
+        let mut factory: *mut IDXGIFactory1 = ptr::null_mut();
+        if !SUCCEEDED(CreateDXGIFactory1(
+            &IDXGIFactory1::uuidof(),
+            &mut factory as *mut _ as *mut _,
+        )) {
+            return adapters;
+        }
+        for i in 0.. {
+            let mut adapter: *mut IDXGIAdapter1 = ptr::null_mut();
+            if !SUCCEEDED((*factory).EnumAdapters1(i, &mut adapter)) {
+                break;
+            }
+            let mut desc: DXGI_ADAPTER_DESC1 = mem::zeroed();
+            (*adapter).GetDesc1(&mut desc);
+            let name_len = desc.Description.iter().position(|&c| c == 0);
+            let name = String::from_utf16_lossy(&desc.Description[..name_len.unwrap_or(128)]);
+            let luid = luid(desc.AdapterLuid.HighPart, desc.AdapterLuid.LowPart);
+            let mut umd: LARGE_INTEGER = mem::zeroed();
+            let driver_version =
+                if SUCCEEDED((*adapter).CheckInterfaceSupport(&IDXGIDevice::uuidof(), &mut umd)) {
+                    let v = *umd.QuadPart() as u64;
+                    Some([
+                        (v >> 48) as u16,
+                        (v >> 32) as u16,
+                        (v >> 16) as u16,
+                        v as u16,
+                    ])
+                } else {
+                    None
+                };
+            let mut outputs = Vec::new();
+            for j in 0.. {
+                let mut output: *mut IDXGIOutput = ptr::null_mut();
+                if !SUCCEEDED((*adapter).EnumOutputs(j, &mut output)) {
+                    break;
+                }
+                let mut desc: DXGI_OUTPUT_DESC = mem::zeroed();
+                (*output).GetDesc(&mut desc);
+                release(output);
+                if desc.AttachedToDesktop == 0 {
+                    continue;
+                }
+                let mut info: MONITORINFO = mem::zeroed();
+                info.cbSize = mem::size_of::<MONITORINFO>() as u32;
+                GetMonitorInfoW(desc.Monitor, &mut info);
+                outputs.push(Output {
+                    rect: desc.DesktopCoordinates,
+                    primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+                    device_name: desc.DeviceName,
+                    monitor: desc.Monitor,
+                    adapter_luid: luid,
+                });
+            }
+            release(adapter);
+            adapters.push(Adapter {
+                name,
+                luid,
+                vendor_id: desc.VendorId,
+                device_id: desc.DeviceId,
+                driver_version,
+                outputs,
+            });
+        }
+        release(factory);
+    }
+    adapters
+}
+
+/// An adapter LUID as one integer
+fn luid(high: i32, low: u32) -> u64 {
+    (high as u32 as u64) << 32 | low as u64
+}
+
+/// The output of a capture source index
+///
+/// Index 0 is the primary output. Other indices count the non-primary
+/// outputs of all adapters, in the order of the adapters.
+pub(crate) fn capture_source_output(index: usize) -> Option<Output> {
+    let mut outputs = adapters().into_iter().flat_map(|adapter| adapter.outputs);
+    if index == 0 {
+        outputs.find(|o| o.primary)
+    } else {
+        outputs.filter(|o| !o.primary).nth(index - 1)
+    }
+}
+
+/// The LUID of the adapter to duplicate the output of a capture source on
+fn adapter_luid(capture_src: usize, adapter: Option<AdapterSelector>) -> Result<u64, InitError> {
+    let output_adapter = |index| {
+        capture_source_output(index)
+            .map(|o| o.adapter_luid)
+            .ok_or_else(|| InitError::NotFound(format!("No capture source {}", index)))
+    };
+    match adapter {
+        None => output_adapter(capture_src),
+        Some(AdapterSelector::LuidOf(index)) => output_adapter(index),
+        Some(AdapterSelector::Primary) => output_adapter(0),
+        Some(AdapterSelector::Index(i)) => adapters()
+            .get(i)
+            .map(|a| a.luid)
+            .ok_or_else(|| InitError::NotFound(format!("No adapter {}", i))),
+    }
+}
+
+/// Convert a timeout to milliseconds for DXGI
+pub(crate) fn timeout_ms(timeout: Duration) -> Result<u32, &'static str> {
+    (timeout.as_secs() as u32)
+        .checked_mul(1000)
+        .and_then(|ms| ms.checked_add(timeout.subsec_millis()))
+        .ok_or(
+            "Failed to convert the given duration to a legal u32 millisecond value due to \
+                integer overflow.",
+        )
+}
+
+/// Formats to duplicate outputs in when capturing HDR, in order of
+/// preference. The output picks the one nearest its own.
+const HDR_FORMATS: [DXGI_FORMAT; 3] = [
+    DXGI_FORMAT_R16G16B16A16_FLOAT,
+    DXGI_FORMAT_R10G10B10A2_UNORM,
+    DXGI_FORMAT_B8G8R8A8_UNORM,
+];
+
+/// Pixels of the duplicated frames, if in an HDR format
+#[derive(Default)]
+struct HdrFrame {
+    format: Option<PixelFormat>,
+    rgba16f: Vec<[u8; 8]>,
+    rgb10a2: Vec<[u8; 4]>,
+}
+
+impl HdrFrame {
+    fn bytes(&self) -> Option<(&[u8], PixelFormat)> {
+        match self.format? {
+            PixelFormat::Rgba16F => Some((self.rgba16f.as_flattened(), PixelFormat::Rgba16F)),
+            format => Some((self.rgb10a2.as_flattened(), format)),
+        }
+    }
+}
+
+/// Duplication of an output, with the device it is duplicated on
+struct Duplication {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    duplication: IDXGIOutputDuplication,
+    /// Bounds of the output in desktop coordinates
+    rect: RECT,
+    rotation: DXGI_MODE_ROTATION,
+    /// CPU readable copy of the duplicated frames, reused across captures
+    staging: Option<ID3D11Texture2D>,
+    /// Shareable GPU copy of the duplicated frames, reused across captures
+    #[cfg(feature = "d3d11")]
+    shared: Option<ID3D11Texture2D>,
+}
+
+impl Duplication {
+    /// Duplicate the output of a capture source index, in its HDR format if
+    /// `hdr`, on the adapter it's attached to unless another is selected
+    fn new(
+        capture_src: usize,
+        hdr: bool,
+        adapter: Option<AdapterSelector>,
+    ) -> Result<Duplication, InitError> {
+        let target = capture_source_output(capture_src)
+            .ok_or_else(|| InitError::NotFound(format!("No capture source {}", capture_src)))?;
+        let selected = adapter_luid(capture_src, adapter)?;
+        let factory: windows::Win32::Graphics::Dxgi::IDXGIFactory1 =
+            unsafe { windows::Win32::Graphics::Dxgi::CreateDXGIFactory1()? };
+        let mut device_adapter = None;
+        let mut found = None;
+        for i in 0.. {
+            let adapter = match unsafe { factory.EnumAdapters1(i) } {
+                Ok(adapter) => adapter,
+                Err(_) => break,
+            };
+            let desc = unsafe { adapter.GetDesc1()? };
+            if luid(desc.AdapterLuid.HighPart, desc.AdapterLuid.LowPart) == selected {
+                device_adapter = Some(adapter.clone());
+            }
+            for j in 0.. {
+                let output = match unsafe { adapter.EnumOutputs(j) } {
+                    Ok(output) => output,
+                    Err(_) => break,
+                };
+                let desc = unsafe { output.GetDesc()? };
+                if found.is_none() && desc.DeviceName == target.device_name {
+                    found = Some((output, desc));
+                }
+            }
+        }
+        let (adapter, (output, desc)) = match (device_adapter, found) {
+            (Some(adapter), Some(found)) => (adapter, found),
+            (None, _) => return Err(InitError::NotFound("No selected adapter".to_string())),
+            (_, None) => {
+                return Err(InitError::NotFound(format!(
+                    "No output of capture source {}",
+                    capture_src
+                )))
+            }
+        };
+        let mut device = None;
+        let mut context = None;
+        unsafe {
+            D3D11CreateDevice(
+                &adapter,
+                D3D_DRIVER_TYPE_UNKNOWN,
+                None,
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                None,
+                D3D11_SDK_VERSION,
+                Some(&mut device),
+                None,
+                Some(&mut context),
+            )?;
+        }
+        let (device, context): (ID3D11Device, ID3D11DeviceContext) =
+            (device.unwrap(), context.unwrap());
+        let duplication = if hdr {
+            // Fails before Windows 10 1703, and in processes that are not
+            // per-monitor DPI aware
+            unsafe {
+                output
+                    .cast::<IDXGIOutput5>()
+                    .map_err(|_| {
+                        InitError::Unsupported("HDR capture requires Windows 10 1703".to_string())
+                    })?
+                    .DuplicateOutput1(&device, 0, &HDR_FORMATS)
+            }
+        } else {
+            unsafe { output.cast::<IDXGIOutput1>()?.DuplicateOutput(&device) }
+        };
+        let duplication = duplication.map_err(|e| {
+            if e.code() == DXGI_ERROR_UNSUPPORTED && selected != target.adapter_luid {
+                InitError::Unsupported(format!(
+                    "The output of capture source {} is not attached to the selected adapter",
+                    capture_src
+                ))
+            } else {
+                e.into()
+            }
+        })?;
+        Ok(Duplication {
+            device,
+            context,
+            duplication,
+            rect: RECT {
+                left: desc.DesktopCoordinates.left,
+                top: desc.DesktopCoordinates.top,
+                right: desc.DesktopCoordinates.right,
+                bottom: desc.DesktopCoordinates.bottom,
+            },
+            rotation: desc.Rotation,
+            staging: None,
+            #[cfg(feature = "d3d11")]
+            shared: None,
+        })
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (
+            (self.rect.right - self.rect.left) as u32,
+            (self.rect.bottom - self.rect.top) as u32,
+        )
+    }
+
+    /// Whether the output is rotated by 90 or 270 degrees, so that the
+    /// duplicated frames are of the transposed size
+    fn transposed(&self) -> bool {
+        self.rotation == DXGI_MODE_ROTATION_ROTATE90
+            || self.rotation == DXGI_MODE_ROTATION_ROTATE270
+    }
+
+    /// Width and height of the duplicated frames, rotated if `upright`
+    fn frame_size(&self, upright: bool) -> (u32, u32) {
+        let (width, height) = self.size();
+        if self.transposed() && !upright {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// The box of the duplicated frames, as scanned out, holding `region` of
+    /// the frames, which is upright if `upright`
+    fn texture_box(&self, region: Rect, upright: bool) -> D3D11_BOX {
+        let (width, height) = self.size();
+        let Rect { x, y, .. } = region;
+        let (w, h) = (region.width, region.height);
+        // (left, top, width, height) of the box
+        let (left, top, bw, bh) = if !upright {
+            (x, y, w, h)
+        } else if self.rotation == DXGI_MODE_ROTATION_ROTATE90 {
+            (y, width - x - w, h, w)
+        } else if self.rotation == DXGI_MODE_ROTATION_ROTATE180 {
+            (width - x - w, height - y - h, w, h)
+        } else if self.rotation == DXGI_MODE_ROTATION_ROTATE270 {
+            (height - y - h, x, h, w)
+        } else {
+            (x, y, w, h)
+        };
+        D3D11_BOX {
+            left,
+            top,
+            front: 0,
+            right: left + bw,
+            bottom: top + bh,
+            back: 1,
+        }
+    }
+
+    /// The staging texture for frames described by `desc`, recreated if the
+    /// size or format of the frames changed
+    fn staging(&mut self, desc: &D3D11_TEXTURE2D_DESC) -> windows::core::Result<ID3D11Texture2D> {
+        if let Some(staging) = &self.staging {
+            let mut staging_desc = D3D11_TEXTURE2D_DESC::default();
+            unsafe { staging.GetDesc(&mut staging_desc) };
+            if (staging_desc.Width, staging_desc.Height, staging_desc.Format)
+                == (desc.Width, desc.Height, desc.Format)
+            {
+                return Ok(staging.clone());
+            }
+        }
+        let staging_desc = D3D11_TEXTURE2D_DESC {
+            Usage: D3D11_USAGE_STAGING,
+            BindFlags: 0,
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+            MiscFlags: 0,
+            ..*desc
+        };
+        let mut staging = None;
+        unsafe {
+            self.device
+                .CreateTexture2D(&staging_desc, None, Some(&mut staging))?
+        };
+        self.staging = staging;
+        Ok(self.staging.clone().unwrap())
+    }
+
+    /// The shareable texture for frames described by `desc`, recreated if
+    /// the size or format of the frames changed
+    #[cfg(feature = "d3d11")]
+    fn shared(&mut self, desc: &D3D11_TEXTURE2D_DESC) -> windows::core::Result<ID3D11Texture2D> {
+        if let Some(shared) = &self.shared {
+            let mut shared_desc = D3D11_TEXTURE2D_DESC::default();
+            unsafe { shared.GetDesc(&mut shared_desc) };
+            if (shared_desc.Width, shared_desc.Height, shared_desc.Format)
+                == (desc.Width, desc.Height, desc.Format)
+            {
+                return Ok(shared.clone());
+            }
+        }
+        let shared_desc = D3D11_TEXTURE2D_DESC {
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32 | D3D11_BIND_RENDER_TARGET.0 as u32,
+            CPUAccessFlags: 0,
+            MiscFlags: D3D11_RESOURCE_MISC_SHARED.0 as u32,
+            ..*desc
+        };
+        let mut shared = None;
+        unsafe {
+            self.device
+                .CreateTexture2D(&shared_desc, None, Some(&mut shared))?
+        };
+        self.shared = shared;
+        Ok(self.shared.clone().unwrap())
+    }
+
+    /// Acquire the next frame and copy `region` of it to the staging
+    /// texture, which is of the size of the region
+    ///
+    /// `region` is upright if `upright`, and of the duplicated frames as
+    /// scanned out otherwise.
+    fn acquire(
+        &mut self,
+        timeout_ms: u32,
+        region: Rect,
+        upright: bool,
+    ) -> windows::core::Result<(ID3D11Texture2D, DXGI_OUTDUPL_FRAME_INFO)> {
+        self.acquire_to(timeout_ms, |duplication, texture| {
+            let src_box = duplication.texture_box(region, upright);
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            unsafe { texture.GetDesc(&mut desc) };
+            let staging = duplication.staging(&D3D11_TEXTURE2D_DESC {
+                Width: src_box.right - src_box.left,
+                Height: src_box.bottom - src_box.top,
+                ..desc
+            })?;
+            unsafe {
+                duplication.context.CopySubresourceRegion(
+                    &staging,
+                    0,
+                    0,
+                    0,
+                    0,
+                    texture,
+                    0,
+                    Some(&src_box),
+                )
+            };
+            Ok(staging)
+        })
+    }
+
+    /// Acquire the next frame and copy it with `copy`, which returns the
+    /// texture it was copied to
+    fn acquire_to(
+        &mut self,
+        timeout_ms: u32,
+        copy: impl FnOnce(&mut Duplication, &ID3D11Texture2D) -> windows::core::Result<ID3D11Texture2D>,
+    ) -> windows::core::Result<(ID3D11Texture2D, DXGI_OUTDUPL_FRAME_INFO)> {
+        let mut info = DXGI_OUTDUPL_FRAME_INFO::default();
+        let mut resource = None;
+        unsafe {
+            self.duplication
+                .AcquireNextFrame(timeout_ms, &mut info, &mut resource)?
+        };
+        let copied = resource
+            .ok_or_else(|| windows::core::Error::new(E_POINTER, "No desktop image"))
+            .and_then(|resource| resource.cast::<ID3D11Texture2D>())
+            .and_then(|texture| copy(self, &texture))
+            .map(|copy| (copy, info));
+        // Release the frame even if the copy failed, or no more frames are
+        // acquired
+        unsafe { self.duplication.ReleaseFrame()? };
+        copied
+    }
+
+    /// Copy the whole of a duplicated frame to the shareable texture
+    #[cfg(feature = "d3d11")]
+    fn copy_shared(&mut self, texture: &ID3D11Texture2D) -> windows::core::Result<ID3D11Texture2D> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+        let shared = self.shared(&desc)?;
+        unsafe { self.context.CopyResource(&shared, texture) };
+        Ok(shared)
+    }
+
+    /// Copy the staging texture, holding a region of the given size, into
+    /// `frame`, or into `hdr` if in an HDR format, undoing the rotation of
+    /// the output if `upright`
+    fn read(
+        &self,
+        staging: &ID3D11Texture2D,
+        (width, height): (u32, u32),
+        upright: bool,
+        frame: &mut Vec<Bgr8>,
+        hdr: &mut HdrFrame,
+    ) -> windows::core::Result<()> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { staging.GetDesc(&mut desc) };
+        let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+        unsafe {
+            self.context
+                .Map(staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?
+        };
+        hdr.format = None;
+        let size = (width, height);
+        // Safe as the pixels are of the format of the texture, and `Bgr8` has
+        // the same layout as B8G8R8A8
+        unsafe {
+            if desc.Format == DXGI_FORMAT_R16G16B16A16_FLOAT {
+                self.copy_pixels(&mapped, size, upright, &mut hdr.rgba16f);
+                hdr.format = Some(PixelFormat::Rgba16F);
+            } else if desc.Format == DXGI_FORMAT_R10G10B10A2_UNORM {
+                self.copy_pixels(&mapped, size, upright, &mut hdr.rgb10a2);
+                hdr.format = Some(PixelFormat::Rgb10A2);
+            } else {
+                self.copy_pixels(&mapped, size, upright, frame);
+            }
+        }
+        unsafe { self.context.Unmap(staging, 0) };
+        Ok(())
+    }
+
+    /// Copy a mapped region of the given size of `T` pixels into `frame`,
+    /// undoing the rotation of the output if `upright`
+    unsafe fn copy_pixels<T: Copy>(
+        &self,
+        mapped: &D3D11_MAPPED_SUBRESOURCE,
+        (width, height): (u32, u32),
+        upright: bool,
+        frame: &mut Vec<T>,
+    ) {
+        let pitch = mapped.RowPitch as usize / mem::size_of::<T>();
+        let (w, h) = (width as usize, height as usize);
+        frame.clear();
+        if w == 0 || h == 0 {
+            return;
+        }
+        // The region is transposed in the texture if the output is
+        let (scan_lines, scan_len) = if upright && self.transposed() {
+            (w, h)
+        } else {
+            (h, w)
+        };
+        let src = std::slice::from_raw_parts(
+            mapped.pData as *const T,
+            pitch * (scan_lines - 1) + scan_len,
+        );
+        frame.reserve(w * h);
+        for y in 0..h {
+            if !upright {
+                frame.extend_from_slice(&src[y * pitch..y * pitch + w]);
+            } else if self.rotation == DXGI_MODE_ROTATION_ROTATE90 {
+                frame.extend((0..w).map(|x| src[(w - 1 - x) * pitch + y]));
+            } else if self.rotation == DXGI_MODE_ROTATION_ROTATE180 {
+                frame.extend((0..w).map(|x| src[(h - 1 - y) * pitch + w - 1 - x]));
+            } else if self.rotation == DXGI_MODE_ROTATION_ROTATE270 {
+                frame.extend((0..w).map(|x| src[x * pitch + h - 1 - y]));
+            } else {
+                frame.extend_from_slice(&src[y * pitch..y * pitch + w]);
+            }
+        }
+    }
+}
+
+/// The monotonic time of a past performance counter value, e.g. the time a
+/// frame was presented
+fn qpc_instant(ticks: i64) -> Option<Instant> {
+    let (mut now, mut frequency) = (0, 0);
+    unsafe {
+        QueryPerformanceCounter(&mut now).ok()?;
+        QueryPerformanceFrequency(&mut frequency).ok()?;
+    }
+    let ago = now.saturating_sub(ticks).max(0) as f64 / frequency as f64;
+    Instant::now().checked_sub(Duration::from_secs_f64(ago))
+}
+
+/// Backend capturing an output with DXGI desktop duplication
+///
+/// Frames are copied through a staging texture into a buffer, both of which
+/// are reused across captures, so capturing does not allocate once the size
+/// of the frames is settled. A failed capture leaves the buffer with the
+/// previous frame, so it's not double buffered, which would delay frames by
+/// one capture.
+pub(crate) struct DxgiBackend {
+    capture_src: usize,
+    /// Adapter to duplicate outputs on, if not the one of the output
+    adapter: Option<AdapterSelector>,
+    /// `None` after access to the output was lost, until it is reacquired
+    duplication: Option<Duplication>,
+    /// The region of the output to copy out of the duplicated frames
+    region: Option<Rect>,
+    frame: Option<Vec<Bgr8>>,
+    width: u32,
+    height: u32,
+    /// Whether to undo the rotation of the output
+    upright: bool,
+    /// How to tone map frames, if duplicated in the HDR format of the output
+    tone_mapping: Option<ToneMapping>,
+    hdr: HdrFrame,
+    /// Whether the last frame was presented anew, rather than only the
+    /// cursor being updated
+    presented: bool,
+    /// When the last presented frame was presented
+    present_time: Option<Instant>,
+}
+
+impl DxgiBackend {
+    pub(crate) fn new(
+        capture_src: usize,
+        timeout: Duration,
+        adapter: Option<AdapterSelector>,
+    ) -> Result<DxgiBackend, InitError> {
+        timeout_ms(timeout).map_err(|e| InitError::InvalidArgument(e.to_string()))?;
+        let duplication = Duplication::new(capture_src, false, adapter)?;
+        Ok(DxgiBackend {
+            capture_src,
+            adapter,
+            duplication: Some(duplication),
+            region: None,
+            frame: None,
+            width: 0,
+            height: 0,
+            upright: true,
+            tone_mapping: None,
+            hdr: HdrFrame::default(),
+            presented: false,
+            present_time: None,
+        })
+    }
+}
+
+impl DxgiBackend {
+    fn capture_error(&mut self, e: windows::core::Error) -> CaptureError {
+        match e.code() {
+            DXGI_ERROR_WAIT_TIMEOUT => CaptureError::Timeout,
+            E_ACCESSDENIED => CaptureError::AccessDenied,
+            DXGI_ERROR_ACCESS_LOST => {
+                self.duplication = None;
+                CaptureError::AccessLost
+            }
+            _ => CaptureError::Platform(e.into()),
+        }
+    }
+
+    /// Capture a frame into the shareable texture
+    #[cfg(feature = "d3d11")]
+    fn capture_shared(&mut self, timeout: Duration) -> Result<D3d11Frame, CaptureError> {
+        let ms = timeout_ms(timeout).map_err(|e| CaptureError::Fail(e.to_string()))?;
+        if self.duplication.is_none() {
+            self.reacquire()?;
+        }
+        let duplication = self.duplication.as_mut().unwrap();
+        let (texture, info) = match duplication.acquire_to(ms, Duplication::copy_shared) {
+            Ok(acquired) => acquired,
+            Err(e) => return Err(self.capture_error(e)),
+        };
+        let duplication = self.duplication.as_ref().unwrap();
+        // Submit the copy, so it's done before other devices read the texture
+        unsafe { duplication.context.Flush() };
+        let shared_handle = unsafe { texture.cast::<IDXGIResource>()?.GetSharedHandle()? };
+        let rotation = match duplication.rotation {
+            DXGI_MODE_ROTATION_ROTATE90 => 90,
+            DXGI_MODE_ROTATION_ROTATE180 => 180,
+            DXGI_MODE_ROTATION_ROTATE270 => 270,
+            _ => 0,
+        };
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+        Ok(D3d11Frame {
+            texture,
+            device: duplication.device.clone(),
+            shared_handle,
+            width: desc.Width,
+            height: desc.Height,
+            rotation,
+            last_present_time: info.LastPresentTime,
+            accumulated_frames: info.AccumulatedFrames,
+        })
+    }
+}
+
+impl Backend for DxgiBackend {
+    fn name(&self) -> &'static str {
+        "DXGI desktop duplication"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        match (self.region, &self.duplication) {
+            (Some(region), _) => (region.width, region.height),
+            (None, Some(duplication)) => duplication.frame_size(self.upright),
+            (None, None) => (self.width, self.height),
+        }
+    }
+
+    fn position(&self) -> (i32, i32) {
+        self.duplication
+            .as_ref()
+            .map_or((0, 0), |d| (d.rect.left, d.rect.top))
+    }
+
+    fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+        let ms = timeout_ms(timeout).map_err(|e| CaptureError::Fail(e.to_string()))?;
+        if self.duplication.is_none() {
+            self.reacquire()?;
+        }
+        let upright = self.upright;
+        let duplication = self.duplication.as_mut().unwrap();
+        let (w, h) = duplication.frame_size(upright);
+        let region = crate::roi::clip(
+            self.region.unwrap_or(Rect {
+                x: 0,
+                y: 0,
+                width: w,
+                height: h,
+            }),
+            w,
+            h,
+        );
+        let frame = self.frame.get_or_insert_with(Vec::new);
+        let hdr = &mut self.hdr;
+        let presented = &mut self.presented;
+        let present_time = &mut self.present_time;
+        let size = (region.width, region.height);
+        let res = duplication
+            .acquire(ms, region, upright)
+            .and_then(|(staging, info)| {
+                *presented = info.AccumulatedFrames > 0;
+                if info.LastPresentTime != 0 {
+                    *present_time = qpc_instant(info.LastPresentTime);
+                }
+                duplication.read(&staging, size, upright, frame, hdr)
+            });
+        match res {
+            Ok(()) => {
+                if let Some((pixels, format)) = self.hdr.bytes() {
+                    let tone_mapping = self.tone_mapping.unwrap_or_default();
+                    hdr::tone_map(pixels, format, tone_mapping, frame);
+                }
+                self.width = region.width;
+                self.height = region.height;
+                Ok(())
+            }
+            Err(e) => Err(self.capture_error(e)),
+        }
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_deref()
+            .map(|frame| (frame, self.width, self.height))
+    }
+
+    fn present_time(&self) -> Option<Instant> {
+        self.present_time
+    }
+
+    /// Frames are acquired on updates of the cursor too, without any frames
+    /// accumulated
+    fn content_changed(&self) -> Option<bool> {
+        self.frame.as_ref().map(|_| self.presented)
+    }
+
+    /// Copies only the region out of the duplicated frames, on the GPU into a
+    /// staging texture of the size of the region
+    fn set_region(&mut self, region: Option<Rect>) -> bool {
+        self.region = region;
+        true
+    }
+
+    fn set_upright(&mut self, upright: bool) {
+        self.upright = upright;
+    }
+
+    /// Rotating 90 degrees clockwise for display means the duplicated frames
+    /// are rotated 270 degrees relative to the desktop
+    fn rotation(&self) -> Rotation {
+        match &self.duplication {
+            Some(d) if !self.upright => match d.rotation {
+                DXGI_MODE_ROTATION_ROTATE90 => Rotation::Rotate270,
+                DXGI_MODE_ROTATION_ROTATE180 => Rotation::Rotate180,
+                DXGI_MODE_ROTATION_ROTATE270 => Rotation::Rotate90,
+                _ => Rotation::Identity,
+            },
+            _ => Rotation::Identity,
+        }
+    }
+
+    /// Duplicates the output anew in its HDR format, or in B8G8R8A8
+    fn set_hdr(&mut self, tone_mapping: Option<ToneMapping>) -> Option<Result<(), InitError>> {
+        if tone_mapping.is_some() != self.tone_mapping.is_some() {
+            match Duplication::new(self.capture_src, tone_mapping.is_some(), self.adapter) {
+                Ok(duplication) => self.duplication = Some(duplication),
+                Err(e) => return Some(Err(e)),
+            }
+            self.hdr.format = None;
+        }
+        self.tone_mapping = tone_mapping;
+        Some(Ok(()))
+    }
+
+    fn hdr_frame(&self) -> Option<(&[u8], PixelFormat)> {
+        self.frame.as_ref().and(self.hdr.bytes())
+    }
+
+    #[cfg(feature = "d3d11")]
+    fn capture_d3d11_texture(
+        &mut self,
+        timeout: Duration,
+    ) -> Option<Result<D3d11Frame, CaptureError>> {
+        Some(self.capture_shared(timeout))
+    }
+
+    fn set_source(&mut self, capture_src: usize) -> Option<Result<(), InitError>> {
+        if capture_src != self.capture_src {
+            match Duplication::new(capture_src, self.tone_mapping.is_some(), self.adapter) {
+                Ok(duplication) => self.duplication = Some(duplication),
+                Err(e) => return Some(Err(e)),
+            }
+            self.capture_src = capture_src;
+            self.region = None;
+        }
+        Some(Ok(()))
+    }
+
+    /// Duplicates the output anew, e.g. after a mode change
+    fn pause(&mut self) {
+        self.duplication = None;
+    }
+
+    fn resume(&mut self) -> Result<(), CaptureError> {
+        match self.duplication {
+            Some(_) => Ok(()),
+            None => self.reacquire(),
+        }
+    }
+
+    fn reacquire(&mut self) -> Result<(), CaptureError> {
+        self.duplication = None;
+        self.duplication = Some(
+            Duplication::new(self.capture_src, self.tone_mapping.is_some(), self.adapter)
+                .map_err(|_| CaptureError::RefreshFailure)?,
+        );
+        Ok(())
+    }
+}
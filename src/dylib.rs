@@ -0,0 +1,79 @@
+//! Minimal runtime loading of dynamic libraries, for integrations with
+//! libraries that aren't available at build time.
+
+use std::ffi::CString;
+use std::io;
+
+/// A dynamically loaded library. Unloaded on drop.
+pub(crate) struct Library {
+    #[cfg(windows)]
+    handle: winapi::shared::minwindef::HMODULE,
+    #[cfg(not(windows))]
+    handle: *mut libc::c_void,
+}
+
+impl Library {
+    /// Load the library with the given name or path
+    #[cfg(windows)]
+    pub(crate) fn open<S: AsRef<std::ffi::OsStr>>(name: S) -> io::Result<Library> {
+        use std::os::windows::ffi::OsStrExt;
+
+        let name: Vec<u16> = name.as_ref().encode_wide().chain(Some(0)).collect();
+        let handle = unsafe { winapi::um::libloaderapi::LoadLibraryW(name.as_ptr()) };
+        if handle.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Library { handle })
+        }
+    }
+
+    /// Load the library with the given name or path
+    #[cfg(not(windows))]
+    pub(crate) fn open<S: AsRef<std::ffi::OsStr>>(name: S) -> io::Result<Library> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = CString::new(name.as_ref().as_bytes())?;
+        let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            let msg = unsafe { std::ffi::CStr::from_ptr(libc::dlerror()) };
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                msg.to_string_lossy().into_owned(),
+            ))
+        } else {
+            Ok(Library { handle })
+        }
+    }
+
+    /// Look up the function `name` in the library
+    ///
+    /// # Safety
+    ///
+    /// `F` must be a function pointer type matching the signature of the function.
+    pub(crate) unsafe fn symbol<F: Copy>(&self, name: &str) -> io::Result<F> {
+        let c_name = CString::new(name)?;
+        #[cfg(windows)]
+        let f = winapi::um::libloaderapi::GetProcAddress(self.handle, c_name.as_ptr()) as *mut ();
+        #[cfg(not(windows))]
+        let f = libc::dlsym(self.handle, c_name.as_ptr()) as *mut ();
+        if f.is_null() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Missing function `{}` in dynamic library", name),
+            ))
+        } else {
+            Ok(std::mem::transmute_copy(&f))
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            #[cfg(windows)]
+            winapi::um::libloaderapi::FreeLibrary(self.handle);
+            #[cfg(not(windows))]
+            libc::dlclose(self.handle);
+        }
+    }
+}
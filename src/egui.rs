@@ -0,0 +1,81 @@
+//! egui integration. `FrameTexture` uploads captured frames to an egui
+//! texture, reusing its allocations between frames, e.g. for capture previews.
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::egui::FrameTexture;
+//! # fn ui(ui: &mut egui::Ui, capturer: &mut Capturer, preview: &mut FrameTexture) {
+//! capturer.capture_store_frame().unwrap();
+//! let (w, h) = capturer.geometry();
+//! let frame = capturer.get_stored_frame().unwrap();
+//! let texture = preview.update(ui.ctx(), frame, w, h);
+//! ui.image((texture.id(), texture.size_vec2()));
+//! # }
+//! ```
+
+use crate::Bgr8;
+use egui::{Color32, ColorImage, Context, TextureHandle, TextureOptions};
+use std::sync::Arc;
+
+/// An egui texture that captured frames can be uploaded to
+pub struct FrameTexture {
+    name: String,
+    options: TextureOptions,
+    image: Arc<ColorImage>,
+    handle: Option<TextureHandle>,
+}
+
+impl FrameTexture {
+    /// Create a texture with the given debug name. Nothing is allocated until
+    /// the first frame is uploaded.
+    pub fn new(name: impl Into<String>) -> FrameTexture {
+        FrameTexture::with_options(name, TextureOptions::LINEAR)
+    }
+
+    /// Create a texture with the given debug name and sampling options
+    pub fn with_options(name: impl Into<String>, options: TextureOptions) -> FrameTexture {
+        FrameTexture {
+            name: name.into(),
+            options,
+            image: Arc::new(ColorImage::default()),
+            handle: None,
+        }
+    }
+
+    /// Upload a frame of the given size to the texture, and return the texture
+    pub fn update(
+        &mut self,
+        ctx: &Context,
+        frame: &[Bgr8],
+        width: u32,
+        height: u32,
+    ) -> &TextureHandle {
+        // Reuses the pixel buffer if egui has let go of the previous upload
+        let image = Arc::make_mut(&mut self.image);
+        to_color_image(frame, width, height, image);
+        match self.handle {
+            Some(ref mut handle) => handle.set(self.image.clone(), self.options),
+            None => {
+                self.handle = Some(ctx.load_texture(&self.name, self.image.clone(), self.options))
+            }
+        }
+        self.handle.as_ref().unwrap()
+    }
+
+    /// The texture, if a frame has been uploaded
+    pub fn texture(&self) -> Option<&TextureHandle> {
+        self.handle.as_ref()
+    }
+}
+
+/// Convert a frame of the given size to an egui `ColorImage`, reusing the
+/// allocation of `image`
+pub fn to_color_image(frame: &[Bgr8], width: u32, height: u32, image: &mut ColorImage) {
+    assert_eq!(frame.len(), width as usize * height as usize);
+    image.size = [width as usize, height as usize];
+    image.source_size = egui::vec2(width as f32, height as f32);
+    image.pixels.clear();
+    image
+        .pixels
+        .extend(frame.iter().map(|p| Color32::from_rgb(p.r, p.g, p.b)));
+}
@@ -0,0 +1,239 @@
+//! Errors of constructing capturers and of capturing
+
+use std::error::Error;
+use std::fmt;
+
+/// Status code of a failed call to a platform API
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PlatformCode {
+    /// A Windows `HRESULT`
+    Hresult(i32),
+    /// An X11 protocol error code, e.g. 3 for `BadWindow`
+    X11(u8),
+    /// A Core Graphics `CGError` on macOS
+    CgError(i32),
+    /// An OS error number, e.g. `errno`
+    Os(i32),
+}
+
+impl fmt::Display for PlatformCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlatformCode::Hresult(hr) => write!(f, "HRESULT {:#010x}", hr),
+            PlatformCode::X11(code) => write!(f, "X error {}", code),
+            PlatformCode::CgError(err) => write!(f, "CGError {}", err),
+            PlatformCode::Os(errno) => write!(f, "{}", std::io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+/// A failed call to a platform API, with its status code
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PlatformError {
+    /// What failed
+    pub message: String,
+    pub code: PlatformCode,
+}
+
+impl PlatformError {
+    pub fn new(message: impl Into<String>, code: PlatformCode) -> PlatformError {
+        PlatformError {
+            message: message.into(),
+            code,
+        }
+    }
+}
+
+impl fmt::Display for PlatformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl Error for PlatformError {}
+
+#[cfg(windows)]
+impl From<windows::core::Error> for PlatformError {
+    fn from(e: windows::core::Error) -> PlatformError {
+        PlatformError::new(e.message(), PlatformCode::Hresult(e.code().0))
+    }
+}
+
+/// Error of a capture
+#[derive(Clone, Debug)]
+pub enum CaptureError {
+    /// Could not duplicate output, access denied. Might be in protected fullscreen.
+    #[cfg(windows)]
+    AccessDenied,
+    /// Access to the duplicated output was lost. Likely, mode was changed e.g. window => full
+    AccessLost,
+    /// Error when trying to refresh outputs after some failure.
+    #[cfg(windows)]
+    RefreshFailure,
+    /// Aquisition of next frame timed out.
+    Timeout,
+    /// Capture is not permitted by the user or the system, e.g. the screen
+    /// recording permission was revoked on macOS. See
+    /// `crate::request_permission`.
+    PermissionDenied,
+    /// The captured frame has the same content as the previous one. Only
+    /// returned with `Capturer::set_skip_duplicates`.
+    NoNewFrame,
+    /// The capturer is paused with `Capturer::pause`
+    Paused,
+    /// A call to a platform API failed
+    Platform(PlatformError),
+    /// General/Unexpected failure
+    Fail(String),
+}
+
+impl CaptureError {
+    /// Name of the kind of the error, e.g. `access_lost`, for metrics and
+    /// traces
+    #[cfg(any(feature = "metrics", feature = "tracing"))]
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            #[cfg(windows)]
+            CaptureError::AccessDenied => "access_denied",
+            CaptureError::AccessLost => "access_lost",
+            #[cfg(windows)]
+            CaptureError::RefreshFailure => "refresh_failure",
+            CaptureError::Timeout => "timeout",
+            CaptureError::PermissionDenied => "permission_denied",
+            CaptureError::NoNewFrame => "no_new_frame",
+            CaptureError::Paused => "paused",
+            CaptureError::Platform(_) => "platform",
+            CaptureError::Fail(_) => "fail",
+        }
+    }
+
+    /// The error of a capture failing to construct its capturer, e.g. of a
+    /// one-shot capture
+    pub(crate) fn from_init(e: InitError) -> CaptureError {
+        match e {
+            InitError::PermissionDenied(_) => CaptureError::PermissionDenied,
+            InitError::Platform(e) => CaptureError::Platform(e),
+            e => CaptureError::Fail(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(windows)]
+            CaptureError::AccessDenied => write!(f, "Access to the capture source was denied"),
+            CaptureError::AccessLost => write!(f, "Access to the capture source was lost"),
+            #[cfg(windows)]
+            CaptureError::RefreshFailure => write!(f, "Failed to reacquire the capture source"),
+            CaptureError::Timeout => write!(f, "Timed out waiting for a frame"),
+            CaptureError::PermissionDenied => write!(f, "Capture is not permitted"),
+            CaptureError::NoNewFrame => write!(f, "No new frame was presented"),
+            CaptureError::Paused => write!(f, "Capture is paused"),
+            CaptureError::Platform(e) => e.fmt(f),
+            CaptureError::Fail(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl Error for CaptureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CaptureError::Platform(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PlatformError> for CaptureError {
+    fn from(e: PlatformError) -> CaptureError {
+        CaptureError::Platform(e)
+    }
+}
+
+#[cfg(windows)]
+impl From<windows::core::Error> for CaptureError {
+    fn from(e: windows::core::Error) -> CaptureError {
+        CaptureError::Platform(e.into())
+    }
+}
+
+/// Error of constructing or configuring a capturer
+#[derive(Clone, Debug)]
+pub enum InitError {
+    /// There is no such capture source, e.g. display or window
+    NotFound(String),
+    /// Not supported on this platform, or by the backend
+    Unsupported(String),
+    /// An argument is out of range, e.g. a timeout or a capture region
+    InvalidArgument(String),
+    /// Capture was not permitted by the user or the system
+    PermissionDenied(String),
+    /// A call to a platform API failed
+    Platform(PlatformError),
+    /// Other failure
+    Other(String),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InitError::NotFound(msg)
+            | InitError::Unsupported(msg)
+            | InitError::InvalidArgument(msg)
+            | InitError::PermissionDenied(msg)
+            | InitError::Other(msg) => f.write_str(msg),
+            InitError::Platform(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InitError::Platform(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PlatformError> for InitError {
+    fn from(e: PlatformError) -> InitError {
+        InitError::Platform(e)
+    }
+}
+
+#[cfg(windows)]
+impl From<windows::core::Error> for InitError {
+    fn from(e: windows::core::Error) -> InitError {
+        InitError::Platform(e.into())
+    }
+}
+
+impl From<String> for InitError {
+    fn from(msg: String) -> InitError {
+        InitError::Other(msg)
+    }
+}
+
+impl From<&str> for InitError {
+    fn from(msg: &str) -> InitError {
+        InitError::Other(msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_error() {
+        let err = CaptureError::from(PlatformError::new("XGetImage failed", PlatformCode::X11(3)));
+        assert_eq!(err.to_string(), "XGetImage failed (X error 3)");
+        let source = err.source().unwrap().downcast_ref::<PlatformError>();
+        assert_eq!(source.unwrap().code, PlatformCode::X11(3));
+        let err = InitError::from("No display");
+        assert!(err.source().is_none());
+        assert_eq!(err.to_string(), "No display");
+    }
+}
@@ -0,0 +1,150 @@
+//! Capture into memory managed by the caller, e.g. shared or GPU-pinned
+//! memory, or buffers of FFI hosts
+
+use crate::{pixel_bytes, Bgr8, CaptureError, Capturer};
+
+/// Provider of buffers to capture frames into
+pub trait FrameAllocator {
+    /// A buffer for a frame of the given size, and its stride, i.e. the
+    /// number of bytes between the starts of rows. `None` if no buffer can
+    /// be provided.
+    fn buffer(&mut self, width: u32, height: u32) -> Option<(&mut [u8], usize)>;
+}
+
+/// Copy a frame of 4 byte pixels of the given size into a buffer with
+/// `stride` bytes between the starts of rows
+///
+/// Fails without writing anything if the buffer is too small.
+fn copy_strided(
+    frame: &[Bgr8],
+    width: u32,
+    height: u32,
+    dst: &mut [u8],
+    stride: usize,
+) -> Result<(), CaptureError> {
+    let row_len = 4 * width as usize;
+    if stride < row_len {
+        return Err(CaptureError::Fail(format!(
+            "Stride of {} bytes is less than the row length of {} bytes",
+            stride, row_len
+        )));
+    }
+    let needed = if height == 0 {
+        0
+    } else {
+        stride * (height as usize - 1) + row_len
+    };
+    if dst.len() < needed {
+        return Err(CaptureError::Fail(format!(
+            "Buffer of {} bytes is too small for a {}x{} frame, which needs {} bytes",
+            dst.len(),
+            width,
+            height,
+            needed
+        )));
+    }
+    let src = pixel_bytes(frame);
+    if stride == row_len {
+        dst[..src.len()].copy_from_slice(src);
+    } else {
+        for (src_row, dst_row) in src.chunks_exact(row_len).zip(dst.chunks_mut(stride)) {
+            dst_row[..row_len].copy_from_slice(src_row);
+        }
+    }
+    Ok(())
+}
+
+impl Capturer {
+    /// Capture the screen into `dst`, and return the size of the frame
+    ///
+    /// `dst` is only resized when the size of the frame changed, so reusing
+    /// the same vector across captures avoids allocating for every frame.
+    pub fn capture_frame_into(&mut self, dst: &mut Vec<Bgr8>) -> Result<(u32, u32), CaptureError> {
+        self.capture_store_frame()?;
+        let (frame, width, height) = self.stored_frame_with_size().unwrap();
+        if dst.len() != frame.len() {
+            dst.resize(frame.len(), Bgr8::default());
+        }
+        dst.copy_from_slice(frame);
+        Ok((width, height))
+    }
+
+    /// Capture the screen into a buffer of tightly packed 4 byte pixels, and
+    /// return the size of the frame
+    ///
+    /// Fails without writing to the buffer if it is too small for the frame.
+    pub fn capture_frame_into_bytes(&mut self, dst: &mut [u8]) -> Result<(u32, u32), CaptureError> {
+        self.capture_store_frame()?;
+        let (frame, width, height) = self.stored_frame_with_size().unwrap();
+        copy_strided(frame, width, height, dst, 4 * width as usize)?;
+        Ok((width, height))
+    }
+
+    /// Capture the screen into a buffer, as 4 byte pixels with `stride` bytes
+    /// between the starts of rows, and return the size of the frame
+    ///
+    /// Fails without writing to the buffer if it is too small for the frame.
+    /// The frame is also stored in `self`, as by `Self::capture_store_frame`.
+    pub fn capture_frame_into_slice(
+        &mut self,
+        dst: &mut [u8],
+        stride: usize,
+    ) -> Result<(u32, u32), CaptureError> {
+        self.capture_store_frame()?;
+        let (width, height) = self.geometry();
+        copy_strided(self.get_stored_frame().unwrap(), width, height, dst, stride)?;
+        Ok((width, height))
+    }
+
+    /// Capture the screen into a raw buffer of `len` bytes. See
+    /// `Self::capture_frame_into_slice`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes of `len` bytes, and not be accessed
+    /// through other pointers during the call.
+    pub unsafe fn capture_frame_into_raw(
+        &mut self,
+        ptr: *mut u8,
+        len: usize,
+        stride: usize,
+    ) -> Result<(u32, u32), CaptureError> {
+        if ptr.is_null() {
+            return Err(CaptureError::Fail("Null buffer pointer".to_string()));
+        }
+        self.capture_frame_into_slice(std::slice::from_raw_parts_mut(ptr, len), stride)
+    }
+
+    /// Capture the screen into a buffer provided by `allocator` for the size
+    /// of the frame, and return the size of the frame
+    pub fn capture_frame_with_allocator<A: FrameAllocator + ?Sized>(
+        &mut self,
+        allocator: &mut A,
+    ) -> Result<(u32, u32), CaptureError> {
+        self.capture_store_frame()?;
+        let (width, height) = self.geometry();
+        let (dst, stride) = allocator.buffer(width, height).ok_or_else(|| {
+            CaptureError::Fail("Allocator provided no buffer for the frame".to_string())
+        })?;
+        copy_strided(self.get_stored_frame().unwrap(), width, height, dst, stride)?;
+        Ok((width, height))
+    }
+}
+
+#[cfg(test)]
+mod external_tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_strided() {
+        let frame: Vec<Bgr8> = (0..4).map(|i| Bgr8::new(i, 0, 0)).collect();
+        let mut dst = vec![0xff; 2 * 12 - 4];
+        copy_strided(&frame, 2, 2, &mut dst, 12).unwrap();
+        let reds: Vec<u8> = [0, 4, 12, 16].iter().map(|&i| dst[i + 2]).collect();
+        assert_eq!(reds, [0, 1, 2, 3]);
+        // Padding between rows is left as is
+        assert_eq!(dst[8..12], [0xff; 4]);
+        assert!(copy_strided(&frame, 2, 2, &mut dst[..19], 12).is_err());
+        assert!(copy_strided(&frame, 2, 2, &mut dst, 4).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Conversion of captured frames to the pixel formats of consumers, e.g.
+//! RGBA for `image`, `qoi` and wgpu textures
+
+use crate::convert::convert;
+use crate::{CaptureError, Capturer};
+use std::time::Instant;
+
+/// Layout of the bytes of a pixel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "net", derive(serde::Serialize, serde::Deserialize))]
+pub enum PixelFormat {
+    /// Blue, green, red, and alpha or padding. The native format of
+    /// captured frames.
+    #[default]
+    Bgra8,
+    /// Red, green, blue, and opaque alpha
+    Rgba8,
+    /// Red, green, and blue
+    Rgb8,
+    /// BT.709 weighted luminance, e.g. for OCR and computer vision
+    Gray8,
+    /// Linear scRGB red, green, blue, and alpha as little-endian half
+    /// precision floats, the format of HDR displays on Windows. See `hdr`.
+    Rgba16F,
+    /// Red, green, blue, and alpha of 10, 10, 10, and 2 bits, from the least
+    /// significant bits of a little-endian `u32`
+    Rgb10A2,
+    /// Red, green, and blue of 5, 6, and 5 bits, from the most significant
+    /// bits of a little-endian `u16`, e.g. for LCDs and LED matrices of
+    /// microcontrollers. Ordered dithered.
+    Rgb565,
+    /// Red, green, and blue of 3, 3, and 2 bits, from the most significant
+    /// bits of a byte. Ordered dithered.
+    Rgb332,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba16F => 8,
+            PixelFormat::Bgra8 | PixelFormat::Rgba8 | PixelFormat::Rgb10A2 => 4,
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Gray8 | PixelFormat::Rgb332 => 1,
+        }
+    }
+}
+
+impl Capturer {
+    /// Set the pixel format of frames captured with
+    /// `Self::capture_frame_converted`
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.pixel_format = format;
+    }
+
+    /// The pixel format of frames captured with
+    /// `Self::capture_frame_converted`
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    /// Capture screen and return the color components of the pixels in the
+    /// given format
+    ///
+    /// When captured in an HDR format with `Self::set_hdr`, frames in that
+    /// format are returned as captured, unless cropped or processed, e.g.
+    /// with the cursor drawn. Other frames are converted from 8 bits.
+    pub fn capture_frame_format(&mut self, format: PixelFormat) -> Result<Vec<u8>, CaptureError> {
+        self.capture_store_frame()?;
+        if let Some(pixels) = self.stored_hdr_frame(format) {
+            return Ok(pixels.to_vec());
+        }
+        let start = Instant::now();
+        let mut bytes = Vec::new();
+        let (frame, width, _) = self.stored_frame_with_size().unwrap();
+        self.in_pool(|| convert(frame, width, format, &mut bytes));
+        self.stats.convert_time += start.elapsed();
+        self.stats.conversions += 1;
+        #[cfg(feature = "tracing")]
+        crate::trace::converted(format, start.elapsed());
+        Ok(bytes)
+    }
+
+    /// Capture screen and return the color components of the pixels as
+    /// RGBA, 4 bytes per pixel
+    ///
+    /// Alpha is opaque, as it's only meaningful on Windows.
+    pub fn capture_frame_rgba(&mut self) -> Result<Vec<u8>, CaptureError> {
+        self.capture_frame_format(PixelFormat::Rgba8)
+    }
+
+    /// Capture screen and return the color components of the pixels as RGB,
+    /// 3 bytes per pixel
+    pub fn capture_frame_rgb(&mut self) -> Result<Vec<u8>, CaptureError> {
+        self.capture_frame_format(PixelFormat::Rgb8)
+    }
+
+    /// Capture screen and return the luminance of the pixels, 1 byte per
+    /// pixel
+    ///
+    /// The luminance is computed from the captured frame directly, without
+    /// an intermediate frame of another format.
+    pub fn capture_frame_gray(&mut self) -> Result<Vec<u8>, CaptureError> {
+        self.capture_frame_format(PixelFormat::Gray8)
+    }
+
+    /// Capture screen and return the pixels as dithered RGB565, 2 bytes per
+    /// pixel, see `PixelFormat::Rgb565`
+    ///
+    /// The pixels are quantized while converting the captured frame, without
+    /// an intermediate frame.
+    pub fn capture_frame_rgb565(&mut self) -> Result<Vec<u8>, CaptureError> {
+        self.capture_frame_format(PixelFormat::Rgb565)
+    }
+}
@@ -0,0 +1,330 @@
+//! Owned captured frames
+
+use crate::color::ColorSpace;
+use crate::coords::Rotation;
+use crate::cursor::FrameCursor;
+#[cfg(target_os = "linux")]
+use crate::dmabuf::DmaBuf;
+use crate::{pixel_bytes, pixel_bytes_mut, Bgr8, Rect, Visibility};
+use std::time::{Duration, Instant};
+
+/// A captured frame of a given size
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pixels: Vec<Bgr8>,
+    width: u32,
+    height: u32,
+    timestamp: Option<Instant>,
+    present_time: Option<Instant>,
+    sequence: u64,
+    dirty_rects: Option<Vec<Rect>>,
+    cursor: Option<FrameCursor>,
+    rotation: Rotation,
+    color_space: ColorSpace,
+    duplicate: bool,
+    latency: Option<Duration>,
+    mask: Option<Vec<Rect>>,
+    thumbnail: Option<Box<Frame>>,
+    visibility: Option<Visibility>,
+    #[cfg(target_os = "linux")]
+    dmabuf: Option<DmaBuf>,
+}
+
+impl Frame {
+    /// Construct a frame of the given size from its pixels, row by row
+    pub fn new(pixels: Vec<Bgr8>, width: u32, height: u32) -> Frame {
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        Frame {
+            pixels,
+            width,
+            height,
+            timestamp: None,
+            present_time: None,
+            sequence: 0,
+            dirty_rects: None,
+            cursor: None,
+            rotation: Rotation::Identity,
+            color_space: ColorSpace::Display,
+            duplicate: false,
+            latency: None,
+            mask: None,
+            thumbnail: None,
+            visibility: None,
+            #[cfg(target_os = "linux")]
+            dmabuf: None,
+        }
+    }
+
+    /// Set the time at which the frame was captured
+    pub fn with_timestamp(mut self, timestamp: Instant) -> Frame {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Monotonic time at which the frame was captured, if known. See
+    /// `Capturer::frame_timestamp`.
+    pub fn timestamp(&self) -> Option<Instant> {
+        self.timestamp
+    }
+
+    /// Set the time at which the frame was presented on the display
+    pub fn with_present_time(mut self, present_time: Instant) -> Frame {
+        self.present_time = Some(present_time);
+        self
+    }
+
+    /// Monotonic time at which the frame was presented on the display, if
+    /// reported by the backend, as with DXGI desktop duplication, X11 with
+    /// the Present extension, and the PipeWire streams of Wayland
+    ///
+    /// Unlike `Self::timestamp`, the time of the capture, this is the time
+    /// to synchronize the frame to, e.g. with audio.
+    pub fn present_time(&self) -> Option<Instant> {
+        self.present_time
+    }
+
+    /// Set the sequence number of the frame
+    pub fn with_sequence(mut self, sequence: u64) -> Frame {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Sequence number of the frame, or 0 if unknown. See
+    /// `Capturer::frame_sequence`.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Set the regions of the frame that changed since the previous frame
+    pub fn with_dirty_rects(mut self, dirty_rects: Vec<Rect>) -> Frame {
+        self.dirty_rects = Some(dirty_rects);
+        self
+    }
+
+    /// Regions of the frame that changed since the previous captured frame,
+    /// if known. See `Capturer::dirty_rects`.
+    pub fn dirty_rects(&self) -> Option<&[Rect]> {
+        self.dirty_rects.as_deref()
+    }
+
+    /// Set the cursor at the time the frame was captured
+    pub fn with_cursor(mut self, cursor: FrameCursor) -> Frame {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    /// The cursor at the time the frame was captured, if tracked. See
+    /// `Capturer::set_track_cursor`.
+    ///
+    /// For drawing the cursor differently than with
+    /// `Capturer::set_include_cursor`, e.g. only on demand.
+    pub fn cursor(&self) -> Option<FrameCursor> {
+        self.cursor
+    }
+
+    /// Set the clockwise rotation of the frame relative to the desktop
+    pub fn with_rotation(mut self, rotation: Rotation) -> Frame {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Clockwise rotation of the frame relative to the desktop. Only not
+    /// `Rotation::Identity` for frames of rotated displays captured with
+    /// `Capturer::set_upright(false)`.
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// Set the color space of the pixels of the frame
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Frame {
+        self.color_space = color_space;
+        self
+    }
+
+    /// The color space of the pixels: the raw values of the framebuffer
+    /// unless converted to sRGB with `Capturer::set_color_management`
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Set whether the frame has the same content as the previous frame
+    pub fn with_duplicate(mut self, duplicate: bool) -> Frame {
+        self.duplicate = duplicate;
+        self
+    }
+
+    /// Whether the backend reported that no new frame was presented since
+    /// the previous capture, e.g. with DXGI desktop duplication, so that
+    /// recorders can skip encoding it. See `Capturer::set_skip_duplicates`.
+    pub fn is_duplicate(&self) -> bool {
+        self.duplicate
+    }
+
+    /// Set the time the frame took to acquire and process
+    pub fn with_latency(mut self, latency: Duration) -> Frame {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Time from starting to acquire the frame to it being ready to return,
+    /// if captured by a `Capturer`. See `Capturer::last_latency`.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Set the rectangles of the frame that were captured
+    pub fn with_mask(mut self, mask: Vec<Rect>) -> Frame {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// The rectangles of the frame that were captured, if masked with
+    /// `Capturer::set_capture_mask`. The pixels outside of them are zero.
+    pub fn mask(&self) -> Option<&[Rect]> {
+        self.mask.as_deref()
+    }
+
+    /// Set the scaled down copy of the frame
+    pub fn with_thumbnail(mut self, thumbnail: Frame) -> Frame {
+        self.thumbnail = Some(Box::new(thumbnail));
+        self
+    }
+
+    /// A scaled down copy of the frame, e.g. for a live preview, if set with
+    /// `Capturer::set_thumbnail`
+    pub fn thumbnail(&self) -> Option<&Frame> {
+        self.thumbnail.as_deref()
+    }
+
+    /// Set how much of the captured window was shown
+    pub fn with_visibility(mut self, visibility: Visibility) -> Frame {
+        self.visibility = Some(visibility);
+        self
+    }
+
+    /// How much of the captured window was shown when the frame was
+    /// captured, with `Capturer::new_window` on Windows and X11. See
+    /// `Capturer::visibility`.
+    pub fn visibility(&self) -> Option<Visibility> {
+        self.visibility
+    }
+
+    /// Set the DMA-BUF the frame was captured into
+    #[cfg(target_os = "linux")]
+    pub fn with_dmabuf(mut self, dmabuf: DmaBuf) -> Frame {
+        self.dmabuf = Some(dmabuf);
+        self
+    }
+
+    /// The DMA-BUF in GPU memory the frame was captured into, if the backend
+    /// captured into one. See `CapturerBuilder::dmabuf`.
+    ///
+    /// The DMA-BUF is of the whole capture source, without the capture
+    /// region or the cursor overlay applied. Frames captured while regions
+    /// are redacted have none.
+    #[cfg(target_os = "linux")]
+    pub fn dmabuf(&self) -> Option<&DmaBuf> {
+        self.dmabuf.as_ref()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the width and height of the frame
+    pub fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Number of bytes from the start of one row to the next
+    ///
+    /// The rows of frames are not padded, so this is always 4 times the
+    /// width.
+    pub fn stride(&self) -> usize {
+        self.width as usize * std::mem::size_of::<Bgr8>()
+    }
+
+    /// The pixels of the frame, row by row
+    pub fn pixels(&self) -> &[Bgr8] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Bgr8] {
+        &mut self.pixels
+    }
+
+    /// The color components of the pixels, 4 bytes per pixel
+    pub fn as_bytes(&self) -> &[u8] {
+        pixel_bytes(&self.pixels)
+    }
+
+    /// The color components of the pixels as mutable bytes, e.g. for
+    /// processing in place with code working on BGRA bytes
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        pixel_bytes_mut(&mut self.pixels)
+    }
+
+    pub fn into_pixels(self) -> Vec<Bgr8> {
+        self.pixels
+    }
+}
+
+/// Bookkeeping of the capture times and sequence numbers of frames
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FrameClock {
+    pub(crate) timestamp: Option<Instant>,
+    pub(crate) prev_timestamp: Option<Instant>,
+    pub(crate) sequence: u64,
+}
+
+impl FrameClock {
+    /// Record that a frame was captured now
+    pub(crate) fn tick(&mut self) {
+        self.prev_timestamp = self.timestamp;
+        self.timestamp = Some(Instant::now());
+        self.sequence += 1;
+    }
+
+    /// Estimate of the frames presented between the last two captures at
+    /// the given refresh rate
+    pub(crate) fn skipped(&self, refresh_rate: f64) -> u64 {
+        match (self.prev_timestamp, self.timestamp) {
+            (Some(prev), Some(cur)) => {
+                let frames = ((cur - prev).as_secs_f64() * refresh_rate).round() as u64;
+                frames.saturating_sub(1)
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod frame_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_frame_clock_skipped() {
+        let mut clock = FrameClock::default();
+        assert_eq!(clock.skipped(60.0), 0);
+        clock.tick();
+        let t = clock.timestamp.unwrap();
+        clock.prev_timestamp = Some(t);
+        clock.timestamp = Some(t + Duration::from_millis(50));
+        // 3 refreshes at 60 Hz, of which the last one was captured
+        assert_eq!(clock.skipped(60.0), 2);
+        assert_eq!(clock.sequence, 1);
+    }
+
+    #[test]
+    fn test_frame_bytes() {
+        let mut frame = Frame::new(vec![Bgr8::new(1, 2, 3); 2], 2, 1);
+        assert_eq!(frame.as_bytes(), [3, 2, 1, 255, 3, 2, 1, 255]);
+        frame.as_bytes_mut()[6] = 9;
+        assert_eq!(frame.pixels()[1], Bgr8::new(9, 2, 3));
+    }
+}
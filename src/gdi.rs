@@ -0,0 +1,188 @@
+//! Capture with GDI `BitBlt`, for when DXGI desktop duplication is
+//! unavailable, e.g. over some RDP sessions and in session 0
+//!
+//! Slower than desktop duplication, as every capture copies the whole area
+//! through the CPU, and without dirty rectangles or present times.
+
+use crate::backend::Backend;
+use crate::{Bgr8, CaptureError, InitError, Rect};
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+use winapi::um::wingdi::{
+    BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, GetDIBits,
+    SelectObject, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, CAPTUREBLT, DIB_RGB_COLORS, SRCCOPY,
+};
+use winapi::um::winuser::{GetDC, GetSystemMetrics, ReleaseDC, SM_CXSCREEN, SM_CYSCREEN};
+
+fn fail(msg: &str) -> CaptureError {
+    CaptureError::Fail(format!("{}: {}", msg, std::io::Error::last_os_error()))
+}
+
+/// Captures a monitor by copying it out of the screen DC
+pub(crate) struct GdiBackend {
+    /// Bounds of the monitor in desktop coordinates, as position and size
+    position: (i32, i32),
+    size: (u32, u32),
+    region: Option<Rect>,
+    frame: Option<Vec<Bgr8>>,
+    width: u32,
+    height: u32,
+}
+
+impl GdiBackend {
+    /// Capture the output at index `capture_src`, in the same order as DXGI
+    /// desktop duplication
+    ///
+    /// Without any DXGI outputs, e.g. in session 0, index 0 is the primary
+    /// screen.
+    pub(crate) fn new(capture_src: usize) -> Result<GdiBackend, InitError> {
+        let (position, size) = match crate::dxgi::capture_source_output(capture_src) {
+            Some(o) => (
+                (o.rect.left, o.rect.top),
+                (
+                    (o.rect.right - o.rect.left) as u32,
+                    (o.rect.bottom - o.rect.top) as u32,
+                ),
+            ),
+            None if capture_src == 0 => {
+                let (w, h) =
+                    unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) };
+                if w <= 0 || h <= 0 {
+                    return Err(InitError::NotFound("No screen to capture".to_string()));
+                }
+                ((0, 0), (w as u32, h as u32))
+            }
+            None => {
+                return Err(InitError::NotFound(format!(
+                    "No output at index {}",
+                    capture_src
+                )))
+            }
+        };
+        Ok(GdiBackend {
+            position,
+            size,
+            region: None,
+            frame: None,
+            width: 0,
+            height: 0,
+        })
+    }
+
+    /// Copy an area of the screen, in desktop coordinates, into `pixels`
+    fn blit(
+        (x, y): (i32, i32),
+        (w, h): (u32, u32),
+        pixels: &mut Vec<Bgr8>,
+    ) -> Result<(), CaptureError> {
+        pixels.resize(w as usize * h as usize, Bgr8::default());
+        let mut info: BITMAPINFO = unsafe { mem::zeroed() };
+        info.bmiHeader = BITMAPINFOHEADER {
+            biSize: mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: w as i32,
+            biHeight: -(h as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB,
+            ..unsafe { mem::zeroed() }
+        };
+        unsafe {
+            let screen = GetDC(ptr::null_mut());
+            if screen.is_null() {
+                return Err(fail("Failed to get the screen DC"));
+            }
+            let dc = CreateCompatibleDC(screen);
+            let bitmap = CreateCompatibleBitmap(screen, w as i32, h as i32);
+            let res = if dc.is_null() || bitmap.is_null() {
+                Err(fail("Failed to create a bitmap"))
+            } else {
+                let old = SelectObject(dc, bitmap as _);
+                // With `CAPTUREBLT`, layered windows are included
+                let copied = BitBlt(
+                    dc,
+                    0,
+                    0,
+                    w as i32,
+                    h as i32,
+                    screen,
+                    x,
+                    y,
+                    SRCCOPY | CAPTUREBLT,
+                );
+                SelectObject(dc, old);
+                if copied == 0 {
+                    Err(fail("Failed to copy the screen"))
+                } else if GetDIBits(
+                    dc,
+                    bitmap,
+                    0,
+                    h,
+                    pixels.as_mut_ptr() as _,
+                    &mut info,
+                    DIB_RGB_COLORS,
+                ) == 0
+                {
+                    Err(fail("Failed to read the screen bitmap"))
+                } else {
+                    Ok(())
+                }
+            };
+            if !bitmap.is_null() {
+                DeleteObject(bitmap as _);
+            }
+            if !dc.is_null() {
+                DeleteDC(dc);
+            }
+            ReleaseDC(ptr::null_mut(), screen);
+            res?;
+        }
+        // GDI leaves the alpha channel undefined
+        for p in pixels.iter_mut() {
+            p.a = 255;
+        }
+        Ok(())
+    }
+}
+
+impl Backend for GdiBackend {
+    fn name(&self) -> &'static str {
+        "gdi"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        match self.region {
+            Some(r) => (r.width, r.height),
+            None => self.size,
+        }
+    }
+
+    fn position(&self) -> (i32, i32) {
+        self.position
+    }
+
+    fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+        let (x, y) = self.position;
+        let ((x, y), (w, h)) = match self.region {
+            Some(r) => ((x + r.x as i32, y + r.y as i32), (r.width, r.height)),
+            None => ((x, y), self.size),
+        };
+        let mut pixels = self.frame.take().unwrap_or_default();
+        GdiBackend::blit((x, y), (w, h), &mut pixels)?;
+        self.frame = Some(pixels);
+        self.width = w;
+        self.height = h;
+        Ok(())
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_deref()
+            .map(|frame| (frame, self.width, self.height))
+    }
+
+    fn set_region(&mut self, region: Option<Rect>) -> bool {
+        self.region = region;
+        true
+    }
+}
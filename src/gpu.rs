@@ -0,0 +1,135 @@
+//! wgpu integration. `WgpuTexture` uploads captured frames to a wgpu
+//! texture, reusing it between frames of the same size, e.g. to composite the
+//! captured screen in a renderer.
+//!
+//! Frames are uploaded from the frame buffer of the capturer with a single
+//! `Queue::write_texture`, without intermediate conversion. The textures are
+//! `Bgra8Unorm`, and alpha is only meaningful on Windows.
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::gpu::WgpuTexture;
+//! # fn render(device: &wgpu::Device, queue: &wgpu::Queue, capturer: &mut Capturer) {
+//! let mut screen = WgpuTexture::new("screen");
+//! let texture = screen.capture(capturer, device, queue).unwrap();
+//! # }
+//! ```
+
+use crate::{pixel_bytes, Bgr8, CaptureError, Capturer};
+
+/// Format of the textures frames are uploaded to. Same layout as `Bgr8`.
+pub const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
+
+fn create_texture(device: &wgpu::Device, label: &str, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TEXTURE_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn write_texture(queue: &wgpu::Queue, texture: &wgpu::Texture, frame: &[Bgr8]) {
+    let size = texture.size();
+    queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        pixel_bytes(frame),
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * size.width),
+            rows_per_image: Some(size.height),
+        },
+        size,
+    );
+}
+
+/// A wgpu texture that captured frames can be uploaded to
+pub struct WgpuTexture {
+    label: String,
+    texture: Option<wgpu::Texture>,
+}
+
+impl WgpuTexture {
+    /// Create a texture with the given debug label. Nothing is allocated
+    /// until the first frame is uploaded.
+    pub fn new(label: impl Into<String>) -> WgpuTexture {
+        WgpuTexture {
+            label: label.into(),
+            texture: None,
+        }
+    }
+
+    /// Upload a frame of the given size to the texture, and return the
+    /// texture
+    ///
+    /// The texture is recreated when the size of the frames changes.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame: &[Bgr8],
+        width: u32,
+        height: u32,
+    ) -> &wgpu::Texture {
+        assert_eq!(frame.len(), width as usize * height as usize);
+        let stale = self
+            .texture
+            .as_ref()
+            .is_none_or(|t| (t.width(), t.height()) != (width, height));
+        if stale {
+            self.texture = Some(create_texture(device, &self.label, width, height));
+        }
+        let texture = self.texture.as_ref().unwrap();
+        write_texture(queue, texture, frame);
+        texture
+    }
+
+    /// Capture the screen and upload the frame to the texture
+    pub fn capture(
+        &mut self,
+        capturer: &mut Capturer,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<&wgpu::Texture, CaptureError> {
+        capturer.capture_store_frame()?;
+        let (frame, width, height) = capturer.stored_frame_with_size().unwrap();
+        Ok(self.update(device, queue, frame, width, height))
+    }
+
+    /// The texture of the last uploaded frame
+    pub fn texture(&self) -> Option<&wgpu::Texture> {
+        self.texture.as_ref()
+    }
+}
+
+impl Capturer {
+    /// Capture the screen and upload the frame to a new wgpu texture
+    ///
+    /// Use `WgpuTexture` to reuse the texture between frames.
+    pub fn capture_wgpu_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<wgpu::Texture, CaptureError> {
+        self.capture_store_frame()?;
+        let (frame, width, height) = self.stored_frame_with_size().unwrap();
+        let texture = create_texture(device, "captrs frame", width, height);
+        write_texture(queue, &texture, frame);
+        Ok(texture)
+    }
+}
@@ -0,0 +1,132 @@
+//! GStreamer integration. Feeds captured frames into an `appsrc` element, so
+//! captrs can be used as the screen source of an existing GStreamer pipeline.
+//!
+//! ```no_run
+//! # use captrs::{Capturer, gst::CapturerSrc};
+//! use gstreamer::prelude::*;
+//!
+//! gstreamer::init().unwrap();
+//! let pipeline = gstreamer::parse::launch("appsrc name=src ! videoconvert ! autovideosink")
+//!     .unwrap()
+//!     .downcast::<gstreamer::Pipeline>()
+//!     .unwrap();
+//! let appsrc = pipeline.by_name("src").unwrap().downcast().unwrap();
+//! let mut src = CapturerSrc::with_appsrc(Capturer::new(0).unwrap(), appsrc, 30).unwrap();
+//! pipeline.set_state(gstreamer::State::Playing).unwrap();
+//! loop {
+//!     src.push_frame().unwrap();
+//! }
+//! ```
+
+use crate::{CaptureError, Capturer};
+use gstreamer as gst;
+use gstreamer_app::AppSrc;
+use gstreamer_video::{VideoFormat, VideoInfo};
+
+/// Error when pushing a captured frame into the pipeline
+#[derive(Clone, Debug)]
+pub enum PushError {
+    /// The frame could not be captured
+    Capture(CaptureError),
+    /// The pipeline did not accept the frame, e.g. because it's flushing or has
+    /// reached end-of-stream
+    Flow(gst::FlowError),
+}
+
+/// A `Capturer` feeding an `appsrc` element
+///
+/// Frames are sent as raw `BGRx` video with timestamps derived from the
+/// configured frame rate. The `appsrc` is configured to block when its
+/// internal queue is full, so `push_frame` is throttled by the downstream
+/// elements.
+pub struct CapturerSrc {
+    capturer: Capturer,
+    appsrc: AppSrc,
+    fps: u32,
+    geometry: (u32, u32),
+    n_frames: u64,
+}
+
+impl CapturerSrc {
+    /// Create a new `appsrc` element fed by `capturer` at `fps` frames per second
+    pub fn new(capturer: Capturer, fps: u32) -> Result<CapturerSrc, gst::glib::BoolError> {
+        CapturerSrc::with_appsrc(capturer, AppSrc::builder().build(), fps)
+    }
+
+    /// Configure an existing `appsrc` element, e.g. from a pipeline created with
+    /// `gst::parse::launch`, to be fed by `capturer` at `fps` frames per second
+    pub fn with_appsrc(
+        capturer: Capturer,
+        appsrc: AppSrc,
+        fps: u32,
+    ) -> Result<CapturerSrc, gst::glib::BoolError> {
+        assert!(fps > 0, "Frame rate must be positive");
+        let geometry = capturer.geometry();
+        appsrc.set_caps(Some(&video_caps(geometry, fps)?));
+        appsrc.set_format(gst::Format::Time);
+        appsrc.set_is_live(true);
+        appsrc.set_do_timestamp(false);
+        appsrc.set_block(true);
+        // Queue at most two frames before blocking
+        appsrc.set_max_bytes(2 * 4 * geometry.0 as u64 * geometry.1 as u64);
+        Ok(CapturerSrc {
+            capturer,
+            appsrc,
+            fps,
+            geometry,
+            n_frames: 0,
+        })
+    }
+
+    /// The `appsrc` element, to be added to and linked in a pipeline
+    pub fn appsrc(&self) -> &AppSrc {
+        &self.appsrc
+    }
+
+    /// The underlying capturer
+    pub fn capturer(&mut self) -> &mut Capturer {
+        &mut self.capturer
+    }
+
+    /// Capture a frame and push it into the pipeline
+    ///
+    /// Blocks while the `appsrc` queue is full.
+    pub fn push_frame(&mut self) -> Result<gst::FlowSuccess, PushError> {
+        let data = self
+            .capturer
+            .capture_frame_components()
+            .map_err(PushError::Capture)?;
+        let geometry = self.capturer.geometry();
+        if geometry != self.geometry {
+            let caps = video_caps(geometry, self.fps)
+                .map_err(|e| PushError::Capture(CaptureError::Fail(e.to_string())))?;
+            self.appsrc.set_caps(Some(&caps));
+            self.geometry = geometry;
+        }
+        let frame_duration = gst::ClockTime::SECOND / self.fps as u64;
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(frame_duration * self.n_frames);
+            buffer.set_duration(frame_duration);
+        }
+        self.n_frames += 1;
+        self.appsrc.push_buffer(buffer).map_err(PushError::Flow)
+    }
+
+    /// Signal that no more frames will be pushed
+    pub fn end_of_stream(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        self.appsrc.end_of_stream()
+    }
+}
+
+/// Caps of raw video frames as captured, at the given frame rate
+pub fn video_caps(
+    (width, height): (u32, u32),
+    fps: u32,
+) -> Result<gst::Caps, gst::glib::BoolError> {
+    VideoInfo::builder(VideoFormat::Bgrx, width, height)
+        .fps(gst::Fraction::new(fps as i32, 1))
+        .build()?
+        .to_caps()
+}
@@ -0,0 +1,302 @@
+//! Capture of displays in HDR or 10-bit mode in their native format, and
+//! tone mapping of it to 8-bit frames for consumers of SDR frames
+//!
+//! `PixelFormat::Rgba16F` is linear scRGB, where 1.0 is the 80 nit white of
+//! sRGB and brighter colors exceed 1.0. `PixelFormat::Rgb10A2` is sRGB with
+//! 10 bits per channel, as of displays in 10-bit SDR mode.
+
+use crate::{Bgr8, CaptureError, Capturer, InitError, PixelFormat};
+use std::sync::OnceLock;
+
+/// How to map HDR colors to 8-bit frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    /// Brightness of SDR white in scRGB, i.e. in units of 80 nits. E.g. 2.5
+    /// with the SDR content brightness of Windows set to 200 nits.
+    pub sdr_white: f32,
+    /// Whether to compress colors from 75% of SDR white up smoothly, keeping
+    /// highlights distinguishable, instead of clipping colors brighter than
+    /// SDR white
+    pub compress_highlights: bool,
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping {
+            sdr_white: 1.0,
+            compress_highlights: true,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Map a linear scRGB channel to linear SDR in `0.0..=1.0`
+    fn apply(self, v: f32) -> f32 {
+        const KNEE: f32 = 0.75;
+        let v = (v / self.sdr_white).max(0.0);
+        if self.compress_highlights && v > KNEE {
+            // Continuous with a slope of 1 at the knee, approaching 1
+            let t = (v - KNEE) / (1.0 - KNEE);
+            KNEE + (1.0 - KNEE) * t / (1.0 + t)
+        } else {
+            v.min(1.0)
+        }
+    }
+}
+
+/// The value of an IEEE 754 half precision float
+pub(crate) fn f16_to_f32(h: u16) -> f32 {
+    let h = h as u32;
+    let sign = (h & 0x8000) << 16;
+    let exp = (h >> 10) & 0x1f;
+    let mant = h & 0x3ff;
+    match exp {
+        0 => {
+            // Zero or subnormal
+            let v = mant as f32 / (1 << 24) as f32;
+            if sign == 0 {
+                v
+            } else {
+                -v
+            }
+        }
+        31 => f32::from_bits(sign | 0x7f80_0000 | (mant << 13)),
+        _ => f32::from_bits(sign | ((exp + 112) << 23) | (mant << 13)),
+    }
+}
+
+/// The IEEE 754 half precision float nearest to `v`
+pub(crate) fn f32_to_f16(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mant = bits & 0x7f_ffff;
+    if v.is_nan() {
+        sign | 0x7e00
+    } else if exp >= 31 {
+        sign | 0x7c00
+    } else if exp <= 0 {
+        if exp < -10 {
+            return sign;
+        }
+        let shift = (14 - exp) as u32;
+        sign | (((mant | 0x80_0000) + (1 << (shift - 1))) >> shift) as u16
+    } else {
+        // Rounding may carry into the exponent, as it should
+        sign | (((exp as u32) << 10) + ((mant + 0x1000) >> 13)) as u16
+    }
+}
+
+/// Linear light of an sRGB encoded value
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// sRGB encoding of linear light
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Number of entries of `ENCODE_LUT`, precise enough for 8-bit output
+const ENCODE_LUT_LEN: usize = 4096;
+
+/// 8-bit sRGB encoding of linear light in steps of `1 / (ENCODE_LUT_LEN - 1)`
+fn encode_lut() -> &'static [u8; ENCODE_LUT_LEN] {
+    static LUT: OnceLock<[u8; ENCODE_LUT_LEN]> = OnceLock::new();
+    LUT.get_or_init(|| {
+        let mut lut = [0; ENCODE_LUT_LEN];
+        for (i, e) in lut.iter_mut().enumerate() {
+            let v = i as f32 / (ENCODE_LUT_LEN - 1) as f32;
+            *e = (linear_to_srgb(v) * 255.0).round() as u8;
+        }
+        lut
+    })
+}
+
+/// Half precision linear light of 8-bit sRGB values
+fn decode_lut() -> &'static [u16; 256] {
+    static LUT: OnceLock<[u16; 256]> = OnceLock::new();
+    LUT.get_or_init(|| {
+        let mut lut = [0; 256];
+        for (i, d) in lut.iter_mut().enumerate() {
+            *d = f32_to_f16(srgb_to_linear(i as f32 / 255.0));
+        }
+        lut
+    })
+}
+
+/// Tone map pixels of an HDR format to 8-bit BGRA, replacing the contents of
+/// `dst`
+///
+/// `format` must be `PixelFormat::Rgba16F` or `PixelFormat::Rgb10A2`. 10-bit
+/// pixels are already SDR, and are only rounded to 8 bits.
+pub fn tone_map(src: &[u8], format: PixelFormat, tone_mapping: ToneMapping, dst: &mut Vec<Bgr8>) {
+    dst.clear();
+    match format {
+        PixelFormat::Rgba16F => {
+            let lut = encode_lut();
+            let encode = |bytes: &[u8]| {
+                let v = tone_mapping.apply(f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]])));
+                lut[(v * (ENCODE_LUT_LEN - 1) as f32).round() as usize]
+            };
+            dst.extend(src.chunks_exact(8).map(|p| Bgr8 {
+                b: encode(&p[4..6]),
+                g: encode(&p[2..4]),
+                r: encode(&p[0..2]),
+                a: 255,
+            }));
+        }
+        PixelFormat::Rgb10A2 => {
+            let to_8 = |v: u32| (((v & 0x3ff) * 255 + 511) / 1023) as u8;
+            dst.extend(src.chunks_exact(4).map(|p| {
+                let v = u32::from_le_bytes([p[0], p[1], p[2], p[3]]);
+                Bgr8 {
+                    b: to_8(v >> 20),
+                    g: to_8(v >> 10),
+                    r: to_8(v),
+                    a: 255,
+                }
+            }));
+        }
+        _ => panic!("{:?} is not an HDR pixel format", format),
+    }
+}
+
+/// Convert 8-bit pixels to linear half precision RGBA with opaque alpha.
+/// `dst` must have room for 8 bytes per pixel.
+pub(crate) fn to_rgba16f(src: &[Bgr8], dst: &mut [u8]) {
+    let lut = decode_lut();
+    let one = f32_to_f16(1.0).to_le_bytes();
+    for (p, d) in src.iter().zip(dst.chunks_exact_mut(8)) {
+        d[0..2].copy_from_slice(&lut[p.r as usize].to_le_bytes());
+        d[2..4].copy_from_slice(&lut[p.g as usize].to_le_bytes());
+        d[4..6].copy_from_slice(&lut[p.b as usize].to_le_bytes());
+        d[6..8].copy_from_slice(&one);
+    }
+}
+
+/// Convert 8-bit pixels to 10 bits per channel with opaque alpha. `dst`
+/// must have room for 4 bytes per pixel.
+pub(crate) fn to_rgb10a2(src: &[Bgr8], dst: &mut [u8]) {
+    let to_10 = |v: u8| (v as u32 * 1023 + 127) / 255;
+    for (p, d) in src.iter().zip(dst.chunks_exact_mut(4)) {
+        let v = to_10(p.r) | to_10(p.g) << 10 | to_10(p.b) << 20 | 3 << 30;
+        d.copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+impl Capturer {
+    /// Capture in the format of the display if it's in HDR or 10-bit mode,
+    /// tone mapping to the 8-bit frames of the other capture methods.
+    /// `None` to stop.
+    ///
+    /// Only supported with DXGI desktop duplication on Windows 10 1703 and
+    /// later, in processes that are per-monitor DPI aware. The HDR frames
+    /// are retrieved with `Self::capture_frame_format` in the format
+    /// `Self::hdr_format`.
+    pub fn set_hdr(&mut self, tone_mapping: Option<ToneMapping>) -> Result<(), InitError> {
+        match self.backend.set_hdr(tone_mapping) {
+            Some(res) => res?,
+            None if tone_mapping.is_some() => {
+                return Err(InitError::Unsupported(format!(
+                    "HDR capture is not supported by the {} backend",
+                    self.backend.name()
+                )))
+            }
+            None => (),
+        }
+        self.hdr = tone_mapping;
+        Ok(())
+    }
+
+    /// How HDR frames are tone mapped, if captured
+    pub fn hdr(&self) -> Option<ToneMapping> {
+        self.hdr
+    }
+
+    /// HDR format of the last captured frame, if it was captured in one
+    pub fn hdr_format(&self) -> Option<PixelFormat> {
+        self.backend.hdr_frame().map(|(_, format)| format)
+    }
+
+    /// The pixels of the last captured frame in `format`, if it was captured
+    /// in it and the 8-bit frame is not processed, e.g. cropped or with the
+    /// cursor drawn
+    pub(crate) fn stored_hdr_frame(&self, format: PixelFormat) -> Option<&[u8]> {
+        self.backend
+            .hdr_frame()
+            .filter(|&(_, hdr_format)| hdr_format == format && self.processed.is_none())
+            .map(|(pixels, _)| pixels)
+    }
+
+    /// Capture screen and return the pixels as linear half precision RGBA,
+    /// 8 bytes per pixel, as `PixelFormat::Rgba16F`
+    ///
+    /// Without `Self::set_hdr`, or when the display is not in HDR mode, the
+    /// pixels are converted from the 8-bit frame.
+    pub fn capture_frame_rgba16f(&mut self) -> Result<Vec<u8>, CaptureError> {
+        self.capture_frame_format(PixelFormat::Rgba16F)
+    }
+}
+
+#[cfg(test)]
+mod hdr_tests {
+    use super::*;
+
+    #[test]
+    fn test_f16() {
+        for &(h, v) in &[
+            (0x0000, 0.0),
+            (0x3c00, 1.0),
+            (0xc000, -2.0),
+            (0x3555, 0.333_251_95),
+            (0x0001, 5.960_464_5e-8),
+            (0x7bff, 65504.0),
+        ] {
+            assert_eq!(f16_to_f32(h), v);
+            assert_eq!(f32_to_f16(v), h);
+        }
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn test_tone_map() {
+        let pixels = [Bgr8::new(255, 128, 0), Bgr8::new(10, 20, 30)];
+        let mut bytes = vec![0; 8 * pixels.len()];
+        to_rgba16f(&pixels, &mut bytes);
+        let mut mapped = Vec::new();
+        let clip = ToneMapping {
+            sdr_white: 1.0,
+            compress_highlights: false,
+        };
+        tone_map(&bytes, PixelFormat::Rgba16F, clip, &mut mapped);
+        assert_eq!(mapped, pixels);
+
+        let mut bytes = vec![0; 4 * pixels.len()];
+        to_rgb10a2(&pixels, &mut bytes);
+        tone_map(&bytes, PixelFormat::Rgb10A2, clip, &mut mapped);
+        assert_eq!(mapped, pixels);
+
+        // Twice as bright as SDR white is compressed below white
+        let bright = [0x4000u16, 0x3c00, 0, 0x3c00];
+        let bytes: Vec<u8> = bright.iter().flat_map(|v| v.to_le_bytes()).collect();
+        tone_map(
+            &bytes,
+            PixelFormat::Rgba16F,
+            ToneMapping::default(),
+            &mut mapped,
+        );
+        assert!(mapped[0].r > mapped[0].g && mapped[0].r < 255);
+        tone_map(&bytes, PixelFormat::Rgba16F, clip, &mut mapped);
+        assert_eq!(mapped[0].r, 255);
+    }
+}
@@ -0,0 +1,47 @@
+//! `image` crate integration, e.g. for saving screenshots
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! capturer.capture_image().unwrap().save("shot.png").unwrap();
+//! ```
+
+use crate::convert::to_rgba;
+use crate::{Bgr8, CaptureError, Capturer, Frame};
+use image::RgbaImage;
+
+fn rgba_image(pixels: &[Bgr8], width: u32, height: u32) -> RgbaImage {
+    let mut data = vec![0; 4 * pixels.len()];
+    to_rgba(pixels, &mut data);
+    RgbaImage::from_raw(width, height, data).expect("Pixels of frame size")
+}
+
+impl Frame {
+    /// Convert the frame to an RGBA image with opaque alpha
+    pub fn to_image(&self) -> RgbaImage {
+        rgba_image(self.pixels(), self.width(), self.height())
+    }
+}
+
+impl Capturer {
+    /// Capture screen and return the frame as an RGBA image with opaque
+    /// alpha
+    pub fn capture_image(&mut self) -> Result<RgbaImage, CaptureError> {
+        self.capture_store_frame()?;
+        let (pixels, width, height) = self.stored_frame_with_size().unwrap();
+        Ok(rgba_image(pixels, width, height))
+    }
+}
+
+#[cfg(test)]
+mod image_tests {
+    use super::*;
+
+    #[test]
+    fn test_to_image() {
+        let frame = Frame::new(vec![Bgr8::new(10, 20, 30), Bgr8::new(40, 50, 60)], 2, 1);
+        let image = frame.to_image();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(1, 0).0, [40, 50, 60, 255]);
+    }
+}
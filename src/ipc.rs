@@ -0,0 +1,439 @@
+//! Streaming of frames to other local processes over a Unix domain socket on
+//! *nix, or a named pipe on Windows, so they can consume captures without
+//! linking Rust.
+//!
+//! Clients only read. Each frame is sent as a 32 byte header followed by the
+//! payload, with all fields in little-endian byte order:
+//!
+//! | Offset | Type | Field                                           |
+//! |--------|------|-------------------------------------------------|
+//! | 0      | u32  | Magic, `b"CPTF"`                                |
+//! | 4      | u16  | Protocol version, currently 1                   |
+//! | 6      | u16  | Payload format, e.g. `FORMAT_BGRX8`             |
+//! | 8      | u32  | Frame width                                     |
+//! | 12     | u32  | Frame height                                    |
+//! | 16     | u64  | Frame number, starting at 1                     |
+//! | 24     | u64  | Length of the payload in bytes                  |
+//! | 32     |      | Payload                                         |
+//!
+//! Raw BGRX8 payloads have no row padding.
+
+use crate::{pixel_bytes, Bgr8};
+use std::convert::TryInto;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CPTF");
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 32;
+/// 4 byte blue, green, red, and padding
+pub const FORMAT_BGRX8: u16 = 0;
+/// A JPEG image
+pub const FORMAT_JPEG: u16 = 1;
+/// An H.264 access unit in Annex B format
+pub const FORMAT_H264: u16 = 2;
+/// Default time a write to a client may block before it's disconnected
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Header of a frame sent over the socket
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// Payload format, e.g. `FORMAT_BGRX8`
+    pub format: u16,
+    pub width: u32,
+    pub height: u32,
+    /// Number of the frame, starting at 1 for the first sent frame
+    pub frame_number: u64,
+    /// Length of the payload in bytes
+    pub len: u64,
+}
+
+impl FrameHeader {
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0; HEADER_SIZE];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.format.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.width.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.height.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.frame_number.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    fn from_bytes(buf: &[u8; HEADER_SIZE]) -> io::Result<FrameHeader> {
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        if u32_at(0) != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Bad frame magic",
+            ));
+        }
+        if u16_at(4) != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unsupported protocol version",
+            ));
+        }
+        Ok(FrameHeader {
+            format: u16_at(6),
+            width: u32_at(8),
+            height: u32_at(12),
+            frame_number: u64_at(16),
+            len: u64_at(24),
+        })
+    }
+}
+
+/// Server sending frames to every connected client
+///
+/// Clients are accepted on a background thread. Frames are written from the
+/// thread calling `send_frame`, and clients that stop reading are
+/// disconnected once a write blocks for longer than the write timeout.
+pub struct FrameServer {
+    name: String,
+    clients: Arc<Mutex<Vec<sys::Stream>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    frame_number: u64,
+    write_timeout: Duration,
+}
+
+impl FrameServer {
+    /// Start listening at the given path on *nix, or the given pipe name, e.g.
+    /// `\\.\pipe\captrs`, on Windows
+    pub fn bind(name: &str) -> io::Result<FrameServer> {
+        let mut listener = sys::Listener::bind(name)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (clients, stop) = (clients.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok(_) if stop.load(Ordering::SeqCst) => break,
+                        Ok(stream) => clients.lock().unwrap().push(stream),
+                        Err(_) => thread::sleep(std::time::Duration::from_millis(100)),
+                    }
+                }
+            })
+        };
+        Ok(FrameServer {
+            name: name.to_string(),
+            clients,
+            stop,
+            thread: Some(thread),
+            frame_number: 0,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+        })
+    }
+
+    /// Set how long a write to a client may block before the client is
+    /// disconnected, by default `DEFAULT_WRITE_TIMEOUT`
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        assert!(!timeout.is_zero());
+        self.write_timeout = timeout;
+    }
+
+    /// Number of connected clients
+    pub fn n_clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Send a frame of the given size to all clients
+    pub fn send_frame(&mut self, frame: &[Bgr8], width: u32, height: u32) -> io::Result<()> {
+        self.send_frame_components(pixel_bytes(frame), width, height)
+    }
+
+    /// Send a frame of the given size, as returned by
+    /// `Capturer::capture_frame_components`, to all clients
+    pub fn send_frame_components(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> io::Result<()> {
+        if data.len() as u64 != 4 * width as u64 * height as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame size does not match the given geometry",
+            ));
+        }
+        self.send_encoded(FORMAT_BGRX8, data, width, height);
+        Ok(())
+    }
+
+    /// Send an encoded frame, e.g. a JPEG image, of the given format and size
+    /// to all clients
+    ///
+    /// Clients that fail to receive the frame, or stop reading for longer than
+    /// the write timeout, are disconnected.
+    pub fn send_encoded(&mut self, format: u16, data: &[u8], width: u32, height: u32) {
+        self.frame_number += 1;
+        let header = FrameHeader {
+            format,
+            width,
+            height,
+            frame_number: self.frame_number,
+            len: data.len() as u64,
+        }
+        .to_bytes();
+        let timeout = self.write_timeout;
+        self.clients.lock().unwrap().retain_mut(|client| {
+            sys::write_all(client, &header, timeout)
+                .and_then(|_| sys::write_all(client, data, timeout))
+                .is_ok()
+        });
+    }
+}
+
+impl Drop for FrameServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the accepting thread up
+        let _ = sys::connect(&self.name);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Client receiving frames from a `FrameServer`
+pub struct FrameClient {
+    stream: sys::Stream,
+}
+
+impl FrameClient {
+    /// Connect to a server at the given path on *nix, or the given pipe name
+    /// on Windows
+    pub fn connect(name: &str) -> io::Result<FrameClient> {
+        sys::connect(name).map(|stream| FrameClient { stream })
+    }
+
+    /// Block until the next frame is received, and read its payload into `buf`
+    pub fn read_frame(&mut self, buf: &mut Vec<u8>) -> io::Result<FrameHeader> {
+        let mut header = [0; HEADER_SIZE];
+        self.stream.read_exact(&mut header)?;
+        let header = FrameHeader::from_bytes(&header)?;
+        buf.clear();
+        (&mut self.stream).take(header.len).read_to_end(buf)?;
+        if buf.len() as u64 != header.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(header)
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::io::{self, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    pub type Stream = UnixStream;
+
+    pub struct Listener {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl Listener {
+        pub fn bind(path: &str) -> io::Result<Listener> {
+            UnixListener::bind(path).map(|listener| Listener {
+                listener,
+                path: path.into(),
+            })
+        }
+
+        pub fn accept(&mut self) -> io::Result<Stream> {
+            self.listener.accept().map(|(stream, _)| stream)
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    pub fn connect(path: &str) -> io::Result<Stream> {
+        UnixStream::connect(path)
+    }
+
+    /// Write all of `buf`, failing if a write blocks for longer than
+    /// `timeout`
+    pub fn write_all(stream: &mut Stream, buf: &[u8], timeout: Duration) -> io::Result<()> {
+        stream.set_write_timeout(Some(timeout))?;
+        stream.write_all(buf)
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::OsStr;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::FromRawHandle;
+    use std::ptr;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW, SetNamedPipeHandleState};
+    use winapi::um::winbase::{
+        PIPE_ACCESS_OUTBOUND, PIPE_NOWAIT, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+    use winapi::um::winnt::HANDLE;
+
+    pub type Stream = File;
+
+    /// The name of the pipe, and the instance waiting for the next client.
+    /// An instance is always kept, so clients can connect between accepts.
+    pub struct Listener {
+        name: Vec<u16>,
+        pending: HANDLE,
+    }
+
+    // The pending pipe instance is only used by the owner of the listener
+    unsafe impl Send for Listener {}
+
+    fn create_instance(name: &[u16]) -> io::Result<HANDLE> {
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_OUTBOUND,
+                PIPE_TYPE_BYTE | PIPE_WAIT,
+                PIPE_UNLIMITED_INSTANCES,
+                1 << 20,
+                0,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(handle)
+        }
+    }
+
+    impl Listener {
+        pub fn bind(name: &str) -> io::Result<Listener> {
+            let name: Vec<u16> = OsStr::new(name).encode_wide().chain(Some(0)).collect();
+            let pending = create_instance(&name)?;
+            Ok(Listener { name, pending })
+        }
+
+        pub fn accept(&mut self) -> io::Result<Stream> {
+            if unsafe { ConnectNamedPipe(self.pending, ptr::null_mut()) } == 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                    return Err(err);
+                }
+            }
+            let next = create_instance(&self.name)?;
+            let connected = std::mem::replace(&mut self.pending, next);
+            let stream = unsafe { File::from_raw_handle(connected as _) };
+            // Writes return right away once the pipe is full, so that they
+            // can time out
+            let mut mode = PIPE_TYPE_BYTE | PIPE_NOWAIT;
+            if unsafe {
+                SetNamedPipeHandleState(connected, &mut mode, ptr::null_mut(), ptr::null_mut())
+            } == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(stream)
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            unsafe { CloseHandle(self.pending) };
+        }
+    }
+
+    pub fn connect(name: &str) -> io::Result<Stream> {
+        OpenOptions::new().read(true).open(name)
+    }
+
+    /// Write all of `buf` to a pipe in nonblocking mode, failing if nothing
+    /// could be written for longer than `timeout`
+    pub fn write_all(stream: &mut Stream, mut buf: &[u8], timeout: Duration) -> io::Result<()> {
+        let mut deadline = Instant::now() + timeout;
+        while !buf.is_empty() {
+            match stream.write(buf)? {
+                0 if Instant::now() >= deadline => return Err(io::ErrorKind::TimedOut.into()),
+                0 => thread::sleep(Duration::from_millis(1)),
+                n => {
+                    buf = &buf[n..];
+                    deadline = Instant::now() + timeout;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod ipc_tests {
+    use super::*;
+
+    #[test]
+    fn test_send_receive() {
+        let path = std::env::temp_dir().join(format!("captrs-test-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut server = FrameServer::bind(path).unwrap();
+        let mut client = FrameClient::connect(path).unwrap();
+        while server.n_clients() == 0 {
+            thread::yield_now();
+        }
+        let data: Vec<u8> = (0..4 * 3 * 2).collect();
+        server.send_frame_components(&data, 3, 2).unwrap();
+        let mut buf = Vec::new();
+        let header = client.read_frame(&mut buf).unwrap();
+        assert_eq!(
+            header,
+            FrameHeader {
+                format: FORMAT_BGRX8,
+                width: 3,
+                height: 2,
+                frame_number: 1,
+                len: data.len() as u64,
+            }
+        );
+        assert_eq!(buf, data);
+        assert_eq!(
+            server
+                .send_frame_components(&data, 2, 2)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_stalled_client() {
+        let path =
+            std::env::temp_dir().join(format!("captrs-test-stalled-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut server = FrameServer::bind(path).unwrap();
+        server.set_write_timeout(Duration::from_millis(50));
+        // Connected, but never reading
+        let _stalled = FrameClient::connect(path).unwrap();
+        while server.n_clients() == 0 {
+            thread::yield_now();
+        }
+        // Larger than the socket buffers
+        let data = vec![0; 4 << 20];
+        server.send_frame_components(&data, 1024, 1024).unwrap();
+        assert_eq!(server.n_clients(), 0);
+    }
+}
@@ -0,0 +1,136 @@
+//! Captures minimizing the delay from a frame being available to it being
+//! returned, e.g. for overlays reacting to games
+//!
+//! ```no_run
+//! # use captrs::{Capturer, LatencyMode};
+//! let mut capturer = Capturer::new(0).unwrap();
+//! capturer.set_latency_mode(LatencyMode::LowLatency);
+//! loop {
+//!     if let Ok(frame) = capturer.capture_frame_meta() {
+//!         println!("Captured in {:?}", frame.latency());
+//!     }
+//! }
+//! ```
+
+use crate::{CaptureError, Capturer};
+use std::time::{Duration, Instant};
+
+/// Whether captures favour throughput or latency
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LatencyMode {
+    /// Wait for new frames, pacing captures as configured
+    #[default]
+    Throughput,
+    /// Poll for new frames and return them as soon as they are read, see
+    /// `Capturer::set_latency_mode`
+    LowLatency,
+}
+
+impl Capturer {
+    /// Set whether captures favour throughput or latency
+    ///
+    /// With `LatencyMode::LowLatency`, captures are not delayed by
+    /// `Self::set_max_fps` or `Self::set_sync`, and poll for new frames
+    /// until the timeout rather than blocking on the backend, e.g. with
+    /// `AcquireNextFrame(0)` with DXGI desktop duplication. On X11, frames
+    /// are read through shared memory right away, without waiting for
+    /// damage. `Self::capture_frame_converted` returns the frames as
+    /// captured, in `PixelFormat::Bgra8`, skipping the conversion to
+    /// `Self::pixel_format`.
+    pub fn set_latency_mode(&mut self, mode: LatencyMode) {
+        self.latency_mode = mode;
+        self.backend.set_immediate(mode == LatencyMode::LowLatency);
+    }
+
+    /// Whether captures favour throughput or latency
+    pub fn latency_mode(&self) -> LatencyMode {
+        self.latency_mode
+    }
+
+    /// Time from starting to acquire the last captured frame to it being
+    /// stored, processed, and ready to return. `None` before the first
+    /// frame.
+    ///
+    /// Also attached to the frames of `Self::capture_frame_meta`, as
+    /// `Frame::latency`.
+    pub fn last_latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    pub(crate) fn low_latency(&self) -> bool {
+        self.latency_mode == LatencyMode::LowLatency
+    }
+
+    /// Capture a frame with the backend, polling for it without blocking if
+    /// low latency
+    pub(crate) fn acquire_frame(&mut self) -> Result<(), CaptureError> {
+        if !self.low_latency() {
+            return self.backend.capture_frame(self.capture_timeout());
+        }
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.backend.capture_frame(Duration::ZERO) {
+                Err(CaptureError::Timeout) if Instant::now() < deadline => std::thread::yield_now(),
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod latency_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::{Bgr8, PixelFormat};
+
+    /// Backend of 2x1 frames, with a new one every third poll if not waited
+    /// for
+    struct Polled {
+        polls: u32,
+        frame: Vec<Bgr8>,
+    }
+
+    impl Backend for Polled {
+        fn name(&self) -> &'static str {
+            "polled"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (2, 1)
+        }
+
+        fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+            self.polls += 1;
+            if timeout.is_zero() && !self.polls.is_multiple_of(3) {
+                return Err(CaptureError::Timeout);
+            }
+            self.frame = vec![Bgr8::new(1, 2, 3); 2];
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.frame, 2, 1))
+        }
+    }
+
+    #[test]
+    fn test_low_latency() {
+        let mut capturer = Capturer::with_backend(Box::new(Polled {
+            polls: 0,
+            frame: Vec::new(),
+        }));
+        capturer.set_pixel_format(PixelFormat::Rgb8);
+        assert_eq!(capturer.last_latency(), None);
+        assert_eq!(capturer.capture_frame_converted().unwrap().len(), 6);
+        capturer.set_latency_mode(LatencyMode::LowLatency);
+        assert_eq!(capturer.latency_mode(), LatencyMode::LowLatency);
+        // Polled until the third poll, in the format of the backend
+        assert_eq!(
+            capturer.capture_frame_converted().unwrap(),
+            [3, 2, 1, 255, 3, 2, 1, 255]
+        );
+        let frame = capturer.capture_frame_meta().unwrap();
+        assert!(frame.latency().is_some());
+        assert_eq!(frame.latency(), capturer.last_latency());
+    }
+}
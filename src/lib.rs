@@ -1,199 +1,916 @@
 //! Cross-platform screen capture. Uses DXGI desktop-duplication on Windows,
-//! and X11 (xlib, XGetImage) on *nix
+//! and X11 (xlib, XShmGetImage or XGetImage) on *nix, or the ScreenCast portal and PipeWire
+//! on Wayland with the `wayland` feature. Uses `CGDisplayStream` on macOS, and
+//! `MediaProjection` on Android with the `android` feature, and
+//! `getDisplayMedia` in browsers with the `web` feature.
 
 #[cfg(windows)]
 extern crate dxgcap;
-#[cfg(not(windows))]
+#[cfg(unix)]
+extern crate libc;
+#[cfg(windows)]
+extern crate winapi;
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
 extern crate x11cap;
 
-use std::time::Duration;
+mod analysis;
+#[cfg(all(target_os = "android", feature = "android"))]
+pub mod android;
+#[cfg(feature = "async")]
+pub mod async_capture;
+pub mod backend;
+#[cfg(feature = "bevy")]
+pub mod bevy;
+mod builder;
+mod burst;
+pub mod channel;
+pub mod color;
+mod compare;
+mod convert;
+pub mod coords;
+mod crop;
+pub mod cursor;
+#[cfg(all(windows, feature = "d3d11"))]
+pub mod d3d11;
+pub mod diagnostics;
+pub mod diff;
+mod display;
+#[cfg(target_os = "linux")]
+pub mod dmabuf;
+#[cfg(target_os = "linux")]
+pub mod drm;
+#[cfg(feature = "dump")]
+pub mod dump;
+#[cfg(windows)]
+mod dxgi;
+#[cfg(any(
+    windows,
+    all(unix, not(target_os = "macos"), not(target_os = "android")),
+    feature = "ndi"
+))]
+mod dylib;
+#[cfg(feature = "egui")]
+pub mod egui;
+mod error;
+pub mod external;
+mod format;
+mod frame;
+#[cfg(windows)]
+mod gdi;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+#[cfg(feature = "gstreamer")]
+pub mod gst;
+pub mod hdr;
+#[cfg(feature = "image")]
+mod image;
+#[cfg(any(unix, windows))]
+pub mod ipc;
+pub mod latency;
+#[cfg(target_os = "macos")]
+mod macos;
+mod mask;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mirror;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod multi;
+#[cfg(feature = "ndi")]
+pub mod ndi;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod overlay;
+mod palette;
+mod parallel;
+pub mod permission;
+mod pool;
+mod preview;
+mod priority;
+pub mod protected;
+#[cfg(feature = "python")]
+mod python;
+pub mod rate;
+#[cfg(feature = "gif")]
+pub mod recorder;
+pub mod redact;
+pub mod replay;
+pub mod roi;
+#[cfg(feature = "webrtc")]
+pub mod rtc;
+#[cfg(feature = "rtsp")]
+pub mod rtsp;
+#[cfg(feature = "save")]
+pub mod save;
+pub mod scale;
+pub mod schedule;
+pub mod scrap;
+mod screenshot;
+#[cfg(feature = "sdl2")]
+pub mod sdl2;
+mod search;
+#[cfg(any(unix, windows))]
+pub mod shm;
+#[cfg(feature = "softbuffer")]
+pub mod softbuffer;
+pub mod split;
+pub mod stats;
+#[cfg(feature = "mjpeg")]
+pub mod stream;
+pub mod sync;
+pub mod threaded;
+pub mod tiles;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(target_os = "linux")]
+pub mod v4l2;
+#[cfg(feature = "recorder-ffmpeg")]
+pub mod video;
+pub mod watcher;
+#[cfg(all(
+    unix,
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    feature = "wayland"
+))]
+mod wayland;
+#[cfg(all(target_arch = "wasm32", feature = "web"))]
+pub mod web;
+#[cfg(any(target_os = "linux", windows))]
+pub mod webcam;
+#[cfg(windows)]
+mod wgc;
+#[cfg(windows)]
+pub mod wincam;
+mod window;
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+mod x11;
+#[cfg(all(
+    unix,
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    feature = "x11-xcb"
+))]
+mod xcb;
+mod yuv;
 
-/// Color represented by additive channels: Blue (b), Green (g), Red (r), and Alpha (a)
+pub use analysis::{EdgeZones, Histogram, LedLayout};
+pub use backend::Backend;
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+pub use builder::X11Api;
 #[cfg(windows)]
-pub type Bgr8 = dxgcap::BGRA8;
-/// Color represented by additive channels: Blue (b), Green (g), and Red (r).
+pub use builder::{AdapterSelector, WindowsBackend};
+pub use builder::{Backoff, CapturerBuilder, RecoveryPolicy, RetryOn, RetryPolicy};
+pub use burst::Burst;
+pub use channel::{BoundedReceiver, OverflowPolicy};
+pub use compare::FrameHash;
+pub use diagnostics::diagnostics;
+pub use display::{displays, DisplayInfo};
+pub use error::{CaptureError, InitError, PlatformCode, PlatformError};
+pub use format::PixelFormat;
+pub use frame::Frame;
+pub use latency::LatencyMode;
+pub use multi::{capture_all_displays_stitched, MultiCapturer};
+pub use permission::{permission_status, request_permission, PermissionStatus};
+pub use pool::{FramePool, PoolStats, PooledFrame};
+pub use priority::{ThreadOptions, ThreadPriority};
+pub use protected::ProtectedPolicy;
+pub use rate::{FrameRateConverter, OutputRate, ResampleStrategy};
+pub use scale::ScaleFilter;
+pub use screenshot::{screenshot, screenshot_all};
+pub use split::{FrameProducer, FrameReceiver};
+pub use stats::CapturerStats;
+pub use sync::SyncMode;
+pub use threaded::ThreadedCapturer;
+pub use window::{Visibility, WindowId};
+pub use yuv::{YuvFormat, YuvFrame};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Color represented by additive channels: Blue (b), Green (g), Red (r), and Alpha (a)
 ///
-/// A fourth field of padding makes this struct 4 bytes.
-#[cfg(not(windows))]
-pub type Bgr8 = x11cap::Bgr8;
-
-#[derive(Clone, Debug)]
-pub enum CaptureError {
-    /// Could not duplicate output, access denied. Might be in protected fullscreen.
-    #[cfg(windows)]
-    AccessDenied,
-    /// Access to the duplicated output was lost. Likely, mode was changed e.g. window => full
-    AccessLost,
-    /// Error when trying to refresh outputs after some failure.
-    #[cfg(windows)]
-    RefreshFailure,
-    /// Aquisition of next frame timed out.
-    Timeout,
-    /// General/Unexpected failure
-    Fail(String),
+/// In captured frames, alpha is only meaningful on Windows. On other platforms
+/// it's padding that makes this struct 4 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Bgr8 {
+    pub b: u8,
+    pub g: u8,
+    pub r: u8,
+    pub a: u8,
+}
+
+impl Bgr8 {
+    /// Construct an opaque color
+    pub const fn new(r: u8, g: u8, b: u8) -> Bgr8 {
+        Bgr8 { b, g, r, a: 255 }
+    }
 }
 
-/// A screen capturer.
-///
-/// Can capture video frames with reasonable performance for
-/// screenshooting, recording, streaming, etc.
 #[cfg(windows)]
-pub struct Capturer {
-    dxgi_manager: dxgcap::DXGIManager,
-    width: usize,
-    height: usize,
-    image: Option<Vec<Bgr8>>,
+impl From<dxgcap::BGRA8> for Bgr8 {
+    fn from(p: dxgcap::BGRA8) -> Bgr8 {
+        Bgr8 {
+            b: p.b,
+            g: p.g,
+            r: p.r,
+            a: p.a,
+        }
+    }
+}
+
+/// A rectangular region of a frame, in pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
 }
 
+/// What to capture
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CaptureSource {
+    /// A monitor, by index
+    Monitor(usize),
+    /// A monitor, by the name or monitor name of its display, e.g. `DP-1` or
+    /// `DELL U2720Q`. See `DisplayInfo`.
+    Name(String),
+    /// A single window, following it when it's moved or resized
+    Window(WindowId),
+    /// All monitors, stitched into one frame of their bounding box at their
+    /// positions. Gaps between monitors are black.
+    VirtualDesktop,
+    /// The whole screen of the X server, spanning all monitors as laid out
+    /// by it, read in one piece. On other platforms, and in Wayland sessions,
+    /// like `Self::VirtualDesktop`.
+    Screen,
+}
+
+/// What happened in an iteration of capture, or a change of the capture
+/// source, see `Capturer::poll_event`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CaptureEvent {
+    /// A new frame was captured and stored, see `Capturer::get_stored_frame`
+    NewFrame { sequence: u64 },
+    /// No new frame was presented before the timeout
+    NoChange,
+    /// The size of the captured frames changed, e.g. because the resolution
+    /// of the display changed or the window was resized
+    GeometryChanged { new: (u32, u32) },
+    /// Access to the capture source was lost, e.g. because the display mode
+    /// changed. See `Capturer::reacquire`.
+    SourceLost,
+    /// Access to the capture source was lost and reacquired, e.g. with
+    /// `Capturer::set_auto_recover`
+    Recovered,
+    /// The image of the cursor changed, while tracking it with
+    /// `Capturer::set_track_cursor`. See `Capturer::cursor_image`.
+    CursorShapeChanged { serial: u64 },
+}
+
+/// Hook processing captured frames in place, see `Capturer::set_frame_hook`
+type FrameHook = Box<dyn FnMut(&mut Frame) + Send>;
+
 /// A screen capturer.
 ///
 /// Can capture video frames with reasonable performance for
 /// screenshooting, recording, streaming, etc.
-#[cfg(not(windows))]
+///
+/// A capturer is `Send`, so it can be constructed on one thread and moved to
+/// a capture thread, but not `Sync`. The thread owning it is the only one
+/// using its connections to the display server or GPU.
 pub struct Capturer {
-    x11_capturer: x11cap::Capturer,
-    pub image: Option<x11cap::Image>,
+    backend: Box<dyn Backend>,
+    redactions: Vec<(Rect, redact::Redaction)>,
+    excluded: window::ExcludedWindows,
+    /// The window the capture region follows, if any
+    followed: Option<window::FollowedWindow>,
+    overlay: Option<overlay::Overlay>,
+    clock: frame::FrameClock,
+    cursor: cursor::CursorState,
+    frame_interval: Option<Duration>,
+    timeout: Duration,
+    latency_mode: LatencyMode,
+    /// Time the last captured frame took to acquire and process
+    latency: Option<Duration>,
+    retry: Option<RetryPolicy>,
+    /// Whether to reacquire the capture source when access to it is lost
+    auto_recover: bool,
+    pixel_format: PixelFormat,
+    /// Whether to rotate frames of rotated displays upright
+    upright: bool,
+    /// How HDR frames are tone mapped, if captured
+    hdr: Option<hdr::ToneMapping>,
+    /// Whether to convert frames from the color space of the display to sRGB
+    color_managed: bool,
+    /// The ICC profile of the display, if color managed and it has one
+    display_profile: Option<color::IccProfile>,
+    /// Whether the capture source is released, see `Self::pause`
+    paused: bool,
+    /// Events not yet polled, oldest first
+    events: VecDeque<CaptureEvent>,
+    /// Whether to fail captures of frames without new content
+    skip_duplicates: bool,
+    protected: ProtectedPolicy,
+    /// Frame stored in place of a capture that failed, with its width and
+    /// height, e.g. black while protected content is displayed
+    substitute: Option<(Vec<Bgr8>, u32, u32)>,
+    /// Timing of the vertical blanks to capture after, if synced to them
+    vblank: Option<sync::VBlank>,
+    /// Size of the last captured frame
+    last_geometry: Option<(u32, u32)>,
+    /// The region of the capture source to capture, if not all of it
+    region: Option<Rect>,
+    /// The bounding box of the rectangles to capture, and the rectangles
+    /// relative to it, if masked
+    mask: Option<(Rect, Vec<Rect>)>,
+    /// Whether the backend captures only `region`, so that frames need not
+    /// be cropped
+    native_region: bool,
+    /// Whether to crop the black borders of frames
+    auto_crop: bool,
+    /// Whether the borders to crop are detected for the capture source
+    crop_detected: bool,
+    /// The size and filter to scale frames to, if any
+    output_size: Option<(u32, u32, ScaleFilter)>,
+    /// The size of the thumbnails attached to frames, if any
+    thumbnail_size: Option<(u32, u32)>,
+    /// Copy of the frame of the backend with the cursor drawn, the
+    /// redactions applied, and scaled, if any
+    processed: Option<Vec<Bgr8>>,
+    /// Frame to scale, if it must be processed before being scaled
+    unscaled: Vec<Bgr8>,
+    /// Called with each captured frame to process it in place, if set
+    frame_hook: Option<FrameHook>,
+    /// Copy of the last frame returned by `Self::capture_frame_changed`, if
+    /// compared to detect changes
+    last_changed: Vec<Bgr8>,
+    stats: CapturerStats,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::CaptureMetrics,
+    /// Pool to convert and scale frames on, if not the global one
+    #[cfg(feature = "rayon")]
+    pool: Option<std::sync::Arc<rayon::ThreadPool>>,
 }
 
 impl Capturer {
     /// Construct a new capturer for a given capture source, e.g. a display.
-    #[cfg(windows)]
-    pub fn new(capture_src: usize) -> Result<Capturer, String> {
-        Capturer::new_with_timeout(capture_src, Duration::from_millis(200))
-    }
-
-    /// Windows only, does nothing on other platforms. Construct a new capturer for a given capture source, e.g. a display, with a given timeout.
-    #[cfg(windows)]
-    pub fn new_with_timeout(capture_src: usize, timeout: Duration) -> Result<Capturer, String> {
-        (timeout.as_secs() as u32)
-            .checked_mul(1000)
-            .and_then(|ms| ms.checked_add(timeout.subsec_millis()))
-            .ok_or(
-                "Failed to convert the given duration to a legal u32 millisecond value due to \
-                    integer overflow.",
-            )
-            .and_then(|timeout| {
-                dxgcap::DXGIManager::new(timeout).map(|mut mgr| {
-                    mgr.set_capture_source_index(capture_src);
-                    Capturer {
-                        dxgi_manager: mgr,
-                        width: 0,
-                        height: 0,
-                        image: None,
-                    }
-                })
-            })
-            .map_err(|err| err.to_owned())
+    ///
+    /// The backend is chosen at runtime, see `backend::default_backend`.
+    pub fn new(capture_src: usize) -> Result<Capturer, InitError> {
+        backend::default_backend(capture_src).map(Capturer::with_backend)
     }
 
-    /// Construct a new capturer for a given capture source, e.g. a display.
-    #[cfg(not(windows))]
-    pub fn new(capture_src: usize) -> Result<Capturer, String> {
-        x11cap::Capturer::new(x11cap::CaptureSource::Monitor(capture_src))
-            .map(|c| Capturer {
-                x11_capturer: c,
-                image: None,
-            })
-            .map_err(|()| "Failed to initialize capturer".to_string())
+    /// Construct a new capturer capturing a single window
+    ///
+    /// The frames are of the window's contents, and change size when the
+    /// window is resized. See `WindowId::find` to find a window by title.
+    pub fn new_window(window: WindowId) -> Result<Capturer, InitError> {
+        backend::window_backend(window).map(Capturer::with_backend)
     }
 
-    /// Windows only, does nothing on other platforms. Construct a new capturer for a given capture source, e.g. a display, with a given timeout.
-    #[cfg(not(windows))]
-    pub fn new_with_timeout(_capture_src: usize, _timeout: Duration) -> Result<Capturer, String> {
-        Err("Windows only method. Does nothing on other platforms.".to_string())
+    /// Construct a new capturer for the monitor with a name, e.g.
+    /// `DELL U2720Q`, see `CaptureSource::Name`
+    ///
+    /// Fails with `InitError::NotFound`, listing the names of the displays,
+    /// if no display has the name.
+    pub fn new_by_name(name: &str) -> Result<Capturer, InitError> {
+        Capturer::from_source(CaptureSource::Name(name.to_string()))
     }
 
-    /// Returns the width and height of the area to capture
-    #[cfg(windows)]
-    pub fn geometry(&self) -> (u32, u32) {
-        let (w, h) = self.dxgi_manager.geometry();
-        (w as u32, h as u32)
+    /// Construct a new capturer for a monitor, a window, or the virtual
+    /// desktop
+    pub fn from_source(source: CaptureSource) -> Result<Capturer, InitError> {
+        backend::source_backend(source).map(Capturer::with_backend)
+    }
+
+    /// Construct a new capturer capturing a window selected by the user
+    /// through the ScreenCast portal, e.g. a native Wayland window
+    #[cfg(all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "android"),
+        feature = "wayland"
+    ))]
+    pub fn select_window() -> Result<Capturer, InitError> {
+        wayland::Session::select_window().map(|session| Capturer::with_backend(Box::new(session)))
+    }
+
+    /// Construct a new capturer capturing with a given backend, e.g. a custom
+    /// one
+    pub fn with_backend(backend: Box<dyn Backend>) -> Capturer {
+        Capturer {
+            backend,
+            redactions: Vec::new(),
+            excluded: Default::default(),
+            followed: None,
+            overlay: None,
+            clock: frame::FrameClock::default(),
+            cursor: cursor::CursorState::default(),
+            frame_interval: None,
+            timeout: Duration::from_millis(200),
+            latency_mode: LatencyMode::Throughput,
+            latency: None,
+            retry: None,
+            auto_recover: false,
+            pixel_format: PixelFormat::Bgra8,
+            upright: true,
+            hdr: None,
+            color_managed: false,
+            display_profile: None,
+            paused: false,
+            events: VecDeque::new(),
+            skip_duplicates: false,
+            protected: ProtectedPolicy::Error,
+            substitute: None,
+            vblank: None,
+            last_geometry: None,
+            region: None,
+            mask: None,
+            native_region: false,
+            auto_crop: false,
+            crop_detected: false,
+            output_size: None,
+            thumbnail_size: None,
+            processed: None,
+            unscaled: Vec::new(),
+            frame_hook: None,
+            last_changed: Vec::new(),
+            stats: CapturerStats::default(),
+            #[cfg(feature = "metrics")]
+            metrics: crate::metrics::CaptureMetrics::new(),
+            #[cfg(feature = "rayon")]
+            pool: None,
+        }
+    }
+
+    /// Construct a new capturer for a given capture source, e.g. a display,
+    /// with a given timeout. See `Self::set_timeout`.
+    pub fn new_with_timeout(capture_src: usize, timeout: Duration) -> Result<Capturer, InitError> {
+        Capturer::builder()
+            .source(capture_src)
+            .timeout(timeout)
+            .build()
+    }
+
+    /// Name of the backend capturing the frames
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
     }
 
-    /// Returns the width and height of the area to capture
-    #[cfg(not(windows))]
+    /// Set how long to wait for a new frame before timing out
+    ///
+    /// Captures time out with `CaptureError::Timeout` when the screen is
+    /// still. On X11, this requires the XDamage extension, without which
+    /// frames are captured immediately.
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), InitError> {
+        #[cfg(windows)]
+        dxgi::timeout_ms(timeout).map_err(|e| InitError::InvalidArgument(e.to_string()))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// How long to wait for a new frame before timing out
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns the width and height of the captured frames
+    ///
+    /// This is the size of the capture region, if one is set, and the
+    /// output size, if frames are scaled.
     pub fn geometry(&self) -> (u32, u32) {
-        let geo = self.x11_capturer.get_geometry();
-        (geo.width, geo.height)
+        match self.output_size {
+            Some((width, height, _)) => (width, height),
+            None => self.source_geometry(),
+        }
+    }
+
+    /// Width and height of the area to capture, before scaling
+    pub(crate) fn source_geometry(&self) -> (u32, u32) {
+        match self.cropped_region() {
+            Some(region) => (region.width, region.height),
+            None => self.backend.geometry(),
+        }
     }
 
     /// Returns the horizontal and vertical offset of the capture source
     /// from the primary display.
-    #[cfg(not(windows))]
+    ///
+    /// This is the offset of the capture region, if one is set.
     pub fn position(&self) -> (i32, i32) {
-        let geo = self.x11_capturer.get_geometry();
-        (geo.x, geo.y)
+        let (x, y) = self.backend.position();
+        match self.region {
+            Some(region) => (x + region.x as i32, y + region.y as i32),
+            None => (x, y),
+        }
     }
 
-    /// Capture screen and return an owned `Vec` of the image color data
+    /// Capture only a region of the capture source, in pixels relative to
+    /// its top left corner
     ///
-    /// On Windows there's no performance difference between doing
-    /// `self.capture_frame` and `self.capture_store_frame(); self.get_stored_frame()`
-    #[cfg(windows)]
-    pub fn capture_frame(&mut self) -> Result<Vec<Bgr8>, CaptureError> {
-        use dxgcap::CaptureError::*;
+    /// Backends read only the region where supported, e.g. with
+    /// `XShmGetImage` of the region on X11, and the captured frames are
+    /// cropped otherwise. Either way, the frames and `Self::geometry` are
+    /// of the region.
+    pub fn set_capture_region(&mut self, region: Rect) -> Result<(), InitError> {
+        self.backend.set_region(None);
+        let (width, height) = self.backend.geometry();
+        if region.width == 0
+            || region.height == 0
+            || region
+                .x
+                .checked_add(region.width)
+                .is_none_or(|right| right > width)
+            || region
+                .y
+                .checked_add(region.height)
+                .is_none_or(|bottom| bottom > height)
+        {
+            self.region = None;
+            return Err(InitError::InvalidArgument(format!(
+                "Region {:?} is not within the {}x{} capture source",
+                region, width, height
+            )));
+        }
+        self.native_region = self.backend.set_region(Some(region));
+        self.region = Some(region);
+        self.processed = None;
+        Ok(())
+    }
 
-        match self.dxgi_manager.capture_frame() {
-            Ok((data, (w, h))) => {
-                self.width = w;
-                self.height = h;
-                Ok(data)
-            }
-            Err(AccessDenied) => Err(CaptureError::AccessDenied),
-            Err(AccessLost) => Err(CaptureError::AccessLost),
-            Err(RefreshFailure) => Err(CaptureError::RefreshFailure),
-            Err(Timeout) => Err(CaptureError::Timeout),
-            Err(Fail(e)) => Err(CaptureError::Fail(e.to_string())),
+    /// Capture all of the capture source again
+    pub fn clear_capture_region(&mut self) {
+        self.backend.set_region(None);
+        self.region = None;
+        self.mask = None;
+        self.processed = None;
+    }
+
+    /// The region of the capture source to capture, if not all of it
+    pub fn capture_region(&self) -> Option<Rect> {
+        self.region
+    }
+
+    /// Reacquire the capture source, e.g. after a capture failed with
+    /// `CaptureError::AccessLost` because the display mode changed
+    ///
+    /// Re-creates the desktop duplication on Windows, and the connection to
+    /// the X server on X11. The capture region is kept if it is still within
+    /// the capture source, and cleared otherwise.
+    pub fn reacquire(&mut self) -> Result<(), CaptureError> {
+        let res = self.backend.reacquire();
+        #[cfg(feature = "tracing")]
+        trace::reacquired(&res);
+        res?;
+        self.crop_detected = false;
+        if let Some(region) = self.region {
+            // Clears the region if it no longer fits
+            let _ = self.set_capture_region(region);
         }
+        Ok(())
     }
 
-    /// Capture screen and return an owned `Vec` of the image color data in bgr format
-    #[cfg(windows)]
-    pub fn capture_frame_components(&mut self) -> Result<Vec<u8>, CaptureError> {
-        use dxgcap::CaptureError::*;
+    /// Reacquire the capture source before retrying a capture that failed
+    /// with `CaptureError::AccessLost`
+    fn reacquire_lost(&mut self, err: &CaptureError) -> Result<(), CaptureError> {
+        if let CaptureError::AccessLost = err {
+            self.reacquire()?;
+            self.stats.recoveries += 1;
+            self.push_event(CaptureEvent::Recovered);
+        }
+        Ok(())
+    }
 
-        match self.dxgi_manager.capture_frame_components() {
-            Ok((data, (w, h))) => {
-                self.width = w;
-                self.height = h;
-                Ok(data)
+    /// The retry policy of captures, retrying lost access at least once with
+    /// `Self::set_auto_recover`
+    fn effective_retry(&self) -> Option<RetryPolicy> {
+        let mut retry = self.retry;
+        if self.auto_recover {
+            let retry = retry.get_or_insert(RetryPolicy {
+                max_attempts: 1,
+                backoff: Backoff::None,
+                retry_on: RetryOn::NONE,
+            });
+            retry.retry_on.access_lost = true;
+            retry.max_attempts = retry.max_attempts.max(2);
+        }
+        retry
+    }
+
+    /// Release the capture source until `Self::resume`, e.g. while the
+    /// application is minimized, keeping the configuration of the capturer
+    ///
+    /// Releases the desktop duplication with DXGI, stops the display stream
+    /// on macOS, and pauses the PipeWire stream on Wayland, keeping the
+    /// screen cast session so that the user is not asked again. Captures
+    /// fail with `CaptureError::Paused` while paused.
+    pub fn pause(&mut self) {
+        if !self.paused {
+            self.backend.pause();
+            self.paused = true;
+        }
+    }
+
+    /// Acquire the capture source released by `Self::pause` again
+    ///
+    /// The capture region is kept if it is still within the capture source,
+    /// and cleared otherwise. If resuming fails, the capturer stays paused.
+    pub fn resume(&mut self) -> Result<(), CaptureError> {
+        if self.paused {
+            self.backend.resume()?;
+            self.paused = false;
+            self.crop_detected = false;
+            if let Some(region) = self.region {
+                // Clears the region if it no longer fits
+                let _ = self.set_capture_region(region);
             }
-            Err(AccessDenied) => Err(CaptureError::AccessDenied),
-            Err(AccessLost) => Err(CaptureError::AccessLost),
-            Err(RefreshFailure) => Err(CaptureError::RefreshFailure),
-            Err(Timeout) => Err(CaptureError::Timeout),
-            Err(Fail(e)) => Err(CaptureError::Fail(e.to_string())),
         }
+        Ok(())
     }
 
-    /// Capture screen and store in `self` for later retreival
-    #[cfg(windows)]
-    pub fn capture_store_frame(&mut self) -> Result<(), CaptureError> {
-        use dxgcap::CaptureError::*;
-
-        match self.dxgi_manager.capture_frame() {
-            Ok((data, (w, h))) => {
-                self.image = Some(data);
-                self.width = w;
-                self.height = h;
-                Ok(())
+    /// Whether the capture source is released by `Self::pause`
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Switch to capturing the monitor with the given index, keeping the
+    /// configuration of the capturer
+    ///
+    /// The capture region is kept if it is within the new monitor, and
+    /// cleared otherwise. The change of size, if any, is reported by
+    /// `Self::poll_event` with the next frame.
+    pub fn set_capture_source(&mut self, capture_src: usize) -> Result<(), InitError> {
+        match self.backend.set_source(capture_src) {
+            Some(res) => res?,
+            None => {
+                self.backend = backend::default_backend(capture_src)?;
+                self.backend.set_upright(self.upright);
+                self.backend.set_immediate(self.low_latency());
+                if self.hdr.is_some() {
+                    // Captures 8-bit frames if the new backend can't do HDR
+                    let _ = self.backend.set_hdr(self.hdr);
+                }
             }
-            Err(AccessDenied) => Err(CaptureError::AccessDenied),
-            Err(AccessLost) => Err(CaptureError::AccessLost),
-            Err(RefreshFailure) => Err(CaptureError::RefreshFailure),
-            Err(Timeout) => Err(CaptureError::Timeout),
-            Err(Fail(e)) => Err(CaptureError::Fail(e.to_string())),
         }
+        self.native_region = false;
+        self.crop_detected = false;
+        self.processed = None;
+        if let Some(region) = self.region {
+            // Clears the region if it doesn't fit
+            let _ = self.set_capture_region(region);
+        }
+        if self.color_managed {
+            self.load_display_profile();
+        }
+        Ok(())
+    }
+
+    /// Switch to capturing the monitor with the given name, as
+    /// `DisplayInfo::name` or `DisplayInfo::monitor_name`. See
+    /// `Self::set_capture_source`.
+    pub fn set_capture_source_by_name(&mut self, name: &str) -> Result<(), InitError> {
+        self.set_capture_source(display::display_index(name)?)
+    }
+
+    /// The oldest event that has not been polled yet, or else the event of
+    /// capturing a frame, waiting up to `timeout` for a new one
+    ///
+    /// A loop of polled events tells what happened in each iteration: a new
+    /// frame, no change, or a lost capture source, preceded by the changes
+    /// detected by the capture, e.g. `CaptureEvent::Recovered` and
+    /// `CaptureEvent::GeometryChanged`. Failures other than a lost source or
+    /// a timeout are returned as errors.
+    pub fn poll_event(&mut self, timeout: Duration) -> Result<CaptureEvent, CaptureError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        let timeout = std::mem::replace(&mut self.timeout, timeout);
+        let res = self.capture_store_frame();
+        self.timeout = timeout;
+        let event = match res {
+            Ok(()) => CaptureEvent::NewFrame {
+                sequence: self.clock.sequence,
+            },
+            Err(CaptureError::Timeout) | Err(CaptureError::NoNewFrame) => CaptureEvent::NoChange,
+            Err(CaptureError::AccessLost) => CaptureEvent::SourceLost,
+            Err(e) => return Err(e),
+        };
+        self.events.push_back(event);
+        Ok(self.events.pop_front().unwrap())
+    }
+
+    /// The oldest change of the capture source that has not been polled yet,
+    /// if any, without capturing
+    ///
+    /// Changes are detected when frames are captured. Only the latest
+    /// event of each kind is kept.
+    pub fn pending_event(&mut self) -> Option<CaptureEvent> {
+        self.events.pop_front()
+    }
+
+    /// Queue an event, replacing the pending one of the same kind, if any
+    fn push_event(&mut self, event: CaptureEvent) {
+        let kind = std::mem::discriminant(&event);
+        self.events.retain(|e| std::mem::discriminant(e) != kind);
+        self.events.push_back(event);
+    }
+
+    /// The capture region, if frames of the backend must be cropped to it,
+    /// clipped to the capture source
+    fn cropped_region(&self) -> Option<Rect> {
+        let (width, height) = self.backend.geometry();
+        self.region
+            .filter(|_| !self.native_region)
+            .map(|region| roi::clip(region, width, height))
     }
 
     /// Capture screen and return an owned `Vec` of the image color data
     ///
     /// Worse performance than `self.capture_store_frame(); self.get_stored_frame()`
     /// due to an extra `.to_vec()` call.
-    #[cfg(not(windows))]
     pub fn capture_frame(&mut self) -> Result<Vec<Bgr8>, CaptureError> {
+        self.capture_store_frame()?;
+        let start = Instant::now();
+        let frame = self.get_stored_frame().unwrap().to_vec();
+        self.stats.copy_time += start.elapsed();
+        Ok(frame)
+    }
+
+    /// Capture screen and borrow the image color data stored in `self`
+    ///
+    /// Equivalent to `self.capture_store_frame(); self.get_stored_frame()`.
+    /// Backends reuse their buffers, so with e.g. DXGI desktop duplication
+    /// this does not allocate per frame.
+    pub fn capture_frame_borrowed(&mut self) -> Result<&[Bgr8], CaptureError> {
+        self.capture_store_frame()?;
+        Ok(self.get_stored_frame().unwrap())
+    }
+
+    /// Capture screen and return an owned `Frame`, with its size, capture
+    /// timestamp, and sequence number
+    ///
+    /// Gaps in the sequence numbers of consecutive frames mean that frames
+    /// were captured but not retrieved, e.g. by another method.
+    pub fn capture_frame_meta(&mut self) -> Result<Frame, CaptureError> {
+        self.capture_store_frame()?;
+        let start = Instant::now();
+        let frame = self.stored_frame_meta();
+        self.stats.copy_time += start.elapsed();
+        Ok(frame)
+    }
+
+    /// Wait up to `timeout` for the screen to change, and capture it as
+    /// `Self::capture_frame_meta`. Fails with `CaptureError::Timeout` if it
+    /// didn't change.
+    ///
+    /// Blocks on the compositor where possible: DXGI frames with new
+    /// content, rather than only cursor updates, and XDamage on X11. With
+    /// other backends, frames are captured every 16 ms and compared to the
+    /// last frame returned.
+    pub fn capture_frame_changed(&mut self, timeout: Duration) -> Result<Frame, CaptureError> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(16);
+        let deadline = Instant::now() + timeout;
+        let capture_timeout = self.timeout;
+        let res = loop {
+            self.timeout = deadline.saturating_duration_since(Instant::now());
+            match self.capture_store_frame() {
+                Ok(()) if self.stored_frame_changed() => break Ok(self.stored_frame_meta()),
+                Ok(()) | Err(CaptureError::Timeout | CaptureError::NoNewFrame)
+                    if Instant::now() < deadline =>
+                {
+                    if self.backend.content_changed().is_none()
+                        && self.backend.dirty_rects().is_none()
+                    {
+                        // The backend doesn't wait for changes
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        std::thread::sleep(POLL_INTERVAL.min(remaining));
+                    }
+                }
+                Ok(()) | Err(CaptureError::NoNewFrame) => break Err(CaptureError::Timeout),
+                Err(e) => break Err(e),
+            }
+        };
+        self.timeout = capture_timeout;
+        res
+    }
+
+    /// Whether the backend reported that the stored frame has the same
+    /// content as the previous one
+    ///
+    /// Frames with the cursor, hidden regions, or an overlay drawn over them
+    /// are never duplicates, as what's drawn may have changed.
+    fn stored_frame_duplicate(&self) -> bool {
+        if self.draws_over() {
+            return false;
+        }
+        match self.backend.content_changed() {
+            Some(changed) => !changed,
+            None => self.backend.dirty_rects().is_some_and(|r| r.is_empty()),
+        }
+    }
+
+    /// Whether the stored frame has new content, as reported by the backend
+    /// or compared to the last frame returned by
+    /// `Self::capture_frame_changed`
+    fn stored_frame_changed(&mut self) -> bool {
+        if let Some(changed) = self.backend.content_changed() {
+            return changed;
+        }
+        if let Some(rects) = self.backend.dirty_rects() {
+            return !rects.is_empty();
+        }
+        let frame = match &self.processed {
+            Some(frame) => frame,
+            None => self
+                .backend
+                .frame()
+                .map_or(&[][..], |(pixels, _, _)| pixels),
+        };
+        let changed = self.last_changed.as_slice() != frame;
+        if changed {
+            self.last_changed.clear();
+            self.last_changed.extend_from_slice(frame);
+        }
+        changed
+    }
+
+    /// The stored frame as an owned `Frame`, with its metadata
+    fn stored_frame_meta(&self) -> Frame {
+        self.stored_frame_meta_in(Vec::new())
+    }
+
+    /// `Self::stored_frame_meta`, copying the pixels into `buf` to reuse its
+    /// allocation
+    pub(crate) fn stored_frame_meta_in(&self, mut buf: Vec<Bgr8>) -> Frame {
+        let (pixels, width, height) = self.stored_frame_with_size().unwrap();
+        let thumbnail = self.thumbnail(pixels, width, height);
+        buf.clear();
+        buf.extend_from_slice(pixels);
+        let frame = self.with_frame_meta(Frame::new(buf, width, height));
+        match thumbnail {
+            Some(thumbnail) => frame.with_thumbnail(thumbnail),
+            None => frame,
+        }
+    }
+
+    /// A frame of the pixels of the stored frame, with its metadata
+    fn with_frame_meta(&self, frame: Frame) -> Frame {
+        let mut frame = frame.with_sequence(self.clock.sequence);
+        if let Some(timestamp) = self.clock.timestamp {
+            frame = frame.with_timestamp(timestamp);
+        }
+        if let Some(present_time) = self.backend.present_time() {
+            frame = frame.with_present_time(present_time);
+        }
+        if let Some(dirty_rects) = self.dirty_rects() {
+            frame = frame.with_dirty_rects(dirty_rects);
+        }
+        if let Some(cursor) = self.cursor.frame_cursor(self.position()) {
+            frame = frame.with_cursor(cursor);
+        }
+        frame = frame
+            .with_rotation(self.backend.rotation())
+            .with_color_space(self.color_space())
+            .with_duplicate(self.stored_frame_duplicate());
+        if let Some(latency) = self.latency {
+            frame = frame.with_latency(latency);
+        }
+        if let Some(mask) = self.frame_mask() {
+            frame = frame.with_mask(mask);
+        }
+        if let Some(visibility) = self.backend.visibility() {
+            frame = frame.with_visibility(visibility);
+        }
+        // The DMA-BUF would leak the hidden regions
+        #[cfg(target_os = "linux")]
+        if let Some(dmabuf) = self.backend.dmabuf().filter(|_| !self.hides_regions()) {
+            frame = frame.with_dmabuf(dmabuf);
+        }
+        frame
+    }
+
+    /// Capture screen and return an owned `Vec` of the image color data in bgr format
+    ///
+    /// Each pixel is 4 bytes: blue, green, red, and alpha or padding,
+    /// regardless of `Self::set_pixel_format`. See
+    /// `Self::capture_frame_converted` for frames in that format.
+    pub fn capture_frame_components(&mut self) -> Result<Vec<u8>, CaptureError> {
         self.capture_store_frame()
-            .map(|_| self.get_stored_frame().unwrap().to_vec())
+            .map(|_| pixel_bytes(self.get_stored_frame().unwrap()).to_vec())
+    }
+
+    /// Capture screen and return the color components of the pixels in the
+    /// format set with `Self::set_pixel_format`
+    ///
+    /// Always `PixelFormat::Bgra8` with `LatencyMode::LowLatency`.
+    pub fn capture_frame_converted(&mut self) -> Result<Vec<u8>, CaptureError> {
+        if self.low_latency() {
+            return self.capture_frame_format(PixelFormat::Bgra8);
+        }
+        self.capture_frame_format(self.pixel_format)
     }
 
     /// Capture screen and store in `self` for later retreival
@@ -202,21 +919,408 @@ impl Capturer {
     /// `Self::capture_frame`.
     ///
     /// Recommended over `Self::capture_frame` unless an owned `Vec` is required.
-    #[cfg(not(windows))]
     pub fn capture_store_frame(&mut self) -> Result<(), CaptureError> {
-        match self.x11_capturer.capture_frame() {
-            Ok(image) => {
-                self.image = Some(image);
-                Ok(())
+        if self.paused {
+            return Err(CaptureError::Paused);
+        }
+        #[cfg(feature = "tracing")]
+        let span = trace::capture_span(self.backend.name());
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        if !self.low_latency() {
+            self.pace();
+            self.wait_vblank();
+        }
+        self.follow();
+        // Detected again with the next capture if it fails
+        let _ = self.detect_crop();
+        let retry = self.effective_retry();
+        let mut retries = 0;
+        let acquire = Instant::now();
+        let res = loop {
+            let start = Instant::now();
+            let res = self.acquire_frame();
+            self.stats.acquire_time += start.elapsed();
+            #[cfg(feature = "tracing")]
+            trace::acquired(
+                &res,
+                start.elapsed(),
+                self.backend.dirty_rects().map(<[_]>::len),
+            );
+            match res {
+                Err(e) if retry.is_some_and(|r| r.should_retry(&e, retries)) => {
+                    retries += 1;
+                    if let Err(e) = self.reacquire_lost(&e) {
+                        diagnostics::record_error(&e);
+                        break Err(e);
+                    }
+                }
+                Err(e) => {
+                    diagnostics::record_error(&e);
+                    break Err(e);
+                }
+                res => break res,
+            }
+        };
+        self.stats.record(&res);
+        if res.is_ok() {
+            self.frame_captured();
+            let start = Instant::now();
+            self.processed = if self.needs_postprocess() {
+                let mut frame = self.processed.take().unwrap_or_default();
+                let (pixels, w, h) = self.backend.frame().unwrap();
+                let region = self.cropped_region().map_or(
+                    Rect {
+                        x: 0,
+                        y: 0,
+                        width: w,
+                        height: h,
+                    },
+                    |region| roi::clip(region, w, h),
+                );
+                match self.output_size {
+                    // Scale while copying
+                    Some((ow, oh, filter))
+                        if !self.draws_over() && self.capture_mask().is_none() =>
+                    {
+                        self.in_pool(|| {
+                            scale::scale(pixels, w as usize, region, (ow, oh), filter, &mut frame)
+                        })
+                    }
+                    output_size => {
+                        let mut unscaled = std::mem::take(&mut self.unscaled);
+                        if let Some(mask) = self.capture_mask() {
+                            mask::copy_masked(pixels, w as usize, region, mask, &mut unscaled);
+                        } else {
+                            unscaled.clear();
+                            for y in region.y..region.y + region.height {
+                                let start = (y * w + region.x) as usize;
+                                unscaled.extend_from_slice(
+                                    &pixels[start..start + region.width as usize],
+                                );
+                            }
+                        }
+                        self.postprocess(&mut unscaled, region.width, region.height);
+                        if let Some((ow, oh, filter)) = output_size {
+                            let full = Rect {
+                                x: 0,
+                                y: 0,
+                                ..region
+                            };
+                            let stride = region.width as usize;
+                            self.in_pool(|| {
+                                scale::scale(&unscaled, stride, full, (ow, oh), filter, &mut frame)
+                            });
+                        } else {
+                            std::mem::swap(&mut frame, &mut unscaled);
+                        }
+                        self.unscaled = unscaled;
+                    }
+                }
+                if let Some(profile) = &self.display_profile {
+                    color::ColorTransform::apply(profile, &mut frame);
+                }
+                Some(frame)
+            } else {
+                None
+            };
+            self.stats.copy_time += start.elapsed();
+            #[cfg(feature = "tracing")]
+            {
+                span.record("sequence", self.clock.sequence);
+                trace::copied(start.elapsed(), self.geometry(), self.processed.is_some());
             }
-            Err(x11cap::CaptureError::Fail(e)) => Err(CaptureError::Fail(e.to_string())),
+            self.run_frame_hook();
+            self.check_geometry();
+            self.latency = Some(acquire.elapsed());
         }
+        let res = match res {
+            Ok(()) if self.skip_duplicates && self.stored_frame_duplicate() => {
+                Err(CaptureError::NoNewFrame)
+            }
+            res => res,
+        };
+        let res = self.protected_fallback(res);
+        #[cfg(feature = "metrics")]
+        self.metrics.record(&res, self.stored_frame_bytes());
+        res
     }
 
     /// Get the last frame stored in `self` by `Self::capture_store_frame`,
     /// if one has ever been stored.
     pub fn get_stored_frame(&self) -> Option<&[Bgr8]> {
-        self.image.as_ref().map(|img| img.as_slice())
+        if let Some((frame, _, _)) = &self.substitute {
+            return Some(frame);
+        }
+        match &self.processed {
+            Some(frame) => Some(frame),
+            None => self.backend.frame().map(|(pixels, _, _)| pixels),
+        }
+    }
+
+    /// Get the last frame stored in `self` mutably, e.g. to draw on it before
+    /// it's read with `Self::get_stored_frame`
+    ///
+    /// Frames of the backend that are not processed, e.g. cropped or with
+    /// the cursor drawn, are copied on the first call after each capture.
+    /// See `Self::set_frame_hook` to process every frame in place.
+    pub fn get_stored_frame_mut(&mut self) -> Option<&mut [Bgr8]> {
+        if let Some((frame, _, _)) = &mut self.substitute {
+            return Some(frame);
+        }
+        if self.processed.is_none() {
+            let (pixels, _, _) = self.backend.frame()?;
+            self.processed = Some(pixels.to_vec());
+        }
+        self.processed.as_deref_mut()
+    }
+
+    /// Process every captured frame in place with `hook`, e.g. to watermark
+    /// or redact it, before it's stored and handed out
+    ///
+    /// The hook runs in the capture call, on the frame after it's cropped,
+    /// scaled, and has the cursor drawn, with the metadata of
+    /// `Self::capture_frame_meta`. Its pixels are those stored, so no other
+    /// copy is made, unless the frame of the backend would not have been
+    /// processed otherwise.
+    pub fn set_frame_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&mut Frame) + Send + 'static,
+    {
+        self.frame_hook = Some(Box::new(hook));
+        self.processed = None;
+    }
+
+    /// Stop processing frames with the hook set with `Self::set_frame_hook`
+    pub fn clear_frame_hook(&mut self) {
+        self.frame_hook = None;
+    }
+
+    /// Whether frames are processed with a hook, see `Self::set_frame_hook`
+    pub fn has_frame_hook(&self) -> bool {
+        self.frame_hook.is_some()
+    }
+
+    /// Run the frame hook, if any, on the processed frame
+    fn run_frame_hook(&mut self) {
+        if self.frame_hook.is_none() {
+            return;
+        }
+        let (width, height) = match self.stored_frame_with_size() {
+            Some((_, width, height)) => (width, height),
+            None => return,
+        };
+        let pixels = match self.processed.take() {
+            Some(pixels) => pixels,
+            None => return,
+        };
+        let mut frame = self.with_frame_meta(Frame::new(pixels, width, height));
+        if let Some(hook) = &mut self.frame_hook {
+            hook(&mut frame);
+        }
+        self.processed = Some(frame.into_pixels());
+    }
+
+    /// Get the color components of the last stored frame as bytes, 4 per
+    /// pixel in the order of `Bgr8`, e.g. for encoders taking BGRA
+    pub fn get_stored_frame_bytes(&self) -> Option<&[u8]> {
+        self.get_stored_frame().map(pixel_bytes)
+    }
+
+    /// Regions of the last stored frame that changed since the previous
+    /// captured frame, if the backend tracks damage
+    ///
+    /// Supported with XDamage on X11, and with `Windows.Graphics.Capture` on
+    /// Windows 11 24H2 and later, but not with DXGI desktop duplication.
+    /// Useful to only encode the changed parts of frames, e.g. for remote
+    /// desktop.
+    ///
+    /// The cursor and redactions are not included.
+    pub fn dirty_rects(&self) -> Option<Vec<Rect>> {
+        if self.substitute.is_some() {
+            return None;
+        }
+        let rects = self.backend.dirty_rects()?;
+        let (_, width, height) = self.backend.frame()?;
+        let rects = match self.cropped_region() {
+            Some(region) => {
+                let region = roi::clip(region, width, height);
+                rects
+                    .iter()
+                    .filter_map(|rect| intersection(*rect, region))
+                    .map(|rect| Rect {
+                        x: rect.x - region.x,
+                        y: rect.y - region.y,
+                        ..rect
+                    })
+                    .collect()
+            }
+            None => rects.to_vec(),
+        };
+        Some(match self.output_size {
+            Some((ow, oh, _)) => {
+                let size = self.source_geometry();
+                rects
+                    .into_iter()
+                    .map(|rect| scale::scale_rect(rect, size, (ow, oh)))
+                    .collect()
+            }
+            None => rects,
+        })
+    }
+
+    /// The last stored frame and its width and height
+    fn stored_frame_with_size(&self) -> Option<(&[Bgr8], u32, u32)> {
+        if let Some((frame, width, height)) = &self.substitute {
+            return Some((frame, *width, *height));
+        }
+        let (pixels, width, height) = self.backend.frame()?;
+        match &self.processed {
+            Some(frame) => {
+                let (width, height) = match (self.output_size, self.cropped_region()) {
+                    (Some((width, height, _)), _) => (width, height),
+                    (None, Some(region)) => {
+                        let region = roi::clip(region, width, height);
+                        (region.width, region.height)
+                    }
+                    (None, None) => (width, height),
+                };
+                Some((frame, width, height))
+            }
+            None => Some((pixels, width, height)),
+        }
+    }
+
+    /// Monotonic time at which the last frame was captured, if one has ever
+    /// been captured
+    ///
+    /// `Instant` is backed by `QueryPerformanceCounter` on Windows and
+    /// `CLOCK_MONOTONIC` on Linux. The backends do not expose presentation
+    /// times, so this is the time at which the frame was acquired.
+    pub fn frame_timestamp(&self) -> Option<Instant> {
+        self.clock.timestamp
+    }
+
+    /// Sequence number of the last captured frame, counting from 1, or 0 if
+    /// no frame has been captured
+    pub fn frame_sequence(&self) -> u64 {
+        self.clock.sequence
+    }
+
+    /// Estimate of the number of frames that a display of the given refresh
+    /// rate in Hz presented between the last two captures, but that were not
+    /// captured
+    ///
+    /// The backends do not report how many frames they accumulated, so this
+    /// is estimated from the capture timestamps.
+    pub fn skipped_frames(&self, refresh_rate: f64) -> u64 {
+        self.clock.skipped(refresh_rate)
+    }
+
+    /// Whether captured frames need to be cropped, or the cursor drawn or
+    /// regions hidden
+    fn needs_postprocess(&self) -> bool {
+        self.draws_over()
+            || self.cropped_region().is_some()
+            || self.capture_mask().is_some()
+            || self.output_size.is_some()
+            || self.display_profile.is_some()
+            || self.frame_hook.is_some()
+    }
+
+    /// Whether the cursor, regions hidden, or an overlay are drawn over
+    /// captured frames
+    fn draws_over(&self) -> bool {
+        self.cursor.include || self.hides_regions() || self.overlay.is_some()
+    }
+
+    /// Whether regions of captured frames are redacted or blacked out as
+    /// excluded windows
+    pub(crate) fn hides_regions(&self) -> bool {
+        !self.redactions.is_empty() || self.excluded.blacks_out()
+    }
+
+    /// Draw the cursor in, apply the redactions and exclusions to, and
+    /// composite the overlay onto a captured frame of the given size
+    fn postprocess(&mut self, frame: &mut [Bgr8], width: u32, height: u32) {
+        if self.cursor.include {
+            let origin = match self.cursor.origin {
+                Some((size, origin)) if size == (width, height) => origin,
+                _ => {
+                    // The position can only change along with the mode
+                    let origin = self.position();
+                    self.cursor.origin = Some(((width, height), origin));
+                    origin
+                }
+            };
+            self.cursor.draw(frame, width, height, origin);
+        }
+        redact::apply_mask(frame, width, height, &self.redactions);
+        let origin = self.position();
+        self.excluded.black_out(frame, (width, height), origin);
+        if let Some(overlay) = &self.overlay {
+            overlay.draw(frame, width, height);
+        }
+    }
+
+    /// Update the per-frame state after a frame is captured
+    fn frame_captured(&mut self) {
+        self.clock.tick();
+        self.vblank_captured();
+        if let Some(serial) = self.cursor.frame_captured() {
+            self.push_event(CaptureEvent::CursorShapeChanged { serial });
+        }
+    }
+
+    /// Queue a `CaptureEvent::GeometryChanged` if the size of the stored
+    /// frame differs from that of the previous frame
+    fn check_geometry(&mut self) {
+        let size = self.stored_frame_with_size().map(|(_, w, h)| (w, h));
+        if let (Some(old), Some(new)) = (self.last_geometry, size) {
+            if old != new {
+                self.push_event(CaptureEvent::GeometryChanged { new });
+            }
+        }
+        self.last_geometry = size;
+    }
+
+    #[cfg(feature = "metrics")]
+    fn stored_frame_bytes(&self) -> usize {
+        self.get_stored_frame().map_or(0, std::mem::size_of_val)
+    }
+}
+
+/// The intersection of two rectangles, if they overlap
+pub(crate) fn intersection(a: Rect, b: Rect) -> Option<Rect> {
+    let x = a.x.max(b.x);
+    let y = a.y.max(b.y);
+    let right = (a.x + a.width).min(b.x + b.width);
+    let bottom = (a.y + a.height).min(b.y + b.height);
+    (right > x && bottom > y).then_some(Rect {
+        x,
+        y,
+        width: right - x,
+        height: bottom - y,
+    })
+}
+
+/// View the color components of some pixels as plain bytes
+pub(crate) fn pixel_bytes(pixels: &[Bgr8]) -> &[u8] {
+    // `Bgr8` consists of 4 bytes without any padding, so its components can be
+    // viewed as plain bytes
+    unsafe {
+        std::slice::from_raw_parts(pixels.as_ptr() as *const u8, std::mem::size_of_val(pixels))
+    }
+}
+
+/// View the color components of some pixels as plain mutable bytes
+pub(crate) fn pixel_bytes_mut(pixels: &mut [Bgr8]) -> &mut [u8] {
+    // As above, and any bytes are valid components
+    unsafe {
+        std::slice::from_raw_parts_mut(
+            pixels.as_mut_ptr() as *mut u8,
+            std::mem::size_of_val(pixels),
+        )
     }
 }
 
@@ -267,4 +1371,4 @@ mod captrs_tests_not_windows {
         // should be width * height * $ (RGBA)
         assert_eq!((w * h) as usize, frame.len())
     }
-}
\ No newline at end of file
+}
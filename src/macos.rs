@@ -0,0 +1,251 @@
+//! Capture on macOS with `CGDisplayStream`
+//!
+//! Frames are delivered by CoreGraphics on a dispatch queue as `IOSurface`s,
+//! and the latest one is copied out for the capturer.
+//!
+//! `CGDisplayStream` is deprecated in favor of ScreenCaptureKit as of macOS
+//! 14, but is still available, and works on all versions of macOS since
+//! 10.8. Capture requires the screen recording permission.
+
+#![allow(deprecated)]
+
+use crate::backend::Backend;
+use crate::{Bgr8, CaptureError, InitError, PlatformCode, PlatformError};
+use block2::RcBlock;
+use dispatch2::DispatchQueue;
+use objc2_core_foundation::CFRetained;
+use objc2_core_graphics::{
+    CGDirectDisplayID, CGDisplayBounds, CGDisplayCopyDisplayMode, CGDisplayMode, CGDisplayStream,
+    CGDisplayStreamFrameStatus, CGDisplayStreamUpdate, CGError, CGGetActiveDisplayList,
+    CGPreflightScreenCaptureAccess,
+};
+use objc2_io_surface::{IOSurfaceLockOptions, IOSurfaceRef};
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// `kCVPixelFormatType_32BGRA`
+const PIXEL_FORMAT_BGRA: i32 = 0x4247_5241;
+
+/// Maximum number of displays to enumerate
+const MAX_DISPLAYS: u32 = 16;
+
+/// The latest frame delivered by the display stream
+#[derive(Default)]
+struct Latest {
+    frame: Vec<Bgr8>,
+    width: u32,
+    height: u32,
+    /// Whether `frame` has been delivered since the last capture
+    new: bool,
+    /// Whether the stream has stopped, e.g. because the display was removed
+    stopped: bool,
+}
+
+type Shared = Arc<(Mutex<Latest>, Condvar)>;
+
+/// IDs of the active displays, with the main display first
+pub(crate) fn displays() -> Vec<CGDirectDisplayID> {
+    let mut ids = [0; MAX_DISPLAYS as usize];
+    let mut count = 0;
+    let err = unsafe { CGGetActiveDisplayList(MAX_DISPLAYS, ids.as_mut_ptr(), &mut count) };
+    if err != CGError::Success {
+        return Vec::new();
+    }
+    ids[..count as usize].to_vec()
+}
+
+/// Size of a display in pixels
+fn display_size(display: CGDirectDisplayID) -> (u32, u32) {
+    let mode = CGDisplayCopyDisplayMode(display);
+    (
+        CGDisplayMode::pixel_width(mode.as_deref()) as u32,
+        CGDisplayMode::pixel_height(mode.as_deref()) as u32,
+    )
+}
+
+/// Position of a display relative to the main display, in points
+fn display_position(display: CGDirectDisplayID) -> (i32, i32) {
+    let bounds = CGDisplayBounds(display);
+    (bounds.origin.x as i32, bounds.origin.y as i32)
+}
+
+/// A running display stream of a display
+pub(crate) struct Stream {
+    capture_src: usize,
+    stream: CFRetained<CGDisplayStream>,
+    shared: Shared,
+    position: (i32, i32),
+    width: u32,
+    height: u32,
+    frame: Option<Vec<Bgr8>>,
+}
+
+impl Stream {
+    /// Start streaming the display at index `capture_src` of the active
+    /// displays
+    pub(crate) fn new(capture_src: usize) -> Result<Stream, InitError> {
+        let display = *displays()
+            .get(capture_src)
+            .ok_or_else(|| InitError::NotFound(format!("No display at index {}", capture_src)))?;
+        let (width, height) = display_size(display);
+        let shared: Shared = Arc::default();
+        let handler = {
+            let shared = shared.clone();
+            RcBlock::new(
+                move |status: CGDisplayStreamFrameStatus,
+                      _time: u64,
+                      surface: *mut IOSurfaceRef,
+                      _update: *const CGDisplayStreamUpdate| {
+                    let (latest, cond) = &*shared;
+                    let mut latest = latest.lock().unwrap();
+                    match status {
+                        CGDisplayStreamFrameStatus::FrameComplete if !surface.is_null() => {
+                            copy_surface(unsafe { &*surface }, &mut latest);
+                            latest.new = true;
+                        }
+                        CGDisplayStreamFrameStatus::Stopped => latest.stopped = true,
+                        _ => return,
+                    }
+                    cond.notify_all();
+                },
+            )
+        };
+        let queue = DispatchQueue::new("captrs.display-stream", None);
+        let stream = unsafe {
+            CGDisplayStream::with_dispatch_queue(
+                display,
+                width as usize,
+                height as usize,
+                PIXEL_FORMAT_BGRA,
+                None,
+                &queue,
+                RcBlock::as_ptr(&handler),
+            )
+        }
+        .ok_or_else(|| {
+            if CGPreflightScreenCaptureAccess() {
+                InitError::Other("Failed to create display stream".to_string())
+            } else {
+                InitError::PermissionDenied("Screen recording is not permitted".to_string())
+            }
+        })?;
+        let err = CGDisplayStream::start(Some(&stream));
+        if err != CGError::Success {
+            return Err(InitError::Platform(PlatformError::new(
+                "Failed to start display stream",
+                PlatformCode::CgError(err.0),
+            )));
+        }
+        Ok(Stream {
+            capture_src,
+            stream,
+            shared,
+            position: display_position(display),
+            width,
+            height,
+            frame: None,
+        })
+    }
+}
+
+// The display stream is only started and stopped by the thread owning the
+// backend, and frames are handed over through `Shared`
+unsafe impl Send for Stream {}
+
+impl Backend for Stream {
+    fn name(&self) -> &'static str {
+        "CGDisplayStream"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The position of the display relative to the main display
+    fn position(&self) -> (i32, i32) {
+        self.position
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_deref()
+            .map(|frame| (frame, self.width, self.height))
+    }
+
+    /// Wait up to `timeout` for a new frame, and store it
+    ///
+    /// Display streams only deliver frames when the display changes, so this
+    /// times out when the screen is still.
+    fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+        let (latest, cond) = &*self.shared;
+        let (mut latest, _) = cond
+            .wait_timeout_while(latest.lock().unwrap(), timeout, |l| !l.new && !l.stopped)
+            .unwrap();
+        if latest.stopped {
+            return Err(if CGPreflightScreenCaptureAccess() {
+                CaptureError::AccessLost
+            } else {
+                CaptureError::PermissionDenied
+            });
+        }
+        if !latest.new {
+            return Err(CaptureError::Timeout);
+        }
+        latest.new = false;
+        let frame = self.frame.get_or_insert_with(Vec::new);
+        std::mem::swap(frame, &mut latest.frame);
+        self.width = latest.width;
+        self.height = latest.height;
+        Ok(())
+    }
+
+    fn pause(&mut self) {
+        CGDisplayStream::stop(Some(&self.stream));
+    }
+
+    fn resume(&mut self) -> Result<(), CaptureError> {
+        let err = CGDisplayStream::start(Some(&self.stream));
+        if err != CGError::Success {
+            return Err(CaptureError::Platform(PlatformError::new(
+                "Failed to start display stream",
+                PlatformCode::CgError(err.0),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Starts a new stream of the display, e.g. after its mode changed
+    fn reacquire(&mut self) -> Result<(), CaptureError> {
+        *self = Stream::new(self.capture_src).map_err(|e| CaptureError::Fail(e.to_string()))?;
+        Ok(())
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        CGDisplayStream::stop(Some(&self.stream));
+    }
+}
+
+/// Copy the pixels of a BGRA surface into the latest frame
+fn copy_surface(surface: &IOSurfaceRef, latest: &mut Latest) {
+    unsafe {
+        surface.lock(IOSurfaceLockOptions::ReadOnly, ptr::null_mut());
+    }
+    let (width, height) = (surface.width(), surface.height());
+    let stride = surface.bytes_per_row();
+    let base = surface.base_address().as_ptr() as *const u8;
+    latest.frame.clear();
+    latest.frame.reserve(width * height);
+    for y in 0..height {
+        // `Bgr8` has the same layout as BGRA
+        let row = unsafe { std::slice::from_raw_parts(base.add(y * stride) as *const Bgr8, width) };
+        latest.frame.extend_from_slice(row);
+    }
+    unsafe {
+        surface.unlock(IOSurfaceLockOptions::ReadOnly, ptr::null_mut());
+    }
+    latest.width = width as u32;
+    latest.height = height as u32;
+}
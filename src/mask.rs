@@ -0,0 +1,200 @@
+//! Capture of an area of the capture source that is not a rectangle, e.g.
+//! an L-shaped one, as a union of rectangles
+
+use crate::{intersection, Bgr8, Capturer, InitError, Rect};
+
+/// The bounding box of the non-empty rectangles, if any
+fn bounding_box(rects: &[Rect]) -> Option<Rect> {
+    let mut rects = rects.iter().filter(|r| r.width > 0 && r.height > 0);
+    let first = *rects.next()?;
+    let (mut left, mut top) = (first.x, first.y);
+    let (mut right, mut bottom) = (first.x + first.width, first.y + first.height);
+    for r in rects {
+        left = left.min(r.x);
+        top = top.min(r.y);
+        right = right.max(r.x + r.width);
+        bottom = bottom.max(r.y + r.height);
+    }
+    Some(Rect {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+/// Scale a rectangle of a frame of size `from` to a frame of size `to`,
+/// rounding outwards
+fn scale_rect(r: Rect, from: (u32, u32), to: (u32, u32)) -> Rect {
+    let scale = |v: u32, from: u32, to: u32, ceil: bool| {
+        let v = v as u64 * to as u64;
+        let from = from.max(1) as u64;
+        (if ceil { v.div_ceil(from) } else { v / from }) as u32
+    };
+    let (x, y) = (
+        scale(r.x, from.0, to.0, false),
+        scale(r.y, from.1, to.1, false),
+    );
+    Rect {
+        x,
+        y,
+        width: scale(r.x + r.width, from.0, to.0, true) - x,
+        height: scale(r.y + r.height, from.1, to.1, true) - y,
+    }
+}
+
+/// Copy the pixels of `region` of a frame with `stride` pixels per row into
+/// `dst`, only those within `mask`, relative to the region, zeroing the
+/// others
+pub(crate) fn copy_masked(
+    pixels: &[Bgr8],
+    stride: usize,
+    region: Rect,
+    mask: &[Rect],
+    dst: &mut Vec<Bgr8>,
+) {
+    let w = region.width as usize;
+    dst.clear();
+    dst.resize(w * region.height as usize, Bgr8::default());
+    let bounds = Rect {
+        x: 0,
+        y: 0,
+        ..region
+    };
+    for r in mask.iter().filter_map(|&r| intersection(r, bounds)) {
+        let n = r.width as usize;
+        for y in r.y..r.y + r.height {
+            let src = (region.y + y) as usize * stride + (region.x + r.x) as usize;
+            let start = y as usize * w + r.x as usize;
+            dst[start..start + n].copy_from_slice(&pixels[src..src + n]);
+        }
+    }
+}
+
+impl Capturer {
+    /// Capture only the pixels within a union of rectangles, e.g. an
+    /// L-shaped area, in pixels relative to the top left corner of the
+    /// capture source
+    ///
+    /// The capture region is set to the bounding box of the rectangles, and
+    /// of the frames, only the pixels within the rectangles are copied. The
+    /// others are zeroed. Frames report the rectangles relative to them as
+    /// `Frame::mask`. Setting another capture region disables the mask.
+    ///
+    /// Fails with `InitError::InvalidArgument` if the rectangles are all
+    /// empty, or not within the capture source.
+    pub fn set_capture_mask(&mut self, rects: Vec<Rect>) -> Result<(), InitError> {
+        let bbox = bounding_box(&rects)
+            .ok_or_else(|| InitError::InvalidArgument("The mask is empty".to_string()))?;
+        self.set_capture_region(bbox)?;
+        let mask = rects
+            .into_iter()
+            .filter(|r| r.width > 0 && r.height > 0)
+            .map(|r| Rect {
+                x: r.x - bbox.x,
+                y: r.y - bbox.y,
+                ..r
+            })
+            .collect();
+        self.mask = Some((bbox, mask));
+        Ok(())
+    }
+
+    /// Capture all of the capture source again, see
+    /// `Self::set_capture_mask`
+    pub fn clear_capture_mask(&mut self) {
+        if self.mask.take().is_some() {
+            self.clear_capture_region();
+        }
+    }
+
+    /// The rectangles of the capture mask, relative to the capture region,
+    /// if masked
+    pub fn capture_mask(&self) -> Option<&[Rect]> {
+        match &self.mask {
+            Some((bbox, mask)) if self.region == Some(*bbox) => Some(mask),
+            _ => None,
+        }
+    }
+
+    /// The rectangles of the capture mask relative to the stored frame, if
+    /// masked, which may be scaled
+    pub(crate) fn frame_mask(&self) -> Option<Vec<Rect>> {
+        let mask = self.capture_mask()?;
+        let region = self.region?;
+        let (width, height) = (region.width, region.height);
+        Some(match self.output_size {
+            Some((ow, oh, _)) => mask
+                .iter()
+                .map(|&r| scale_rect(r, (width, height), (ow, oh)))
+                .collect(),
+            None => mask.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod mask_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::CaptureError;
+    use std::time::Duration;
+
+    /// Backend of 4x4 frames of pixels numbered by their red component
+    struct Numbered(Vec<Bgr8>);
+
+    impl Backend for Numbered {
+        fn name(&self) -> &'static str {
+            "numbered"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (4, 4)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.0 = (1..=16).map(|i| Bgr8::new(i, 0, 0)).collect();
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 4, 4))
+        }
+    }
+
+    #[test]
+    fn test_capture_mask() {
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let mut capturer = Capturer::with_backend(Box::new(Numbered(Vec::new())));
+        assert!(capturer.set_capture_mask(vec![rect(1, 1, 0, 2)]).is_err());
+        assert!(capturer.set_capture_mask(vec![rect(3, 3, 2, 1)]).is_err());
+        // An L of the left column and bottom row of the 3x3 bottom right
+        capturer
+            .set_capture_mask(vec![rect(1, 1, 1, 3), rect(1, 3, 3, 1)])
+            .unwrap();
+        assert_eq!(capturer.capture_region(), Some(rect(1, 1, 3, 3)));
+        assert_eq!(
+            capturer.capture_mask().unwrap(),
+            [rect(0, 0, 1, 3), rect(0, 2, 3, 1)]
+        );
+        let frame = capturer.capture_frame_meta().unwrap();
+        let reds: Vec<_> = frame.pixels().iter().map(|p| p.r).collect();
+        assert_eq!(reds, [6, 0, 0, 10, 0, 0, 14, 15, 16]);
+        assert_eq!(frame.mask().unwrap().len(), 2);
+
+        capturer
+            .set_output_size(6, 6, crate::ScaleFilter::Nearest)
+            .unwrap();
+        let frame = capturer.capture_frame_meta().unwrap();
+        assert_eq!(frame.mask().unwrap(), [rect(0, 0, 2, 6), rect(0, 4, 6, 2)]);
+
+        capturer.clear_capture_mask();
+        assert_eq!(capturer.capture_region(), None);
+        assert!(capturer.capture_mask().is_none());
+    }
+}
@@ -0,0 +1,92 @@
+//! Capture health metrics, emitted through the
+//! [metrics](https://crates.io/crates/metrics) facade. Install a recorder,
+//! e.g. `metrics-exporter-prometheus`, to collect them.
+//!
+//! Metrics are recorded by every `Capturer`.
+
+use crate::CaptureError;
+use metrics::{counter, describe_counter, describe_gauge, gauge, Unit};
+use std::sync::Once;
+use std::time::Instant;
+
+/// Counter of captured frames
+pub const FRAMES_CAPTURED: &str = "captrs_frames_captured_total";
+/// Counter of bytes of pixel data captured
+pub const BYTES_CAPTURED: &str = "captrs_bytes_captured_total";
+/// Counter of captures that timed out
+pub const TIMEOUTS: &str = "captrs_capture_timeouts_total";
+/// Counter of failed captures, other than timeouts, labeled by `kind`
+pub const ERRORS: &str = "captrs_capture_errors_total";
+/// Counter of successful captures following a failed one
+pub const RECOVERIES: &str = "captrs_capture_recoveries_total";
+/// Gauge of the rate of captured frames per second, smoothed
+pub const FPS: &str = "captrs_capture_fps";
+
+/// Weight of the latest frame interval in the smoothed frame rate
+const FPS_SMOOTHING: f64 = 0.1;
+
+fn describe() {
+    static DESCRIBED: Once = Once::new();
+    DESCRIBED.call_once(|| {
+        describe_counter!(FRAMES_CAPTURED, Unit::Count, "Frames captured");
+        describe_counter!(BYTES_CAPTURED, Unit::Bytes, "Bytes of pixel data captured");
+        describe_counter!(TIMEOUTS, Unit::Count, "Captures that timed out");
+        describe_counter!(ERRORS, Unit::Count, "Failed captures, other than timeouts");
+        describe_counter!(
+            RECOVERIES,
+            Unit::Count,
+            "Successful captures after a failure"
+        );
+        describe_gauge!(FPS, "Captured frames per second");
+    });
+}
+
+/// Metrics state of a capturer
+pub(crate) struct CaptureMetrics {
+    last_frame: Option<Instant>,
+    fps: f64,
+    failing: bool,
+}
+
+impl CaptureMetrics {
+    pub(crate) fn new() -> CaptureMetrics {
+        describe();
+        CaptureMetrics {
+            last_frame: None,
+            fps: 0.0,
+            failing: false,
+        }
+    }
+
+    /// Record the result of a capture, of `bytes` bytes if successful
+    pub(crate) fn record<T>(&mut self, result: &Result<T, CaptureError>, bytes: usize) {
+        match result {
+            Ok(_) => {
+                counter!(FRAMES_CAPTURED).increment(1);
+                counter!(BYTES_CAPTURED).increment(bytes as u64);
+                if self.failing {
+                    counter!(RECOVERIES).increment(1);
+                    self.failing = false;
+                }
+                let now = Instant::now();
+                if let Some(last) = self.last_frame {
+                    let dt = now.duration_since(last).as_secs_f64();
+                    if dt > 0.0 {
+                        self.fps = if self.fps == 0.0 {
+                            1.0 / dt
+                        } else {
+                            self.fps + FPS_SMOOTHING * (1.0 / dt - self.fps)
+                        };
+                        gauge!(FPS).set(self.fps);
+                    }
+                }
+                self.last_frame = Some(now);
+            }
+            Err(CaptureError::Timeout) => counter!(TIMEOUTS).increment(1),
+            Err(e) => {
+                counter!(ERRORS, "kind" => e.kind()).increment(1);
+                self.failing = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! Mirroring of the screen to other machines over TCP, without the overhead
+//! of a video codec or WebRTC stack. Suitable for LANs.
+//!
+//! The sender splits frames into tiles, and only sends the tiles that changed
+//! since the previous frame. Receivers that connect get a full frame first.
+//! Each update is sent as a 24 byte header followed by the tiles, with all
+//! fields in little-endian byte order:
+//!
+//! | Offset | Type | Field                                             |
+//! |--------|------|---------------------------------------------------|
+//! | 0      | u32  | Magic, `b"CPTM"`                                  |
+//! | 4      | u16  | Protocol version, currently 1                     |
+//! | 6      | u16  | Flags. Bit 0 is set if the update is a full frame |
+//! | 8      | u32  | Frame width                                       |
+//! | 12     | u32  | Frame height                                      |
+//! | 16     | u32  | Number of tiles                                   |
+//! | 20     | u32  | Reserved                                          |
+//!
+//! Each tile is its x, y, width, and height as u32, followed by its rows of
+//! 4 byte BGRX pixels. See `examples/mirror_receiver.rs` for a receiver.
+
+use crate::diff::{changed_tiles, copy_region};
+use crate::{pixel_bytes, Bgr8, Rect};
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CPTM");
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 24;
+const FLAG_KEY_FRAME: u16 = 1;
+/// Default width and height of tiles in pixels
+pub const DEFAULT_TILE_SIZE: u32 = 64;
+/// Default largest width and height of frames accepted by receivers
+pub const DEFAULT_MAX_DIMENSION: u32 = 16384;
+/// Default time a write to a receiver may block before it's disconnected
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn encode_update(
+    frame: &[u8],
+    width: u32,
+    height: u32,
+    tiles: &[Rect],
+    key: bool,
+    buf: &mut Vec<u8>,
+) {
+    buf.clear();
+    buf.extend_from_slice(&MAGIC.to_le_bytes());
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.extend_from_slice(&(if key { FLAG_KEY_FRAME } else { 0 }).to_le_bytes());
+    buf.extend_from_slice(&width.to_le_bytes());
+    buf.extend_from_slice(&height.to_le_bytes());
+    buf.extend_from_slice(&(tiles.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    write_tiles(frame, width, tiles, buf);
+}
+
+/// Append the tiles of a frame of BGRX bytes, each as its x, y, width, and
+/// height as u32, followed by its rows of pixels
+pub(crate) fn write_tiles(frame: &[u8], width: u32, tiles: &[Rect], buf: &mut Vec<u8>) {
+    for &tile in tiles {
+        for v in [tile.x, tile.y, tile.width, tile.height] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        copy_region(frame, width, tile, buf);
+    }
+}
+
+/// Read `n_tiles` tiles written by `write_tiles` into a frame of BGRX bytes
+/// of the given size, pushing their regions to `tiles`
+pub(crate) fn read_tiles(
+    reader: &mut impl Read,
+    frame: &mut [u8],
+    width: u32,
+    height: u32,
+    n_tiles: u32,
+    tiles: &mut Vec<Rect>,
+) -> io::Result<()> {
+    let stride = 4 * width as usize;
+    for _ in 0..n_tiles {
+        let mut rect = [0; 16];
+        reader.read_exact(&mut rect)?;
+        let field = |i: usize| u32::from_le_bytes(rect[4 * i..4 * i + 4].try_into().unwrap());
+        let tile = Rect {
+            x: field(0),
+            y: field(1),
+            width: field(2),
+            height: field(3),
+        };
+        if tile.x as u64 + tile.width as u64 > width as u64
+            || tile.y as u64 + tile.height as u64 > height as u64
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Tile is out of bounds",
+            ));
+        }
+        for row in tile.y..tile.y + tile.height {
+            let start = row as usize * stride + 4 * tile.x as usize;
+            reader.read_exact(&mut frame[start..start + 4 * tile.width as usize])?;
+        }
+        tiles.push(tile);
+    }
+    Ok(())
+}
+
+/// Sender of frames to every connected `MirrorReceiver`
+///
+/// Receivers are accepted on a background thread. Updates are written from
+/// the thread calling `send_frame`, and receivers that stop reading are
+/// disconnected once a write blocks for longer than the write timeout.
+pub struct MirrorSender {
+    local_addr: SocketAddr,
+    pending: Arc<Mutex<Vec<TcpStream>>>,
+    clients: Vec<TcpStream>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    tile_size: u32,
+    write_timeout: Duration,
+    prev: Vec<u8>,
+    width: u32,
+    height: u32,
+    buf: Vec<u8>,
+}
+
+impl MirrorSender {
+    /// Start listening for receivers at the given address
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<MirrorSender> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let pending = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (pending, stop) = (pending.clone(), stop.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let _ = stream.set_nodelay(true);
+                        pending.lock().unwrap().push(stream);
+                    }
+                }
+            })
+        };
+        Ok(MirrorSender {
+            local_addr,
+            pending,
+            clients: Vec::new(),
+            stop,
+            thread: Some(thread),
+            tile_size: DEFAULT_TILE_SIZE,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            prev: Vec::new(),
+            width: 0,
+            height: 0,
+            buf: Vec::new(),
+        })
+    }
+
+    /// The address the sender is listening at
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Set the width and height of tiles in pixels. Smaller tiles send less
+    /// unchanged pixels, but take more time to compare.
+    pub fn set_tile_size(&mut self, tile_size: u32) {
+        assert!(tile_size > 0);
+        self.tile_size = tile_size;
+    }
+
+    /// Set how long a write to a receiver may block before the receiver is
+    /// disconnected, by default `DEFAULT_WRITE_TIMEOUT`
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        assert!(!timeout.is_zero());
+        self.write_timeout = timeout;
+        self.clients
+            .retain(|client| client.set_write_timeout(Some(timeout)).is_ok());
+    }
+
+    /// Number of connected receivers
+    pub fn n_clients(&self) -> usize {
+        self.clients.len() + self.pending.lock().unwrap().len()
+    }
+
+    /// Send a frame of the given size to all receivers
+    pub fn send_frame(&mut self, frame: &[Bgr8], width: u32, height: u32) {
+        self.send_frame_components(pixel_bytes(frame), width, height)
+    }
+
+    /// Send a frame of the given size, as returned by
+    /// `Capturer::capture_frame_components`, to all receivers
+    ///
+    /// Receivers that fail to receive the update are disconnected.
+    pub fn send_frame_components(&mut self, frame: &[u8], width: u32, height: u32) {
+        assert_eq!(frame.len(), 4 * width as usize * height as usize);
+        let full = [Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }];
+        let mut new_clients = std::mem::take(&mut *self.pending.lock().unwrap());
+        let timeout = self.write_timeout;
+        new_clients.retain(|client| client.set_write_timeout(Some(timeout)).is_ok());
+        if (width, height) != (self.width, self.height) {
+            // Everyone needs a full frame of the new size
+            new_clients.append(&mut self.clients);
+        } else if !self.clients.is_empty() {
+            let tiles = changed_tiles(&self.prev, frame, width, height, self.tile_size);
+            if !tiles.is_empty() {
+                encode_update(frame, width, height, &tiles, false, &mut self.buf);
+                let buf = &self.buf;
+                self.clients
+                    .retain_mut(|client| client.write_all(buf).is_ok());
+            }
+        }
+        if !new_clients.is_empty() {
+            encode_update(frame, width, height, &full, true, &mut self.buf);
+            let buf = &self.buf;
+            new_clients.retain_mut(|client| client.write_all(buf).is_ok());
+            self.clients.append(&mut new_clients);
+        }
+        self.prev.clear();
+        self.prev.extend_from_slice(frame);
+        self.width = width;
+        self.height = height;
+    }
+}
+
+impl Drop for MirrorSender {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the accepting thread up
+        let mut addr = self.local_addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        let _ = TcpStream::connect(addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Receiver of frames from a `MirrorSender`, keeping a copy of the latest
+/// frame
+pub struct MirrorReceiver {
+    stream: TcpStream,
+    frame: Vec<u8>,
+    width: u32,
+    height: u32,
+    max_dimension: u32,
+    tiles: Vec<Rect>,
+}
+
+impl MirrorReceiver {
+    /// Connect to a sender at the given address
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<MirrorReceiver> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(MirrorReceiver {
+            stream,
+            frame: Vec::new(),
+            width: 0,
+            height: 0,
+            max_dimension: DEFAULT_MAX_DIMENSION,
+            tiles: Vec::new(),
+        })
+    }
+
+    /// Set the largest width and height of frames to accept, by default
+    /// `DEFAULT_MAX_DIMENSION`
+    ///
+    /// Full frames are allocated by the size in their header, before their
+    /// pixels arrive, so larger frames fail with `InvalidData` rather than
+    /// exhausting memory.
+    pub fn set_max_dimension(&mut self, max_dimension: u32) {
+        self.max_dimension = max_dimension;
+    }
+
+    /// Block until the next update is received and apply it to the frame
+    ///
+    /// Returns the regions of the frame that were updated.
+    pub fn recv(&mut self) -> io::Result<&[Rect]> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut header = [0; HEADER_SIZE];
+        self.stream.read_exact(&mut header)?;
+        let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        if u32_at(0) != MAGIC {
+            return Err(invalid("Bad update magic"));
+        }
+        if u16_at(4) != VERSION {
+            return Err(invalid("Unsupported protocol version"));
+        }
+        let (width, height, n_tiles) = (u32_at(8), u32_at(12), u32_at(16));
+        if u16_at(6) & FLAG_KEY_FRAME != 0 {
+            let len = (4 * width as usize)
+                .checked_mul(height as usize)
+                .filter(|_| width <= self.max_dimension && height <= self.max_dimension)
+                .ok_or_else(|| invalid("Frame is too large"))?;
+            self.frame.resize(len, 0);
+            self.width = width;
+            self.height = height;
+        } else if (width, height) != (self.width, self.height) {
+            return Err(invalid("Update does not match the size of the frame"));
+        }
+        self.tiles.clear();
+        read_tiles(
+            &mut self.stream,
+            &mut self.frame,
+            width,
+            height,
+            n_tiles,
+            &mut self.tiles,
+        )?;
+        Ok(&self.tiles)
+    }
+
+    /// The latest frame, as 4 byte BGRX pixels
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// The width and height of the latest frame
+    pub fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod mirror_tests {
+    use super::*;
+
+    #[test]
+    fn test_stalled_receiver() {
+        let mut sender = MirrorSender::bind("127.0.0.1:0").unwrap();
+        sender.set_write_timeout(Duration::from_millis(50));
+        // Connected, but never reading
+        let _stalled = TcpStream::connect(sender.local_addr()).unwrap();
+        while sender.n_clients() == 0 {
+            thread::yield_now();
+        }
+        // Larger than the socket buffers
+        let (w, h) = (2048, 2048);
+        sender.send_frame_components(&vec![0; 4 * w * h], w as u32, h as u32);
+        assert_eq!(sender.n_clients(), 0);
+    }
+
+    #[test]
+    fn test_mirror() {
+        let mut sender = MirrorSender::bind("127.0.0.1:0").unwrap();
+        sender.set_tile_size(2);
+        let mut receiver = MirrorReceiver::connect(sender.local_addr()).unwrap();
+        while sender.n_clients() == 0 {
+            thread::yield_now();
+        }
+        let (w, h) = (4, 4);
+        let mut frame = vec![0u8; 4 * w * h];
+        sender.send_frame_components(&frame, w as u32, h as u32);
+        assert_eq!(receiver.recv().unwrap().len(), 1);
+        assert_eq!(receiver.frame(), &frame[..]);
+
+        // Change the pixel at (3, 0), in the top right tile
+        frame[4 * 3] = 255;
+        sender.send_frame_components(&frame, w as u32, h as u32);
+        let tiles = receiver.recv().unwrap().to_vec();
+        assert_eq!(
+            tiles,
+            vec![Rect {
+                x: 2,
+                y: 0,
+                width: 2,
+                height: 2
+            }]
+        );
+        assert_eq!(receiver.frame(), &frame[..]);
+    }
+
+    #[test]
+    fn test_max_dimension() {
+        let mut sender = MirrorSender::bind("127.0.0.1:0").unwrap();
+        let mut receiver = MirrorReceiver::connect(sender.local_addr()).unwrap();
+        receiver.set_max_dimension(2);
+        while sender.n_clients() == 0 {
+            thread::yield_now();
+        }
+        sender.send_frame_components(&[0; 4 * 4 * 4], 4, 4);
+        let err = receiver.recv().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(receiver.frame().is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+//! A backend of synthetic frames, for testing code using `Capturer` without
+//! a display, e.g. on CI
+//!
+//! Frames are deterministic, depending only on the pattern, the size, and
+//! the number of frames captured before. Errors and timeouts can be
+//! simulated.
+//!
+//! ```
+//! # use captrs::{Bgr8, CaptureError, Capturer};
+//! # use captrs::mock::{MockBackend, Pattern};
+//! let backend = MockBackend::new(64, 48, Pattern::Solid(Bgr8::new(255, 0, 0)))
+//!     .errors(vec![CaptureError::Timeout]);
+//! let mut capturer = Capturer::with_backend(Box::new(backend));
+//! assert!(capturer.capture_frame().is_err());
+//! assert_eq!(capturer.capture_frame().unwrap()[0], Bgr8::new(255, 0, 0));
+//! ```
+
+use crate::backend::Backend;
+use crate::{Bgr8, CaptureError, Rect};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// What the synthetic frames show
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Pattern {
+    /// A single color, the same in every frame
+    Solid(Bgr8),
+    /// Red increasing to the right and green increasing downwards, with blue
+    /// increasing by one every frame
+    Gradient,
+    /// A square of `size` pixels on a background, moving 8 pixels to the
+    /// right and 4 down every frame, and wrapping around at the edges
+    MovingBox {
+        size: u32,
+        color: Bgr8,
+        background: Bgr8,
+    },
+}
+
+/// The square of a moving box of `size` in frame `n` of the given size
+fn box_rect(width: u32, height: u32, size: u32, n: u64) -> Rect {
+    let size = size.min(width).min(height);
+    let x_range = (width - size) as u64 + 1;
+    let y_range = (height - size) as u64 + 1;
+    Rect {
+        x: (n * 8 % x_range) as u32,
+        y: (n * 4 % y_range) as u32,
+        width: size,
+        height: size,
+    }
+}
+
+/// Backend generating synthetic frames of a pattern
+pub struct MockBackend {
+    width: u32,
+    height: u32,
+    pattern: Pattern,
+    errors: VecDeque<CaptureError>,
+    timeout_every: Option<u64>,
+    /// Number of captures attempted, including failed ones
+    captures: u64,
+    /// Number of frames generated
+    frames: u64,
+    frame: Option<Vec<Bgr8>>,
+    dirty_rects: Vec<Rect>,
+}
+
+impl MockBackend {
+    /// Construct a backend of frames of the given size and pattern
+    pub fn new(width: u32, height: u32, pattern: Pattern) -> MockBackend {
+        MockBackend {
+            width,
+            height,
+            pattern,
+            errors: VecDeque::new(),
+            timeout_every: None,
+            captures: 0,
+            frames: 0,
+            frame: None,
+            dirty_rects: Vec::new(),
+        }
+    }
+
+    /// Fail the first captures with these errors, in order, before
+    /// generating frames
+    ///
+    /// After a `CaptureError::AccessLost`, reacquiring always succeeds.
+    pub fn errors(mut self, errors: impl IntoIterator<Item = CaptureError>) -> MockBackend {
+        self.errors.extend(errors);
+        self
+    }
+
+    /// Time out every `n`th capture, e.g. every 3rd with `n = 3`, like a
+    /// backend waiting for changes of a still screen
+    pub fn timeout_every(mut self, n: u64) -> MockBackend {
+        assert!(n > 0);
+        self.timeout_every = Some(n);
+        self
+    }
+
+    fn generate(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let n = self.frames;
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        let frame = self.frame.get_or_insert_with(Vec::new);
+        frame.clear();
+        self.dirty_rects.clear();
+        match self.pattern {
+            Pattern::Solid(color) => {
+                frame.resize(width as usize * height as usize, color);
+                if n == 0 {
+                    self.dirty_rects.push(full);
+                }
+            }
+            Pattern::Gradient => {
+                let scale = |i: u32, len: u32| (i * 255 / len.saturating_sub(1).max(1)) as u8;
+                frame.extend((0..height).flat_map(|y| {
+                    (0..width).map(move |x| Bgr8::new(scale(x, width), scale(y, height), n as u8))
+                }));
+                self.dirty_rects.push(full);
+            }
+            Pattern::MovingBox {
+                size,
+                color,
+                background,
+            } => {
+                frame.resize(width as usize * height as usize, background);
+                let rect = box_rect(width, height, size, n);
+                for y in rect.y..rect.y + rect.height {
+                    let row = (y * width) as usize;
+                    frame[row + rect.x as usize..row + (rect.x + rect.width) as usize].fill(color);
+                }
+                if n == 0 {
+                    self.dirty_rects.push(full);
+                } else {
+                    self.dirty_rects.push(box_rect(width, height, size, n - 1));
+                    self.dirty_rects.push(rect);
+                }
+            }
+        }
+        self.frames += 1;
+    }
+}
+
+impl Backend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+        self.captures += 1;
+        if let Some(e) = self.errors.pop_front() {
+            return Err(e);
+        }
+        if self
+            .timeout_every
+            .is_some_and(|n| self.captures.is_multiple_of(n))
+        {
+            return Err(CaptureError::Timeout);
+        }
+        self.generate();
+        Ok(())
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_deref()
+            .map(|frame| (frame, self.width, self.height))
+    }
+
+    fn dirty_rects(&self) -> Option<&[Rect]> {
+        self.frame.as_ref().map(|_| &self.dirty_rects[..])
+    }
+}
+
+#[cfg(test)]
+mod mock_tests {
+    use super::*;
+    use crate::Capturer;
+
+    #[test]
+    fn test_moving_box() {
+        let (black, white) = (Bgr8::new(0, 0, 0), Bgr8::new(255, 255, 255));
+        let pattern = Pattern::MovingBox {
+            size: 2,
+            color: white,
+            background: black,
+        };
+        let backend = MockBackend::new(16, 8, pattern).timeout_every(3);
+        let mut capturer = Capturer::with_backend(Box::new(backend));
+        let first = capturer.capture_frame().unwrap();
+        assert_eq!(&first[..3], [white, white, black]);
+        let second = capturer.capture_frame().unwrap();
+        assert_eq!(second[4 * 16 + 8], white);
+        assert_eq!(second.iter().filter(|&&p| p == white).count(), 4);
+        assert!(matches!(
+            capturer.capture_frame(),
+            Err(CaptureError::Timeout)
+        ));
+
+        let mut backend = MockBackend::new(16, 8, pattern);
+        backend.capture_frame(Duration::ZERO).unwrap();
+        backend.capture_frame(Duration::ZERO).unwrap();
+        assert_eq!(
+            backend.dirty_rects().unwrap(),
+            [box_rect(16, 8, 2, 0), box_rect(16, 8, 2, 1)]
+        );
+    }
+}
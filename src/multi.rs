@@ -0,0 +1,494 @@
+//! Simultaneous capture of several displays
+//!
+//! ```no_run
+//! # use captrs::MultiCapturer;
+//! let mut capturer = MultiCapturer::all().unwrap();
+//! for frame in capturer.capture().unwrap() {
+//!     println!("Display {}: {}x{}", frame.display, frame.frame.width(), frame.frame.height());
+//! }
+//! let desktop = capturer.capture_stitched().unwrap();
+//! ```
+//!
+//! For a single screenshot of the whole desktop, see
+//! `capture_all_displays_stitched`.
+
+use crate::backend::Backend;
+use crate::{
+    displays, Bgr8, CaptureError, Capturer, DisplayInfo, Frame, InitError, Rect, ThreadOptions,
+};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A frame captured from a display of a `MultiCapturer`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplayFrame {
+    /// Index of the display as a capture source, as `DisplayInfo::index`
+    pub display: usize,
+    /// Offset of the display from the primary display, as
+    /// `Capturer::position`
+    pub position: (i32, i32),
+    pub frame: Frame,
+}
+
+/// A capturer on a thread of its own, capturing on request
+struct Worker {
+    display: usize,
+    request: Option<Sender<()>>,
+    response: Receiver<Result<DisplayFrame, CaptureError>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// A capturer of several displays at once, with one `Capturer` per display
+///
+/// Each display is captured on a thread of its own, so the frames of all
+/// displays are captured at about the same time.
+pub struct MultiCapturer {
+    workers: Vec<Worker>,
+}
+
+impl MultiCapturer {
+    /// Capture all displays, as listed by `displays`
+    pub fn all() -> Result<MultiCapturer, InitError> {
+        let indices: Vec<usize> = displays().iter().map(|d| d.index).collect();
+        if indices.is_empty() {
+            return Err(InitError::NotFound("No displays".to_string()));
+        }
+        MultiCapturer::new(&indices)
+    }
+
+    /// Capture the displays with the given capture source indices
+    pub fn new(displays: &[usize]) -> Result<MultiCapturer, InitError> {
+        MultiCapturer::spawn(displays, Capturer::new)
+    }
+
+    /// Capture the given displays with capturers constructed by `make` on
+    /// the capture threads, e.g. with a `CapturerBuilder`
+    ///
+    /// Blocks until all capturers are constructed.
+    pub fn spawn<F>(displays: &[usize], make: F) -> Result<MultiCapturer, InitError>
+    where
+        F: Fn(usize) -> Result<Capturer, InitError> + Clone + Send + 'static,
+    {
+        MultiCapturer::spawn_with(displays, make, |_| ThreadOptions::default())
+    }
+
+    /// Capture as with `Self::spawn`, with the priority and CPU affinity of
+    /// the capture thread of each display given by `options`, e.g. to pin
+    /// each display to a core of its own
+    ///
+    /// ```no_run
+    /// use captrs::{Capturer, MultiCapturer, ThreadOptions, ThreadPriority};
+    ///
+    /// let capturer = MultiCapturer::spawn_with(&[0, 1], Capturer::new, |display| {
+    ///     ThreadOptions::new(ThreadPriority::Highest).affinity(vec![display])
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn spawn_with<F, O>(
+        displays: &[usize],
+        make: F,
+        options: O,
+    ) -> Result<MultiCapturer, InitError>
+    where
+        F: Fn(usize) -> Result<Capturer, InitError> + Clone + Send + 'static,
+        O: Fn(usize) -> ThreadOptions,
+    {
+        let mut workers = Vec::with_capacity(displays.len());
+        let mut inits = Vec::with_capacity(displays.len());
+        for &display in displays {
+            let (request_tx, request_rx) = mpsc::channel();
+            let (response_tx, response_rx) = mpsc::channel();
+            let (init_tx, init_rx) = mpsc::channel();
+            let make = make.clone();
+            let options = options(display);
+            let thread = thread::Builder::new()
+                .name(format!("captrs-capture-{}", display))
+                .spawn(move || {
+                    let mut capturer = match options.apply().and_then(|()| make(display)) {
+                        Ok(capturer) => capturer,
+                        Err(e) => return init_tx.send(Err(e)).unwrap_or(()),
+                    };
+                    let _ = init_tx.send(Ok(()));
+                    capture_on_request(&mut capturer, display, request_rx, response_tx)
+                })
+                .map_err(|e| e.to_string())?;
+            workers.push(Worker {
+                display,
+                request: Some(request_tx),
+                response: response_rx,
+                thread: Some(thread),
+            });
+            inits.push(init_rx);
+        }
+        // Construct the capturers in parallel too
+        for init in inits {
+            init.recv()
+                .map_err(|_| "Capture thread panicked".to_string())??;
+        }
+        Ok(MultiCapturer { workers })
+    }
+
+    /// The capture source indices of the displays, in the order of their
+    /// frames
+    pub fn displays(&self) -> Vec<usize> {
+        self.workers.iter().map(|w| w.display).collect()
+    }
+
+    /// Capture all displays at once
+    ///
+    /// Fails with the first error of any display, after all captures have
+    /// finished.
+    pub fn capture(&mut self) -> Result<Vec<DisplayFrame>, CaptureError> {
+        self.capture_each().into_iter().collect()
+    }
+
+    /// Capture all displays at once, with the result of each display
+    pub fn capture_each(&mut self) -> Vec<Result<DisplayFrame, CaptureError>> {
+        for worker in &self.workers {
+            if let Some(request) = &worker.request {
+                let _ = request.send(());
+            }
+        }
+        self.workers
+            .iter()
+            .map(|worker| {
+                worker.response.recv().unwrap_or_else(|_| {
+                    Err(CaptureError::Fail(format!(
+                        "Capture thread of display {} panicked",
+                        worker.display
+                    )))
+                })
+            })
+            .collect()
+    }
+
+    /// Capture all displays at once, and stitch the frames into one frame of
+    /// the virtual desktop. See `stitch`.
+    pub fn capture_stitched(&mut self) -> Result<Frame, CaptureError> {
+        self.capture().map(|frames| stitch(&frames))
+    }
+}
+
+impl Drop for MultiCapturer {
+    fn drop(&mut self) {
+        for worker in &mut self.workers {
+            // Closing the channel stops the thread
+            worker.request.take();
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+fn capture_on_request(
+    capturer: &mut Capturer,
+    display: usize,
+    requests: Receiver<()>,
+    responses: Sender<Result<DisplayFrame, CaptureError>>,
+) {
+    while requests.recv().is_ok() {
+        let res = capturer.capture_frame_meta().map(|frame| DisplayFrame {
+            display,
+            position: capturer.position(),
+            frame,
+        });
+        if responses.send(res).is_err() {
+            return;
+        }
+    }
+}
+
+/// The top left corner and size of the bounding box of the frames of
+/// displays, on the desktop
+fn bounding_box(frames: &[DisplayFrame]) -> ((i32, i32), (u32, u32)) {
+    let left = frames.iter().map(|f| f.position.0).min().unwrap_or(0);
+    let top = frames.iter().map(|f| f.position.1).min().unwrap_or(0);
+    let right = frames
+        .iter()
+        .map(|f| f.position.0 + f.frame.width() as i32)
+        .max()
+        .unwrap_or(left);
+    let bottom = frames
+        .iter()
+        .map(|f| f.position.1 + f.frame.height() as i32)
+        .max()
+        .unwrap_or(top);
+    ((left, top), ((right - left) as u32, (bottom - top) as u32))
+}
+
+/// Stitch the frames of displays into one frame of the bounding box of the
+/// displays, placing each at its position
+///
+/// Areas not covered by any display are black. Frames are placed in pixels
+/// at their positions, so displays should have the same scale factor.
+pub fn stitch(frames: &[DisplayFrame]) -> Frame {
+    let (_, (width, height)) = bounding_box(frames);
+    let mut pixels = vec![Bgr8::new(0, 0, 0); width as usize * height as usize];
+    for (f, area) in frames.iter().zip(stitched_areas(frames)) {
+        let (x, y) = (area.x as usize, area.y as usize);
+        let w = f.frame.width() as usize;
+        for (i, row) in f.frame.pixels().chunks_exact(w.max(1)).enumerate() {
+            let start = (y + i) * width as usize + x;
+            pixels[start..start + w].copy_from_slice(row);
+        }
+    }
+    Frame::new(pixels, width, height)
+}
+
+/// The areas of the frames of displays in the frame stitched from them with
+/// `stitch`, in the order of the frames
+pub fn stitched_areas(frames: &[DisplayFrame]) -> Vec<Rect> {
+    let ((left, top), _) = bounding_box(frames);
+    frames
+        .iter()
+        .map(|f| Rect {
+            x: (f.position.0 - left) as u32,
+            y: (f.position.1 - top) as u32,
+            width: f.frame.width(),
+            height: f.frame.height(),
+        })
+        .collect()
+}
+
+/// Capture all displays once, stitched into one frame of the whole desktop
+/// as with `stitch`, with the area of each display in it
+///
+/// Displays of any resolution and position are placed relative to the top
+/// left corner of their bounding box, e.g. displays left of or above the
+/// primary one at negative positions. For screenshot tools capturing
+/// everything.
+pub fn capture_all_displays_stitched() -> Result<(Frame, Vec<(DisplayInfo, Rect)>), CaptureError> {
+    let mut capturer = MultiCapturer::all().map_err(CaptureError::from_init)?;
+    let frames = capturer.capture()?;
+    let infos = displays();
+    let areas = frames
+        .iter()
+        .zip(stitched_areas(&frames))
+        .filter_map(|(f, area)| {
+            let info = infos.iter().find(|d| d.index == f.display)?;
+            Some((info.clone(), area))
+        })
+        .collect();
+    Ok((stitch(&frames), areas))
+}
+
+/// Backend capturing all displays, stitched into one frame
+pub(crate) struct VirtualDesktop {
+    capturer: MultiCapturer,
+    /// The last frame of each display, reused when a display times out
+    last: Vec<Option<DisplayFrame>>,
+    frame: Option<Frame>,
+    position: (i32, i32),
+    geometry: (u32, u32),
+}
+
+impl VirtualDesktop {
+    pub(crate) fn new() -> Result<VirtualDesktop, InitError> {
+        VirtualDesktop::with_capturer(MultiCapturer::all()?)
+    }
+
+    fn with_capturer(capturer: MultiCapturer) -> Result<VirtualDesktop, InitError> {
+        // The bounding box of the displays until the first capture
+        let displays: Vec<_> = displays()
+            .into_iter()
+            .filter(|d| capturer.displays().contains(&d.index))
+            .collect();
+        let left = displays.iter().map(|d| d.position.0).min().unwrap_or(0);
+        let top = displays.iter().map(|d| d.position.1).min().unwrap_or(0);
+        let right = displays
+            .iter()
+            .map(|d| d.position.0 + d.size.0 as i32)
+            .max();
+        let bottom = displays
+            .iter()
+            .map(|d| d.position.1 + d.size.1 as i32)
+            .max();
+        let geometry = (
+            (right.unwrap_or(left) - left) as u32,
+            (bottom.unwrap_or(top) - top) as u32,
+        );
+        Ok(VirtualDesktop {
+            last: vec![None; capturer.workers.len()],
+            capturer,
+            frame: None,
+            position: (left, top),
+            geometry,
+        })
+    }
+}
+
+impl Backend for VirtualDesktop {
+    fn name(&self) -> &'static str {
+        "virtual desktop"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        self.geometry
+    }
+
+    /// The top left corner of the bounding box of the displays
+    fn position(&self) -> (i32, i32) {
+        self.position
+    }
+
+    /// Capture all displays, and stitch the frames
+    ///
+    /// Displays that time out keep their previous frame. Times out only if
+    /// no display has a new frame.
+    fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+        let mut any_new = false;
+        for (last, res) in self.last.iter_mut().zip(self.capturer.capture_each()) {
+            match res {
+                Ok(frame) => {
+                    *last = Some(frame);
+                    any_new = true;
+                }
+                Err(CaptureError::Timeout | CaptureError::NoNewFrame) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        if !any_new || self.last.iter().any(Option::is_none) {
+            return Err(CaptureError::Timeout);
+        }
+        let frames: Vec<DisplayFrame> = self.last.iter().flatten().cloned().collect();
+        let frame = stitch(&frames);
+        self.position = (
+            frames.iter().map(|f| f.position.0).min().unwrap_or(0),
+            frames.iter().map(|f| f.position.1).min().unwrap_or(0),
+        );
+        self.geometry = frame.geometry();
+        self.frame = Some(frame);
+        Ok(())
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_ref()
+            .map(|frame| (frame.pixels(), frame.width(), frame.height()))
+    }
+
+    fn reacquire(&mut self) -> Result<(), CaptureError> {
+        let displays = self.capturer.displays();
+        // Release the displays before capturing them again
+        self.capturer = MultiCapturer {
+            workers: Vec::new(),
+        };
+        self.capturer =
+            MultiCapturer::new(&displays).map_err(|e| CaptureError::Fail(e.to_string()))?;
+        self.last = vec![None; displays.len()];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod multi_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use std::time::Duration;
+
+    /// Backend of 2x1 frames of the color of the display index, positioned
+    /// side by side
+    struct Solid {
+        display: usize,
+        frame: Option<Vec<Bgr8>>,
+    }
+
+    impl Backend for Solid {
+        fn name(&self) -> &'static str {
+            "solid"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (2, 1)
+        }
+
+        fn position(&self) -> (i32, i32) {
+            (2 * self.display as i32, self.display as i32)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.frame = Some(vec![Bgr8::new(self.display as u8, 0, 0); 2]);
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            self.frame.as_deref().map(|frame| (frame, 2, 1))
+        }
+    }
+
+    #[test]
+    fn test_multi_capturer() {
+        let mut capturer = MultiCapturer::spawn(&[1, 2], |display| {
+            Ok(Capturer::with_backend(Box::new(Solid {
+                display,
+                frame: None,
+            })))
+        })
+        .unwrap();
+        let frames = capturer.capture().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].display, 2);
+        assert_eq!(frames[1].position, (4, 2));
+
+        let desktop = capturer.capture_stitched().unwrap();
+        assert_eq!(desktop.geometry(), (4, 2));
+        let (black, one, two) = (Bgr8::new(0, 0, 0), Bgr8::new(1, 0, 0), Bgr8::new(2, 0, 0));
+        assert_eq!(
+            desktop.pixels(),
+            [one, one, black, black, black, black, two, two]
+        );
+    }
+
+    #[test]
+    fn test_stitched_areas() {
+        // A 2x2 display left of and above a 3x1 primary display
+        let frame = |display: usize, position, w: usize, h: usize| DisplayFrame {
+            display,
+            position,
+            frame: Frame::new(
+                vec![Bgr8::new(display as u8, 0, 0); w * h],
+                w as u32,
+                h as u32,
+            ),
+        };
+        let frames = [frame(1, (0, 0), 3, 1), frame(2, (-2, -1), 2, 2)];
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            stitched_areas(&frames),
+            [rect(2, 1, 3, 1), rect(0, 0, 2, 2)]
+        );
+        let desktop = stitch(&frames);
+        assert_eq!(desktop.geometry(), (5, 2));
+        let (black, one, two) = (Bgr8::new(0, 0, 0), Bgr8::new(1, 0, 0), Bgr8::new(2, 0, 0));
+        assert_eq!(
+            desktop.pixels(),
+            [two, two, black, black, black, two, two, one, one, one]
+        );
+        assert_eq!(stitch(&[]).geometry(), (0, 0));
+    }
+
+    #[test]
+    fn test_virtual_desktop() {
+        let capturer = MultiCapturer::spawn(&[1, 2], |display| {
+            Ok(Capturer::with_backend(Box::new(Solid {
+                display,
+                frame: None,
+            })))
+        })
+        .unwrap();
+        let mut desktop = VirtualDesktop::with_capturer(capturer).unwrap();
+        desktop.capture_frame(Duration::ZERO).unwrap();
+        assert_eq!(desktop.geometry(), (4, 2));
+        assert_eq!(desktop.position(), (2, 1));
+        assert_eq!(desktop.frame().unwrap().0[6], Bgr8::new(2, 0, 0));
+    }
+}
@@ -0,0 +1,158 @@
+//! Output that publishes captured frames as an NDI source on the local
+//! network, for use in live production software such as OBS or vMix.
+//!
+//! The NDI runtime is loaded dynamically when a sender is created, so it only
+//! needs to be installed on machines that actually use NDI. The runtime is
+//! looked up in the directory given by the `NDI_RUNTIME_DIR_V6` or
+//! `NDI_RUNTIME_DIR_V5` environment variable, and then by its default name.
+
+use crate::dylib::Library;
+use crate::Bgr8;
+use std::ffi::{c_char, c_void, CString};
+use std::io;
+use std::path::Path;
+use std::ptr;
+
+#[cfg(windows)]
+const NDI_LIBRARY_NAME: &str = "Processing.NDI.Lib.x64.dll";
+#[cfg(target_os = "macos")]
+const NDI_LIBRARY_NAME: &str = "libndi.dylib";
+#[cfg(all(not(windows), not(target_os = "macos")))]
+const NDI_LIBRARY_NAME: &str = "libndi.so.6";
+
+const NDI_FOURCC_BGRX: i32 = i32::from_le_bytes(*b"BGRX");
+const NDI_FRAME_FORMAT_PROGRESSIVE: i32 = 1;
+const NDI_SEND_TIMECODE_SYNTHESIZE: i64 = i64::MAX;
+
+/// `NDIlib_send_create_t`
+#[repr(C)]
+struct NdiSendCreate {
+    p_ndi_name: *const c_char,
+    p_groups: *const c_char,
+    clock_video: bool,
+    clock_audio: bool,
+}
+
+/// `NDIlib_video_frame_v2_t`
+#[repr(C)]
+struct NdiVideoFrameV2 {
+    xres: i32,
+    yres: i32,
+    four_cc: i32,
+    frame_rate_n: i32,
+    frame_rate_d: i32,
+    picture_aspect_ratio: f32,
+    frame_format_type: i32,
+    timecode: i64,
+    p_data: *const u8,
+    line_stride_in_bytes: i32,
+    p_metadata: *const c_char,
+    timestamp: i64,
+}
+
+type NdiSendInstance = *mut c_void;
+type NdiInitialize = unsafe extern "C" fn() -> bool;
+type NdiSendCreateFn = unsafe extern "C" fn(*const NdiSendCreate) -> NdiSendInstance;
+type NdiSendDestroy = unsafe extern "C" fn(NdiSendInstance);
+type NdiSendSendVideoV2 = unsafe extern "C" fn(NdiSendInstance, *const NdiVideoFrameV2);
+
+fn load_ndi_library() -> io::Result<Library> {
+    let mut last_err = None;
+    for var in &["NDI_RUNTIME_DIR_V6", "NDI_RUNTIME_DIR_V5"] {
+        if let Some(dir) = std::env::var_os(var) {
+            match Library::open(Path::new(&dir).join(NDI_LIBRARY_NAME)) {
+                Ok(lib) => return Ok(lib),
+                Err(e) => last_err = Some(e),
+            }
+        }
+    }
+    Library::open(NDI_LIBRARY_NAME).map_err(|e| last_err.unwrap_or(e))
+}
+
+/// An NDI source that frames can be sent to
+pub struct NdiSender {
+    _library: Library,
+    instance: NdiSendInstance,
+    send_destroy: NdiSendDestroy,
+    send_video: NdiSendSendVideoV2,
+    frame_rate: (u32, u32),
+}
+
+impl NdiSender {
+    /// Create an NDI source with the given name, which is what it will be
+    /// listed as on the network
+    ///
+    /// `frame_rate` is the intended frame rate as a fraction
+    /// `(numerator, denominator)`, e.g. `(30, 1)` or `(60000, 1001)`. If
+    /// `clock_video` is set, `send_frame` blocks as needed to pace frames to
+    /// this rate.
+    pub fn new(name: &str, frame_rate: (u32, u32), clock_video: bool) -> io::Result<NdiSender> {
+        let name = CString::new(name)?;
+        let library = load_ndi_library()?;
+        let (initialize, send_create, send_destroy, send_video) = unsafe {
+            (
+                library.symbol::<NdiInitialize>("NDIlib_initialize")?,
+                library.symbol::<NdiSendCreateFn>("NDIlib_send_create")?,
+                library.symbol::<NdiSendDestroy>("NDIlib_send_destroy")?,
+                library.symbol::<NdiSendSendVideoV2>("NDIlib_send_send_video_v2")?,
+            )
+        };
+        if !unsafe { initialize() } {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "NDI is not supported on this CPU",
+            ));
+        }
+        let settings = NdiSendCreate {
+            p_ndi_name: name.as_ptr(),
+            p_groups: ptr::null(),
+            clock_video,
+            clock_audio: false,
+        };
+        let instance = unsafe { send_create(&settings) };
+        if instance.is_null() {
+            return Err(io::Error::other("Failed to create NDI sender"));
+        }
+        Ok(NdiSender {
+            _library: library,
+            instance,
+            send_destroy,
+            send_video,
+            frame_rate,
+        })
+    }
+
+    /// Send a frame of the given size
+    pub fn send_frame(&mut self, frame: &[Bgr8], width: u32, height: u32) -> io::Result<()> {
+        if frame.len() != width as usize * height as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame size does not match the given geometry",
+            ));
+        }
+        let video_frame = NdiVideoFrameV2 {
+            xres: width as i32,
+            yres: height as i32,
+            four_cc: NDI_FOURCC_BGRX,
+            frame_rate_n: self.frame_rate.0 as i32,
+            frame_rate_d: self.frame_rate.1 as i32,
+            picture_aspect_ratio: 0.0,
+            frame_format_type: NDI_FRAME_FORMAT_PROGRESSIVE,
+            timecode: NDI_SEND_TIMECODE_SYNTHESIZE,
+            p_data: frame.as_ptr() as *const u8,
+            line_stride_in_bytes: (width as usize * std::mem::size_of::<Bgr8>()) as i32,
+            p_metadata: ptr::null(),
+            timestamp: 0,
+        };
+        // The synchronous variant copies the frame before returning, so the
+        // frame doesn't need to outlive this call
+        unsafe { (self.send_video)(self.instance, &video_frame) };
+        Ok(())
+    }
+}
+
+impl Drop for NdiSender {
+    fn drop(&mut self) {
+        unsafe { (self.send_destroy)(self.instance) }
+    }
+}
@@ -0,0 +1,588 @@
+//! Serialization of frames for transport between processes or machines, and
+//! delta encoding of consecutive frames with `DeltaEncoder`
+//!
+//! Each frame is written as a 40 byte header followed by the payload, with
+//! all fields in little-endian byte order:
+//!
+//! | Offset | Type | Field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | 0      | u32  | Magic, `b"CPTN"`                                       |
+//! | 4      | u16  | Format version, currently 2                            |
+//! | 6      | u8   | Pixel format, 0 for BGRX8                              |
+//! | 7      | u8   | Compression, 0 for none, 1 for LZ4, or 2 for zstd      |
+//! | 8      | u32  | Frame width                                            |
+//! | 12     | u32  | Frame height                                           |
+//! | 16     | u32  | Stride, the number of bytes from one row to the next   |
+//! | 20     | u32  | Length of the payload in bytes                         |
+//! | 24     | u64  | Sequence number                                        |
+//! | 32     | u64  | Capture time in µs since the Unix epoch, or 0          |
+//! | 40     |      | Payload                                                |
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::net::Compression;
+//! # use std::net::TcpStream;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let mut stream = TcpStream::connect("192.168.1.2:7878").unwrap();
+//! let frame = capturer.capture_frame_meta().unwrap();
+//! frame.serialize(&mut stream, Compression::None).unwrap();
+//! ```
+
+use crate::diff::changed_tiles;
+use crate::mirror::{read_tiles, write_tiles};
+use crate::{Bgr8, Frame, PixelFormat, Rect};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CPTN");
+const VERSION: u16 = 2;
+const HEADER_SIZE: usize = 40;
+const FORMAT_BGRX8: u8 = 0;
+/// Compression level of zstd, favoring speed
+#[cfg(feature = "net-zstd")]
+const ZSTD_LEVEL: i32 = 1;
+
+/// Compression of the pixels of serialized frames
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Compression {
+    #[default]
+    None = 0,
+    /// An LZ4 frame. Fast enough to compress every frame, and compresses the
+    /// flat areas of typical desktop content well. Requires the `net-lz4`
+    /// feature.
+    #[cfg(feature = "net-lz4")]
+    Lz4 = 1,
+    /// A zstd frame. Slower than LZ4, but smaller. Requires the `net-zstd`
+    /// feature.
+    #[cfg(feature = "net-zstd")]
+    Zstd = 2,
+}
+
+impl Compression {
+    /// Compress a payload
+    fn compress(self, src: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+        Ok(match self {
+            Compression::None => Cow::Borrowed(src),
+            #[cfg(feature = "net-lz4")]
+            Compression::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(src)?;
+                Cow::Owned(encoder.finish()?)
+            }
+            #[cfg(feature = "net-zstd")]
+            Compression::Zstd => Cow::Owned(zstd::bulk::compress(src, ZSTD_LEVEL)?),
+        })
+    }
+
+    /// Decompress a payload of the given compression code, that is `size`
+    /// bytes when decompressed
+    ///
+    /// The size is untrusted, so the output grows with the decompressed data
+    /// rather than being allocated up front.
+    fn decompress(code: u8, src: Vec<u8>, size: u64) -> io::Result<Vec<u8>> {
+        let out = match code {
+            0 => src,
+            #[cfg(feature = "net-lz4")]
+            1 => {
+                let mut out = Vec::new();
+                lz4_flex::frame::FrameDecoder::new(&src[..])
+                    .take(size.saturating_add(1))
+                    .read_to_end(&mut out)?;
+                out
+            }
+            #[cfg(feature = "net-zstd")]
+            2 => {
+                let mut out = Vec::new();
+                zstd::stream::Decoder::new(&src[..])?
+                    .take(size.saturating_add(1))
+                    .read_to_end(&mut out)?;
+                out
+            }
+            _ => return Err(invalid("Unsupported compression")),
+        };
+        if out.len() as u64 != size {
+            return Err(invalid("Payload does not match its size"));
+        }
+        Ok(out)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "Frame is too large")
+}
+
+/// Metadata of a serialized frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameHeader {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    /// Number of bytes from the start of one row to the next
+    pub stride: u32,
+    pub sequence: u64,
+    /// Wall clock time at which the frame was captured, if known
+    pub timestamp: Option<SystemTime>,
+    pub compression: Compression,
+}
+
+impl FrameHeader {
+    fn to_bytes(self, len: u32) -> [u8; HEADER_SIZE] {
+        let micros = self
+            .timestamp
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_micros() as u64);
+        let mut buf = [0; HEADER_SIZE];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        buf[6] = FORMAT_BGRX8;
+        buf[7] = self.compression as u8;
+        buf[8..12].copy_from_slice(&self.width.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.height.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.stride.to_le_bytes());
+        buf[20..24].copy_from_slice(&len.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[32..40].copy_from_slice(&micros.to_le_bytes());
+        buf
+    }
+
+    /// Parse a header, and the length of the payload following it
+    fn from_bytes(buf: &[u8; HEADER_SIZE]) -> io::Result<(FrameHeader, u32)> {
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        if u32_at(0) != MAGIC {
+            return Err(invalid("Bad frame magic"));
+        }
+        if u16::from_le_bytes([buf[4], buf[5]]) != VERSION {
+            return Err(invalid("Unsupported format version"));
+        }
+        if buf[6] != FORMAT_BGRX8 {
+            return Err(invalid("Unsupported pixel format"));
+        }
+        let compression = match buf[7] {
+            0 => Compression::None,
+            #[cfg(feature = "net-lz4")]
+            1 => Compression::Lz4,
+            #[cfg(feature = "net-zstd")]
+            2 => Compression::Zstd,
+            _ => return Err(invalid("Unsupported compression")),
+        };
+        let (width, height, stride) = (u32_at(8), u32_at(12), u32_at(16));
+        if (stride as u64) < 4 * width as u64 {
+            return Err(invalid("Stride is less than the width of the frame"));
+        }
+        let micros = u64_at(32);
+        let header = FrameHeader {
+            width,
+            height,
+            format: PixelFormat::Bgra8,
+            stride,
+            sequence: u64_at(24),
+            timestamp: (micros != 0).then(|| UNIX_EPOCH + Duration::from_micros(micros)),
+            compression,
+        };
+        Ok((header, u32_at(20)))
+    }
+}
+
+/// The wall clock time of a monotonic time in the past
+fn system_time(instant: Instant) -> SystemTime {
+    SystemTime::now() - instant.elapsed()
+}
+
+/// The monotonic time of a wall clock time, clamped to now if in the future
+fn instant(time: SystemTime) -> Instant {
+    let ago = SystemTime::now().duration_since(time).unwrap_or_default();
+    let now = Instant::now();
+    now.checked_sub(ago).unwrap_or(now)
+}
+
+impl Frame {
+    /// The metadata of the frame, as it would be serialized
+    pub fn header(&self, compression: Compression) -> FrameHeader {
+        FrameHeader {
+            width: self.width(),
+            height: self.height(),
+            format: PixelFormat::Bgra8,
+            stride: self.stride() as u32,
+            sequence: self.sequence(),
+            timestamp: self.timestamp().map(system_time),
+            compression,
+        }
+    }
+
+    /// Write the frame in the format described in the module documentation
+    ///
+    /// The timestamp is written as wall clock time, so it's only meaningful
+    /// to receivers on other machines as far as the clocks are synchronized.
+    pub fn serialize(&self, writer: &mut impl Write, compression: Compression) -> io::Result<()> {
+        let header = self.header(compression);
+        let payload = compression.compress(self.as_bytes())?;
+        let len = payload.len().try_into().map_err(|_| too_large())?;
+        writer.write_all(&header.to_bytes(len))?;
+        writer.write_all(&payload)
+    }
+
+    /// Read a frame written by `Self::serialize`
+    pub fn deserialize(reader: &mut impl Read) -> io::Result<Frame> {
+        let mut buf = [0; HEADER_SIZE];
+        reader.read_exact(&mut buf)?;
+        let (header, len) = FrameHeader::from_bytes(&buf)?;
+        let mut payload = Vec::new();
+        reader.take(len as u64).read_to_end(&mut payload)?;
+        if payload.len() != len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let size = header.stride as u64 * header.height as u64;
+        let bytes = Compression::decompress(header.compression as u8, payload, size)?;
+        let (width, height) = (header.width as usize, header.height as usize);
+        let mut pixels = Vec::new();
+        pixels.resize(width * height, Bgr8::default());
+        if width > 0 {
+            for (row, dst) in bytes
+                .chunks_exact(header.stride as usize)
+                .zip(pixels.chunks_exact_mut(width))
+            {
+                copy_pixels(&row[..4 * width], dst);
+            }
+        }
+        let mut frame =
+            Frame::new(pixels, header.width, header.height).with_sequence(header.sequence);
+        if let Some(timestamp) = header.timestamp {
+            frame = frame.with_timestamp(instant(timestamp));
+        }
+        Ok(frame)
+    }
+}
+
+const DELTA_MAGIC: u32 = u32::from_le_bytes(*b"CPTD");
+const DELTA_HEADER_SIZE: usize = 28;
+const FLAG_KEY_FRAME: u8 = 1;
+
+/// Copy 4 byte BGRX pixels into `dst`
+fn copy_pixels(src: &[u8], dst: &mut [Bgr8]) {
+    for (p, dst) in src.chunks_exact(4).zip(dst) {
+        *dst = Bgr8 {
+            b: p[0],
+            g: p[1],
+            r: p[2],
+            a: p[3],
+        };
+    }
+}
+
+/// Encoder of consecutive frames into updates of only the tiles that changed
+/// since the previous frame, for a `DeltaDecoder`, e.g. for a remote viewer
+/// over a slow link
+///
+/// Each update is a 28 byte header followed by the payload, with all fields
+/// in little-endian byte order:
+///
+/// | Offset | Type | Field                                                 |
+/// |--------|------|-------------------------------------------------------|
+/// | 0      | u32  | Magic, `b"CPTD"`                                      |
+/// | 4      | u16  | Format version, currently 2                           |
+/// | 6      | u8   | Flags. Bit 0 is set if the update is a full frame     |
+/// | 7      | u8   | Compression, 0 for none, 1 for LZ4, or 2 for zstd     |
+/// | 8      | u32  | Frame width                                           |
+/// | 12     | u32  | Frame height                                          |
+/// | 16     | u32  | Number of tiles                                       |
+/// | 20     | u32  | Length of the payload in bytes                        |
+/// | 24     | u32  | Length of the payload in bytes when decompressed      |
+///
+/// The decompressed payload is the tiles in the format of the updates of
+/// `mirror::MirrorSender`, each as its x, y, width, and height as u32,
+/// followed by its rows of 4 byte BGRX pixels.
+pub struct DeltaEncoder {
+    tile_size: u32,
+    compression: Compression,
+    prev: Vec<u8>,
+    width: u32,
+    height: u32,
+    tiles: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl DeltaEncoder {
+    /// Construct an encoder of tiles `tile_size` pixels square
+    pub fn new(tile_size: u32, compression: Compression) -> DeltaEncoder {
+        assert!(tile_size > 0);
+        DeltaEncoder {
+            tile_size,
+            compression,
+            prev: Vec::new(),
+            width: 0,
+            height: 0,
+            tiles: Vec::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Encode the next frame into an update
+    ///
+    /// The first frame, and frames of another size than the previous one,
+    /// are encoded whole. Frames without changes are encoded as updates
+    /// without tiles.
+    ///
+    /// Fails if compressing the update fails, leaving the encoder at the
+    /// previous frame.
+    pub fn encode(&mut self, frame: &Frame) -> io::Result<&[u8]> {
+        self.encode_components(frame.as_bytes(), frame.width(), frame.height())
+    }
+
+    /// Encode the next frame of the given size, as returned by
+    /// `Capturer::capture_frame_components`, into an update
+    ///
+    /// Fails with `InvalidInput` if the frame is not of the given size.
+    pub fn encode_components(
+        &mut self,
+        frame: &[u8],
+        width: u32,
+        height: u32,
+    ) -> io::Result<&[u8]> {
+        if frame.len() as u64 != 4 * width as u64 * height as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame does not match its size",
+            ));
+        }
+        let key = (width, height) != (self.width, self.height);
+        let tiles = if key {
+            vec![Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }]
+        } else {
+            changed_tiles(&self.prev, frame, width, height, self.tile_size)
+        };
+        self.tiles.clear();
+        write_tiles(frame, width, &tiles, &mut self.tiles);
+        let payload = self.compression.compress(&self.tiles)?;
+        let len: u32 = payload.len().try_into().map_err(|_| too_large())?;
+        let size: u32 = self.tiles.len().try_into().map_err(|_| too_large())?;
+        self.buf.clear();
+        self.buf.extend_from_slice(&DELTA_MAGIC.to_le_bytes());
+        self.buf.extend_from_slice(&VERSION.to_le_bytes());
+        self.buf.push(if key { FLAG_KEY_FRAME } else { 0 });
+        self.buf.push(self.compression as u8);
+        for v in [width, height, tiles.len() as u32] {
+            self.buf.extend_from_slice(&v.to_le_bytes());
+        }
+        self.buf.extend_from_slice(&len.to_le_bytes());
+        self.buf.extend_from_slice(&size.to_le_bytes());
+        self.buf.extend_from_slice(&payload);
+        self.prev.clear();
+        self.prev.extend_from_slice(frame);
+        self.width = width;
+        self.height = height;
+        Ok(&self.buf)
+    }
+
+    /// Encode the next frame whole, e.g. when a new viewer joins or an update
+    /// was lost
+    pub fn reset(&mut self) {
+        self.width = 0;
+        self.height = 0;
+    }
+}
+
+/// Decoder of the updates of a `DeltaEncoder`, reconstructing the frames
+#[derive(Default)]
+pub struct DeltaDecoder {
+    frame: Option<Frame>,
+    payload: Vec<u8>,
+    tiles: Vec<Rect>,
+}
+
+impl DeltaDecoder {
+    pub fn new() -> DeltaDecoder {
+        DeltaDecoder::default()
+    }
+
+    /// Read the next update and apply it to the frame
+    ///
+    /// Returns the regions of the frame that were updated.
+    pub fn decode(&mut self, reader: &mut impl Read) -> io::Result<&[Rect]> {
+        let mut header = [0; DELTA_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        if u32_at(0) != DELTA_MAGIC {
+            return Err(invalid("Bad update magic"));
+        }
+        if u16::from_le_bytes([header[4], header[5]]) != VERSION {
+            return Err(invalid("Unsupported format version"));
+        }
+        let (width, height, n_tiles) = (u32_at(8), u32_at(12), u32_at(16));
+        let (len, size) = (u32_at(20), u32_at(24));
+        let mut payload = std::mem::take(&mut self.payload);
+        payload.clear();
+        reader.take(len as u64).read_to_end(&mut payload)?;
+        if payload.len() != len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let payload = Compression::decompress(header[7], payload, size as u64)?;
+        if header[6] & FLAG_KEY_FRAME != 0 {
+            let black = Bgr8::new(0, 0, 0);
+            // The sizes are untrusted, so check them against the payload
+            // before allocating the frame
+            let n = (width as usize)
+                .checked_mul(height as usize)
+                .ok_or_else(|| invalid("Frame is too large"))?;
+            let needed = n.checked_mul(4).and_then(|bytes| bytes.checked_add(16));
+            if needed.is_none_or(|needed| payload.len() < needed) {
+                return Err(invalid("Full frame update is too short"));
+            }
+            self.frame = Some(Frame::new(vec![black; n], width, height));
+        }
+        let frame = match &mut self.frame {
+            Some(frame) if frame.geometry() == (width, height) => frame,
+            _ => return Err(invalid("Update does not match the size of the frame")),
+        };
+        self.tiles.clear();
+        read_tiles(
+            &mut &payload[..],
+            frame.as_bytes_mut(),
+            width,
+            height,
+            n_tiles,
+            &mut self.tiles,
+        )
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("Truncated tile"),
+            _ => e,
+        })?;
+        // Reuse the allocation for the next payload
+        self.payload = payload;
+        Ok(&self.tiles)
+    }
+
+    /// The reconstructed frame, once a full frame was decoded
+    pub fn frame(&self) -> Option<&Frame> {
+        self.frame.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod net_tests {
+    use super::*;
+
+    const COMPRESSIONS: &[Compression] = &[
+        Compression::None,
+        #[cfg(feature = "net-lz4")]
+        Compression::Lz4,
+        #[cfg(feature = "net-zstd")]
+        Compression::Zstd,
+    ];
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let pixels = (0..12u8).map(|v| Bgr8::new(v, 0, 255 - v)).collect();
+        let frame = Frame::new(pixels, 4, 3)
+            .with_sequence(42)
+            .with_timestamp(Instant::now());
+        for &compression in COMPRESSIONS {
+            let mut buf = Vec::new();
+            frame.serialize(&mut buf, compression).unwrap();
+            let read = Frame::deserialize(&mut &buf[..]).unwrap();
+            assert_eq!(read.pixels(), frame.pixels());
+            assert_eq!(read.geometry(), (4, 3));
+            assert_eq!(read.sequence(), 42);
+            let skew = read.timestamp().unwrap().max(frame.timestamp().unwrap())
+                - read.timestamp().unwrap().min(frame.timestamp().unwrap());
+            assert!(skew < Duration::from_millis(100));
+        }
+        let mut buf = Vec::new();
+        frame.serialize(&mut buf, Compression::None).unwrap();
+        buf[0] = 0;
+        assert!(Frame::deserialize(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn test_compressed_payload() {
+        let frame = Frame::new(vec![Bgr8::new(7, 7, 7); 64 * 64], 64, 64);
+        for &compression in &COMPRESSIONS[1..] {
+            let mut buf = Vec::new();
+            frame.serialize(&mut buf, compression).unwrap();
+            assert!(buf.len() < frame.as_bytes().len() / 2);
+            assert_eq!(
+                Frame::deserialize(&mut &buf[..]).unwrap().pixels(),
+                frame.pixels()
+            );
+            // A payload decompressing to more than the size of the frame
+            buf[12..16].copy_from_slice(&32u32.to_le_bytes());
+            assert!(Frame::deserialize(&mut &buf[..]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let black = Bgr8::new(0, 0, 0);
+        let mut frame = Frame::new(vec![black; 8 * 6], 8, 6);
+        for &compression in COMPRESSIONS {
+            let mut encoder = DeltaEncoder::new(4, compression);
+            let mut decoder = DeltaDecoder::new();
+            let update = encoder.encode(&frame).unwrap().to_vec();
+            assert_eq!(decoder.decode(&mut &update[..]).unwrap().len(), 1);
+            assert_eq!(decoder.frame(), Some(&frame));
+
+            frame.pixels_mut()[8 * 5 + 7] = Bgr8::new(255, 0, 0);
+            let update = encoder.encode(&frame).unwrap().to_vec();
+            let tiles = decoder.decode(&mut &update[..]).unwrap();
+            assert_eq!(
+                tiles,
+                [Rect {
+                    x: 4,
+                    y: 4,
+                    width: 4,
+                    height: 2
+                }]
+            );
+            assert_eq!(decoder.frame(), Some(&frame));
+
+            let update = encoder.encode(&frame).unwrap().to_vec();
+            assert!(decoder.decode(&mut &update[..]).unwrap().is_empty());
+            frame.pixels_mut()[8 * 5 + 7] = black;
+
+            // A decoder that missed the full frame can't apply updates
+            let mut late = DeltaDecoder::new();
+            let update = encoder.encode(&frame).unwrap().to_vec();
+            assert!(late.decode(&mut &update[..]).is_err());
+            encoder.reset();
+            let update = encoder.encode(&frame).unwrap().to_vec();
+            late.decode(&mut &update[..]).unwrap();
+            assert_eq!(late.frame(), Some(&frame));
+        }
+    }
+
+    #[test]
+    fn test_delta_oversized_key_frame() {
+        let frame = Frame::new(vec![Bgr8::new(0, 0, 0); 4], 2, 2);
+        let mut update = DeltaEncoder::new(4, Compression::None)
+            .encode(&frame)
+            .unwrap()
+            .to_vec();
+        update[8..16].copy_from_slice(&[0xFF; 8]);
+        assert_eq!(
+            DeltaDecoder::new()
+                .decode(&mut &update[..])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            DeltaEncoder::new(4, Compression::None)
+                .encode_components(&[0; 3], 1, 1)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Compositing of an image, e.g. a watermark or logo, onto captured frames
+//!
+//! ```no_run
+//! # use captrs::{Bgr8, Capturer, Frame};
+//! # use captrs::overlay::OverlayPosition;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let logo = Frame::new(vec![Bgr8::new(255, 255, 255); 64 * 64], 64, 64);
+//! capturer
+//!     .set_overlay(logo, OverlayPosition::BottomRight(16), 0.5)
+//!     .unwrap();
+//! ```
+
+use crate::{Bgr8, Capturer, Frame, InitError};
+
+/// Where to place an overlay in frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OverlayPosition {
+    /// In a corner, with a margin in pixels
+    TopLeft(u32),
+    TopRight(u32),
+    BottomLeft(u32),
+    BottomRight(u32),
+    Center,
+    /// With the top left corner of the image at a position in the frame. May
+    /// be partly outside of the frame.
+    At(i32, i32),
+}
+
+impl OverlayPosition {
+    /// Position of the top left corner of an image of the given size in a
+    /// frame of the given size
+    fn origin(self, (width, height): (u32, u32), (w, h): (u32, u32)) -> (i64, i64) {
+        let (width, height, w, h) = (width as i64, height as i64, w as i64, h as i64);
+        match self {
+            OverlayPosition::TopLeft(m) => (m as i64, m as i64),
+            OverlayPosition::TopRight(m) => (width - w - m as i64, m as i64),
+            OverlayPosition::BottomLeft(m) => (m as i64, height - h - m as i64),
+            OverlayPosition::BottomRight(m) => (width - w - m as i64, height - h - m as i64),
+            OverlayPosition::Center => ((width - w) / 2, (height - h) / 2),
+            OverlayPosition::At(x, y) => (x as i64, y as i64),
+        }
+    }
+}
+
+/// An image composited onto captured frames, see `Capturer::set_overlay`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlay {
+    /// The image, with straight alpha
+    pub image: Frame,
+    pub position: OverlayPosition,
+    /// Opacity of the whole image, from 0.0 to 1.0
+    pub opacity: f32,
+}
+
+impl Overlay {
+    /// Composite the overlay onto a frame of the given size
+    pub(crate) fn draw(&self, frame: &mut [Bgr8], width: u32, height: u32) {
+        let (x, y) = self.position.origin((width, height), self.image.geometry());
+        let opacity = (self.opacity * 255.0).round() as u8;
+        let image = (self.image.pixels(), self.image.geometry());
+        blend(frame, (width, height), image, (x, y), opacity);
+    }
+}
+
+/// Alpha blend an image with straight alpha onto a frame of the given size,
+/// with the top left corner of the image at `(x, y)`, scaling its alpha by
+/// `opacity` out of 255
+pub(crate) fn blend(
+    frame: &mut [Bgr8],
+    (width, height): (u32, u32),
+    (image, (iw, ih)): (&[Bgr8], (u32, u32)),
+    (x, y): (i64, i64),
+    opacity: u8,
+) {
+    for sy in 0..ih as i64 {
+        let fy = y + sy;
+        if fy < 0 || fy >= height as i64 {
+            continue;
+        }
+        for sx in 0..iw as i64 {
+            let fx = x + sx;
+            if fx < 0 || fx >= width as i64 {
+                continue;
+            }
+            let src = image[(sy * iw as i64 + sx) as usize];
+            let dst = &mut frame[(fy * width as i64 + fx) as usize];
+            let a = (src.a as u32 * opacity as u32 + 127) / 255;
+            let mix = |s: u8, d: u8| ((s as u32 * a + d as u32 * (255 - a) + 127) / 255) as u8;
+            dst.b = mix(src.b, dst.b);
+            dst.g = mix(src.g, dst.g);
+            dst.r = mix(src.r, dst.r);
+        }
+    }
+}
+
+impl Capturer {
+    /// Composite an image with straight alpha, e.g. a watermark, onto all
+    /// subsequently captured frames, with its alpha scaled by `opacity`
+    /// from 0.0 to 1.0
+    ///
+    /// The overlay is drawn over the cursor and redactions, in pixels of the
+    /// frame before it's scaled to the output size. Replace it to animate
+    /// it, e.g. with a rendered timestamp.
+    pub fn set_overlay(
+        &mut self,
+        image: Frame,
+        position: OverlayPosition,
+        opacity: f32,
+    ) -> Result<(), InitError> {
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(InitError::InvalidArgument(format!(
+                "Opacity {} is not between 0 and 1",
+                opacity
+            )));
+        }
+        self.overlay = Some(Overlay {
+            image,
+            position,
+            opacity,
+        });
+        Ok(())
+    }
+
+    /// Stop compositing an overlay onto captured frames
+    pub fn clear_overlay(&mut self) {
+        self.overlay = None;
+    }
+
+    /// The overlay composited onto captured frames, if any
+    pub fn overlay(&self) -> Option<&Overlay> {
+        self.overlay.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod overlay_tests {
+    use super::*;
+
+    #[test]
+    fn test_overlay() {
+        let white = Bgr8::new(255, 255, 255);
+        let mut half = Bgr8::new(0, 0, 0);
+        half.a = 128;
+        let image = Frame::new(vec![white, half], 2, 1);
+        let overlay = Overlay {
+            image,
+            position: OverlayPosition::BottomRight(1),
+            opacity: 1.0,
+        };
+        let mut frame = vec![white; 4 * 3];
+        overlay.draw(&mut frame, 4, 3);
+        // Blended at (1, 1) and (2, 1)
+        assert_eq!(frame[5], white);
+        assert_eq!(frame[6].r, 127);
+        assert_eq!(frame[7], white);
+
+        let overlay = Overlay {
+            opacity: 0.5,
+            position: OverlayPosition::At(-1, 0),
+            ..overlay
+        };
+        let mut frame = vec![white; 4];
+        overlay.draw(&mut frame, 2, 2);
+        assert_eq!(frame[0].r, 191);
+        assert_eq!(frame[1], white);
+    }
+}
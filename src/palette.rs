@@ -0,0 +1,148 @@
+//! Extraction of the dominant colors of frames, by median cut quantization
+
+use crate::{Bgr8, CaptureError, Capturer, Frame};
+use std::cmp::Reverse;
+
+/// Maximum number of pixels sampled when building the color histogram
+const MAX_SAMPLES: usize = 1 << 18;
+
+/// A box of histogram bins, as indices into the list of bins
+struct ColorBox {
+    start: usize,
+    end: usize,
+    population: u64,
+}
+
+/// Colors quantized to 5 bits per channel, and their counts
+fn histogram(pixels: &[Bgr8]) -> Vec<([u8; 3], u32)> {
+    let mut counts = vec![0u32; 1 << 15];
+    let step = pixels.len().div_ceil(MAX_SAMPLES).max(1);
+    for p in pixels.iter().step_by(step) {
+        let i = ((p.r as usize >> 3) << 10) | ((p.g as usize >> 3) << 5) | (p.b as usize >> 3);
+        counts[i] += 1;
+    }
+    counts
+        .iter()
+        .enumerate()
+        .filter(|&(_, &n)| n > 0)
+        .map(|(i, &n)| ([(i >> 10) as u8, (i >> 5 & 31) as u8, (i & 31) as u8], n))
+        .collect()
+}
+
+/// The channel with the widest range of values in some bins
+fn widest_channel(bins: &[([u8; 3], u32)]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (min, max) = bins.iter().fold((u8::MAX, 0), |(min, max), (rgb, _)| {
+                (min.min(rgb[c]), max.max(rgb[c]))
+            });
+            (c, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+/// Up to `k` dominant colors of some pixels, most common first
+pub(crate) fn dominant_colors(pixels: &[Bgr8], k: usize) -> Vec<Bgr8> {
+    let mut bins = histogram(pixels);
+    if bins.is_empty() || k == 0 {
+        return Vec::new();
+    }
+    let mut boxes = vec![ColorBox {
+        start: 0,
+        end: bins.len(),
+        population: bins.iter().map(|&(_, n)| n as u64).sum(),
+    }];
+    while boxes.len() < k {
+        // Split the most populous box that has more than one color
+        let i = match (0..boxes.len())
+            .filter(|&i| boxes[i].end - boxes[i].start > 1)
+            .max_by_key(|&i| boxes[i].population)
+        {
+            Some(i) => i,
+            None => break,
+        };
+        let ColorBox {
+            start,
+            end,
+            population,
+        } = boxes[i];
+        let bins = &mut bins[start..end];
+        let (channel, _) = widest_channel(bins);
+        bins.sort_unstable_by_key(|&(rgb, _)| rgb[channel]);
+        // Split at the median by population, leaving at least one bin per box
+        let mut acc = 0;
+        let mut mid = 1;
+        for (j, &(_, n)) in bins.iter().enumerate() {
+            acc += n as u64;
+            if acc * 2 >= population {
+                mid = (j + 1).clamp(1, bins.len() - 1);
+                break;
+            }
+        }
+        let lower: u64 = bins[..mid].iter().map(|&(_, n)| n as u64).sum();
+        boxes[i] = ColorBox {
+            start,
+            end: start + mid,
+            population: lower,
+        };
+        boxes.push(ColorBox {
+            start: start + mid,
+            end,
+            population: population - lower,
+        });
+    }
+    boxes.sort_by_key(|b| Reverse(b.population));
+    boxes
+        .iter()
+        .map(|b| {
+            let mut sums = [0u64; 3];
+            for &(rgb, n) in &bins[b.start..b.end] {
+                for (s, &v) in sums.iter_mut().zip(&rgb) {
+                    // Center of the 5-bit bin in 8 bits
+                    *s += ((v as u64) << 3 | 4) * n as u64;
+                }
+            }
+            let [r, g, b] = sums.map(|s| (s / b.population) as u8);
+            Bgr8::new(r, g, b)
+        })
+        .collect()
+}
+
+impl Frame {
+    /// Up to `k` dominant colors of the frame, most common first
+    ///
+    /// Uses median cut quantization on a subsample of the pixels, so the
+    /// colors are approximate.
+    pub fn dominant_colors(&self, k: usize) -> Vec<Bgr8> {
+        dominant_colors(self.pixels(), k)
+    }
+}
+
+impl Capturer {
+    /// Capture the screen and return up to `k` of its dominant colors, most
+    /// common first. See `Frame::dominant_colors`.
+    pub fn capture_dominant_colors(&mut self, k: usize) -> Result<Vec<Bgr8>, CaptureError> {
+        self.capture_store_frame()?;
+        Ok(dominant_colors(self.get_stored_frame().unwrap(), k))
+    }
+}
+
+#[cfg(test)]
+mod palette_tests {
+    use super::*;
+
+    #[test]
+    fn test_dominant_colors() {
+        let (red, blue) = (Bgr8::new(250, 10, 10), Bgr8::new(10, 10, 250));
+        let mut pixels = vec![red; 30];
+        pixels.extend(vec![blue; 10]);
+        let frame = Frame::new(pixels, 40, 1);
+        let colors = frame.dominant_colors(2);
+        assert_eq!(colors.len(), 2);
+        // Quantized to the center of the 5-bit bins
+        assert_eq!(colors[0], Bgr8::new(252, 12, 12));
+        assert_eq!(colors[1], Bgr8::new(12, 12, 252));
+        assert_eq!(frame.dominant_colors(5).len(), 2);
+    }
+}
@@ -0,0 +1,120 @@
+//! Splitting of pixel conversion and scaling across threads with rayon, with
+//! the `rayon` feature. Without it, the work is done on the calling thread.
+
+use crate::Capturer;
+#[cfg(feature = "rayon")]
+use crate::InitError;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "rayon")]
+use std::sync::Arc;
+
+/// Number of pixels converted per task
+#[cfg(feature = "rayon")]
+const CHUNK_PIXELS: usize = 1 << 16;
+
+/// Convert `src` into `dst`, of `n` elements per pixel, with `f` applied to
+/// chunks of the pixels in parallel
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+pub(crate) fn convert_chunks<T: Sync, U: Send>(
+    src: &[T],
+    dst: &mut [U],
+    n: usize,
+    f: fn(&[T], &mut [U]),
+) {
+    #[cfg(feature = "rayon")]
+    src.par_chunks(CHUNK_PIXELS)
+        .zip(dst.par_chunks_mut(n * CHUNK_PIXELS))
+        .for_each(|(src, dst)| f(src, dst));
+    #[cfg(not(feature = "rayon"))]
+    f(src, dst)
+}
+
+/// Fill the rows of `len` elements of `dst` in parallel, with `f` given the
+/// index of each row
+pub(crate) fn fill_rows<T: Send>(dst: &mut [T], len: usize, f: impl Fn(usize, &mut [T]) + Sync) {
+    if len == 0 {
+        return;
+    }
+    #[cfg(feature = "rayon")]
+    dst.par_chunks_mut(len)
+        .enumerate()
+        .for_each(|(y, row)| f(y, row));
+    #[cfg(not(feature = "rayon"))]
+    dst.chunks_mut(len)
+        .enumerate()
+        .for_each(|(y, row)| f(y, row));
+}
+
+impl Capturer {
+    /// Run `f` on the thread pool of the capturer, so that the parallel
+    /// conversions in it use the pool
+    pub(crate) fn in_pool<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        #[cfg(feature = "rayon")]
+        if let Some(pool) = &self.pool {
+            return pool.install(f);
+        }
+        f()
+    }
+
+    /// Convert and scale frames on a dedicated pool of `threads` threads,
+    /// rather than on the global rayon pool
+    #[cfg(feature = "rayon")]
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), InitError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("captrs-{}", i))
+            .build()
+            .map_err(|e| InitError::Other(e.to_string()))?;
+        self.pool = Some(Arc::new(pool));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "rayon"))]
+mod parallel_tests {
+    use crate::backend::Backend;
+    use crate::{Bgr8, CaptureError, Capturer, Frame, PixelFormat, ScaleFilter};
+    use std::time::Duration;
+
+    /// Backend of a 300x200 gradient
+    struct Gradient(Vec<Bgr8>);
+
+    impl Backend for Gradient {
+        fn name(&self) -> &'static str {
+            "gradient"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (300, 200)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 300, 200))
+        }
+    }
+
+    #[test]
+    fn test_threads() {
+        let pixels: Vec<Bgr8> = (0..200u32)
+            .flat_map(|y| (0..300u32).map(move |x| Bgr8::new(x as u8, y as u8, (x ^ y) as u8)))
+            .collect();
+        let frame = Frame::new(pixels.clone(), 300, 200);
+        let mut capturer = Capturer::builder()
+            .threads(3)
+            .build_with_backend(Box::new(Gradient(pixels)))
+            .unwrap();
+        let rgb = capturer.capture_frame_format(PixelFormat::Rgb8).unwrap();
+        assert_eq!(&rgb[3 * 301..3 * 302], [1, 1, 0]);
+        capturer
+            .set_output_size(75, 50, ScaleFilter::Bilinear)
+            .unwrap();
+        capturer.capture_store_frame().unwrap();
+        let scaled = frame.scaled(75, 50, ScaleFilter::Bilinear);
+        assert_eq!(capturer.get_stored_frame().unwrap(), scaled.pixels());
+    }
+}
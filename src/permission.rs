@@ -0,0 +1,102 @@
+//! The permission to capture the screen, which the user must grant on macOS,
+//! through the ScreenCast portal on Wayland, and for a `MediaProjection` on
+//! Android
+//!
+//! ```no_run
+//! use captrs::{permission_status, request_permission, PermissionStatus};
+//!
+//! if permission_status() != PermissionStatus::Granted {
+//!     if let Err(e) = request_permission() {
+//!         eprintln!("Screen capture is not permitted: {}", e);
+//!     }
+//! }
+//! ```
+
+use crate::InitError;
+
+/// Whether the screen may be captured
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PermissionStatus {
+    /// Capture is permitted, or needs no permission on the platform, e.g. on
+    /// Windows and X11
+    Granted,
+    /// Capture was denied, or has not been requested yet
+    Denied,
+    /// Only known when capture is requested, e.g. on Wayland, where the
+    /// portal asks the user for every session
+    Unknown,
+}
+
+/// Whether the screen may be captured, without asking the user
+pub fn permission_status() -> PermissionStatus {
+    #[cfg(target_os = "macos")]
+    return if objc2_core_graphics::CGPreflightScreenCaptureAccess() {
+        PermissionStatus::Granted
+    } else {
+        PermissionStatus::Denied
+    };
+    #[cfg(all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "android"),
+        feature = "wayland"
+    ))]
+    if crate::wayland::is_wayland_session() {
+        return PermissionStatus::Unknown;
+    }
+    #[cfg(target_os = "android")]
+    return if projection_set() {
+        PermissionStatus::Granted
+    } else {
+        PermissionStatus::Denied
+    };
+    #[cfg(not(any(target_os = "macos", target_os = "android")))]
+    PermissionStatus::Granted
+}
+
+/// Ask the user for the permission to capture the screen, if required
+///
+/// On macOS, the system prompt is shown the first time, and the permission
+/// may only take effect once the process is restarted. On Wayland, the
+/// ScreenCast portal dialog is shown, and its session is closed once the user
+/// has answered. On Android, the app must ask for a `MediaProjection` itself,
+/// and pass it to `crate::android::set_media_projection`. Fails with
+/// `InitError::PermissionDenied` if not permitted.
+pub fn request_permission() -> Result<(), InitError> {
+    #[cfg(target_os = "macos")]
+    if !objc2_core_graphics::CGRequestScreenCaptureAccess() {
+        return Err(InitError::PermissionDenied(
+            "Screen recording is not permitted. Allow it in System Settings, under Privacy \
+             & Security."
+                .to_string(),
+        ));
+    }
+    #[cfg(all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "android"),
+        feature = "wayland"
+    ))]
+    if crate::wayland::is_wayland_session() {
+        crate::wayland::Session::new(0)?;
+    }
+    #[cfg(target_os = "android")]
+    if !projection_set() {
+        return Err(InitError::PermissionDenied(
+            "No MediaProjection was granted. Request one with \
+             `MediaProjectionManager.createScreenCaptureIntent`, and pass it to \
+             `captrs::android::set_media_projection`."
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a `MediaProjection` was granted to capture with
+#[cfg(target_os = "android")]
+fn projection_set() -> bool {
+    #[cfg(feature = "android")]
+    return crate::android::projection_set();
+    #[cfg(not(feature = "android"))]
+    false
+}
@@ -0,0 +1,203 @@
+//! Pooling of the buffers of captured frames, for allocation-free capture at
+//! high frame rates
+//!
+//! ```no_run
+//! use captrs::{Capturer, FramePool};
+//!
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let pool = FramePool::new(4);
+//! loop {
+//!     let frame = pool.capture(&mut capturer).unwrap();
+//!     println!("{}x{}", frame.width(), frame.height());
+//!     // The buffer of the frame returns to the pool here
+//! }
+//! ```
+
+use crate::{Bgr8, CaptureError, Capturer, Frame};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, Weak};
+
+/// Statistics of the use of a `FramePool`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Frames captured into the pool
+    pub captured: u64,
+    /// Captures for which all buffers were in use, so that one was
+    /// allocated
+    pub exhausted: u64,
+    /// Buffers of dropped frames that were freed rather than returned,
+    /// because the pool was full
+    pub discarded: u64,
+}
+
+struct Shared {
+    size: usize,
+    /// Buffers not in use by frames
+    free: Mutex<Vec<Vec<Bgr8>>>,
+    stats: Mutex<PoolStats>,
+}
+
+impl Shared {
+    fn recycle(&self, buf: Vec<Bgr8>) {
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.size {
+            free.push(buf);
+        } else {
+            drop(free);
+            self.stats.lock().unwrap().discarded += 1;
+        }
+    }
+}
+
+/// A pool of frame buffers that captured frames are copied into and return
+/// to when dropped
+///
+/// As long as at most `size` frames of the pool are alive at once, capture
+/// allocates no memory once the buffers have grown to the size of the
+/// frames. Cloning the pool shares its buffers.
+#[derive(Clone)]
+pub struct FramePool {
+    shared: Arc<Shared>,
+}
+
+impl FramePool {
+    /// A pool of `size` buffers, allocated as they are first used
+    pub fn new(size: usize) -> FramePool {
+        FramePool {
+            shared: Arc::new(Shared {
+                size,
+                free: Mutex::new((0..size).map(|_| Vec::new()).collect()),
+                stats: Mutex::new(PoolStats::default()),
+            }),
+        }
+    }
+
+    /// Capture a frame with `capturer` into a buffer of the pool
+    ///
+    /// If all buffers are in use, one is allocated, and counted in
+    /// `PoolStats::exhausted`.
+    pub fn capture(&self, capturer: &mut Capturer) -> Result<PooledFrame, CaptureError> {
+        capturer.capture_store_frame()?;
+        let buf = self.shared.free.lock().unwrap().pop();
+        let mut stats = self.shared.stats.lock().unwrap();
+        stats.captured += 1;
+        if buf.is_none() {
+            stats.exhausted += 1;
+        }
+        drop(stats);
+        let frame = capturer.stored_frame_meta_in(buf.unwrap_or_default());
+        Ok(PooledFrame(Arc::new(Pooled {
+            frame: Some(frame),
+            pool: Arc::downgrade(&self.shared),
+        })))
+    }
+
+    /// Number of buffers kept by the pool
+    pub fn size(&self) -> usize {
+        self.shared.size
+    }
+
+    /// Number of buffers not in use by frames
+    pub fn available(&self) -> usize {
+        self.shared.free.lock().unwrap().len()
+    }
+
+    /// Statistics of the captures into the pool so far
+    pub fn stats(&self) -> PoolStats {
+        *self.shared.stats.lock().unwrap()
+    }
+}
+
+struct Pooled {
+    frame: Option<Frame>,
+    pool: Weak<Shared>,
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        if let (Some(frame), Some(pool)) = (self.frame.take(), self.pool.upgrade()) {
+            pool.recycle(frame.into_pixels());
+        }
+    }
+}
+
+/// A frame captured into a `FramePool`, dereferencing to `Frame`
+///
+/// Clones share the frame, and its buffer returns to the pool when the last
+/// clone is dropped.
+#[derive(Clone)]
+pub struct PooledFrame(Arc<Pooled>);
+
+impl Deref for PooledFrame {
+    type Target = Frame;
+
+    fn deref(&self) -> &Frame {
+        self.0.frame.as_ref().unwrap()
+    }
+}
+
+impl std::fmt::Debug for PooledFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("PooledFrame").field(&**self).finish()
+    }
+}
+
+#[cfg(test)]
+mod pool_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use std::time::Duration;
+
+    /// Backend of the same 2x2 frame every time
+    struct Still(Vec<Bgr8>);
+
+    impl Backend for Still {
+        fn name(&self) -> &'static str {
+            "still"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (2, 2)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 2, 2))
+        }
+    }
+
+    #[test]
+    fn test_frame_pool() {
+        let mut capturer = Capturer::with_backend(Box::new(Still(vec![Bgr8::new(1, 2, 3); 4])));
+        let pool = FramePool::new(2);
+        let frames: Vec<_> = (0..3)
+            .map(|_| pool.capture(&mut capturer).unwrap())
+            .collect();
+        assert_eq!(frames[0].pixels(), [Bgr8::new(1, 2, 3); 4]);
+        assert_eq!(pool.available(), 0);
+        assert_eq!(pool.stats().exhausted, 1);
+        let buffers: Vec<_> = frames.iter().map(|f| f.pixels().as_ptr()).collect();
+        let clone = frames[0].clone();
+        drop(frames);
+        // The first frame is still shared
+        assert_eq!(pool.available(), 2);
+        assert_eq!(pool.stats().discarded, 0);
+        // The pool is full
+        drop(clone);
+        assert_eq!(pool.available(), 2);
+
+        let frame = pool.capture(&mut capturer).unwrap();
+        assert!(buffers[1..].contains(&frame.pixels().as_ptr()));
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                captured: 4,
+                exhausted: 1,
+                discarded: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Low resolution previews of frames, e.g. for live thumbnails in UIs
+
+use crate::scale::{scale, ScaleFilter};
+use crate::{Bgr8, CaptureError, Capturer, Frame, InitError, Rect};
+
+/// Size of a preview at most `max_width` wide of a frame, keeping the aspect
+/// ratio
+fn preview_size(width: u32, height: u32, max_width: u32) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (0, 0);
+    }
+    let pw = max_width.clamp(1, width);
+    let ph = ((height as u64 * pw as u64 + width as u64 / 2) / width as u64).max(1);
+    (pw, ph as u32)
+}
+
+/// Scale down a frame by averaging boxes of pixels, calling `row` with each
+/// source row before it's accumulated
+///
+/// This lets the caller copy the frame in the same pass.
+fn downscale_with(
+    pixels: &[Bgr8],
+    width: u32,
+    height: u32,
+    max_width: u32,
+    mut row: impl FnMut(&[Bgr8]),
+) -> Frame {
+    let (pw, ph) = preview_size(width, height, max_width);
+    let (w, n_cols) = (width as usize, pw as usize);
+    // Column of the preview for each column of the frame
+    let columns: Vec<usize> = (0..w).map(|x| x * n_cols / w).collect();
+    let mut col_counts = vec![0u32; n_cols];
+    for &c in &columns {
+        col_counts[c] += 1;
+    }
+    let mut preview = Vec::with_capacity(n_cols * ph as usize);
+    let mut sums = vec![[0u32; 3]; n_cols];
+    let mut n_rows = 0;
+    for y in 0..height as usize {
+        let src = &pixels[y * w..(y + 1) * w];
+        row(src);
+        for (p, &c) in src.iter().zip(&columns) {
+            let s = &mut sums[c];
+            s[0] += p.b as u32;
+            s[1] += p.g as u32;
+            s[2] += p.r as u32;
+        }
+        n_rows += 1;
+        let last_of_preview_row = y + 1 == height as usize
+            || (y + 1) * ph as usize / height as usize != y * ph as usize / height as usize;
+        if last_of_preview_row {
+            for (s, &count) in sums.iter_mut().zip(&col_counts) {
+                let n = count * n_rows;
+                let [b, g, r] = s.map(|v| ((v + n / 2) / n) as u8);
+                preview.push(Bgr8::new(r, g, b));
+                *s = [0; 3];
+            }
+            n_rows = 0;
+        }
+    }
+    Frame::new(preview, pw, ph)
+}
+
+impl Frame {
+    /// A preview of the frame at most `max_width` wide, keeping the aspect
+    /// ratio, scaled down by averaging boxes of pixels
+    pub fn preview(&self, max_width: u32) -> Frame {
+        let (width, height) = self.geometry();
+        downscale_with(self.pixels(), width, height, max_width, |_| ())
+    }
+}
+
+impl Capturer {
+    /// Capture the screen and return an owned copy of the frame along with a
+    /// preview of it at most `max_width` wide
+    ///
+    /// The preview is generated in the same pass as the copy, which is much
+    /// cheaper than scaling down the full frame afterwards.
+    pub fn capture_frame_with_preview(
+        &mut self,
+        max_width: u32,
+    ) -> Result<(Frame, Frame), CaptureError> {
+        self.capture_store_frame()?;
+        let (width, height) = self.geometry();
+        let pixels = self.get_stored_frame().unwrap();
+        let mut full = Vec::with_capacity(pixels.len());
+        let preview = downscale_with(pixels, width, height, max_width, |row| {
+            full.extend_from_slice(row)
+        });
+        let timestamp = self.frame_timestamp().unwrap();
+        let sequence = self.frame_sequence();
+        Ok((
+            Frame::new(full, width, height)
+                .with_timestamp(timestamp)
+                .with_sequence(sequence),
+            preview.with_timestamp(timestamp).with_sequence(sequence),
+        ))
+    }
+
+    /// Attach a thumbnail of `width` x `height` to each captured `Frame`, as
+    /// `Frame::thumbnail`
+    ///
+    /// Thumbnails are scaled down from the same copy of the frame as the
+    /// frame itself, with the cursor, redactions and capture mask applied,
+    /// so a UI can show a live preview without scaling full frames or
+    /// capturing twice.
+    pub fn set_thumbnail(&mut self, width: u32, height: u32) -> Result<(), InitError> {
+        if width == 0 || height == 0 {
+            return Err(InitError::InvalidArgument(format!(
+                "Thumbnail size {}x{} is empty",
+                width, height
+            )));
+        }
+        self.thumbnail_size = Some((width, height));
+        Ok(())
+    }
+
+    /// Stop attaching thumbnails to frames
+    pub fn clear_thumbnail(&mut self) {
+        self.thumbnail_size = None;
+    }
+
+    /// The size of the thumbnails attached to frames, if any
+    pub fn thumbnail_size(&self) -> Option<(u32, u32)> {
+        self.thumbnail_size
+    }
+
+    /// The thumbnail of the stored frame, if thumbnails are attached
+    pub(crate) fn thumbnail(&self, pixels: &[Bgr8], width: u32, height: u32) -> Option<Frame> {
+        let size = self.thumbnail_size?;
+        let mut thumbnail = Vec::new();
+        if width > 0 && height > 0 {
+            let rect = Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            };
+            scale(
+                pixels,
+                width as usize,
+                rect,
+                size,
+                ScaleFilter::Bilinear,
+                &mut thumbnail,
+            );
+        } else {
+            thumbnail.resize(size.0 as usize * size.1 as usize, Bgr8::default());
+        }
+        let mut frame = Frame::new(thumbnail, size.0, size.1).with_sequence(self.frame_sequence());
+        if let Some(timestamp) = self.frame_timestamp() {
+            frame = frame.with_timestamp(timestamp);
+        }
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod preview_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use std::time::Duration;
+
+    /// Backend of the same 8x4 frame every time
+    struct Still(Vec<Bgr8>);
+
+    impl Backend for Still {
+        fn name(&self) -> &'static str {
+            "still"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (8, 4)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 8, 4))
+        }
+    }
+
+    #[test]
+    fn test_preview() {
+        // Left half black, right half white
+        let pixels = (0..8 * 4)
+            .map(|i| {
+                let v = if i % 8 < 4 { 0 } else { 255 };
+                Bgr8::new(v, v, v)
+            })
+            .collect();
+        let frame = Frame::new(pixels, 8, 4);
+        let preview = frame.preview(2);
+        assert_eq!(preview.geometry(), (2, 1));
+        assert_eq!(
+            preview.pixels(),
+            &[Bgr8::new(0, 0, 0), Bgr8::new(255, 255, 255)]
+        );
+        assert_eq!(frame.preview(100), frame);
+        assert_eq!(frame.preview(3).geometry(), (3, 2));
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let pixels = (0..32).map(|i| Bgr8::new(8 * i, 0, 0)).collect();
+        let mut capturer = Capturer::with_backend(Box::new(Still(pixels)));
+        assert!(capturer.set_thumbnail(0, 2).is_err());
+        assert!(capturer.capture_frame_meta().unwrap().thumbnail().is_none());
+        capturer.set_thumbnail(4, 2).unwrap();
+        let frame = capturer.capture_frame_meta().unwrap();
+        let thumbnail = frame.thumbnail().unwrap();
+        assert_eq!(thumbnail.geometry(), (4, 2));
+        assert_eq!(thumbnail.sequence(), frame.sequence());
+        assert_eq!(
+            thumbnail.pixels(),
+            frame.scaled(4, 2, ScaleFilter::Bilinear).pixels()
+        );
+        capturer.clear_thumbnail();
+        assert!(capturer.capture_frame_meta().unwrap().thumbnail().is_none());
+    }
+}
@@ -0,0 +1,224 @@
+//! Scheduling priority and CPU affinity of the capture threads of
+//! `ThreadedCapturer` and `MultiCapturer`, so that captures are not starved
+//! by e.g. an encoder at high resolutions
+
+use crate::InitError;
+
+/// Scheduling priority of a capture thread
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ThreadPriority {
+    /// The priority of the spawning thread
+    #[default]
+    Normal,
+    /// `THREAD_PRIORITY_ABOVE_NORMAL` on Windows, and a nice value of -5 on
+    /// Linux and Android
+    AboveNormal,
+    /// `THREAD_PRIORITY_HIGHEST` on Windows, and a nice value of -10 on Linux
+    /// and Android
+    Highest,
+    /// Realtime scheduling where permitted: the MMCSS `Capture` task on
+    /// Windows, and `SCHED_FIFO` on Linux and Android, which requires
+    /// `CAP_SYS_NICE` or an `RLIMIT_RTPRIO`. Falls back to `Self::Highest`
+    /// where not permitted.
+    Realtime,
+}
+
+/// Options of the capture threads of `ThreadedCapturer::spawn_with` and
+/// `MultiCapturer::spawn_with`
+///
+/// Applied before the capturer is constructed on the thread, so that
+/// threads spawned by backends inherit them on Linux.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ThreadOptions {
+    pub priority: ThreadPriority,
+    /// Indices of the CPU cores the thread may run on, or any if `None`.
+    /// Only on Windows, Linux and Android.
+    pub affinity: Option<Vec<usize>>,
+}
+
+impl ThreadOptions {
+    /// Options of a thread of the given priority, on any core
+    pub fn new(priority: ThreadPriority) -> ThreadOptions {
+        ThreadOptions {
+            priority,
+            affinity: None,
+        }
+    }
+
+    /// Pin the thread to the given CPU cores
+    pub fn affinity(mut self, cores: impl Into<Vec<usize>>) -> ThreadOptions {
+        self.affinity = Some(cores.into());
+        self
+    }
+
+    /// Apply the options to the calling thread
+    ///
+    /// Fails with `InitError::PermissionDenied` if a priority above normal is
+    /// not permitted, and with `InitError::InvalidArgument` if no core of
+    /// the affinity exists.
+    pub(crate) fn apply(&self) -> Result<(), InitError> {
+        if let Some(cores) = &self.affinity {
+            if cores.is_empty() {
+                return Err(InitError::InvalidArgument(
+                    "Affinity of no CPU cores".to_string(),
+                ));
+            }
+            sys::set_affinity(cores)?;
+        }
+        match self.priority {
+            ThreadPriority::Normal => Ok(()),
+            ThreadPriority::Realtime if sys::set_realtime() => Ok(()),
+            ThreadPriority::AboveNormal => sys::set_priority(ThreadPriority::AboveNormal),
+            ThreadPriority::Highest | ThreadPriority::Realtime => {
+                sys::set_priority(ThreadPriority::Highest)
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::ThreadPriority;
+    use crate::InitError;
+    use windows::core::w;
+    use windows::Win32::System::Threading::{
+        AvSetMmThreadCharacteristicsW, AvSetMmThreadPriority, GetCurrentThread,
+        SetThreadAffinityMask, SetThreadPriority, AVRT_PRIORITY_HIGH, THREAD_PRIORITY_ABOVE_NORMAL,
+        THREAD_PRIORITY_HIGHEST,
+    };
+
+    pub fn set_affinity(cores: &[usize]) -> Result<(), InitError> {
+        let bits = usize::BITS as usize;
+        let mask = cores
+            .iter()
+            .filter(|&&core| core < bits)
+            .fold(0usize, |mask, &core| mask | 1 << core);
+        // Fails if no core of the mask is in the process affinity
+        if mask == 0 || unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+            return Err(InitError::InvalidArgument(format!(
+                "No CPU cores {:?}",
+                cores
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn set_priority(priority: ThreadPriority) -> Result<(), InitError> {
+        let priority = match priority {
+            ThreadPriority::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+            _ => THREAD_PRIORITY_HIGHEST,
+        };
+        unsafe { SetThreadPriority(GetCurrentThread(), priority) }.map_err(InitError::from)
+    }
+
+    /// Register the thread with MMCSS as a capture task, boosting it for as
+    /// long as it lives
+    pub fn set_realtime() -> bool {
+        let mut task_index = 0;
+        match unsafe { AvSetMmThreadCharacteristicsW(w!("Capture"), &mut task_index) } {
+            Ok(handle) => {
+                let _ = unsafe { AvSetMmThreadPriority(handle, AVRT_PRIORITY_HIGH) };
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use super::ThreadPriority;
+    use crate::{InitError, PlatformCode, PlatformError};
+    use std::io;
+    use std::mem;
+
+    /// Priority of the thread with `SCHED_FIFO`, from 1 to 99. Above the
+    /// default of threaded IRQ handlers, 50, would starve them.
+    const FIFO_PRIORITY: libc::c_int = 10;
+
+    pub fn set_affinity(cores: &[usize]) -> Result<(), InitError> {
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            for &core in cores {
+                if core < libc::CPU_SETSIZE as usize {
+                    libc::CPU_SET(core, &mut set);
+                }
+            }
+            // 0 is the calling thread
+            if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(InitError::InvalidArgument(format!(
+                    "No CPU cores {:?}: {}",
+                    cores,
+                    io::Error::last_os_error()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the nice value of the thread, which is per thread on Linux
+    pub fn set_priority(priority: ThreadPriority) -> Result<(), InitError> {
+        let nice = match priority {
+            ThreadPriority::AboveNormal => -5,
+            _ => -10,
+        };
+        unsafe {
+            let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+            if libc::setpriority(libc::PRIO_PROCESS, tid, nice) != 0 {
+                let err = io::Error::last_os_error();
+                let code = PlatformCode::Os(err.raw_os_error().unwrap_or(0));
+                return Err(match err.kind() {
+                    io::ErrorKind::PermissionDenied => InitError::PermissionDenied(format!(
+                        "Not permitted to raise the priority of the capture thread: {}",
+                        err
+                    )),
+                    _ => PlatformError::new("setpriority failed", code).into(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_realtime() -> bool {
+        let param = libc::sched_param {
+            sched_priority: FIFO_PRIORITY,
+        };
+        unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) == 0 }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "android")))]
+mod sys {
+    use super::ThreadPriority;
+    use crate::InitError;
+
+    pub fn set_affinity(_cores: &[usize]) -> Result<(), InitError> {
+        Err(InitError::Unsupported(
+            "CPU affinity of capture threads is not supported on this platform".to_string(),
+        ))
+    }
+
+    pub fn set_priority(_priority: ThreadPriority) -> Result<(), InitError> {
+        Err(InitError::Unsupported(
+            "Priority of capture threads is not supported on this platform".to_string(),
+        ))
+    }
+
+    pub fn set_realtime() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod priority_tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        ThreadOptions::default().apply().unwrap();
+        assert!(matches!(
+            ThreadOptions::default().affinity(vec![]).apply(),
+            Err(InitError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+//! Handling of protected content, e.g. DRM-protected video in fullscreen,
+//! which can't be captured
+//!
+//! ```no_run
+//! # use captrs::{Capturer, ProtectedPolicy};
+//! let mut capturer = Capturer::new(0).unwrap();
+//! capturer.set_protected_policy(ProtectedPolicy::LastGoodFrame);
+//! ```
+
+#[cfg(windows)]
+use crate::Bgr8;
+use crate::{CaptureError, Capturer};
+
+/// What to do when a capture fails because protected content is displayed,
+/// i.e. with `CaptureError::AccessDenied` on Windows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ProtectedPolicy {
+    /// Return the error
+    #[default]
+    Error,
+    /// Store a black frame, of the size of the captured frames
+    BlackFrame,
+    /// Store the last captured frame again, or a black frame if none was
+    /// captured
+    LastGoodFrame,
+}
+
+impl Capturer {
+    /// Set what to do when a capture fails because protected content is
+    /// displayed, e.g. to keep a stream going with black or frozen frames
+    ///
+    /// Substituted frames are stored as if captured, and counted as errors in
+    /// `Self::stats`.
+    pub fn set_protected_policy(&mut self, policy: ProtectedPolicy) {
+        self.protected = policy;
+    }
+
+    /// What to do when a capture fails because protected content is
+    /// displayed
+    pub fn protected_policy(&self) -> ProtectedPolicy {
+        self.protected
+    }
+
+    /// Store a substitute frame in place of a capture that failed because of
+    /// protected content, as set by `Self::set_protected_policy`
+    pub(crate) fn protected_fallback(
+        &mut self,
+        res: Result<(), CaptureError>,
+    ) -> Result<(), CaptureError> {
+        match res {
+            #[cfg(windows)]
+            Err(CaptureError::AccessDenied) if self.protected != ProtectedPolicy::Error => {
+                let stored = self.get_stored_frame().is_some();
+                if self.protected == ProtectedPolicy::BlackFrame || !stored {
+                    let (width, height) = self.geometry();
+                    let mut pixels = self.substitute.take().map_or_else(Vec::new, |(p, _, _)| p);
+                    pixels.clear();
+                    pixels.resize(width as usize * height as usize, Bgr8::new(0, 0, 0));
+                    self.substitute = Some((pixels, width, height));
+                }
+                self.frame_captured();
+                Ok(())
+            }
+            res => {
+                if res.is_ok() {
+                    self.substitute = None;
+                }
+                res
+            }
+        }
+    }
+}
+
+#[cfg(all(test, windows))]
+mod protected_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use std::time::Duration;
+
+    /// Backend of a 2x1 frame, denying access after the first capture
+    struct Protected {
+        captures: u32,
+        frame: [Bgr8; 2],
+    }
+
+    impl Backend for Protected {
+        fn name(&self) -> &'static str {
+            "protected"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (2, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.captures += 1;
+            if self.captures > 1 {
+                Err(CaptureError::AccessDenied)
+            } else {
+                Ok(())
+            }
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.frame[..], 2, 1)).filter(|_| self.captures > 0)
+        }
+    }
+
+    #[test]
+    fn test_protected_policy() {
+        let white = Bgr8::new(255, 255, 255);
+        let backend = || {
+            Box::new(Protected {
+                captures: 0,
+                frame: [white; 2],
+            })
+        };
+        let mut capturer = Capturer::with_backend(backend());
+        capturer.capture_store_frame().unwrap();
+        assert!(matches!(
+            capturer.capture_store_frame(),
+            Err(CaptureError::AccessDenied)
+        ));
+
+        let mut capturer = Capturer::with_backend(backend());
+        capturer.set_protected_policy(ProtectedPolicy::LastGoodFrame);
+        capturer.capture_store_frame().unwrap();
+        capturer.capture_store_frame().unwrap();
+        assert_eq!(capturer.get_stored_frame().unwrap(), [white; 2]);
+
+        capturer.set_protected_policy(ProtectedPolicy::BlackFrame);
+        capturer.capture_store_frame().unwrap();
+        assert_eq!(
+            capturer.get_stored_frame().unwrap(),
+            [Bgr8::new(0, 0, 0); 2]
+        );
+        assert_eq!(capturer.capture_frame_meta().unwrap().geometry(), (2, 1));
+    }
+}
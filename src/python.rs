@@ -0,0 +1,229 @@
+//! Python bindings. Built as the `captrs` extension module with
+//! [maturin](https://www.maturin.rs), e.g. `maturin develop`.
+//!
+//! ```python
+//! import captrs, numpy
+//!
+//! capturer = captrs.Capturer(0)
+//! frame = capturer.capture_frame()
+//! pixels = numpy.asarray(frame)  # shape (height, width, 4), BGRX
+//!
+//! # Like `mss().grab(...)`, of a region of the first display
+//! pixels = numpy.asarray(captrs.grab(region=(0, 0, 640, 480)))
+//! ```
+
+use crate::{CaptureError, Capturer, InitError, Rect};
+use pyo3::exceptions::{
+    PyBufferError, PyPermissionError, PyRuntimeError, PyTimeoutError, PyValueError,
+};
+use pyo3::ffi;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+fn init_error(e: InitError) -> PyErr {
+    match e {
+        InitError::NotFound(_) | InitError::InvalidArgument(_) => {
+            PyValueError::new_err(e.to_string())
+        }
+        InitError::PermissionDenied(_) => PyPermissionError::new_err(e.to_string()),
+        e => PyRuntimeError::new_err(e.to_string()),
+    }
+}
+
+fn capture_error(e: CaptureError) -> PyErr {
+    match e {
+        CaptureError::Timeout => PyTimeoutError::new_err("Acquisition of next frame timed out"),
+        CaptureError::PermissionDenied => PyPermissionError::new_err("Capture is not permitted"),
+        e => PyRuntimeError::new_err(format!("Failed to capture frame: {}", e)),
+    }
+}
+
+/// A screen capturer
+#[pyclass(name = "Capturer", module = "captrs", unsendable)]
+struct PyCapturer {
+    capturer: Capturer,
+}
+
+#[pymethods]
+impl PyCapturer {
+    /// Construct a new capturer for a given capture source, e.g. a display,
+    /// by index or by name as in `displays()`
+    #[new]
+    #[pyo3(signature = (capture_src = 0, name = None))]
+    fn new(capture_src: usize, name: Option<&str>) -> PyResult<PyCapturer> {
+        match name {
+            Some(name) => Capturer::new_by_name(name),
+            None => Capturer::new(capture_src),
+        }
+        .map(|capturer| PyCapturer { capturer })
+        .map_err(init_error)
+    }
+
+    /// The width and height of the area to capture
+    fn geometry(&self) -> (u32, u32) {
+        self.capturer.geometry()
+    }
+
+    /// Only capture a region of the display, given as `(x, y, width,
+    /// height)`, or the whole display again with `None`
+    fn set_region(&mut self, region: Option<(u32, u32, u32, u32)>) -> PyResult<()> {
+        match region {
+            Some((x, y, width, height)) => self
+                .capturer
+                .set_capture_region(Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                })
+                .map_err(init_error),
+            None => {
+                self.capturer.clear_capture_region();
+                Ok(())
+            }
+        }
+    }
+
+    /// The region of the display to capture, if set
+    fn region(&self) -> Option<(u32, u32, u32, u32)> {
+        self.capturer
+            .capture_region()
+            .map(|r| (r.x, r.y, r.width, r.height))
+    }
+
+    /// Capture the screen
+    fn capture_frame(&mut self) -> PyResult<PyFrame> {
+        let frame = self.capturer.capture_frame_meta().map_err(capture_error)?;
+        let (width, height) = frame.geometry();
+        Ok(PyFrame::new(frame.as_bytes().to_vec(), width, height))
+    }
+}
+
+/// A captured frame of 4 byte BGRX pixels
+///
+/// Supports the buffer protocol as a read-only array of shape
+/// `(height, width, 4)`, so it can be viewed with e.g. `numpy.asarray`.
+#[pyclass(name = "Frame", module = "captrs", frozen)]
+struct PyFrame {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    shape: [ffi::Py_ssize_t; 3],
+    strides: [ffi::Py_ssize_t; 3],
+}
+
+impl PyFrame {
+    fn new(data: Vec<u8>, width: u32, height: u32) -> PyFrame {
+        let (w, h) = (width as ffi::Py_ssize_t, height as ffi::Py_ssize_t);
+        PyFrame {
+            data,
+            width,
+            height,
+            shape: [h, w, 4],
+            strides: [w * 4, 4, 1],
+        }
+    }
+}
+
+#[pymethods]
+impl PyFrame {
+    #[getter]
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[getter]
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The pixel data as `bytes`
+    fn tobytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.data)
+    }
+
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        if flags & ffi::PyBUF_WRITABLE == ffi::PyBUF_WRITABLE {
+            return Err(PyBufferError::new_err("Frame is read-only"));
+        }
+        let frame = slf.get();
+        let has = |flag: c_int| flags & flag == flag;
+        let view = &mut *view;
+        view.buf = frame.data.as_ptr() as *mut c_void;
+        view.len = frame.data.len() as ffi::Py_ssize_t;
+        view.readonly = 1;
+        view.itemsize = 1;
+        view.format = if has(ffi::PyBUF_FORMAT) {
+            b"B\0".as_ptr() as *mut c_char
+        } else {
+            ptr::null_mut()
+        };
+        // Without PyBUF_ND the consumer expects a flat buffer
+        if has(ffi::PyBUF_ND) {
+            view.ndim = 3;
+            view.shape = frame.shape.as_ptr() as *mut _;
+        } else {
+            view.ndim = 1;
+            view.shape = ptr::null_mut();
+        }
+        view.strides = if has(ffi::PyBUF_STRIDES) {
+            frame.strides.as_ptr() as *mut _
+        } else {
+            ptr::null_mut()
+        };
+        view.suboffsets = ptr::null_mut();
+        view.internal = ptr::null_mut();
+        view.obj = slf.into_any().into_ptr();
+        Ok(())
+    }
+}
+
+/// Capture a display once, or a region of it given as `(x, y, width,
+/// height)`, like `mss().grab(...)`
+#[pyfunction]
+#[pyo3(signature = (capture_src = 0, region = None))]
+fn grab(capture_src: usize, region: Option<(u32, u32, u32, u32)>) -> PyResult<PyFrame> {
+    let mut capturer = PyCapturer::new(capture_src, None)?;
+    capturer.set_region(region)?;
+    capturer.capture_frame()
+}
+
+/// The displays that can be captured, as dicts with the fields of
+/// `DisplayInfo`
+#[pyfunction]
+fn displays(py: Python<'_>) -> PyResult<Vec<Bound<'_, PyDict>>> {
+    crate::displays()
+        .into_iter()
+        .map(|d| {
+            let dict = PyDict::new(py);
+            dict.set_item("index", d.index)?;
+            dict.set_item("name", d.name)?;
+            dict.set_item("monitor_name", d.monitor_name)?;
+            dict.set_item("position", d.position)?;
+            dict.set_item("size", d.size)?;
+            dict.set_item("physical_size", d.physical_size)?;
+            dict.set_item("dpi", d.dpi())?;
+            dict.set_item("scale_factor", d.scale_factor)?;
+            dict.set_item("refresh_rate", d.refresh_rate)?;
+            dict.set_item("bit_depth", d.bit_depth)?;
+            dict.set_item("primary", d.primary)?;
+            Ok(dict)
+        })
+        .collect()
+}
+
+/// Cross-platform screen capture
+#[pymodule]
+fn captrs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyCapturer>()?;
+    m.add_class::<PyFrame>()?;
+    m.add_function(wrap_pyfunction!(displays, m)?)?;
+    m.add_function(wrap_pyfunction!(grab, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+//! Conversion of captured frames to a constant frame rate, e.g. for
+//! recording a 144 Hz display at 30 frames per second without the judder of
+//! dropping frames unevenly
+//!
+//! ```no_run
+//! use captrs::{Capturer, FrameRateConverter, OutputRate, ResampleStrategy};
+//!
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let mut converter =
+//!     FrameRateConverter::new(OutputRate::Exact(30), ResampleStrategy::Blend).unwrap();
+//! loop {
+//!     if let Ok(frame) = capturer.capture_frame_meta() {
+//!         for frame in converter.push(frame) {
+//!             println!("Output frame {} at {:?}", frame.sequence(), frame.timestamp());
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::{Bgr8, Frame, InitError};
+use std::time::{Duration, Instant};
+
+/// Rate of the frames output by a `FrameRateConverter`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OutputRate {
+    /// Whole frames per second
+    Exact(u32),
+    /// Frames per second as a numerator and denominator, e.g. 30000 / 1001
+    /// for NTSC rates
+    Ratio(u32, u32),
+}
+
+impl OutputRate {
+    /// Time between output frames, `None` if the rate is zero
+    fn period(self) -> Option<Duration> {
+        let (num, den) = match self {
+            OutputRate::Exact(fps) => (fps, 1),
+            OutputRate::Ratio(num, den) => (num, den),
+        };
+        if num == 0 || den == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            1_000_000_000 * den as u64 / num as u64,
+        ))
+    }
+}
+
+/// How output frames are made of the source frames
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ResampleStrategy {
+    /// The source frame closest in time to the output frame, duplicated or
+    /// dropped as needed
+    Nearest,
+    /// The average of the source frames shown since the previous output
+    /// frame, weighted by how long each was shown, like the exposure of a
+    /// camera
+    #[default]
+    Blend,
+}
+
+/// Converter of timestamped frames of a varying rate to evenly timed frames
+/// of a constant rate
+///
+/// Source frames are pushed as they are captured, and output frames are
+/// returned once the source frames around them are known, so they lag by up
+/// to one source frame. Source frames are timed by `Frame::timestamp`, or
+/// the time they are pushed if they have none. Output frames are timed and
+/// numbered from the first source frame, at `Frame::timestamp` and
+/// `Frame::sequence`.
+pub struct FrameRateConverter {
+    period: Duration,
+    strategy: ResampleStrategy,
+    /// The last source frame, and the time it's shown from
+    last: Option<(Frame, Instant)>,
+    /// Time of the next output frame
+    next: Option<Instant>,
+    /// Sequence number of the next output frame
+    sequence: u64,
+    /// Weighted sums of the components of the source frames shown since the
+    /// last output frame, if blending
+    sums: Vec<[u64; 4]>,
+    /// Sum of the weights of `sums`, in microseconds shown
+    weight: u64,
+}
+
+impl FrameRateConverter {
+    /// Fails with `InitError::InvalidArgument` if the rate is zero
+    pub fn new(rate: OutputRate, strategy: ResampleStrategy) -> Result<Self, InitError> {
+        let period = rate
+            .period()
+            .ok_or_else(|| InitError::InvalidArgument(format!("Invalid output rate {:?}", rate)))?;
+        Ok(FrameRateConverter {
+            period,
+            strategy,
+            last: None,
+            next: None,
+            sequence: 0,
+            sums: Vec::new(),
+            weight: 0,
+        })
+    }
+
+    /// Time between output frames
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Push the next source frame, and return the output frames due before
+    /// it, oldest first
+    ///
+    /// Returns several frames if source frames are missing, e.g. while the
+    /// screen is still, or none if they come faster than the output rate.
+    pub fn push(&mut self, frame: Frame) -> Vec<Frame> {
+        let time = frame.timestamp().unwrap_or_else(Instant::now);
+        let (last, from) = match self.last.take() {
+            Some(last) => last,
+            None => {
+                self.next = Some(time + self.period);
+                self.last = Some((frame, time));
+                return Vec::new();
+            }
+        };
+        let time = time.max(from);
+        let mut shown_from = from;
+        let mut next = self.next.unwrap_or(time);
+        let mut output = Vec::new();
+        while next <= time {
+            let out = match self.strategy {
+                ResampleStrategy::Nearest if next - from <= time - next => last.clone(),
+                ResampleStrategy::Nearest => frame.clone(),
+                ResampleStrategy::Blend => {
+                    self.accumulate(&last, next - shown_from);
+                    self.blended(&last)
+                }
+            };
+            output.push(out.with_timestamp(next).with_sequence(self.sequence));
+            self.sequence += 1;
+            shown_from = next;
+            next += self.period;
+        }
+        if self.strategy == ResampleStrategy::Blend {
+            self.accumulate(&last, time - shown_from);
+        }
+        self.next = Some(next);
+        self.last = Some((frame, time));
+        output
+    }
+
+    /// Start over, timing output frames from the next source frame
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.next = None;
+        self.sums.clear();
+        self.weight = 0;
+    }
+
+    /// Add a source frame shown for `shown` to the blend
+    fn accumulate(&mut self, frame: &Frame, shown: Duration) {
+        if self.sums.len() != frame.pixels().len() {
+            // Only frames of the same size are blended
+            self.sums.clear();
+            self.sums.resize(frame.pixels().len(), [0; 4]);
+            self.weight = 0;
+        }
+        let weight = shown.as_micros() as u64;
+        if weight == 0 {
+            return;
+        }
+        for (sum, p) in self.sums.iter_mut().zip(frame.pixels()) {
+            for (s, v) in sum.iter_mut().zip([p.b, p.g, p.r, p.a]) {
+                *s += v as u64 * weight;
+            }
+        }
+        self.weight += weight;
+    }
+
+    /// The blend of the source frames since the last output frame, of the
+    /// size of `frame`, and reset it
+    fn blended(&mut self, frame: &Frame) -> Frame {
+        let weight = std::mem::take(&mut self.weight);
+        if weight == 0 {
+            return frame.clone();
+        }
+        let pixels = self
+            .sums
+            .iter_mut()
+            .map(|sum| {
+                let [b, g, r, a] = sum.map(|s| ((s + weight / 2) / weight) as u8);
+                *sum = [0; 4];
+                Bgr8 { b, g, r, a }
+            })
+            .collect();
+        Frame::new(pixels, frame.width(), frame.height())
+    }
+}
+
+#[cfg(test)]
+mod rate_tests {
+    use super::*;
+
+    /// Output frames of 1x1 source frames of brightness 0, 10, 20... at
+    /// 100 Hz, as their brightnesses
+    fn convert(strategy: ResampleStrategy, rate: OutputRate) -> Vec<u8> {
+        let mut converter = FrameRateConverter::new(rate, strategy).unwrap();
+        let start = Instant::now();
+        let mut output = Vec::new();
+        for i in 0..9 {
+            let frame = Frame::new(vec![Bgr8::new(10 * i, 0, 0)], 1, 1)
+                .with_timestamp(start + Duration::from_millis(10 * i as u64));
+            for frame in converter.push(frame) {
+                assert_eq!(frame.sequence(), output.len() as u64);
+                let at = start + converter.period() * (output.len() as u32 + 1);
+                assert_eq!(frame.timestamp(), Some(at));
+                output.push(frame.pixels()[0].r);
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_frame_rate_conversion() {
+        let rate = OutputRate::Exact(25);
+        assert_eq!(convert(ResampleStrategy::Nearest, rate), [40, 80]);
+        // The 4 frames shown during each output frame
+        assert_eq!(convert(ResampleStrategy::Blend, rate), [15, 55]);
+        // Upsampled by duplicating frames
+        let rate = OutputRate::Ratio(200, 1);
+        assert_eq!(
+            convert(ResampleStrategy::Nearest, rate),
+            [0, 10, 10, 20, 20, 30, 30, 40, 40, 50, 50, 60, 60, 70, 70, 80]
+        );
+        assert!(FrameRateConverter::new(OutputRate::Ratio(30, 0), Default::default()).is_err());
+    }
+}
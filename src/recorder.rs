@@ -0,0 +1,270 @@
+//! Recording of short animated GIFs or APNGs, e.g. for bug reports
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::recorder::{AnimationFormat, Recorder};
+//! # use std::time::Duration;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let mut recorder = Recorder::new(AnimationFormat::Gif)
+//!     .fps(10.0)
+//!     .max_width(800)
+//!     .duration(Duration::from_secs(5));
+//! recorder.record(&mut capturer).unwrap();
+//! recorder.save("bug.gif").unwrap();
+//! ```
+
+use crate::convert::{to_rgb, to_rgba};
+use crate::save::SaveError;
+use crate::schedule::ClockScheduler;
+use crate::{CaptureError, Capturer, Frame};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Format of a recorded animation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AnimationFormat {
+    /// Animated GIF, with the colors of each frame quantized to a palette of
+    /// 256
+    Gif,
+    /// Animated PNG, in full color
+    Apng,
+}
+
+/// Stops a recording in progress, e.g. from another thread
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+}
+
+/// Recorder of frames into an animation
+///
+/// Frames are kept in memory until the animation is written, so recordings
+/// should be short, or scaled down with `Recorder::max_width`. All frames
+/// must be of the same size as the first. Frames of other sizes, e.g. after
+/// a resolution change, are skipped.
+pub struct Recorder {
+    format: AnimationFormat,
+    fps: f64,
+    max_width: Option<u32>,
+    duration: Option<Duration>,
+    stop: StopHandle,
+    /// Frames and their presentation timestamps
+    frames: Vec<(Frame, Duration)>,
+}
+
+impl Recorder {
+    /// Construct a recorder of 10 frames per second, in full size, recording
+    /// until stopped
+    pub fn new(format: AnimationFormat) -> Recorder {
+        Recorder {
+            format,
+            fps: 10.0,
+            max_width: None,
+            duration: None,
+            stop: StopHandle::default(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Capture at `fps` frames per second
+    pub fn fps(mut self, fps: f64) -> Recorder {
+        assert!(fps > 0.0);
+        self.fps = fps;
+        self
+    }
+
+    /// Scale frames down to at most `max_width` wide, keeping the aspect
+    /// ratio
+    pub fn max_width(mut self, max_width: u32) -> Recorder {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    /// Stop recording after `duration`
+    pub fn duration(mut self, duration: Duration) -> Recorder {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// A handle to stop `Recorder::record`
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Number of frames recorded
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Add a frame, to be shown at `pts` from the start of the animation
+    pub fn push_frame(&mut self, frame: &Frame, pts: Duration) {
+        let frame = match self.max_width {
+            Some(max_width) if max_width < frame.width() => frame.preview(max_width),
+            _ => frame.clone(),
+        };
+        if let Some((first, _)) = self.frames.first() {
+            if first.geometry() != frame.geometry() {
+                return;
+            }
+        }
+        self.frames.push((frame, pts));
+    }
+
+    /// Capture frames at the set rate until the set duration has passed, or
+    /// until stopped with a `StopHandle`
+    ///
+    /// Frames are appended to those already recorded.
+    pub fn record(&mut self, capturer: &mut Capturer) -> Result<(), SaveError> {
+        self.stop.0.store(false, Ordering::Relaxed);
+        let offset = self.frames.last().map_or(Duration::ZERO, |&(_, pts)| {
+            pts + Duration::from_secs_f64(1.0 / self.fps)
+        });
+        let start = Instant::now();
+        let mut scheduler = ClockScheduler::new(move || start.elapsed(), self.fps);
+        while !self.stop.0.load(Ordering::Relaxed) {
+            if self.duration.is_some_and(|d| start.elapsed() >= d) {
+                break;
+            }
+            // On timeouts the screen is unchanged, so the last frame is
+            // shown for longer
+            match scheduler.capture(capturer) {
+                Ok(scheduled) => self.push_frame(&scheduled.frame, offset + scheduled.pts),
+                Err(CaptureError::Timeout | CaptureError::NoNewFrame) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// How long each frame is shown, until the next frame
+    fn delays(&self) -> Vec<Duration> {
+        let interval = Duration::from_secs_f64(1.0 / self.fps);
+        let mut delays: Vec<Duration> = self
+            .frames
+            .windows(2)
+            .map(|w| w[1].1.saturating_sub(w[0].1))
+            .collect();
+        if !self.frames.is_empty() {
+            delays.push(interval);
+        }
+        delays
+    }
+
+    /// Encode the recorded frames and write the animation
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), SaveError> {
+        let (width, height) = self.frames.first().map_or((0, 0), |(f, _)| f.geometry());
+        if self.frames.is_empty() || width == 0 || height == 0 {
+            return Err(SaveError::Encode("No frames recorded".to_string()));
+        }
+        match self.format {
+            AnimationFormat::Gif => self.write_gif(writer, width, height),
+            AnimationFormat::Apng => self.write_apng(writer, width, height),
+        }
+    }
+
+    fn write_gif<W: Write>(&self, writer: W, width: u32, height: u32) -> Result<(), SaveError> {
+        let encode_err = |e: gif::EncodingError| SaveError::Encode(e.to_string());
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(SaveError::Encode("Frame too large for GIF".to_string()));
+        }
+        let (w, h) = (width as u16, height as u16);
+        let mut encoder = gif::Encoder::new(writer, w, h, &[]).map_err(encode_err)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(encode_err)?;
+        let mut rgba = Vec::new();
+        for ((frame, _), delay) in self.frames.iter().zip(self.delays()) {
+            rgba.resize(4 * frame.pixels().len(), 0);
+            to_rgba(frame.pixels(), &mut rgba);
+            let mut gif_frame = gif::Frame::from_rgba_speed(w, h, &mut rgba, 10);
+            // In hundredths of a second
+            gif_frame.delay = (delay.as_millis() / 10).clamp(1, u16::MAX as u128) as u16;
+            encoder.write_frame(&gif_frame).map_err(encode_err)?;
+        }
+        Ok(())
+    }
+
+    fn write_apng<W: Write>(&self, writer: W, width: u32, height: u32) -> Result<(), SaveError> {
+        let encode_err = |e: png::EncodingError| SaveError::Encode(e.to_string());
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(self.frames.len() as u32, 0)
+            .map_err(encode_err)?;
+        let mut writer = encoder.write_header().map_err(encode_err)?;
+        let mut rgb = Vec::new();
+        for ((frame, _), delay) in self.frames.iter().zip(self.delays()) {
+            // In thousandths of a second
+            let ms = delay.as_millis().clamp(1, u16::MAX as u128) as u16;
+            writer.set_frame_delay(ms, 1000).map_err(encode_err)?;
+            rgb.resize(3 * frame.pixels().len(), 0);
+            to_rgb(frame.pixels(), &mut rgb);
+            writer.write_image_data(&rgb).map_err(encode_err)?;
+        }
+        writer.finish().map_err(encode_err)
+    }
+
+    /// Encode the recorded frames and save the animation to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod recorder_tests {
+    use super::*;
+    use crate::Bgr8;
+
+    fn recording(format: AnimationFormat) -> Recorder {
+        let mut recorder = Recorder::new(format).max_width(2);
+        let ms = Duration::from_millis;
+        for (i, &pts) in [ms(0), ms(100), ms(300)].iter().enumerate() {
+            let frame = Frame::new(vec![Bgr8::new(50 * i as u8, 0, 0); 16], 4, 4);
+            recorder.push_frame(&frame, pts);
+        }
+        // Of another size, so skipped
+        recorder.push_frame(&Frame::new(vec![Bgr8::new(0, 0, 0); 2], 1, 2), ms(400));
+        recorder
+    }
+
+    #[test]
+    fn test_recorder() {
+        let recorder = recording(AnimationFormat::Gif);
+        assert_eq!(recorder.len(), 3);
+        assert_eq!(recorder.frames[0].0.geometry(), (2, 2));
+        let ms = Duration::from_millis;
+        assert_eq!(recorder.delays(), [ms(100), ms(200), ms(100)]);
+
+        let mut gif = Vec::new();
+        recorder.write(&mut gif).unwrap();
+        let mut decoder = gif::DecodeOptions::new().read_info(&gif[..]).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, [10, 20, 10]);
+
+        let mut apng = Vec::new();
+        recording(AnimationFormat::Apng).write(&mut apng).unwrap();
+        let reader = png::Decoder::new(std::io::Cursor::new(apng))
+            .read_info()
+            .unwrap();
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 3);
+    }
+}
@@ -0,0 +1,248 @@
+//! Redaction of regions of frames, to hide sensitive parts of the screen
+//! before frames leave the process
+
+use crate::roi::clip;
+use crate::{Bgr8, Capturer, Frame, Rect};
+
+/// How to obscure a region of a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Redaction {
+    /// Box blur with the given radius in pixels
+    Blur(u32),
+    /// Replace blocks of the given size in pixels by their average color
+    Pixelate(u32),
+    /// Fill with black
+    Blackout,
+}
+
+/// Box blur a line of `n` pixels starting at `start` with a distance of
+/// `step` between pixels
+fn blur_line(
+    pixels: &mut [Bgr8],
+    start: usize,
+    step: usize,
+    n: usize,
+    radius: usize,
+    line: &mut Vec<Bgr8>,
+) {
+    line.clear();
+    line.extend((0..n).map(|i| pixels[start + i * step]));
+    // Running sums over the window [i - radius, i + radius], clamped to the line
+    let mut sums = [0u32; 3];
+    let add = |sums: &mut [u32; 3], p: Bgr8, sign: i32| {
+        for (s, v) in sums.iter_mut().zip([p.b, p.g, p.r]) {
+            *s = (*s as i32 + sign * v as i32) as u32;
+        }
+    };
+    for p in &line[..radius.min(n)] {
+        add(&mut sums, *p, 1);
+    }
+    for i in 0..n {
+        if i + radius < n {
+            add(&mut sums, line[i + radius], 1);
+        }
+        if i > radius {
+            add(&mut sums, line[i - radius - 1], -1);
+        }
+        let count = ((i + radius).min(n - 1) + 1 - i.saturating_sub(radius)) as u32;
+        let [b, g, r] = sums.map(|s| ((s + count / 2) / count) as u8);
+        let p = &mut pixels[start + i * step];
+        p.b = b;
+        p.g = g;
+        p.r = r;
+    }
+}
+
+/// Obscure a region of a frame of the given size
+pub(crate) fn redact(
+    pixels: &mut [Bgr8],
+    width: u32,
+    height: u32,
+    region: Rect,
+    redaction: Redaction,
+) {
+    let r = clip(region, width, height);
+    let w = width as usize;
+    let rows = r.y as usize..(r.y + r.height) as usize;
+    let cols = r.x as usize..(r.x + r.width) as usize;
+    match redaction {
+        Redaction::Blackout => {
+            for y in rows {
+                for p in &mut pixels[y * w + cols.start..y * w + cols.end] {
+                    *p = Bgr8 {
+                        a: p.a,
+                        ..Bgr8::default()
+                    };
+                }
+            }
+        }
+        Redaction::Blur(radius) => {
+            let mut line = Vec::new();
+            for y in rows.clone() {
+                blur_line(
+                    pixels,
+                    y * w + cols.start,
+                    1,
+                    cols.len(),
+                    radius as usize,
+                    &mut line,
+                );
+            }
+            for x in cols {
+                blur_line(
+                    pixels,
+                    rows.start * w + x,
+                    w,
+                    rows.len(),
+                    radius as usize,
+                    &mut line,
+                );
+            }
+        }
+        Redaction::Pixelate(size) => {
+            let size = size.max(1);
+            for by in (r.y..r.y + r.height).step_by(size as usize) {
+                let bh = size.min(r.y + r.height - by);
+                for bx in (r.x..r.x + r.width).step_by(size as usize) {
+                    let block = Rect {
+                        x: bx,
+                        y: by,
+                        width: size.min(r.x + r.width - bx),
+                        height: bh,
+                    };
+                    let avg = crate::analysis::region_average(pixels, width, block);
+                    for y in block.y as usize..(block.y + block.height) as usize {
+                        let start = y * w + block.x as usize;
+                        for p in &mut pixels[start..start + block.width as usize] {
+                            *p = Bgr8 { a: p.a, ..avg };
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Obscure the regions of a mask in a frame of the given size
+pub(crate) fn apply_mask(pixels: &mut [Bgr8], width: u32, height: u32, mask: &[(Rect, Redaction)]) {
+    for &(region, redaction) in mask {
+        redact(pixels, width, height, region, redaction);
+    }
+}
+
+impl Capturer {
+    /// Obscure a region of all subsequently captured frames
+    ///
+    /// The region is obscured before any capture method returns data, in
+    /// frames in every pixel format. While any region is redacted, captures
+    /// that bypass the copy of the frame fail or omit the bypassing data:
+    /// `Self::capture_d3d11_texture` fails, and frames carry no DMA-BUF.
+    pub fn add_redaction(&mut self, region: Rect, redaction: Redaction) {
+        self.redactions.push((region, redaction));
+    }
+
+    /// Stop obscuring regions of subsequently captured frames
+    pub fn clear_redactions(&mut self) {
+        self.redactions.clear();
+    }
+
+    /// The regions obscured in captured frames
+    pub fn redactions(&self) -> &[(Rect, Redaction)] {
+        &self.redactions
+    }
+}
+
+impl Frame {
+    /// Obscure some regions of the frame. Regions are clipped to the bounds
+    /// of the frame.
+    pub fn redact(&mut self, regions: &[Rect], redaction: Redaction) {
+        let (width, height) = self.geometry();
+        for &region in regions {
+            redact(self.pixels_mut(), width, height, region, redaction);
+        }
+    }
+}
+
+#[cfg(test)]
+mod redact_tests {
+    use super::*;
+    use crate::PixelFormat;
+
+    #[test]
+    fn test_redact() {
+        let pixels = (0..16).map(|i| Bgr8::new(16 * i, 0, 0)).collect();
+        let frame = Frame::new(pixels, 4, 4);
+        let region = Rect {
+            x: 2,
+            y: 2,
+            width: 8,
+            height: 8,
+        };
+        let red = |f: &Frame| f.pixels().iter().map(|p| p.r).collect::<Vec<_>>();
+
+        let mut blackout = frame.clone();
+        blackout.redact(&[region], Redaction::Blackout);
+        assert_eq!(red(&blackout)[8..], [128, 144, 0, 0, 192, 208, 0, 0]);
+
+        let mut pixelated = frame.clone();
+        pixelated.redact(&[region], Redaction::Pixelate(2));
+        // The average of 160, 176, 224, and 240
+        assert_eq!(
+            red(&pixelated)[8..],
+            [128, 144, 200, 200, 192, 208, 200, 200]
+        );
+
+        let mut blurred = frame.clone();
+        blurred.redact(&[region], Redaction::Blur(1));
+        assert_eq!(red(&blurred)[8..], [128, 144, 200, 200, 192, 208, 200, 200]);
+        assert_eq!(red(&blurred)[..8], red(&frame)[..8]);
+    }
+    /// Backend of a white 2x1 frame
+    struct White(Vec<Bgr8>);
+
+    impl crate::Backend for White {
+        fn name(&self) -> &'static str {
+            "white"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (2, 1)
+        }
+
+        fn capture_frame(
+            &mut self,
+            _timeout: std::time::Duration,
+        ) -> Result<(), crate::CaptureError> {
+            self.0 = vec![Bgr8::new(255, 255, 255); 2];
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 2, 1))
+        }
+    }
+
+    #[test]
+    fn test_capturer_redactions() {
+        let mut capturer = Capturer::with_backend(Box::new(White(Vec::new())));
+        let region = Rect {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        capturer.add_redaction(region, Redaction::Blackout);
+        let (black, white) = (Bgr8::new(0, 0, 0), Bgr8::new(255, 255, 255));
+        assert_eq!(capturer.capture_frame().unwrap(), [black, white]);
+        assert_eq!(
+            capturer.capture_frame_meta().unwrap().pixels(),
+            [black, white]
+        );
+        assert_eq!(
+            capturer.capture_frame_format(PixelFormat::Rgb8).unwrap(),
+            [0, 0, 0, 255, 255, 255]
+        );
+        capturer.clear_redactions();
+        assert_eq!(capturer.capture_frame().unwrap(), [white, white]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Instant replay: the last seconds of a capture kept in memory, to be saved
+//! on demand, e.g. to clip the last 30 seconds after something happened
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::replay::ReplayBuffer;
+//! # use std::time::Duration;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let mut replay = ReplayBuffer::new(Duration::from_secs(30)).max_bytes(2 << 30);
+//! for _ in 0..1000 {
+//!     let _ = replay.capture(&mut capturer);
+//! }
+//! println!("{} frames over {:?}", replay.len(), replay.buffered());
+//! ```
+
+use crate::{Bgr8, CaptureError, Capturer, Frame};
+use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// Ring buffer of the frames of the last seconds of a capture
+///
+/// Frames older than the duration of the buffer, relative to the newest
+/// frame, are dropped as frames are pushed. So are the oldest frames when
+/// the pixels of the buffered frames exceed `Self::max_bytes`.
+pub struct ReplayBuffer {
+    duration: Duration,
+    max_bytes: Option<usize>,
+    frames: VecDeque<(Frame, Instant)>,
+    bytes: usize,
+    /// Pixels of the last dropped frame, reused for the next capture
+    recycled: Vec<Bgr8>,
+}
+
+impl ReplayBuffer {
+    /// Construct a buffer of the last `duration` of frames
+    pub fn new(duration: Duration) -> ReplayBuffer {
+        ReplayBuffer {
+            duration,
+            max_bytes: None,
+            frames: VecDeque::new(),
+            bytes: 0,
+            recycled: Vec::new(),
+        }
+    }
+
+    /// Bound the memory used by the pixels of the buffered frames
+    pub fn max_bytes(mut self, max_bytes: usize) -> ReplayBuffer {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Number of buffered frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Time between the oldest and the newest buffered frame
+    pub fn buffered(&self) -> Duration {
+        match (self.frames.front(), self.frames.back()) {
+            (Some((_, first)), Some((_, last))) => last.saturating_duration_since(*first),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// The buffered frames, oldest first, with their times relative to the
+    /// oldest frame
+    pub fn frames(&self) -> impl Iterator<Item = (&Frame, Duration)> + '_ {
+        let start = self.frames.front().map(|&(_, t)| t);
+        self.frames
+            .iter()
+            .map(move |(frame, t)| (frame, t.saturating_duration_since(start.unwrap())))
+    }
+
+    /// Drop all buffered frames
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
+
+    /// Add a frame, captured at its timestamp or else now
+    pub fn push(&mut self, frame: Frame) {
+        let time = frame.timestamp().unwrap_or_else(Instant::now);
+        self.bytes += frame.as_bytes().len();
+        self.frames.push_back((frame, time));
+        while self.frames.len() > 1 {
+            let (frame, first) = &self.frames[0];
+            let expired = time.saturating_duration_since(*first) > self.duration;
+            let full = self
+                .max_bytes
+                .is_some_and(|max_bytes| self.bytes > max_bytes);
+            if !expired && !full {
+                break;
+            }
+            self.bytes -= frame.as_bytes().len();
+            let (frame, _) = self.frames.pop_front().unwrap();
+            self.recycled = frame.into_pixels();
+        }
+    }
+
+    /// Capture a frame into the buffer, reusing the allocation of a dropped
+    /// frame if any
+    pub fn capture(&mut self, capturer: &mut Capturer) -> Result<(), CaptureError> {
+        capturer.capture_store_frame()?;
+        let frame = capturer.stored_frame_meta_in(mem::take(&mut self.recycled));
+        self.push(frame);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+
+    fn frame(start: Instant, ms: u64) -> Frame {
+        Frame::new(vec![Bgr8::new(0, 0, 0); 4], 2, 2)
+            .with_timestamp(start + Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn test_replay_buffer() {
+        let start = Instant::now();
+        let mut replay = ReplayBuffer::new(Duration::from_millis(100));
+        assert_eq!(replay.buffered(), Duration::ZERO);
+        for ms in (0..=150).step_by(50) {
+            replay.push(frame(start, ms));
+        }
+        // The frame at 0 ms is more than 100 ms older than the newest
+        assert_eq!(replay.len(), 3);
+        assert_eq!(replay.buffered(), Duration::from_millis(100));
+        let times: Vec<Duration> = replay.frames().map(|(_, t)| t).collect();
+        assert_eq!(times[2], Duration::from_millis(100));
+
+        // Two frames of 16 bytes fit
+        let mut replay = ReplayBuffer::new(Duration::from_secs(1)).max_bytes(40);
+        for ms in 0..5 {
+            replay.push(frame(start, ms));
+        }
+        assert_eq!(replay.len(), 2);
+        replay.clear();
+        assert!(replay.is_empty());
+    }
+}
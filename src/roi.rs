@@ -0,0 +1,195 @@
+//! Processing of regions of interest of captured frames
+//!
+//! Register named regions with callbacks on a `RoiPipeline`, and each frame
+//! passed through it delivers views of the regions to their callbacks,
+//! without cropping or copying the frame. Useful for OCR of parts of the
+//! screen, parsing HUD values of games, etc.
+
+use crate::{Bgr8, CaptureError, Capturer, Frame, Rect};
+
+/// A view of a region of a frame
+#[derive(Clone, Copy, Debug)]
+pub struct RegionView<'a> {
+    frame: &'a [Bgr8],
+    stride: usize,
+    region: Rect,
+}
+
+impl<'a> RegionView<'a> {
+    /// The region of the frame that is viewed
+    pub fn region(&self) -> Rect {
+        self.region
+    }
+
+    pub fn width(&self) -> u32 {
+        self.region.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.region.height
+    }
+
+    /// The pixels of row `y` of the region
+    pub fn row(&self, y: u32) -> &'a [Bgr8] {
+        assert!(y < self.region.height);
+        let start = (self.region.y + y) as usize * self.stride + self.region.x as usize;
+        &self.frame[start..start + self.region.width as usize]
+    }
+
+    /// The rows of the region, from top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &'a [Bgr8]> + '_ {
+        (0..self.region.height).map(move |y| self.row(y))
+    }
+
+    /// Copy the region into an owned frame
+    pub fn to_frame(&self) -> Frame {
+        let mut pixels =
+            Vec::with_capacity(self.region.width as usize * self.region.height as usize);
+        for row in self.rows() {
+            pixels.extend_from_slice(row);
+        }
+        Frame::new(pixels, self.region.width, self.region.height)
+    }
+}
+
+/// The intersection of a region with a frame of the given size
+pub(crate) fn clip(region: Rect, width: u32, height: u32) -> Rect {
+    let x = region.x.min(width);
+    let y = region.y.min(height);
+    Rect {
+        x,
+        y,
+        width: region.width.min(width - x),
+        height: region.height.min(height - y),
+    }
+}
+
+type Callback<'f> = Box<dyn FnMut(&str, RegionView) + 'f>;
+
+struct Roi<'f> {
+    name: String,
+    region: Rect,
+    callback: Callback<'f>,
+}
+
+/// A set of named regions of interest, with callbacks for each
+#[derive(Default)]
+pub struct RoiPipeline<'f> {
+    rois: Vec<Roi<'f>>,
+}
+
+impl<'f> RoiPipeline<'f> {
+    pub fn new() -> RoiPipeline<'f> {
+        RoiPipeline::default()
+    }
+
+    /// Register a region that `callback` will be called with for each frame,
+    /// replacing any region of the same name
+    ///
+    /// Regions are clipped to the bounds of the frame. Empty regions are
+    /// skipped.
+    pub fn add<F>(&mut self, name: &str, region: Rect, callback: F)
+    where
+        F: FnMut(&str, RegionView) + 'f,
+    {
+        self.remove(name);
+        self.rois.push(Roi {
+            name: name.to_string(),
+            region,
+            callback: Box::new(callback),
+        });
+    }
+
+    /// Remove the region of the given name. Returns whether it existed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.rois.len();
+        self.rois.retain(|roi| roi.name != name);
+        self.rois.len() != len
+    }
+
+    /// The region of the given name, if registered
+    pub fn region(&self, name: &str) -> Option<Rect> {
+        self.rois
+            .iter()
+            .find(|roi| roi.name == name)
+            .map(|roi| roi.region)
+    }
+
+    /// Move or resize the region of the given name. Returns whether it
+    /// existed.
+    pub fn set_region(&mut self, name: &str, region: Rect) -> bool {
+        match self.rois.iter_mut().find(|roi| roi.name == name) {
+            Some(roi) => {
+                roi.region = region;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Call the callbacks of the regions with views of a frame of the given
+    /// size, in the order the regions were registered
+    pub fn process(&mut self, frame: &[Bgr8], width: u32, height: u32) {
+        assert_eq!(frame.len(), width as usize * height as usize);
+        for roi in &mut self.rois {
+            let region = clip(roi.region, width, height);
+            if region.width == 0 || region.height == 0 {
+                continue;
+            }
+            let view = RegionView {
+                frame,
+                stride: width as usize,
+                region,
+            };
+            (roi.callback)(&roi.name, view)
+        }
+    }
+}
+
+impl Capturer {
+    /// Capture the screen and pass the frame through a pipeline of regions
+    /// of interest
+    ///
+    /// The frame is stored in `self`, as by `Self::capture_store_frame`.
+    pub fn capture_rois(&mut self, pipeline: &mut RoiPipeline) -> Result<(), CaptureError> {
+        self.capture_store_frame()?;
+        let (width, height) = self.geometry();
+        pipeline.process(self.get_stored_frame().unwrap(), width, height);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod roi_tests {
+    use super::*;
+
+    #[test]
+    fn test_roi_pipeline() {
+        let frame: Vec<Bgr8> = (0..16).map(|i| Bgr8::new(i, 0, 0)).collect();
+        let mut seen = Vec::new();
+        {
+            let mut pipeline = RoiPipeline::new();
+            let region = Rect {
+                x: 1,
+                y: 2,
+                width: 2,
+                height: 5,
+            };
+            pipeline.add("score", region, |name, view| {
+                let reds: Vec<u8> = view.rows().flatten().map(|p| p.r).collect();
+                seen.push((name.to_string(), view.region(), reds));
+            });
+            pipeline.process(&frame, 4, 4);
+        }
+        let clipped = Rect {
+            x: 1,
+            y: 2,
+            width: 2,
+            height: 2,
+        };
+        assert_eq!(
+            seen,
+            vec![("score".to_string(), clipped, vec![9, 10, 13, 14])]
+        );
+    }
+}
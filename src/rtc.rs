@@ -0,0 +1,189 @@
+//! WebRTC integration. Encodes captured frames with H.264 (OpenH264) and
+//! writes them to a `webrtc-rs` video track, for browser-based remote viewing
+//! without an external media server.
+//!
+//! Add the track to a peer connection, and let the capture track handle
+//! keyframe requests from the remote peer:
+//!
+//! ```no_run
+//! # async fn f(peer_connection: webrtc::peer_connection::RTCPeerConnection) {
+//! # use captrs::{Capturer, rtc::CaptureTrack};
+//! # use std::sync::Arc;
+//! # use webrtc::track::track_local::TrackLocal;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let mut track = CaptureTrack::new(30).unwrap();
+//! let sender = peer_connection
+//!     .add_track(track.track() as Arc<dyn TrackLocal + Send + Sync>)
+//!     .await
+//!     .unwrap();
+//! track.watch_rtcp(sender);
+//! // ... signalling ...
+//! loop {
+//!     track.wait_for_next_frame().await;
+//!     capturer.capture_store_frame().unwrap();
+//!     let (w, h) = capturer.geometry();
+//!     track.write_frame(capturer.get_stored_frame().unwrap(), w, h).await.unwrap();
+//! }
+//! # }
+//! ```
+
+use crate::{pixel_bytes, Bgr8};
+use openh264::encoder::{Encoder, EncoderConfig};
+use openh264::formats::{BgraSliceU8, YUVBuffer, YUVSource};
+use openh264::OpenH264API;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use webrtc::api::media_engine::MIME_TYPE_H264;
+use webrtc::media::Sample;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+
+/// Error when writing a frame to the track
+#[derive(Debug)]
+pub enum TrackError {
+    /// The frame could not be encoded
+    Encode(openh264::Error),
+    /// The encoded frame could not be written to the track
+    Write(webrtc::Error),
+}
+
+/// A WebRTC video track fed with captured frames
+pub struct CaptureTrack {
+    track: Arc<TrackLocalStaticSample>,
+    encoder: Encoder,
+    yuv: Option<YUVBuffer>,
+    keyframe_requested: Arc<AtomicBool>,
+    interval: Interval,
+    last_frame: Option<Instant>,
+    frame_duration: Duration,
+}
+
+impl CaptureTrack {
+    /// Create a new H.264 track, paced for `fps` frames per second
+    pub fn new(fps: u32) -> Result<CaptureTrack, openh264::Error> {
+        CaptureTrack::with_bitrate(fps, 4_000_000)
+    }
+
+    /// Create a new H.264 track, paced for `fps` frames per second and encoded
+    /// with a target bitrate of `bitrate` bits per second
+    pub fn with_bitrate(fps: u32, bitrate: u32) -> Result<CaptureTrack, openh264::Error> {
+        assert!(fps > 0, "Frame rate must be positive");
+        let config = EncoderConfig::new()
+            .max_frame_rate(fps as f32)
+            .set_bitrate_bps(bitrate);
+        let encoder = Encoder::with_api_config(OpenH264API::from_source(), config)?;
+        let track = Arc::new(TrackLocalStaticSample::new(
+            RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_H264.to_owned(),
+                clock_rate: 90000,
+                sdp_fmtp_line:
+                    "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f"
+                        .to_owned(),
+                ..Default::default()
+            },
+            "video".to_owned(),
+            "captrs".to_owned(),
+        ));
+        let frame_duration = Duration::from_secs(1) / fps;
+        let mut interval = tokio::time::interval(frame_duration);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        Ok(CaptureTrack {
+            track,
+            encoder,
+            yuv: None,
+            keyframe_requested: Arc::new(AtomicBool::new(false)),
+            interval,
+            last_frame: None,
+            frame_duration,
+        })
+    }
+
+    /// The track, to be added to a peer connection
+    pub fn track(&self) -> Arc<TrackLocalStaticSample> {
+        self.track.clone()
+    }
+
+    /// Request that the next frame is encoded as a keyframe
+    pub fn request_keyframe(&self) {
+        self.keyframe_requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Spawn a task reading RTCP packets from the sender of this track, which
+    /// requests a keyframe whenever the remote peer sends a picture loss
+    /// indication or full intra request
+    ///
+    /// Must be called from within a tokio runtime. The task ends when the
+    /// sender is closed.
+    pub fn watch_rtcp(&self, sender: Arc<RTCRtpSender>) {
+        let keyframe_requested = self.keyframe_requested.clone();
+        tokio::spawn(async move {
+            while let Ok((packets, _)) = sender.read_rtcp().await {
+                for packet in packets {
+                    let any = packet.as_any();
+                    if any.is::<PictureLossIndication>() || any.is::<FullIntraRequest>() {
+                        keyframe_requested.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Wait until it's time to capture the next frame, according to the frame
+    /// rate of the track. Frames that are late are skipped, rather than sent in
+    /// a burst.
+    pub async fn wait_for_next_frame(&mut self) {
+        self.interval.tick().await;
+    }
+
+    /// Encode a frame of the given size and write it to the track
+    ///
+    /// Width and height must be even. The duration of the sample is the time
+    /// since the previous frame was written.
+    pub async fn write_frame(
+        &mut self,
+        frame: &[Bgr8],
+        width: u32,
+        height: u32,
+    ) -> Result<(), TrackError> {
+        let (w, h) = (width as usize, height as usize);
+        if frame.len() != w * h || w % 2 != 0 || h % 2 != 0 {
+            return Err(TrackError::Encode(openh264::Error::msg(
+                "Frame size must match its geometry, and width and height must be even",
+            )));
+        }
+        let source = BgraSliceU8::new(pixel_bytes(frame), (w, h));
+        match &mut self.yuv {
+            Some(yuv) if yuv.dimensions() == (w, h) => yuv.read_rgb(source),
+            yuv => *yuv = Some(YUVBuffer::from_rgb_source(source)),
+        }
+        if self.keyframe_requested.swap(false, Ordering::SeqCst) {
+            self.encoder.force_intra_frame();
+        }
+        let data = self
+            .encoder
+            .encode(self.yuv.as_ref().unwrap())
+            .map_err(TrackError::Encode)?
+            .to_vec();
+
+        let now = Instant::now();
+        let duration = self
+            .last_frame
+            .map_or(self.frame_duration, |last| now - last);
+        self.last_frame = Some(now);
+        let sample = Sample {
+            data: data.into(),
+            timestamp: SystemTime::now(),
+            duration,
+            ..Default::default()
+        };
+        self.track
+            .write_sample(&sample)
+            .await
+            .map_err(TrackError::Write)
+    }
+}
@@ -0,0 +1,164 @@
+//! RTSP server streaming the encoded capture, so media players and NVRs on the
+//! network can subscribe to the desktop feed, e.g. with
+//! `vlc rtsp://<host>:8554/screen`.
+//!
+//! Built on GStreamer's RTSP server. Frames are encoded with the GStreamer
+//! elements given to `RtspServer::with_encoder`, by default H.264 with `x264enc`.
+//!
+//! ```no_run
+//! # use captrs::{Capturer, rtsp::RtspServer};
+//! gstreamer::init().unwrap();
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let server = RtspServer::new(8554, "/screen", capturer.geometry(), 30).unwrap();
+//! loop {
+//!     server.push_frame(&mut capturer).unwrap();
+//! }
+//! ```
+
+use crate::gst::{video_caps, PushError};
+use crate::Capturer;
+use gstreamer as gst;
+use gstreamer::glib;
+use gstreamer::prelude::*;
+use gstreamer_app::AppSrc;
+use gstreamer_rtsp_server::prelude::*;
+use gstreamer_rtsp_server::{RTSPMediaFactory, RTSPServer};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Default encoder and payloader: low-latency H.264 over RTP
+pub const DEFAULT_ENCODER: &str =
+    "x264enc tune=zerolatency speed-preset=ultrafast ! rtph264pay name=pay0 pt=96";
+
+/// An RTSP server serving one stream of captured frames
+///
+/// All clients share the same encoded stream. Frames are pushed from the
+/// caller's thread with `push_frame`, while the server runs in a thread of
+/// its own.
+pub struct RtspServer {
+    appsrcs: Arc<Mutex<Vec<AppSrc>>>,
+    main_loop: glib::MainLoop,
+    thread: Option<JoinHandle<()>>,
+    fps: u32,
+}
+
+impl RtspServer {
+    /// Start an RTSP server on `port`, serving frames of size `geometry` at
+    /// `fps` frames per second at `mount`, e.g. `"/screen"`
+    pub fn new(
+        port: u16,
+        mount: &str,
+        geometry: (u32, u32),
+        fps: u32,
+    ) -> Result<RtspServer, glib::BoolError> {
+        RtspServer::with_encoder(port, mount, geometry, fps, DEFAULT_ENCODER)
+    }
+
+    /// Like `new`, but with custom encoder and RTP payloader elements, in
+    /// `gst-launch` syntax. The payloader must be named `pay0`.
+    pub fn with_encoder(
+        port: u16,
+        mount: &str,
+        geometry: (u32, u32),
+        fps: u32,
+        encoder: &str,
+    ) -> Result<RtspServer, glib::BoolError> {
+        assert!(fps > 0, "Frame rate must be positive");
+        let caps = video_caps(geometry, fps)?;
+        let server = RTSPServer::new();
+        server.set_service(&port.to_string());
+        let mounts = server
+            .mount_points()
+            .ok_or_else(|| glib::bool_error!("RTSP server has no mount points"))?;
+        let factory = RTSPMediaFactory::new();
+        factory.set_launch(&format!(
+            "( appsrc name=captrs ! videoconvert ! {} )",
+            encoder
+        ));
+        factory.set_shared(true);
+
+        let appsrcs = Arc::new(Mutex::new(Vec::new()));
+        let configured_appsrcs = appsrcs.clone();
+        factory.connect_media_configure(move |_, media| {
+            let appsrc = media
+                .element()
+                .downcast::<gst::Bin>()
+                .ok()
+                .and_then(|bin| bin.by_name("captrs"))
+                .and_then(|element| element.downcast::<AppSrc>().ok());
+            if let Some(appsrc) = appsrc {
+                appsrc.set_caps(Some(&caps));
+                appsrc.set_format(gst::Format::Time);
+                appsrc.set_is_live(true);
+                appsrc.set_do_timestamp(true);
+                configured_appsrcs.lock().unwrap().push(appsrc);
+            }
+        });
+        mounts.add_factory(mount, factory);
+
+        let context = glib::MainContext::new();
+        server.attach(Some(&context))?;
+        let main_loop = glib::MainLoop::new(Some(&context), false);
+        let thread_loop = main_loop.clone();
+        let thread = thread::spawn(move || {
+            // The server must be kept alive for as long as the loop runs
+            let _server = server;
+            thread_loop.run()
+        });
+        Ok(RtspServer {
+            appsrcs,
+            main_loop,
+            thread: Some(thread),
+            fps,
+        })
+    }
+
+    /// The frame rate the stream is advertised with
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    /// Returns whether any client is currently receiving the stream
+    pub fn has_clients(&self) -> bool {
+        !self.appsrcs.lock().unwrap().is_empty()
+    }
+
+    /// Capture a frame and send it to all connected clients
+    ///
+    /// If no client is connected the frame is still captured, but discarded.
+    pub fn push_frame(&self, capturer: &mut Capturer) -> Result<(), PushError> {
+        let data = capturer
+            .capture_frame_components()
+            .map_err(PushError::Capture)?;
+        self.push_frame_components(data)
+    }
+
+    /// Send an already captured frame, as returned by
+    /// `Capturer::capture_frame_components`, to all connected clients
+    ///
+    /// The frame must be of the size the server was created with.
+    pub fn push_frame_components(&self, data: Vec<u8>) -> Result<(), PushError> {
+        let mut appsrcs = self.appsrcs.lock().unwrap();
+        if appsrcs.is_empty() {
+            return Ok(());
+        }
+        let buffer = gst::Buffer::from_mut_slice(data);
+        // Media that has been torn down refuses buffers with `Flushing` or `Eos`,
+        // and is forgotten
+        appsrcs.retain(|appsrc| match appsrc.push_buffer(buffer.clone()) {
+            Ok(_) => true,
+            Err(gst::FlowError::Flushing) | Err(gst::FlowError::Eos) => false,
+            Err(_) => true,
+        });
+        Ok(())
+    }
+}
+
+impl Drop for RtspServer {
+    fn drop(&mut self) {
+        self.main_loop.quit();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Saving captured frames as PNG, JPEG or QOI images
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::save::ImageFormat;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! capturer.capture_to_file("shot.png", ImageFormat::Png).unwrap();
+//! ```
+
+use crate::convert::to_rgb;
+use crate::replay::ReplayBuffer;
+use crate::{pixel_bytes, Bgr8, CaptureError, Capturer, Frame};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Format of a saved image
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Png,
+    /// JPEG with a quality from 1 to 100
+    Jpeg {
+        quality: u8,
+    },
+    Qoi,
+}
+
+impl ImageFormat {
+    /// The format of the file extension of a path, e.g. PNG for `shot.png`.
+    /// JPEG has a quality of 90.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<ImageFormat> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg { quality: 90 }),
+            "qoi" => Some(ImageFormat::Qoi),
+            _ => None,
+        }
+    }
+
+    /// The usual file extension of the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg { .. } => "jpg",
+            ImageFormat::Qoi => "qoi",
+        }
+    }
+}
+
+/// Error of capturing and saving an image
+#[derive(Debug)]
+pub enum SaveError {
+    Capture(CaptureError),
+    /// The file could not be written
+    Io(io::Error),
+    /// The frame could not be encoded, e.g. as it's too large for the format
+    Encode(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Capture(e) => e.fmt(f),
+            SaveError::Io(e) => e.fmt(f),
+            SaveError::Encode(msg) => write!(f, "Failed to encode image: {}", msg),
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SaveError::Capture(e) => Some(e),
+            SaveError::Io(e) => Some(e),
+            SaveError::Encode(_) => None,
+        }
+    }
+}
+
+impl From<CaptureError> for SaveError {
+    fn from(e: CaptureError) -> SaveError {
+        SaveError::Capture(e)
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> SaveError {
+        SaveError::Io(e)
+    }
+}
+
+fn rgb(pixels: &[Bgr8]) -> Vec<u8> {
+    let mut data = vec![0; 3 * pixels.len()];
+    to_rgb(pixels, &mut data);
+    data
+}
+
+/// Encode a frame of the given size in the given format
+pub fn encode(
+    pixels: &[Bgr8],
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+) -> Result<Vec<u8>, SaveError> {
+    assert_eq!(pixels.len(), width as usize * height as usize);
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(&mut out, width, height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .write_header()
+                .and_then(|mut writer| writer.write_image_data(&rgb(pixels)))
+                .map_err(|e| SaveError::Encode(e.to_string()))?;
+        }
+        ImageFormat::Jpeg { quality } => {
+            if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                return Err(SaveError::Encode("Frame too large for JPEG".to_string()));
+            }
+            jpeg_encoder::Encoder::new(&mut out, quality.clamp(1, 100))
+                .encode(
+                    pixel_bytes(pixels),
+                    width as u16,
+                    height as u16,
+                    jpeg_encoder::ColorType::Bgra,
+                )
+                .map_err(|e| SaveError::Encode(e.to_string()))?;
+        }
+        ImageFormat::Qoi => {
+            out = qoi::encode_to_vec(rgb(pixels), width, height)
+                .map_err(|e| SaveError::Encode(e.to_string()))?;
+        }
+    }
+    Ok(out)
+}
+
+/// Save a frame of the given size to a file in the given format
+pub fn save(
+    path: impl AsRef<Path>,
+    pixels: &[Bgr8],
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+) -> Result<(), SaveError> {
+    let data = encode(pixels, width, height, format)?;
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&data)?;
+    file.flush()?;
+    Ok(())
+}
+
+impl Frame {
+    /// Save the frame to a file in the given format
+    pub fn save(&self, path: impl AsRef<Path>, format: ImageFormat) -> Result<(), SaveError> {
+        save(path, self.pixels(), self.width(), self.height(), format)
+    }
+}
+
+impl ReplayBuffer {
+    /// Save the buffered frames to the directory `dir` as an image sequence,
+    /// oldest first, named by their index and time in milliseconds, e.g.
+    /// `00003_0100.png`. Returns the paths of the images.
+    pub fn save_images(
+        &self,
+        dir: impl AsRef<Path>,
+        format: ImageFormat,
+    ) -> Result<Vec<PathBuf>, SaveError> {
+        std::fs::create_dir_all(&dir)?;
+        self.frames()
+            .enumerate()
+            .map(|(i, (frame, time))| {
+                let name = format!("{:05}_{:04}.{}", i, time.as_millis(), format.extension());
+                let path = dir.as_ref().join(name);
+                frame.save(&path, format)?;
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+impl Capturer {
+    /// Capture screen and save the frame to a file in the given format
+    pub fn capture_to_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: ImageFormat,
+    ) -> Result<(), SaveError> {
+        self.capture_store_frame()?;
+        let (pixels, width, height) = self.stored_frame_with_size().unwrap();
+        save(path, pixels, width, height, format)
+    }
+}
+
+#[cfg(test)]
+mod save_tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let pixels = vec![Bgr8::new(10, 20, 30), Bgr8::new(40, 50, 60)];
+        let png = encode(&pixels, 2, 1, ImageFormat::Png).unwrap();
+        assert_eq!(png[1..4], *b"PNG");
+        let jpeg = encode(&pixels, 2, 1, ImageFormat::Jpeg { quality: 80 }).unwrap();
+        assert_eq!(jpeg[..2], [0xff, 0xd8]);
+        let qoi = encode(&pixels, 2, 1, ImageFormat::Qoi).unwrap();
+        let (header, data) = qoi::decode_to_vec(qoi).unwrap();
+        assert_eq!((header.width, header.height), (2, 1));
+        assert_eq!(data, [10, 20, 30, 40, 50, 60]);
+        assert_eq!(
+            ImageFormat::from_path("shot.JPG"),
+            Some(ImageFormat::Jpeg { quality: 90 })
+        );
+        assert_eq!(
+            ImageFormat::from_path(format!("shot.{}", ImageFormat::Qoi.extension())),
+            Some(ImageFormat::Qoi)
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Scaling of captured frames to an output size, e.g. a 64x36 image for an
+//! ambilight, in the same pass as the frame is copied out of the backend
+
+use crate::parallel::fill_rows;
+use crate::{Bgr8, Capturer, Frame, InitError, Rect};
+
+/// Filter to resample frames with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ScaleFilter {
+    /// The nearest pixel. Fastest, but aliases when scaling down.
+    Nearest,
+    /// Linear interpolation, widened when scaling down so every covered
+    /// pixel contributes
+    #[default]
+    Bilinear,
+    /// Lanczos with 3 lobes. Sharpest, and slowest.
+    Lanczos,
+}
+
+impl ScaleFilter {
+    /// Support radius in source pixels when not scaling down, and kernel
+    fn kernel(self) -> (f32, fn(f32) -> f32) {
+        match self {
+            ScaleFilter::Nearest => (0.5, |x| if x.abs() <= 0.5 { 1.0 } else { 0.0 }),
+            ScaleFilter::Bilinear => (1.0, |x| (1.0 - x.abs()).max(0.0)),
+            ScaleFilter::Lanczos => (3.0, lanczos3),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if x.abs() < 3.0 {
+        sinc(x) * sinc(x / 3.0)
+    } else {
+        0.0
+    }
+}
+
+/// First source index and normalized weights of the source pixels of each
+/// of `dst_len` destination pixels
+fn weights(src_len: u32, dst_len: u32, filter: ScaleFilter) -> Vec<(usize, Vec<f32>)> {
+    let scale = src_len as f32 / dst_len as f32;
+    let (support, kernel) = filter.kernel();
+    // Widen the kernel when scaling down, so every source pixel contributes
+    let widen = scale.max(1.0);
+    let support = support * widen;
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).clamp(start + 1, src_len as usize);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| kernel((j as f32 + 0.5 - center) / widen))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum != 0.0 {
+                weights.iter_mut().for_each(|w| *w /= sum);
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+/// Scale `rect` of a frame of `stride` pixels per row to `width` x `height`,
+/// replacing the contents of `dst`
+pub(crate) fn scale(
+    src: &[Bgr8],
+    stride: usize,
+    rect: Rect,
+    (width, height): (u32, u32),
+    filter: ScaleFilter,
+    dst: &mut Vec<Bgr8>,
+) {
+    dst.clear();
+    dst.resize(width as usize * height as usize, Bgr8::default());
+    let row = |y: usize| &src[y * stride + rect.x as usize..][..rect.width as usize];
+    if filter == ScaleFilter::Nearest {
+        let columns: Vec<usize> = (0..width as usize)
+            .map(|x| (2 * x + 1) * rect.width as usize / (2 * width as usize))
+            .collect();
+        fill_rows(dst, width as usize, |y, dst_row| {
+            let src_row =
+                row(rect.y as usize + (2 * y + 1) * rect.height as usize / (2 * height as usize));
+            for (d, &x) in dst_row.iter_mut().zip(&columns) {
+                *d = src_row[x];
+            }
+        });
+        return;
+    }
+    // Horizontally into rows of the output width, then vertically
+    let columns = weights(rect.width, width, filter);
+    let mut rows = vec![[0.0f32; 3]; rect.height as usize * width as usize];
+    fill_rows(&mut rows, width as usize, |y, dst_row| {
+        let src_row = row(rect.y as usize + y);
+        for (d, (start, weights)) in dst_row.iter_mut().zip(&columns) {
+            for (p, &w) in src_row[*start..].iter().zip(weights) {
+                d[0] += p.b as f32 * w;
+                d[1] += p.g as f32 * w;
+                d[2] += p.r as f32 * w;
+            }
+        }
+    });
+    let to_u8 = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    let lines = weights(rect.height, height, filter);
+    fill_rows(dst, width as usize, |y, dst_row| {
+        let (start, weights) = &lines[y];
+        for (x, d) in dst_row.iter_mut().enumerate() {
+            let mut sum = [0.0f32; 3];
+            for (y, &w) in (*start..).zip(weights) {
+                let p = rows[y * width as usize + x];
+                sum[0] += p[0] * w;
+                sum[1] += p[1] * w;
+                sum[2] += p[2] * w;
+            }
+            *d = Bgr8::new(to_u8(sum[2]), to_u8(sum[1]), to_u8(sum[0]));
+        }
+    });
+}
+
+/// `rect` of a frame of `from` size in a frame of `to` size, rounded outwards
+pub(crate) fn scale_rect(rect: Rect, from: (u32, u32), to: (u32, u32)) -> Rect {
+    let floor = |v: u32, from: u32, to: u32| (v as u64 * to as u64 / from as u64) as u32;
+    let ceil = |v: u32, from: u32, to: u32| (v as u64 * to as u64).div_ceil(from as u64) as u32;
+    let (x, y) = (floor(rect.x, from.0, to.0), floor(rect.y, from.1, to.1));
+    Rect {
+        x,
+        y,
+        width: ceil(rect.x + rect.width, from.0, to.0) - x,
+        height: ceil(rect.y + rect.height, from.1, to.1) - y,
+    }
+}
+
+impl Frame {
+    /// A copy of the frame scaled to `width` x `height`
+    pub fn scaled(&self, width: u32, height: u32, filter: ScaleFilter) -> Frame {
+        let (w, h) = self.geometry();
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: w,
+            height: h,
+        };
+        let mut pixels = Vec::new();
+        if w > 0 && h > 0 {
+            scale(
+                self.pixels(),
+                w as usize,
+                rect,
+                (width, height),
+                filter,
+                &mut pixels,
+            );
+        } else {
+            pixels.resize(width as usize * height as usize, Bgr8::default());
+        }
+        Frame::new(pixels, width, height)
+    }
+}
+
+impl Capturer {
+    /// Scale captured frames to `width` x `height`
+    ///
+    /// Frames are scaled in the same pass as they are copied out of the
+    /// backend, unless the cursor or redactions must be drawn first. Either
+    /// way, `Self::geometry` and the frames are of the output size, while
+    /// capture regions and redactions remain in pixels of the capture
+    /// source.
+    pub fn set_output_size(
+        &mut self,
+        width: u32,
+        height: u32,
+        filter: ScaleFilter,
+    ) -> Result<(), InitError> {
+        if width == 0 || height == 0 {
+            return Err(InitError::InvalidArgument(format!(
+                "Output size {}x{} is empty",
+                width, height
+            )));
+        }
+        self.output_size = Some((width, height, filter));
+        self.processed = None;
+        Ok(())
+    }
+
+    /// Return frames of the size of the capture source or region again
+    pub fn clear_output_size(&mut self) {
+        self.output_size = None;
+        self.processed = None;
+    }
+
+    /// The size frames are scaled to, if any
+    pub fn output_size(&self) -> Option<(u32, u32)> {
+        self.output_size.map(|(width, height, _)| (width, height))
+    }
+}
+
+#[cfg(test)]
+mod scale_tests {
+    use super::*;
+
+    #[test]
+    fn test_scale() {
+        // Left half black, right half white
+        let pixels = (0..8 * 4)
+            .map(|i| {
+                let v = if i % 8 < 4 { 0 } else { 255 };
+                Bgr8::new(v, v, v)
+            })
+            .collect();
+        let frame = Frame::new(pixels, 8, 4);
+        let (black, white) = (Bgr8::new(0, 0, 0), Bgr8::new(255, 255, 255));
+        assert_eq!(
+            frame.scaled(2, 1, ScaleFilter::Nearest).pixels(),
+            [black, white]
+        );
+        let bilinear = frame.scaled(2, 1, ScaleFilter::Bilinear);
+        assert!(bilinear.pixels()[0].r < 64 && bilinear.pixels()[1].r > 191);
+        let lanczos = frame.scaled(4, 2, ScaleFilter::Lanczos);
+        assert_eq!(lanczos.geometry(), (4, 2));
+        assert!(lanczos.pixels()[0].r < 20 && lanczos.pixels()[3].r > 235);
+        assert_eq!(frame.scaled(8, 4, ScaleFilter::Bilinear), frame);
+        assert_eq!(
+            scale_rect(
+                Rect {
+                    x: 3,
+                    y: 0,
+                    width: 2,
+                    height: 1
+                },
+                (8, 4),
+                (4, 2)
+            ),
+            Rect {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 1
+            }
+        );
+    }
+
+    /// Backend of the 8x4 frame of `test_scale`
+    struct Halves(Vec<Bgr8>);
+
+    impl crate::Backend for Halves {
+        fn name(&self) -> &'static str {
+            "halves"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (8, 4)
+        }
+
+        fn capture_frame(
+            &mut self,
+            _timeout: std::time::Duration,
+        ) -> Result<(), crate::CaptureError> {
+            self.0 = (0..8 * 4)
+                .map(|i| {
+                    let v = if i % 8 < 4 { 0 } else { 255 };
+                    Bgr8::new(v, v, v)
+                })
+                .collect();
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 8, 4))
+        }
+    }
+
+    #[test]
+    fn test_output_size() {
+        let mut capturer = Capturer::with_backend(Box::new(Halves(Vec::new())));
+        capturer
+            .set_output_size(2, 1, ScaleFilter::Bilinear)
+            .unwrap();
+        assert!(capturer
+            .set_output_size(0, 1, ScaleFilter::Nearest)
+            .is_err());
+        assert_eq!(capturer.geometry(), (2, 1));
+        let frame = capturer.capture_frame_meta().unwrap();
+        assert_eq!(frame.geometry(), (2, 1));
+        assert!(frame.pixels()[1].r > 191);
+        assert_eq!(capturer.coordinate_map().frame_to_desktop(1, 0), (6, 2));
+
+        // Scaled after cropping
+        capturer
+            .set_capture_region(Rect {
+                x: 2,
+                y: 0,
+                width: 4,
+                height: 4,
+            })
+            .unwrap();
+        let frame = capturer.capture_frame_meta().unwrap();
+        assert!(frame.pixels()[0].r < 64);
+        capturer.clear_output_size();
+        assert_eq!(capturer.capture_frame().unwrap().len(), 16);
+    }
+}
@@ -0,0 +1,253 @@
+//! Scheduling of captures on an external clock, e.g. the clock of an audio
+//! device, so that presentation timestamps of frames follow the master clock
+//! of a recording instead of drifting away from it
+
+use crate::{CaptureError, Capturer, Frame};
+use std::time::{Duration, Instant};
+
+/// Longest time to sleep between reads of the clock, in case it does not run
+/// at the same rate as the system clock
+const MAX_SLEEP: Duration = Duration::from_millis(5);
+
+/// A master clock, returning the time elapsed since some fixed point
+pub trait Clock {
+    fn now(&mut self) -> Duration;
+}
+
+impl<F: FnMut() -> Duration> Clock for F {
+    fn now(&mut self) -> Duration {
+        self()
+    }
+}
+
+/// The monotonic system clock, counting from when it was constructed
+#[derive(Clone, Copy, Debug)]
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> MonotonicClock {
+        MonotonicClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> MonotonicClock {
+        MonotonicClock::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&mut self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A frame captured on schedule
+#[derive(Clone, Debug)]
+pub struct ScheduledFrame {
+    pub frame: Frame,
+    /// Presentation timestamp on the master clock
+    pub pts: Duration,
+    /// Number of frame slots skipped since the previous frame because
+    /// capture fell behind the clock
+    pub skipped: u64,
+}
+
+/// Scheduler of captures at a fixed rate on a master clock
+///
+/// Frame `n` is due when the clock reaches `n / fps`, and gets that as its
+/// presentation timestamp. Slots that have already passed when a capture is
+/// due are skipped, so the timestamps never drift from the clock.
+pub struct ClockScheduler<C> {
+    clock: C,
+    interval: Duration,
+    next: u64,
+}
+
+impl<C: Clock> ClockScheduler<C> {
+    /// Construct a scheduler of `fps` frames per second, with the first frame
+    /// due at time 0 on the clock
+    pub fn new(clock: C, fps: f64) -> ClockScheduler<C> {
+        assert!(fps > 0.0);
+        ClockScheduler {
+            clock,
+            interval: Duration::from_secs_f64(1.0 / fps),
+            next: 0,
+        }
+    }
+
+    /// Block until the next frame is due, and return its index and
+    /// presentation timestamp, and the number of skipped slots
+    pub fn wait_next(&mut self) -> (u64, Duration, u64) {
+        let mut now = self.clock.now();
+        let mut due = self.interval * self.next as u32;
+        while now < due {
+            std::thread::sleep((due - now).min(MAX_SLEEP));
+            now = self.clock.now();
+        }
+        // Skip the slots that passed while we were busy
+        let current = (now.as_nanos() / self.interval.as_nanos()) as u64;
+        let skipped = current.saturating_sub(self.next);
+        let index = self.next + skipped;
+        due = self.interval * index as u32;
+        self.next = index + 1;
+        (index, due, skipped)
+    }
+
+    /// Wait until the next frame is due and capture it
+    pub fn capture(&mut self, capturer: &mut Capturer) -> Result<ScheduledFrame, CaptureError> {
+        let (_, pts, skipped) = self.wait_next();
+        let pixels = capturer.capture_frame()?;
+        let (width, height) = capturer.geometry();
+        let mut frame = Frame::new(pixels, width, height).with_sequence(capturer.frame_sequence());
+        if let Some(timestamp) = capturer.frame_timestamp() {
+            frame = frame.with_timestamp(timestamp);
+        }
+        Ok(ScheduledFrame {
+            frame,
+            pts,
+            skipped,
+        })
+    }
+}
+
+/// Iterator of frames captured at an even cadence. See
+/// `Capturer::into_paced_stream`.
+pub struct PacedStream {
+    capturer: Capturer,
+    scheduler: ClockScheduler<MonotonicClock>,
+    /// Timeout of the capturer before pacing
+    timeout: Duration,
+}
+
+impl PacedStream {
+    /// The capturer, e.g. to poll its events
+    pub fn capturer(&mut self) -> &mut Capturer {
+        &mut self.capturer
+    }
+
+    /// Stop pacing, and return the capturer with its timeout restored
+    pub fn into_inner(mut self) -> Capturer {
+        self.capturer.timeout = self.timeout;
+        self.capturer
+    }
+}
+
+impl Iterator for PacedStream {
+    type Item = Result<ScheduledFrame, CaptureError>;
+
+    /// Wait until the next frame is due and capture it, repeating the last
+    /// frame if the screen didn't change
+    fn next(&mut self) -> Option<Result<ScheduledFrame, CaptureError>> {
+        let (_, pts, skipped) = self.scheduler.wait_next();
+        let frame = match self.capturer.capture_store_frame() {
+            Ok(()) => self.capturer.stored_frame_meta(),
+            Err(CaptureError::Timeout | CaptureError::NoNewFrame)
+                if self.capturer.get_stored_frame().is_some() =>
+            {
+                self.capturer.stored_frame_meta()
+            }
+            Err(e) => return Some(Err(e)),
+        };
+        Some(Ok(ScheduledFrame {
+            frame,
+            pts,
+            skipped,
+        }))
+    }
+}
+
+impl Capturer {
+    /// Capture frames at an even cadence of `fps` frames per second
+    ///
+    /// Frames are due at fixed times on the monotonic clock, so the time
+    /// spent capturing doesn't delay the following frames. When capture
+    /// can't keep up, slots are skipped and counted in
+    /// `ScheduledFrame::skipped`. The timeout of the capturer is limited to
+    /// the frame interval, and the last frame is repeated when capture
+    /// times out because the screen is still.
+    pub fn into_paced_stream(self, fps: u32) -> PacedStream {
+        assert!(fps > 0);
+        let timeout = self.timeout;
+        let mut capturer = self;
+        capturer.timeout = timeout.min(Duration::from_secs(1) / fps);
+        PacedStream {
+            capturer,
+            scheduler: ClockScheduler::new(MonotonicClock::new(), fps as f64),
+            timeout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod schedule_tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_wait_next() {
+        // A clock that advances 10 ms every time it's read
+        let t = Cell::new(Duration::ZERO);
+        let clock = || {
+            let now = t.get();
+            t.set(now + Duration::from_millis(10));
+            now
+        };
+        let mut scheduler = ClockScheduler::new(clock, 50.0);
+        let ms = Duration::from_millis;
+        assert_eq!(scheduler.wait_next(), (0, ms(0), 0));
+        assert_eq!(scheduler.wait_next(), (1, ms(20), 0));
+        // Fall behind by 3 slots
+        t.set(t.get() + ms(70));
+        assert_eq!(scheduler.wait_next(), (5, ms(100), 3));
+    }
+
+    /// Backend of a 1x1 frame that times out after the first capture
+    struct Still {
+        frame: Option<Vec<crate::Bgr8>>,
+    }
+
+    impl crate::Backend for Still {
+        fn name(&self) -> &'static str {
+            "still"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            if self.frame.is_some() {
+                return Err(CaptureError::Timeout);
+            }
+            self.frame = Some(vec![crate::Bgr8::new(1, 2, 3)]);
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[crate::Bgr8], u32, u32)> {
+            self.frame.as_deref().map(|frame| (frame, 1, 1))
+        }
+    }
+
+    #[test]
+    fn test_paced_stream() {
+        let capturer = Capturer::with_backend(Box::new(Still { frame: None }));
+        let start = Instant::now();
+        let frames: Vec<ScheduledFrame> = capturer
+            .into_paced_stream(100)
+            .take(3)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        for pair in frames.windows(2) {
+            assert!(pair[1].pts > pair[0].pts);
+            assert_eq!(pair[1].pts.as_nanos() % 10_000_000, 0);
+        }
+        // The still frame is repeated
+        assert!(frames.iter().all(|f| f.frame.sequence() == 1));
+    }
+}
@@ -0,0 +1,121 @@
+//! Compatibility layer mirroring the API of the
+//! [scrap](https://crates.io/crates/scrap) crate, so code written against
+//! scrap can switch to captrs by changing `use scrap::...` to
+//! `use captrs::scrap::...`.
+//!
+//! ```no_run
+//! use captrs::scrap::{Capturer, Display};
+//! use std::io::ErrorKind::WouldBlock;
+//!
+//! let display = Display::primary().unwrap();
+//! let mut capturer = Capturer::new(display).unwrap();
+//! let (w, h) = (capturer.width(), capturer.height());
+//! loop {
+//!     match capturer.frame() {
+//!         Ok(frame) => {
+//!             let stride = frame.len() / h;
+//!             println!("{}x{}, {} bytes per row", w, h, stride);
+//!             break;
+//!         }
+//!         Err(ref e) if e.kind() == WouldBlock => continue,
+//!         Err(e) => panic!("{}", e),
+//!     }
+//! }
+//! ```
+
+use crate::{pixel_bytes, CaptureError};
+use std::io;
+use std::ops::Deref;
+
+/// A display that can be captured
+pub struct Display {
+    index: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Display {
+    /// The primary display
+    pub fn primary() -> io::Result<Display> {
+        Display::open(0)
+    }
+
+    /// All displays
+    pub fn all() -> io::Result<Vec<Display>> {
+        Ok(crate::displays()
+            .into_iter()
+            .map(|d| Display {
+                index: d.index,
+                width: d.size.0 as usize,
+                height: d.size.1 as usize,
+            })
+            .collect())
+    }
+
+    fn open(index: usize) -> io::Result<Display> {
+        let capturer = crate::Capturer::new(index).map_err(io::Error::other)?;
+        let (width, height) = capturer.geometry();
+        Ok(Display {
+            index,
+            width: width as usize,
+            height: height as usize,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+/// A screen capturer of a display
+pub struct Capturer {
+    capturer: crate::Capturer,
+}
+
+impl Capturer {
+    /// Construct a capturer for a display
+    pub fn new(display: Display) -> io::Result<Capturer> {
+        crate::Capturer::new(display.index)
+            .map(|capturer| Capturer { capturer })
+            .map_err(io::Error::other)
+    }
+
+    pub fn width(&self) -> usize {
+        self.capturer.geometry().0 as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.capturer.geometry().1 as usize
+    }
+
+    /// Capture a frame of 4 byte BGRA pixels
+    ///
+    /// Fails with `io::ErrorKind::WouldBlock` if no new frame was available
+    /// in time.
+    pub fn frame(&mut self) -> io::Result<Frame<'_>> {
+        match self.capturer.capture_store_frame() {
+            Ok(()) => Ok(Frame(pixel_bytes(
+                self.capturer.get_stored_frame().unwrap(),
+            ))),
+            Err(CaptureError::Timeout | CaptureError::NoNewFrame) => {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            Err(e) => Err(io::Error::other(format!("{:?}", e))),
+        }
+    }
+}
+
+/// The pixel data of a captured frame, borrowed from the capturer
+pub struct Frame<'a>(&'a [u8]);
+
+impl<'a> Deref for Frame<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0
+    }
+}
@@ -0,0 +1,102 @@
+//! One-shot screenshots, for scripts that don't keep a `Capturer` around
+//!
+//! ```no_run
+//! let frame = captrs::screenshot(0).unwrap();
+//! println!("{}x{}", frame.width(), frame.height());
+//! ```
+
+use crate::{displays, CaptureError, Capturer, DisplayInfo, Frame};
+
+/// Number of captures timing out before giving up. Some backends only
+/// deliver a frame once the screen changes, e.g. `Windows.Graphics.Capture`,
+/// or after warming up, e.g. the ScreenCast portal.
+const ATTEMPTS: u32 = 10;
+
+/// Capture a frame of the display with a capture source index, as
+/// `Capturer::new`
+///
+/// Constructs a capturer for the one frame and drops it afterwards, so
+/// prefer a `Capturer` when capturing repeatedly. Captures timing out are
+/// retried a few times before failing with `CaptureError::Timeout`.
+pub fn screenshot(display: usize) -> Result<Frame, CaptureError> {
+    let mut capturer = Capturer::new(display).map_err(CaptureError::from_init)?;
+    capture_retrying(&mut capturer)
+}
+
+/// Capture a frame of each display, as listed by `displays`, one after the
+/// other
+///
+/// See `screenshot`. To capture the displays at the same time, see
+/// `MultiCapturer`, or `capture_all_displays_stitched` for one frame of the
+/// whole desktop.
+pub fn screenshot_all() -> Result<Vec<(DisplayInfo, Frame)>, CaptureError> {
+    displays()
+        .into_iter()
+        .map(|display| screenshot(display.index).map(|frame| (display, frame)))
+        .collect()
+}
+
+/// Capture a frame, retrying captures that time out
+fn capture_retrying(capturer: &mut Capturer) -> Result<Frame, CaptureError> {
+    let mut attempts = 0;
+    loop {
+        match capturer.capture_frame_meta() {
+            Err(CaptureError::Timeout) if attempts + 1 < ATTEMPTS => attempts += 1,
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod screenshot_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::Bgr8;
+    use std::time::Duration;
+
+    /// Backend of 1x1 frames, timing out a number of times first
+    struct Slow {
+        timeouts: u32,
+        frame: Vec<Bgr8>,
+    }
+
+    impl Backend for Slow {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            if self.timeouts > 0 {
+                self.timeouts -= 1;
+                return Err(CaptureError::Timeout);
+            }
+            self.frame = vec![Bgr8::new(1, 2, 3)];
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.frame, 1, 1))
+        }
+    }
+
+    fn capturer(timeouts: u32) -> Capturer {
+        Capturer::with_backend(Box::new(Slow {
+            timeouts,
+            frame: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn test_capture_retrying() {
+        let frame = capture_retrying(&mut capturer(ATTEMPTS - 1)).unwrap();
+        assert_eq!(frame.pixels(), [Bgr8::new(1, 2, 3)]);
+        assert!(matches!(
+            capture_retrying(&mut capturer(ATTEMPTS)),
+            Err(CaptureError::Timeout)
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+//! SDL2 integration. Streams captured frames into SDL textures, e.g. for
+//! preview windows or mirroring.
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # fn main() -> Result<(), String> {
+//! let mut capturer = Capturer::new(0)?;
+//! let (w, h) = capturer.geometry();
+//! let sdl = sdl2::init()?;
+//! let window = sdl.video()?.window("mirror", w, h).build().map_err(|e| e.to_string())?;
+//! let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+//! let creator = canvas.texture_creator();
+//! let mut texture = captrs::sdl2::create_texture(&creator, w, h).map_err(|e| e.to_string())?;
+//! loop {
+//!     if capturer.capture_store_frame().is_ok() {
+//!         let frame = capturer.get_stored_frame().unwrap();
+//!         captrs::sdl2::update_texture(&mut texture, frame, w, h)?;
+//!     }
+//!     canvas.copy(&texture, None, None)?;
+//!     canvas.present();
+//! }
+//! # }
+//! ```
+
+use crate::{pixel_bytes, Bgr8};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Texture, TextureCreator, TextureValueError};
+
+/// The SDL pixel format with the same memory layout as `Bgr8`
+#[cfg(target_endian = "little")]
+pub const PIXEL_FORMAT: PixelFormatEnum = PixelFormatEnum::RGB888;
+/// The SDL pixel format with the same memory layout as `Bgr8`
+#[cfg(target_endian = "big")]
+pub const PIXEL_FORMAT: PixelFormatEnum = PixelFormatEnum::BGRX8888;
+
+/// Create a streaming texture that frames of the given size can be written to
+pub fn create_texture<T>(
+    creator: &TextureCreator<T>,
+    width: u32,
+    height: u32,
+) -> Result<Texture<'_>, TextureValueError> {
+    creator.create_texture_streaming(PIXEL_FORMAT, width, height)
+}
+
+/// Write a frame of the given size to a streaming texture of the same size,
+/// as created by `create_texture`
+pub fn update_texture(
+    texture: &mut Texture,
+    frame: &[Bgr8],
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    let query = texture.query();
+    if (query.width, query.height) != (width, height) {
+        return Err("Frame size does not match the size of the texture".to_string());
+    }
+    if frame.len() != width as usize * height as usize {
+        return Err("Frame length does not match the given size".to_string());
+    }
+    let src = pixel_bytes(frame);
+    let row_len = width as usize * 4;
+    texture.with_lock(None, |dst, pitch| {
+        // The rows of the locked texture may be padded
+        for (dst_row, src_row) in dst.chunks_mut(pitch).zip(src.chunks_exact(row_len)) {
+            dst_row[..row_len].copy_from_slice(src_row);
+        }
+    })
+}
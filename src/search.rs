@@ -0,0 +1,146 @@
+//! Search for colors and images in frames, e.g. for UI automation
+
+use crate::{Bgr8, Frame, Rect};
+
+/// Whether the red, green, and blue components of two colors each differ by
+/// at most `tolerance`
+fn matches(p: Bgr8, color: Bgr8, tolerance: u8) -> bool {
+    p.r.abs_diff(color.r) <= tolerance
+        && p.g.abs_diff(color.g) <= tolerance
+        && p.b.abs_diff(color.b) <= tolerance
+}
+
+/// Sum of absolute differences of the red, green, and blue components of two
+/// rows of pixels
+fn row_sad(a: &[Bgr8], b: &[Bgr8]) -> u64 {
+    a.iter()
+        .zip(b)
+        .map(|(p, q)| {
+            p.r.abs_diff(q.r) as u64 + p.g.abs_diff(q.g) as u64 + p.b.abs_diff(q.b) as u64
+        })
+        .sum()
+}
+
+impl Frame {
+    /// Coordinates of the pixels of the frame matching `color`, row by row
+    ///
+    /// A pixel matches if its red, green, and blue components each differ
+    /// from those of `color` by at most `tolerance`.
+    pub fn find_color(&self, color: Bgr8, tolerance: u8) -> Vec<(u32, u32)> {
+        let (width, height) = self.geometry();
+        self.find_color_in(
+            color,
+            tolerance,
+            Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+        )
+    }
+
+    /// Coordinates of the pixels matching `color` within a region of the
+    /// frame, row by row. See `find_color`.
+    pub fn find_color_in(&self, color: Bgr8, tolerance: u8, region: Rect) -> Vec<(u32, u32)> {
+        let (width, height) = self.geometry();
+        assert!(
+            region.x as u64 + region.width as u64 <= width as u64
+                && region.y as u64 + region.height as u64 <= height as u64,
+            "region is out of bounds"
+        );
+        let mut found = Vec::new();
+        for y in region.y..region.y + region.height {
+            let start = y as usize * width as usize + region.x as usize;
+            let row = &self.pixels()[start..start + region.width as usize];
+            found.extend(
+                row.iter()
+                    .enumerate()
+                    .filter(|&(_, &p)| matches(p, color, tolerance))
+                    .map(|(i, _)| (region.x + i as u32, y)),
+            );
+        }
+        found
+    }
+
+    /// Position of the top left corner of the best match of `needle` in the
+    /// frame, if any
+    ///
+    /// Matches are compared by the sum of absolute differences of their
+    /// components, skipping a position as soon as it can not beat the best
+    /// match so far. A position matches if the mean absolute difference of
+    /// the red, green, and blue components is at most `tolerance`.
+    pub fn find_image(&self, needle: &Frame, tolerance: u8) -> Option<(u32, u32)> {
+        let (width, height) = self.geometry();
+        let (nw, nh) = needle.geometry();
+        if nw == 0 || nh == 0 || nw > width || nh > height {
+            return None;
+        }
+        let (width, nw, nh) = (width as usize, nw as usize, nh as usize);
+        let pixels = self.pixels();
+        let needle = needle.pixels();
+        // A match must have a SAD no greater than this
+        let mut limit = tolerance as u64 * 3 * nw as u64 * nh as u64;
+        let mut best = None;
+        for y in 0..=height as usize - nh {
+            for x in 0..=width - nw {
+                let mut sad = 0;
+                for row in 0..nh {
+                    let start = (y + row) * width + x;
+                    sad += row_sad(
+                        &pixels[start..start + nw],
+                        &needle[row * nw..(row + 1) * nw],
+                    );
+                    if sad > limit {
+                        break;
+                    }
+                }
+                if sad <= limit {
+                    best = Some((x as u32, y as u32));
+                    if sad == 0 {
+                        return best;
+                    }
+                    // Only look for strictly better matches from now on
+                    limit = sad - 1;
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod search_tests {
+    use super::*;
+
+    #[test]
+    fn test_find_color() {
+        let mut pixels = vec![Bgr8::new(0, 0, 0); 4 * 3];
+        pixels[4 + 1] = Bgr8::new(200, 10, 10);
+        pixels[2 * 4 + 3] = Bgr8::new(205, 5, 12);
+        let frame = Frame::new(pixels, 4, 3);
+        let red = Bgr8::new(200, 10, 10);
+        assert_eq!(frame.find_color(red, 0), vec![(1, 1)]);
+        assert_eq!(frame.find_color(red, 5), vec![(1, 1), (3, 2)]);
+        let region = Rect {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 3,
+        };
+        assert_eq!(frame.find_color_in(red, 5, region), vec![(3, 2)]);
+    }
+
+    #[test]
+    fn test_find_image() {
+        let mut pixels = vec![Bgr8::new(0, 0, 0); 5 * 4];
+        pixels[2 * 5 + 3] = Bgr8::new(255, 255, 255);
+        pixels[2 * 5 + 4] = Bgr8::new(250, 250, 250);
+        let frame = Frame::new(pixels, 5, 4);
+        let white = Frame::new(vec![Bgr8::new(255, 255, 255); 2], 2, 1);
+        assert_eq!(frame.find_image(&white, 0), None);
+        assert_eq!(frame.find_image(&white, 3), Some((3, 2)));
+        let dot = Frame::new(vec![Bgr8::new(250, 250, 250)], 1, 1);
+        assert_eq!(frame.find_image(&dot, 0), Some((4, 2)));
+    }
+}
@@ -0,0 +1,544 @@
+//! Export of frames through a named shared-memory region, so a separate
+//! process, e.g. a sandboxed encoder, can read frames without any copies over
+//! the IPC boundary.
+//!
+//! The region is a POSIX shared-memory object (`shm_open`) on *nix, and a
+//! named file mapping (`CreateFileMapping`) on Windows. It starts with a
+//! header, followed by the pixel data of the latest frame:
+//!
+//! | Offset | Type | Field                                               |
+//! |--------|------|-----------------------------------------------------|
+//! | 0      | u32  | Magic, `b"CPTR"`                                    |
+//! | 4      | u32  | Protocol version, currently 1                       |
+//! | 8      | u64  | Capacity of the pixel data in bytes                 |
+//! | 16     | u64  | Sequence number. Odd while a frame is being written |
+//! | 24     | u32  | Frame width                                         |
+//! | 28     | u32  | Frame height                                        |
+//! | 32     | u32  | Bytes per row                                       |
+//! | 36     | u32  | Pixel format. 0 is 4 byte BGRX, like `Bgr8`         |
+//! | 40     | u64  | Frame number, starting at 1                         |
+//! | 64     |      | Pixel data                                          |
+//!
+//! All fields are in native byte order. A reader reads the sequence number,
+//! then the frame, and then the sequence number again. If the two sequence
+//! numbers differ or are odd, the frame was overwritten during the read and
+//! must be discarded.
+
+use crate::{pixel_bytes, Bgr8};
+use std::io;
+use std::mem;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CPTR");
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 64;
+/// 4 byte blue, green, red, and padding
+pub const FORMAT_BGRX8: u32 = 0;
+
+#[repr(C)]
+struct Header {
+    magic: u32,
+    version: u32,
+    capacity: u64,
+    seq: AtomicU64,
+    width: u32,
+    height: u32,
+    stride: u32,
+    format: u32,
+    frame_number: u64,
+}
+
+/// Metadata of a frame in shared memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShmFrameInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row
+    pub stride: u32,
+    /// Pixel format. Currently always `FORMAT_BGRX8`.
+    pub format: u32,
+    /// Number of the frame, starting at 1 for the first written frame
+    pub frame_number: u64,
+}
+
+/// Writer of frames to a named shared-memory region
+///
+/// The region is removed when the writer is dropped, but stays mapped for
+/// readers that have already opened it.
+pub struct ShmWriter {
+    mapping: sys::Mapping,
+    frame_number: u64,
+}
+
+impl ShmWriter {
+    /// Create a shared-memory region with the given name, with room for
+    /// `capacity` bytes of pixel data, e.g. `4 * width * height`
+    ///
+    /// On *nix the name should start with a `/`. On Windows, it may have a
+    /// `Local\` or `Global\` prefix.
+    pub fn create(name: &str, capacity: usize) -> io::Result<ShmWriter> {
+        let mapping = sys::Mapping::create(name, HEADER_SIZE + capacity)?;
+        let header = Header {
+            magic: MAGIC,
+            version: VERSION,
+            capacity: capacity as u64,
+            seq: AtomicU64::new(0),
+            width: 0,
+            height: 0,
+            stride: 0,
+            format: FORMAT_BGRX8,
+            frame_number: 0,
+        };
+        unsafe { (mapping.ptr as *mut Header).write(header) };
+        Ok(ShmWriter {
+            mapping,
+            frame_number: 0,
+        })
+    }
+
+    /// Capacity of the region's pixel data in bytes
+    pub fn capacity(&self) -> usize {
+        self.mapping.len - HEADER_SIZE
+    }
+
+    /// Write a frame of the given size
+    pub fn write_frame(&mut self, frame: &[Bgr8], width: u32, height: u32) -> io::Result<()> {
+        self.write_frame_components(pixel_bytes(frame), width, height)
+    }
+
+    /// Write a frame of the given size as bytes, in the layout returned by
+    /// `Capturer::capture_frame_components`
+    pub fn write_frame_components(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> io::Result<()> {
+        let stride = width as usize * mem::size_of::<Bgr8>();
+        if data.len() != stride * height as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame size does not match the given geometry",
+            ));
+        }
+        if data.len() > self.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame is larger than the shared-memory region",
+            ));
+        }
+        self.frame_number += 1;
+        let header = self.mapping.ptr as *mut Header;
+        unsafe {
+            let seq = &(*header).seq;
+            let s = seq.load(Ordering::Relaxed);
+            seq.store(s + 1, Ordering::Relaxed);
+            fence(Ordering::Release);
+            (*header).width = width;
+            (*header).height = height;
+            (*header).stride = stride as u32;
+            (*header).format = FORMAT_BGRX8;
+            (*header).frame_number = self.frame_number;
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.mapping.ptr.add(HEADER_SIZE),
+                data.len(),
+            );
+            seq.store(s + 2, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+/// Default time that `ShmReader` waits for the writer to finish writing a
+/// frame
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Reader of frames from a named shared-memory region created by a
+/// `ShmWriter`, possibly in another process
+pub struct ShmReader {
+    mapping: sys::Mapping,
+    read_timeout: Duration,
+}
+
+impl ShmReader {
+    /// Open an existing shared-memory region with the given name
+    pub fn open(name: &str) -> io::Result<ShmReader> {
+        let mapping = sys::Mapping::open(name)?;
+        let invalid = |msg| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        if mapping.len < HEADER_SIZE {
+            return invalid("Shared-memory region is too small");
+        }
+        let header = unsafe { &*(mapping.ptr as *const Header) };
+        if header.magic != MAGIC {
+            return invalid("Shared-memory region is not a captrs frame export");
+        }
+        if header.version != VERSION {
+            return invalid("Unsupported shared-memory protocol version");
+        }
+        if (mapping.len - HEADER_SIZE) < header.capacity as usize {
+            return invalid("Shared-memory region is smaller than its capacity");
+        }
+        Ok(ShmReader {
+            mapping,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+        })
+    }
+
+    /// Set how long reads wait for the writer to finish writing a frame, by
+    /// default `DEFAULT_READ_TIMEOUT`
+    ///
+    /// The wait is bounded so that a writer that died mid-write doesn't hang
+    /// the reader.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.mapping.ptr as *const Header) }
+    }
+
+    /// Sequence number and metadata of the latest frame, or `None` if a frame
+    /// is being written
+    fn begin_read(&self) -> Option<(u64, ShmFrameInfo)> {
+        let header = self.mapping.ptr as *const Header;
+        let s1 = self.header().seq.load(Ordering::Acquire);
+        if s1 & 1 == 1 {
+            return None;
+        }
+        let info = unsafe {
+            ShmFrameInfo {
+                width: std::ptr::read_volatile(&(*header).width),
+                height: std::ptr::read_volatile(&(*header).height),
+                stride: std::ptr::read_volatile(&(*header).stride),
+                format: std::ptr::read_volatile(&(*header).format),
+                frame_number: std::ptr::read_volatile(&(*header).frame_number),
+            }
+        };
+        Some((s1, info))
+    }
+
+    /// Whether the frame with sequence number `s1` wasn't overwritten since
+    /// `begin_read`
+    fn end_read(&self, s1: u64) -> bool {
+        fence(Ordering::Acquire);
+        self.header().seq.load(Ordering::Relaxed) == s1
+    }
+
+    /// Pixel data of the given frame, or `None` if it doesn't fit the region
+    fn data(&self, info: &ShmFrameInfo) -> Option<(*const u8, usize)> {
+        let len = info.stride as usize * info.height as usize;
+        if len > self.mapping.len - HEADER_SIZE {
+            None
+        } else {
+            Some((unsafe { self.mapping.ptr.add(HEADER_SIZE) }, len))
+        }
+    }
+
+    /// Retry `read` until it succeeds, or until the writer hasn't finished
+    /// writing a frame within the read timeout
+    fn retry<R>(&self, mut read: impl FnMut() -> Option<R>) -> Option<R> {
+        let deadline = Instant::now() + self.read_timeout;
+        loop {
+            if let Some(r) = read() {
+                return Some(r);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Number of the latest frame, or 0 if no frame has been written yet
+    ///
+    /// Returns `None` if the writer doesn't finish writing a frame within the
+    /// read timeout, e.g. because it died mid-write.
+    pub fn latest_frame_number(&self) -> Option<u64> {
+        self.retry(|| {
+            let (s1, info) = self.begin_read()?;
+            if self.end_read(s1) {
+                Some(info.frame_number)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Access the latest frame in place, without copying it
+    ///
+    /// Returns `None` if no frame has been written yet, or if the frame was
+    /// being written before or while `f` ran, in which case any result
+    /// computed from the pixels is unreliable and the call should be retried.
+    ///
+    /// # Safety
+    ///
+    /// The writer, possibly in another process, may overwrite the pixels while
+    /// `f` runs. `f` must treat the slice as volatile memory: read each byte
+    /// at most once, not rely on it staying the same, and not let the slice or
+    /// anything borrowed from it escape. Use `read_frame` to get a consistent
+    /// copy instead.
+    pub unsafe fn with_frame<R, F: FnOnce(ShmFrameInfo, &[u8]) -> R>(&self, f: F) -> Option<R> {
+        let (s1, info) = self.begin_read()?;
+        if info.frame_number == 0 {
+            return None;
+        }
+        let (ptr, len) = self.data(&info)?;
+        let r = f(info, std::slice::from_raw_parts(ptr, len));
+        if self.end_read(s1) {
+            Some(r)
+        } else {
+            None
+        }
+    }
+
+    /// Copy the latest frame into `buf`, retrying if it's overwritten during
+    /// the copy
+    ///
+    /// Returns `None` if no frame has been written yet, or if the writer
+    /// doesn't finish writing a frame within the read timeout.
+    pub fn read_frame(&self, buf: &mut Vec<u8>) -> Option<ShmFrameInfo> {
+        self.retry(|| {
+            let (s1, info) = self.begin_read()?;
+            if info.frame_number == 0 {
+                return Some(None);
+            }
+            let (ptr, len) = self.data(&info)?;
+            unsafe { copy_volatile(ptr, len, buf) };
+            if self.end_read(s1) {
+                Some(Some(info))
+            } else {
+                None
+            }
+        })
+        .flatten()
+    }
+}
+
+/// Copy `len` bytes at `src`, which may be written concurrently, into `buf`
+///
+/// `src` must be 8 byte aligned.
+unsafe fn copy_volatile(src: *const u8, len: usize, buf: &mut Vec<u8>) {
+    buf.clear();
+    buf.reserve(len);
+    let words = src as *const u64;
+    for i in 0..len / 8 {
+        buf.extend_from_slice(&std::ptr::read_volatile(words.add(i)).to_ne_bytes());
+    }
+    for i in len / 8 * 8..len {
+        buf.push(std::ptr::read_volatile(src.add(i)));
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::CString;
+    use std::io;
+    use std::ptr;
+
+    pub struct Mapping {
+        pub ptr: *mut u8,
+        pub len: usize,
+        /// Name to unlink on drop, if we created the region
+        owned_name: Option<CString>,
+    }
+
+    fn cvt(res: libc::c_int) -> io::Result<libc::c_int> {
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(res)
+        }
+    }
+
+    unsafe fn map(fd: libc::c_int, len: usize, prot: libc::c_int) -> io::Result<*mut u8> {
+        let ptr = libc::mmap(ptr::null_mut(), len, prot, libc::MAP_SHARED, fd, 0);
+        if ptr == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ptr as *mut u8)
+        }
+    }
+
+    impl Mapping {
+        pub fn create(name: &str, len: usize) -> io::Result<Mapping> {
+            let name = CString::new(name)?;
+            unsafe {
+                let fd = cvt(libc::shm_open(
+                    name.as_ptr(),
+                    libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                    0o600,
+                ))?;
+                let mapped = cvt(libc::ftruncate(fd, len as libc::off_t))
+                    .and_then(|_| map(fd, len, libc::PROT_READ | libc::PROT_WRITE));
+                libc::close(fd);
+                match mapped {
+                    Ok(ptr) => Ok(Mapping {
+                        ptr,
+                        len,
+                        owned_name: Some(name),
+                    }),
+                    Err(e) => {
+                        libc::shm_unlink(name.as_ptr());
+                        Err(e)
+                    }
+                }
+            }
+        }
+
+        pub fn open(name: &str) -> io::Result<Mapping> {
+            let name = CString::new(name)?;
+            unsafe {
+                let fd = cvt(libc::shm_open(name.as_ptr(), libc::O_RDONLY, 0))?;
+                let mut stat = std::mem::zeroed::<libc::stat>();
+                let mapped = cvt(libc::fstat(fd, &mut stat))
+                    .and_then(|_| map(fd, stat.st_size as usize, libc::PROT_READ));
+                libc::close(fd);
+                Ok(Mapping {
+                    ptr: mapped?,
+                    len: stat.st_size as usize,
+                    owned_name: None,
+                })
+            }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len);
+                if let Some(name) = &self.owned_name {
+                    libc::shm_unlink(name.as_ptr());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::OsStr;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use winapi::shared::minwindef::FALSE;
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::memoryapi::{
+        CreateFileMappingW, MapViewOfFile, OpenFileMappingW, UnmapViewOfFile, VirtualQuery,
+        FILE_MAP_ALL_ACCESS, FILE_MAP_READ,
+    };
+    use winapi::um::winnt::{HANDLE, MEMORY_BASIC_INFORMATION, PAGE_READWRITE};
+
+    pub struct Mapping {
+        pub ptr: *mut u8,
+        pub len: usize,
+        handle: HANDLE,
+    }
+
+    fn wide(name: &str) -> Vec<u16> {
+        OsStr::new(name).encode_wide().chain(Some(0)).collect()
+    }
+
+    impl Mapping {
+        pub fn create(name: &str, len: usize) -> io::Result<Mapping> {
+            let name = wide(name);
+            unsafe {
+                let handle = CreateFileMappingW(
+                    INVALID_HANDLE_VALUE,
+                    ptr::null_mut(),
+                    PAGE_READWRITE,
+                    (len as u64 >> 32) as u32,
+                    len as u32,
+                    name.as_ptr(),
+                );
+                if handle.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+                let ptr = MapViewOfFile(handle, FILE_MAP_ALL_ACCESS, 0, 0, len);
+                if ptr.is_null() {
+                    let err = io::Error::last_os_error();
+                    CloseHandle(handle);
+                    return Err(err);
+                }
+                Ok(Mapping {
+                    ptr: ptr as *mut u8,
+                    len,
+                    handle,
+                })
+            }
+        }
+
+        pub fn open(name: &str) -> io::Result<Mapping> {
+            let name = wide(name);
+            unsafe {
+                let handle = OpenFileMappingW(FILE_MAP_READ, FALSE, name.as_ptr());
+                if handle.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+                let ptr = MapViewOfFile(handle, FILE_MAP_READ, 0, 0, 0);
+                if ptr.is_null() {
+                    let err = io::Error::last_os_error();
+                    CloseHandle(handle);
+                    return Err(err);
+                }
+                let mut info = std::mem::zeroed::<MEMORY_BASIC_INFORMATION>();
+                VirtualQuery(ptr, &mut info, std::mem::size_of_val(&info));
+                Ok(Mapping {
+                    ptr: ptr as *mut u8,
+                    len: info.RegionSize,
+                    handle,
+                })
+            }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe {
+                UnmapViewOfFile(self.ptr as *const _);
+                CloseHandle(self.handle);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod shm_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_read() {
+        let name = format!("/captrs-test-{}", std::process::id());
+        let mut writer = ShmWriter::create(&name, 4 * 4 * 2).unwrap();
+        let reader = ShmReader::open(&name).unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(reader.read_frame(&mut buf), None);
+
+        let data: Vec<u8> = (0..32).collect();
+        writer.write_frame_components(&data, 4, 2).unwrap();
+        let info = reader.read_frame(&mut buf).unwrap();
+        assert_eq!((info.width, info.height, info.stride), (4, 2, 16));
+        assert_eq!(info.frame_number, 1);
+        assert_eq!(buf, data);
+
+        assert!(writer.write_frame_components(&[0; 64], 4, 4).is_err());
+    }
+
+    #[test]
+    fn test_dead_writer() {
+        let name = format!("/captrs-test-dead-{}", std::process::id());
+        let mut writer = ShmWriter::create(&name, 4 * 4 * 2).unwrap();
+        let mut reader = ShmReader::open(&name).unwrap();
+        reader.set_read_timeout(Duration::from_millis(10));
+        writer.write_frame_components(&[0; 32], 4, 2).unwrap();
+        assert_eq!(reader.latest_frame_number(), Some(1));
+
+        // Leave the sequence number odd, as if the writer died mid-write
+        let header = writer.mapping.ptr as *const Header;
+        unsafe { (*header).seq.fetch_add(1, Ordering::Release) };
+        assert_eq!(reader.latest_frame_number(), None);
+        assert_eq!(reader.read_frame(&mut Vec::new()), None);
+        assert_eq!(unsafe { reader.with_frame(|info, _| info) }, None);
+    }
+}
@@ -0,0 +1,102 @@
+//! softbuffer integration. Blits captured frames, scaled, onto a softbuffer
+//! surface, e.g. for a window mirroring the screen. See `examples/mirror.rs`.
+
+use crate::Bgr8;
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use softbuffer::{SoftBufferError, Surface};
+
+/// How a frame is scaled to the size of the destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scaling {
+    /// Stretch the frame to fill the destination
+    Stretch,
+    /// Scale the frame to fit inside the destination, keeping the aspect
+    /// ratio, with black borders
+    Fit,
+}
+
+/// Blit a frame of the given size onto the current buffer of a surface,
+/// and present it
+///
+/// The surface must have been resized, e.g. to the size of its window.
+pub fn present<D, W>(
+    surface: &mut Surface<D, W>,
+    frame: &[Bgr8],
+    width: u32,
+    height: u32,
+    scaling: Scaling,
+) -> Result<(), SoftBufferError>
+where
+    D: HasDisplayHandle,
+    W: HasWindowHandle,
+{
+    let mut buffer = surface.buffer_mut()?;
+    let (dst_width, dst_height) = (buffer.width().get(), buffer.height().get());
+    blit(
+        frame,
+        width,
+        height,
+        &mut buffer,
+        dst_width,
+        dst_height,
+        scaling,
+    );
+    buffer.present()
+}
+
+/// Scale a frame of the given size onto a destination of 0RGB pixels, as used
+/// by softbuffer, with nearest neighbour sampling
+pub fn blit(
+    frame: &[Bgr8],
+    width: u32,
+    height: u32,
+    dst: &mut [u32],
+    dst_width: u32,
+    dst_height: u32,
+    scaling: Scaling,
+) {
+    assert_eq!(frame.len(), width as usize * height as usize);
+    assert_eq!(dst.len(), dst_width as usize * dst_height as usize);
+    if width == 0 || height == 0 {
+        dst.fill(0);
+        return;
+    }
+    let (scaled_width, scaled_height) = match scaling {
+        Scaling::Stretch => (dst_width, dst_height),
+        Scaling::Fit => {
+            // Compare dst_width / width and dst_height / height without rounding
+            if dst_width as u64 * height as u64 <= dst_height as u64 * width as u64 {
+                let h = (height as u64 * dst_width as u64 / width as u64) as u32;
+                (dst_width, h)
+            } else {
+                let w = (width as u64 * dst_height as u64 / height as u64) as u32;
+                (w, dst_height)
+            }
+        }
+    };
+    let (x0, y0) = (
+        (dst_width - scaled_width) / 2,
+        (dst_height - scaled_height) / 2,
+    );
+    // Source column of each destination column, computed once rather than per row
+    let columns: Vec<usize> = (0..scaled_width)
+        .map(|x| (x as u64 * width as u64 / scaled_width as u64) as usize)
+        .collect();
+    for (y, dst_row) in dst.chunks_exact_mut(dst_width as usize).enumerate() {
+        let y = y as u32;
+        if y < y0 || y >= y0 + scaled_height {
+            dst_row.fill(0);
+            continue;
+        }
+        let src_y = ((y - y0) as u64 * height as u64 / scaled_height as u64) as usize;
+        let src_row = &frame[src_y * width as usize..][..width as usize];
+        let (left, rest) = dst_row.split_at_mut(x0 as usize);
+        let (middle, right) = rest.split_at_mut(scaled_width as usize);
+        left.fill(0);
+        right.fill(0);
+        for (dst_px, &src_x) in middle.iter_mut().zip(&columns) {
+            let Bgr8 { r, g, b, .. } = src_row[src_x];
+            *dst_px = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+    }
+}
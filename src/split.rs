@@ -0,0 +1,226 @@
+//! One capture loop feeding any number of consumers, e.g. a preview and an
+//! encoder, each reading the latest frame without blocking the capture
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! let (mut producer, receiver) = captrs::Capturer::new(0).unwrap().split();
+//! let mut preview = receiver.clone();
+//! std::thread::spawn(move || {
+//!     while let Some(frame) = preview.recv_timeout(Duration::from_secs(1)) {
+//!         println!("{}x{}", frame.width(), frame.height());
+//!     }
+//! });
+//! loop {
+//!     let _ = producer.capture();
+//! }
+//! ```
+
+use crate::{CaptureError, Capturer, Frame};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Number of published frames kept for reuse once no receiver holds them.
+/// With the latest frame, the frames are triple buffered.
+const SPARES: usize = 2;
+
+/// The latest published frame, and the number of frames published
+#[derive(Default)]
+struct Latest {
+    frame: Option<Arc<Frame>>,
+    published: u64,
+}
+
+#[derive(Default)]
+struct Shared {
+    latest: Mutex<Latest>,
+    new_frame: Condvar,
+    closed: AtomicBool,
+}
+
+/// The capturing half of `Capturer::split`, publishing frames to its
+/// `FrameReceiver`s
+///
+/// Receivers are notified that the producer is gone on drop.
+pub struct FrameProducer {
+    capturer: Capturer,
+    shared: Arc<Shared>,
+    /// Frames published before the latest, reused once no receiver holds
+    /// them
+    spares: Vec<Arc<Frame>>,
+}
+
+impl FrameProducer {
+    /// Capture a frame and publish it as the latest frame
+    ///
+    /// The pixels are copied into the buffer of an earlier frame that no
+    /// receiver holds anymore, if any, so that no allocation is needed in
+    /// the steady state.
+    pub fn capture(&mut self) -> Result<(), CaptureError> {
+        self.capturer.capture_store_frame()?;
+        let buf = self
+            .spares
+            .iter()
+            .position(|frame| Arc::strong_count(frame) == 1)
+            .and_then(|i| Arc::try_unwrap(self.spares.swap_remove(i)).ok())
+            .map_or_else(Vec::new, Frame::into_pixels);
+        let frame = Arc::new(self.capturer.stored_frame_meta_in(buf));
+        let mut latest = self.shared.latest.lock().unwrap();
+        let prev = latest.frame.replace(frame);
+        latest.published += 1;
+        drop(latest);
+        self.shared.new_frame.notify_all();
+        if let Some(prev) = prev {
+            if self.spares.len() == SPARES {
+                self.spares.remove(0);
+            }
+            self.spares.push(prev);
+        }
+        Ok(())
+    }
+
+    /// A new receiver of the published frames
+    pub fn receiver(&self) -> FrameReceiver {
+        FrameReceiver {
+            shared: self.shared.clone(),
+            seen: 0,
+        }
+    }
+
+    /// The underlying capturer
+    pub fn capturer(&self) -> &Capturer {
+        &self.capturer
+    }
+
+    /// The underlying capturer, e.g. to change its capture region
+    pub fn capturer_mut(&mut self) -> &mut Capturer {
+        &mut self.capturer
+    }
+}
+
+impl Drop for FrameProducer {
+    fn drop(&mut self) {
+        // Lock to not race receivers about to wait
+        let _latest = self.shared.latest.lock().unwrap();
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.new_frame.notify_all();
+    }
+}
+
+/// A consumer of the frames of a `FrameProducer`. Cheap to clone.
+#[derive(Clone)]
+pub struct FrameReceiver {
+    shared: Arc<Shared>,
+    /// Number of frames published when this receiver last retrieved one
+    seen: u64,
+}
+
+impl FrameReceiver {
+    /// The latest published frame, if any frame has been published
+    pub fn latest(&mut self) -> Option<Arc<Frame>> {
+        let latest = self.shared.latest.lock().unwrap();
+        self.seen = latest.published;
+        latest.frame.clone()
+    }
+
+    /// Wait up to `timeout` for a frame this receiver has not retrieved yet
+    ///
+    /// Returns `None` on timeout, or if the producer is gone.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Arc<Frame>> {
+        let latest = self.shared.latest.lock().unwrap();
+        let (latest, _) = self
+            .shared
+            .new_frame
+            .wait_timeout_while(latest, timeout, |l| {
+                l.published == self.seen && !self.shared.closed.load(Ordering::SeqCst)
+            })
+            .unwrap();
+        if latest.published == self.seen {
+            return None;
+        }
+        self.seen = latest.published;
+        latest.frame.clone()
+    }
+
+    /// Whether the producer is gone, so no more frames will be published
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Capturer {
+    /// Split into a producer capturing and publishing frames, e.g. in a
+    /// loop on a thread of its own, and a receiver of them, which can be
+    /// cloned for any number of consumers
+    pub fn split(self) -> (FrameProducer, FrameReceiver) {
+        let producer = FrameProducer {
+            capturer: self,
+            shared: Arc::new(Shared::default()),
+            spares: Vec::with_capacity(SPARES),
+        };
+        let receiver = producer.receiver();
+        (producer, receiver)
+    }
+}
+
+#[cfg(test)]
+mod split_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::Bgr8;
+
+    /// Backend of 1x1 frames of increasing brightness
+    struct Counter(Vec<Bgr8>);
+
+    impl Backend for Counter {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            let v = self.0.first().map_or(0, |p| p.r + 1);
+            self.0 = vec![Bgr8::new(v, v, v)];
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            Some((&self.0, 1, 1))
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let capturer = Capturer::with_backend(Box::new(Counter(Vec::new())));
+        let (mut producer, mut receiver) = capturer.split();
+        assert!(receiver.latest().is_none());
+        let timeout = Duration::from_millis(10);
+        assert!(receiver.recv_timeout(timeout).is_none());
+
+        producer.capture().unwrap();
+        let mut other = receiver.clone();
+        assert_eq!(receiver.recv_timeout(timeout).unwrap().pixels()[0].r, 0);
+        assert!(receiver.recv_timeout(timeout).is_none());
+        producer.capture().unwrap();
+        // Receivers only see the latest frame, independently of each other
+        producer.capture().unwrap();
+        assert_eq!(other.recv_timeout(timeout).unwrap().pixels()[0].r, 2);
+        let held = receiver.latest().unwrap();
+        for _ in 0..4 {
+            producer.capture().unwrap();
+        }
+        assert_eq!(held.pixels()[0].r, 2);
+        assert_eq!(receiver.latest().unwrap().pixels()[0].r, 6);
+
+        // Waiting receivers are woken when the producer is dropped
+        other.latest();
+        let waiting = std::thread::spawn(move || other.recv_timeout(Duration::from_secs(10)));
+        drop(producer);
+        assert!(waiting.join().unwrap().is_none());
+        assert!(receiver.is_closed());
+    }
+}
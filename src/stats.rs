@@ -0,0 +1,94 @@
+//! Cumulative statistics of the captures of a `Capturer`, e.g. to monitor
+//! the health of a long-running stream
+
+use crate::{CaptureError, Capturer};
+use std::time::Duration;
+
+/// Cumulative statistics of the captures of a `Capturer`. See
+/// `Capturer::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapturerStats {
+    /// Frames captured
+    pub frames: u64,
+    /// Captures that timed out, e.g. because the screen was still
+    pub timeouts: u64,
+    /// Captures that failed otherwise
+    pub errors: u64,
+    /// Times the capture source was reacquired after access to it was lost
+    pub recoveries: u64,
+    /// Total time spent acquiring frames from the backend, including the
+    /// copy into its buffer
+    pub acquire_time: Duration,
+    /// Total time spent copying frames, to crop them or draw the cursor, and
+    /// out of the capturer, e.g. by `Capturer::capture_frame`
+    pub copy_time: Duration,
+    /// Number of frames converted to other pixel formats
+    pub conversions: u64,
+    /// Total time spent converting frames to other pixel formats
+    pub convert_time: Duration,
+}
+
+impl CapturerStats {
+    /// Mean time to acquire a frame from the backend
+    pub fn mean_acquire_latency(&self) -> Option<Duration> {
+        mean(self.acquire_time, self.frames)
+    }
+
+    /// Mean time spent copying a frame
+    pub fn mean_copy_time(&self) -> Option<Duration> {
+        mean(self.copy_time, self.frames)
+    }
+
+    /// Mean time to convert a frame to another pixel format
+    pub fn mean_convert_time(&self) -> Option<Duration> {
+        mean(self.convert_time, self.conversions)
+    }
+
+    /// Count the result of a capture
+    pub(crate) fn record(&mut self, result: &Result<(), CaptureError>) {
+        match result {
+            Ok(()) => self.frames += 1,
+            Err(CaptureError::Timeout) => self.timeouts += 1,
+            Err(_) => self.errors += 1,
+        }
+    }
+}
+
+fn mean(total: Duration, n: u64) -> Option<Duration> {
+    if n == 0 {
+        None
+    } else {
+        Some(Duration::from_secs_f64(total.as_secs_f64() / n as f64))
+    }
+}
+
+impl Capturer {
+    /// Statistics of the captures since the capturer was constructed, or
+    /// since `Self::reset_stats`
+    pub fn stats(&self) -> CapturerStats {
+        self.stats
+    }
+
+    /// Reset the statistics to zero
+    pub fn reset_stats(&mut self) {
+        self.stats = CapturerStats::default();
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut stats = CapturerStats::default();
+        assert_eq!(stats.mean_acquire_latency(), None);
+        stats.record(&Ok(()));
+        stats.record(&Ok(()));
+        stats.record(&Err(CaptureError::Timeout));
+        stats.record(&Err(CaptureError::AccessLost));
+        stats.acquire_time = Duration::from_millis(10);
+        assert_eq!((stats.frames, stats.timeouts, stats.errors), (2, 1, 1));
+        assert_eq!(stats.mean_acquire_latency(), Some(Duration::from_millis(5)));
+    }
+}
@@ -0,0 +1,177 @@
+//! Embedded HTTP server streaming the live capture as multipart MJPEG, which
+//! any browser can view without additional client software.
+//!
+//! ```no_run
+//! # use captrs::{Capturer, stream::MjpegServer};
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let server = MjpegServer::bind("0.0.0.0:8080").unwrap();
+//! server.run(&mut capturer, 15).unwrap();
+//! ```
+
+use crate::{pixel_bytes, Bgr8, CaptureError, Capturer};
+use jpeg_encoder::{ColorType, Encoder};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BOUNDARY: &str = "captrsframe";
+
+/// The most recently encoded frame, shared with the client threads
+struct Shared {
+    /// Sequence number and JPEG data of the latest frame
+    frame: Mutex<(u64, Arc<Vec<u8>>)>,
+    new_frame: Condvar,
+    n_clients: AtomicUsize,
+}
+
+/// An HTTP server streaming frames as MJPEG to any number of clients
+///
+/// Each client is served by a thread of its own. Frames are encoded once,
+/// regardless of the number of clients, and not at all when there are none.
+pub struct MjpegServer {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    quality: u8,
+    n_frames: u64,
+}
+
+impl MjpegServer {
+    /// Start listening for HTTP connections on `addr`, e.g. `"0.0.0.0:8080"`
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<MjpegServer> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            frame: Mutex::new((0, Arc::new(Vec::new()))),
+            new_frame: Condvar::new(),
+            n_clients: AtomicUsize::new(0),
+        });
+        let accept_shared = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let client_shared = accept_shared.clone();
+                thread::spawn(move || {
+                    client_shared.n_clients.fetch_add(1, Ordering::SeqCst);
+                    let _ = serve_client(stream, &client_shared);
+                    client_shared.n_clients.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        Ok(MjpegServer {
+            shared,
+            local_addr,
+            quality: 80,
+            n_frames: 0,
+        })
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Set the JPEG quality, from 1 to 100. Defaults to 80.
+    pub fn set_quality(&mut self, quality: u8) {
+        self.quality = quality.clamp(1, 100);
+    }
+
+    /// Returns the number of currently connected clients
+    pub fn n_clients(&self) -> usize {
+        self.shared.n_clients.load(Ordering::SeqCst)
+    }
+
+    /// Encode a frame of the given size and send it to all connected clients
+    pub fn send_frame(&mut self, frame: &[Bgr8], width: u32, height: u32) -> io::Result<()> {
+        if self.n_clients() == 0 {
+            return Ok(());
+        }
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame too large for JPEG",
+            ));
+        }
+        let mut jpeg = Vec::new();
+        Encoder::new(&mut jpeg, self.quality)
+            .encode(
+                pixel_bytes(frame),
+                width as u16,
+                height as u16,
+                ColorType::Bgra,
+            )
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.n_frames += 1;
+        *self.shared.frame.lock().unwrap() = (self.n_frames, Arc::new(jpeg));
+        self.shared.new_frame.notify_all();
+        Ok(())
+    }
+
+    /// Capture and serve frames at `fps` frames per second, forever
+    ///
+    /// Nothing is captured while no clients are connected. Timeouts are
+    /// skipped. Returns if any other error occurs.
+    pub fn run(mut self, capturer: &mut Capturer, fps: u32) -> io::Result<()> {
+        assert!(fps > 0, "Frame rate must be positive");
+        let frame_duration = Duration::from_secs(1) / fps;
+        let mut next_frame = Instant::now();
+        loop {
+            if self.n_clients() > 0 {
+                match capturer.capture_store_frame() {
+                    Ok(()) => {
+                        let (w, h) = capturer.geometry();
+                        let frame = capturer.get_stored_frame().unwrap();
+                        self.send_frame(frame, w, h)?;
+                    }
+                    Err(CaptureError::Timeout | CaptureError::NoNewFrame) => (),
+                    Err(e) => return Err(io::Error::other(e)),
+                }
+            }
+            next_frame += frame_duration;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                next_frame = now;
+            }
+        }
+    }
+}
+
+fn serve_client(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    // Whatever the request, respond with the stream. Just consume the headers.
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\n\
+         Content-Type: multipart/x-mixed-replace; boundary={}\r\n\
+         Cache-Control: no-cache\r\n\
+         Connection: close\r\n\r\n",
+        BOUNDARY
+    )?;
+    let mut last_seq = 0;
+    loop {
+        let jpeg = {
+            let mut frame = shared.frame.lock().unwrap();
+            while frame.0 == last_seq {
+                frame = shared.new_frame.wait(frame).unwrap();
+            }
+            last_seq = frame.0;
+            frame.1.clone()
+        };
+        write!(
+            stream,
+            "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            BOUNDARY,
+            jpeg.len()
+        )?;
+        stream.write_all(&jpeg)?;
+        stream.write_all(b"\r\n")?;
+    }
+}
@@ -0,0 +1,135 @@
+//! Captures locked to the refresh of the display, for tear-free and
+//! judder-free recordings
+//!
+//! ```no_run
+//! # use captrs::{Capturer, SyncMode};
+//! let mut capturer = Capturer::new(0).unwrap();
+//! capturer.set_sync(SyncMode::VBlank).unwrap();
+//! loop {
+//!     if let Ok(frame) = capturer.capture_frame_meta() {
+//!         println!("Presented at {:?}", frame.present_time());
+//!     }
+//! }
+//! ```
+
+use crate::{Capturer, InitError};
+use std::time::{Duration, Instant};
+
+/// When to capture frames
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SyncMode {
+    /// As soon as a capture is requested
+    #[default]
+    Free,
+    /// Right after the next vertical blank of the display, so at most one
+    /// frame is captured per refresh
+    VBlank,
+}
+
+/// Timing of the vertical blanks of a display
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct VBlank {
+    period: Duration,
+    /// A past vertical blank, if any was waited for
+    last: Option<Instant>,
+}
+
+impl VBlank {
+    fn new(period: Duration) -> VBlank {
+        VBlank { period, last: None }
+    }
+
+    /// The first vertical blank after `now`, or `now` if the phase of the
+    /// vertical blanks is not known yet
+    fn next(&self, now: Instant) -> Instant {
+        match self.last {
+            Some(last) => {
+                let period = self.period.as_nanos().max(1);
+                let n = now.saturating_duration_since(last).as_nanos() / period + 1;
+                last + Duration::from_nanos((n * period) as u64)
+            }
+            None => now,
+        }
+    }
+}
+
+impl Capturer {
+    /// Set when to capture frames
+    ///
+    /// With `SyncMode::VBlank`, captures wait for the next vertical blank of
+    /// the display of the capture source, and time out after one refresh
+    /// if nothing new was presented. With DXGI desktop duplication, X11 with
+    /// the Present extension, and PipeWire, the vertical blanks are aligned
+    /// to the present times of frames, which are attached to them as
+    /// `Frame::present_time`. Elsewhere, captures are only paced at the
+    /// refresh rate of the display, with an unknown phase.
+    ///
+    /// Fails with `InitError::Unsupported` if the refresh rate of the display
+    /// is unknown.
+    pub fn set_sync(&mut self, mode: SyncMode) -> Result<(), InitError> {
+        self.vblank = match mode {
+            SyncMode::Free => None,
+            SyncMode::VBlank => {
+                let hz = self.refresh_rate().ok_or_else(|| {
+                    InitError::Unsupported("The refresh rate of the display is unknown".to_string())
+                })?;
+                Some(VBlank::new(Duration::from_secs_f64(1.0 / hz)))
+            }
+        };
+        Ok(())
+    }
+
+    /// When frames are captured
+    pub fn sync(&self) -> SyncMode {
+        match self.vblank {
+            Some(_) => SyncMode::VBlank,
+            None => SyncMode::Free,
+        }
+    }
+
+    /// Sleep until the next vertical blank, if synced to them
+    pub(crate) fn wait_vblank(&mut self) {
+        if let Some(vblank) = &mut self.vblank {
+            let now = Instant::now();
+            let next = vblank.next(now);
+            if next > now {
+                std::thread::sleep(next - now);
+            }
+            vblank.last = Some(next);
+        }
+    }
+
+    /// How long to wait for a frame, at most one refresh if synced to
+    /// vertical blanks
+    pub(crate) fn capture_timeout(&self) -> Duration {
+        match self.vblank {
+            Some(vblank) => self.timeout.min(vblank.period),
+            None => self.timeout,
+        }
+    }
+
+    /// Align the vertical blanks to the time the captured frame was
+    /// presented, if reported by the backend
+    pub(crate) fn vblank_captured(&mut self) {
+        if let (Some(vblank), Some(present)) = (&mut self.vblank, self.backend.present_time()) {
+            vblank.last = Some(present);
+        }
+    }
+}
+
+#[cfg(test)]
+mod sync_tests {
+    use super::*;
+
+    #[test]
+    fn test_vblank_next() {
+        let start = Instant::now();
+        let period = Duration::from_millis(10);
+        let mut vblank = VBlank::new(period);
+        assert_eq!(vblank.next(start), start);
+        vblank.last = Some(start);
+        assert_eq!(vblank.next(start), start + period);
+        let now = start + Duration::from_millis(25);
+        assert_eq!(vblank.next(now), start + 3 * period);
+    }
+}
@@ -0,0 +1,245 @@
+//! Capture on a background thread, keeping only the latest frame
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! let capturer = captrs::ThreadedCapturer::new(0, 30.0).unwrap();
+//! if let Some(frame) = capturer.wait_for_frame(Duration::from_secs(1)) {
+//!     println!("{}x{}", frame.width(), frame.height());
+//! }
+//! println!("{:?}", capturer.stats());
+//! ```
+
+use crate::{CaptureError, Capturer, Frame, InitError, ThreadOptions};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Statistics of the captures of a `ThreadedCapturer`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// Frames captured
+    pub captured: u64,
+    /// Frames replaced by a newer frame before being retrieved
+    pub dropped: u64,
+    /// Captures that timed out, e.g. because the screen was still
+    pub timeouts: u64,
+    /// Captures that failed otherwise
+    pub errors: u64,
+}
+
+/// The latest frame, shared with the capture thread
+#[derive(Default)]
+struct Latest {
+    frame: Option<Arc<Frame>>,
+    /// Whether `frame` has been retrieved
+    retrieved: bool,
+    last_error: Option<CaptureError>,
+    stats: CaptureStats,
+}
+
+#[derive(Default)]
+struct Shared {
+    latest: Mutex<Latest>,
+    new_frame: Condvar,
+    stop: AtomicBool,
+}
+
+/// A capturer running on a thread of its own at a fixed rate, keeping only
+/// the latest frame
+///
+/// Frames are retrieved without ever blocking on the capture backend. The
+/// thread is stopped on drop.
+pub struct ThreadedCapturer {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThreadedCapturer {
+    /// Start capturing the given capture source at up to `fps` frames per
+    /// second
+    ///
+    /// Blocks until the capturer is constructed.
+    pub fn new(capture_src: usize, fps: f64) -> Result<ThreadedCapturer, InitError> {
+        ThreadedCapturer::spawn(move || Capturer::new(capture_src), fps)
+    }
+
+    /// Start capturing at up to `fps` frames per second with a capturer
+    /// constructed by `make` on the capture thread, e.g. with a
+    /// `CapturerBuilder`
+    ///
+    /// Blocks until the capturer is constructed.
+    pub fn spawn<F>(make: F, fps: f64) -> Result<ThreadedCapturer, InitError>
+    where
+        F: FnOnce() -> Result<Capturer, InitError> + Send + 'static,
+    {
+        ThreadedCapturer::spawn_with(make, fps, ThreadOptions::default())
+    }
+
+    /// Start capturing as with `Self::spawn`, on a thread with the given
+    /// priority and CPU affinity
+    ///
+    /// ```no_run
+    /// use captrs::{Capturer, ThreadOptions, ThreadPriority, ThreadedCapturer};
+    ///
+    /// let options = ThreadOptions::new(ThreadPriority::Realtime).affinity(vec![2, 3]);
+    /// let capturer = ThreadedCapturer::spawn_with(|| Capturer::new(0), 60.0, options).unwrap();
+    /// ```
+    pub fn spawn_with<F>(
+        make: F,
+        fps: f64,
+        options: ThreadOptions,
+    ) -> Result<ThreadedCapturer, InitError>
+    where
+        F: FnOnce() -> Result<Capturer, InitError> + Send + 'static,
+    {
+        // Tiny frame rates have intervals too long for a `Duration`
+        let interval = Some(fps)
+            .filter(|&fps| fps > 0.0)
+            .and_then(|fps| Duration::try_from_secs_f64(1.0 / fps).ok())
+            .ok_or_else(|| InitError::InvalidArgument(format!("Invalid frame rate {}", fps)))?;
+        let shared = Arc::new(Shared::default());
+        let (init_tx, init_rx) = mpsc::channel();
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("captrs-capture".to_string())
+                .spawn(move || {
+                    let mut capturer = match options.apply().and_then(|()| make()) {
+                        Ok(capturer) => capturer,
+                        Err(e) => return init_tx.send(Err(e)).unwrap_or(()),
+                    };
+                    let _ = init_tx.send(Ok(()));
+                    capture_loop(&mut capturer, interval, &shared)
+                })
+                .map_err(|e| e.to_string())?
+        };
+        init_rx
+            .recv()
+            .map_err(|_| "Capture thread panicked".to_string())??;
+        Ok(ThreadedCapturer {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// The latest captured frame, if any frame has been captured
+    pub fn latest_frame(&self) -> Option<Arc<Frame>> {
+        let mut latest = self.shared.latest.lock().unwrap();
+        latest.retrieved = true;
+        latest.frame.clone()
+    }
+
+    /// Wait up to `timeout` for a frame that has not been retrieved yet
+    pub fn wait_for_frame(&self, timeout: Duration) -> Option<Arc<Frame>> {
+        let latest = self.shared.latest.lock().unwrap();
+        let (mut latest, _) = self
+            .shared
+            .new_frame
+            .wait_timeout_while(latest, timeout, |l| l.frame.is_none() || l.retrieved)
+            .unwrap();
+        if latest.retrieved {
+            return None;
+        }
+        latest.retrieved = true;
+        latest.frame.clone()
+    }
+
+    /// The last capture error other than a timeout, if any
+    pub fn last_error(&self) -> Option<CaptureError> {
+        self.shared.latest.lock().unwrap().last_error.clone()
+    }
+
+    /// Statistics of the captures so far
+    pub fn stats(&self) -> CaptureStats {
+        self.shared.latest.lock().unwrap().stats
+    }
+}
+
+impl Drop for ThreadedCapturer {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn capture_loop(capturer: &mut Capturer, interval: Duration, shared: &Shared) {
+    let mut next_capture = Instant::now();
+    while !shared.stop.load(Ordering::SeqCst) {
+        let res = capturer.capture_frame_meta();
+        let mut latest = shared.latest.lock().unwrap();
+        match res {
+            Ok(frame) => {
+                latest.stats.captured += 1;
+                if latest.frame.is_some() && !latest.retrieved {
+                    latest.stats.dropped += 1;
+                }
+                latest.frame = Some(Arc::new(frame));
+                latest.retrieved = false;
+                shared.new_frame.notify_all();
+            }
+            Err(CaptureError::Timeout | CaptureError::NoNewFrame) => latest.stats.timeouts += 1,
+            Err(e) => {
+                latest.stats.errors += 1;
+                latest.last_error = Some(e);
+            }
+        }
+        drop(latest);
+        next_capture += interval;
+        let now = Instant::now();
+        if next_capture > now {
+            thread::sleep(next_capture - now);
+        } else {
+            next_capture = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod threaded_tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::Bgr8;
+
+    struct Still(Option<Vec<Bgr8>>);
+
+    impl Backend for Still {
+        fn name(&self) -> &'static str {
+            "still"
+        }
+
+        fn geometry(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+            self.0 = Some(vec![Bgr8::new(1, 2, 3)]);
+            Ok(())
+        }
+
+        fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+            self.0.as_deref().map(|frame| (frame, 1, 1))
+        }
+    }
+
+    #[test]
+    fn test_threaded_capturer() {
+        let make = || Ok(Capturer::with_backend(Box::new(Still(None))));
+        let capturer = ThreadedCapturer::spawn(make, 200.0).unwrap();
+        let first = capturer.wait_for_frame(Duration::from_secs(5)).unwrap();
+        assert_eq!(first.pixels(), [Bgr8::new(1, 2, 3)]);
+        let second = capturer.wait_for_frame(Duration::from_secs(5)).unwrap();
+        assert!(second.sequence() > first.sequence());
+        let stats = capturer.stats();
+        assert!(stats.captured >= stats.dropped + 2);
+        for fps in [0.0, -1.0, f64::NAN, 1e-300] {
+            assert!(matches!(
+                ThreadedCapturer::spawn(make, fps),
+                Err(InitError::InvalidArgument(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Frames split into fixed size tiles with stable checksums, e.g. to only
+//! encode or transmit the tiles that changed, like remote desktops
+//!
+//! ```
+//! # use captrs::{Bgr8, Frame};
+//! let prev = Frame::new(vec![Bgr8::new(0, 0, 0); 128 * 128], 128, 128);
+//! let mut cur = prev.clone();
+//! cur.pixels_mut()[100 * 128 + 100] = Bgr8::new(255, 255, 255);
+//! let prev_sums = prev.tile_checksums(64);
+//! let changed: Vec<usize> = cur
+//!     .tiles(64)
+//!     .filter(|tile| tile.checksum() != prev_sums[tile.index])
+//!     .map(|tile| tile.index)
+//!     .collect();
+//! assert_eq!(changed, [3]);
+//! ```
+
+use crate::{pixel_bytes, Bgr8, Frame, Rect};
+use std::convert::TryInto;
+
+const PRIME1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME5: u64 = 0x27D4_EB2F_1656_67C5;
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME1)
+}
+
+fn merge_round(acc: u64, v: u64) -> u64 {
+    (acc ^ round(0, v))
+        .wrapping_mul(PRIME1)
+        .wrapping_add(PRIME4)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// Streaming XXH64 with a seed of 0, hashing the concatenation of the
+/// slices it's updated with
+pub struct Xxh64 {
+    v: [u64; 4],
+    buf: [u8; 32],
+    buf_len: usize,
+    total_len: u64,
+}
+
+impl Xxh64 {
+    pub fn new() -> Xxh64 {
+        Xxh64 {
+            v: [
+                PRIME1.wrapping_add(PRIME2),
+                PRIME2,
+                0,
+                0u64.wrapping_sub(PRIME1),
+            ],
+            buf: [0; 32],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, v) in self.v.iter_mut().enumerate() {
+            *v = round(*v, read_u64(&stripe[8 * i..]));
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+        if self.buf_len > 0 {
+            let n = bytes.len().min(32 - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&bytes[..n]);
+            self.buf_len += n;
+            bytes = &bytes[n..];
+            if self.buf_len < 32 {
+                return;
+            }
+            let buf = self.buf;
+            self.stripe(&buf);
+            self.buf_len = 0;
+        }
+        let mut stripes = bytes.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    pub fn finish(&self) -> u64 {
+        let [v1, v2, v3, v4] = self.v;
+        let mut h = if self.total_len >= 32 {
+            let h = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            self.v.iter().fold(h, |h, &v| merge_round(h, v))
+        } else {
+            PRIME5
+        };
+        h = h.wrapping_add(self.total_len);
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            h ^= round(0, read_u64(rest));
+            h = h.rotate_left(27).wrapping_mul(PRIME1).wrapping_add(PRIME4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let k = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+            h ^= k.wrapping_mul(PRIME1);
+            h = h.rotate_left(23).wrapping_mul(PRIME2).wrapping_add(PRIME3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            h ^= (byte as u64).wrapping_mul(PRIME5);
+            h = h.rotate_left(11).wrapping_mul(PRIME1);
+        }
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME3);
+        h ^ (h >> 32)
+    }
+}
+
+impl Default for Xxh64 {
+    fn default() -> Xxh64 {
+        Xxh64::new()
+    }
+}
+
+/// A view of a tile of a frame
+#[derive(Clone, Copy, Debug)]
+pub struct Tile<'a> {
+    /// Row-major index of the tile among the tiles of the frame
+    pub index: usize,
+    /// The pixels of the frame in the tile
+    pub rect: Rect,
+    frame: &'a Frame,
+}
+
+impl<'a> Tile<'a> {
+    /// The rows of pixels of the tile, top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &'a [Bgr8]> + 'a {
+        let (width, rect, pixels) = (self.frame.width() as usize, self.rect, self.frame.pixels());
+        (rect.y..rect.y + rect.height).map(move |y| {
+            let start = y as usize * width + rect.x as usize;
+            &pixels[start..start + rect.width as usize]
+        })
+    }
+
+    /// Copy of the pixels of the tile, row by row
+    pub fn to_pixels(&self) -> Vec<Bgr8> {
+        self.rows().flatten().copied().collect()
+    }
+
+    /// XXH64 of the bytes of the pixels of the tile, row by row
+    ///
+    /// Stable across runs and platforms, so checksums can be compared with
+    /// those computed elsewhere.
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = Xxh64::new();
+        for row in self.rows() {
+            hasher.update(pixel_bytes(row));
+        }
+        hasher.finish()
+    }
+}
+
+/// Iterator over the tiles of a frame, row by row. See `Frame::tiles`.
+pub struct Tiles<'a> {
+    frame: &'a Frame,
+    tile_size: u32,
+    columns: usize,
+    index: usize,
+    len: usize,
+}
+
+impl<'a> Iterator for Tiles<'a> {
+    type Item = Tile<'a>;
+
+    fn next(&mut self) -> Option<Tile<'a>> {
+        if self.index >= self.len {
+            return None;
+        }
+        let (width, height) = self.frame.geometry();
+        let x = (self.index % self.columns) as u32 * self.tile_size;
+        let y = (self.index / self.columns) as u32 * self.tile_size;
+        let tile = Tile {
+            index: self.index,
+            rect: Rect {
+                x,
+                y,
+                width: self.tile_size.min(width - x),
+                height: self.tile_size.min(height - y),
+            },
+            frame: self.frame,
+        };
+        self.index += 1;
+        Some(tile)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.len - self.index;
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for Tiles<'_> {}
+
+impl Frame {
+    /// The tiles of `tile_size` pixels square of the frame, row by row
+    ///
+    /// Tiles at the right and bottom edges are smaller if the size of the
+    /// frame is not a multiple of `tile_size`.
+    pub fn tiles(&self, tile_size: u32) -> Tiles<'_> {
+        assert!(tile_size > 0);
+        let (width, height) = self.geometry();
+        let columns = width.div_ceil(tile_size) as usize;
+        let rows = height.div_ceil(tile_size) as usize;
+        Tiles {
+            frame: self,
+            tile_size,
+            columns,
+            index: 0,
+            len: columns * rows,
+        }
+    }
+
+    /// `Tile::checksum` of every tile of `Self::tiles`, in the same order
+    pub fn tile_checksums(&self, tile_size: u32) -> Vec<u64> {
+        self.tiles(tile_size).map(|tile| tile.checksum()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tiles_tests {
+    use super::*;
+
+    fn xxh64(bytes: &[u8]) -> u64 {
+        let mut hasher = Xxh64::new();
+        hasher.update(bytes);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_xxh64() {
+        assert_eq!(xxh64(b""), 0xEF46_DB37_51D8_E999);
+        assert_eq!(xxh64(b"abc"), 0x44BC_2CF5_AD77_0999);
+        // Streaming in uneven pieces hashes the same as at once
+        let bytes: Vec<u8> = (0..100u8).collect();
+        let mut hasher = Xxh64::new();
+        for chunk in bytes.chunks(7) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), xxh64(&bytes));
+    }
+
+    #[test]
+    fn test_tiles() {
+        let pixels = (0..5 * 3).map(|i| Bgr8::new(i as u8, 0, 0)).collect();
+        let frame = Frame::new(pixels, 5, 3);
+        let tiles: Vec<Tile> = frame.tiles(2).collect();
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[5].rect,
+            Rect {
+                x: 4,
+                y: 2,
+                width: 1,
+                height: 1
+            }
+        );
+        assert_eq!(tiles[1].to_pixels()[3], Bgr8::new(8, 0, 0));
+        assert_eq!(
+            tiles[0].checksum(),
+            xxh64(pixel_bytes(&[
+                Bgr8::new(0, 0, 0),
+                Bgr8::new(1, 0, 0),
+                Bgr8::new(5, 0, 0),
+                Bgr8::new(6, 0, 0),
+            ]))
+        );
+        let sums = frame.tile_checksums(2);
+        assert_ne!(sums[0], sums[1]);
+    }
+}
@@ -0,0 +1,64 @@
+//! Instrumentation of captures with [tracing](https://crates.io/crates/tracing)
+//! spans and events. Install a subscriber, e.g. `tracing-subscriber`, to
+//! collect them.
+//!
+//! Each capture is a `capture` span at debug level, of the backend and the
+//! sequence number of the frame. Within it, the acquisition of frames from
+//! the backend and their copy out of it are events at trace level, with
+//! their durations and the number of dirty rectangles. Failed captures,
+//! other than timeouts, are warnings with the kind and platform code of the
+//! error. Reacquisitions of the capture source are info events, and
+//! conversions to pixel formats debug events.
+
+use crate::{CaptureError, PixelFormat};
+use std::time::Duration;
+use tracing::field::{display, Empty};
+use tracing::{debug, info, trace, warn, Span};
+
+/// Span of a capture, recording the sequence number of the frame once
+/// captured
+pub(crate) fn capture_span(backend: &'static str) -> Span {
+    tracing::debug_span!("capture", backend, sequence = Empty)
+}
+
+/// Record an attempt to acquire a frame from the backend
+pub(crate) fn acquired(
+    res: &Result<(), CaptureError>,
+    elapsed: Duration,
+    dirty_rects: Option<usize>,
+) {
+    let elapsed_us = elapsed.as_micros() as u64;
+    match res {
+        Ok(()) => trace!(elapsed_us, dirty_rects, "acquired frame"),
+        Err(CaptureError::Timeout | CaptureError::NoNewFrame) => {
+            trace!(elapsed_us, "no new frame")
+        }
+        Err(e) => {
+            let code = match e {
+                CaptureError::Platform(e) => Some(display(e.code)),
+                _ => None,
+            };
+            warn!(elapsed_us, kind = e.kind(), code, error = %e, "capture failed")
+        }
+    }
+}
+
+/// Record the copy and processing of a frame out of the backend
+pub(crate) fn copied(elapsed: Duration, (width, height): (u32, u32), processed: bool) {
+    let elapsed_us = elapsed.as_micros() as u64;
+    trace!(elapsed_us, width, height, processed, "copied frame");
+}
+
+/// Record a reacquisition of the capture source
+pub(crate) fn reacquired(res: &Result<(), CaptureError>) {
+    match res {
+        Ok(()) => info!("reacquired capture source"),
+        Err(e) => warn!(kind = e.kind(), error = %e, "failed to reacquire capture source"),
+    }
+}
+
+/// Record a conversion of a frame to a pixel format
+pub(crate) fn converted(format: PixelFormat, elapsed: Duration) {
+    let elapsed_us = elapsed.as_micros() as u64;
+    debug!(?format, elapsed_us, "converted frame");
+}
@@ -0,0 +1,135 @@
+//! Sink that writes captured frames to a V4L2 output device, such as one
+//! created by [v4l2loopback](https://github.com/umlaeute/v4l2loopback), so the
+//! captured screen shows up as a webcam in browsers and conferencing apps.
+
+use crate::{pixel_bytes, Bgr8};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+const V4L2_BUF_TYPE_VIDEO_OUTPUT: u32 = 2;
+const V4L2_FIELD_NONE: u32 = 1;
+const V4L2_COLORSPACE_SRGB: u32 = 8;
+/// Blue, green, red, and one byte of padding. Same layout as `Bgr8`.
+const V4L2_PIX_FMT_BGR32: u32 = fourcc(b"BGR4");
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    code[0] as u32 | (code[1] as u32) << 8 | (code[2] as u32) << 16 | (code[3] as u32) << 24
+}
+
+/// `struct v4l2_pix_format` from `linux/videodev2.h`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct V4l2PixFormat {
+    width: u32,
+    height: u32,
+    pixelformat: u32,
+    field: u32,
+    bytesperline: u32,
+    sizeimage: u32,
+    colorspace: u32,
+    priv_: u32,
+    flags: u32,
+    ycbcr_enc: u32,
+    quantization: u32,
+    xfer_func: u32,
+}
+
+/// The `fmt` union of `struct v4l2_format`. Some of the members we don't use
+/// contain pointers, which gives the union pointer alignment.
+#[repr(C)]
+union V4l2FormatUnion {
+    pix: V4l2PixFormat,
+    raw_data: [u8; 200],
+    _align: [usize; 0],
+}
+
+/// `struct v4l2_format` from `linux/videodev2.h`
+#[repr(C)]
+struct V4l2Format {
+    type_: u32,
+    fmt: V4l2FormatUnion,
+}
+
+/// `_IOWR('V', 5, struct v4l2_format)`
+const VIDIOC_S_FMT: libc::c_ulong = (3 << 30)
+    | ((mem::size_of::<V4l2Format>() as libc::c_ulong) << 16)
+    | ((b'V' as libc::c_ulong) << 8)
+    | 5;
+
+/// A V4L2 output device that frames of a fixed size can be written to
+pub struct V4l2Sink {
+    device: File,
+    width: u32,
+    height: u32,
+}
+
+impl V4l2Sink {
+    /// Open a V4L2 output device, e.g. `/dev/video10`, and configure it for
+    /// frames of the given size.
+    pub fn open<P: AsRef<Path>>(device: P, width: u32, height: u32) -> io::Result<V4l2Sink> {
+        let device = OpenOptions::new().write(true).open(device)?;
+        let bytes_per_line = width * mem::size_of::<Bgr8>() as u32;
+        let mut format = V4l2Format {
+            type_: V4L2_BUF_TYPE_VIDEO_OUTPUT,
+            fmt: V4l2FormatUnion { raw_data: [0; 200] },
+        };
+        format.fmt.pix = V4l2PixFormat {
+            width,
+            height,
+            pixelformat: V4L2_PIX_FMT_BGR32,
+            field: V4L2_FIELD_NONE,
+            bytesperline: bytes_per_line,
+            sizeimage: bytes_per_line * height,
+            colorspace: V4L2_COLORSPACE_SRGB,
+            priv_: 0,
+            flags: 0,
+            ycbcr_enc: 0,
+            quantization: 0,
+            xfer_func: 0,
+        };
+        let res = unsafe { libc::ioctl(device.as_raw_fd(), VIDIOC_S_FMT as _, &mut format) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(V4l2Sink {
+            device,
+            width,
+            height,
+        })
+    }
+
+    /// Returns the width and height of the frames the device is configured for
+    pub fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Write a frame to the device
+    ///
+    /// The frame must be of the size the device was opened with.
+    pub fn write_frame(&mut self, frame: &[Bgr8]) -> io::Result<()> {
+        if frame.len() != self.width as usize * self.height as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame size does not match the size of the V4L2 device",
+            ));
+        }
+        self.device.write_all(pixel_bytes(frame))
+    }
+}
+
+#[cfg(test)]
+mod v4l2_tests {
+    use super::*;
+
+    #[test]
+    fn test_abi() {
+        // Values from linux/videodev2.h on 64-bit targets
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(VIDIOC_S_FMT, 0xc0d05605);
+        assert_eq!(mem::size_of::<V4l2PixFormat>(), 48);
+        assert_eq!(V4L2_PIX_FMT_BGR32, 0x34524742);
+    }
+}
@@ -0,0 +1,368 @@
+//! Recording of video files by piping NV12 frames into an `ffmpeg`
+//! subprocess. `ffmpeg` must be installed and in `PATH`.
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::video::VideoRecorder;
+//! # use std::time::Duration;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let mut recorder = VideoRecorder::new("screen.mp4")
+//!     .codec("libx264")
+//!     .bitrate(8_000_000)
+//!     .fps(30.0)
+//!     .duration(Duration::from_secs(10));
+//! recorder.record(&mut capturer).unwrap();
+//! recorder.finish().unwrap();
+//! ```
+
+use crate::replay::ReplayBuffer;
+use crate::schedule::ClockScheduler;
+use crate::{Bgr8, CaptureError, Capturer, YuvFormat, YuvFrame};
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Error of recording a video
+#[derive(Debug)]
+pub enum VideoError {
+    Capture(CaptureError),
+    /// `ffmpeg` could not be started, or the frames could not be written to
+    /// it
+    Io(io::Error),
+    /// `ffmpeg` exited unsuccessfully
+    Ffmpeg(ExitStatus),
+    /// The size of the frames changed during the recording
+    SizeChanged,
+}
+
+impl fmt::Display for VideoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VideoError::Capture(e) => e.fmt(f),
+            VideoError::Io(e) => write!(f, "Failed to run ffmpeg: {}", e),
+            VideoError::Ffmpeg(status) => write!(f, "ffmpeg failed: {}", status),
+            VideoError::SizeChanged => write!(f, "Resolution changed during recording"),
+        }
+    }
+}
+
+impl Error for VideoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            VideoError::Capture(e) => Some(e),
+            VideoError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<CaptureError> for VideoError {
+    fn from(e: CaptureError) -> VideoError {
+        VideoError::Capture(e)
+    }
+}
+
+impl From<io::Error> for VideoError {
+    fn from(e: io::Error) -> VideoError {
+        VideoError::Io(e)
+    }
+}
+
+/// Stops a recording in progress, e.g. from another thread
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+}
+
+/// The running `ffmpeg` process and the size of its frames
+struct Encoder {
+    child: Child,
+    stdin: ChildStdin,
+    width: u32,
+    height: u32,
+}
+
+/// Recorder of frames into a video file, e.g. MP4 or MKV
+///
+/// `ffmpeg` is started with the first frame, as the size of the video is
+/// that of the first frame. The container is picked by `ffmpeg` from the
+/// extension of the output path, unless set with `VideoRecorder::container`.
+pub struct VideoRecorder {
+    output: OsString,
+    codec: String,
+    bitrate: Option<u64>,
+    fps: f64,
+    container: Option<String>,
+    threads: usize,
+    duration: Option<Duration>,
+    stop: StopHandle,
+    encoder: Option<Encoder>,
+}
+
+impl VideoRecorder {
+    /// Construct a recorder of H.264 video with `libx264` at 30 frames per
+    /// second, recording until stopped
+    pub fn new(output: impl AsRef<Path>) -> VideoRecorder {
+        VideoRecorder {
+            output: output.as_ref().as_os_str().to_owned(),
+            codec: "libx264".to_string(),
+            bitrate: None,
+            fps: 30.0,
+            container: None,
+            threads: 1,
+            duration: None,
+            stop: StopHandle::default(),
+            encoder: None,
+        }
+    }
+
+    /// Encode with an `ffmpeg` encoder, e.g. `libx265` or `h264_nvenc`
+    pub fn codec(mut self, codec: impl Into<String>) -> VideoRecorder {
+        self.codec = codec.into();
+        self
+    }
+
+    /// Target bitrate in bits per second. By default, that of the encoder.
+    pub fn bitrate(mut self, bitrate: u64) -> VideoRecorder {
+        self.bitrate = Some(bitrate);
+        self
+    }
+
+    /// Frame rate of the video, and the rate of `VideoRecorder::record`
+    pub fn fps(mut self, fps: f64) -> VideoRecorder {
+        assert!(fps > 0.0);
+        self.fps = fps;
+        self
+    }
+
+    /// `ffmpeg` format of the container, e.g. `mp4` or `matroska`
+    pub fn container(mut self, container: impl Into<String>) -> VideoRecorder {
+        self.container = Some(container.into());
+        self
+    }
+
+    /// Convert frames to NV12 with up to `threads` threads
+    pub fn threads(mut self, threads: usize) -> VideoRecorder {
+        self.threads = threads;
+        self
+    }
+
+    /// Stop `VideoRecorder::record` after `duration`
+    pub fn duration(mut self, duration: Duration) -> VideoRecorder {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// A handle to stop `VideoRecorder::record`
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    fn ffmpeg_command(&self, width: u32, height: u32) -> Command {
+        let mut cmd = Command::new("ffmpeg");
+        cmd.args(["-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pix_fmt", "nv12"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &self.fps.to_string(), "-i", "-"])
+            .args(["-c:v", &self.codec]);
+        if let Some(bitrate) = self.bitrate {
+            cmd.args(["-b:v", &bitrate.to_string()]);
+        }
+        if let Some(container) = &self.container {
+            cmd.args(["-f", container]);
+        }
+        cmd.arg(&self.output);
+        cmd
+    }
+
+    /// Add a frame of the given size to the video. The frame is shown for
+    /// `1 / fps` seconds.
+    pub fn push_frame(
+        &mut self,
+        pixels: &[Bgr8],
+        width: u32,
+        height: u32,
+    ) -> Result<(), VideoError> {
+        let yuv = YuvFrame::convert_parallel(pixels, width, height, YuvFormat::Nv12, self.threads);
+        self.push_yuv(&yuv, 1)
+    }
+
+    fn push_yuv(&mut self, yuv: &YuvFrame, repeat: u64) -> Result<(), VideoError> {
+        if self.encoder.is_none() {
+            let mut child = self
+                .ffmpeg_command(yuv.width(), yuv.height())
+                .stdin(Stdio::piped())
+                .spawn()?;
+            let stdin = child.stdin.take().unwrap();
+            self.encoder = Some(Encoder {
+                child,
+                stdin,
+                width: yuv.width(),
+                height: yuv.height(),
+            });
+        }
+        let encoder = self.encoder.as_mut().unwrap();
+        if (encoder.width, encoder.height) != (yuv.width(), yuv.height()) {
+            return Err(VideoError::SizeChanged);
+        }
+        for _ in 0..repeat {
+            encoder.stdin.write_all(yuv.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Capture frames at the set frame rate until the set duration has
+    /// passed, or until stopped with a `StopHandle`
+    ///
+    /// When captures fall behind or time out, the last frame is repeated, so
+    /// the video plays at the speed it was recorded at.
+    pub fn record(&mut self, capturer: &mut Capturer) -> Result<(), VideoError> {
+        self.stop.0.store(false, Ordering::Relaxed);
+        let start = Instant::now();
+        let mut scheduler = ClockScheduler::new(move || start.elapsed(), self.fps);
+        let mut last: Option<YuvFrame> = None;
+        while !self.stop.0.load(Ordering::Relaxed) {
+            if self.duration.is_some_and(|d| start.elapsed() >= d) {
+                break;
+            }
+            match scheduler.capture(capturer) {
+                Ok(scheduled) => {
+                    let frame = &scheduled.frame;
+                    let yuv = YuvFrame::convert_parallel(
+                        frame.pixels(),
+                        frame.width(),
+                        frame.height(),
+                        YuvFormat::Nv12,
+                        self.threads,
+                    );
+                    if let Some(prev) = &last {
+                        self.push_yuv(prev, scheduled.skipped)?;
+                    }
+                    self.push_yuv(&yuv, 1)?;
+                    last = Some(yuv);
+                }
+                Err(CaptureError::Timeout | CaptureError::NoNewFrame) => {
+                    if let Some(prev) = &last {
+                        self.push_yuv(prev, 1)?;
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the video, and wait for `ffmpeg` to write it
+    pub fn finish(mut self) -> Result<(), VideoError> {
+        match self.encoder.take() {
+            Some(Encoder {
+                mut child, stdin, ..
+            }) => {
+                drop(stdin);
+                let status = child.wait()?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(VideoError::Ffmpeg(status))
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl ReplayBuffer {
+    /// Encode the buffered frames into a video with `recorder`, and finish
+    /// it. The duration of the set frame rate is ignored.
+    ///
+    /// Frames are repeated or dropped to play at the speed they were
+    /// captured at, at the frame rate of the recorder.
+    pub fn save_video(&self, mut recorder: VideoRecorder) -> Result<(), VideoError> {
+        let fps = recorder.fps;
+        let times: Vec<Duration> = self.frames().map(|(_, time)| time).collect();
+        // Number of video frames written so far
+        let mut written = 0;
+        for (i, (frame, _)) in self.frames().enumerate() {
+            let end = match times.get(i + 1) {
+                Some(next) => (next.as_secs_f64() * fps).round() as u64,
+                None => written + 1,
+            };
+            if end <= written {
+                continue;
+            }
+            let yuv = YuvFrame::convert_parallel(
+                frame.pixels(),
+                frame.width(),
+                frame.height(),
+                YuvFormat::Nv12,
+                recorder.threads,
+            );
+            recorder.push_yuv(&yuv, end - written)?;
+            written = end;
+        }
+        recorder.finish()
+    }
+}
+
+impl Drop for VideoRecorder {
+    fn drop(&mut self) {
+        if let Some(Encoder {
+            mut child, stdin, ..
+        }) = self.encoder.take()
+        {
+            drop(stdin);
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod video_tests {
+    use super::*;
+
+    #[test]
+    fn test_ffmpeg_command() {
+        let recorder = VideoRecorder::new("out.mkv")
+            .codec("libx265")
+            .bitrate(4_000_000)
+            .fps(24.0)
+            .container("matroska");
+        let cmd = recorder.ffmpeg_command(640, 480);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "-loglevel",
+                "error",
+                "-y",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "nv12",
+                "-s",
+                "640x480",
+                "-r",
+                "24",
+                "-i",
+                "-",
+                "-c:v",
+                "libx265",
+                "-b:v",
+                "4000000",
+                "-f",
+                "matroska",
+                "out.mkv"
+            ]
+        );
+    }
+}
@@ -0,0 +1,642 @@
+//! Notifications of displays being plugged in, unplugged, or rearranged,
+//! and of regions of the screen changing, see `RegionWatcher`
+//!
+//! ```no_run
+//! # use captrs::watcher::{DisplayEvent, DisplayWatcher};
+//! let watcher = DisplayWatcher::new().unwrap();
+//! for event in watcher.iter() {
+//!     if let DisplayEvent::DisplayRemoved(display) = event {
+//!         println!("{} was unplugged", display.name);
+//!     }
+//! }
+//! ```
+
+use crate::{displays, roi, Bgr8, CaptureError, Capturer, DisplayInfo, Frame, InitError, Rect};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often displays are compared when the platform doesn't notify of
+/// changes, and how long stopping the watcher may take
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A change of the displays
+///
+/// Displays are identified by their names. Capture source indices of other
+/// displays may change when a display is added or removed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisplayEvent {
+    DisplayAdded(DisplayInfo),
+    DisplayRemoved(DisplayInfo),
+    /// The position or size of a display changed, e.g. because the
+    /// resolution changed or the displays were rearranged
+    GeometryChanged {
+        old: DisplayInfo,
+        new: DisplayInfo,
+    },
+    /// Another display became the primary display
+    PrimaryChanged(DisplayInfo),
+}
+
+/// The changes from the displays `old` to the displays `new`
+fn diff(old: &[DisplayInfo], new: &[DisplayInfo]) -> Vec<DisplayEvent> {
+    let find = |displays: &[DisplayInfo], name: &str| -> Option<DisplayInfo> {
+        displays.iter().find(|d| d.name == name).cloned()
+    };
+    let mut events = Vec::new();
+    for d in old {
+        if find(new, &d.name).is_none() {
+            events.push(DisplayEvent::DisplayRemoved(d.clone()));
+        }
+    }
+    for d in new {
+        match find(old, &d.name) {
+            None => events.push(DisplayEvent::DisplayAdded(d.clone())),
+            Some(o) if (o.position, o.size) != (d.position, d.size) => {
+                events.push(DisplayEvent::GeometryChanged {
+                    old: o,
+                    new: d.clone(),
+                })
+            }
+            Some(_) => (),
+        }
+    }
+    let primary =
+        |displays: &[DisplayInfo]| displays.iter().find(|d| d.primary).map(|d| d.name.clone());
+    if let Some(name) = primary(new).filter(|name| Some(name) != primary(old).as_ref()) {
+        events.extend(find(new, &name).map(DisplayEvent::PrimaryChanged));
+    }
+    events
+}
+
+/// A watcher of changes of the displays on a thread of its own
+///
+/// Changes are detected with `WM_DISPLAYCHANGE` on Windows and RandR
+/// notifications on X11, and by comparing the displays periodically on
+/// other platforms. The thread is stopped on drop.
+pub struct DisplayWatcher {
+    events: Receiver<DisplayEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DisplayWatcher {
+    /// Start watching the displays
+    pub fn new() -> Result<DisplayWatcher, InitError> {
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("captrs-display-watcher".to_string())
+                .spawn(move || watch(tx, &stop))
+                .map_err(|e| e.to_string())?
+        };
+        Ok(DisplayWatcher {
+            events: rx,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// The next change, if any has happened
+    pub fn try_recv(&self) -> Option<DisplayEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next change
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<DisplayEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Wait for changes, forever
+    pub fn iter(&self) -> impl Iterator<Item = DisplayEvent> + '_ {
+        self.events.iter()
+    }
+}
+
+impl Drop for DisplayWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch(events: Sender<DisplayEvent>, stop: &AtomicBool) {
+    let mut notifier = sys::Notifier::new();
+    let mut known = displays();
+    while !stop.load(Ordering::SeqCst) {
+        let changed = match &mut notifier {
+            Some(notifier) => notifier.wait(POLL_INTERVAL),
+            None => {
+                thread::sleep(POLL_INTERVAL);
+                true
+            }
+        };
+        if !changed {
+            continue;
+        }
+        let current = displays();
+        for event in diff(&known, &current) {
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+        known = current;
+    }
+}
+
+/// A change of a region watched by a `RegionWatcher`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionChange {
+    /// Id of the watch, as returned by `RegionWatcher::watch`
+    pub id: usize,
+    /// The watched region, clipped to the frames
+    pub region: Rect,
+    /// Number of pixels of the region that differ by more than the
+    /// threshold from when it last changed
+    pub changed_pixels: usize,
+    /// The new content of the region, with the timestamp and sequence
+    /// number of the frame it was captured in
+    pub frame: Frame,
+}
+
+type ChangeCallback = Box<dyn FnMut(&RegionChange) + Send>;
+
+/// A region watched by a `RegionWatcher`
+struct Watch {
+    id: usize,
+    region: Rect,
+    threshold: u8,
+    /// Called with the changes, instead of sending them as events, if set
+    callback: Option<ChangeCallback>,
+    /// The content of the region when it last changed
+    last: Option<Frame>,
+}
+
+impl Watch {
+    /// Compare the region of a captured frame to its last content
+    ///
+    /// The first frame is the baseline, and never a change.
+    fn update(&mut self, frame: &Frame) -> Option<RegionChange> {
+        let (width, height) = frame.geometry();
+        let region = roi::clip(self.region, width, height);
+        let mut pixels = Vec::with_capacity(region.width as usize * region.height as usize);
+        for y in region.y..region.y + region.height {
+            let start = (y * width + region.x) as usize;
+            pixels.extend_from_slice(&frame.pixels()[start..start + region.width as usize]);
+        }
+        let mut current =
+            Frame::new(pixels, region.width, region.height).with_sequence(frame.sequence());
+        if let Some(timestamp) = frame.timestamp() {
+            current = current.with_timestamp(timestamp);
+        }
+        let changed_pixels = match &self.last {
+            None => {
+                self.last = Some(current);
+                return None;
+            }
+            // E.g. when the resolution changed
+            Some(last) if last.geometry() != current.geometry() => current.pixels().len(),
+            Some(last) => changed_pixels(last.pixels(), current.pixels(), self.threshold),
+        };
+        if changed_pixels == 0 {
+            return None;
+        }
+        self.last = Some(current.clone());
+        Some(RegionChange {
+            id: self.id,
+            region,
+            changed_pixels,
+            frame: current,
+        })
+    }
+}
+
+/// Number of pixels of which a component differs by more than `threshold`
+fn changed_pixels(old: &[Bgr8], new: &[Bgr8], threshold: u8) -> usize {
+    old.iter()
+        .zip(new)
+        .filter(|(a, b)| {
+            a.b.abs_diff(b.b) > threshold
+                || a.g.abs_diff(b.g) > threshold
+                || a.r.abs_diff(b.r) > threshold
+        })
+        .count()
+}
+
+/// The watches of a `RegionWatcher`, shared with its thread
+#[derive(Default)]
+struct Watches {
+    watches: Vec<Watch>,
+    next_id: usize,
+}
+
+/// A watcher of changes of regions of the screen, capturing at a low frame
+/// rate on a thread of its own
+///
+/// Useful for test automation waiting for the UI to change, e.g. a dialog to
+/// appear, without polling full frames. Each region is compared to its
+/// content when it last changed, so that slow changes are caught too. The
+/// thread is stopped on drop.
+///
+/// ```no_run
+/// # use captrs::watcher::RegionWatcher;
+/// # use captrs::{Capturer, Rect};
+/// let capturer = Capturer::new(0).unwrap();
+/// let watcher = RegionWatcher::new(capturer, 2.0).unwrap();
+/// let status = Rect { x: 0, y: 1040, width: 400, height: 40 };
+/// watcher.watch(status, 16);
+/// for change in watcher.iter() {
+///     println!("{} pixels of the status bar changed", change.changed_pixels);
+/// }
+/// ```
+pub struct RegionWatcher {
+    events: Receiver<RegionChange>,
+    watches: Arc<Mutex<Watches>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RegionWatcher {
+    /// Start capturing with `capturer` at `fps` frames per second, e.g. 2,
+    /// watching no regions yet
+    ///
+    /// Regions are in pixels of the frames of the capturer.
+    pub fn new(mut capturer: Capturer, fps: f64) -> Result<RegionWatcher, InitError> {
+        if !(fps > 0.0 && fps.is_finite()) {
+            return Err(InitError::InvalidArgument(format!(
+                "Invalid frame rate {}",
+                fps
+            )));
+        }
+        capturer.set_max_fps(Some(fps));
+        let interval = Duration::from_secs_f64(1.0 / fps);
+        let (tx, rx) = mpsc::channel();
+        let watches = Arc::new(Mutex::new(Watches::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (watches, stop) = (watches.clone(), stop.clone());
+            thread::Builder::new()
+                .name("captrs-region-watcher".to_string())
+                .spawn(move || watch_regions(capturer, interval, &watches, tx, &stop))
+                .map_err(|e| e.to_string())?
+        };
+        Ok(RegionWatcher {
+            events: rx,
+            watches,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Watch a region for changes of pixels by more than `threshold` in any
+    /// color component, reported as events. Returns the id of the watch.
+    pub fn watch(&self, region: Rect, threshold: u8) -> usize {
+        self.add(region, threshold, None)
+    }
+
+    /// Watch a region as `Self::watch`, calling `callback` on the watcher
+    /// thread with the changes instead of reporting them as events
+    pub fn watch_with<F>(&self, region: Rect, threshold: u8, callback: F) -> usize
+    where
+        F: FnMut(&RegionChange) + Send + 'static,
+    {
+        self.add(region, threshold, Some(Box::new(callback)))
+    }
+
+    fn add(&self, region: Rect, threshold: u8, callback: Option<ChangeCallback>) -> usize {
+        let mut watches = self.watches.lock().unwrap();
+        let id = watches.next_id;
+        watches.next_id += 1;
+        watches.watches.push(Watch {
+            id,
+            region,
+            threshold,
+            callback,
+            last: None,
+        });
+        id
+    }
+
+    /// Stop watching a region. Returns whether it was watched.
+    pub fn unwatch(&self, id: usize) -> bool {
+        let mut watches = self.watches.lock().unwrap();
+        let len = watches.watches.len();
+        watches.watches.retain(|w| w.id != id);
+        watches.watches.len() != len
+    }
+
+    /// The next change, if any has happened
+    pub fn try_recv(&self) -> Option<RegionChange> {
+        self.events.try_recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next change
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<RegionChange> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Wait for changes, forever
+    pub fn iter(&self) -> impl Iterator<Item = RegionChange> + '_ {
+        self.events.iter()
+    }
+}
+
+impl Drop for RegionWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch_regions(
+    mut capturer: Capturer,
+    interval: Duration,
+    watches: &Mutex<Watches>,
+    events: Sender<RegionChange>,
+    stop: &AtomicBool,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        let frame = match capturer.capture_frame_meta() {
+            Ok(frame) => frame,
+            Err(CaptureError::Timeout | CaptureError::NoNewFrame) => continue,
+            Err(_) => {
+                // Not paced by the capturer
+                thread::sleep(interval);
+                continue;
+            }
+        };
+        let mut watches = watches.lock().unwrap();
+        for watch in &mut watches.watches {
+            if let Some(change) = watch.update(&frame) {
+                match &mut watch.callback {
+                    Some(callback) => callback(&change),
+                    None => {
+                        let _ = events.send(change);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::cell::Cell;
+    use std::mem;
+    use std::ptr;
+    use std::time::Duration;
+    use winapi::shared::minwindef::{FALSE, LPARAM, LRESULT, UINT, WPARAM};
+    use winapi::shared::windef::HWND;
+    use winapi::um::winuser::{
+        CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW,
+        MsgWaitForMultipleObjects, PeekMessageW, RegisterClassW, TranslateMessage, PM_REMOVE,
+        QS_ALLINPUT, WM_DISPLAYCHANGE, WNDCLASSW,
+    };
+
+    thread_local! {
+        /// Whether `WM_DISPLAYCHANGE` was received since the last wait
+        static CHANGED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    unsafe extern "system" fn window_proc(
+        hwnd: HWND,
+        msg: UINT,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        if msg == WM_DISPLAYCHANGE {
+            CHANGED.with(|changed| changed.set(true));
+        }
+        DefWindowProcW(hwnd, msg, wparam, lparam)
+    }
+
+    /// A hidden top-level window, as `WM_DISPLAYCHANGE` is only sent to
+    /// top-level windows
+    pub struct Notifier {
+        hwnd: HWND,
+    }
+
+    impl Notifier {
+        pub fn new() -> Option<Notifier> {
+            let class: Vec<u16> = "captrs-display-watcher\0".encode_utf16().collect();
+            unsafe {
+                let mut wc: WNDCLASSW = mem::zeroed();
+                wc.lpfnWndProc = Some(window_proc);
+                wc.lpszClassName = class.as_ptr();
+                // Fails if already registered by another watcher, which is
+                // fine
+                RegisterClassW(&wc);
+                let hwnd = CreateWindowExW(
+                    0,
+                    class.as_ptr(),
+                    class.as_ptr(),
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                );
+                if hwnd.is_null() {
+                    None
+                } else {
+                    Some(Notifier { hwnd })
+                }
+            }
+        }
+
+        /// Wait up to `timeout` for `WM_DISPLAYCHANGE`. Returns whether it
+        /// was received.
+        pub fn wait(&mut self, timeout: Duration) -> bool {
+            unsafe {
+                let ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+                MsgWaitForMultipleObjects(0, ptr::null(), FALSE, ms, QS_ALLINPUT);
+                let mut msg = mem::zeroed();
+                while PeekMessageW(&mut msg, ptr::null_mut(), 0, 0, PM_REMOVE) != 0 {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+            CHANGED.with(|changed| changed.replace(false))
+        }
+    }
+
+    impl Drop for Notifier {
+        fn drop(&mut self) {
+            unsafe { DestroyWindow(self.hwnd) };
+        }
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "android", target_arch = "wasm32"))]
+mod sys {
+    use std::time::Duration;
+
+    /// Displays are compared periodically instead
+    pub enum Notifier {}
+
+    impl Notifier {
+        pub fn new() -> Option<Notifier> {
+            None
+        }
+
+        pub fn wait(&mut self, _timeout: Duration) -> bool {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+mod sys {
+    use std::os::raw::c_int;
+    use std::time::Duration;
+    use x11::{xlib, xrandr};
+
+    /// A connection to the X server, selecting RandR notifications
+    pub struct Notifier {
+        display: *mut xlib::Display,
+    }
+
+    impl Notifier {
+        pub fn new() -> Option<Notifier> {
+            unsafe {
+                let display = crate::x11::open_display();
+                if display.is_null() {
+                    return None;
+                }
+                let root = xlib::XDefaultRootWindow(display);
+                xrandr::XRRSelectInput(
+                    display,
+                    root,
+                    xrandr::RRScreenChangeNotifyMask
+                        | xrandr::RRCrtcChangeNotifyMask
+                        | xrandr::RROutputChangeNotifyMask,
+                );
+                xlib::XFlush(display);
+                Some(Notifier { display })
+            }
+        }
+
+        /// Wait up to `timeout` for RandR notifications. Returns whether any
+        /// was received.
+        pub fn wait(&mut self, timeout: Duration) -> bool {
+            unsafe {
+                if xlib::XPending(self.display) == 0 {
+                    let mut fd = libc::pollfd {
+                        fd: xlib::XConnectionNumber(self.display),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    };
+                    let ms = timeout.as_millis().min(c_int::MAX as u128) as c_int;
+                    libc::poll(&mut fd, 1, ms);
+                }
+                let mut changed = false;
+                while xlib::XPending(self.display) > 0 {
+                    let mut event = std::mem::zeroed();
+                    xlib::XNextEvent(self.display, &mut event);
+                    changed = true;
+                }
+                changed
+            }
+        }
+    }
+
+    impl Drop for Notifier {
+        fn drop(&mut self) {
+            unsafe { xlib::XCloseDisplay(self.display) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod watcher_tests {
+    use super::*;
+
+    fn display(name: &str, position: (i32, i32), primary: bool) -> DisplayInfo {
+        DisplayInfo {
+            index: 0,
+            name: name.to_string(),
+            monitor_name: None,
+            position,
+            size: (1920, 1080),
+            physical_size: None,
+            scale_factor: 1.0,
+            refresh_rate: None,
+            bit_depth: None,
+            primary,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = [
+            display("DP-1", (0, 0), true),
+            display("DP-2", (1920, 0), false),
+        ];
+        let new = [
+            display("DP-2", (0, 0), true),
+            display("HDMI-1", (1920, 0), false),
+        ];
+        assert_eq!(
+            diff(&old, &new),
+            [
+                DisplayEvent::DisplayRemoved(old[0].clone()),
+                DisplayEvent::GeometryChanged {
+                    old: old[1].clone(),
+                    new: new[0].clone()
+                },
+                DisplayEvent::DisplayAdded(new[1].clone()),
+                DisplayEvent::PrimaryChanged(new[0].clone()),
+            ]
+        );
+        assert_eq!(diff(&new, &new), []);
+    }
+
+    #[test]
+    fn test_region_changes() {
+        let mut watch = Watch {
+            id: 1,
+            region: Rect {
+                x: 2,
+                y: 0,
+                width: 4,
+                height: 1,
+            },
+            threshold: 8,
+            callback: None,
+            last: None,
+        };
+        // 4x2 frames, with the region clipped to the right half of the top
+        let frame = |pixels: [u8; 8]| {
+            let pixels = pixels.iter().map(|&v| Bgr8::new(v, v, v)).collect();
+            Frame::new(pixels, 4, 2)
+        };
+        assert_eq!(watch.update(&frame([0; 8])), None);
+        // Outside of the region, or within the threshold
+        assert_eq!(watch.update(&frame([50, 50, 8, 0, 50, 50, 50, 50])), None);
+        let change = watch
+            .update(&frame([0, 0, 9, 0, 0, 0, 0, 0]).with_sequence(3))
+            .unwrap();
+        assert_eq!(change.id, 1);
+        assert_eq!(change.changed_pixels, 1);
+        assert_eq!(change.region.width, 2);
+        assert_eq!(
+            change.frame.pixels(),
+            [Bgr8::new(9, 9, 9), Bgr8::new(0, 0, 0)]
+        );
+        assert_eq!(change.frame.sequence(), 3);
+        // Compared to the content when it last changed
+        assert_eq!(watch.update(&frame([0, 0, 9, 0, 0, 0, 0, 0])), None);
+    }
+}
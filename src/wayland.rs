@@ -0,0 +1,461 @@
+//! Capture on Wayland through the `org.freedesktop.portal.ScreenCast` portal
+//! and PipeWire
+//!
+//! The portal asks the user which monitor to share, and then provides a
+//! PipeWire stream of it. Frames are pulled from the stream with the
+//! GStreamer `pipewiresrc` element, which requires the PipeWire GStreamer
+//! plugin to be installed.
+//!
+//! With `CapturerBuilder::dmabuf`, frames are negotiated in DMA-BUFs when
+//! the compositor supports it, and the DMA-BUFs are exported with
+//! `Frame::dmabuf`.
+//!
+//! The timestamps of the PipeWire buffers, set by the compositor when it
+//! presents the frames, are reported as `Frame::present_time`.
+
+use crate::backend::Backend;
+use crate::dmabuf::{drm_fourcc, DmaBuf, DRM_FORMAT_MOD_LINEAR};
+use crate::{Bgr8, CaptureError, InitError};
+use gio::glib::{self, prelude::*, variant::ObjectPath, Variant, VariantDict};
+use gio::prelude::*;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_allocators::DmaBufMemory;
+use gstreamer_app::AppSink;
+use gstreamer_video::{VideoFormat, VideoInfo};
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::os::fd::{BorrowedFd, FromRawFd, OwnedFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREEN_CAST: &str = "org.freedesktop.portal.ScreenCast";
+
+/// `types` of sources to select, as a bitmask
+const SOURCE_TYPE_MONITOR: u32 = 1;
+const SOURCE_TYPE_WINDOW: u32 = 2;
+
+/// Counter making the handle tokens of requests unique within the process
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(0);
+
+/// Whether the current session is a Wayland session
+pub(crate) fn is_wayland_session() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some()
+        || std::env::var("XDG_SESSION_TYPE").is_ok_and(|t| t == "wayland")
+}
+
+/// A screen cast session with a running PipeWire stream
+pub(crate) struct Session {
+    connection: gio::DBusConnection,
+    session_handle: String,
+    pipeline: gst::Pipeline,
+    sink: AppSink,
+    /// The PipeWire remote. Must outlive the pipeline.
+    _remote: OwnedFd,
+    position: (i32, i32),
+    width: u32,
+    height: u32,
+    frame: Option<Vec<Bgr8>>,
+    /// The DMA-BUF of the last frame, if it was captured into one
+    dmabuf: Option<DmaBuf>,
+    /// Time at which the last frame was presented, if timestamped
+    present_time: Option<Instant>,
+    /// The last sample, kept until the next capture so that PipeWire does
+    /// not reuse its DMA-BUF in the meantime
+    last_sample: Option<gst::Sample>,
+}
+
+impl Session {
+    /// Start a screen cast of a monitor selected by the user
+    ///
+    /// If `capture_src` is greater than 0, the user may select several
+    /// monitors, and the one at that index is captured.
+    pub(crate) fn new(capture_src: usize) -> Result<Session, InitError> {
+        Session::start(capture_src, SOURCE_TYPE_MONITOR, false)
+    }
+
+    /// Start a screen cast of a monitor selected by the user, with frames in
+    /// DMA-BUFs if the compositor supports it
+    pub(crate) fn with_dmabuf(capture_src: usize) -> Result<Session, InitError> {
+        Session::start(capture_src, SOURCE_TYPE_MONITOR, true)
+    }
+
+    /// Start a screen cast of a window selected by the user
+    pub(crate) fn select_window() -> Result<Session, InitError> {
+        Session::start(0, SOURCE_TYPE_WINDOW, false)
+    }
+
+    /// Start a screen cast of the source at index `capture_src` of the
+    /// sources of `source_types` selected by the user
+    fn start(capture_src: usize, source_types: u32, dmabuf: bool) -> Result<Session, InitError> {
+        gst::init().map_err(|e| e.to_string())?;
+        let connection = gio::bus_get_sync(gio::BusType::Session, gio::Cancellable::NONE)
+            .map_err(|e| format!("Failed to connect to the session bus: {}", e))?;
+
+        let results = request(&connection, "CreateSession", |token| {
+            let options = options(token);
+            options.insert_value("session_handle_token", &token.to_variant());
+            vec![options.end()]
+        })?;
+        let session_handle: String = results
+            .lookup("session_handle")
+            .ok()
+            .flatten()
+            .ok_or("Portal returned no session handle")?;
+        let session_path = object_path(&session_handle)?;
+
+        request(&connection, "SelectSources", |token| {
+            let options = options(token);
+            options.insert_value("types", &source_types.to_variant());
+            options.insert_value("multiple", &(capture_src > 0).to_variant());
+            vec![session_path.to_variant(), options.end()]
+        })?;
+
+        let results = request(&connection, "Start", |token| {
+            vec![
+                session_path.to_variant(),
+                "".to_variant(),
+                options(token).end(),
+            ]
+        })?;
+        let streams = results
+            .lookup_value("streams", None)
+            .ok_or("Portal returned no streams")?;
+        if capture_src >= streams.n_children() {
+            return Err(InitError::NotFound(format!(
+                "Capture source {} was not selected, only {} sources were",
+                capture_src,
+                streams.n_children()
+            )));
+        }
+        let stream = streams.child_value(capture_src);
+        let node_id: u32 = stream.child_value(0).get().ok_or("Invalid stream")?;
+        let props = VariantDict::new(Some(&stream.child_value(1)));
+        let position = props.lookup("position").ok().flatten().unwrap_or((0, 0));
+        let (width, height): (i32, i32) = props.lookup("size").ok().flatten().unwrap_or((0, 0));
+
+        let remote = open_pipewire_remote(&connection, &session_path)?;
+        // `videoconvert` only handles frames in system memory, so with
+        // DMA-BUFs the frames must already be in a format we can read.
+        // DMA-BUFs negotiated without DRM modifiers are linear.
+        let convert = if dmabuf {
+            "video/x-raw(memory:DMABuf),format={BGRx,BGRA}; video/x-raw,format={BGRx,BGRA}"
+        } else {
+            "videoconvert ! video/x-raw,format=BGRx"
+        };
+        let description = format!(
+            "pipewiresrc fd={} path={} always-copy={} ! {} \
+             ! appsink name=sink max-buffers=1 drop=true sync=false",
+            std::os::fd::AsRawFd::as_raw_fd(&remote),
+            node_id,
+            !dmabuf,
+            convert
+        );
+        let pipeline = gst::parse::launch(&description)
+            .map_err(|e| e.to_string())?
+            .downcast::<gst::Pipeline>()
+            .map_err(|_| "Failed to create the capture pipeline")?;
+        let sink = pipeline
+            .by_name("sink")
+            .and_then(|sink| sink.downcast::<AppSink>().ok())
+            .ok_or("Failed to create the capture pipeline")?;
+        pipeline
+            .set_state(gst::State::Playing)
+            .map_err(|e| format!("Failed to start the capture pipeline: {}", e))?;
+        Ok(Session {
+            connection,
+            session_handle,
+            pipeline,
+            sink,
+            _remote: remote,
+            position,
+            width: width.max(0) as u32,
+            height: height.max(0) as u32,
+            frame: None,
+            dmabuf: None,
+            present_time: None,
+            last_sample: None,
+        })
+    }
+
+    /// The error to report when no sample could be pulled
+    fn pipeline_error(&self) -> CaptureError {
+        let bus = self.pipeline.bus().expect("Pipeline without bus");
+        if let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error]) {
+            if let gst::MessageView::Error(err) = msg.view() {
+                return CaptureError::Fail(err.error().to_string());
+            }
+        }
+        if self.sink.is_eos() {
+            // The user or compositor stopped the screen cast
+            CaptureError::AccessLost
+        } else {
+            CaptureError::Timeout
+        }
+    }
+
+    /// Export the DMA-BUF of a buffer, if it's in one
+    /// The instant of a running time of the pipeline, e.g. of the timestamp
+    /// of a buffer
+    fn running_instant(&self, running_time: gst::ClockTime) -> Option<Instant> {
+        let now = self.pipeline.clock()?.time()?.nseconds();
+        let time = self.pipeline.base_time()?.nseconds() + running_time.nseconds();
+        Instant::now().checked_sub(Duration::from_nanos(now.saturating_sub(time)))
+    }
+
+    fn export_dmabuf(buffer: &gst::BufferRef, info: &VideoInfo) -> Option<DmaBuf> {
+        if buffer.n_memory() != 1 {
+            return None;
+        }
+        let memory = buffer
+            .peek_memory(0)
+            .downcast_memory_ref::<DmaBufMemory>()?;
+        // The fd stays open as long as the sample, so it's valid to borrow
+        let fd = unsafe { BorrowedFd::borrow_raw(memory.fd()) }
+            .try_clone_to_owned()
+            .ok()?;
+        let fourcc = match info.format() {
+            VideoFormat::Bgrx => drm_fourcc(b"XR24"),
+            VideoFormat::Bgra => drm_fourcc(b"AR24"),
+            _ => return None,
+        };
+        Some(DmaBuf::new(
+            fd,
+            (info.width(), info.height()),
+            fourcc,
+            DRM_FORMAT_MOD_LINEAR,
+            info.offset()[0] + memory.offset(),
+            info.stride()[0] as usize,
+        ))
+    }
+}
+
+impl Backend for Session {
+    fn name(&self) -> &'static str {
+        "ScreenCast portal with PipeWire"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The position of the monitor in the compositor's logical layout, or
+    /// (0, 0) for windows
+    fn position(&self) -> (i32, i32) {
+        self.position
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_deref()
+            .map(|frame| (frame, self.width, self.height))
+    }
+
+    /// Wait up to `timeout` for a new frame, and store it
+    ///
+    /// PipeWire only produces frames when the monitor changes, so this times
+    /// out when the screen is still.
+    fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+        let timeout = gst::ClockTime::from_nseconds(timeout.as_nanos() as u64);
+        let sample = match self.sink.try_pull_sample(timeout) {
+            Some(sample) => sample,
+            None => return Err(self.pipeline_error()),
+        };
+        let info = sample
+            .caps()
+            .and_then(|caps| VideoInfo::from_caps(caps).ok())
+            .ok_or_else(|| CaptureError::Fail("Sample without video caps".to_string()))?;
+        self.dmabuf = sample
+            .buffer()
+            .and_then(|buffer| Session::export_dmabuf(buffer, &info));
+        self.present_time = sample
+            .buffer()
+            .and_then(|buffer| buffer.pts())
+            .and_then(|pts| self.running_instant(pts));
+        let buffer = sample
+            .buffer()
+            .and_then(|buffer| buffer.map_readable().ok())
+            .ok_or_else(|| CaptureError::Fail("Sample without readable buffer".to_string()))?;
+        let (width, height) = (info.width(), info.height());
+        let stride = info.stride()[0] as usize;
+        let row_len = 4 * width as usize;
+        let frame = self.frame.get_or_insert_with(Vec::new);
+        frame.clear();
+        frame.reserve(width as usize * height as usize);
+        for row in buffer.chunks(stride).take(height as usize) {
+            frame.extend(row[..row_len].chunks_exact(4).map(|p| Bgr8 {
+                b: p[0],
+                g: p[1],
+                r: p[2],
+                a: 255,
+            }));
+        }
+        self.width = width;
+        self.height = height;
+        drop(buffer);
+        self.last_sample = Some(sample);
+        Ok(())
+    }
+
+    fn present_time(&self) -> Option<Instant> {
+        self.present_time
+    }
+
+    /// Pauses the PipeWire stream, keeping the screen cast session, so that
+    /// the user is not asked again on resume
+    fn pause(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Paused);
+        self.last_sample = None;
+    }
+
+    fn resume(&mut self) -> Result<(), CaptureError> {
+        self.pipeline
+            .set_state(gst::State::Playing)
+            .map_err(|e| CaptureError::Fail(e.to_string()))?;
+        Ok(())
+    }
+
+    fn dmabuf(&self) -> Option<DmaBuf> {
+        self.dmabuf.clone()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+        let _ = self.connection.call_sync(
+            Some(PORTAL_NAME),
+            &self.session_handle,
+            "org.freedesktop.portal.Session",
+            "Close",
+            None,
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::Cancellable::NONE,
+        );
+    }
+}
+
+/// Options of a request with a handle token
+fn options(token: &str) -> VariantDict {
+    let options = VariantDict::new(None);
+    options.insert_value("handle_token", &token.to_variant());
+    options
+}
+
+fn object_path(path: &str) -> Result<ObjectPath, String> {
+    ObjectPath::try_from(path).map_err(|e| e.to_string())
+}
+
+/// Call a method of the screen cast portal that returns a request, and wait
+/// for the results of the request
+///
+/// `args` gets the handle token of the request and returns the arguments of
+/// the call.
+fn request(
+    connection: &gio::DBusConnection,
+    method: &str,
+    args: impl FnOnce(&str) -> Vec<Variant>,
+) -> Result<VariantDict, InitError> {
+    let token = format!(
+        "captrs{}_{}",
+        std::process::id(),
+        NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+    );
+    let sender = connection
+        .unique_name()
+        .ok_or("Connection has no unique name")?;
+    let sender = sender.trim_start_matches(':').replace('.', "_");
+    let request_path = format!("{}/request/{}/{}", PORTAL_PATH, sender, token);
+
+    let context = glib::MainContext::new();
+    context
+        .with_thread_default(|| {
+            // Subscribe before the call, so the response can't be missed
+            let response = Rc::new(RefCell::new(None));
+            let subscription = {
+                let response = response.clone();
+                connection.signal_subscribe(
+                    Some(PORTAL_NAME),
+                    Some("org.freedesktop.portal.Request"),
+                    Some("Response"),
+                    Some(&request_path),
+                    None,
+                    gio::DBusSignalFlags::NONE,
+                    move |_, _, _, _, _, params| {
+                        *response.borrow_mut() = Some(params.clone());
+                    },
+                )
+            };
+            let res = connection
+                .call_sync(
+                    Some(PORTAL_NAME),
+                    PORTAL_PATH,
+                    SCREEN_CAST,
+                    method,
+                    Some(&Variant::tuple_from_iter(args(&token))),
+                    None,
+                    gio::DBusCallFlags::NONE,
+                    -1,
+                    gio::Cancellable::NONE,
+                )
+                .map_err(|e| format!("{} failed: {}", method, e));
+            if res.is_ok() {
+                while response.borrow().is_none() {
+                    context.iteration(true);
+                }
+            }
+            connection.signal_unsubscribe(subscription);
+            res?;
+            let params = response.take().unwrap();
+            match params.child_value(0).get::<u32>() {
+                Some(0) => Ok(VariantDict::new(Some(&params.child_value(1)))),
+                Some(1) => Err(InitError::PermissionDenied(format!(
+                    "{} was cancelled by the user",
+                    method
+                ))),
+                _ => Err(InitError::Other(format!("{} failed", method))),
+            }
+        })
+        .map_err(|e| InitError::Other(e.to_string()))?
+}
+
+/// Open the PipeWire remote of a started session
+fn open_pipewire_remote(
+    connection: &gio::DBusConnection,
+    session_path: &ObjectPath,
+) -> Result<OwnedFd, String> {
+    let args = Variant::tuple_from_iter([session_path.to_variant(), VariantDict::new(None).end()]);
+    let (reply, fds) = connection
+        .call_with_unix_fd_list_sync(
+            Some(PORTAL_NAME),
+            PORTAL_PATH,
+            SCREEN_CAST,
+            "OpenPipeWireRemote",
+            Some(&args),
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::UnixFDList::NONE,
+            gio::Cancellable::NONE,
+        )
+        .map_err(|e| format!("OpenPipeWireRemote failed: {}", e))?;
+    let index = reply
+        .child_value(0)
+        .get::<glib::variant::Handle>()
+        .ok_or("Invalid PipeWire remote handle")?;
+    let fd = fds
+        .ok_or("Portal returned no PipeWire remote")?
+        .get(index.0)
+        .map_err(|e| e.to_string())?;
+    // The fd is owned by the list, so keep a duplicate
+    let fd = unsafe { libc::dup(fd) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
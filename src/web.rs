@@ -0,0 +1,264 @@
+//! Capture in browsers with `getDisplayMedia`, on `wasm32-unknown-unknown`
+//! with the `web` feature
+//!
+//! The browser asks the user what to share, e.g. a screen, window or tab. The
+//! shared stream plays in a detached `<video>` element, and frames are read
+//! back by drawing it to a canvas. As the browser can't be blocked on, a
+//! `WebCapturer` is used instead of a `Capturer`, with the frame API of
+//! `crate::async_capture::AsyncCapturer`.
+//!
+//! ```no_run
+//! use captrs::web::WebCapturer;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! // In response to a user gesture, e.g. a click
+//! let capturer = WebCapturer::new().await?;
+//! let frame = capturer.capture_frame_async().await?;
+//! println!("{}x{}", frame.width(), frame.height());
+//! let frames = capturer.frames(); // impl Stream<Item = Result<Frame, CaptureError>>
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Bgr8, CaptureError, Frame, InitError};
+use futures_core::Stream;
+use js_sys::{Object, Promise, Reflect};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    CanvasRenderingContext2d, DisplayMediaStreamConstraints, Document, HtmlCanvasElement,
+    HtmlVideoElement, MediaStream, MediaStreamTrack, MediaStreamTrackState,
+};
+
+/// A string property of a JavaScript error, e.g. its `name`
+fn property(e: &JsValue, key: &str) -> Option<String> {
+    Reflect::get(e, &JsValue::from_str(key)).ok()?.as_string()
+}
+
+fn message(e: &JsValue) -> String {
+    property(e, "message")
+        .or_else(|| e.as_string())
+        .unwrap_or_else(|| format!("{:?}", e))
+}
+
+fn init_error(e: JsValue) -> InitError {
+    match property(&e, "name").as_deref() {
+        // Also when not called in response to a user gesture
+        Some("NotAllowedError") => InitError::PermissionDenied(message(&e)),
+        Some("NotFoundError") | Some("NotSupportedError") => InitError::Unsupported(message(&e)),
+        _ => InitError::Other(message(&e)),
+    }
+}
+
+fn capture_error(e: JsValue) -> CaptureError {
+    match property(&e, "name").as_deref() {
+        // Reading back cross-origin content is not allowed
+        Some("SecurityError") => CaptureError::PermissionDenied,
+        _ => CaptureError::Fail(message(&e)),
+    }
+}
+
+/// Create an element of the document, e.g. a `<video>`
+fn create<T: JsCast>(document: &Document, name: &str) -> Result<T, InitError> {
+    document
+        .create_element(name)
+        .map_err(init_error)?
+        .dyn_into()
+        .map_err(|_| InitError::Unsupported(format!("Failed to create a <{}> element", name)))
+}
+
+/// A promise resolved before the browser renders its next frame
+fn animation_frame() -> Promise {
+    Promise::new(&mut |resolve, reject| {
+        let requested = web_sys::window().map(|w| w.request_animation_frame(&resolve));
+        if !matches!(requested, Some(Ok(_))) {
+            let _ = reject.call1(&JsValue::UNDEFINED, &"No animation frames".into());
+        }
+    })
+}
+
+/// The shared stream, and the elements it's read back through
+struct Shared {
+    stream: MediaStream,
+    video: HtmlVideoElement,
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+}
+
+impl Shared {
+    /// Whether the user stopped sharing
+    fn ended(&self) -> bool {
+        let track = self.stream.get_video_tracks().get(0);
+        track
+            .dyn_ref::<MediaStreamTrack>()
+            .is_none_or(|track| track.ready_state() == MediaStreamTrackState::Ended)
+    }
+
+    fn capture_frame(&self) -> Result<Frame, CaptureError> {
+        if self.ended() {
+            return Err(CaptureError::AccessLost);
+        }
+        let (width, height) = (self.video.video_width(), self.video.video_height());
+        if width == 0 || height == 0 {
+            return Err(CaptureError::Timeout);
+        }
+        if (self.canvas.width(), self.canvas.height()) != (width, height) {
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
+        }
+        self.context
+            .draw_image_with_html_video_element(&self.video, 0.0, 0.0)
+            .map_err(capture_error)?;
+        let data = self
+            .context
+            .get_image_data(0.0, 0.0, width as f64, height as f64)
+            .map_err(capture_error)?
+            .data();
+        let mut pixels = vec![Bgr8::default(); width as usize * height as usize];
+        crate::convert::from_rgba(&data, &mut pixels);
+        Ok(Frame::new(pixels, width, height))
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.video.set_src_object(None);
+        for track in self.stream.get_tracks().iter() {
+            if let Some(track) = track.dyn_ref::<MediaStreamTrack>() {
+                track.stop();
+            }
+        }
+    }
+}
+
+/// A capturer of a screen, window or tab shared by the user
+///
+/// Sharing stops when the `WebCapturer` and all its `FrameStream`s are
+/// dropped.
+pub struct WebCapturer {
+    shared: Rc<Shared>,
+}
+
+impl WebCapturer {
+    /// Ask the user what to share, and start capturing it once shared
+    ///
+    /// Must be called in response to a user gesture, e.g. a click. Fails with
+    /// `InitError::PermissionDenied` if the user declines, or if there was no
+    /// gesture.
+    pub async fn new() -> Result<WebCapturer, InitError> {
+        let window = web_sys::window()
+            .ok_or_else(|| InitError::Unsupported("Not running in a browser window".to_string()))?;
+        let document = window
+            .document()
+            .ok_or_else(|| InitError::Unsupported("The window has no document".to_string()))?;
+        let devices = window.navigator().media_devices().map_err(init_error)?;
+        let constraints = DisplayMediaStreamConstraints::new();
+        constraints.set_video_bool(true);
+        constraints.set_audio_bool(false);
+        let shared = devices
+            .get_display_media_with_constraints(&constraints)
+            .map_err(init_error)?;
+        let stream: MediaStream = JsFuture::from(shared)
+            .await
+            .map_err(init_error)?
+            .unchecked_into();
+        let video: HtmlVideoElement = create(&document, "video")?;
+        video.set_muted(true);
+        video.set_src_object(Some(&stream));
+        let canvas: HtmlCanvasElement = create(&document, "canvas")?;
+        // Keeps the canvas in memory rather than on the GPU, for readback
+        let options = Object::new();
+        Reflect::set(&options, &"willReadFrequently".into(), &JsValue::TRUE).map_err(init_error)?;
+        let context = canvas
+            .get_context_with_context_options("2d", &options)
+            .map_err(init_error)?
+            .and_then(|context| context.dyn_into().ok())
+            .ok_or_else(|| InitError::Unsupported("2D canvases are not supported".to_string()))?;
+        let shared = Rc::new(Shared {
+            stream,
+            video,
+            canvas,
+            context,
+        });
+        // Resolved once the first frame is shown, and its size known
+        let playing = shared.video.play().map_err(init_error)?;
+        JsFuture::from(playing).await.map_err(init_error)?;
+        Ok(WebCapturer { shared })
+    }
+
+    /// The width and height of the shared stream
+    pub fn geometry(&self) -> (u32, u32) {
+        (
+            self.shared.video.video_width(),
+            self.shared.video.video_height(),
+        )
+    }
+
+    /// Read back the current frame of the shared stream
+    ///
+    /// Fails with `CaptureError::AccessLost` once the user stopped sharing,
+    /// and with `CaptureError::PermissionDenied` if the browser doesn't allow
+    /// reading it back.
+    pub fn capture_frame(&self) -> Result<Frame, CaptureError> {
+        self.shared.capture_frame()
+    }
+
+    /// Wait until the browser renders its next frame, and capture the shared
+    /// stream as `Self::capture_frame`
+    ///
+    /// Browsers pause rendering in background tabs, and so this too.
+    pub fn capture_frame_async(&self) -> impl Future<Output = Result<Frame, CaptureError>> {
+        let shared = self.shared.clone();
+        async move {
+            JsFuture::from(animation_frame())
+                .await
+                .map_err(capture_error)?;
+            shared.capture_frame()
+        }
+    }
+
+    /// A stream of frames, captured as they are polled, at most one per
+    /// frame of the browser
+    pub fn frames(&self) -> FrameStream {
+        FrameStream {
+            shared: self.shared.clone(),
+            pending: None,
+        }
+    }
+}
+
+/// A stream of captured frames, from `WebCapturer::frames`
+///
+/// Capture errors are yielded as items. The stream ends when the user stops
+/// sharing.
+pub struct FrameStream {
+    shared: Rc<Shared>,
+    pending: Option<JsFuture>,
+}
+
+impl Stream for FrameStream {
+    type Item = Result<Frame, CaptureError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let pending = this
+            .pending
+            .get_or_insert_with(|| JsFuture::from(animation_frame()));
+        let res = match Pin::new(pending).poll(cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.pending = None;
+        match res
+            .map_err(capture_error)
+            .and_then(|_| this.shared.capture_frame())
+        {
+            Err(CaptureError::AccessLost) => Poll::Ready(None),
+            res => Poll::Ready(Some(res)),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! A virtual camera of the platform, showing the captured screen as a webcam
+//! in browsers and conferencing apps
+//!
+//! On Linux, this is the first [v4l2loopback](https://github.com/umlaeute/v4l2loopback)
+//! device, see `V4l2Sink`. On Windows, it's a softcam camera, see
+//! `SoftcamSink`.
+//!
+//! ```no_run
+//! # use captrs::Capturer;
+//! # use captrs::webcam::VirtualCamera;
+//! let mut capturer = Capturer::new(0).unwrap();
+//! let (w, h) = capturer.geometry();
+//! let camera = VirtualCamera::open(w, h, 30).unwrap();
+//! camera.run(&mut capturer, 30).unwrap();
+//! ```
+
+#[cfg(target_os = "linux")]
+use crate::v4l2::V4l2Sink;
+#[cfg(windows)]
+use crate::wincam::SoftcamSink;
+use crate::{Bgr8, CaptureError, Capturer};
+use std::io;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Name of the softcam DLL, loaded from the DLL search path
+#[cfg(windows)]
+const SOFTCAM_DLL: &str = "softcam.dll";
+
+/// The v4l2loopback devices, in order of their device nodes
+///
+/// v4l2loopback devices are virtual, and have a `max_openers` attribute no
+/// other V4L2 driver has.
+#[cfg(target_os = "linux")]
+pub fn loopback_devices() -> Vec<PathBuf> {
+    let mut devices: Vec<PathBuf> = std::fs::read_dir("/sys/devices/virtual/video4linux")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().join("max_openers").exists())
+        .map(|entry| PathBuf::from("/dev").join(entry.file_name()))
+        .collect();
+    devices.sort_by_key(|path| {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        name.trim_start_matches("video")
+            .parse::<u32>()
+            .unwrap_or(u32::MAX)
+    });
+    devices
+}
+
+/// A virtual camera that frames of a fixed size can be written to
+pub struct VirtualCamera {
+    #[cfg(target_os = "linux")]
+    sink: V4l2Sink,
+    #[cfg(windows)]
+    sink: SoftcamSink,
+}
+
+impl VirtualCamera {
+    /// Open the virtual camera for frames of the given size and frame rate
+    #[cfg(target_os = "linux")]
+    pub fn open(width: u32, height: u32, _fps: u32) -> io::Result<VirtualCamera> {
+        let device = loopback_devices().into_iter().next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "No v4l2loopback device. Is the v4l2loopback module loaded?",
+            )
+        })?;
+        let sink = V4l2Sink::open(device, width, height)?;
+        Ok(VirtualCamera { sink })
+    }
+
+    /// Open the virtual camera for frames of the given size and frame rate
+    #[cfg(windows)]
+    pub fn open(width: u32, height: u32, fps: u32) -> io::Result<VirtualCamera> {
+        let sink = SoftcamSink::open(SOFTCAM_DLL, width, height, fps as f32)?;
+        Ok(VirtualCamera { sink })
+    }
+
+    /// Returns the width and height of the frames the camera is configured for
+    pub fn geometry(&self) -> (u32, u32) {
+        self.sink.geometry()
+    }
+
+    /// Write a frame to the camera
+    ///
+    /// The frame must be of the size the camera was opened with.
+    pub fn write_frame(&mut self, frame: &[Bgr8]) -> io::Result<()> {
+        self.sink.write_frame(frame)
+    }
+
+    /// Capture and write frames at `fps` frames per second, forever
+    ///
+    /// Timeouts are skipped. Returns if any other error occurs, e.g. when the
+    /// captured frames change size.
+    pub fn run(mut self, capturer: &mut Capturer, fps: u32) -> io::Result<()> {
+        assert!(fps > 0, "Frame rate must be positive");
+        let frame_duration = Duration::from_secs(1) / fps;
+        let mut next_frame = Instant::now();
+        loop {
+            match capturer.capture_store_frame() {
+                Ok(()) => self.write_frame(capturer.get_stored_frame().unwrap())?,
+                Err(CaptureError::Timeout | CaptureError::NoNewFrame) => (),
+                Err(e) => return Err(io::Error::other(e)),
+            }
+            next_frame += frame_duration;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                next_frame = now;
+            }
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Capture with the WinRT `Windows.Graphics.Capture` API
+//!
+//! An alternative to DXGI desktop duplication, which works in some cases
+//! where duplication fails, e.g. on some hybrid-GPU laptops. Requires
+//! Windows 10 1803 or later.
+//!
+//! Also captures single windows, by `HWND`.
+
+use crate::backend::Backend;
+use crate::{Bgr8, CaptureError, InitError, Rect, Visibility, WindowId};
+use std::sync::mpsc;
+use std::time::Duration;
+use windows::core::{factory, Interface};
+use windows::Foundation::TypedEventHandler;
+use windows::Graphics::Capture::{
+    Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureItem, GraphicsCaptureSession,
+};
+use windows::Graphics::DirectX::Direct3D11::IDirect3DDevice;
+use windows::Graphics::DirectX::DirectXPixelFormat;
+use windows::Graphics::SizeInt32;
+use windows::Win32::Foundation::{HWND, POINT, RECT};
+use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_HARDWARE;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BOX,
+    D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_MAPPED_SUBRESOURCE,
+    D3D11_MAP_READ, D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+};
+use windows::Win32::Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED};
+use windows::Win32::Graphics::Dxgi::IDXGIDevice;
+use windows::Win32::Graphics::Gdi::{
+    CombineRgn, CreateRectRgn, DeleteObject, MonitorFromPoint, MONITOR_DEFAULTTONULL, NULLREGION,
+    RGN_DIFF,
+};
+use windows::Win32::System::WinRT::Direct3D11::{
+    CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess,
+};
+use windows::Win32::System::WinRT::Graphics::Capture::IGraphicsCaptureItemInterop;
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetWindow, GetWindowRect, IsIconic, IsWindow, IsWindowVisible, GW_HWNDPREV,
+};
+
+const PIXEL_FORMAT: DirectXPixelFormat = DirectXPixelFormat::B8G8R8A8UIntNormalized;
+
+/// Number of frames in the frame pool
+const BUFFERS: i32 = 2;
+
+/// Backend capturing a monitor or window with `Windows.Graphics.Capture`
+pub(crate) struct WgcBackend {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    winrt_device: IDirect3DDevice,
+    frame_pool: Direct3D11CaptureFramePool,
+    session: GraphicsCaptureSession,
+    /// Signalled by the frame pool when a frame arrives
+    frame_arrived: mpsc::Receiver<()>,
+    /// Texture the frames are copied to, to be read by the CPU
+    staging: Option<ID3D11Texture2D>,
+    /// The captured window, if not a monitor
+    window: Option<HWND>,
+    /// Visibility of the window at the last capture
+    visibility: Option<Visibility>,
+    /// The region of the monitor or window to copy out of the frames
+    region: Option<Rect>,
+    position: (i32, i32),
+    size: SizeInt32,
+    frame: Option<Vec<Bgr8>>,
+    /// The dirty regions of `frame`, if supported
+    dirty: Option<Vec<Rect>>,
+    width: u32,
+    height: u32,
+}
+
+/// Add the dirty regions of a frame, or set `dirty` to `None` if they are
+/// unsupported, i.e. before Windows 11 24H2
+fn add_dirty_regions(frame: &Direct3D11CaptureFrame, dirty: &mut Option<Vec<Rect>>) {
+    match (frame.DirtyRegions(), dirty.as_mut()) {
+        (Ok(regions), Some(dirty)) => dirty.extend(regions.into_iter().map(|r| Rect {
+            x: r.X.max(0) as u32,
+            y: r.Y.max(0) as u32,
+            width: r.Width.max(0) as u32,
+            height: r.Height.max(0) as u32,
+        })),
+        _ => *dirty = None,
+    }
+}
+
+/// Whether a window is cloaked by DWM, e.g. when on another virtual desktop
+unsafe fn cloaked(hwnd: HWND) -> bool {
+    let mut cloaked = 0u32;
+    DwmGetWindowAttribute(
+        hwnd,
+        DWMWA_CLOAKED,
+        &mut cloaked as *mut u32 as *mut _,
+        std::mem::size_of::<u32>() as u32,
+    )
+    .is_ok()
+        && cloaked != 0
+}
+
+/// How much of a window is shown on the desktop, i.e. not minimized, cloaked,
+/// or covered by the visible windows above it in the Z order
+fn window_visibility(hwnd: HWND) -> Visibility {
+    unsafe {
+        if !IsWindowVisible(hwnd).as_bool() || IsIconic(hwnd).as_bool() {
+            return Visibility::Minimized;
+        }
+        if cloaked(hwnd) {
+            return Visibility::Occluded;
+        }
+        let mut rect = RECT::default();
+        if GetWindowRect(hwnd, &mut rect).is_err() {
+            return Visibility::Visible;
+        }
+        let visible = CreateRectRgn(rect.left, rect.top, rect.right, rect.bottom);
+        let mut covered = false;
+        let mut above = GetWindow(hwnd, GW_HWNDPREV);
+        while let Ok(other) = above {
+            let mut other_rect = RECT::default();
+            if IsWindowVisible(other).as_bool()
+                && !IsIconic(other).as_bool()
+                && !cloaked(other)
+                && GetWindowRect(other, &mut other_rect).is_ok()
+                && other_rect.left < rect.right
+                && rect.left < other_rect.right
+                && other_rect.top < rect.bottom
+                && rect.top < other_rect.bottom
+            {
+                covered = true;
+                let region = CreateRectRgn(
+                    other_rect.left,
+                    other_rect.top,
+                    other_rect.right,
+                    other_rect.bottom,
+                );
+                let remaining = CombineRgn(visible, visible, region, RGN_DIFF);
+                let _ = DeleteObject(region);
+                if remaining == NULLREGION {
+                    let _ = DeleteObject(visible);
+                    return Visibility::Occluded;
+                }
+            }
+            above = GetWindow(other, GW_HWNDPREV);
+        }
+        let _ = DeleteObject(visible);
+        if covered {
+            Visibility::PartiallyOccluded
+        } else {
+            Visibility::Visible
+        }
+    }
+}
+
+impl WgcBackend {
+    /// Start capturing the output at index `capture_src`, in the same order as
+    /// DXGI desktop duplication
+    pub(crate) fn new(capture_src: usize) -> Result<WgcBackend, InitError> {
+        let output = crate::dxgi::capture_source_output(capture_src)
+            .ok_or_else(|| InitError::NotFound(format!("No output at index {}", capture_src)))?;
+        let position = (output.rect.left, output.rect.top);
+        let monitor = unsafe {
+            MonitorFromPoint(
+                POINT {
+                    x: position.0,
+                    y: position.1,
+                },
+                MONITOR_DEFAULTTONULL,
+            )
+        };
+        let item = unsafe {
+            factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()
+                .and_then(|interop| interop.CreateForMonitor(monitor))
+        };
+        item.and_then(|item| WgcBackend::start(item, None, position))
+            .map_err(InitError::from)
+    }
+
+    /// Start capturing a window
+    pub(crate) fn new_window(window: WindowId) -> Result<WgcBackend, InitError> {
+        let hwnd = HWND(window.0 as *mut _);
+        if !unsafe { IsWindow(hwnd) }.as_bool() {
+            return Err(InitError::NotFound(format!(
+                "No window with handle {:#x}",
+                window.0
+            )));
+        }
+        let item = unsafe {
+            factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()
+                .and_then(|interop| interop.CreateForWindow(hwnd))
+        };
+        item.and_then(|item| WgcBackend::start(item, Some(hwnd), (0, 0)))
+            .map_err(InitError::from)
+    }
+
+    fn start(
+        item: GraphicsCaptureItem,
+        window: Option<HWND>,
+        position: (i32, i32),
+    ) -> windows::core::Result<WgcBackend> {
+        let mut device = None;
+        let mut context = None;
+        unsafe {
+            D3D11CreateDevice(
+                None,
+                D3D_DRIVER_TYPE_HARDWARE,
+                None,
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                None,
+                D3D11_SDK_VERSION,
+                Some(&mut device),
+                None,
+                Some(&mut context),
+            )?;
+        }
+        let (device, context): (ID3D11Device, ID3D11DeviceContext) =
+            (device.unwrap(), context.unwrap());
+        let winrt_device: IDirect3DDevice =
+            unsafe { CreateDirect3D11DeviceFromDXGIDevice(&device.cast::<IDXGIDevice>()?)? }
+                .cast()?;
+        let size = item.Size()?;
+        let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
+            &winrt_device,
+            PIXEL_FORMAT,
+            BUFFERS,
+            size,
+        )?;
+        let (sender, frame_arrived) = mpsc::channel();
+        frame_pool.FrameArrived(&TypedEventHandler::new(move |_, _| {
+            let _ = sender.send(());
+            Ok(())
+        }))?;
+        let session = frame_pool.CreateCaptureSession(&item)?;
+        // The cursor is drawn by the capturer, if included. Only supported
+        // since Windows 10 2004.
+        let _ = session.SetIsCursorCaptureEnabled(false);
+        session.StartCapture()?;
+        Ok(WgcBackend {
+            device,
+            context,
+            winrt_device,
+            frame_pool,
+            session,
+            frame_arrived,
+            staging: None,
+            window,
+            visibility: None,
+            region: None,
+            position,
+            size,
+            frame: None,
+            dirty: None,
+            width: size.Width as u32,
+            height: size.Height as u32,
+        })
+    }
+
+    /// A staging texture matching the description of a frame texture
+    fn staging(&mut self, desc: &D3D11_TEXTURE2D_DESC) -> windows::core::Result<ID3D11Texture2D> {
+        if let Some(staging) = &self.staging {
+            let mut staging_desc = D3D11_TEXTURE2D_DESC::default();
+            unsafe { staging.GetDesc(&mut staging_desc) };
+            if (staging_desc.Width, staging_desc.Height) == (desc.Width, desc.Height) {
+                return Ok(staging.clone());
+            }
+        }
+        let staging_desc = D3D11_TEXTURE2D_DESC {
+            Usage: D3D11_USAGE_STAGING,
+            BindFlags: 0,
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+            MiscFlags: 0,
+            ..*desc
+        };
+        let mut staging = None;
+        unsafe {
+            self.device
+                .CreateTexture2D(&staging_desc, None, Some(&mut staging))?
+        };
+        self.staging = staging;
+        Ok(self.staging.clone().unwrap())
+    }
+
+    /// Copy the latest frame of the frame pool into `self.frame`
+    fn read_frame(&mut self) -> windows::core::Result<()> {
+        let mut frame = self.frame_pool.TryGetNextFrame()?;
+        // The dirty regions of the skipped frames are dirty as well
+        let mut dirty = Some(Vec::new());
+        add_dirty_regions(&frame, &mut dirty);
+        while let Ok(next) = self.frame_pool.TryGetNextFrame() {
+            frame.Close()?;
+            frame = next;
+            add_dirty_regions(&frame, &mut dirty);
+        }
+        let content_size = frame.ContentSize()?;
+        let texture: ID3D11Texture2D = unsafe {
+            frame
+                .Surface()?
+                .cast::<IDirect3DDxgiInterfaceAccess>()?
+                .GetInterface()?
+        };
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+        // The frame may be smaller than the buffer while the pool is resized
+        let region = crate::roi::clip(
+            self.region.unwrap_or(Rect {
+                x: 0,
+                y: 0,
+                width: desc.Width,
+                height: desc.Height,
+            }),
+            (content_size.Width as u32).min(desc.Width),
+            (content_size.Height as u32).min(desc.Height),
+        );
+        let (width, height) = (region.width, region.height);
+        let staging = self.staging(&D3D11_TEXTURE2D_DESC {
+            Width: width,
+            Height: height,
+            ..desc
+        })?;
+        let src_box = D3D11_BOX {
+            left: region.x,
+            top: region.y,
+            front: 0,
+            right: region.x + width,
+            bottom: region.y + height,
+            back: 1,
+        };
+        let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+        unsafe {
+            self.context
+                .CopySubresourceRegion(&staging, 0, 0, 0, 0, &texture, 0, Some(&src_box));
+            self.context
+                .Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+        }
+        let pixels = self.frame.get_or_insert_with(Vec::new);
+        pixels.clear();
+        pixels.reserve(width as usize * height as usize);
+        for y in 0..height as usize {
+            // `Bgr8` has the same layout as B8G8R8A8
+            let row = unsafe {
+                std::slice::from_raw_parts(
+                    (mapped.pData as *const u8).add(y * mapped.RowPitch as usize) as *const Bgr8,
+                    width as usize,
+                )
+            };
+            pixels.extend_from_slice(row);
+        }
+        unsafe { self.context.Unmap(&staging, 0) };
+        let first = self.dirty.is_none() || (self.width, self.height) != (width, height);
+        self.dirty = dirty.map(|dirty| {
+            if first {
+                // Nothing of the same size was captured before
+                vec![Rect {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                }]
+            } else {
+                dirty
+                    .into_iter()
+                    .filter_map(|rect| crate::intersection(rect, region))
+                    .map(|rect| Rect {
+                        x: rect.x - region.x,
+                        y: rect.y - region.y,
+                        ..rect
+                    })
+                    .collect()
+            }
+        });
+        self.width = width;
+        self.height = height;
+        frame.Close()?;
+        if (content_size.Width, content_size.Height) != (self.size.Width, self.size.Height) {
+            // The monitor or window changed size
+            self.size = content_size;
+            self.frame_pool
+                .Recreate(&self.winrt_device, PIXEL_FORMAT, BUFFERS, content_size)?;
+        }
+        Ok(())
+    }
+}
+
+// The frame pool is free-threaded, the WinRT objects are agile, and the
+// immediate context is only ever used by the thread owning the backend
+unsafe impl Send for WgcBackend {}
+
+impl Backend for WgcBackend {
+    fn name(&self) -> &'static str {
+        "Windows.Graphics.Capture"
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The position of the monitor, or of the window including its frame
+    fn position(&self) -> (i32, i32) {
+        match self.window {
+            Some(hwnd) => {
+                let mut rect = RECT::default();
+                let _ = unsafe { GetWindowRect(hwnd, &mut rect) };
+                (rect.left, rect.top)
+            }
+            None => self.position,
+        }
+    }
+
+    /// Wait up to `timeout` for a new frame, and store it
+    ///
+    /// Frames only arrive when the monitor changes, so this times out when
+    /// the screen is still.
+    fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+        self.visibility = self.window.map(window_visibility);
+        match self.frame_arrived.recv_timeout(timeout) {
+            Ok(()) => {
+                while self.frame_arrived.try_recv().is_ok() {}
+                self.read_frame().map_err(|e| {
+                    // The next frame is entirely dirty
+                    self.dirty = None;
+                    CaptureError::from(e)
+                })
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => match self.window {
+                // Frames stop arriving when the window is closed
+                Some(hwnd) if !unsafe { IsWindow(hwnd) }.as_bool() => Err(CaptureError::AccessLost),
+                _ => Err(CaptureError::Timeout),
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(CaptureError::AccessLost),
+        }
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_deref()
+            .map(|frame| (frame, self.width, self.height))
+    }
+
+    fn dirty_rects(&self) -> Option<&[Rect]> {
+        self.dirty.as_deref()
+    }
+
+    fn visibility(&self) -> Option<Visibility> {
+        self.visibility
+    }
+
+    /// Copies only the region out of the frame textures, with
+    /// `CopySubresourceRegion`
+    fn set_region(&mut self, region: Option<Rect>) -> bool {
+        self.region = region;
+        self.dirty = None;
+        (self.width, self.height) = match region {
+            Some(region) => (region.width, region.height),
+            None => (self.size.Width as u32, self.size.Height as u32),
+        };
+        true
+    }
+}
+
+impl Drop for WgcBackend {
+    fn drop(&mut self) {
+        let _ = self.session.Close();
+        let _ = self.frame_pool.Close();
+    }
+}
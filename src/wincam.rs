@@ -0,0 +1,110 @@
+//! Sink that publishes captured frames as a virtual camera on Windows, so the
+//! captured screen can be selected as a camera in Teams, Zoom, OBS, etc.
+//!
+//! The camera itself is provided by a [softcam](https://github.com/tshino/softcam)
+//! DirectShow filter, which must be installed (registered with `regsvr32`)
+//! separately. Its DLL is loaded at runtime, so nothing needs to be linked at
+//! build time.
+//!
+//! A native Media Foundation virtual camera (`MFCreateVirtualCamera`, Windows 11)
+//! also requires a registered COM media source, and is not provided here.
+
+use crate::dylib::Library;
+use crate::Bgr8;
+use std::ffi::c_void;
+use std::io;
+use std::path::Path;
+
+type ScCamera = *mut c_void;
+type ScCreateCamera = unsafe extern "C" fn(width: i32, height: i32, framerate: f32) -> ScCamera;
+type ScDeleteCamera = unsafe extern "C" fn(camera: ScCamera);
+type ScSendFrame = unsafe extern "C" fn(camera: ScCamera, image_bits: *const c_void);
+type ScWaitForConnection = unsafe extern "C" fn(camera: ScCamera, timeout: f32) -> bool;
+
+/// A virtual camera backed by softcam, that frames of a fixed size can be sent to
+pub struct SoftcamSink {
+    _library: Library,
+    camera: ScCamera,
+    delete_camera: ScDeleteCamera,
+    send_frame: ScSendFrame,
+    wait_for_connection: ScWaitForConnection,
+    width: u32,
+    height: u32,
+    buf: Vec<u8>,
+}
+
+impl SoftcamSink {
+    /// Load the softcam DLL, e.g. `softcam.dll`, and create a virtual camera
+    /// for frames of the given size and framerate.
+    ///
+    /// Only one softcam camera can exist at a time.
+    pub fn open<P: AsRef<Path>>(
+        dll: P,
+        width: u32,
+        height: u32,
+        framerate: f32,
+    ) -> io::Result<SoftcamSink> {
+        let library = Library::open(dll.as_ref())?;
+        let (create_camera, delete_camera, send_frame, wait_for_connection) = unsafe {
+            (
+                library.symbol::<ScCreateCamera>("scCreateCamera")?,
+                library.symbol::<ScDeleteCamera>("scDeleteCamera")?,
+                library.symbol::<ScSendFrame>("scSendFrame")?,
+                library.symbol::<ScWaitForConnection>("scWaitForConnection")?,
+            )
+        };
+        let camera = unsafe { create_camera(width as i32, height as i32, framerate) };
+        if camera.is_null() {
+            return Err(io::Error::other(
+                "Failed to create softcam camera. Is another one already running?",
+            ));
+        }
+        Ok(SoftcamSink {
+            _library: library,
+            camera,
+            delete_camera,
+            send_frame,
+            wait_for_connection,
+            width,
+            height,
+            buf: Vec::with_capacity(width as usize * height as usize * 3),
+        })
+    }
+
+    /// Returns the width and height of the frames the camera is configured for
+    pub fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Block until an application opens the camera, or until `timeout` seconds
+    /// have passed. A timeout of 0 waits forever.
+    ///
+    /// Returns whether an application is connected.
+    pub fn wait_for_connection(&mut self, timeout: f32) -> bool {
+        unsafe { (self.wait_for_connection)(self.camera, timeout) }
+    }
+
+    /// Send a frame to the camera
+    ///
+    /// The frame must be of the size the camera was opened with.
+    pub fn write_frame(&mut self, frame: &[Bgr8]) -> io::Result<()> {
+        if frame.len() != self.width as usize * self.height as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame size does not match the size of the virtual camera",
+            ));
+        }
+        // softcam takes 24-bit BGR pixels
+        self.buf.clear();
+        self.buf
+            .extend(frame.iter().flat_map(|&Bgr8 { b, g, r, .. }| [b, g, r]));
+        unsafe { (self.send_frame)(self.camera, self.buf.as_ptr() as *const c_void) };
+        Ok(())
+    }
+}
+
+impl Drop for SoftcamSink {
+    fn drop(&mut self) {
+        unsafe { (self.delete_camera)(self.camera) }
+    }
+}
@@ -0,0 +1,432 @@
+//! Identification of application windows, for capture of a single window
+//! or exclusion from captures
+//!
+//! See `Capturer::new_window`, `Capturer::exclude_window` and
+//! `Capturer::follow_window`.
+
+use crate::{redact, Capturer, InitError, Rect};
+
+/// A native window handle: an `HWND` on Windows, and an X11 window ID on
+/// *nix
+///
+/// On Wayland, only XWayland windows have IDs. Native Wayland windows can
+/// be captured with `Capturer::select_window` instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WindowId(pub u64);
+
+impl WindowId {
+    /// Find a visible top-level window whose title contains `title`
+    pub fn find(title: &str) -> Option<WindowId> {
+        sys::find(title)
+    }
+}
+
+/// How much of a captured window is shown on the screen, see
+/// `Frame::visibility`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Visibility {
+    Visible,
+    /// Partly covered by other windows
+    PartiallyOccluded,
+    /// Entirely covered by other windows, or cloaked, e.g. on another
+    /// virtual desktop
+    Occluded,
+    /// Minimized, or otherwise not shown
+    Minimized,
+}
+
+/// Windows excluded from captures
+#[derive(Default)]
+pub(crate) struct ExcludedWindows {
+    /// The windows, and whether they are excluded by the system rather than
+    /// blacked out
+    windows: Vec<(WindowId, bool)>,
+    bounds: sys::Bounds,
+}
+
+impl ExcludedWindows {
+    /// Whether any window is blacked out in captured frames
+    pub(crate) fn blacks_out(&self) -> bool {
+        self.windows.iter().any(|&(_, native)| !native)
+    }
+
+    /// Black out the windows not excluded by the system in a frame of the
+    /// given size, whose top left corner is at `origin` on the desktop
+    pub(crate) fn black_out(
+        &mut self,
+        frame: &mut [crate::Bgr8],
+        (width, height): (u32, u32),
+        origin: (i32, i32),
+    ) {
+        for &(window, native) in &self.windows {
+            if native {
+                continue;
+            }
+            if let Some(bounds) = self.bounds.bounds(window) {
+                let region = frame_region(bounds, (width, height), origin);
+                redact::redact(frame, width, height, region, redact::Redaction::Blackout);
+            }
+        }
+    }
+}
+
+/// A window followed by the capture region, see `Capturer::follow_window`
+pub(crate) struct FollowedWindow {
+    window: WindowId,
+    bounds: sys::Bounds,
+}
+
+/// The centre of bounds on the desktop
+fn centre((x, y, w, h): (i32, i32, u32, u32)) -> (i64, i64) {
+    (x as i64 + w as i64 / 2, y as i64 + h as i64 / 2)
+}
+
+/// Whether a point on the desktop is within a display of the given size,
+/// whose top left corner is at `origin`
+fn contains(origin: (i32, i32), (width, height): (u32, u32), (x, y): (i64, i64)) -> bool {
+    let (left, top) = (origin.0 as i64, origin.1 as i64);
+    (left..left + width as i64).contains(&x) && (top..top + height as i64).contains(&y)
+}
+
+/// The part of a frame of the given size, whose top left corner is at
+/// `origin` on the desktop, covered by bounds on the desktop
+fn frame_region((x, y, w, h): (i32, i32, u32, u32), size: (u32, u32), origin: (i32, i32)) -> Rect {
+    let (x, y) = (x as i64 - origin.0 as i64, y as i64 - origin.1 as i64);
+    let clamp = |v: i64, max: u32| v.clamp(0, max as i64) as u32;
+    let (left, top) = (clamp(x, size.0), clamp(y, size.1));
+    let (right, bottom) = (clamp(x + w as i64, size.0), clamp(y + h as i64, size.1));
+    Rect {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    }
+}
+
+impl Capturer {
+    /// How much of the captured window was shown on the screen at the last
+    /// capture, with `Self::new_window`. `None` for other capture sources,
+    /// and on platforms without window capture.
+    ///
+    /// Also attached to frames, as `Frame::visibility`, so that stale
+    /// frames of minimized windows, or frames of windows covered by others,
+    /// can be skipped.
+    pub fn visibility(&self) -> Option<Visibility> {
+        self.backend.visibility()
+    }
+
+    /// Keep a window, e.g. the control panel of a screen sharing app, out of
+    /// subsequently captured frames
+    ///
+    /// On Windows 10 2004 and later, windows of the calling process are
+    /// excluded by the system with `SetWindowDisplayAffinity`, so that
+    /// what's behind them is captured. Otherwise, i.e. for windows of other
+    /// processes, older versions of Windows, and X11, the area of the window
+    /// is blacked out in every frame, following it as it moves. Not
+    /// supported on macOS.
+    pub fn exclude_window(&mut self, window: WindowId) -> Result<(), InitError> {
+        if cfg!(target_os = "macos") {
+            return Err(InitError::Unsupported(
+                "Excluding windows is not supported on macOS".to_string(),
+            ));
+        }
+        if !self.excluded.bounds.exists(window) {
+            return Err(InitError::NotFound(format!(
+                "No window with ID {:#x}",
+                window.0
+            )));
+        }
+        if self.excluded.windows.iter().all(|&(w, _)| w != window) {
+            let native = sys::set_excluded(window, true);
+            self.excluded.windows.push((window, native));
+        }
+        self.processed = None;
+        Ok(())
+    }
+
+    /// Capture a window excluded with `Self::exclude_window` again
+    pub fn include_window(&mut self, window: WindowId) {
+        self.excluded.windows.retain(|&(w, native)| {
+            if w == window && native {
+                sys::set_excluded(window, false);
+            }
+            w != window
+        });
+    }
+
+    /// The windows excluded from captures
+    pub fn excluded_windows(&self) -> Vec<WindowId> {
+        self.excluded.windows.iter().map(|&(w, _)| w).collect()
+    }
+
+    /// Crop subsequently captured frames to a window, following it as it
+    /// moves and is resized
+    ///
+    /// The bounds of the window are queried before each capture, and the
+    /// capture region set to them. When the centre of the window moves to
+    /// another monitor, the capture source is switched to that monitor.
+    /// Unlike `Self::new_window`, other windows on top of it are captured
+    /// too. While the window is minimized or hidden, the last region is
+    /// kept. Not supported on macOS.
+    pub fn follow_window(&mut self, window: WindowId) -> Result<(), InitError> {
+        if cfg!(target_os = "macos") {
+            return Err(InitError::Unsupported(
+                "Following windows is not supported on macOS".to_string(),
+            ));
+        }
+        let mut bounds: sys::Bounds = Default::default();
+        if !bounds.exists(window) {
+            return Err(InitError::NotFound(format!(
+                "No window with ID {:#x}",
+                window.0
+            )));
+        }
+        self.followed = Some(FollowedWindow { window, bounds });
+        self.follow();
+        Ok(())
+    }
+
+    /// Stop following the window followed with `Self::follow_window`, and
+    /// capture all of the capture source again
+    pub fn unfollow_window(&mut self) {
+        if self.followed.take().is_some() {
+            self.clear_capture_region();
+        }
+    }
+
+    /// The window followed by the capture region, if any
+    pub fn followed_window(&self) -> Option<WindowId> {
+        self.followed.as_ref().map(|followed| followed.window)
+    }
+
+    /// Move the capture region, and switch the capture source if needed, to
+    /// the current bounds of the followed window, if any
+    pub(crate) fn follow(&mut self) {
+        let bounds = match &mut self.followed {
+            Some(followed) => match followed.bounds.bounds(followed.window) {
+                Some(bounds) => bounds,
+                None => return,
+            },
+            None => return,
+        };
+        let point = centre(bounds);
+        if !contains(self.backend.position(), self.backend.geometry(), point) {
+            let display = crate::display::displays()
+                .into_iter()
+                .find(|d| contains(d.position, d.size, point));
+            if let Some(display) = display {
+                let _ = self.set_capture_source(display.index);
+            }
+        }
+        let region = frame_region(bounds, self.backend.geometry(), self.backend.position());
+        if region.width > 0 && region.height > 0 && self.region != Some(region) {
+            let _ = self.set_capture_region(region);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::WindowId;
+    use windows::Win32::Foundation::{BOOL, HWND, LPARAM, RECT};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        EnumWindows, GetWindowRect, GetWindowTextW, IsIconic, IsWindow, IsWindowVisible,
+        SetWindowDisplayAffinity, WDA_EXCLUDEFROMCAPTURE, WDA_NONE,
+    };
+
+    fn hwnd(window: WindowId) -> HWND {
+        HWND(window.0 as *mut _)
+    }
+
+    /// Queries of the bounds of windows
+    #[derive(Default)]
+    pub struct Bounds;
+
+    impl Bounds {
+        pub fn exists(&mut self, window: WindowId) -> bool {
+            unsafe { IsWindow(hwnd(window)).as_bool() }
+        }
+
+        /// Bounds of a visible window on the desktop
+        pub fn bounds(&mut self, window: WindowId) -> Option<(i32, i32, u32, u32)> {
+            let hwnd = hwnd(window);
+            unsafe {
+                if !IsWindowVisible(hwnd).as_bool() || IsIconic(hwnd).as_bool() {
+                    return None;
+                }
+                let mut rect = RECT::default();
+                GetWindowRect(hwnd, &mut rect).ok()?;
+                let width = (rect.right - rect.left).max(0) as u32;
+                let height = (rect.bottom - rect.top).max(0) as u32;
+                Some((rect.left, rect.top, width, height))
+            }
+        }
+    }
+
+    /// Exclude a window from captures by the system, or stop. Only
+    /// succeeds for windows of this process.
+    pub fn set_excluded(window: WindowId, excluded: bool) -> bool {
+        let affinity = if excluded {
+            WDA_EXCLUDEFROMCAPTURE
+        } else {
+            WDA_NONE
+        };
+        unsafe { SetWindowDisplayAffinity(hwnd(window), affinity) }.is_ok()
+    }
+
+    struct Search<'a> {
+        title: &'a str,
+        found: Option<HWND>,
+    }
+
+    unsafe extern "system" fn visit(hwnd: HWND, search: LPARAM) -> BOOL {
+        let search = &mut *(search.0 as *mut Search);
+        if !IsWindowVisible(hwnd).as_bool() {
+            return true.into();
+        }
+        let mut buf = [0u16; 512];
+        let len = GetWindowTextW(hwnd, &mut buf).max(0) as usize;
+        if String::from_utf16_lossy(&buf[..len]).contains(search.title) {
+            search.found = Some(hwnd);
+            // Stop the enumeration
+            return false.into();
+        }
+        true.into()
+    }
+
+    pub fn find(title: &str) -> Option<WindowId> {
+        let mut search = Search { title, found: None };
+        // Fails when the enumeration is stopped, i.e. when the window is found
+        let _ = unsafe { EnumWindows(Some(visit), LPARAM(&mut search as *mut Search as isize)) };
+        search.found.map(|hwnd| WindowId(hwnd.0 as u64))
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "android", target_arch = "wasm32"))]
+mod sys {
+    use super::WindowId;
+
+    pub fn find(_title: &str) -> Option<WindowId> {
+        None
+    }
+
+    #[derive(Default)]
+    pub struct Bounds;
+
+    impl Bounds {
+        pub fn exists(&mut self, _window: WindowId) -> bool {
+            false
+        }
+
+        pub fn bounds(&mut self, _window: WindowId) -> Option<(i32, i32, u32, u32)> {
+            None
+        }
+    }
+
+    pub fn set_excluded(_window: WindowId, _excluded: bool) -> bool {
+        false
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos"), not(target_os = "android")))]
+mod sys {
+    use super::WindowId;
+    use x11::xlib;
+
+    // `xlib::Window` is only 64 bits on 64-bit targets
+    #[allow(clippy::unnecessary_cast)]
+    pub fn find(title: &str) -> Option<WindowId> {
+        crate::x11::find_window(title).map(|window| WindowId(window as u64))
+    }
+
+    /// Queries of the bounds of windows, over a connection opened on first
+    /// use
+    #[derive(Default)]
+    pub struct Bounds {
+        display: Option<*mut xlib::Display>,
+    }
+
+    // The connection is only ever used by the thread owning the capturer
+    unsafe impl Send for Bounds {}
+
+    impl Bounds {
+        fn display(&mut self) -> Option<*mut xlib::Display> {
+            if self.display.is_none() {
+                let display = crate::x11::open_display();
+                self.display = Some(display).filter(|d| !d.is_null());
+            }
+            self.display
+        }
+
+        pub fn exists(&mut self, window: WindowId) -> bool {
+            let display = match self.display() {
+                Some(display) => display,
+                None => return false,
+            };
+            let mut attrs = unsafe { std::mem::zeroed::<xlib::XWindowAttributes>() };
+            unsafe {
+                crate::x11::without_errors(display, || {
+                    xlib::XGetWindowAttributes(display, window.0 as xlib::Window, &mut attrs)
+                })
+            }
+            .is_ok_and(|status| status != 0)
+        }
+
+        /// Bounds of a viewable window on the root window
+        pub fn bounds(&mut self, window: WindowId) -> Option<(i32, i32, u32, u32)> {
+            crate::x11::window_bounds(self.display()?, window.0 as xlib::Window)
+        }
+    }
+
+    impl Drop for Bounds {
+        fn drop(&mut self) {
+            if let Some(display) = self.display {
+                unsafe { xlib::XCloseDisplay(display) };
+            }
+        }
+    }
+
+    /// Windows can't be excluded by the X server
+    pub fn set_excluded(_window: WindowId, _excluded: bool) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod window_tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_region() {
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        // A window partly left of a monitor at (1920, 0)
+        let bounds = (1800, 100, 200, 50);
+        assert_eq!(
+            frame_region(bounds, (1920, 1080), (1920, 0)),
+            rect(0, 100, 80, 50)
+        );
+        assert_eq!(
+            frame_region(bounds, (1920, 1080), (0, 0)),
+            rect(1800, 100, 120, 50)
+        );
+        // Not on the monitor at all
+        assert_eq!(frame_region(bounds, (100, 100), (-100, 0)).width, 0);
+    }
+
+    #[test]
+    fn test_contains_centre() {
+        // Mostly on the monitor right of the primary one
+        let bounds = (1800, 100, 400, 300);
+        assert_eq!(centre(bounds), (2000, 250));
+        assert!(contains((1920, 0), (1920, 1080), centre(bounds)));
+        assert!(!contains((0, 0), (1920, 1080), centre(bounds)));
+        // Edges are exclusive on the right and bottom
+        assert!(contains((-1920, 0), (1920, 1080), (-1920, 0)));
+        assert!(!contains((-1920, 0), (1920, 1080), (0, 0)));
+    }
+}
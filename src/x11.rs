@@ -0,0 +1,1395 @@
+//! Capture with X11
+//!
+//! Frames are read into a shared memory segment with the MIT-SHM extension
+//! (`XShmGetImage`), which avoids copying every frame through the X socket.
+//! If the extension is unavailable, e.g. for a remote X server, frames are
+//! read with `XGetImage` instead.
+//!
+//! The damaged regions of frames are tracked with XDamage, if available.
+//!
+//! Monitors are the active CRTCs of the connected RandR outputs, see
+//! `monitors`, so that monitor indices map to physical monitors however the
+//! screen is laid out. The whole screen is captured with
+//! `CaptureSource::Screen`.
+//!
+//! Single windows are captured with `XGetImage` of the window, redirected
+//! with XComposite so that covered windows can be captured.
+
+use crate::backend::Backend;
+use crate::dylib::Library;
+use crate::{
+    Bgr8, CaptureError, InitError, PlatformCode, PlatformError, Rect, Visibility, WindowId,
+};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_short, c_uint, c_ulong};
+use std::ptr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use x11::{xfixes, xlib, xrandr, xshm};
+
+// The `x11` crate only declares the MIT-SHM functions
+#[link(name = "Xext")]
+extern "C" {}
+
+#[link(name = "Xcomposite")]
+extern "C" {
+    fn XCompositeQueryExtension(
+        display: *mut xlib::Display,
+        event_base: *mut c_int,
+        error_base: *mut c_int,
+    ) -> xlib::Bool;
+    fn XCompositeRedirectWindow(display: *mut xlib::Display, window: xlib::Window, update: c_int);
+    fn XCompositeUnredirectWindow(display: *mut xlib::Display, window: xlib::Window, update: c_int);
+}
+
+/// Initialize Xlib for use from multiple threads, once, before the first
+/// connection is opened
+pub(crate) fn init_threads() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe {
+        xlib::XInitThreads();
+    });
+}
+
+/// Open a connection to the default X display, or null if it failed
+pub(crate) fn open_display() -> *mut xlib::Display {
+    init_threads();
+    unsafe { xlib::XOpenDisplay(ptr::null()) }
+}
+
+/// `CompositeRedirectAutomatic`
+const COMPOSITE_REDIRECT_AUTOMATIC: c_int = 0;
+
+/// Code of the first X error that occurred in `without_errors`, or 0 if
+/// none did. Error codes start at 1.
+static X_ERROR: AtomicU8 = AtomicU8::new(0);
+
+unsafe extern "C" fn error_handler(
+    _display: *mut xlib::Display,
+    event: *mut xlib::XErrorEvent,
+) -> c_int {
+    let _ = X_ERROR.compare_exchange(0, (*event).error_code, Ordering::SeqCst, Ordering::SeqCst);
+    0
+}
+
+/// Run `f` and wait for the X server to process its requests, returning
+/// the code of the first X error if one occurred
+///
+/// Errors are reported asynchronously, and by default exit the process.
+pub(crate) unsafe fn without_errors<T>(
+    display: *mut xlib::Display,
+    f: impl FnOnce() -> T,
+) -> Result<T, u8> {
+    X_ERROR.store(0, Ordering::SeqCst);
+    let prev_handler = xlib::XSetErrorHandler(Some(error_handler));
+    let res = f();
+    xlib::XSync(display, xlib::False);
+    xlib::XSetErrorHandler(prev_handler);
+    match X_ERROR.load(Ordering::SeqCst) {
+        0 => Ok(res),
+        code => Err(code),
+    }
+}
+
+/// A physical monitor of the X server, in root window coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct X11Monitor {
+    /// Name of the RandR output, e.g. `DP-1`
+    pub(crate) name: String,
+    /// The RandR output, or 0 if not known through RandR
+    pub(crate) output: c_ulong,
+    /// The RandR CRTC scanning out the monitor, or 0 if not known through
+    /// RandR
+    pub(crate) crtc: c_ulong,
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) primary: bool,
+}
+
+/// Order monitors as capture sources: the primary monitor first, then left
+/// to right and top to bottom, skipping mirrors of the same area
+///
+/// Shared by the Xlib and XCB backends, so that indices map to the same
+/// monitors with both.
+pub(crate) fn order_monitors(mut monitors: Vec<X11Monitor>) -> Vec<X11Monitor> {
+    monitors.sort_by_key(|m| (!m.primary, m.x, m.y, m.width, m.height));
+    let mut areas = std::collections::HashSet::new();
+    monitors.retain(|m| areas.insert((m.x, m.y, m.width, m.height)));
+    monitors
+}
+
+/// `XineramaScreenInfo`
+#[repr(C)]
+struct XineramaScreenInfo {
+    screen_number: c_int,
+    x_org: c_short,
+    y_org: c_short,
+    width: c_short,
+    height: c_short,
+}
+
+type XineramaIsActive = unsafe extern "C" fn(*mut xlib::Display) -> xlib::Bool;
+type XineramaQueryScreens =
+    unsafe extern "C" fn(*mut xlib::Display, *mut c_int) -> *mut XineramaScreenInfo;
+
+/// The monitors of the active CRTCs of the connected RandR outputs, in the
+/// order of `order_monitors`
+///
+/// Drivers merging monitors into one RandR output, like NVIDIA TwinView,
+/// report the monitors with Xinerama, so the Xinerama screens are used
+/// instead when there are more of them. libXinerama is loaded at runtime.
+pub(crate) unsafe fn monitors(display: *mut xlib::Display) -> Vec<X11Monitor> {
+    let root = xlib::XDefaultRootWindow(display);
+    let mut monitors = Vec::new();
+    let (mut event_base, mut error_base) = (0, 0);
+    if xrandr::XRRQueryExtension(display, &mut event_base, &mut error_base) != xlib::False {
+        let resources = xrandr::XRRGetScreenResourcesCurrent(display, root);
+        if !resources.is_null() {
+            let primary = xrandr::XRRGetOutputPrimary(display, root);
+            let outputs =
+                std::slice::from_raw_parts((*resources).outputs, (*resources).noutput as usize);
+            for &output in outputs {
+                if let Some(monitor) = output_monitor(display, resources, output, primary) {
+                    monitors.push(monitor);
+                }
+            }
+            xrandr::XRRFreeScreenResources(resources);
+        }
+    }
+    let xinerama = xinerama_monitors(display);
+    if xinerama.len() > monitors.len() {
+        monitors = xinerama;
+    }
+    order_monitors(monitors)
+}
+
+/// The monitor of an output, if connected and driven by a CRTC
+unsafe fn output_monitor(
+    display: *mut xlib::Display,
+    resources: *mut xrandr::XRRScreenResources,
+    output: xrandr::RROutput,
+    primary: xrandr::RROutput,
+) -> Option<X11Monitor> {
+    let info = xrandr::XRRGetOutputInfo(display, resources, output);
+    if info.is_null() {
+        return None;
+    }
+    let connected = (*info).connection == xrandr::RR_Connected as xrandr::Connection;
+    let crtc = (*info).crtc;
+    let name = std::slice::from_raw_parts((*info).name as *const u8, (*info).nameLen as usize);
+    let name = String::from_utf8_lossy(name).into_owned();
+    xrandr::XRRFreeOutputInfo(info);
+    if !connected || crtc == 0 {
+        return None;
+    }
+    let crtc_info = xrandr::XRRGetCrtcInfo(display, resources, crtc);
+    if crtc_info.is_null() {
+        return None;
+    }
+    let monitor = X11Monitor {
+        name,
+        output,
+        crtc,
+        x: (*crtc_info).x,
+        y: (*crtc_info).y,
+        width: (*crtc_info).width,
+        height: (*crtc_info).height,
+        primary: output == primary,
+    };
+    xrandr::XRRFreeCrtcInfo(crtc_info);
+    Some(monitor).filter(|m| m.width > 0 && m.height > 0)
+}
+
+/// The screens of Xinerama, if active
+unsafe fn xinerama_monitors(display: *mut xlib::Display) -> Vec<X11Monitor> {
+    let lib = match Library::open("libXinerama.so.1") {
+        Ok(lib) => lib,
+        Err(_) => return Vec::new(),
+    };
+    let (is_active, query_screens) = match (
+        lib.symbol::<XineramaIsActive>("XineramaIsActive"),
+        lib.symbol::<XineramaQueryScreens>("XineramaQueryScreens"),
+    ) {
+        (Ok(is_active), Ok(query_screens)) => (is_active, query_screens),
+        _ => return Vec::new(),
+    };
+    if is_active(display) == xlib::False {
+        return Vec::new();
+    }
+    let mut n = 0;
+    let screens = query_screens(display, &mut n);
+    if screens.is_null() {
+        return Vec::new();
+    }
+    let monitors = std::slice::from_raw_parts(screens, n as usize)
+        .iter()
+        .map(|s| X11Monitor {
+            name: format!("Xinerama-{}", s.screen_number),
+            output: 0,
+            crtc: 0,
+            x: s.x_org as i32,
+            y: s.y_org as i32,
+            width: s.width as u32,
+            height: s.height as u32,
+            // Xinerama lists the primary screen first
+            primary: s.screen_number == 0,
+        })
+        .collect();
+    xlib::XFree(screens as *mut _);
+    monitors
+}
+
+/// The size of the whole screen of the X server, spanning all monitors
+pub(crate) unsafe fn screen_size(display: *mut xlib::Display) -> (u32, u32) {
+    let screen = xlib::XDefaultScreen(display);
+    (
+        xlib::XDisplayWidth(display, screen) as u32,
+        xlib::XDisplayHeight(display, screen) as u32,
+    )
+}
+
+/// The area of a monitor by index, in the order of `monitors`, or of the
+/// whole screen if `None`
+///
+/// Without RandR and Xinerama, the whole screen is the only monitor.
+fn source_area(monitor: Option<usize>) -> Result<x11cap::CaptureSource, InitError> {
+    let display = open_display();
+    if display.is_null() {
+        return Err(InitError::Other(
+            "Failed to connect to the X server".to_string(),
+        ));
+    }
+    let area = unsafe {
+        let monitors = monitors(display);
+        let (width, height) = screen_size(display);
+        xlib::XCloseDisplay(display);
+        match monitor {
+            None => Some((0, 0, width, height)),
+            Some(0) if monitors.is_empty() => Some((0, 0, width, height)),
+            Some(i) => monitors.get(i).map(|m| (m.x, m.y, m.width, m.height)),
+        }
+    };
+    match area {
+        Some((x, y, width, height)) => Ok(x11cap::CaptureSource::Region {
+            x,
+            y,
+            width,
+            height,
+        }),
+        None => Err(InitError::NotFound(format!(
+            "No monitor {}",
+            monitor.unwrap_or_default()
+        ))),
+    }
+}
+
+/// An image in a shared memory segment attached to the X server
+struct ShmImage {
+    display: *mut xlib::Display,
+    root: xlib::Window,
+    image: *mut xlib::XImage,
+    info: Box<xshm::XShmSegmentInfo>,
+    captured: bool,
+}
+
+impl ShmImage {
+    /// Attach a segment for images of `width` x `height`, if the X server
+    /// supports MIT-SHM and is on the same machine
+    fn new(width: u32, height: u32) -> Option<ShmImage> {
+        unsafe {
+            let display = open_display();
+            if display.is_null() {
+                return None;
+            }
+            let mut shm = ShmImage {
+                display,
+                root: xlib::XDefaultRootWindow(display),
+                image: ptr::null_mut(),
+                info: Box::new(xshm::XShmSegmentInfo {
+                    shmseg: 0,
+                    shmid: -1,
+                    shmaddr: ptr::null_mut(),
+                    readOnly: xlib::False,
+                }),
+                captured: false,
+            };
+            if xshm::XShmQueryExtension(display) == xlib::False {
+                return None;
+            }
+            let screen = xlib::XDefaultScreen(display);
+            shm.image = xshm::XShmCreateImage(
+                display,
+                xlib::XDefaultVisual(display, screen),
+                xlib::XDefaultDepth(display, screen) as u32,
+                xlib::ZPixmap,
+                ptr::null_mut(),
+                &mut *shm.info,
+                width,
+                height,
+            );
+            if shm.image.is_null() || !shm.has_bgr_layout() {
+                return None;
+            }
+            let size = (*shm.image).bytes_per_line as usize * height as usize;
+            shm.info.shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if shm.info.shmid < 0 {
+                return None;
+            }
+            let addr = libc::shmat(shm.info.shmid, ptr::null(), 0);
+            // Marked for removal right away, so the segment is freed when
+            // detached, even if the process is killed
+            libc::shmctl(shm.info.shmid, libc::IPC_RMID, ptr::null_mut());
+            if addr as isize == -1 {
+                return None;
+            }
+            shm.info.shmaddr = addr as *mut _;
+            (*shm.image).data = addr as *mut _;
+
+            // Attaching fails with an X error if the server can't access the
+            // segment
+            let info = &mut *shm.info;
+            let attached = without_errors(display, || xshm::XShmAttach(display, info)).ok();
+            if attached.is_none_or(|attached| attached == xlib::False) {
+                shm.info.shmseg = 0;
+                return None;
+            }
+            Some(shm)
+        }
+    }
+
+    /// Whether the pixels of the image are laid out as `Bgr8`
+    fn has_bgr_layout(&self) -> bool {
+        let image = unsafe { &*self.image };
+        image.depth == 24
+            && image.bits_per_pixel == 32
+            && image.red_mask == 0xFF_0000
+            && image.green_mask == 0xFF00
+            && image.blue_mask == 0xFF
+            && image.bytes_per_line == 4 * image.width
+    }
+
+    fn capture(&mut self, x: i32, y: i32) -> Result<(), CaptureError> {
+        let (display, root, image) = (self.display, self.root, self.image);
+        // Fails with an X error, e.g. if the area is outside of the root
+        // window after a mode change
+        let ok = unsafe {
+            without_errors(display, || {
+                xshm::XShmGetImage(display, root, image, x, y, !0)
+            })
+        }
+        .map_err(|code| {
+            self.captured = false;
+            PlatformError::new("XShmGetImage failed", PlatformCode::X11(code))
+        })?;
+        self.captured = ok != xlib::False;
+        if self.captured {
+            Ok(())
+        } else {
+            Err(CaptureError::Fail("XShmGetImage failed".to_string()))
+        }
+    }
+
+    /// The captured frame and its width and height, if a frame has been
+    /// captured
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        let image = unsafe { &*self.image };
+        let len = image.width as usize * image.height as usize;
+        // The layout is checked by `has_bgr_layout`, with padding in place of
+        // alpha
+        let pixels = unsafe { std::slice::from_raw_parts(image.data as *const Bgr8, len) };
+        self.captured
+            .then_some((pixels, image.width as u32, image.height as u32))
+    }
+}
+
+impl Drop for ShmImage {
+    fn drop(&mut self) {
+        unsafe {
+            if self.info.shmseg != 0 {
+                xshm::XShmDetach(self.display, &mut *self.info);
+                xlib::XSync(self.display, xlib::False);
+            }
+            if !self.image.is_null() {
+                // The data is the segment, which is not owned by the image
+                (*self.image).data = ptr::null_mut();
+                xlib::XDestroyImage(self.image);
+            }
+            if !self.info.shmaddr.is_null() {
+                libc::shmdt(self.info.shmaddr as *const _);
+            }
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+type XDamageQueryExtension =
+    unsafe extern "C" fn(*mut xlib::Display, *mut c_int, *mut c_int) -> xlib::Bool;
+type XDamageCreate = unsafe extern "C" fn(*mut xlib::Display, xlib::Drawable, c_int) -> xlib::XID;
+type XDamageDestroy = unsafe extern "C" fn(*mut xlib::Display, xlib::XID);
+type XDamageSubtract = unsafe extern "C" fn(
+    *mut xlib::Display,
+    xlib::XID,
+    xfixes::XserverRegion,
+    xfixes::XserverRegion,
+);
+
+/// `XDamageReportNonEmpty`
+const DAMAGE_REPORT_NON_EMPTY: c_int = 3;
+
+/// Tracking of the damaged regions of the root window with XDamage
+///
+/// libXdamage is loaded at runtime, so that it's not required to capture.
+struct Damage {
+    display: *mut xlib::Display,
+    damage: xlib::XID,
+    /// Region that the damage is moved to when fetched
+    region: xfixes::XserverRegion,
+    destroy: XDamageDestroy,
+    subtract: XDamageSubtract,
+    /// The damaged regions of the last captured frame
+    rects: Option<Vec<Rect>>,
+    _lib: Library,
+}
+
+impl Damage {
+    fn new() -> Option<Damage> {
+        unsafe {
+            let lib = Library::open("libXdamage.so.1").ok()?;
+            let query: XDamageQueryExtension = lib.symbol("XDamageQueryExtension").ok()?;
+            let create: XDamageCreate = lib.symbol("XDamageCreate").ok()?;
+            let destroy: XDamageDestroy = lib.symbol("XDamageDestroy").ok()?;
+            let subtract: XDamageSubtract = lib.symbol("XDamageSubtract").ok()?;
+            let display = open_display();
+            if display.is_null() {
+                return None;
+            }
+            let (mut event_base, mut error_base) = (0, 0);
+            if query(display, &mut event_base, &mut error_base) == xlib::False {
+                xlib::XCloseDisplay(display);
+                return None;
+            }
+            let root = xlib::XDefaultRootWindow(display);
+            Some(Damage {
+                display,
+                damage: create(display, root, DAMAGE_REPORT_NON_EMPTY),
+                region: xfixes::XFixesCreateRegion(display, ptr::null_mut(), 0),
+                destroy,
+                subtract,
+                rects: None,
+                _lib: lib,
+            })
+        }
+    }
+
+    /// Fetch the regions of `area` damaged since the last call, relative to
+    /// `area`, and reset the damage
+    ///
+    /// Must be called before capturing the frame, so that damage during the
+    /// capture is reported for the next frame as well.
+    fn update(&mut self, area: x11cap::Geometry) {
+        let area_rect = Rect {
+            x: 0,
+            y: 0,
+            width: area.width,
+            height: area.height,
+        };
+        unsafe {
+            // Only the events of the connection are the damage notifications,
+            // which need not be handled
+            while xlib::XPending(self.display) > 0 {
+                let mut event = std::mem::zeroed();
+                xlib::XNextEvent(self.display, &mut event);
+            }
+            (self.subtract)(self.display, self.damage, 0, self.region);
+            if self.rects.is_none() {
+                // Nothing was captured before the first frame
+                self.rects = Some(vec![area_rect]);
+                return;
+            }
+            let mut n_rects = 0;
+            let rects = xfixes::XFixesFetchRegion(self.display, self.region, &mut n_rects);
+            let dirty = self.rects.get_or_insert_with(Vec::new);
+            dirty.clear();
+            if rects.is_null() {
+                return;
+            }
+            for r in std::slice::from_raw_parts(rects, n_rects as usize) {
+                // Clip to the area, in root window coordinates
+                let x = (r.x as i32).max(area.x);
+                let y = (r.y as i32).max(area.y);
+                let right = (r.x as i32 + r.width as i32).min(area.x + area.width as i32);
+                let bottom = (r.y as i32 + r.height as i32).min(area.y + area.height as i32);
+                if right > x && bottom > y {
+                    dirty.push(Rect {
+                        x: (x - area.x) as u32,
+                        y: (y - area.y) as u32,
+                        width: (right - x) as u32,
+                        height: (bottom - y) as u32,
+                    });
+                }
+            }
+            xlib::XFree(rects as *mut _);
+        }
+    }
+
+    /// Wait up to `timeout` for damage of `area`, and fetch it like
+    /// `Self::update`. Returns whether `area` was damaged.
+    ///
+    /// The area of the first frame is always damaged.
+    fn wait(&mut self, area: x11cap::Geometry, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.update(area);
+            if self.rects.as_ref().is_none_or(|rects| !rects.is_empty()) {
+                return true;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            unsafe {
+                // Notifications may have been read while fetching the region
+                if xlib::XQLength(self.display) > 0 {
+                    continue;
+                }
+                let mut fd = libc::pollfd {
+                    fd: xlib::XConnectionNumber(self.display),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                let ms = remaining.as_millis().clamp(1, c_int::MAX as u128) as c_int;
+                libc::poll(&mut fd, 1, ms);
+            }
+        }
+    }
+}
+
+impl Drop for Damage {
+    fn drop(&mut self) {
+        unsafe {
+            (self.destroy)(self.display, self.damage);
+            xfixes::XFixesDestroyRegion(self.display, self.region);
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+type XPresentQueryExtension =
+    unsafe extern "C" fn(*mut xlib::Display, *mut c_int, *mut c_int, *mut c_int) -> xlib::Bool;
+type XPresentSelectInput =
+    unsafe extern "C" fn(*mut xlib::Display, xlib::Window, c_uint) -> xlib::XID;
+type XPresentNotifyMSC = unsafe extern "C" fn(*mut xlib::Display, xlib::Window, u32, u64, u64, u64);
+
+/// `PresentCompleteNotifyMask`
+const PRESENT_COMPLETE_NOTIFY_MASK: c_uint = 2;
+/// `PresentCompleteNotify`
+const PRESENT_COMPLETE_NOTIFY: c_int = 1;
+
+/// `XPresentCompleteNotifyEvent`
+#[repr(C)]
+struct XPresentCompleteNotifyEvent {
+    type_: c_int,
+    serial: c_ulong,
+    send_event: xlib::Bool,
+    display: *mut xlib::Display,
+    extension: c_int,
+    evtype: c_int,
+    eid: u32,
+    window: xlib::Window,
+    serial_number: u32,
+    ust: u64,
+    msc: u64,
+    kind: u8,
+    mode: u8,
+}
+
+/// The instant of a time of `CLOCK_MONOTONIC`, the clock of the timestamps
+/// of X Present with DRM
+pub(crate) fn monotonic_instant(time: Duration) -> Option<Instant> {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return None;
+    }
+    let now = Duration::new(now.tv_sec as u64, now.tv_nsec as u32);
+    Instant::now().checked_sub(now.saturating_sub(time))
+}
+
+/// Timing of the vertical blanks of the root window with X Present
+///
+/// libXpresent is loaded at runtime, so that it's not required to capture.
+struct Present {
+    display: *mut xlib::Display,
+    root: xlib::Window,
+    /// Major opcode of the extension, which its events are tagged with
+    opcode: c_int,
+    notify_msc: XPresentNotifyMSC,
+    /// Time of the last reported vertical blank, in microseconds of
+    /// `CLOCK_MONOTONIC`
+    last_ust: Option<u64>,
+    _lib: Library,
+}
+
+impl Present {
+    fn new() -> Option<Present> {
+        unsafe {
+            let lib = Library::open("libXpresent.so.1").ok()?;
+            let query: XPresentQueryExtension = lib.symbol("XPresentQueryExtension").ok()?;
+            let select_input: XPresentSelectInput = lib.symbol("XPresentSelectInput").ok()?;
+            let notify_msc: XPresentNotifyMSC = lib.symbol("XPresentNotifyMSC").ok()?;
+            let display = open_display();
+            if display.is_null() {
+                return None;
+            }
+            let (mut opcode, mut event_base, mut error_base) = (0, 0, 0);
+            if query(display, &mut opcode, &mut event_base, &mut error_base) == xlib::False {
+                xlib::XCloseDisplay(display);
+                return None;
+            }
+            let root = xlib::XDefaultRootWindow(display);
+            select_input(display, root, PRESENT_COMPLETE_NOTIFY_MASK);
+            Some(Present {
+                display,
+                root,
+                opcode,
+                notify_msc,
+                last_ust: None,
+                _lib: lib,
+            })
+        }
+    }
+
+    /// Time of the last vertical blank, at which the current contents of the
+    /// screen were presented
+    ///
+    /// Of the CRTC the X server presents the root window on, i.e. the one it
+    /// overlaps the most.
+    fn last_vblank(&mut self) -> Option<Instant> {
+        unsafe {
+            // A target in the past completes right away, at the last vertical
+            // blank
+            (self.notify_msc)(self.display, self.root, 0, 0, 0, 0);
+            xlib::XSync(self.display, xlib::False);
+            while xlib::XPending(self.display) > 0 {
+                let mut event: xlib::XEvent = std::mem::zeroed();
+                xlib::XNextEvent(self.display, &mut event);
+                let cookie = &mut event.generic_event_cookie;
+                if cookie.type_ != xlib::GenericEvent
+                    || cookie.extension != self.opcode
+                    || xlib::XGetEventData(self.display, cookie) == xlib::False
+                {
+                    continue;
+                }
+                if cookie.evtype == PRESENT_COMPLETE_NOTIFY {
+                    let complete = &*(cookie.data as *const XPresentCompleteNotifyEvent);
+                    self.last_ust = Some(complete.ust);
+                }
+                xlib::XFreeEventData(self.display, cookie);
+            }
+        }
+        monotonic_instant(Duration::from_micros(self.last_ust?))
+    }
+}
+
+impl Drop for Present {
+    fn drop(&mut self) {
+        unsafe {
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+/// Backend capturing a monitor, or the whole screen, of the X server
+pub(crate) struct X11Backend {
+    /// Index of the monitor, or `None` for the whole screen
+    monitor: Option<usize>,
+    capturer: x11cap::Capturer,
+    region: Option<Rect>,
+    /// Capturer of the capture region, if one is set
+    region_capturer: Option<x11cap::Capturer>,
+    /// Used instead of the capturers for capture when available
+    shm: Option<ShmImage>,
+    image: Option<x11cap::Image>,
+    damage: Option<Damage>,
+    present: Option<Present>,
+    /// Time at which the last captured frame was presented, if reported
+    present_time: Option<Instant>,
+    /// Whether to read frames without waiting for damage
+    immediate: bool,
+}
+
+impl X11Backend {
+    /// Capture the monitor at an index, see `monitors`
+    pub(crate) fn new(capture_src: usize) -> Result<X11Backend, InitError> {
+        X11Backend::with_monitor(Some(capture_src))
+    }
+
+    /// Capture the whole screen, spanning all monitors, see
+    /// `CaptureSource::Screen`
+    pub(crate) fn new_screen() -> Result<X11Backend, InitError> {
+        X11Backend::with_monitor(None)
+    }
+
+    fn with_monitor(monitor: Option<usize>) -> Result<X11Backend, InitError> {
+        let capturer = x11cap::Capturer::new(source_area(monitor)?)
+            .map_err(|()| "Failed to initialize capturer".to_string())?;
+        let geo = capturer.get_geometry();
+        Ok(X11Backend {
+            monitor,
+            shm: ShmImage::new(geo.width, geo.height),
+            capturer,
+            region: None,
+            region_capturer: None,
+            image: None,
+            damage: Damage::new(),
+            present: Present::new(),
+            present_time: None,
+            immediate: false,
+        })
+    }
+
+    /// The capturer of the area to capture, i.e. the region or monitor
+    fn area_capturer(&mut self) -> &mut x11cap::Capturer {
+        self.region_capturer.as_mut().unwrap_or(&mut self.capturer)
+    }
+
+    /// The area to capture, in root window coordinates
+    fn area(&self) -> x11cap::Geometry {
+        self.region_capturer
+            .as_ref()
+            .unwrap_or(&self.capturer)
+            .get_geometry()
+    }
+}
+
+// The connections of the backend are only ever used by the thread owning it,
+// and Xlib is initialized for threads by `init_threads`
+unsafe impl Send for X11Backend {}
+
+impl Backend for X11Backend {
+    fn name(&self) -> &'static str {
+        if self.shm.is_some() {
+            "X11 XShmGetImage"
+        } else {
+            "X11 XGetImage"
+        }
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        let geo = self.area();
+        (geo.width, geo.height)
+    }
+
+    fn position(&self) -> (i32, i32) {
+        let geo = self.capturer.get_geometry();
+        (geo.x, geo.y)
+    }
+
+    /// Waits up to `timeout` for the area to change, if XDamage is
+    /// available and not reading immediately. Captures immediately
+    /// otherwise.
+    fn capture_frame(&mut self, timeout: Duration) -> Result<(), CaptureError> {
+        let geo = self.area();
+        if let Some(damage) = &mut self.damage {
+            // Still collects the damage when immediate, for the dirty
+            // rectangles
+            let timeout = if self.immediate {
+                Duration::ZERO
+            } else {
+                timeout
+            };
+            if !damage.wait(geo, timeout) && !self.immediate {
+                return Err(CaptureError::Timeout);
+            }
+        }
+        let res = match &mut self.shm {
+            Some(shm) => shm.capture(geo.x, geo.y),
+            None => self
+                .area_capturer()
+                .capture_frame()
+                .map(|image| self.image = Some(image))
+                .map_err(|x11cap::CaptureError::Fail(e)| CaptureError::Fail(e.to_string())),
+        };
+        if let (Err(_), Some(damage)) = (&res, &mut self.damage) {
+            // The damage was not captured, so the next frame is entirely
+            // dirty
+            damage.rects = None;
+        }
+        if res.is_ok() {
+            self.present_time = self.present.as_mut().and_then(Present::last_vblank);
+        }
+        res
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        if let Some(shm) = &self.shm {
+            return shm.frame();
+        }
+        self.image.as_ref().map(|image| {
+            let (w, h) = image.get_dimensions();
+            (x11_pixels(image), w, h)
+        })
+    }
+
+    fn dirty_rects(&self) -> Option<&[Rect]> {
+        self.damage.as_ref()?.rects.as_deref()
+    }
+
+    fn set_immediate(&mut self, immediate: bool) {
+        self.immediate = immediate;
+    }
+
+    /// The last vertical blank before the capture, with X Present
+    fn present_time(&self) -> Option<Instant> {
+        self.present_time
+    }
+
+    /// Reads only the region from the X server
+    fn set_region(&mut self, region: Option<Rect>) -> bool {
+        let geo = self.capturer.get_geometry();
+        self.region = region;
+        self.region_capturer = None;
+        if let Some(region) = region {
+            let source = x11cap::CaptureSource::Region {
+                x: geo.x + region.x as i32,
+                y: geo.y + region.y as i32,
+                width: region.width,
+                height: region.height,
+            };
+            init_threads();
+            match x11cap::Capturer::new(source) {
+                Ok(capturer) => self.region_capturer = Some(capturer),
+                Err(()) => return false,
+            }
+        }
+        self.image = None;
+        if let Some(damage) = &mut self.damage {
+            // The next frame is of a different area
+            damage.rects = None;
+        }
+        if self.shm.is_some() {
+            let (width, height) = self.geometry();
+            self.shm = ShmImage::new(width, height);
+        }
+        true
+    }
+
+    fn set_source(&mut self, capture_src: usize) -> Option<Result<(), InitError>> {
+        let source = match source_area(Some(capture_src)) {
+            Ok(source) => source,
+            Err(e) => return Some(Err(e)),
+        };
+        match x11cap::Capturer::new(source) {
+            Ok(capturer) => self.capturer = capturer,
+            Err(()) => {
+                return Some(Err(InitError::Other(
+                    "Failed to initialize capturer".to_string(),
+                )))
+            }
+        }
+        self.monitor = Some(capture_src);
+        self.set_region(None);
+        Some(Ok(()))
+    }
+
+    /// Reconnects to the X server, picking up changes of the monitor
+    /// configuration
+    fn reacquire(&mut self) -> Result<(), CaptureError> {
+        let source = source_area(self.monitor).map_err(|e| CaptureError::Fail(e.to_string()))?;
+        self.capturer = x11cap::Capturer::new(source)
+            .map_err(|()| CaptureError::Fail("Failed to reconnect to the X server".to_string()))?;
+        let geo = self.capturer.get_geometry();
+        if self.shm.is_some() {
+            self.shm = ShmImage::new(geo.width, geo.height);
+        }
+        let region = self
+            .region
+            .filter(|r| r.x + r.width <= geo.width && r.y + r.height <= geo.height);
+        self.set_region(region);
+        Ok(())
+    }
+}
+
+/// View the pixels of an X11 image as `Bgr8`
+fn x11_pixels(image: &x11cap::Image) -> &[Bgr8] {
+    let pixels = image.as_slice();
+    // `x11cap::Bgr8` is `repr(C)` with the same fields as `Bgr8`, with
+    // padding in place of alpha
+    unsafe { std::slice::from_raw_parts(pixels.as_ptr() as *const Bgr8, pixels.len()) }
+}
+
+/// Title of a window, from `_NET_WM_NAME` or `WM_NAME`
+unsafe fn window_title(display: *mut xlib::Display, window: xlib::Window) -> Option<String> {
+    let net_wm_name = xlib::XInternAtom(
+        display,
+        b"_NET_WM_NAME\0".as_ptr() as *const c_char,
+        xlib::False,
+    );
+    let utf8_string = xlib::XInternAtom(
+        display,
+        b"UTF8_STRING\0".as_ptr() as *const c_char,
+        xlib::False,
+    );
+    let (mut actual_type, mut format, mut n_items, mut remaining) = (0, 0, 0, 0);
+    let mut data = ptr::null_mut();
+    let status = xlib::XGetWindowProperty(
+        display,
+        window,
+        net_wm_name,
+        0,
+        1024,
+        xlib::False,
+        utf8_string,
+        &mut actual_type,
+        &mut format,
+        &mut n_items,
+        &mut remaining,
+        &mut data,
+    );
+    if status == xlib::Success as c_int && !data.is_null() {
+        let title = std::slice::from_raw_parts(data, n_items as usize);
+        let title = String::from_utf8_lossy(title).into_owned();
+        xlib::XFree(data as *mut _);
+        if n_items > 0 {
+            return Some(title);
+        }
+    }
+    let mut name = ptr::null_mut();
+    if xlib::XFetchName(display, window, &mut name) != 0 && !name.is_null() {
+        let title = CStr::from_ptr(name).to_string_lossy().into_owned();
+        xlib::XFree(name as *mut _);
+        return Some(title);
+    }
+    None
+}
+
+/// Find a viewable window, below `window`, whose title contains `title`
+unsafe fn find_child_window(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    title: &str,
+) -> Option<xlib::Window> {
+    let (mut root, mut parent) = (0, 0);
+    let mut children = ptr::null_mut();
+    let mut n_children = 0;
+    if xlib::XQueryTree(
+        display,
+        window,
+        &mut root,
+        &mut parent,
+        &mut children,
+        &mut n_children,
+    ) == 0
+    {
+        return None;
+    }
+    if children.is_null() {
+        return None;
+    }
+    let list = std::slice::from_raw_parts(children, n_children as usize);
+    // Topmost windows are last
+    let found = list.iter().rev().find_map(|&child| {
+        let mut attrs = std::mem::zeroed::<xlib::XWindowAttributes>();
+        xlib::XGetWindowAttributes(display, child, &mut attrs);
+        if attrs.map_state != xlib::IsViewable {
+            return None;
+        }
+        match window_title(display, child) {
+            Some(t) if t.contains(title) => Some(child),
+            _ => find_child_window(display, child, title),
+        }
+    });
+    xlib::XFree(children as *mut _);
+    found
+}
+
+/// Find a viewable window whose title contains `title`
+pub(crate) fn find_window(title: &str) -> Option<xlib::Window> {
+    unsafe {
+        let display = open_display();
+        if display.is_null() {
+            return None;
+        }
+        // Windows may be destroyed while searching
+        let found = without_errors(display, || {
+            find_child_window(display, xlib::XDefaultRootWindow(display), title)
+        })
+        .ok()
+        .flatten();
+        xlib::XCloseDisplay(display);
+        found
+    }
+}
+
+/// Bounds of a window on the root window, or `None` if it's not viewable,
+/// e.g. minimized, or doesn't exist
+pub(crate) fn window_bounds(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+) -> Option<(i32, i32, u32, u32)> {
+    unsafe {
+        let mut attrs = std::mem::zeroed::<xlib::XWindowAttributes>();
+        let (mut x, mut y, mut child) = (0, 0, 0);
+        let ok = without_errors(display, || {
+            xlib::XGetWindowAttributes(display, window, &mut attrs) != 0
+                && xlib::XTranslateCoordinates(
+                    display,
+                    window,
+                    xlib::XDefaultRootWindow(display),
+                    0,
+                    0,
+                    &mut x,
+                    &mut y,
+                    &mut child,
+                ) != 0
+        });
+        if ok != Ok(true) || attrs.map_state != xlib::IsViewable {
+            return None;
+        }
+        Some((x, y, attrs.width as u32, attrs.height as u32))
+    }
+}
+
+/// The top-level window of a window, i.e. its ancestor that is a child of
+/// the root window, e.g. the frame of the window manager
+unsafe fn top_level(display: *mut xlib::Display, mut window: xlib::Window) -> Option<xlib::Window> {
+    loop {
+        let (mut root, mut parent) = (0, 0);
+        let mut children = ptr::null_mut();
+        let mut n_children = 0;
+        let ok = xlib::XQueryTree(
+            display,
+            window,
+            &mut root,
+            &mut parent,
+            &mut children,
+            &mut n_children,
+        );
+        if ok == 0 {
+            return None;
+        }
+        if !children.is_null() {
+            xlib::XFree(children as *mut _);
+        }
+        if parent == root {
+            return Some(window);
+        }
+        window = parent;
+    }
+}
+
+/// A region of a rectangle
+unsafe fn rect_region((x, y): (i32, i32), (width, height): (u32, u32)) -> xlib::Region {
+    let region = xlib::XCreateRegion();
+    let mut rect = xlib::XRectangle {
+        x: x as c_short,
+        y: y as c_short,
+        width: width.min(u16::MAX as u32) as u16,
+        height: height.min(u16::MAX as u32) as u16,
+    };
+    xlib::XUnionRectWithRegion(&mut rect, region, region);
+    region
+}
+
+/// How much of `area` of a window, on the root window, is covered by the
+/// viewable top-level windows above it in the stacking order
+///
+/// Unlike `VisibilityNotify` events, this also works for the windows
+/// redirected by compositing managers, and for the window redirected by
+/// `X11WindowBackend` itself.
+unsafe fn occlusion(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    area: ((i32, i32), (u32, u32)),
+) -> Visibility {
+    let top = match top_level(display, window) {
+        Some(top) => top,
+        None => return Visibility::Visible,
+    };
+    let (mut root, mut parent) = (0, 0);
+    let mut children = ptr::null_mut();
+    let mut n_children = 0;
+    let root_window = xlib::XDefaultRootWindow(display);
+    if xlib::XQueryTree(
+        display,
+        root_window,
+        &mut root,
+        &mut parent,
+        &mut children,
+        &mut n_children,
+    ) == 0
+        || children.is_null()
+    {
+        return Visibility::Visible;
+    }
+    // Bottommost windows are first
+    let list = std::slice::from_raw_parts(children, n_children as usize);
+    let ((x, y), (width, height)) = area;
+    let visible = rect_region((x, y), (width, height));
+    let mut covered = false;
+    for &above in list.iter().skip_while(|&&w| w != top).skip(1) {
+        let mut attrs = std::mem::zeroed::<xlib::XWindowAttributes>();
+        if xlib::XGetWindowAttributes(display, above, &mut attrs) == 0
+            || attrs.map_state != xlib::IsViewable
+            || attrs.class != xlib::InputOutput
+        {
+            continue;
+        }
+        let (ow, oh) = (
+            (attrs.width + 2 * attrs.border_width) as u32,
+            (attrs.height + 2 * attrs.border_width) as u32,
+        );
+        let overlaps = attrs.x < x + width as i32
+            && x < attrs.x + ow as i32
+            && attrs.y < y + height as i32
+            && y < attrs.y + oh as i32;
+        if overlaps {
+            covered = true;
+            let other = rect_region((attrs.x, attrs.y), (ow, oh));
+            xlib::XSubtractRegion(visible, other, visible);
+            xlib::XDestroyRegion(other);
+        }
+    }
+    let hidden = xlib::XEmptyRegion(visible) != 0;
+    xlib::XDestroyRegion(visible);
+    xlib::XFree(children as *mut _);
+    match (covered, hidden) {
+        (false, _) => Visibility::Visible,
+        (true, false) => Visibility::PartiallyOccluded,
+        (true, true) => Visibility::Occluded,
+    }
+}
+
+/// Backend capturing a single window of the X server
+///
+/// The window is redirected with XComposite, if available, so that its
+/// contents can be captured even while it's covered by other windows.
+pub(crate) struct X11WindowBackend {
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    redirected: bool,
+    /// Whether the window is viewable, i.e. not minimized or hidden
+    viewable: bool,
+    /// Visibility of the window at the last capture, see `occlusion`
+    visibility: Visibility,
+    position: (i32, i32),
+    width: u32,
+    height: u32,
+    /// The last captured frame, and its width and height
+    frame: Option<(Vec<Bgr8>, u32, u32)>,
+}
+
+impl X11WindowBackend {
+    pub(crate) fn new(window: WindowId) -> Result<X11WindowBackend, InitError> {
+        unsafe {
+            let display = open_display();
+            if display.is_null() {
+                return Err(InitError::Other("Failed to open X display".to_string()));
+            }
+            let mut backend = X11WindowBackend {
+                display,
+                window: window.0 as xlib::Window,
+                redirected: false,
+                viewable: true,
+                visibility: Visibility::Visible,
+                position: (0, 0),
+                width: 0,
+                height: 0,
+                frame: None,
+            };
+            backend
+                .update_geometry()
+                .map_err(|_| InitError::NotFound(format!("No window with ID {:#x}", window.0)))?;
+            let (mut event_base, mut error_base) = (0, 0);
+            if XCompositeQueryExtension(display, &mut event_base, &mut error_base) != 0 {
+                backend.redirected = without_errors(display, || {
+                    XCompositeRedirectWindow(display, backend.window, COMPOSITE_REDIRECT_AUTOMATIC)
+                })
+                .is_ok();
+            }
+            Ok(backend)
+        }
+    }
+
+    /// Update the size and position of the window, which may have been
+    /// resized or moved
+    fn update_geometry(&mut self) -> Result<(), CaptureError> {
+        unsafe {
+            let display = self.display;
+            let window = self.window;
+            let mut attrs = std::mem::zeroed::<xlib::XWindowAttributes>();
+            let (mut x, mut y, mut child) = (0, 0, 0);
+            let ok = without_errors(display, || {
+                xlib::XGetWindowAttributes(display, window, &mut attrs) != 0
+                    && xlib::XTranslateCoordinates(
+                        display,
+                        window,
+                        xlib::XDefaultRootWindow(display),
+                        0,
+                        0,
+                        &mut x,
+                        &mut y,
+                        &mut child,
+                    ) != 0
+            });
+            if ok != Ok(true) {
+                // The window was destroyed
+                return Err(CaptureError::AccessLost);
+            }
+            self.width = attrs.width as u32;
+            self.height = attrs.height as u32;
+            self.position = (x, y);
+            self.viewable = attrs.map_state == xlib::IsViewable;
+            Ok(())
+        }
+    }
+
+    /// Update whether the window is minimized or covered by others
+    fn update_visibility(&mut self) {
+        let (display, window) = (self.display, self.window);
+        let area = (self.position, (self.width, self.height));
+        self.visibility = if self.viewable {
+            // Windows may be destroyed while walking the stacking order
+            unsafe { without_errors(display, || occlusion(display, window, area)) }
+                .unwrap_or(Visibility::Visible)
+        } else {
+            Visibility::Minimized
+        };
+    }
+}
+
+// See `X11Backend`
+unsafe impl Send for X11WindowBackend {}
+
+impl Backend for X11WindowBackend {
+    fn name(&self) -> &'static str {
+        if self.redirected {
+            "X11 XComposite window"
+        } else {
+            "X11 XGetImage window"
+        }
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The position of the window on the root window
+    fn position(&self) -> (i32, i32) {
+        self.position
+    }
+
+    /// Captures immediately, without waiting for a new frame
+    ///
+    /// Fails if the window is unmapped, e.g. minimized, and with
+    /// `CaptureError::AccessLost` if it was destroyed.
+    fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+        self.update_geometry()?;
+        self.update_visibility();
+        let (display, window, width, height) = (self.display, self.window, self.width, self.height);
+        let image = unsafe {
+            without_errors(display, || {
+                xlib::XGetImage(display, window, 0, 0, width, height, !0, xlib::ZPixmap)
+            })
+        }
+        .map_err(|code| {
+            PlatformError::new("XGetImage of the window failed", PlatformCode::X11(code))
+        })?;
+        if image.is_null() {
+            return Err(CaptureError::Fail(
+                "Failed to get image of window".to_string(),
+            ));
+        }
+        let img = unsafe { &*image };
+        let res = if img.bits_per_pixel == 32
+            && img.red_mask == 0xFF_0000
+            && img.green_mask == 0xFF00
+            && img.blue_mask == 0xFF
+        {
+            let (frame, w, h) = self.frame.get_or_insert_with(Default::default);
+            (*w, *h) = (width, height);
+            frame.clear();
+            frame.reserve(width as usize * height as usize);
+            for y in 0..height as usize {
+                // The layout is checked above. The fourth byte is alpha for
+                // windows of depth 32, and padding otherwise.
+                let row = unsafe {
+                    std::slice::from_raw_parts(
+                        img.data.add(y * img.bytes_per_line as usize) as *const Bgr8,
+                        width as usize,
+                    )
+                };
+                frame.extend_from_slice(row);
+            }
+            Ok(())
+        } else {
+            Err(CaptureError::Fail(format!(
+                "Unsupported window depth {}",
+                img.depth
+            )))
+        };
+        unsafe { xlib::XDestroyImage(image) };
+        res
+    }
+
+    fn visibility(&self) -> Option<Visibility> {
+        Some(self.visibility)
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        self.frame
+            .as_ref()
+            .map(|(frame, w, h)| (frame.as_slice(), *w, *h))
+    }
+}
+
+impl Drop for X11WindowBackend {
+    fn drop(&mut self) {
+        unsafe {
+            if self.redirected {
+                let (display, window) = (self.display, self.window);
+                let _ = without_errors(display, || {
+                    XCompositeUnredirectWindow(display, window, COMPOSITE_REDIRECT_AUTOMATIC)
+                });
+            }
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+#[cfg(test)]
+mod x11_tests {
+    use super::*;
+
+    fn monitor(name: &str, x: i32, y: i32, primary: bool) -> X11Monitor {
+        X11Monitor {
+            name: name.to_string(),
+            output: 0,
+            crtc: 0,
+            x,
+            y,
+            width: 1920,
+            height: 1080,
+            primary,
+        }
+    }
+
+    #[test]
+    fn test_monitor_order() {
+        let monitors = order_monitors(vec![
+            monitor("DP-2", 1920, 0, false),
+            monitor("HDMI-1", 0, 1080, false),
+            monitor("DP-1", 0, 0, false),
+            monitor("DP-3", 3840, 0, true),
+            // Mirroring DP-3
+            monitor("eDP-1", 3840, 0, false),
+        ]);
+        let names: Vec<_> = monitors.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["DP-3", "DP-1", "HDMI-1", "DP-2"]);
+    }
+}
@@ -0,0 +1,418 @@
+//! Capture of X11 monitors through XCB, with the `x11-xcb` feature
+//!
+//! Unlike Xlib, XCB connections are thread-safe. libxcb is loaded at runtime.
+//! Frames are read with MIT-SHM when the X server supports it and is on the
+//! same machine, and with `GetImage` otherwise. Monitors are enumerated with
+//! RandR CRTCs and outputs, in the same order as the Xlib backend. The last vertical blank
+//! before each capture is reported as its present time with the Present
+//! extension.
+
+use crate::backend::Backend;
+use crate::x11::X11Monitor;
+use crate::{pixel_bytes_mut, Bgr8, CaptureError, InitError, Rect};
+use std::os::raw::c_ulong;
+use std::ptr;
+use std::time::{Duration, Instant};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::present::{self, ConnectionExt as _};
+use x11rb::protocol::randr::{self, ConnectionExt as _};
+use x11rb::protocol::shm::{self, ConnectionExt as _};
+use x11rb::protocol::xinerama::ConnectionExt as _;
+use x11rb::protocol::xproto::{ConnectionExt as _, ImageFormat, Window};
+use x11rb::protocol::Event;
+use x11rb::xcb_ffi::XCBConnection;
+
+fn fail(e: impl std::fmt::Display) -> CaptureError {
+    CaptureError::Fail(e.to_string())
+}
+
+fn init_error(e: impl std::fmt::Display) -> InitError {
+    InitError::Other(e.to_string())
+}
+
+/// Position and size of an area in root window coordinates
+type Area = ((i32, i32), (u32, u32));
+
+/// A shared memory segment attached to the X server, of one frame
+struct Segment {
+    seg: shm::Seg,
+    addr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Segment {
+    /// Attach a segment of `len` bytes, if the server can access it
+    fn new(conn: &XCBConnection, len: usize) -> Option<Segment> {
+        conn.extension_information(shm::X11_EXTENSION_NAME).ok()??;
+        unsafe {
+            let id = libc::shmget(libc::IPC_PRIVATE, len.max(1), libc::IPC_CREAT | 0o600);
+            if id < 0 {
+                return None;
+            }
+            let addr = libc::shmat(id, ptr::null(), 0);
+            // Marked for removal right away, so the segment is freed when
+            // detached, even if the process is killed
+            libc::shmctl(id, libc::IPC_RMID, ptr::null_mut());
+            if addr as isize == -1 {
+                return None;
+            }
+            let seg = match conn.generate_id() {
+                Ok(seg) => seg,
+                Err(_) => {
+                    libc::shmdt(addr);
+                    return None;
+                }
+            };
+            let attached = conn
+                .shm_attach(seg, id as u32, false)
+                .ok()
+                .and_then(|cookie| cookie.check().ok());
+            if attached.is_none() {
+                libc::shmdt(addr);
+                return None;
+            }
+            Some(Segment { seg, addr, len })
+        }
+    }
+
+    fn pixels(&self, n: usize) -> &[Bgr8] {
+        unsafe { std::slice::from_raw_parts(self.addr as *const Bgr8, n.min(self.len / 4)) }
+    }
+
+    fn detach(self, conn: &XCBConnection) {
+        let _ = conn.shm_detach(self.seg).map(|cookie| cookie.check());
+        unsafe { libc::shmdt(self.addr) };
+    }
+}
+
+/// Backend capturing a monitor of the X server through XCB
+pub(crate) struct XcbBackend {
+    conn: XCBConnection,
+    root: Window,
+    capture_src: usize,
+    monitor: Area,
+    region: Option<Rect>,
+    /// Used instead of `GetImage` for capture when available
+    shm: Option<Segment>,
+    pixels: Vec<Bgr8>,
+    /// Size of the last captured frame, if any
+    captured: Option<(u32, u32)>,
+    /// Whether the completions of `PresentNotifyMSC` are selected
+    present: bool,
+    /// Time of the last reported vertical blank, in microseconds of
+    /// `CLOCK_MONOTONIC`
+    last_ust: Option<u64>,
+}
+
+// The segment is only accessed through the backend, by one thread at a time
+unsafe impl Send for XcbBackend {}
+
+impl XcbBackend {
+    pub(crate) fn new(capture_src: usize) -> Result<XcbBackend, InitError> {
+        let (conn, screen) = XCBConnection::connect(None).map_err(init_error)?;
+        let setup = conn.setup();
+        let screen = &setup.roots[screen];
+        let root = screen.root;
+        // Frames are read as `Bgr8`, with padding in place of alpha
+        let bgr = screen
+            .allowed_depths
+            .iter()
+            .filter(|d| d.depth == screen.root_depth)
+            .flat_map(|d| &d.visuals)
+            .find(|v| v.visual_id == screen.root_visual)
+            .is_some_and(|v| {
+                v.red_mask == 0xFF_0000 && v.green_mask == 0xFF00 && v.blue_mask == 0xFF
+            });
+        let bpp = setup
+            .pixmap_formats
+            .iter()
+            .find(|f| f.depth == screen.root_depth)
+            .map(|f| f.bits_per_pixel);
+        if !bgr || bpp != Some(32) {
+            return Err(InitError::Unsupported(format!(
+                "Unsupported X11 visual of depth {}",
+                screen.root_depth
+            )));
+        }
+        let mut backend = XcbBackend {
+            conn,
+            root,
+            capture_src,
+            monitor: ((0, 0), (0, 0)),
+            region: None,
+            shm: None,
+            pixels: Vec::new(),
+            captured: None,
+            present: false,
+            last_ust: None,
+        };
+        backend.present = backend.select_present();
+        backend.monitor = backend.monitor_area(capture_src)?;
+        backend.attach();
+        Ok(backend)
+    }
+
+    /// Select the completions of `PresentNotifyMSC` on the root window, if
+    /// the X server has the Present extension. Returns whether selected.
+    fn select_present(&self) -> bool {
+        let supported = self
+            .conn
+            .extension_information(present::X11_EXTENSION_NAME)
+            .is_ok_and(|ext| ext.is_some())
+            && self
+                .conn
+                .present_query_version(1, 0)
+                .is_ok_and(|cookie| cookie.reply().is_ok());
+        supported
+            && self.conn.generate_id().is_ok_and(|eid| {
+                self.conn
+                    .present_select_input(eid, self.root, present::EventMask::COMPLETE_NOTIFY)
+                    .is_ok_and(|cookie| cookie.check().is_ok())
+            })
+    }
+
+    /// The monitors of the active CRTCs of the connected RandR outputs, or
+    /// the Xinerama screens if there are more, like `crate::x11::monitors`
+    fn monitors(&self) -> Vec<X11Monitor> {
+        let mut monitors = self.randr_monitors().unwrap_or_default();
+        let xinerama = self.xinerama_monitors().unwrap_or_default();
+        if xinerama.len() > monitors.len() {
+            monitors = xinerama;
+        }
+        crate::x11::order_monitors(monitors)
+    }
+
+    fn randr_monitors(&self) -> Option<Vec<X11Monitor>> {
+        let resources = self
+            .conn
+            .randr_get_screen_resources_current(self.root)
+            .ok()?
+            .reply()
+            .ok()?;
+        let primary = self
+            .conn
+            .randr_get_output_primary(self.root)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .map(|reply| reply.output);
+        let time = resources.config_timestamp;
+        let mut monitors = Vec::new();
+        for &output in &resources.outputs {
+            let info = self
+                .conn
+                .randr_get_output_info(output, time)
+                .ok()?
+                .reply()
+                .ok()?;
+            if info.connection != randr::Connection::CONNECTED || info.crtc == 0 {
+                continue;
+            }
+            let crtc = self
+                .conn
+                .randr_get_crtc_info(info.crtc, time)
+                .ok()?
+                .reply()
+                .ok()?;
+            if crtc.width == 0 || crtc.height == 0 {
+                continue;
+            }
+            monitors.push(X11Monitor {
+                name: String::from_utf8_lossy(&info.name).into_owned(),
+                output: output as c_ulong,
+                crtc: info.crtc as c_ulong,
+                x: crtc.x as i32,
+                y: crtc.y as i32,
+                width: crtc.width as u32,
+                height: crtc.height as u32,
+                primary: primary == Some(output),
+            });
+        }
+        Some(monitors)
+    }
+
+    fn xinerama_monitors(&self) -> Option<Vec<X11Monitor>> {
+        let active = self.conn.xinerama_is_active().ok()?.reply().ok()?;
+        if active.state == 0 {
+            return None;
+        }
+        let screens = self.conn.xinerama_query_screens().ok()?.reply().ok()?;
+        let monitors = screens
+            .screen_info
+            .iter()
+            .enumerate()
+            .map(|(i, s)| X11Monitor {
+                name: format!("Xinerama-{}", i),
+                output: 0,
+                crtc: 0,
+                x: s.x_org as i32,
+                y: s.y_org as i32,
+                width: s.width as u32,
+                height: s.height as u32,
+                // Xinerama lists the primary screen first
+                primary: i == 0,
+            })
+            .collect();
+        Some(monitors)
+    }
+
+    /// The area of the monitor at an index, in the order of
+    /// `crate::x11::monitors`. Without RandR and Xinerama, index 0 is the
+    /// whole screen.
+    fn monitor_area(&self, capture_src: usize) -> Result<Area, InitError> {
+        let monitors = self.monitors();
+        match monitors.get(capture_src) {
+            Some(m) => Ok(((m.x, m.y), (m.width, m.height))),
+            None if capture_src == 0 && monitors.is_empty() => {
+                let roots = &self.conn.setup().roots;
+                let screen = roots.iter().find(|s| s.root == self.root).unwrap();
+                Ok((
+                    (0, 0),
+                    (
+                        screen.width_in_pixels as u32,
+                        screen.height_in_pixels as u32,
+                    ),
+                ))
+            }
+            None => Err(InitError::NotFound(format!("No monitor {}", capture_src))),
+        }
+    }
+
+    /// The area to capture, i.e. the region or monitor
+    fn area(&self) -> Area {
+        let ((x, y), size) = self.monitor;
+        match self.region {
+            Some(r) => ((x + r.x as i32, y + r.y as i32), (r.width, r.height)),
+            None => ((x, y), size),
+        }
+    }
+
+    /// Attach a segment of the size of the area anew
+    fn attach(&mut self) {
+        if let Some(shm) = self.shm.take() {
+            shm.detach(&self.conn);
+        }
+        let (_, (w, h)) = self.area();
+        self.shm = Segment::new(&self.conn, 4 * w as usize * h as usize);
+        self.captured = None;
+    }
+}
+
+impl Backend for XcbBackend {
+    fn name(&self) -> &'static str {
+        if self.shm.is_some() {
+            "X11 XCB ShmGetImage"
+        } else {
+            "X11 XCB GetImage"
+        }
+    }
+
+    fn geometry(&self) -> (u32, u32) {
+        self.area().1
+    }
+
+    fn position(&self) -> (i32, i32) {
+        self.monitor.0
+    }
+
+    fn capture_frame(&mut self, _timeout: Duration) -> Result<(), CaptureError> {
+        let ((x, y), (w, h)) = self.area();
+        let format = ImageFormat::Z_PIXMAP;
+        if self.present {
+            // A target in the past completes right away, at the last vertical
+            // blank, before the image is replied
+            let _ = self.conn.present_notify_msc(self.root, 0, 0, 0, 0);
+        }
+        match &self.shm {
+            Some(shm) => {
+                self.conn
+                    .shm_get_image(
+                        self.root,
+                        x as i16,
+                        y as i16,
+                        w as u16,
+                        h as u16,
+                        !0,
+                        format.into(),
+                        shm.seg,
+                        0,
+                    )
+                    .map_err(fail)?
+                    .reply()
+                    .map_err(fail)?;
+            }
+            None => {
+                let image = self
+                    .conn
+                    .get_image(
+                        format, self.root, x as i16, y as i16, w as u16, h as u16, !0,
+                    )
+                    .map_err(fail)?
+                    .reply()
+                    .map_err(fail)?;
+                self.pixels.resize(w as usize * h as usize, Bgr8::default());
+                let bytes = pixel_bytes_mut(&mut self.pixels);
+                let n = bytes.len().min(image.data.len());
+                bytes[..n].copy_from_slice(&image.data[..n]);
+            }
+        }
+        self.captured = Some((w, h));
+        while let Ok(Some(event)) = self.conn.poll_for_event() {
+            if let Event::PresentCompleteNotify(complete) = event {
+                self.last_ust = Some(complete.ust);
+            }
+        }
+        Ok(())
+    }
+
+    fn frame(&self) -> Option<(&[Bgr8], u32, u32)> {
+        let (w, h) = self.captured?;
+        let pixels = match &self.shm {
+            Some(shm) => shm.pixels(w as usize * h as usize),
+            None => &self.pixels,
+        };
+        Some((pixels, w, h))
+    }
+
+    /// The last vertical blank before the capture, with X Present
+    fn present_time(&self) -> Option<Instant> {
+        crate::x11::monotonic_instant(Duration::from_micros(self.last_ust?))
+    }
+
+    /// Reads only the region from the X server
+    fn set_region(&mut self, region: Option<Rect>) -> bool {
+        self.region = region;
+        self.attach();
+        true
+    }
+
+    fn set_source(&mut self, capture_src: usize) -> Option<Result<(), InitError>> {
+        match self.monitor_area(capture_src) {
+            Ok(monitor) => self.monitor = monitor,
+            Err(e) => return Some(Err(e)),
+        }
+        self.capture_src = capture_src;
+        self.set_region(None);
+        Some(Ok(()))
+    }
+
+    /// Picks up changes of the monitor configuration
+    fn reacquire(&mut self) -> Result<(), CaptureError> {
+        self.monitor = self
+            .monitor_area(self.capture_src)
+            .map_err(|e| CaptureError::Fail(e.to_string()))?;
+        let (_, (width, height)) = self.monitor;
+        self.region = self
+            .region
+            .filter(|r| r.x + r.width <= width && r.y + r.height <= height);
+        self.attach();
+        Ok(())
+    }
+}
+
+impl Drop for XcbBackend {
+    fn drop(&mut self) {
+        if let Some(shm) = self.shm.take() {
+            shm.detach(&self.conn);
+        }
+    }
+}
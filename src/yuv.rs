@@ -0,0 +1,256 @@
+//! Conversion of captured frames to YUV 4:2:0, e.g. for x264 or hardware
+//! video encoders
+//!
+//! Uses BT.709 coefficients in limited range, i.e. luma from 16 to 235 and
+//! chroma from 16 to 240, which is what HD video encoders expect.
+
+use crate::{Bgr8, CaptureError, Capturer};
+use std::thread;
+
+/// Layout of the planes of a YUV 4:2:0 frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum YuvFormat {
+    /// A Y plane followed by a plane of interleaved U and V samples
+    Nv12,
+    /// A Y plane followed by a U plane and a V plane
+    I420,
+}
+
+/// A frame in a YUV 4:2:0 format
+///
+/// The chroma planes have half the width and height of the frame, rounded
+/// up. Rows are not padded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct YuvFrame {
+    format: YuvFormat,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+/// The chroma planes of some rows of a frame
+enum Chroma<'a> {
+    Interleaved(&'a mut [u8]),
+    Planar(&'a mut [u8], &'a mut [u8]),
+}
+
+/// BT.709 limited range luma of a pixel, in 8-bit fixed point
+#[inline]
+fn y(p: Bgr8) -> u8 {
+    (16 + ((47 * p.r as i32 + 157 * p.g as i32 + 16 * p.b as i32 + 128) >> 8)) as u8
+}
+
+/// BT.709 limited range chroma of the sum of 4 pixels
+#[inline]
+fn uv(r: i32, g: i32, b: i32) -> (u8, u8) {
+    // The sums are of 4 pixels, so shift by 2 more to average them
+    let u = 128 + ((-26 * r - 86 * g + 112 * b + 512) >> 10);
+    let v = 128 + ((112 * r - 102 * g - 10 * b + 512) >> 10);
+    (u as u8, v as u8)
+}
+
+/// Convert some rows of a frame, starting at an even row
+fn convert_rows(src: &[Bgr8], width: usize, y_plane: &mut [u8], mut chroma: Chroma) {
+    for (dst, &p) in y_plane.iter_mut().zip(src) {
+        *dst = y(p);
+    }
+    let rows = src.len() / width;
+    let chroma_width = width.div_ceil(2);
+    for cy in 0..rows.div_ceil(2) {
+        let top = &src[2 * cy * width..][..width];
+        // The last row is repeated for frames of odd height
+        let bottom = &src[(2 * cy + 1).min(rows - 1) * width..][..width];
+        for cx in 0..chroma_width {
+            let (x0, x1) = (2 * cx, (2 * cx + 1).min(width - 1));
+            let (mut r, mut g, mut b) = (0, 0, 0);
+            for p in [top[x0], top[x1], bottom[x0], bottom[x1]] {
+                r += p.r as i32;
+                g += p.g as i32;
+                b += p.b as i32;
+            }
+            let (u, v) = uv(r, g, b);
+            let i = cy * chroma_width + cx;
+            match &mut chroma {
+                Chroma::Interleaved(uv) => {
+                    uv[2 * i] = u;
+                    uv[2 * i + 1] = v;
+                }
+                Chroma::Planar(u_plane, v_plane) => {
+                    u_plane[i] = u;
+                    v_plane[i] = v;
+                }
+            }
+        }
+    }
+}
+
+impl YuvFrame {
+    /// Convert a frame of the given size
+    pub fn convert(pixels: &[Bgr8], width: u32, height: u32, format: YuvFormat) -> YuvFrame {
+        YuvFrame::convert_parallel(pixels, width, height, format, 1)
+    }
+
+    /// Convert a frame of the given size, with up to `threads` threads
+    /// converting bands of rows of the frame
+    pub fn convert_parallel(
+        pixels: &[Bgr8],
+        width: u32,
+        height: u32,
+        format: YuvFormat,
+        threads: usize,
+    ) -> YuvFrame {
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        let (w, h) = (width as usize, height as usize);
+        let chroma_len = w.div_ceil(2) * h.div_ceil(2);
+        let mut data = vec![0; w * h + 2 * chroma_len];
+        if w > 0 && h > 0 {
+            // Bands of an even number of rows, so that chroma rows are not
+            // split between bands
+            let band_rows = 2 * h.div_ceil(2).div_ceil(threads.max(1));
+            let band_chroma = w.div_ceil(2) * band_rows / 2;
+            let (y_plane, chroma) = data.split_at_mut(w * h);
+            let bands = pixels
+                .chunks(w * band_rows)
+                .zip(y_plane.chunks_mut(w * band_rows));
+            let chromas: Vec<Chroma> = match format {
+                YuvFormat::Nv12 => chroma
+                    .chunks_mut(2 * band_chroma)
+                    .map(Chroma::Interleaved)
+                    .collect(),
+                YuvFormat::I420 => {
+                    let (u, v) = chroma.split_at_mut(chroma_len);
+                    u.chunks_mut(band_chroma)
+                        .zip(v.chunks_mut(band_chroma))
+                        .map(|(u, v)| Chroma::Planar(u, v))
+                        .collect()
+                }
+            };
+            let mut jobs = bands.zip(chromas);
+            let first = jobs.next();
+            thread::scope(|s| {
+                for ((src, y_plane), chroma) in jobs {
+                    s.spawn(move || convert_rows(src, w, y_plane, chroma));
+                }
+                // The first band is converted on this thread
+                if let Some(((src, y_plane), chroma)) = first {
+                    convert_rows(src, w, y_plane, chroma);
+                }
+            });
+        }
+        YuvFrame {
+            format,
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn format(&self) -> YuvFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn y_len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Width of the chroma planes, and the number of U and V samples of a
+    /// row of the interleaved plane of NV12
+    pub fn chroma_width(&self) -> u32 {
+        self.width.div_ceil(2)
+    }
+
+    pub fn chroma_height(&self) -> u32 {
+        self.height.div_ceil(2)
+    }
+
+    /// The Y plane
+    pub fn y(&self) -> &[u8] {
+        &self.data[..self.y_len()]
+    }
+
+    /// The plane of interleaved U and V samples of NV12
+    pub fn uv(&self) -> Option<&[u8]> {
+        match self.format {
+            YuvFormat::Nv12 => Some(&self.data[self.y_len()..]),
+            YuvFormat::I420 => None,
+        }
+    }
+
+    /// The U plane of I420
+    pub fn u(&self) -> Option<&[u8]> {
+        let chroma_len = (self.data.len() - self.y_len()) / 2;
+        match self.format {
+            YuvFormat::I420 => Some(&self.data[self.y_len()..][..chroma_len]),
+            YuvFormat::Nv12 => None,
+        }
+    }
+
+    /// The V plane of I420
+    pub fn v(&self) -> Option<&[u8]> {
+        let chroma_len = (self.data.len() - self.y_len()) / 2;
+        match self.format {
+            YuvFormat::I420 => Some(&self.data[self.y_len() + chroma_len..]),
+            YuvFormat::Nv12 => None,
+        }
+    }
+
+    /// All planes, one after the other
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Capturer {
+    /// Capture screen and convert the frame to YUV 4:2:0
+    pub fn capture_frame_yuv(&mut self, format: YuvFormat) -> Result<YuvFrame, CaptureError> {
+        self.capture_frame_yuv_parallel(format, 1)
+    }
+
+    /// Capture screen and convert the frame to YUV 4:2:0 with up to
+    /// `threads` threads, e.g. for 4K frames
+    pub fn capture_frame_yuv_parallel(
+        &mut self,
+        format: YuvFormat,
+        threads: usize,
+    ) -> Result<YuvFrame, CaptureError> {
+        self.capture_store_frame()?;
+        let (pixels, width, height) = self.stored_frame_with_size().unwrap();
+        Ok(YuvFrame::convert_parallel(
+            pixels, width, height, format, threads,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod yuv_tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_yuv() {
+        let white = Bgr8::new(255, 255, 255);
+        let red = Bgr8::new(255, 0, 0);
+        // 3x3, so the last chroma column and row are of single pixels
+        let mut pixels = vec![white; 9];
+        pixels[2] = red;
+        pixels[5] = red;
+        let frame = YuvFrame::convert(&pixels, 3, 3, YuvFormat::I420);
+        assert_eq!(frame.y()[..3], [235, 235, 63]);
+        assert_eq!(frame.u().unwrap(), [128, 102, 128, 128]);
+        assert_eq!(frame.v().unwrap(), [128, 240, 128, 128]);
+        let nv12 = YuvFrame::convert_parallel(&pixels, 3, 3, YuvFormat::Nv12, 4);
+        assert_eq!(nv12.y(), frame.y());
+        assert_eq!(nv12.uv().unwrap()[2..4], [102, 240]);
+    }
+}
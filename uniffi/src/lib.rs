@@ -0,0 +1,152 @@
+//! UniFFI bindings for captrs
+//!
+//! The interface is defined in `src/captrs.udl`. Bindings for e.g. Kotlin or
+//! Swift are generated from the built library with
+//! `cargo run --bin uniffi-bindgen generate --library <lib> --language kotlin --out-dir out`.
+
+extern crate captrs;
+extern crate uniffi;
+
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+
+uniffi::include_scaffolding!("captrs");
+
+/// Error when creating a capturer or capturing a frame
+#[derive(Debug)]
+pub enum CaptureError {
+    /// Could not duplicate output, access denied. Might be in protected fullscreen.
+    AccessDenied,
+    /// Access to the duplicated output was lost. Likely, mode was changed e.g. window => full
+    AccessLost,
+    /// Error when trying to refresh outputs after some failure.
+    RefreshFailure,
+    /// Aquisition of next frame timed out.
+    Timeout,
+    /// General/Unexpected failure
+    Fail { message: String },
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::AccessDenied => write!(f, "Access denied"),
+            CaptureError::AccessLost => write!(f, "Access lost"),
+            CaptureError::RefreshFailure => write!(f, "Failed to refresh outputs"),
+            CaptureError::Timeout => write!(f, "Acquisition of next frame timed out"),
+            CaptureError::Fail { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<captrs::CaptureError> for CaptureError {
+    fn from(err: captrs::CaptureError) -> CaptureError {
+        match err {
+            #[cfg(windows)]
+            captrs::CaptureError::AccessDenied => CaptureError::AccessDenied,
+            captrs::CaptureError::PermissionDenied => CaptureError::AccessDenied,
+            captrs::CaptureError::AccessLost => CaptureError::AccessLost,
+            #[cfg(windows)]
+            captrs::CaptureError::RefreshFailure => CaptureError::RefreshFailure,
+            captrs::CaptureError::Timeout | captrs::CaptureError::NoNewFrame => {
+                CaptureError::Timeout
+            }
+            captrs::CaptureError::Platform(e) => CaptureError::Fail {
+                message: e.to_string(),
+            },
+            e @ captrs::CaptureError::Paused => CaptureError::Fail {
+                message: e.to_string(),
+            },
+            captrs::CaptureError::Fail(message) => CaptureError::Fail { message },
+        }
+    }
+}
+
+/// Width and height of the area to capture
+pub struct Geometry {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A captured frame of 4 byte BGRX pixels
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+enum Request {
+    Geometry(mpsc::Sender<Geometry>),
+    Capture(mpsc::Sender<Result<Frame, CaptureError>>),
+}
+
+/// A screen capturer
+///
+/// `captrs::Capturer` is not `Send`, but UniFFI objects may be used from any
+/// thread, so the capturer lives on a thread of its own that requests are sent
+/// to.
+pub struct Capturer {
+    requests: mpsc::Sender<Request>,
+}
+
+impl Capturer {
+    /// Construct a new capturer for a given capture source, e.g. a display
+    pub fn new(capture_src: u32) -> Result<Capturer, CaptureError> {
+        let (requests, rx) = mpsc::channel();
+        let (init_tx, init_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut capturer = match captrs::Capturer::new(capture_src as usize) {
+                Ok(capturer) => {
+                    let _ = init_tx.send(Ok(()));
+                    capturer
+                }
+                Err(e) => {
+                    let message = e.to_string();
+                    let _ = init_tx.send(Err(CaptureError::Fail { message }));
+                    return;
+                }
+            };
+            // Runs until the `Capturer` is dropped
+            for request in rx {
+                match request {
+                    Request::Geometry(reply) => {
+                        let (width, height) = capturer.geometry();
+                        let _ = reply.send(Geometry { width, height });
+                    }
+                    Request::Capture(reply) => {
+                        let frame = capturer.capture_frame_components().map(|data| {
+                            let (width, height) = capturer.geometry();
+                            Frame {
+                                width,
+                                height,
+                                data,
+                            }
+                        });
+                        let _ = reply.send(frame.map_err(CaptureError::from));
+                    }
+                }
+            }
+        });
+        init_rx.recv().map_err(|_| CaptureError::Fail {
+            message: "Capture thread panicked".to_string(),
+        })??;
+        Ok(Capturer { requests })
+    }
+
+    /// Returns the width and height of the area to capture
+    pub fn geometry(&self) -> Geometry {
+        let (tx, rx) = mpsc::channel();
+        self.requests.send(Request::Geometry(tx)).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// Capture the screen
+    pub fn capture_frame(&self) -> Result<Frame, CaptureError> {
+        let (tx, rx) = mpsc::channel();
+        self.requests.send(Request::Capture(tx)).unwrap();
+        rx.recv().unwrap()
+    }
+}